@@ -0,0 +1,481 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Benchmarks for the off-chain paths that the maintainer runs on every iteration:
+//! reading a snapshot through `GetMultipleAccounts`, and building a `SolidoState`
+//! from it.
+//!
+//! The RPC is replaced by an in-memory map, so these measure our own overhead,
+//! not network latency.
+
+use std::collections::HashMap;
+
+use borsh::BorshSerialize;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_request::RpcError;
+use solana_client::rpc_response::{
+    Response, RpcResponseContext, RpcResult, RpcSimulateTransactionResult,
+};
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::stake::state::{Delegation, Meta, Stake, StakeState};
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Clock, Epoch};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::sysvar::{self, stake_history::StakeHistory};
+use solana_sdk::transaction::{self, Transaction};
+
+use lido::processor::StakeType;
+use lido::state::{Lido, Validator, Validators, LIDO_VERSION};
+use lido::token::Lamports;
+use lido::{RESERVE_ACCOUNT, VALIDATOR_STAKE_ACCOUNT};
+use solido_cli::config::OutputMode;
+use solido_cli::derivation_cache;
+use solido_cli::maintenance::SolidoState;
+use solido_cli::rpc::{
+    AccountFilter, BlockProduction, InflationReward, Rpc, SignatureInfo, TransactionInfo,
+};
+use solido_cli::snapshot::SnapshotClient;
+use solido_cli::Config;
+
+/// The benchmarks only read accounts, other calls fail like a node that does not support them.
+fn unsupported(call: &str) -> ClientError {
+    ClientErrorKind::Custom(format!("{} is not available in the benchmarks.", call)).into()
+}
+
+/// An RPC that serves accounts from memory, with a limit on the accounts per call.
+struct BenchRpc {
+    accounts: HashMap<Pubkey, Account>,
+    max_accounts_per_call: usize,
+}
+
+impl Rpc for BenchRpc {
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        _commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>> {
+        if pubkeys.len() > self.max_accounts_per_call {
+            let message = format!(
+                "Failed to deserialize RPC error response: {{\"code\":-32602,\
+                \"message\":\"Too many inputs provided; max {}\"}} [missing field `data`]",
+                self.max_accounts_per_call
+            );
+            return Err(ClientErrorKind::RpcError(RpcError::RpcRequestError(message)).into());
+        }
+        Ok(Response {
+            context: RpcResponseContext { slot: 0 },
+            value: pubkeys
+                .iter()
+                .map(|k| self.accounts.get(k).cloned())
+                .collect(),
+        })
+    }
+
+    fn simulate_transaction(
+        &self,
+        _transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        Err(unsupported("Simulating transactions"))
+    }
+
+    fn send_transaction(
+        &self,
+        _transaction: &Transaction,
+        _commitment: CommitmentConfig,
+        _skip_preflight: bool,
+    ) -> ClientResult<Signature> {
+        Err(unsupported("Sending transactions"))
+    }
+
+    fn get_signature_status(
+        &self,
+        _signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        Err(unsupported("Sending transactions"))
+    }
+
+    fn is_blockhash_valid(&self, _blockhash: &Hash) -> ClientResult<bool> {
+        Err(unsupported("Sending transactions"))
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Err(unsupported("Sending transactions"))
+    }
+
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        _transaction: &Transaction,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Signature> {
+        Err(unsupported("Sending transactions"))
+    }
+
+    fn get_recent_prioritization_fees(&self, _pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>> {
+        Err(unsupported("getRecentPrioritizationFees"))
+    }
+
+    fn get_program_accounts(
+        &self,
+        _program_id: &Pubkey,
+        _filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        // The benchmark vote accounts hold no data, so we never look up
+        // validator info, and there is none to return anyway.
+        Ok(Vec::new())
+    }
+
+    fn get_inflation_reward(
+        &self,
+        _pubkeys: &[Pubkey],
+        _epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>> {
+        Err(unsupported("getInflationReward"))
+    }
+
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>> {
+        Err(unsupported("getVoteAccounts"))
+    }
+
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>> {
+        Err(unsupported("getBlockProduction"))
+    }
+
+    fn get_signatures_for_address(
+        &self,
+        _address: &Pubkey,
+        _before: Option<&str>,
+        _until: Option<&str>,
+        _limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>> {
+        Err(unsupported("getSignaturesForAddress"))
+    }
+
+    fn get_transaction(&self, _signature: &str) -> ClientResult<Option<TransactionInfo>> {
+        Err(unsupported("getTransaction"))
+    }
+}
+
+fn new_account(lamports: u64, data: Vec<u8>, owner: Pubkey) -> Account {
+    Account {
+        lamports,
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn new_sysvar_account<T: serde::Serialize>(value: &T) -> Account {
+    new_account(1, bincode::serialize(value).unwrap(), sysvar::id())
+}
+
+/// `n` accounts with some data, and their addresses.
+fn new_plain_accounts(n: usize) -> (Vec<Pubkey>, HashMap<Pubkey, Account>) {
+    let addresses: Vec<Pubkey> = (0..n).map(|_| Pubkey::new_unique()).collect();
+    let accounts = addresses
+        .iter()
+        .map(|addr| (*addr, new_account(1, vec![0; 200], Pubkey::default())))
+        .collect();
+    (addresses, accounts)
+}
+
+fn bench_snapshot_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot_read");
+    let n = 1_000;
+    let (addresses, accounts) = new_plain_accounts(n);
+
+    // With a limit of 100 (the default of `--rpc-max-multiple-accounts`), the
+    // snapshot client has to split the read into chunks.
+    for &max_accounts_per_call in &[100, n] {
+        let mut client = SnapshotClient::new(BenchRpc {
+            accounts: accounts.clone(),
+            max_accounts_per_call,
+        });
+        let mut read_all = || {
+            client
+                .with_snapshot(|mut snapshot| {
+                    for address in &addresses {
+                        snapshot.get_account(address)?;
+                    }
+                    Ok(())
+                })
+                .unwrap()
+        };
+
+        // The first read discovers the accounts, and learns the chunk size.
+        read_all();
+
+        group.bench_function(
+            BenchmarkId::new("max_accounts_per_call", max_accounts_per_call),
+            |b| b.iter(&mut read_all),
+        );
+    }
+    group.finish();
+}
+
+fn bench_snapshot_discover(c: &mut Criterion) {
+    // A fresh client does not know which accounts to read, it learns them one
+    // retry at a time, merging the newly referenced accounts into its query.
+    let mut group = c.benchmark_group("snapshot_discover");
+    for &n in &[10, 100, 200] {
+        let (addresses, accounts) = new_plain_accounts(n);
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                let mut client = SnapshotClient::new(BenchRpc {
+                    accounts: accounts.clone(),
+                    max_accounts_per_call: 100,
+                });
+                client
+                    .with_snapshot(|mut snapshot| {
+                        for address in &addresses {
+                            snapshot.get_account(address)?;
+                        }
+                        Ok(())
+                    })
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Build the accounts of a Solido instance with `n` validators that have one stake account each.
+fn new_solido_accounts(n: u32, maintainer: &Pubkey) -> (Pubkey, Pubkey, HashMap<Pubkey, Account>) {
+    let mut accounts = HashMap::new();
+    let solido_program_id = Pubkey::new_unique();
+    let solido_address = Pubkey::new_unique();
+    let rent = Rent::default();
+    let clock = Clock {
+        epoch: 10,
+        ..Clock::default()
+    };
+
+    let (reserve_address, reserve_bump_seed) = Pubkey::find_program_address(
+        &[&solido_address.to_bytes(), RESERVE_ACCOUNT],
+        &solido_program_id,
+    );
+    accounts.insert(
+        reserve_address,
+        new_account(
+            rent.minimum_balance(0),
+            Vec::new(),
+            solana_sdk::system_program::id(),
+        ),
+    );
+
+    let st_sol_mint_address = Pubkey::new_unique();
+    let mint = spl_token::state::Mint {
+        mint_authority: COption::Some(Pubkey::new_unique()),
+        supply: 0,
+        decimals: 9,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    let mut mint_data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+    accounts.insert(
+        st_sol_mint_address,
+        new_account(
+            rent.minimum_balance(mint_data.len()),
+            mint_data,
+            spl_token::id(),
+        ),
+    );
+
+    let mut solido = Lido {
+        lido_version: LIDO_VERSION,
+        st_sol_mint: st_sol_mint_address,
+        st_sol_token_program: spl_token::id(),
+        sol_reserve_account_bump_seed: reserve_bump_seed,
+        validators: Validators::new(n),
+        ..Lido::default()
+    };
+
+    let stake_rent = rent.minimum_balance(StakeState::size_of());
+    let stake_lamports = 10_000_000_000;
+    for _ in 0..n {
+        let vote_account = Pubkey::new_unique();
+        accounts.insert(
+            vote_account,
+            new_account(
+                rent.minimum_balance(0),
+                Vec::new(),
+                solana_vote_program::id(),
+            ),
+        );
+        let mut validator = Validator::new(Pubkey::new_unique());
+        validator.stake_seeds.end = 1;
+        validator.stake_accounts_balance = Lamports(stake_lamports);
+        solido.validators.add(vote_account, validator).unwrap();
+
+        let (stake_address, _) = solido
+            .validators
+            .entries
+            .last()
+            .unwrap()
+            .find_stake_account_address(&solido_program_id, &solido_address, 0, StakeType::Stake);
+        let state = StakeState::Stake(
+            Meta {
+                rent_exempt_reserve: stake_rent,
+                ..Meta::default()
+            },
+            Stake {
+                delegation: Delegation {
+                    voter_pubkey: vote_account,
+                    stake: stake_lamports - stake_rent,
+                    activation_epoch: 1,
+                    ..Delegation::default()
+                },
+                credits_observed: 0,
+            },
+        );
+        let mut data = vec![0; StakeState::size_of()];
+        bincode::serialize_into(&mut data[..], &state).unwrap();
+        accounts.insert(
+            stake_address,
+            new_account(stake_lamports, data, solana_program::stake::program::id()),
+        );
+    }
+
+    accounts.insert(
+        solido_address,
+        new_account(1, solido.try_to_vec().unwrap(), solido_program_id),
+    );
+    accounts.insert(
+        *maintainer,
+        new_account(10_000_000_000, Vec::new(), solana_sdk::system_program::id()),
+    );
+
+    accounts.insert(sysvar::rent::id(), new_sysvar_account(&rent));
+    accounts.insert(sysvar::clock::id(), new_sysvar_account(&clock));
+    accounts.insert(
+        sysvar::epoch_schedule::id(),
+        new_sysvar_account(&EpochSchedule::default()),
+    );
+    accounts.insert(
+        sysvar::stake_history::id(),
+        new_sysvar_account(&StakeHistory::default()),
+    );
+
+    (solido_program_id, solido_address, accounts)
+}
+
+fn bench_solido_state_new(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solido_state_new");
+    group.sample_size(20);
+    let maintainer = Keypair::new();
+
+    // With the cache of parsed accounts, as the maintenance daemon uses it, the
+    // unchanged `Lido` account and stake history are not deserialized again.
+    for &n in &[100, 1_000, 5_000] {
+        let (solido_program_id, solido_address, accounts) =
+            new_solido_accounts(n, &maintainer.pubkey());
+        for &(name, cache_parsed_accounts) in &[("uncached", false), ("cached", true)] {
+            let mut client = SnapshotClient::new(BenchRpc {
+                accounts: accounts.clone(),
+                max_accounts_per_call: usize::MAX,
+            });
+            client.set_cache_parsed_accounts(cache_parsed_accounts);
+            let mut config = Config {
+                client,
+                signer: &maintainer,
+                fee_payer: &maintainer,
+                output_mode: OutputMode::Json,
+                output_transaction_path: None,
+                nonce: None,
+            };
+            let mut read_state = || {
+                config
+                    .with_snapshot(|config| {
+                        SolidoState::new(config, &solido_program_id, &solido_address)
+                    })
+                    .unwrap()
+            };
+
+            // Discover the accounts, and fill the cache, outside of the measurement.
+            read_state();
+
+            group.bench_function(BenchmarkId::new(name, n), |b| b.iter(&mut read_state));
+        }
+    }
+    group.finish();
+}
+
+fn bench_get_solido(c: &mut Criterion) {
+    // Only the deserialization of the `Lido` account, which dominates the
+    // difference between the cached and uncached `solido_state_new`.
+    let mut group = c.benchmark_group("get_solido");
+    let maintainer = Keypair::new();
+    let n = 5_000;
+    let (_, solido_address, accounts) = new_solido_accounts(n, &maintainer.pubkey());
+    for &(name, cache_parsed_accounts) in &[("uncached", false), ("cached", true)] {
+        let mut client = SnapshotClient::new(BenchRpc {
+            accounts: accounts.clone(),
+            max_accounts_per_call: usize::MAX,
+        });
+        client.set_cache_parsed_accounts(cache_parsed_accounts);
+        let mut read_solido = || {
+            client
+                .with_snapshot(|mut snapshot| snapshot.get_solido_shared(&solido_address))
+                .unwrap()
+        };
+        read_solido();
+        group.bench_function(BenchmarkId::new(name, n), |b| b.iter(&mut read_solido));
+    }
+    group.finish();
+}
+
+fn bench_stake_account_address(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stake_account_address");
+    let solido_program_id = Pubkey::new_unique();
+    let solido_address = Pubkey::new_unique();
+    let validators: Vec<Pubkey> = (0..100).map(|_| Pubkey::new_unique()).collect();
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for vote_account in &validators {
+                Pubkey::find_program_address(
+                    &[
+                        &solido_address.to_bytes(),
+                        &vote_account.to_bytes(),
+                        VALIDATOR_STAKE_ACCOUNT,
+                        &0_u64.to_le_bytes()[..],
+                    ],
+                    &solido_program_id,
+                );
+            }
+        })
+    });
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            for vote_account in &validators {
+                derivation_cache::find_stake_account_address(
+                    vote_account,
+                    &solido_program_id,
+                    &solido_address,
+                    0,
+                    StakeType::Stake,
+                );
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_snapshot_read,
+    bench_snapshot_discover,
+    bench_solido_state_new,
+    bench_get_solido,
+    bench_stake_account_address
+);
+criterion_main!(benches);
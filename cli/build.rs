@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Record the git commit and the compiler version that we build with, so the
+//! maintenance daemon can report them in the `solido_maintainer_build_info`
+//! metric.
+
+use std::env;
+use std::process::Command;
+
+/// Run the command and return its trimmed output, or "unknown" if it fails.
+fn get_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|stdout| stdout.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    println!(
+        "cargo:rustc-env=SOLIDO_GIT_COMMIT={}",
+        get_output("git", &["rev-parse", "HEAD"])
+    );
+    println!(
+        "cargo:rustc-env=SOLIDO_RUSTC_VERSION={}",
+        get_output(&rustc, &["--version"])
+    );
+
+    // Rebuild when we commit or check out something else.
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,307 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Reading Solido state as of a past slot, from a dump of accounts.
+//!
+//! RPC nodes only serve the current state of accounts. Archive nodes backed by
+//! BigTable keep blocks and transactions, but not account state, so to inspect
+//! an instance after the fact, we need the accounts as they were at that slot.
+//! An account dump is a json file with the slot, and the accounts in the format
+//! of `getProgramAccounts` and `solana account --output json`:
+//!
+//! ```json
+//! {
+//!   "slot": 123456789,
+//!   "accounts": [
+//!     {
+//!       "pubkey": "49Yi1TKkNyYjPAFdR9LBvoHcUjuPX4Df5T5yv39w2XTn",
+//!       "account": {
+//!         "lamports": 1000000,
+//!         "data": ["<base64>", "base64"],
+//!         "owner": "CrX7kMhLC3cSsXJdT7JDgqrRVWGnUpX3gfEfxxU2NVLi",
+//!         "executable": false,
+//!         "rentEpoch": 300
+//!       }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The `--write-account-dump` option writes the accounts that a command read
+//! in this format, so a dump taken periodically can later be inspected with
+//! `--account-dump`. In that mode, [`DumpRpc`] serves the accounts from the
+//! dump, and refuses to send transactions. Inflation rewards of past epochs
+//! are kept by archive nodes, we still read those from the cluster.
+//!
+//! Dumps also make fixtures for tests: `MockRpc::from_account_dump` serves the
+//! accounts of a dump without any RPC, so a dump written by `solido
+//! explain-maintenance --write-account-dump` turns a state of a real instance
+//! into a deterministic test of `SolidoState` and the maintenance decisions.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_response::{
+    Response, RpcKeyedAccount, RpcResponseContext, RpcResult, RpcSimulateTransactionResult,
+};
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Epoch, Slot};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction};
+
+use crate::error::{CliError, Error};
+use crate::rpc::{
+    AccountFilter, BlockProduction, InflationReward, Rpc, SignatureInfo, TransactionInfo,
+};
+
+/// Accounts as they were at a given slot.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountDump {
+    pub slot: Slot,
+    pub accounts: Vec<RpcKeyedAccount>,
+}
+
+impl AccountDump {
+    /// Create a dump of the accounts that exist, ordered by address.
+    pub fn new(slot: Slot, accounts: &HashMap<Pubkey, Option<Account>>) -> AccountDump {
+        let mut accounts: Vec<RpcKeyedAccount> = accounts
+            .iter()
+            .filter_map(|(pubkey, account)| {
+                account.as_ref().map(|account| RpcKeyedAccount {
+                    pubkey: pubkey.to_string(),
+                    account: UiAccount::encode(
+                        pubkey,
+                        account,
+                        UiAccountEncoding::Base64,
+                        None,
+                        None,
+                    ),
+                })
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        AccountDump { slot, accounts }
+    }
+
+    pub fn read(path: &Path) -> Result<AccountDump, Error> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| CliError::with_cause("Failed to read account dump.", err))?;
+        serde_json::from_str(&json)
+            .map_err(|err| CliError::with_cause("Failed to parse account dump.", err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).expect("Dumps can always be serialized.");
+        fs::write(path, json)
+            .map_err(|err| CliError::with_cause("Failed to write account dump.", err))
+    }
+
+    /// Decode the accounts, failing on the first one that we cannot decode.
+    pub(crate) fn decode(&self) -> Result<HashMap<Pubkey, Account>, Error> {
+        self.accounts
+            .iter()
+            .map(|keyed_account| {
+                let pubkey = Pubkey::from_str(&keyed_account.pubkey)
+                    .map_err(|err| CliError::with_cause("Invalid address in account dump.", err))?;
+                let account = keyed_account.account.decode::<Account>().ok_or_else(|| {
+                    CliError::with_cause(
+                        "Failed to decode account in account dump.",
+                        &keyed_account.pubkey,
+                    )
+                })?;
+                Ok((pubkey, account))
+            })
+            .collect()
+    }
+}
+
+fn read_only_error(call: &str) -> ClientError {
+    ClientErrorKind::Custom(format!(
+        "{} is not available when reading from an account dump.",
+        call
+    ))
+    .into()
+}
+
+/// An [`Rpc`] that serves accounts from an [`AccountDump`], for read-only
+/// inspection of past state.
+pub struct DumpRpc {
+    slot: Slot,
+    accounts: HashMap<Pubkey, Account>,
+
+    /// The cluster, to read what archive nodes do keep for past slots.
+    archive: RpcClient,
+}
+
+impl DumpRpc {
+    pub fn new(dump: &AccountDump, archive: RpcClient) -> Result<DumpRpc, Error> {
+        Ok(DumpRpc {
+            slot: dump.slot,
+            accounts: dump.decode()?,
+            archive,
+        })
+    }
+}
+
+impl Rpc for DumpRpc {
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        _commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>> {
+        Ok(Response {
+            context: RpcResponseContext { slot: self.slot },
+            value: pubkeys
+                .iter()
+                .map(|pubkey| self.accounts.get(pubkey).cloned())
+                .collect(),
+        })
+    }
+
+    fn simulate_transaction(
+        &self,
+        _transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        Err(read_only_error("Simulating transactions"))
+    }
+
+    fn send_transaction(
+        &self,
+        _transaction: &Transaction,
+        _commitment: CommitmentConfig,
+        _skip_preflight: bool,
+    ) -> ClientResult<Signature> {
+        Err(read_only_error("Sending transactions"))
+    }
+
+    fn get_signature_status(
+        &self,
+        _signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        Err(read_only_error("Sending transactions"))
+    }
+
+    fn is_blockhash_valid(&self, _blockhash: &Hash) -> ClientResult<bool> {
+        Err(read_only_error("Sending transactions"))
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Err(read_only_error("Sending transactions"))
+    }
+
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        _transaction: &Transaction,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Signature> {
+        Err(read_only_error("Sending transactions"))
+    }
+
+    fn get_recent_prioritization_fees(&self, _pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>> {
+        Err(read_only_error("getRecentPrioritizationFees"))
+    }
+
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        Ok(self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.owner == *program_id)
+            .filter(|(_, account)| filters.iter().all(|filter| filter.matches(account)))
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .collect())
+    }
+
+    fn get_inflation_reward(
+        &self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>> {
+        Rpc::get_inflation_reward(&self.archive, pubkeys, epoch)
+    }
+
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>> {
+        // The cluster only knows the current stakes, which would not match the dump.
+        Err(read_only_error("getVoteAccounts"))
+    }
+
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>> {
+        Err(read_only_error("getBlockProduction"))
+    }
+
+    fn get_signatures_for_address(
+        &self,
+        _address: &Pubkey,
+        _before: Option<&str>,
+        _until: Option<&str>,
+        _limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>> {
+        // The most recent transactions would be those after the dump.
+        Err(read_only_error("getSignaturesForAddress"))
+    }
+
+    fn get_transaction(&self, signature: &str) -> ClientResult<Option<TransactionInfo>> {
+        // Confirmed transactions do not change, the dump does not affect them.
+        Rpc::get_transaction(&self.archive, signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dump_round_trip() {
+        let address = Pubkey::new_unique();
+        let account = Account {
+            lamports: 1_000_000,
+            data: vec![1, 2, 3],
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 300,
+        };
+        let mut accounts = HashMap::new();
+        accounts.insert(address, Some(account.clone()));
+        // Accounts that did not exist are not in the dump.
+        accounts.insert(Pubkey::new_unique(), None);
+
+        let dump = AccountDump::new(123, &accounts);
+        assert_eq!(dump.accounts.len(), 1);
+        let json = serde_json::to_string(&dump).unwrap();
+        let dump: AccountDump = serde_json::from_str(&json).unwrap();
+
+        let rpc = DumpRpc::new(&dump, RpcClient::new("http://127.0.0.1:8899".to_string()))
+            .ok()
+            .unwrap();
+        let response = rpc
+            .get_multiple_accounts_with_commitment(
+                &[address, Pubkey::new_unique()],
+                CommitmentConfig::confirmed(),
+            )
+            .unwrap();
+        assert_eq!(response.context.slot, 123);
+        assert_eq!(response.value, vec![Some(account.clone()), None]);
+
+        let program_accounts = rpc
+            .get_program_accounts(&account.owner, &[AccountFilter::DataSize(3)])
+            .unwrap();
+        assert_eq!(program_accounts, vec![(address, account)]);
+        assert!(rpc
+            .get_program_accounts(&address, &[AccountFilter::DataSize(3)])
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,461 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Alerts about conditions that need the attention of an operator.
+//!
+//! Unlike the events in `crate::webhook`, which describe everything that
+//! happens, an alert describes a condition that persists: maintenance keeps
+//! failing, the exchange rate was not updated after the epoch boundary, or the
+//! maintainer is running out of SOL. We notify once when a condition starts
+//! ("firing"), and once when it clears ("resolved"), rather than on every poll.
+//!
+//! Notifications are POSTed either as a generic json object, or in the format
+//! of Slack incoming webhooks (a json object with a `text` field), which many
+//! other chat services accept as well.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+use solana_program::clock::{Epoch, Slot};
+use solana_program::pubkey::Pubkey;
+
+use lido::token::Lamports;
+use lido::util::serialize_b58;
+
+use crate::logging::Event;
+use crate::maintenance::SolidoState;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AlertFormat {
+    /// A json object with the status, alert type, message, and alert fields.
+    Json,
+
+    /// A json object with only a `text` field, for Slack incoming webhooks.
+    Slack,
+}
+
+impl FromStr for AlertFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<AlertFormat, &'static str> {
+        match s {
+            "json" => Ok(AlertFormat::Json),
+            "slack" => Ok(AlertFormat::Slack),
+            _ => Err("Invalid alert format, expected 'json' or 'slack'."),
+        }
+    }
+}
+
+/// When to alert. Every alert can be disabled individually.
+#[derive(Clone, Debug, Default)]
+pub struct AlertOptions {
+    /// Alert when this many consecutive iterations failed.
+    pub max_consecutive_errors: Option<u64>,
+
+    /// Alert when the exchange rate was not updated this many slots after the epoch started.
+    pub max_exchange_rate_delay_slots: Option<Slot>,
+
+    /// Alert when the balance of the fee payer, usually the maintainer, drops below this amount.
+    pub min_maintainer_balance: Option<Lamports>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    ErrorStreak {
+        consecutive_errors: u64,
+    },
+    StaleExchangeRate {
+        epoch: Epoch,
+        slots_into_epoch: Slot,
+    },
+    MaintainerBalanceLow {
+        #[serde(serialize_with = "serialize_b58")]
+        maintainer: Pubkey,
+        #[serde(rename = "balance_lamports")]
+        balance: Lamports,
+        #[serde(rename = "threshold_lamports")]
+        threshold: Lamports,
+    },
+    FeePayerBalanceLow {
+        #[serde(serialize_with = "serialize_b58")]
+        fee_payer: Pubkey,
+        #[serde(rename = "balance_lamports")]
+        balance: Lamports,
+        #[serde(rename = "threshold_lamports")]
+        threshold: Lamports,
+    },
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Alert::ErrorStreak { consecutive_errors } => write!(
+                f,
+                "Maintenance failed {} times in a row.",
+                consecutive_errors
+            ),
+            Alert::StaleExchangeRate {
+                epoch,
+                slots_into_epoch,
+            } => write!(
+                f,
+                "The exchange rate was not updated in epoch {}, {} slots after the epoch started.",
+                epoch, slots_into_epoch
+            ),
+            Alert::MaintainerBalanceLow {
+                maintainer,
+                balance,
+                threshold,
+            } => write!(
+                f,
+                "Balance of maintainer {} is {}, below the threshold of {}.",
+                maintainer, balance, threshold
+            ),
+            Alert::FeePayerBalanceLow {
+                fee_payer,
+                balance,
+                threshold,
+            } => write!(
+                f,
+                "Balance of fee payer {} is {}, below the threshold of {}.",
+                fee_payer, balance, threshold
+            ),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Notification {
+    pub status: AlertStatus,
+    pub alert: Alert,
+}
+
+/// Tracks which alerts are firing across daemon iterations.
+#[derive(Default)]
+pub struct AlertDetector {
+    options: AlertOptions,
+    consecutive_errors: u64,
+    error_streak_firing: bool,
+    stale_exchange_rate_firing: bool,
+    maintainer_balance_low_firing: bool,
+}
+
+/// Record that the condition for `alert` holds or not, and notify if that changed.
+fn update(firing: &mut bool, holds: bool, alert: Alert, notifications: &mut Vec<Notification>) {
+    if holds != *firing {
+        *firing = holds;
+        notifications.push(Notification {
+            status: if holds {
+                AlertStatus::Firing
+            } else {
+                AlertStatus::Resolved
+            },
+            alert,
+        });
+    }
+}
+
+impl AlertDetector {
+    pub fn new(options: AlertOptions) -> AlertDetector {
+        AlertDetector {
+            options,
+            ..AlertDetector::default()
+        }
+    }
+
+    /// Return the notifications for one daemon iteration.
+    ///
+    /// `solido` is `None` if the iteration failed. In that case, we know
+    /// nothing new about the state, so only the error streak can change.
+    pub fn detect(&mut self, solido: Option<&SolidoState>) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+
+        let state = match solido {
+            Some(state) => state,
+            None => {
+                self.consecutive_errors += 1;
+                if let Some(max_errors) = self.options.max_consecutive_errors {
+                    update(
+                        &mut self.error_streak_firing,
+                        self.consecutive_errors >= max_errors,
+                        Alert::ErrorStreak {
+                            consecutive_errors: self.consecutive_errors,
+                        },
+                        &mut notifications,
+                    );
+                }
+                return notifications;
+            }
+        };
+
+        update(
+            &mut self.error_streak_firing,
+            false,
+            Alert::ErrorStreak {
+                consecutive_errors: self.consecutive_errors,
+            },
+            &mut notifications,
+        );
+        self.consecutive_errors = 0;
+
+        if let Some(max_delay_slots) = self.options.max_exchange_rate_delay_slots {
+            let slots_into_epoch = state.slots_into_epoch();
+            let is_stale = state.solido.exchange_rate.computed_in_epoch < state.clock.epoch;
+            update(
+                &mut self.stale_exchange_rate_firing,
+                is_stale && slots_into_epoch > max_delay_slots,
+                Alert::StaleExchangeRate {
+                    epoch: state.clock.epoch,
+                    slots_into_epoch,
+                },
+                &mut notifications,
+            );
+        }
+
+        if let Some(threshold) = self.options.min_maintainer_balance {
+            // The account that runs out of SOL is the one that pays the fees.
+            let balance = state.fee_payer_balance;
+            let alert = if state.fee_payer_address == state.maintainer_address {
+                Alert::MaintainerBalanceLow {
+                    maintainer: state.maintainer_address,
+                    balance,
+                    threshold,
+                }
+            } else {
+                Alert::FeePayerBalanceLow {
+                    fee_payer: state.fee_payer_address,
+                    balance,
+                    threshold,
+                }
+            };
+            update(
+                &mut self.maintainer_balance_low_firing,
+                balance < threshold,
+                alert,
+                &mut notifications,
+            );
+        }
+
+        notifications
+    }
+}
+
+#[derive(Serialize)]
+struct JsonBody<'a> {
+    #[serde(serialize_with = "serialize_b58")]
+    solido_address: Pubkey,
+    status: AlertStatus,
+    message: String,
+    #[serde(flatten)]
+    alert: &'a Alert,
+}
+
+/// Return the body of the request for a notification.
+fn format_notification(
+    format: AlertFormat,
+    solido_address: &Pubkey,
+    notification: &Notification,
+) -> serde_json::Value {
+    match format {
+        AlertFormat::Json => serde_json::to_value(JsonBody {
+            solido_address: *solido_address,
+            status: notification.status,
+            message: notification.alert.to_string(),
+            alert: &notification.alert,
+        })
+        .expect("Alerts can always be serialized."),
+        AlertFormat::Slack => {
+            let status = match notification.status {
+                AlertStatus::Firing => "FIRING",
+                AlertStatus::Resolved => "RESOLVED",
+            };
+            serde_json::json!({
+                "text": format!(
+                    "[{}] Solido {}: {}",
+                    status, solido_address, notification.alert
+                ),
+            })
+        }
+    }
+}
+
+/// Delivers alert notifications to a set of endpoints.
+pub struct Alerter {
+    endpoints: Vec<String>,
+    format: AlertFormat,
+    client: reqwest::blocking::Client,
+    detector: AlertDetector,
+}
+
+impl Alerter {
+    pub fn new(endpoints: Vec<String>, format: AlertFormat, options: AlertOptions) -> Alerter {
+        let client = reqwest::blocking::Client::builder()
+            // Don't let a slow endpoint hold up maintenance for long.
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create http client.");
+        Alerter {
+            endpoints,
+            format,
+            client,
+            detector: AlertDetector::new(options),
+        }
+    }
+
+    /// Detect alerts for one daemon iteration, and deliver the notifications.
+    ///
+    /// Delivery is best-effort: we do not retry, errors are only logged.
+    pub fn observe(&mut self, solido_address: &Pubkey, solido: Option<&SolidoState>) {
+        for notification in self.detector.detect(solido) {
+            let body = format_notification(self.format, solido_address, &notification);
+            for endpoint in &self.endpoints {
+                let result = self
+                    .client
+                    .post(endpoint)
+                    .json(&body)
+                    .send()
+                    .and_then(|response| response.error_for_status());
+                if let Err(err) = result {
+                    Event::error(
+                        "alert_delivery_error",
+                        format!("Error while delivering alert to {}: {}", endpoint, err),
+                    )
+                    .field("endpoint", endpoint)
+                    .emit();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::signer::Signer;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    fn firing(alert: Alert) -> Notification {
+        Notification {
+            status: AlertStatus::Firing,
+            alert,
+        }
+    }
+
+    fn resolved(alert: Alert) -> Notification {
+        Notification {
+            status: AlertStatus::Resolved,
+            alert,
+        }
+    }
+
+    #[test]
+    fn test_error_streak_fires_once_and_resolves() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let state = sim.read_state();
+        let mut detector = AlertDetector::new(AlertOptions {
+            max_consecutive_errors: Some(3),
+            ..AlertOptions::default()
+        });
+
+        assert_eq!(detector.detect(None), vec![]);
+        assert_eq!(detector.detect(None), vec![]);
+        assert_eq!(
+            detector.detect(None),
+            vec![firing(Alert::ErrorStreak {
+                consecutive_errors: 3
+            })]
+        );
+        assert_eq!(detector.detect(None), vec![]);
+        assert_eq!(
+            detector.detect(Some(&state)),
+            vec![resolved(Alert::ErrorStreak {
+                consecutive_errors: 4
+            })]
+        );
+        assert_eq!(detector.detect(Some(&state)), vec![]);
+    }
+
+    #[test]
+    fn test_stale_exchange_rate_and_low_balance() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let mut state = sim.read_state();
+        let mut detector = AlertDetector::new(AlertOptions {
+            max_exchange_rate_delay_slots: Some(1_000),
+            min_maintainer_balance: Some(Lamports(1_000_000_000)),
+            ..AlertOptions::default()
+        });
+
+        state.solido.exchange_rate.computed_in_epoch = state.clock.epoch;
+        state.fee_payer_balance = Lamports(2_000_000_000);
+        assert_eq!(detector.detect(Some(&state)), vec![]);
+
+        // A new epoch started, but the exchange rate was not updated for a while.
+        let epoch = state.clock.epoch + 1;
+        state.clock.epoch = epoch;
+        state.clock.slot = state.epoch_schedule.get_first_slot_in_epoch(epoch) + 500;
+        assert_eq!(detector.detect(Some(&state)), vec![]);
+        state.clock.slot += 1_000;
+        state.fee_payer_balance = Lamports(500_000_000);
+        assert_eq!(
+            detector.detect(Some(&state)),
+            vec![
+                firing(Alert::StaleExchangeRate {
+                    epoch,
+                    slots_into_epoch: 1_500,
+                }),
+                firing(Alert::MaintainerBalanceLow {
+                    maintainer: maintainer.pubkey(),
+                    balance: Lamports(500_000_000),
+                    threshold: Lamports(1_000_000_000),
+                }),
+            ]
+        );
+        assert_eq!(detector.detect(Some(&state)), vec![]);
+
+        state.solido.exchange_rate.computed_in_epoch = epoch;
+        let notifications = detector.detect(Some(&state));
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].status, AlertStatus::Resolved);
+    }
+
+    #[test]
+    fn test_format_notification() {
+        let solido_address = Pubkey::new(&[2; 32]);
+        let notification = firing(Alert::ErrorStreak {
+            consecutive_errors: 5,
+        });
+        assert_eq!(
+            format_notification(AlertFormat::Json, &solido_address, &notification),
+            serde_json::json!({
+                "solido_address": solido_address.to_string(),
+                "status": "firing",
+                "message": "Maintenance failed 5 times in a row.",
+                "alert": "error_streak",
+                "consecutive_errors": 5,
+            })
+        );
+        assert_eq!(
+            format_notification(AlertFormat::Slack, &solido_address, &notification),
+            serde_json::json!({
+                "text": format!(
+                    "[FIRING] Solido {}: Maintenance failed 5 times in a row.",
+                    solido_address
+                ),
+            })
+        );
+    }
+}
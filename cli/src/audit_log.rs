@@ -0,0 +1,265 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Append-only log of the transactions that the maintenance daemon submits.
+//!
+//! Every transaction gets one line of JSON, with the wall-clock time, the slot
+//! of the state it was based on, the kind of transaction, its signature, the
+//! fee, and whether it succeeded. This is a record on the host that does not
+//! depend on an RPC node or a block explorer.
+//!
+//! The log is never rewritten. When it grows past the maximum size, we rename
+//! `audit.log` to `audit.log.1`, `audit.log.1` to `audit.log.2`, and so on,
+//! and delete the oldest file beyond the number of files to keep.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::Value;
+use solana_program::clock::Slot;
+use solana_program::pubkey::Pubkey;
+
+use lido::token::Lamports;
+use lido::util::serialize_b58;
+
+use crate::logging::{format_rfc3339, get_instance};
+
+/// Whether a transaction that we submitted succeeded.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    /// The transaction was confirmed.
+    Confirmed,
+
+    /// Signing or sending the transaction failed. It might still have been
+    /// executed, see `error::might_have_executed`.
+    Failed { error: String },
+}
+
+/// One transaction that the daemon submitted.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    /// Wall-clock time at which the daemon finished submitting the transaction.
+    pub timestamp: String,
+
+    /// Value of the `instance` label, when the daemon maintains multiple instances.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido_address: Pubkey,
+
+    /// The slot of the state that the transaction was based on.
+    pub slot: Slot,
+
+    /// The kind of transaction, the operation name of maintenance.
+    pub operation: &'static str,
+
+    /// Details of the transaction, as in the `output` field of the logs.
+    pub output: Value,
+
+    /// The signature of the transaction, base58-encoded, if it was sent.
+    pub transaction_signature: Option<String>,
+
+    /// The base fee and the priority fee, if we know them.
+    pub fee_lamports: Option<u64>,
+
+    pub result: AuditResult,
+}
+
+impl AuditRecord {
+    pub fn new<T: Serialize>(
+        time: SystemTime,
+        solido_address: Pubkey,
+        slot: Slot,
+        operation: &'static str,
+        output: &T,
+    ) -> AuditRecord {
+        AuditRecord {
+            timestamp: format_rfc3339(time),
+            instance: get_instance(),
+            solido_address,
+            slot,
+            operation,
+            output: serde_json::to_value(output).unwrap_or(Value::Null),
+            transaction_signature: None,
+            fee_lamports: None,
+            result: AuditResult::Confirmed,
+        }
+    }
+}
+
+/// The base fee per signature on mainnet-beta and testnet.
+///
+/// This is half of `DEFAULT_TARGET_LAMPORTS_PER_SIGNATURE`, which is what the
+/// cluster charges while the fee rate governor is at its target.
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// Return the fee of a transaction with `num_signatures` signatures, and the given priority fee.
+///
+/// The base fee is fixed per signature, we don't read it from the cluster.
+pub fn get_transaction_fee(num_signatures: u64, priority_fee: Lamports) -> Lamports {
+    Lamports(LAMPORTS_PER_SIGNATURE * num_signatures + priority_fee.0)
+}
+
+/// Appends records to a file, and rotates it when it grows too large.
+pub struct AuditLog {
+    path: PathBuf,
+
+    /// Rotate the file when appending would make it larger than this.
+    max_bytes: u64,
+
+    /// How many rotated files to keep besides the current one.
+    keep_files: u32,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, max_bytes: u64, keep_files: u32) -> AuditLog {
+        AuditLog {
+            path,
+            max_bytes,
+            keep_files,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let oldest = self.rotated_path(self.keep_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..self.keep_files).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    /// Append the record as one line, and flush it to disk.
+    pub fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let current_bytes = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        // A file that holds a record is never empty after rotation, even if
+        // the record alone exceeds the maximum size.
+        if current_bytes > 0 && current_bytes + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_record(slot: Slot) -> AuditRecord {
+        let mut record = AuditRecord::new(
+            SystemTime::UNIX_EPOCH,
+            Pubkey::default(),
+            slot,
+            "UpdateExchangeRate",
+            &"UpdateExchangeRate",
+        );
+        record.transaction_signature = Some("5xyz".to_string());
+        record.fee_lamports = Some(get_transaction_fee(1, Lamports(1_000)).0);
+        record
+    }
+
+    fn read_slots(path: &Path) -> Vec<u64> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["slot"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_appends_one_line_per_record() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(directory.path().join("audit.log"), 1_000_000, 3);
+        log.append(&new_record(1)).unwrap();
+
+        let mut failed = new_record(2);
+        failed.transaction_signature = None;
+        failed.result = AuditResult::Failed {
+            error: "Blockhash not found".to_string(),
+        };
+        log.append(&failed).unwrap();
+
+        let contents = fs::read_to_string(log.path()).unwrap();
+        let records: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timestamp"], "1970-01-01T00:00:00.000Z");
+        assert_eq!(records[0]["operation"], "UpdateExchangeRate");
+        assert_eq!(records[0]["fee_lamports"], 6_000);
+        assert_eq!(records[0]["result"], "confirmed");
+        assert_eq!(records[1]["transaction_signature"], Value::Null);
+        assert_eq!(
+            records[1]["result"]["failed"]["error"],
+            "Blockhash not found"
+        );
+    }
+
+    #[test]
+    fn test_rotates_and_keeps_limited_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("audit.log");
+        let line_bytes = serde_json::to_vec(&new_record(0)).unwrap().len() as u64 + 1;
+        // Two records fit in a file, the third one goes to the next file.
+        let log = AuditLog::new(path.clone(), 2 * line_bytes, 2);
+        for slot in 0..7 {
+            log.append(&new_record(slot)).unwrap();
+        }
+
+        assert_eq!(read_slots(&path), vec![6]);
+        assert_eq!(
+            read_slots(&directory.path().join("audit.log.1")),
+            vec![4, 5]
+        );
+        assert_eq!(
+            read_slots(&directory.path().join("audit.log.2")),
+            vec![2, 3]
+        );
+        // The oldest records were deleted.
+        assert!(!directory.path().join("audit.log.3").exists());
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use std::{
+    fmt::Debug,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -14,39 +15,172 @@ use solana_sdk::pubkey::{ParsePubkeyError, Pubkey};
 use lido::token::Lamports;
 use lido::token::StLamports;
 
+use crate::alerts::AlertFormat;
+use crate::error::{CliError, Error};
+use crate::index::IndexFormat;
+use crate::logging::LogFormat;
+use crate::manifest::{ConfigOption, Shell};
+use crate::rpc::Commitment;
+use crate::validators_report::ValidatorSortKey;
+
+/// Read an option from the config file, or return an error message if it is invalid.
 pub fn get_option_from_config<T: FromStr>(
     name: &'static str,
     config_file: Option<&ConfigFile>,
-) -> Option<T> {
-    let config_file = config_file?;
-    let value = config_file.values.get(name)?;
-    if let Value::String(str_value) = value {
-        match T::from_str(str_value) {
-            Err(_) => {
-                eprintln!("Could not convert {} from string.", str_value);
-                std::process::exit(1);
+) -> Result<Option<T>, String> {
+    let config_file = match config_file {
+        Some(config_file) => config_file,
+        None => return Ok(None),
+    };
+    let str_value = match config_file.values.get(name) {
+        Some(Value::String(str_value)) => str_value.clone(),
+        Some(Value::Number(number)) => number.to_string(),
+        Some(Value::Bool(b)) => b.to_string(),
+        // `validate_config_file` reports values of other types.
+        _ => return Ok(None),
+    };
+    match T::from_str(&str_value) {
+        Err(_) => Err(format!(
+            "Invalid value \"{}\" for \"{}\" in the config file.",
+            str_value, name
+        )),
+        Ok(t) => Ok(Some(t)),
+    }
+}
+
+/// Describe the values of an option that are set in more than one place, if they differ.
+///
+/// The command line takes precedence over the config file, which takes
+/// precedence over the environment.
+pub fn describe_conflict<T: Debug>(
+    name: &str,
+    env_var_name: &str,
+    from_cli: Option<&T>,
+    from_config: Option<&T>,
+    from_env: Option<&T>,
+) -> Option<String> {
+    let sources: Vec<(String, String)> = vec![
+        ("on the command line".to_string(), from_cli),
+        ("in the config file".to_string(), from_config),
+        (format!("in {}", env_var_name), from_env),
+    ]
+    .into_iter()
+    .filter_map(|(source, value)| value.map(|value| (source, format!("{:?}", value))))
+    .collect();
+    if sources.iter().all(|(_, value)| *value == sources[0].1) {
+        return None;
+    }
+    let values: Vec<String> = sources
+        .iter()
+        .map(|(source, value)| format!("{} {}", value, source))
+        .collect();
+    Some(format!(
+        "{} is set to {}. Using the value {}.",
+        name,
+        values.join(", "),
+        sources[0].0,
+    ))
+}
+
+/// Return the number of single-character edits that turn `a` into `b`.
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Check the config file against the options of all commands.
+///
+/// Keys that no command knows are most likely typos, which would otherwise
+/// silently fall back to the default. We also check the values of addresses
+/// and paths, also for keys of other commands, so a config file shared between
+/// commands is valid for all of them.
+pub fn validate_config_file(
+    config_file: &ConfigFile,
+    options: &[ConfigOption],
+) -> Result<(), Vec<String>> {
+    let values = match config_file.values.as_object() {
+        Some(values) => values,
+        None => {
+            return Err(vec![
+                "The config file must contain a json object.".to_string()
+            ])
+        }
+    };
+    let mut problems = Vec::new();
+    for (key, value) in values {
+        let option = match options.iter().find(|option| option.key == key) {
+            Some(option) => option,
+            None => {
+                let suggestion = options
+                    .iter()
+                    .map(|option| (get_edit_distance(key, option.key), option.key))
+                    .filter(|(distance, _)| *distance <= 3)
+                    .min();
+                problems.push(match suggestion {
+                    Some((_, suggestion)) => format!(
+                        "Unknown key \"{}\" in the config file, did you mean \"{}\"?",
+                        key, suggestion
+                    ),
+                    None => format!("Unknown key \"{}\" in the config file.", key),
+                });
+                continue;
             }
-            Ok(t) => Some(t),
+        };
+        let str_value = match value {
+            Value::String(str_value) => str_value,
+            Value::Number(..) | Value::Bool(..) => continue,
+            _ => {
+                problems.push(format!(
+                    "Expected a string, number, or boolean for \"{}\" in the config file, found {}.",
+                    key, value
+                ));
+                continue;
+            }
+        };
+        match option.type_name {
+            "Pubkey" if Pubkey::from_str(str_value).is_err() => problems.push(format!(
+                "Invalid address \"{}\" for \"{}\" in the config file.",
+                str_value, key
+            )),
+            "PathBuf" if str_value.starts_with('~') => problems.push(format!(
+                "The path \"{}\" for \"{}\" in the config file starts with '~', \
+                but paths in the config file are not expanded. Use an absolute path.",
+                str_value, key
+            )),
+            _ => {}
         }
+    }
+    if problems.is_empty() {
+        Ok(())
     } else {
-        // TODO: Support numbers
-        None
+        Err(problems)
     }
 }
 
-pub fn get_option_from_env<T: FromStr>(str_key: &str) -> Option<T> {
-    let env_var = (std::env::var(str_key)).ok()?;
+/// Read an option from the environment, or return an error message if it is invalid.
+pub fn get_option_from_env<T: FromStr>(str_key: &str) -> Result<Option<T>, String> {
+    let env_var = match std::env::var(str_key) {
+        Ok(env_var) => env_var,
+        Err(_) => return Ok(None),
+    };
     match T::from_str(&env_var) {
-        Ok(t) => Some(t),
-        Err(_) => {
-            eprintln!(
-                "Could not convert environment variable {}={} from string.",
-                str_key, env_var
-            );
-            std::process::exit(1);
-        }
+        Ok(t) => Ok(Some(t)),
+        Err(_) => Err(format!(
+            "Could not convert environment variable {}={} from string.",
+            str_key, env_var
+        )),
     }
 }
+
 /// Generates a struct that derives `Clap` for usage with a config file.
 ///
 /// This macro avoids code repetition by implementing a function that sweeps
@@ -126,30 +260,85 @@ macro_rules! cli_opt_struct {
             /// fields.
             #[allow(dead_code)]
             pub fn merge_with_config_and_environment(&mut self, config_file: Option<&ConfigFile>) {
-                let mut failed = false;
+                if let Err(problems) = self.try_merge_with_config_and_environment(config_file) {
+                    for problem in problems {
+                        eprintln!("{}", problem);
+                    }
+                    std::process::exit(1);
+                }
+            }
+
+            /// Like `merge_with_config_and_environment`, but returns the
+            /// problems instead of exiting, for reloading a config at runtime.
+            #[allow(dead_code)]
+            pub fn try_merge_with_config_and_environment(
+                &mut self,
+                config_file: Option<&ConfigFile>,
+            ) -> Result<(), Vec<String>> {
+                let mut problems = Vec::new();
                 $(
                     let from_cli = self.$field.take();
                     let str_field = stringify!($field);
-                    let from_config = get_option_from_config(str_field, config_file);
+                    let from_config = get_option_from_config(str_field, config_file)
+                        .unwrap_or_else(|problem| {
+                            problems.push(problem);
+                            None
+                        });
 
                     #[allow(unused_mut, unused_assignments)]
                     let mut default = None;
                     $(default = Some($default);)?
                     let env_var_name = format!("SOLIDO_{}", str_field.to_ascii_uppercase());
-                    let env_option = get_option_from_env(&env_var_name);
+                    let env_option = get_option_from_env(&env_var_name)
+                        .unwrap_or_else(|problem| {
+                            problems.push(problem);
+                            None
+                        });
+                    if let Some(conflict) = describe_conflict(
+                        &format!("--{}", str_field.replace("_", "-")),
+                        &env_var_name,
+                        from_cli.as_ref(),
+                        from_config.as_ref(),
+                        env_option.as_ref(),
+                    ) {
+                        eprintln!("Warning: {}", conflict);
+                    }
                     // Sets the field with the argument or the config field.
                     self.$field = from_cli.or(from_config).or(env_option).or(default);
                     if self.$field.is_none() {
-                        failed = true;
-                        eprintln!("Expected --{} to be provided on the command line, set in config file with key \"{}\", or specified in an environment variable with key \"{}\".",
-                        str_field.replace("_", "-"), str_field, env_var_name);
+                        problems.push(format!("Expected --{} to be provided on the command line, set in config file with key \"{}\", or specified in an environment variable with key \"{}\".",
+                        str_field.replace("_", "-"), str_field, env_var_name));
                     }
                 )*
-                if failed {
-                    std::process::exit(1);
+                if problems.is_empty() {
+                    Ok(())
+                } else {
+                    Err(problems)
                 }
             }
 
+            /// Describe the options, for `solido manifest`.
+            #[allow(dead_code)]
+            pub fn config_options() -> Vec<crate::manifest::ConfigOption> {
+                vec![
+                    $(
+                        crate::manifest::ConfigOption {
+                            key: stringify!($field),
+                            type_name: stringify!($type),
+                            default: {
+                                #[allow(unused_mut, unused_assignments)]
+                                let mut default = None;
+                                $(
+                                    let value: $type = $default;
+                                    default = Some(crate::manifest::format_default(&value));
+                                )?
+                                default
+                            },
+                        },
+                    )*
+                ]
+            }
+
             $(
                 // Implement a getter for every field in the struct
                 #[allow(dead_code)]
@@ -179,6 +368,146 @@ impl FromStr for PubkeyVec {
     }
 }
 
+/// A Solido instance for `run-maintainer --instances`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceSpec {
+    /// Name of the instance, the value of the `instance` label on its metrics.
+    pub name: String,
+    pub solido_program_id: Pubkey,
+    pub solido_address: Pubkey,
+    /// Comma-separated RPC urls, like `--cluster`.
+    pub cluster: String,
+}
+
+/// Type to represent the list of instances of `run-maintainer --instances`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstanceSpecs(pub Vec<InstanceSpec>);
+
+/// Constructs an `InstanceSpecs` from a string by splitting it by ';', and every
+/// instance by ':' into a name, program id, Solido address, and RPC urls. The
+/// urls come last, so they can contain ':' themselves.
+impl FromStr for InstanceSpecs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut specs = Vec::new();
+        for spec in s.split(';').map(|spec| spec.trim()) {
+            if spec.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = spec.splitn(4, ':').collect();
+            if parts.len() != 4 {
+                return Err(format!(
+                    "Expected <name>:<program_id>:<solido_address>:<rpc_urls>, found \"{}\".",
+                    spec
+                ));
+            }
+            let parse_pubkey = |key: &str| {
+                Pubkey::from_str(key).map_err(|_| format!("Invalid address \"{}\".", key))
+            };
+            specs.push(InstanceSpec {
+                name: parts[0].to_string(),
+                solido_program_id: parse_pubkey(parts[1])?,
+                solido_address: parse_pubkey(parts[2])?,
+                cluster: parts[3].to_string(),
+            });
+        }
+        Ok(InstanceSpecs(specs))
+    }
+}
+
+/// Type to represent the destinations of `run-maintainer --developer-fee-splits`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeSplits(pub Vec<(Pubkey, u8)>);
+
+/// Constructs a `FeeSplits` from a string by splitting it by ',', and every
+/// destination by ':' into an address and a percentage. The percentages must
+/// add up to 100.
+impl FromStr for FeeSplits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut splits = Vec::new();
+        for split in s.split(',').map(|split| split.trim()) {
+            if split.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = split.split(':').collect();
+            if parts.len() != 2 {
+                return Err(format!(
+                    "Expected <address>:<percentage>, found \"{}\".",
+                    split
+                ));
+            }
+            let address = Pubkey::from_str(parts[0])
+                .map_err(|_| format!("Invalid address \"{}\".", parts[0]))?;
+            let percentage = match parts[1].parse::<u8>() {
+                Ok(percentage) if (1..=100).contains(&percentage) => percentage,
+                _ => {
+                    return Err(format!(
+                        "Invalid percentage \"{}\", expected a number from 1 to 100.",
+                        parts[1]
+                    ))
+                }
+            };
+            splits.push((address, percentage));
+        }
+        let total: u32 = splits
+            .iter()
+            .map(|(_, percentage)| *percentage as u32)
+            .sum();
+        if !splits.is_empty() && total != 100 {
+            return Err(format!(
+                "The percentages add up to {}, they must add up to 100.",
+                total
+            ));
+        }
+        Ok(FeeSplits(splits))
+    }
+}
+
+/// Type to represent the constant labels of `run-maintainer --metrics-labels`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsLabels(pub Vec<(String, String)>);
+
+/// Constructs a `MetricsLabels` from a string by splitting it by ',', and every
+/// label by the first '=' into a name and a value.
+impl FromStr for MetricsLabels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels: Vec<(String, String)> = Vec::new();
+        for label in s.split(',').map(|label| label.trim()) {
+            if label.is_empty() {
+                continue;
+            }
+            let (name, value) = match label.find('=') {
+                Some(i) => (&label[..i], &label[i + 1..]),
+                None => return Err(format!("Expected <name>=<value>, found \"{}\".", label)),
+            };
+            // Names starting with "__" are reserved for Prometheus itself.
+            let is_valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.starts_with("__");
+            if !is_valid {
+                return Err(format!(
+                    "Invalid label name \"{}\", use only letters, digits, and '_'.",
+                    name
+                ));
+            }
+            // The daemon sets these labels itself, a metric cannot have them twice.
+            if name == "instance" || name == "le" {
+                return Err(format!("Label name \"{}\" is reserved.", name));
+            }
+            if labels.iter().any(|(existing, _)| existing == name) {
+                return Err(format!("Label name \"{}\" occurs more than once.", name));
+            }
+            labels.push((name.to_string(), value.to_string()));
+        }
+        Ok(MetricsLabels(labels))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ConfigFile {
     pub values: Value,
@@ -190,6 +519,15 @@ pub fn read_config(config_path: &Path) -> ConfigFile {
     ConfigFile { values }
 }
 
+/// Like `read_config`, but returns an error instead of panicking.
+pub fn try_read_config(config_path: &Path) -> Result<ConfigFile, Error> {
+    let file_content = std::fs::read(config_path)
+        .map_err(|err| CliError::with_cause("Failed to open config file.", err))?;
+    let values: Value = serde_json::from_slice(&file_content)
+        .map_err(|err| CliError::with_cause("Error while reading config.", err))?;
+    Ok(ConfigFile { values })
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum OutputMode {
     /// Output human-readable text to stdout.
@@ -234,17 +572,124 @@ cli_opt_struct! {
         keypair: String => "".to_string(),
 
         /// The keypair to sign and pay with. [default: ~/.config/solana/id.json]
+        ///
+        /// To sign with a Ledger hardware wallet, pass a uri like
+        /// usb://ledger?key=0/0, where the key parameter is the derivation
+        /// path (account/change) of the key to use.
         #[clap(long)]
         keypair_path: PathBuf => get_default_keypair_path(),
 
+        /// Url of an external service to sign with, instead of a keypair.
+        ///
+        /// This can be an http:// or https:// url, or unix:// followed by
+        /// the path of a Unix domain socket. The service holds the private key,
+        /// so it never has to be loaded into memory. This takes precedence
+        /// over --keypair and --keypair-path.
+        #[clap(long)]
+        remote_signer_url: String => "".to_string(),
+
+        /// The keypair to pay transaction fees with. [default: the signer]
+        ///
+        /// This lets the signer be an authority that holds no SOL, while a
+        /// separate low-value key pays the fees. Like --keypair-path, this
+        /// can be a usb://ledger uri, or a public key with --output-transaction.
+        #[clap(long, value_name = "path")]
+        fee_payer_keypair: PathBuf => PathBuf::default(),
+
+        /// Write transactions to this file instead of sending them.
+        ///
+        /// The file contains the transaction signed by all signers except the
+        /// ones that are not available. To build a transaction for a key that
+        /// is not available on this machine, pass its public key as
+        /// --keypair-path. Use `solido submit-transaction` to sign and submit
+        /// the transaction later.
+        #[clap(long)]
+        output_transaction: String => "".to_string(),
+
+        /// Durable nonce account to use instead of a recent blockhash.
+        ///
+        /// Transactions that use a recent blockhash expire after about two
+        /// minutes; transactions that use a nonce stay valid until the nonce
+        /// is advanced. This is useful with --output-transaction, when signing
+        /// takes a while.
+        #[clap(long, value_name = "address")]
+        nonce_account: Pubkey => Pubkey::default(),
+
+        /// Authority of the nonce account. [default: the signer]
+        ///
+        /// If this is not the signer, it needs to sign separately, with
+        /// --output-transaction and `solido submit-transaction`.
+        #[clap(long, value_name = "address")]
+        nonce_authority: Pubkey => Pubkey::default(),
+
         /// URL of cluster to connect to (e.g., https://api.devnet.solana.com for solana devnet)
+        ///
+        /// To fail over to a different RPC node when one is unreachable or rate
+        /// limits us, pass multiple urls separated by commas.
         #[clap(long)]
         cluster: String => "http://127.0.0.1:8899".to_owned(),
 
+        /// Commitment level at which to read accounts and confirm transactions.
+        ///
+        /// Use 'finalized' for safety, at the cost of latency, or 'processed'
+        /// for the lowest latency, at the risk of acting on state that is
+        /// rolled back.
+        #[clap(long, possible_values = &["processed", "confirmed", "finalized"])]
+        commitment: Commitment => Commitment::Confirmed,
+
+        /// Maximum number of requests per second to each RPC endpoint. Defaults to 0, which means no limit.
+        ///
+        /// Public RPC endpoints rate limit their clients. With a limit, we
+        /// pace our requests to stay within it, and after a '429 Too Many
+        /// Requests' response, we pause before the next request.
+        #[clap(long, value_name = "n")]
+        rpc_requests_per_second: u32 => 0,
+
+        /// Number of requests that we can make to an RPC endpoint at once, after an idle period. Defaults to 10.
+        ///
+        /// Only has an effect together with --rpc-requests-per-second.
+        #[clap(long, value_name = "n")]
+        rpc_burst: u32 => 10,
+
         /// Whether to output text or json.
         #[clap(long = "output", possible_values = &["text", "json"])]
         output_mode: OutputMode => OutputMode::Text,
 
+        /// Whether to log events as human-readable text, or as one json object per line.
+        ///
+        /// Json logs include a timestamp, severity, and the fields of every
+        /// event, for ingestion into log aggregators. This affects the messages
+        /// that the maintenance daemon prints, not the output of commands.
+        #[clap(long, possible_values = &["text", "json"])]
+        log_format: LogFormat => LogFormat::Text,
+
+        /// Read accounts from this account dump, instead of from the cluster.
+        ///
+        /// This inspects the state of an instance at the slot of the dump, see
+        /// --write-account-dump. Only 'show-solido', 'verify', 'rewards-report',
+        /// and 'explain-maintenance' support this. Inflation rewards are still read
+        /// from the cluster, which needs to be an archive node for past epochs.
+        #[clap(long, value_name = "path")]
+        account_dump: PathBuf => PathBuf::default(),
+
+        /// The slot that the --account-dump must be at. Defaults to 0, which accepts any slot.
+        ///
+        /// RPC nodes only serve the current state of accounts, and archive
+        /// nodes keep blocks but not account state, so inspecting a past slot
+        /// requires a dump taken at that slot.
+        #[clap(long, value_name = "slot")]
+        at_slot: u64 => 0,
+
+        /// Write the accounts that the command read to this file, as an account dump.
+        ///
+        /// Take dumps periodically, to be able to inspect the state at those
+        /// slots later with --account-dump. A dump written by 'explain-maintenance'
+        /// can also serve as a test fixture, see `MockRpc::from_account_dump`.
+        /// Only 'show-solido', 'verify', 'rewards-report', and
+        /// 'explain-maintenance' support this.
+        #[clap(long, value_name = "path")]
+        write_account_dump: PathBuf => PathBuf::default(),
+
         /// Optional config path
         #[clap(long)]
         config: PathBuf => PathBuf::default(),
@@ -327,6 +772,24 @@ cli_opt_struct! {
     }
 }
 
+cli_opt_struct! {
+    DepositStakeAccountOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Active stake account to deposit, delegated to one of the Solido validators.
+        ///
+        /// The signer must be both the staker and the withdrawer of the account.
+        #[clap(long, value_name = "address")]
+        stake_account: Pubkey,
+    }
+}
+
 cli_opt_struct! {
     WithdrawOpts {
          /// Address of the Solido program.
@@ -340,6 +803,48 @@ cli_opt_struct! {
          /// Amount to withdraw in stSOL, using . as decimal separator.
          #[clap(long, value_name = "st_sol")]
          amount_st_sol: StLamports,
+
+         /// Deactivate the new stake account in the same transaction, so its
+         /// SOL can be withdrawn with `solana withdraw-stake` after the epoch
+         /// ends.
+         #[clap(long)]
+         deactivate: bool => false,
+    }
+}
+
+cli_opt_struct! {
+    CreateWithdrawTicketOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Amount to withdraw in stSOL, using . as decimal separator.
+        #[clap(long, value_name = "st_sol")]
+        amount_st_sol: StLamports,
+    }
+}
+
+cli_opt_struct! {
+    ClaimWithdrawTicketOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Id of the withdraw ticket, as printed by `create-withdraw-ticket`.
+        #[clap(long, value_name = "id")]
+        ticket_id: u64,
+
+        /// Account that receives the SOL. [default: the signer]
+        #[clap(long, value_name = "address")]
+        recipient: Pubkey => Pubkey::default(),
     }
 }
 
@@ -395,52 +900,66 @@ cli_opt_struct! {
 }
 
 cli_opt_struct! {
-    AddRemoveMaintainerOpts {
+    ReplaceValidatorVoteAccountOpts {
         /// Address of the Solido program.
         #[clap(long, value_name = "address")]
         solido_program_id: Pubkey,
+
         /// Account that stores the data for this Solido instance.
         #[clap(long, value_name = "address")]
         solido_address: Pubkey,
 
-        /// Maintainer to add or remove.
+        /// Address of the vote account that the validator uses now.
         #[clap(long, value_name = "address")]
-        maintainer_address: Pubkey,
+        old_validator_vote_account: Pubkey,
+
+        /// Address of the vote account to replace it with.
+        #[clap(long, value_name = "address")]
+        new_validator_vote_account: Pubkey,
 
         /// Multisig instance.
         #[clap(long, value_name = "address")]
         multisig_address: Pubkey,
 
         /// Address of the Multisig program.
-        #[clap(long)]
+        #[clap(long, value_name = "address")]
         multisig_program_id: Pubkey,
     }
 }
 
 cli_opt_struct! {
-     ShowSolidoOpts {
-        /// The solido instance to show.
-        #[clap(long, value_name = "address")]
-        solido_address: Pubkey,
+    SetValidatorWeightOpts {
         /// Address of the Solido program.
         #[clap(long, value_name = "address")]
         solido_program_id: Pubkey,
-    }
-}
 
-cli_opt_struct! {
-    ShowSolidoAuthoritiesOpts {
-        /// The solido instance to show authorities.
+        /// Account that stores the data for this Solido instance.
         #[clap(long, value_name = "address")]
         solido_address: Pubkey,
-        /// Address of the Solido program.
+
+        /// Address of the validator vote account.
         #[clap(long, value_name = "address")]
-        solido_program_id: Pubkey,
-   }
+        validator_vote_account: Pubkey,
+
+        /// New weight of the validator, relative to the weights of the other validators.
+        ///
+        /// Validators get a share of the stake proportional to their weight. A
+        /// validator with weight 0 receives no new stake.
+        #[clap(long, value_name = "weight")]
+        weight: u32,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
 }
 
 cli_opt_struct! {
-    PerformMaintenanceOpts {
+    SetMaxStakePerValidatorOpts {
         /// Address of the Solido program.
         #[clap(long, value_name = "address")]
         solido_program_id: Pubkey,
@@ -449,99 +968,578 @@ cli_opt_struct! {
         #[clap(long, value_name = "address")]
         solido_address: Pubkey,
 
-        /// Vote account that the maintainer tries to claim validator's fees
-        /// from. If none is set, the validator will ignore the instruction to
-        /// claim fees.
-        #[clap(long)]
-        validator_vote_account : Pubkey => Pubkey::default(),
-    }
-}
-
-// Multisig opts
-
-cli_opt_struct! {
-    CreateMultisigOpts {
-        /// How many signatures are needed to approve a transaction.
-        #[clap(long)]
-        threshold: u64,
+        /// Maximum stake per validator, in SOL, using . as decimal separator.
+        /// Set to 0 to remove the maximum.
+        #[clap(long, value_name = "sol")]
+        max_stake_per_validator_sol: Lamports,
 
-        /// The public keys of the multisig owners, who can sign transactions.
-        /// Addresses are given separated by comma.
-        #[clap(long)]
-        owners: PubkeyVec,
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
 
         /// Address of the Multisig program.
-        #[clap(long)]
+        #[clap(long, value_name = "address")]
         multisig_program_id: Pubkey,
     }
 }
 
-impl CreateMultisigOpts {
-    /// Perform a few basic checks to rule out nonsensical multisig settings.
-    ///
-    /// Exits if validation fails.
-    pub fn validate_or_exit(&self) {
-        if *self.threshold() > self.owners().0.len() as u64 {
-            println!("Threshold must be at most the number of owners.");
-            std::process::exit(1);
-        }
-        if *self.threshold() == 0 {
-            println!("Threshold must be at least 1.");
-            std::process::exit(1);
-        }
-    }
-}
-
 cli_opt_struct! {
-ProposeUpgradeOpts {
-        /// The multisig account whose owners should vote for this proposal.
-        #[clap(long, value_name = "address")]
-        multisig_address: Pubkey,
-
-        /// The program id of the program to upgrade.
-        #[clap(long, value_name = "address")]
-        program_address: Pubkey,
-
-        /// The address that holds the new program data.
+    SetMaxTotalSolOpts {
+        /// Address of the Solido program.
         #[clap(long, value_name = "address")]
-        buffer_address: Pubkey,
+        solido_program_id: Pubkey,
 
-        /// Account that will receive leftover funds from the buffer account.
+        /// Account that stores the data for this Solido instance.
         #[clap(long, value_name = "address")]
-        spill_address: Pubkey,
+        solido_address: Pubkey,
 
-        /// Address of the Multisig program.
-        #[clap(long)]
-        multisig_program_id: Pubkey,
-    }
-}
+        /// Maximum amount of SOL managed by Solido, in SOL, using . as decimal
+        /// separator. Deposits that would exceed it fail. Set to 0 to remove
+        /// the maximum.
+        #[clap(long, value_name = "sol")]
+        max_total_sol: Lamports,
 
-cli_opt_struct! {
-ShowMultisigOpts {
-        /// The multisig account to display.
+        /// Multisig instance.
         #[clap(long, value_name = "address")]
         multisig_address: Pubkey,
 
         /// Address of the Multisig program.
-        #[clap(long)]
+        #[clap(long, value_name = "address")]
         multisig_program_id: Pubkey,
     }
 }
 
 cli_opt_struct! {
-    ShowTransactionOpts {
-        /// The transaction to display.
+    ScheduleRewardDistributionOpts {
+        /// Address of the Solido program.
         #[clap(long, value_name = "address")]
-        transaction_address: Pubkey,
+        solido_program_id: Pubkey,
 
-        /// The transaction to display.
+        /// Account that stores the data for this Solido instance.
         #[clap(long, value_name = "address")]
-        solido_program_id: Pubkey,
+        solido_address: Pubkey,
 
-        /// Address of the Multisig program.
-        #[clap(long)]
-        multisig_program_id: Pubkey,
-    }
+        // See also the docs section of `create-solido` in main.rs for a description
+        // of the fee shares.
+        /// Treasury fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        treasury_fee_share: u32,
+
+        /// Validation fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        validation_fee_share: u32,
+
+        /// Developer fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        developer_fee_share: u32,
+
+        /// Share of the rewards that goes to stSOL appreciation (the non-fee part).
+        #[clap(long, value_name = "int")]
+        st_sol_appreciation_share: u32,
+
+        /// Number of epochs after the epoch in which the multisig executes the
+        /// transaction, at which the new distribution takes effect. Defaults to
+        /// the minimum that the program accepts, 2 epochs.
+        #[clap(long, value_name = "epochs")]
+        delay_epochs: u64 => lido::MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    MigrateStateOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    PauseResumeOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    AddRemoveMaintainerOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Maintainer to add or remove.
+        #[clap(long, value_name = "address")]
+        maintainer_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    CreateDepositorAllowlistOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    AddRemoveAllowedDepositorOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Depositor to add or remove, the account that signs deposits.
+        #[clap(long, value_name = "address")]
+        depositor_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    EnableDisableDepositorAllowlistOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    RotateMaintainerOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Maintainer to remove, after the new maintainer is added.
+        #[clap(long, value_name = "address")]
+        old_maintainer_address: Pubkey,
+
+        /// Maintainer to add.
+        #[clap(long, value_name = "address")]
+        new_maintainer_address: Pubkey,
+
+        /// Multisig instance.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+
+        /// How often to check whether the new maintainer was added, while the multisig approves and executes the proposal. Defaults to 10.
+        #[clap(long, value_name = "seconds")]
+        poll_seconds: u64 => 10,
+    }
+}
+
+cli_opt_struct! {
+     ShowSolidoOpts {
+        /// The solido instance to show.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    DiffStateOpts {
+        /// The solido instance to compare.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Account dump with the state to compare from, see --write-account-dump.
+        #[clap(long, value_name = "path")]
+        from_dump: PathBuf,
+
+        /// Account dump with the state to compare to. Defaults to the current state of the cluster.
+        #[clap(long, value_name = "path")]
+        to_dump: PathBuf => PathBuf::default(),
+
+        /// The slot that the --from-dump must be at. Defaults to 0, which accepts any slot.
+        #[clap(long, value_name = "slot")]
+        from_slot: u64 => 0,
+
+        /// The slot that the --to-dump must be at. Defaults to 0, which accepts any slot.
+        #[clap(long, value_name = "slot")]
+        to_slot: u64 => 0,
+    }
+}
+
+cli_opt_struct! {
+    TopOpts {
+        /// The solido instance to show.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Time to wait in seconds between refreshes. Defaults to 5s.
+        #[clap(long, value_name = "seconds")]
+        refresh_seconds: u64 => 5,
+
+        /// Number of recent maintainer transactions to show. Defaults to 10.
+        #[clap(long, value_name = "int")]
+        recent_transactions: usize => 10,
+    }
+}
+
+cli_opt_struct! {
+    IndexOpts {
+        /// The solido instance to index.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// File to write the events to. When the file exists, indexing continues after the last event in it.
+        #[clap(long, value_name = "path")]
+        output_path: PathBuf,
+
+        /// Format of the output file, 'csv' or 'sqlite'. SQLite requires the `sqlite` feature. Defaults to csv.
+        #[clap(long, value_name = "format")]
+        output_format: IndexFormat => IndexFormat::Csv,
+
+        /// Time to wait in seconds between checks for new transactions. Defaults to 10s.
+        #[clap(long, value_name = "seconds")]
+        poll_seconds: u64 => 10,
+
+        /// When true, exit once all past transactions are indexed, instead of waiting for new ones. Defaults to false.
+        #[clap(long)]
+        once: bool => false,
+    }
+}
+
+cli_opt_struct! {
+    ShowStakePoolOpts {
+        /// The solido instance to show.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    RewardsReportOpts {
+        /// The solido instance to report on.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Number of completed epochs to report, counting back from the current epoch.
+        #[clap(long, value_name = "n")]
+        epochs: u64 => 10,
+    }
+}
+
+cli_opt_struct! {
+    VerifyOpts {
+        /// The solido instance to verify.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    ShowStakeAccountsOpts {
+        /// The solido instance to show the stake accounts for.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    ShowValidatorsOpts {
+        /// The solido instance to show the validators of.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Sort by highest stake, highest estimated APY, lowest skip rate, or name. Defaults to stake.
+        #[clap(long, possible_values = &["stake", "apy", "skip-rate", "name"])]
+        sort_by: ValidatorSortKey => ValidatorSortKey::Stake,
+    }
+}
+
+cli_opt_struct! {
+    ShowKeeperScheduleOpts {
+        /// The solido instance to show the schedule for.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    ApplyValidatorPolicyOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// The TOML file with the curation policy.
+        #[clap(long, value_name = "path")]
+        policy_path: PathBuf,
+
+        /// When true, propose a multisig transaction for every validator to add or deactivate, instead of only reporting them. Defaults to false.
+        #[clap(long)]
+        propose: bool => false,
+
+        /// Multisig instance, required with --propose.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey => Pubkey::default(),
+
+        /// Address of the Multisig program, required with --propose.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey => Pubkey::default(),
+    }
+}
+
+cli_opt_struct! {
+    SubmitTransactionOpts {
+        /// The file written by --output-transaction.
+        #[clap(long, value_name = "path")]
+        transaction_path: PathBuf,
+
+        /// When true, only add the signature of the signer to the file, without submitting. Use this on an air-gapped machine. Defaults to false.
+        #[clap(long)]
+        sign_only: bool => false,
+    }
+}
+
+cli_opt_struct! {
+    ShowSolidoAuthoritiesOpts {
+        /// The solido instance to show authorities.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+   }
+}
+
+cli_opt_struct! {
+    ExportIdlOpts {
+        /// Address of the Solido program, included in the IDL.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    CompletionsOpts {
+        /// The shell to generate completions for.
+        #[clap(value_name = "shell", possible_values = &["bash", "zsh", "fish"])]
+        shell: Shell,
+    }
+}
+
+cli_opt_struct! {
+    PerformMaintenanceOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Vote account that the maintainer tries to claim validator's fees
+        /// from. If none is set, the validator will ignore the instruction to
+        /// claim fees.
+        #[clap(long)]
+        validator_vote_account : Pubkey => Pubkey::default(),
+    }
+}
+
+cli_opt_struct! {
+    ExplainMaintenanceOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+    }
+}
+
+// Multisig opts
+
+cli_opt_struct! {
+    CreateMultisigOpts {
+        /// How many signatures are needed to approve a transaction.
+        #[clap(long)]
+        threshold: u64,
+
+        /// The public keys of the multisig owners, who can sign transactions.
+        /// Addresses are given separated by comma.
+        #[clap(long)]
+        owners: PubkeyVec,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+    }
+}
+
+impl CreateMultisigOpts {
+    /// Perform a few basic checks to rule out nonsensical multisig settings.
+    ///
+    /// Exits if validation fails.
+    pub fn validate_or_exit(&self) {
+        if *self.threshold() > self.owners().0.len() as u64 {
+            eprintln!("Threshold must be at most the number of owners.");
+            std::process::exit(1);
+        }
+        if *self.threshold() == 0 {
+            eprintln!("Threshold must be at least 1.");
+            std::process::exit(1);
+        }
+    }
+}
+
+cli_opt_struct! {
+ProposeUpgradeOpts {
+        /// The multisig account whose owners should vote for this proposal.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// The program id of the program to upgrade.
+        #[clap(long, value_name = "address")]
+        program_address: Pubkey,
+
+        /// The address that holds the new program data.
+        #[clap(long, value_name = "address")]
+        buffer_address: Pubkey,
+
+        /// Account that will receive leftover funds from the buffer account.
+        #[clap(long, value_name = "address")]
+        spill_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+ShowMultisigOpts {
+        /// The multisig account to display.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    ShowTransactionOpts {
+        /// The transaction to display.
+        #[clap(long, value_name = "address")]
+        transaction_address: Pubkey,
+
+        /// The transaction to display.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long)]
+        multisig_program_id: Pubkey,
+    }
 }
 
 cli_opt_struct! {
@@ -620,6 +1618,222 @@ cli_opt_struct! {
     }
 }
 
+cli_opt_struct! {
+    GovernanceAddValidatorOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Address of the validator vote account.
+        #[clap(long, value_name = "address")]
+        validator_vote_account: Pubkey,
+
+        /// Validator stSol token account.
+        #[clap(long, value_name = "address")]
+        validator_fee_account: Pubkey,
+
+        /// Address of the SPL Governance program.
+        #[clap(long, value_name = "address")]
+        governance_program_id: Pubkey,
+
+        /// Governance that is the manager of the Solido instance.
+        #[clap(long, value_name = "address")]
+        governance_address: Pubkey,
+
+        /// Mint of the tokens that vote on the proposal, the community or council mint of the realm. The signer must have deposited enough of these tokens in the realm to create proposals.
+        #[clap(long, value_name = "address")]
+        governing_token_mint: Pubkey,
+
+        /// Name of the proposal, shown to voters.
+        #[clap(long, value_name = "name")]
+        proposal_name: String,
+
+        /// Link to a description of the proposal, shown to voters. Defaults to no description.
+        #[clap(long, value_name = "url")]
+        description_link: String => "".to_owned(),
+    }
+}
+
+cli_opt_struct! {
+    GovernanceDeactivateValidatorOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Address of the validator vote account.
+        #[clap(long, value_name = "address")]
+        validator_vote_account: Pubkey,
+
+        /// Address of the SPL Governance program.
+        #[clap(long, value_name = "address")]
+        governance_program_id: Pubkey,
+
+        /// Governance that is the manager of the Solido instance.
+        #[clap(long, value_name = "address")]
+        governance_address: Pubkey,
+
+        /// Mint of the tokens that vote on the proposal, the community or council mint of the realm. The signer must have deposited enough of these tokens in the realm to create proposals.
+        #[clap(long, value_name = "address")]
+        governing_token_mint: Pubkey,
+
+        /// Name of the proposal, shown to voters.
+        #[clap(long, value_name = "name")]
+        proposal_name: String,
+
+        /// Link to a description of the proposal, shown to voters. Defaults to no description.
+        #[clap(long, value_name = "url")]
+        description_link: String => "".to_owned(),
+    }
+}
+
+cli_opt_struct! {
+    GovernanceScheduleRewardDistributionOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        // See also the docs section of `create-solido` in main.rs for a description
+        // of the fee shares.
+        /// Treasury fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        treasury_fee_share: u32,
+
+        /// Validation fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        validation_fee_share: u32,
+
+        /// Developer fee share of the rewards.
+        #[clap(long, value_name = "int")]
+        developer_fee_share: u32,
+
+        /// Share of the rewards that goes to stSOL appreciation (the non-fee part).
+        #[clap(long, value_name = "int")]
+        st_sol_appreciation_share: u32,
+
+        /// Number of epochs after the epoch in which the proposal executes, at
+        /// which the new distribution takes effect. Defaults to the minimum that
+        /// the program accepts, 2 epochs.
+        #[clap(long, value_name = "epochs")]
+        delay_epochs: u64 => lido::MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS,
+
+        /// Address of the SPL Governance program.
+        #[clap(long, value_name = "address")]
+        governance_program_id: Pubkey,
+
+        /// Governance that is the manager of the Solido instance.
+        #[clap(long, value_name = "address")]
+        governance_address: Pubkey,
+
+        /// Mint of the tokens that vote on the proposal, the community or council mint of the realm. The signer must have deposited enough of these tokens in the realm to create proposals.
+        #[clap(long, value_name = "address")]
+        governing_token_mint: Pubkey,
+
+        /// Name of the proposal, shown to voters.
+        #[clap(long, value_name = "name")]
+        proposal_name: String,
+
+        /// Link to a description of the proposal, shown to voters. Defaults to no description.
+        #[clap(long, value_name = "url")]
+        description_link: String => "".to_owned(),
+    }
+}
+
+cli_opt_struct! {
+    GovernanceChangeFeeRecipientsOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// New stSOL SPL token account that receives the treasury fees. Defaults to the current one.
+        #[clap(long, value_name = "address")]
+        treasury_account: Pubkey => Pubkey::default(),
+
+        /// New stSOL SPL token account that receives the developer fees. Defaults to the current one.
+        #[clap(long, value_name = "address")]
+        developer_account: Pubkey => Pubkey::default(),
+
+        /// Address of the SPL Governance program.
+        #[clap(long, value_name = "address")]
+        governance_program_id: Pubkey,
+
+        /// Governance that is the manager of the Solido instance.
+        #[clap(long, value_name = "address")]
+        governance_address: Pubkey,
+
+        /// Mint of the tokens that vote on the proposal, the community or council mint of the realm. The signer must have deposited enough of these tokens in the realm to create proposals.
+        #[clap(long, value_name = "address")]
+        governing_token_mint: Pubkey,
+
+        /// Name of the proposal, shown to voters.
+        #[clap(long, value_name = "name")]
+        proposal_name: String,
+
+        /// Link to a description of the proposal, shown to voters. Defaults to no description.
+        #[clap(long, value_name = "url")]
+        description_link: String => "".to_owned(),
+    }
+}
+
+cli_opt_struct! {
+    ValidatorJoinOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Address of the vote account of the validator that wants to join.
+        #[clap(long, value_name = "address")]
+        validator_vote_account: Pubkey,
+
+        /// stSOL token account that receives the validation fees. Defaults to the associated stSOL account of the signer, which gets created if it does not exist.
+        #[clap(long, value_name = "address")]
+        validator_fee_account: Pubkey => Pubkey::default(),
+
+        /// Multisig instance that is the manager of the Solido instance, to propose adding the validator to.
+        #[clap(long, value_name = "address")]
+        multisig_address: Pubkey,
+
+        /// Address of the Multisig program.
+        #[clap(long, value_name = "address")]
+        multisig_program_id: Pubkey,
+    }
+}
+
+cli_opt_struct! {
+    ValidatorStatusOpts {
+        /// Address of the Solido program.
+        #[clap(long, value_name = "address")]
+        solido_program_id: Pubkey,
+
+        /// Account that stores the data for this Solido instance.
+        #[clap(long, value_name = "address")]
+        solido_address: Pubkey,
+
+        /// Address of the validator vote account.
+        #[clap(long, value_name = "address")]
+        validator_vote_account: Pubkey,
+    }
+}
+
 cli_opt_struct! {
     RunMaintainerOpts {
         /// Address of the Solido program.
@@ -630,10 +1844,22 @@ cli_opt_struct! {
         #[clap(long)]
         solido_address: Pubkey,
 
-        /// Listen address and port for the http server that serves a /metrics endpoint. Defaults to 0.0.0.0:8923.
+        /// Listen address and port for the http server that serves a /metrics endpoint, or unix:<path> to listen on a unix domain socket at that path instead. Defaults to 0.0.0.0:8923.
         #[clap(long)]
         listen: String => "0.0.0.0:8923".to_owned(),
 
+        /// Path to a PEM certificate chain. When set, the http server on --listen serves https with this certificate, instead of plain http. Requires --http-tls-key-path, and a build with the `tls` feature. Defaults to serving plain http.
+        #[clap(long)]
+        http_tls_cert_path: String => "".to_owned(),
+
+        /// Path to the PEM private key of --http-tls-cert-path.
+        #[clap(long)]
+        http_tls_key_path: String => "".to_owned(),
+
+        /// When set, the http server responds with 401 Unauthorized to requests that lack an 'Authorization: Bearer <token>' header with this token. This applies to all endpoints, including /metrics and /quitquitquit. Defaults to not requiring a token.
+        #[clap(long)]
+        http_bearer_token: String => "".to_owned(),
+
         // The expected wait time is half the max poll interval. A max poll interval
         // of a few minutes should be plenty fast for a production deployment, but
         // for testing you can reduce this value to make the daemon more responsive,
@@ -641,6 +1867,190 @@ cli_opt_struct! {
         /// Maximum time to wait in seconds after there was no maintenance to perform, before checking again. Defaults to 120s
         #[clap(long)]
         max_poll_interval_seconds: u64 => 120,
+
+        /// Maximum time to wait in seconds after consecutive errors, before checking again. The wait time starts at --max-poll-interval-seconds and doubles after every error, until a poll succeeds. Defaults to 900s.
+        #[clap(long)]
+        error_backoff_max_seconds: u64 => 900,
+
+        /// Postgres connection string, e.g. "postgresql://user@localhost/solido". When set, record every observed state and performed maintenance in this database. Requires the `postgres` feature. Defaults to not recording history.
+        #[clap(long)]
+        postgres_url: String => "".to_owned(),
+
+        /// Directory to write per-epoch analytics to, as newline-delimited JSON. Defaults to not exporting.
+        #[clap(long)]
+        export_path: String => "".to_owned(),
+
+        /// File to append every transaction that the daemon submits to, as newline-delimited JSON, with the time, slot, kind of transaction, signature, fee, and result. Defaults to not keeping an audit log.
+        #[clap(long)]
+        audit_log_path: String => "".to_owned(),
+
+        /// Rotate the audit log when it would grow larger than this many megabytes. Defaults to 100.
+        #[clap(long)]
+        audit_log_max_megabytes: u64 => 100,
+
+        /// How many rotated audit log files to keep, the oldest are deleted. Defaults to 10.
+        #[clap(long)]
+        audit_log_keep_files: u32 => 10,
+
+        /// File to save the metrics counters, and what the daemon learned about the RPC, to about once a minute and at shutdown, and to restore them from at startup. This keeps counters from resetting when the daemon restarts. Defaults to not saving.
+        #[clap(long)]
+        state_file: String => "".to_owned(),
+
+        /// Comma-separated list of urls to POST events to. Defaults to not sending events.
+        #[clap(long)]
+        webhook_urls: String => "".to_owned(),
+
+        /// Secret for the HMAC-SHA256 signature in the X-Solido-Signature header of webhook requests. Required when --webhook-urls is set.
+        #[clap(long)]
+        webhook_secret: String => "".to_owned(),
+
+        /// Comma-separated list of urls to POST the exchange rate to, as a json object, after startup and whenever the exchange rate is updated. When --webhook-secret is set, the requests are signed like webhook requests. To publish the rate on-chain instead, see --publish-exchange-rate-interval-slots. Defaults to not pushing the exchange rate.
+        #[clap(long)]
+        exchange_rate_push_urls: String => "".to_owned(),
+
+        /// Publish the exchange rate to the exchange rate oracle account when it changes, and otherwise at least once every this many slots. The maintainer pays for the account on the first publication. Defaults to 0, which disables publishing.
+        #[clap(long)]
+        publish_exchange_rate_interval_slots: u64 => 0,
+
+        /// At the start of every epoch, leave the tasks in the keeper schedule (see 'show-keeper-schedule') to keepers for this many seconds, and only execute them if they are still not done afterwards. Note that withdrawals are not possible until the exchange rate is updated. Defaults to 0, which executes the tasks right away.
+        #[clap(long)]
+        keeper_grace_period_seconds: u64 => 0,
+
+        /// Number of slots before and after an epoch boundary in which the daemon polls every second, instead of at a random interval up to --max-poll-interval-seconds, and in which updating the exchange rate goes before all other maintenance. After the boundary, we only poll every second until the exchange rate is updated. Defaults to 150 slots, about a minute. 0 disables this.
+        #[clap(long)]
+        epoch_boundary_slots: u64 => 150,
+
+        /// When true, simulate the maintenance transactions and print them, instead of sending them. Use this to validate a new deployment against live state before funding the maintainer. Defaults to false.
+        #[clap(long)]
+        dry_run: bool => false,
+
+        /// When true, only observe: read the state, check it, and export metrics and the maintenance that is due, but never sign or send transactions. This does not load the maintainer keypair, so it needs none. Defaults to false.
+        #[clap(long)]
+        observe_only: bool => false,
+
+        /// Price per compute unit to pay as priority fee for maintenance transactions, in micro-lamports. With --dynamic-priority-fee, this is the minimum price. Defaults to 0, which pays no priority fee.
+        #[clap(long)]
+        priority_fee_micro_lamports: u64 => 0,
+
+        /// When true, pay the 75th percentile of the prioritization fees that recent transactions paid for the accounts that the maintenance transaction writes to, as reported by getRecentPrioritizationFees, but at least --priority-fee-micro-lamports. Defaults to false.
+        #[clap(long)]
+        dynamic_priority_fee: bool => false,
+
+        /// When true, simulate every maintenance transaction before sending it, and don't send it if the simulation fails, but log the error with the simulation logs. This avoids paying fees for transactions that fail because the state changed after we read it, at the cost of one more RPC call per transaction. Defaults to false.
+        #[clap(long)]
+        simulate_before_send: bool => false,
+
+        /// When true, send every maintenance transaction to all RPC endpoints in --cluster at once, instead of only to the endpoint that we read from, and confirm it through whichever endpoint sees it land first. The transaction lands only once, the leader rejects duplicates. This makes the daemon less dependent on a single RPC node forwarding its transactions, at the cost of more RPC calls per transaction. Per endpoint, the outcomes are exported as the solido_rpc_broadcasts_total and solido_rpc_broadcast_confirmations_total metrics. Defaults to false.
+        #[clap(long)]
+        broadcast_transactions: bool => false,
+
+        /// When true, a POST request to /quitquitquit on the --listen address shuts down the daemon after the current iteration, like SIGTERM does. Only enable this when the address is not reachable by untrusted parties. Defaults to false.
+        #[clap(long)]
+        enable_quit_endpoint: bool => false,
+
+        /// When true, a POST request to /reload on the --listen address makes the daemon reload its config before the next iteration, like SIGHUP does. This also loads the maintainer keypair from --keypair-path again. Only enable this when the address is not reachable by untrusted parties. Defaults to false.
+        #[clap(long)]
+        enable_reload_endpoint: bool => false,
+
+        /// When nonzero, deactivate validators that earned no vote credits in this many consecutive epochs, so they get no new stake, and their stake gets unstaked. Only the manager can deactivate: when the maintainer is the manager, it deactivates the validator itself, otherwise it proposes the deactivation to --manager-multisig-address or --manager-governance-address, if set. Defaults to 0, which disables deactivation.
+        #[clap(long)]
+        deactivate_delinquent_epochs: u64 => 0,
+
+        /// The multisig that is the manager of the Solido instance, to propose deactivations of delinquent validators to. The maintainer must be one of its owners. Defaults to not proposing.
+        #[clap(long)]
+        manager_multisig_address: Pubkey => Pubkey::default(),
+
+        /// Address of the multisig program of --manager-multisig-address.
+        #[clap(long)]
+        multisig_program_id: Pubkey => Pubkey::default(),
+
+        /// The SPL Governance governance that is the manager of the Solido instance, to propose deactivations of delinquent validators to, as an alternative to --manager-multisig-address. The maintainer must have deposited enough --governing-token-mint tokens in the realm to create proposals. Defaults to not proposing.
+        #[clap(long)]
+        manager_governance_address: Pubkey => Pubkey::default(),
+
+        /// Address of the SPL Governance program of --manager-governance-address.
+        #[clap(long)]
+        governance_program_id: Pubkey => Pubkey::default(),
+
+        /// Mint of the tokens that vote on the proposals to --manager-governance-address.
+        #[clap(long)]
+        governing_token_mint: Pubkey => Pubkey::default(),
+
+        /// When nonzero, coordinate with other maintainer daemons through the on-chain maintainer lease: only perform maintenance while holding the lease, and acquire or renew it for this many slots. Other daemons stand by, but keep serving metrics, and take over when the lease expires. At most 1500. Defaults to 0, which performs maintenance regardless of the lease.
+        #[clap(long)]
+        maintainer_lease_slots: u64 => 0,
+
+        /// When true, just before sending a maintenance transaction, inspect the recent transactions on the account that it writes to, and skip the transaction when another maintainer performed the same task after we read the state. This costs a few RPC calls per transaction, but saves the fee of a duplicate transaction when several maintainers run without the lease. Skips are exported as the solido_maintenance_skipped_duplicate_total metric. Defaults to false.
+        #[clap(long)]
+        skip_duplicate_work: bool => false,
+
+        /// When true, read the stake of every vote account in the cluster, and when staking deposits and rebalancing, give validators in the superminority (the largest validators that together hold more than a third of the stake) no stake beyond what they have. The program only accepts deposits to the validators with the least stake relative to their weight, so this prefers validators outside the superminority among those; to reduce the stake of a validator further, lower its weight. Defaults to false.
+        #[clap(long)]
+        avoid_superminority: bool => false,
+
+        /// Defer stake deposits and rebalancing that would move less than this many SOL, until enough deposits or imbalance add up. The deferred amount is exported as the solido_maintenance_deferred_stake_sol metric. Unstaking to fund withdraw tickets and from inactive validators is never deferred. Defaults to 0, which performs stake movements of any size.
+        #[clap(long)]
+        minimum_stake_delta_sol: Lamports => Lamports(0),
+
+        /// Don't perform maintenance that moves stake (stake deposits, rebalancing, and unstaking) when the state that we read appears to lag more than this many slots behind the cluster. We estimate the lag from how far the clock sysvar lags behind local time, so this relies on an accurate local clock. Refusals are logged, and counted in the solido_maintenance_stale_state_refusals_total metric. Defaults to 0, which disables the check.
+        #[clap(long)]
+        max_state_lag_slots: u64 => 0,
+
+        /// Comma-separated list of urls to POST alerts to, when an alert starts firing and when it resolves. Defaults to not sending alerts.
+        #[clap(long)]
+        alert_webhook_urls: String => "".to_owned(),
+
+        /// Format of the alert requests: 'json' for a json object with the alert fields, or 'slack' for a Slack-compatible incoming webhook message. Defaults to json.
+        #[clap(long, possible_values = &["json", "slack"])]
+        alert_webhook_format: AlertFormat => AlertFormat::Json,
+
+        /// Alert when this many consecutive maintenance iterations failed. Defaults to 5, 0 disables the alert.
+        #[clap(long)]
+        alert_consecutive_errors: u64 => 5,
+
+        /// Alert when the exchange rate was not updated this many slots after the start of an epoch. Defaults to 0, which disables the alert.
+        #[clap(long)]
+        alert_exchange_rate_delay_slots: u64 => 0,
+
+        /// Alert when the balance of the maintainer, or of the fee payer if --fee-payer-keypair is set, drops below this many SOL. Defaults to 0, which disables the alert.
+        #[clap(long)]
+        alert_maintainer_balance_below_sol: Lamports => Lamports(0),
+
+        /// Keypair of a funding account to top up the fee payer from, the maintainer unless --fee-payer-keypair is set. When the balance of the fee payer drops below --top-up-below-sol, transfer --top-up-amount-sol to it from this account, which also pays the fee of the transfer. Defaults to no top-ups.
+        #[clap(long, value_name = "path")]
+        top_up_funding_keypair: PathBuf => PathBuf::default(),
+
+        /// Top up the fee payer when its balance drops below this many SOL, see --top-up-funding-keypair. Defaults to 0.
+        #[clap(long)]
+        top_up_below_sol: Lamports => Lamports(0),
+
+        /// Amount of SOL to transfer to the fee payer per top-up, see --top-up-funding-keypair. Defaults to 0.
+        #[clap(long)]
+        top_up_amount_sol: Lamports => Lamports(0),
+
+        /// Keypair of the owner of the developer fee stSOL account. When set, the daemon transfers the developer fee to the --developer-fee-splits destinations, once there is no maintenance left to do, and the account holds at least --developer-fee-claim-minimum-st-sol. Only applies to --solido-address. Defaults to not claiming the developer fee.
+        #[clap(long, value_name = "path")]
+        developer_fee_keypair: PathBuf => PathBuf::default(),
+
+        /// Where to transfer the developer fee to, see --developer-fee-keypair, separated by ','. Every destination is '<st_sol_account>:<percentage>', and the percentages must add up to 100. What rounding leaves goes to the last destination. Defaults to none.
+        #[clap(long)]
+        developer_fee_splits: FeeSplits => FeeSplits::default(),
+
+        /// Claim the developer fee only when the developer fee account holds at least this much stSOL, see --developer-fee-keypair. Defaults to 0, which claims any amount.
+        #[clap(long)]
+        developer_fee_claim_minimum_st_sol: StLamports => StLamports(0),
+
+        /// More Solido instances to maintain in this daemon, besides --solido-address, separated by ';'. Every instance is '<name>:<program_id>:<solido_address>:<rpc_urls>', where the RPC urls are like --cluster. The daemon interleaves maintenance of the instances, and labels all metrics with the instance name. The maintainer keypair and the other options apply to all instances, except --manager-multisig-address, which only applies to --solido-address. Defaults to maintaining only --solido-address.
+        #[clap(long)]
+        instances: InstanceSpecs => InstanceSpecs::default(),
+
+        /// Name of the instance of --solido-address, for the instance label when there are --instances. Defaults to 'main'.
+        #[clap(long)]
+        instance_name: String => "main".to_owned(),
+
+        /// Labels to add to every metric on the /metrics endpoint, separated by ',', e.g. 'cluster=mainnet-beta,deployment=solido-prod'. These tell apart the metrics of daemons in different environments, without relabeling in Prometheus. The names 'instance' and 'le' are reserved. Defaults to no extra labels.
+        #[clap(long)]
+        metrics_labels: MetricsLabels => MetricsLabels::default(),
     }
 }
 
@@ -677,3 +2087,91 @@ cli_opt_struct! {
         amount: u64
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validate(values: Value) -> Result<(), Vec<String>> {
+        validate_config_file(&ConfigFile { values }, &RunMaintainerOpts::config_options())
+    }
+
+    #[test]
+    fn test_validate_config_file_suggests_known_key() {
+        let solido_address = Pubkey::new_unique().to_string();
+        assert_eq!(
+            validate(serde_json::json!({ "solido_adress": solido_address })),
+            Err(vec![
+                "Unknown key \"solido_adress\" in the config file, did you mean \"solido_address\"?"
+                    .to_string()
+            ])
+        );
+        assert_eq!(
+            validate(serde_json::json!({ "frobnicate": "true" })),
+            Err(vec![
+                "Unknown key \"frobnicate\" in the config file.".to_string()
+            ])
+        );
+        assert_eq!(
+            validate(serde_json::json!({
+                "solido_address": solido_address,
+                "max_poll_interval_seconds": 120,
+                "dry_run": true,
+            })),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_validate_config_file_checks_addresses_and_paths() {
+        assert_eq!(
+            validate(serde_json::json!({
+                "listen": ["0.0.0.0:8923"],
+                "solido_address": "not-an-address",
+                "top_up_funding_keypair": "~/funding.json",
+            })),
+            Err(vec![
+                "Expected a string, number, or boolean for \"listen\" in the config file, \
+                found [\"0.0.0.0:8923\"]."
+                    .to_string(),
+                "Invalid address \"not-an-address\" for \"solido_address\" in the config file."
+                    .to_string(),
+                "The path \"~/funding.json\" for \"top_up_funding_keypair\" in the config file \
+                starts with '~', but paths in the config file are not expanded. Use an absolute path."
+                    .to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_describe_conflict_names_every_source() {
+        assert_eq!(
+            describe_conflict(
+                "--dry-run",
+                "SOLIDO_DRY_RUN",
+                Some(&true),
+                Some(&false),
+                None
+            ),
+            Some(
+                "--dry-run is set to true on the command line, false in the config file. \
+                Using the value on the command line."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            describe_conflict(
+                "--dry-run",
+                "SOLIDO_DRY_RUN",
+                None,
+                Some(&true),
+                Some(&true)
+            ),
+            None
+        );
+        assert_eq!(
+            describe_conflict::<bool>("--dry-run", "SOLIDO_DRY_RUN", None, None, None),
+            None
+        );
+    }
+}
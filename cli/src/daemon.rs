@@ -8,25 +8,81 @@
 //! executes maintenance tasks if needed. It also publishes a snapshot of its
 //! most recently seen Solido state in an `Arc` so the http threads can serve it
 //! without blocking the main loop.
+//!
+//! On SIGHUP, or a POST to `/reload` when enabled, the main loop reads its
+//! options again before the next iteration. This also loads the maintainer
+//! keypair from `--keypair-path` again, so the key can be rotated without a
+//! restart.
+//!
+//! On SIGINT or SIGTERM, or a POST to `/quitquitquit` when enabled, the main
+//! loop finishes the iteration that is in progress, so we don't abandon a
+//! transaction halfway, publishes its final metrics, and then the daemon stops
+//! the http server and exits.
+//!
+//! With `--state-file`, the main loop periodically saves its metrics, and what
+//! the snapshot client learned about the RPC, and restores them at startup, so
+//! counters continue where they were before a restart.
+//!
+//! With `--instances`, one daemon maintains several Solido instances. Every
+//! instance has its own RPC client, metrics, and error backoff, and the main
+//! loop polls whichever instance is due first. The http server serves the
+//! metrics of all instances, with an `instance` label.
 
+use std::collections::BTreeMap;
+use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use rand::Rng;
-use tiny_http::{Request, Response, Server};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::clock::{Slot, DEFAULT_MS_PER_SLOT};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::Transaction;
+use tiny_http::{Header, Method, Response, Server};
 
+use lido::token::{Lamports, StLamports};
+
+use crate::alerts::{AlertOptions, Alerter};
+use crate::audit_log::{get_transaction_fee, AuditLog, AuditRecord, AuditResult};
 use crate::config::RunMaintainerOpts;
-use crate::error::AsPrettyError;
-use crate::maintenance::{try_perform_maintenance, MaintenanceOutput, SolidoState};
-use crate::prometheus::{write_metric, Metric, MetricFamily};
-use crate::SnapshotClientConfig;
+use crate::error::{Abort, AsPrettyError, Error};
+use crate::exchange_rate_feed::ExchangeRateFeed;
+use crate::export::EpochExporter;
+use crate::governance::ManagerGovernance;
+use crate::history::{self, History};
+use crate::logging::{self, Event};
+use crate::maintenance::{
+    explain_maintenance, get_rejected_deposits, prepare_claim_developer_fee, prepare_maintenance,
+    prepare_propose_deactivation, prepare_top_up_fee_payer, select_maintenance, send_maintenance,
+    DeveloperFeeClaim, FeePayerTopUp, MaintenanceAttempt, MaintenanceExplanation,
+    MaintenanceOptions, MaintenanceOutput, ManagerMultisig, PerformedDeveloperFeeClaim,
+    PerformedMaintenance, PerformedTopUp, PreparedMaintenance, ProposedDeactivation,
+    RejectedDeposits, SolidoState,
+};
+use crate::priority_fee::PriorityFeeOptions;
+use crate::prometheus::{
+    write_merged, write_metric, write_with_labels, DurationHistogram, Metric, MetricFamily,
+};
+use crate::reloadable_signer::ReloadableSigner;
+use crate::rpc::Commitment;
+use crate::snapshot::{LearnedState, RpcMetrics, SnapshotClient};
+use crate::unix_http::{UnixHttpServer, UNIX_SOCKET_PREFIX};
+use crate::webhook::Webhooks;
+use crate::{get_signer_from_path, Config, SnapshotClientConfig};
 
 /// Metrics counters that track how many maintenance operations we performed.
-#[derive(Clone)]
-struct MaintenanceMetrics {
+///
+/// Fields that are missing in a state file of an older version default to zero.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MaintenanceMetrics {
     /// Number of times that we checked if there was maintenance to perform.
     polls: u64,
 
@@ -58,9 +114,430 @@ struct MaintenanceMetrics {
 
     /// Number of times we performed `RemoveValidator`.
     transactions_remove_validator: u64,
+
+    /// Number of times we performed `PublishExchangeRate`.
+    transactions_publish_exchange_rate: u64,
+
+    /// Number of times we performed `AcquireMaintainerLease`.
+    transactions_acquire_maintainer_lease: u64,
+
+    /// Number of times we performed `Rebalance`.
+    transactions_rebalance: u64,
+
+    /// Number of times we performed `Unstake` to cover withdraw tickets.
+    transactions_unstake_for_withdraw_tickets: u64,
+
+    /// Number of times we performed `FundWithdrawTicket`.
+    transactions_fund_withdraw_ticket: u64,
+
+    /// Number of times we performed `DeactivateValidator`.
+    transactions_deactivate_validator: u64,
+
+    /// Number of times we performed `SweepToReserve`.
+    transactions_sweep_to_reserve: u64,
+
+    /// Number of times we proposed `DeactivateValidator` to the manager multisig.
+    deactivations_proposed: u64,
+
+    /// Number of times we transferred SOL from the funding account to the fee payer.
+    fee_payer_top_ups: u64,
+
+    /// Number of times we claimed the developer fee, see `--developer-fee-keypair`.
+    developer_fee_claims: u64,
+
+    /// Amount of developer fee that we claimed, in stLamports.
+    developer_fee_claimed_st_lamports: u64,
+
+    /// Number of times we did not send a maintenance transaction, because
+    /// another maintainer performed the same task, see `--skip-duplicate-work`.
+    skipped_duplicate: u64,
+
+    /// Number of polls in which we did not move stake, because the state
+    /// lagged too far behind the cluster, see `--max-state-lag-slots`.
+    stale_state_refusals: u64,
+
+    /// Priority fees that we paid for maintenance transactions, see `crate::priority_fee`.
+    priority_fees_lamports: u64,
+
+    /// Number of deposits that failed because the depositor is not on the
+    /// depositor allowlist, see `maintenance::get_rejected_deposits`.
+    rejected_deposits: u64,
+
+    /// The most recent signature on the Solido account when we last counted
+    /// rejected deposits. We count the failed transactions after it.
+    rejected_deposits_until: Option<String>,
+
+    /// Upper bound of the time we wait before the next poll after an error, see [`Backoff`].
+    ///
+    /// This is 0 if the most recent iteration succeeded.
+    error_backoff_seconds: u64,
+
+    /// The stake deposit that we defer because it is below `--minimum-stake-delta-sol`.
+    ///
+    /// This is a gauge of the most recent iteration, so we don't save it.
+    #[serde(skip)]
+    deferred_stake_deposit: Lamports,
+
+    /// The rebalance that we defer because it is below `--minimum-stake-delta-sol`.
+    #[serde(skip)]
+    deferred_rebalance: Lamports,
+
+    /// Slot of the most recent state that we read.
+    #[serde(skip)]
+    observed_slot: Option<Slot>,
+
+    /// How far the clock sysvar lagged behind local time, in the most recent
+    /// state that we read. When the RPC node falls behind, this grows.
+    #[serde(skip)]
+    clock_lag: Option<Duration>,
+
+    /// Duration of reading the state and performing maintenance, per iteration.
+    iteration_duration: DurationHistogram,
+
+    /// Latencies of the RPC calls, copied from the snapshot client after every iteration.
+    rpc: RpcMetrics,
+
+    /// The commitment level of the RPC calls, copied from the snapshot client too.
+    #[serde(skip)]
+    commitment: Commitment,
+
+    /// The build and configuration of the daemon, set after every iteration too.
+    #[serde(skip)]
+    config: ConfigMetrics,
+}
+
+/// The version of the daemon, with the commit and compiler that `build.rs` recorded.
+#[derive(Clone, Copy, Default)]
+struct BuildInfo {
+    version: &'static str,
+    commit: &'static str,
+    rustc: &'static str,
+}
+
+impl BuildInfo {
+    fn current() -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("SOLIDO_GIT_COMMIT"),
+            rustc: env!("SOLIDO_RUSTC_VERSION"),
+        }
+    }
+}
+
+/// The configuration of an instance, exported so we can verify which version
+/// and configuration every maintainer replica runs.
+#[derive(Clone, Default)]
+struct ConfigMetrics {
+    build: BuildInfo,
+    solido_program_id: Pubkey,
+    solido_address: Pubkey,
+    max_poll_interval_seconds: u64,
+    keeper_grace_period_seconds: u64,
+    minimum_stake_delta: Lamports,
+    dry_run: bool,
+    observe_only: bool,
+
+    /// The maintenance tasks that are optional, and whether they are enabled.
+    tasks: Vec<(&'static str, bool)>,
+}
+
+impl ConfigMetrics {
+    fn new(
+        solido_program_id: Pubkey,
+        solido_address: Pubkey,
+        max_poll_interval: Duration,
+        options: &MaintenanceOptions,
+    ) -> ConfigMetrics {
+        ConfigMetrics {
+            build: BuildInfo::current(),
+            solido_program_id,
+            solido_address,
+            max_poll_interval_seconds: max_poll_interval.as_secs(),
+            keeper_grace_period_seconds: options
+                .keeper_grace_period
+                .map_or(0, |period| period.as_secs()),
+            minimum_stake_delta: options.minimum_stake_delta.unwrap_or(Lamports(0)),
+            dry_run: options.dry_run,
+            observe_only: options.observe_only,
+            tasks: vec![
+                (
+                    "PublishExchangeRate",
+                    options.publish_exchange_rate_max_age_slots.is_some(),
+                ),
+                (
+                    "AcquireMaintainerLease",
+                    options.maintainer_lease_slots.is_some(),
+                ),
+                (
+                    "DeactivateValidator",
+                    options.deactivate_delinquent_epochs.is_some(),
+                ),
+                (
+                    "ProposeDeactivation",
+                    options.deactivate_delinquent_epochs.is_some()
+                        && (options.manager_multisig.is_some()
+                            || options.manager_governance.is_some()),
+                ),
+            ],
+        }
+    }
+
+    fn write_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_build_info",
+                help: "Always 1, the labels identify the version of the maintainer.",
+                type_: "gauge",
+                metrics: vec![Metric::new(1_u64)
+                    .with_label("version", self.build.version.to_string())
+                    .with_label("commit", self.build.commit.to_string())
+                    .with_label("rustc", self.build.rustc.to_string())],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_config_info",
+                help: "Always 1, the labels identify the Solido instance that the maintainer maintains.",
+                type_: "gauge",
+                metrics: vec![Metric::new(1_u64)
+                    .with_label("solido_program_id", self.solido_program_id.to_string())
+                    .with_label("solido_address", self.solido_address.to_string())],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_max_poll_interval_seconds",
+                help: "Maximum time to wait when there was no maintenance to perform, --max-poll-interval-seconds.",
+                type_: "gauge",
+                metrics: vec![Metric::new(self.max_poll_interval_seconds)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_keeper_grace_period_seconds",
+                help: "Time that we leave keeper tasks to keepers at the start of an epoch, --keeper-grace-period-seconds.",
+                type_: "gauge",
+                metrics: vec![Metric::new(self.keeper_grace_period_seconds)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_minimum_stake_delta_sol",
+                help: "Stake movements below this amount are deferred, --minimum-stake-delta-sol.",
+                type_: "gauge",
+                metrics: vec![Metric::new_sol(self.minimum_stake_delta)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_dry_run",
+                help: "1 if the maintainer only simulates maintenance transactions, --dry-run, 0 otherwise.",
+                type_: "gauge",
+                metrics: vec![Metric::new(self.dry_run as u64)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_observe_only",
+                help: "1 if the maintainer only observes and never signs transactions, --observe-only, 0 otherwise.",
+                type_: "gauge",
+                metrics: vec![Metric::new(self.observe_only as u64)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintainer_task_enabled",
+                help: "1 if the optional maintenance task is enabled, 0 otherwise.",
+                type_: "gauge",
+                metrics: self
+                    .tasks
+                    .iter()
+                    .map(|(task, enabled)| {
+                        Metric::new(*enabled as u64).with_label("task", task.to_string())
+                    })
+                    .collect(),
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// How often we save the state file, see `--state-file`.
+const STATE_FILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the daemon saves in its `--state-file`.
+#[derive(Default, Deserialize, Serialize)]
+pub struct DaemonState {
+    pub metrics: MaintenanceMetrics,
+    pub snapshot_client: LearnedState,
+
+    /// The state of the instances in `--instances`, by name. The instance of
+    /// `--solido-address` uses the fields above, like when it is the only one.
+    #[serde(default)]
+    pub instances: BTreeMap<String, DaemonState>,
+}
+
+impl DaemonState {
+    /// Read the state file, or return `None` if it does not exist.
+    pub fn load(path: &Path) -> io::Result<Option<DaemonState>> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let state = serde_json::from_str(&json)?;
+        Ok(Some(state))
+    }
+
+    /// Write the state file atomically, so a crash while writing keeps the previous state.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// Restore the metrics and the learned state of the snapshot clients from the state file.
+///
+/// A missing or unreadable state file is not fatal, we start from scratch then.
+fn restore_state(instances: &mut [Instance], path: &Path) {
+    match DaemonState::load(path) {
+        Ok(Some(mut state)) => {
+            Event::info(
+                "state_restored",
+                format!("Restored metrics from {}.", path.display()),
+            )
+            .field("path", path.display().to_string())
+            .field("polls", state.metrics.polls)
+            .emit();
+            let (primary, others) = instances
+                .split_first_mut()
+                .expect("The daemon maintains at least one instance.");
+            for instance in others {
+                let name = instance.name.as_ref().expect("Named in --instances.");
+                if let Some(instance_state) = state.instances.remove(name) {
+                    instance.restore(instance_state);
+                }
+            }
+            primary.restore(state);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            Event::warning(
+                "state_file_error",
+                format!("Failed to read state file, starting from scratch: {}", err),
+            )
+            .field("path", path.display().to_string())
+            .emit();
+        }
+    }
+}
+
+fn save_state(instances: &[Instance], path: &Path) {
+    let (primary, others) = instances
+        .split_first()
+        .expect("The daemon maintains at least one instance.");
+    let mut state = primary.get_state();
+    state.instances = others
+        .iter()
+        .map(|instance| {
+            let name = instance.name.clone().expect("Named in --instances.");
+            (name, instance.get_state())
+        })
+        .collect();
+    if let Err(err) = state.save(path) {
+        Event::error(
+            "state_file_error",
+            format!("Failed to save state file: {}", err),
+        )
+        .field("path", path.display().to_string())
+        .emit();
+    }
+}
+
+/// Label the metrics of RPC calls with the commitment level at which we made them.
+fn with_commitment_label(metrics: Vec<Metric>, commitment: Commitment) -> Vec<Metric> {
+    metrics
+        .into_iter()
+        .map(|metric| metric.with_label("commitment", commitment.as_str().to_string()))
+        .collect()
 }
 
 impl MaintenanceMetrics {
+    /// Increment the counter for the maintenance operation that was performed.
+    pub fn observe_maintenance(&mut self, maintenance_output: &MaintenanceOutput) {
+        match maintenance_output {
+            MaintenanceOutput::StakeDeposit { .. } => {
+                self.transactions_stake_deposit += 1;
+            }
+            MaintenanceOutput::UpdateExchangeRate => {
+                self.transactions_update_exchange_rate += 1;
+            }
+            MaintenanceOutput::WithdrawInactiveStake { .. } => {
+                self.transactions_withdraw_inactive_stake += 1;
+            }
+            MaintenanceOutput::CollectValidatorFee { .. } => {
+                self.transactions_collect_validator_fee += 1
+            }
+            MaintenanceOutput::MergeStake { .. } => self.transactions_merge_stake += 1,
+            MaintenanceOutput::ClaimValidatorFee { .. } => {
+                self.transactions_claim_validator_fee += 1
+            }
+            MaintenanceOutput::UnstakeFromInactiveValidator { .. } => {
+                self.transactions_unstake_from_inactive_validator += 1
+            }
+            MaintenanceOutput::RemoveValidator { .. } => self.transactions_remove_validator += 1,
+            MaintenanceOutput::PublishExchangeRate { .. } => {
+                self.transactions_publish_exchange_rate += 1
+            }
+            MaintenanceOutput::AcquireMaintainerLease { .. } => {
+                self.transactions_acquire_maintainer_lease += 1
+            }
+            MaintenanceOutput::RebalanceUnstake { .. } => self.transactions_rebalance += 1,
+            MaintenanceOutput::UnstakeForWithdrawTickets { .. } => {
+                self.transactions_unstake_for_withdraw_tickets += 1
+            }
+            MaintenanceOutput::FundWithdrawTicket { .. } => {
+                self.transactions_fund_withdraw_ticket += 1
+            }
+            MaintenanceOutput::DeactivateValidator { .. } => {
+                self.transactions_deactivate_validator += 1
+            }
+            MaintenanceOutput::SweepToReserve { .. } => self.transactions_sweep_to_reserve += 1,
+        }
+    }
+
+    /// Record the stake movements that we defer, so they don't go unnoticed.
+    pub fn observe_deferred_stake(&mut self, deferred: &[MaintenanceOutput]) {
+        self.deferred_stake_deposit = Lamports(0);
+        self.deferred_rebalance = Lamports(0);
+        for output in deferred {
+            match output {
+                MaintenanceOutput::StakeDeposit { amount, .. } => {
+                    self.deferred_stake_deposit = *amount
+                }
+                MaintenanceOutput::RebalanceUnstake { amount, .. } => {
+                    self.deferred_rebalance = *amount
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Count the rejected deposits, and remember where to continue next time.
+    pub fn observe_rejected_deposits(&mut self, rejected: RejectedDeposits) {
+        self.rejected_deposits += rejected.count;
+        self.rejected_deposits_until = rejected.newest_signature;
+    }
+
     /// Serialize metrics in Prometheus text format.
     pub fn write_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
         write_metric(
@@ -102,223 +579,3199 @@ impl MaintenanceMetrics {
                         .with_label("operation", "UnstakeFromInactiveValidator".to_string()),
                     Metric::new(self.transactions_remove_validator)
                         .with_label("operation", "RemoveValidator".to_string()),
+                    Metric::new(self.transactions_publish_exchange_rate)
+                        .with_label("operation", "PublishExchangeRate".to_string()),
+                    Metric::new(self.transactions_acquire_maintainer_lease)
+                        .with_label("operation", "AcquireMaintainerLease".to_string()),
+                    Metric::new(self.transactions_rebalance)
+                        .with_label("operation", "Rebalance".to_string()),
+                    Metric::new(self.transactions_unstake_for_withdraw_tickets)
+                        .with_label("operation", "UnstakeForWithdrawTickets".to_string()),
+                    Metric::new(self.transactions_fund_withdraw_ticket)
+                        .with_label("operation", "FundWithdrawTicket".to_string()),
+                    Metric::new(self.transactions_deactivate_validator)
+                        .with_label("operation", "DeactivateValidator".to_string()),
+                    Metric::new(self.transactions_sweep_to_reserve)
+                        .with_label("operation", "SweepToReserve".to_string()),
                 ],
             },
         )?;
-        Ok(())
-    }
-}
-
-/// Snapshot of metrics and Solido state.
-struct Snapshot {
-    /// Metrics about what the daemon has done so far.
-    metrics: MaintenanceMetrics,
-
-    /// The current state of on-chain accounts, and the time at which we obtained
-    /// that data.
-    solido: Option<SolidoState>,
-}
-
-/// Mutex that holds the latest snapshot.
-///
-/// At startup it holds None, after that it will always hold Some Arc.
-/// To read the current snapshot, we only have to lock the mutex briefly,
-/// so we can clone the arc, and then we can continue to work with that
-/// snapshot without any lock. This holds for publishing a new state as well:
-/// we can prepare it privately, and we only need to lock the mutex briefly
-/// to swap the Arc.
-type SnapshotMutex = Mutex<Option<Arc<Snapshot>>>;
-
-/// Run the maintenance loop.
-fn run_main_loop(
-    config: &mut SnapshotClientConfig,
-    opts: &RunMaintainerOpts,
-    snapshot_mutex: &SnapshotMutex,
-) {
-    let mut metrics = MaintenanceMetrics {
-        polls: 0,
-        errors: 0,
-        transactions_stake_deposit: 0,
-        transactions_update_exchange_rate: 0,
-        transactions_withdraw_inactive_stake: 0,
-        transactions_collect_validator_fee: 0,
-        transactions_merge_stake: 0,
-        transactions_claim_validator_fee: 0,
-        transactions_unstake_from_inactive_validator: 0,
-        transactions_remove_validator: 0,
-    };
-    let mut rng = rand::thread_rng();
-
-    loop {
-        metrics.polls += 1;
-        let mut do_wait = false;
-
-        let result = config.with_snapshot(|mut config| {
-            let state =
-                SolidoState::new(&mut config, opts.solido_program_id(), opts.solido_address())?;
-
-            match try_perform_maintenance(&mut config, &state)? {
-                None => {
-                    // Nothing to be done, try again later.
-                    do_wait = true;
-                }
-                Some(maintenance_output) => {
-                    println!("{}", maintenance_output);
-                    match maintenance_output {
-                        MaintenanceOutput::StakeDeposit { .. } => {
-                            metrics.transactions_stake_deposit += 1;
-                        }
-                        MaintenanceOutput::UpdateExchangeRate => {
-                            metrics.transactions_update_exchange_rate += 1;
-                        }
-                        MaintenanceOutput::WithdrawInactiveStake { .. } => {
-                            metrics.transactions_withdraw_inactive_stake += 1;
-                        }
-                        MaintenanceOutput::CollectValidatorFee { .. } => {
-                            metrics.transactions_collect_validator_fee += 1
-                        }
-                        MaintenanceOutput::MergeStake { .. } => {
-                            metrics.transactions_merge_stake += 1
-                        }
-                        MaintenanceOutput::ClaimValidatorFee { .. } => {
-                            metrics.transactions_claim_validator_fee += 1
-                        }
-                        MaintenanceOutput::UnstakeFromInactiveValidator { .. } => {
-                            metrics.transactions_unstake_from_inactive_validator += 1
-                        }
-                        MaintenanceOutput::RemoveValidator { .. } => {
-                            metrics.transactions_remove_validator += 1
-                        }
-                    }
-                }
-            }
-
-            Ok(state)
-        });
-
-        let state = match result {
-            Ok(state) => Some(state),
-            Err(err) => {
-                println!("Error in maintenance.");
-                err.print_pretty();
-                metrics.errors += 1;
-
-                // If the error was caused by a connectivity problem, we shouldn't
-                // hammer the RPC again straight away. Even better would be to do
-                // exponential backoff with jitter, but let's not go there right now.
-                do_wait = true;
-
-                None
-            }
-        };
-
-        // Publish the new state and metrics, so the webserver can serve them.
-        let snapshot = Snapshot {
-            metrics: metrics.clone(),
-            solido: state,
-        };
-        snapshot_mutex.lock().unwrap().replace(Arc::new(snapshot));
-
-        if do_wait {
-            // Sleep a random time, to avoid a thundering herd problem, in case
-            // multiple maintainer bots happened to run in sync. They would all
-            // try to create the same transaction, and only one would pass.
-            let max_poll_interval = Duration::from_secs(*opts.max_poll_interval_seconds());
-            let sleep_time = rng.gen_range(Duration::from_secs(0)..max_poll_interval);
-            println!("Sleeping {:?} until next iteration ...", sleep_time);
-            std::thread::sleep(sleep_time);
-        }
-    }
-}
-
-fn serve_request(request: Request, snapshot_mutex: &SnapshotMutex) -> Result<(), std::io::Error> {
-    // Take the current snapshot. This only holds the lock briefly, and does
-    // not prevent other threads from updating the snapshot while this request
-    // handler is running.
-    let option_snapshot = snapshot_mutex.lock().unwrap().clone();
-
-    // It might be that no snapshot is available yet. This happens when we just
-    // started the server, and the main loop has not yet queried the RPC for the
-    // latest state.
-    let snapshot = match option_snapshot {
-        Some(arc_snapshot) => arc_snapshot,
-        None => {
-            return request.respond(
-                Response::from_string(
-                    "Service Unavailable\n\nServer is still starting, try again shortly.",
-                )
-                .with_status_code(503),
-            );
-        }
-    };
-
-    // We don't even look at the request, for now we always serve the metrics.
-
-    let mut out: Vec<u8> = Vec::new();
-    let mut is_ok = snapshot.metrics.write_prometheus(&mut out).is_ok();
-
-    if let Some(ref solido) = snapshot.solido {
-        is_ok = is_ok && solido.write_prometheus(&mut out).is_ok();
-    }
-
-    if is_ok {
-        request.respond(Response::from_data(out))
-    } else {
-        request.respond(Response::from_string("error").with_status_code(500))
-    }
-}
-
-/// Spawn threads that run the http server.
-fn start_http_server(
-    opts: &RunMaintainerOpts,
-    snapshot_mutex: Arc<SnapshotMutex>,
-) -> Vec<JoinHandle<()>> {
-    let server = match Server::http(opts.listen().clone()) {
-        Ok(server) => Arc::new(server),
-        Err(err) => {
-            eprintln!(
-                "Error: {}\nFailed to start http server on {}. Is the daemon already running?",
-                err,
-                opts.listen(),
-            );
-            std::process::exit(1);
-        }
-    };
-
-    println!("Http server listening on {}", opts.listen());
-
-    // Spawn a number of http handler threads, so we can handle requests in
-    // parallel. This server is only used to serve metrics, it can be super basic,
-    // but some degree of parallelism is nice in case a client is slow to send
-    // its request or something like that.
-    (0..num_cpus::get())
-        .map(|i| {
-            let server_clone = server.clone();
-            let snapshot_mutex_clone = snapshot_mutex.clone();
-            std::thread::Builder::new()
-                .name(format!("http_handler_{}", i))
-                .spawn(move || {
-                    for request in server_clone.incoming_requests() {
-                        // Ignore any errors; if we fail to respond, then there's little
-                        // we can do about it here ... the client should just retry.
-                        let _ = serve_request(request, &*snapshot_mutex_clone);
-                    }
-                })
-                .expect("Failed to spawn http handler thread.")
-        })
-        .collect()
-}
-
-/// Run the maintenance daemon.
-pub fn main(config: &mut SnapshotClientConfig, opts: &RunMaintainerOpts) {
-    let snapshot_mutex = Arc::new(Mutex::new(None));
-    let http_threads = start_http_server(opts, snapshot_mutex.clone());
-
-    run_main_loop(config, opts, &*snapshot_mutex);
-
-    // We never get here, the main loop should run indefinitely until the program
-    // is killed, and while the main loop runs, the http server also serves.
-    for thread in http_threads {
-        thread.join().unwrap();
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_deactivations_proposed_total",
+                help: "Number of times we proposed to deactivate a delinquent validator to the manager multisig, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.deactivations_proposed)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_fee_payer_top_ups_total",
+                help: "Number of times we topped up the fee payer from the funding account, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.fee_payer_top_ups)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_developer_fee_claims_total",
+                help: "Number of times we claimed the developer fee, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.developer_fee_claims)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_developer_fee_claimed_st_sol_total",
+                help: "Amount of developer fee that we claimed and transferred to the destinations, in stSOL, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new_st_sol(StLamports(
+                    self.developer_fee_claimed_st_lamports,
+                ))],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_skipped_duplicate_total",
+                help: "Number of times we did not send a maintenance transaction, because another maintainer performed the same task, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.skipped_duplicate)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_stale_state_refusals_total",
+                help: "Number of times we did not move stake, because the state lagged more than --max-state-lag-slots behind the cluster, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.stale_state_refusals)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_priority_fees_lamports_total",
+                help: "Priority fees paid for maintenance transactions, in addition to the base fee, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.priority_fees_lamports)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rejected_deposits_total",
+                help: "Number of deposits that failed because the depositor is not on the depositor allowlist, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.rejected_deposits)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_error_backoff_seconds",
+                help: "Maximum time to wait before polling again after consecutive errors, or 0 when the last poll succeeded.",
+                type_: "gauge",
+                metrics: vec![Metric::new(self.error_backoff_seconds)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_deferred_stake_sol",
+                help: "Amount of the stake movement that we defer because it is below --minimum-stake-delta-sol, in SOL, or 0 when there is none.",
+                type_: "gauge",
+                metrics: vec![
+                    Metric::new_sol(self.deferred_stake_deposit)
+                        .with_label("operation", "StakeDeposit".to_string()),
+                    Metric::new_sol(self.deferred_rebalance)
+                        .with_label("operation", "Rebalance".to_string()),
+                ],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_maintenance_iteration_duration_seconds",
+                help: "Time it took to read the state and perform maintenance, including retries.",
+                type_: "histogram",
+                metrics: self.iteration_duration.to_metrics(),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_get_multiple_accounts_duration_seconds",
+                help: "Time it took to complete a GetMultipleAccounts call.",
+                type_: "histogram",
+                metrics: with_commitment_label(
+                    self.rpc.get_multiple_accounts.to_metrics(),
+                    self.commitment,
+                ),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_send_and_confirm_transaction_duration_seconds",
+                help: "Time it took to send a transaction until it was confirmed, or failed to confirm.",
+                type_: "histogram",
+                metrics: with_commitment_label(
+                    self.rpc.send_and_confirm_transaction.to_metrics(),
+                    self.commitment,
+                ),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_transactions_landed_total",
+                help: "Number of transactions that we sent that landed, including ones that failed on-chain, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.rpc.transactions.landed)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_transactions_dropped_total",
+                help: "Number of times a transaction did not land in time, and we broadcast it again, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.rpc.transactions.dropped)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_transactions_expired_total",
+                help: "Number of transactions whose blockhash expired before they landed, since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(self.rpc.transactions.expired)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_endpoint_active",
+                help: "1 for the RPC endpoint that we currently use, 0 for the others. Endpoints are numbered in the order of --cluster.",
+                type_: "gauge",
+                metrics: (0..self.rpc.failovers.len())
+                    .map(|i| {
+                        Metric::new((i == self.rpc.active_endpoint) as u64)
+                            .with_label("endpoint", i.to_string())
+                    })
+                    .collect(),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_failovers_total",
+                help: "Number of times the RPC endpoint failed or rate limited us, and we failed over to the next endpoint, since launch.",
+                type_: "counter",
+                metrics: self
+                    .rpc
+                    .failovers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, count)| Metric::new(*count).with_label("endpoint", i.to_string()))
+                    .collect(),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_rate_limited_total",
+                help: "Number of times the RPC endpoint responded with 429 Too Many Requests when we read accounts, since launch.",
+                type_: "counter",
+                metrics: self
+                    .rpc
+                    .rate_limited
+                    .iter()
+                    .enumerate()
+                    .map(|(i, count)| Metric::new(*count).with_label("endpoint", i.to_string()))
+                    .collect(),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_broadcasts_total",
+                help: "Number of times we sent a transaction to the RPC endpoint with --broadcast-transactions, by whether the endpoint accepted it, since launch.",
+                type_: "counter",
+                metrics: self
+                    .rpc
+                    .broadcasts
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, counts)| {
+                        vec![
+                            Metric::new(counts.accepted)
+                                .with_label("endpoint", i.to_string())
+                                .with_label("result", "accepted".to_string()),
+                            Metric::new(counts.failed)
+                                .with_label("endpoint", i.to_string())
+                                .with_label("result", "failed".to_string()),
+                        ]
+                    })
+                    .collect(),
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_rpc_broadcast_confirmations_total",
+                help: "Number of transactions sent with --broadcast-transactions for which the RPC endpoint was the first to report that they landed, since launch.",
+                type_: "counter",
+                metrics: self
+                    .rpc
+                    .broadcasts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, counts)| {
+                        Metric::new(counts.confirmed_first).with_label("endpoint", i.to_string())
+                    })
+                    .collect(),
+            },
+        )?;
+        if let Some(slot) = self.observed_slot {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_rpc_observed_slot",
+                    help: "Slot of the most recent state that we read from the RPC node.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new(slot)],
+                },
+            )?;
+        }
+        if let Some(clock_lag) = self.clock_lag {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_rpc_clock_lag_seconds",
+                    help: "How far the clock sysvar lagged behind local time in the most recent state that we read, or 0 if it was ahead.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new_seconds(clock_lag)],
+                },
+            )?;
+        }
+        if let Some(round_trip) = self.rpc.last_round_trip {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_rpc_last_round_trip_seconds",
+                    help: "Duration of the most recent successful GetMultipleAccounts call.",
+                    type_: "gauge",
+                    metrics: with_commitment_label(
+                        vec![Metric::new_seconds(round_trip)],
+                        self.commitment,
+                    ),
+                },
+            )?;
+        }
+        self.config.write_prometheus(out)
+    }
+}
+
+/// Snapshot of metrics and Solido state of one instance.
+struct InstanceSnapshot {
+    /// Value of the `instance` label, see [`Instance::name`].
+    name: Option<String>,
+
+    /// Metrics about what the daemon has done so far.
+    metrics: MaintenanceMetrics,
+
+    /// The current state of on-chain accounts, and the time at which we obtained
+    /// that data.
+    solido: Option<SolidoState>,
+
+    /// What maintenance decided for `solido`, served on `/state`.
+    maintenance: Option<MaintenanceExplanation>,
+}
+
+impl InstanceSnapshot {
+    fn write_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.metrics.write_prometheus(out)?;
+        if let Some(ref solido) = self.solido {
+            solido.write_prometheus(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of all instances that we polled at least once.
+struct Snapshot {
+    instances: Vec<Arc<InstanceSnapshot>>,
+
+    /// The --metrics-labels to add to every metric.
+    metrics_labels: Vec<(String, String)>,
+}
+
+/// Mutex that holds the latest snapshot.
+///
+/// At startup it holds None, after that it will always hold Some Arc.
+/// To read the current snapshot, we only have to lock the mutex briefly,
+/// so we can clone the arc, and then we can continue to work with that
+/// snapshot without any lock. This holds for publishing a new state as well:
+/// we can prepare it privately, and we only need to lock the mutex briefly
+/// to swap the Arc.
+type SnapshotMutex = Mutex<Option<Arc<Snapshot>>>;
+
+/// The daemon's source of wall-clock time.
+///
+/// The daemon itself uses [`SystemClock`]. Simulations substitute a fake clock,
+/// so they can skip over the time that the daemon sleeps, rather than waiting.
+/// Note that the on-chain notion of time (the slot and epoch) comes from the
+/// clock sysvar, which we read through the RPC like any other account.
+pub trait WallClock {
+    fn now(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock of the machine that the daemon runs on.
+pub struct SystemClock;
+
+impl WallClock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Set when the daemon should stop after the current iteration.
+#[derive(Clone, Default)]
+pub struct ShutdownFlag(Arc<AtomicBool>);
+
+impl ShutdownFlag {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Set when the daemon should reload its options before the next iteration.
+#[derive(Clone, Default)]
+pub struct ReloadFlag(Arc<AtomicBool>);
+
+impl ReloadFlag {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Return whether a reload was requested, and clear the request.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Request shutdown on SIGINT and SIGTERM, and a reload on SIGHUP.
+///
+/// A second SIGINT or SIGTERM exits immediately.
+fn register_signal_handlers(shutdown: &ShutdownFlag, reload: &ReloadFlag) {
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        let shutdown = shutdown.clone();
+        let handler = move || {
+            if shutdown.is_requested() {
+                // The user insists, don't wait for the iteration to finish.
+                // Unlike `std::process::exit`, `_exit` is async-signal-safe.
+                unsafe { libc::_exit(1) };
+            }
+            shutdown.request();
+        };
+        // Safety: the handler only touches an atomic, or exits the process,
+        // both of which are safe to do in a signal handler.
+        unsafe { signal_hook_registry::register(signal, handler) }
+            .expect("Failed to register signal handler.");
+    }
+
+    let reload = reload.clone();
+    // Safety: the handler only touches an atomic.
+    unsafe { signal_hook_registry::register(libc::SIGHUP, move || reload.request()) }
+        .expect("Failed to register signal handler.");
+}
+
+/// Sleep for `duration`, but wake up early when shutdown is requested.
+fn sleep_unless_shutdown(wall_clock: &dyn WallClock, duration: Duration, shutdown: &ShutdownFlag) {
+    // Signals do not interrupt `std::thread::sleep`, so sleep in short steps.
+    let step = Duration::from_millis(500);
+    let mut remaining = duration;
+    while remaining > Duration::from_secs(0) && !shutdown.is_requested() {
+        let sleep_time = remaining.min(step);
+        wall_clock.sleep(sleep_time);
+        remaining -= sleep_time;
+    }
+}
+
+/// The result of a single iteration of the maintenance loop.
+pub struct IterationOutcome {
+    /// Whether there was nothing to do, or an error, so we should sleep before polling again.
+    pub do_wait: bool,
+
+    /// The state that we observed, if we managed to read it.
+    pub solido: Option<SolidoState>,
+
+    /// The maintenance that we performed, if any.
+    pub maintenance_output: Option<MaintenanceOutput>,
+}
+
+/// What [`run_iteration`] decided to do in its snapshot, with the transactions that it signed.
+enum PlannedWork {
+    /// We only observe, and don't sign anything.
+    Observe,
+
+    /// The fee payer runs low, top it up before anything else.
+    TopUp(Transaction, PerformedTopUp),
+
+    /// There is maintenance to perform.
+    Maintenance(PreparedMaintenance),
+
+    /// There is no maintenance to perform, but maybe a proposal or a claim.
+    Idle {
+        proposal: Option<(Transaction, ProposedDeactivation)>,
+        claim: Option<(Transaction, PerformedDeveloperFeeClaim)>,
+    },
+}
+
+/// Poll the on-chain state once, and perform maintenance if there is any to perform.
+///
+/// When the fee payer runs low and `fee_payer_top_up` is set, we top it up
+/// first, and perform maintenance in the next iteration. When there is no
+/// maintenance to perform and `developer_fee_claim` is set, we claim the
+/// developer fee.
+pub fn run_iteration(
+    config: &mut SnapshotClientConfig,
+    wall_clock: &dyn WallClock,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    maintenance_options: &MaintenanceOptions,
+    fee_payer_top_up: Option<&FeePayerTopUp>,
+    developer_fee_claim: Option<&DeveloperFeeClaim>,
+    audit_log: Option<&AuditLog>,
+    metrics: &mut MaintenanceMetrics,
+) -> IterationOutcome {
+    metrics.polls += 1;
+    let mut do_wait = false;
+    let mut performed = None;
+
+    let start = Instant::now();
+    // `with_snapshot` calls the closure again when it reads an account that
+    // was not in the snapshot, so in there we only read and sign. We send the
+    // transactions, and update metrics, after the snapshot.
+    let result = config.with_snapshot(|mut config| {
+        let mut state = SolidoState::new(&mut config, solido_program_id, solido_address)?;
+        if maintenance_options.avoid_superminority {
+            state.load_superminority(&mut config)?;
+        }
+        state.produced_at = wall_clock.now();
+
+        let rejected_deposits = if state.solido.is_depositor_allowlist_enabled {
+            Some(get_rejected_deposits(
+                &mut config,
+                solido_address,
+                metrics.rejected_deposits_until.as_deref(),
+            )?)
+        } else {
+            None
+        };
+
+        // An observer has no key to sign with, so it only reports what a
+        // maintainer would do, and waits for the state to change.
+        if maintenance_options.observe_only {
+            return Ok((state, rejected_deposits, PlannedWork::Observe));
+        }
+
+        // A dry run sends no transactions, so it needs no top-up either.
+        if let Some(top_up) = fee_payer_top_up.filter(|_| !maintenance_options.dry_run) {
+            if let Some((transaction, performed)) =
+                prepare_top_up_fee_payer(&mut config, &state, top_up)?
+            {
+                let work = PlannedWork::TopUp(transaction, performed);
+                return Ok((state, rejected_deposits, work));
+            }
+        }
+
+        let work = match prepare_maintenance(&mut config, &state, maintenance_options)? {
+            Some(prepared) => PlannedWork::Maintenance(prepared),
+            None => {
+                let proposal =
+                    prepare_propose_deactivation(&mut config, &state, maintenance_options)?;
+                let claim = match developer_fee_claim.filter(|_| !maintenance_options.dry_run) {
+                    Some(claim) => prepare_claim_developer_fee(&mut config, &state, claim)?,
+                    None => None,
+                };
+                PlannedWork::Idle { proposal, claim }
+            }
+        };
+        Ok((state, rejected_deposits, work))
+    });
+
+    let result = result.and_then(|(state, rejected_deposits, work)| {
+        if let Some(rejected_deposits) = rejected_deposits {
+            metrics.observe_rejected_deposits(rejected_deposits);
+        }
+        if let Some(minimum) = maintenance_options.minimum_stake_delta {
+            metrics.observe_deferred_stake(&state.get_deferred_stake_movements(minimum));
+        }
+        if !state
+            .get_stale_stake_movements(maintenance_options)
+            .is_empty()
+        {
+            metrics.stale_state_refusals += 1;
+        }
+
+        // The maintainer signs maintenance transactions, and a separate fee payer signs too.
+        let num_signatures = if state.fee_payer_address == state.maintainer_address {
+            1
+        } else {
+            2
+        };
+
+        match work {
+            PlannedWork::Observe => {
+                do_wait = true;
+                if let Some((_, output)) = select_maintenance(&state, maintenance_options) {
+                    Event::info(
+                        "maintenance_due",
+                        format!("Observing only, not performing:\n{}", output),
+                    )
+                    .field("operation", output.operation_name())
+                    .field("output", &output)
+                    .field("slot", state.clock.slot)
+                    .emit();
+                }
+            }
+            PlannedWork::TopUp(transaction, performed) => {
+                config.send_transaction(&transaction)?;
+                Event::info(
+                    "fee_payer_topped_up",
+                    format!(
+                        "Transferred {} from {} to fee payer {}.",
+                        performed.amount, performed.funding_address, performed.fee_payer_address,
+                    ),
+                )
+                .field("funding_address", performed.funding_address.to_string())
+                .field("fee_payer_address", performed.fee_payer_address.to_string())
+                .field("amount_lamports", performed.amount.0)
+                .field(
+                    "transaction_signature",
+                    performed.transaction_signature.to_string(),
+                )
+                .field("slot", state.clock.slot)
+                .emit();
+                let mut record = AuditRecord::new(
+                    wall_clock.now(),
+                    *solido_address,
+                    state.clock.slot,
+                    "TopUpFeePayer",
+                    &serde_json::json!({
+                        "funding_address": performed.funding_address.to_string(),
+                        "fee_payer_address": performed.fee_payer_address.to_string(),
+                        "amount_lamports": performed.amount.0,
+                    }),
+                );
+                record.transaction_signature = Some(performed.transaction_signature.to_string());
+                // The funding account pays the fee, and is the only signer.
+                record.fee_lamports = Some(get_transaction_fee(1, Lamports(0)).0);
+                append_audit_record(audit_log, &record);
+                metrics.fee_payer_top_ups += 1;
+                // The fee payer balance in the state is outdated now, poll
+                // again right away rather than fail the balance check.
+            }
+            PlannedWork::Idle { proposal, claim } => {
+                // Nothing to be done, try again later.
+                do_wait = true;
+
+                // Proposals wait for the multisig owners, they don't change
+                // the state, so we can still wait after making one.
+                if let Some((transaction, proposed)) = proposal {
+                    let transaction_signature = config.send_transaction(&transaction)?;
+                    Event::warning(
+                        "deactivation_proposed",
+                        format!(
+                            "Validator {} earned no vote credits in {} epochs, proposed to \
+                            deactivate it in {} {}.",
+                            proposed.validator_vote_account,
+                            proposed.delinquent_epochs,
+                            proposed.proposal_kind,
+                            proposed.transaction_address,
+                        ),
+                    )
+                    .field("output", &proposed)
+                    .field("slot", state.clock.slot)
+                    .emit();
+                    let mut record = AuditRecord::new(
+                        wall_clock.now(),
+                        *solido_address,
+                        state.clock.slot,
+                        "ProposeDeactivation",
+                        &proposed,
+                    );
+                    record.transaction_signature = Some(transaction_signature.to_string());
+                    append_audit_record(audit_log, &record);
+                    metrics.deactivations_proposed += 1;
+                }
+
+                // Claiming only moves stSOL out of the developer fee account,
+                // which maintenance does not read, so we can still wait too.
+                if let Some((transaction, claimed)) = claim {
+                    config.send_transaction(&transaction)?;
+                    Event::info(
+                        "developer_fee_claimed",
+                        format!(
+                            "Transferred {} from developer fee account {} to {} destinations.",
+                            claimed.amount,
+                            claimed.developer_account,
+                            claimed.transfers.len(),
+                        ),
+                    )
+                    .field("output", &claimed)
+                    .field(
+                        "transaction_signature",
+                        claimed.transaction_signature.to_string(),
+                    )
+                    .field("slot", state.clock.slot)
+                    .emit();
+                    let mut record = AuditRecord::new(
+                        wall_clock.now(),
+                        *solido_address,
+                        state.clock.slot,
+                        "ClaimDeveloperFee",
+                        &claimed,
+                    );
+                    record.transaction_signature = Some(claimed.transaction_signature.to_string());
+                    // The owner of the developer fee account signs, and the fee payer too.
+                    let claim_signatures = transaction.signatures.len() as u64;
+                    record.fee_lamports =
+                        Some(get_transaction_fee(claim_signatures, Lamports(0)).0);
+                    append_audit_record(audit_log, &record);
+                    metrics.developer_fee_claims += 1;
+                    metrics.developer_fee_claimed_st_lamports += claimed.amount.0;
+                }
+            }
+            PlannedWork::Maintenance(prepared) => match send_maintenance(config, prepared) {
+                MaintenanceAttempt::SkippedDuplicate {
+                    output,
+                    duplicate_signature,
+                } => {
+                    Event::info(
+                        "skipped_duplicate",
+                        format!(
+                            "Skipped {}, another maintainer performed it in transaction {}.",
+                            output.operation_name(),
+                            duplicate_signature,
+                        ),
+                    )
+                    .field("operation", output.operation_name())
+                    .field("duplicate_signature", &duplicate_signature)
+                    .field("slot", state.clock.slot)
+                    .emit();
+                    metrics.skipped_duplicate += 1;
+                    // Our state is outdated, read it again before doing more.
+                    do_wait = false;
+                }
+                MaintenanceAttempt::Performed(PerformedMaintenance {
+                    output,
+                    priority_fee,
+                    transaction_signature,
+                }) => {
+                    Event::info("maintenance_performed", output.to_string())
+                        .field("operation", output.operation_name())
+                        .field("output", &output)
+                        .field("transaction_signature", transaction_signature.to_string())
+                        .field("priority_fee_lamports", priority_fee.0)
+                        .field("slot", state.clock.slot)
+                        .emit();
+                    let mut record = AuditRecord::new(
+                        wall_clock.now(),
+                        *solido_address,
+                        state.clock.slot,
+                        output.operation_name(),
+                        &output,
+                    );
+                    record.transaction_signature = Some(transaction_signature.to_string());
+                    record.fee_lamports = Some(get_transaction_fee(num_signatures, priority_fee).0);
+                    append_audit_record(audit_log, &record);
+                    metrics.observe_maintenance(&output);
+                    metrics.priority_fees_lamports += priority_fee.0;
+                    performed = Some(output);
+                }
+                MaintenanceAttempt::Failed {
+                    output,
+                    priority_fee,
+                    error,
+                } => {
+                    let mut record = AuditRecord::new(
+                        wall_clock.now(),
+                        *solido_address,
+                        state.clock.slot,
+                        output.operation_name(),
+                        &output,
+                    );
+                    record.fee_lamports = Some(get_transaction_fee(num_signatures, priority_fee).0);
+                    record.result = AuditResult::Failed {
+                        error: error.summary(),
+                    };
+                    append_audit_record(audit_log, &record);
+                    // We handle the error like any other error in maintenance.
+                    return Err(error);
+                }
+            },
+        }
+
+        Ok(state)
+    });
+    metrics.iteration_duration.observe(start.elapsed());
+    if let Some(output) = &performed {
+        // E.g. the stake accounts that we merged no longer exist, so the next
+        // snapshot does not need to read them.
+        config
+            .client
+            .forget_accounts(&output.get_closed_accounts(solido_program_id, solido_address));
+    }
+    metrics.rpc = config.client.rpc_metrics().clone();
+    metrics.commitment = config.client.commitment();
+
+    let solido = match result {
+        Ok(state) => {
+            metrics.observed_slot = Some(state.clock.slot);
+            metrics.clock_lag = Some(state.get_clock_lag());
+            Some(state)
+        }
+        Err(err) => {
+            Event::error("maintenance_error", "Error in maintenance.")
+                .with_error(&err)
+                .emit();
+            metrics.errors += 1;
+
+            // If the error was caused by a connectivity problem, we shouldn't
+            // hammer the RPC again straight away. The main loop backs off
+            // exponentially when errors persist.
+            do_wait = true;
+
+            None
+        }
+    };
+
+    IterationOutcome {
+        do_wait,
+        solido,
+        maintenance_output: performed,
+    }
+}
+
+/// Append the record to the audit log, if there is one.
+///
+/// Failing to write the audit log should not stop maintenance, so we only
+/// report the error.
+fn append_audit_record(audit_log: Option<&AuditLog>, record: &AuditRecord) {
+    if let Some(audit_log) = audit_log {
+        if let Err(err) = audit_log.append(record) {
+            Event::error(
+                "audit_log_error",
+                format!(
+                    "Failed to append to audit log {}: {}",
+                    audit_log.path().display(),
+                    err
+                ),
+            )
+            .field("path", audit_log.path().display().to_string())
+            .emit();
+        }
+    }
+}
+
+/// Exponential backoff with jitter, for how long to wait after consecutive errors.
+///
+/// The wait time window starts at `base`, and doubles after every consecutive
+/// error, up to `max`. We wait a random time in the upper half of the window,
+/// so multiple daemons that fail at the same time do not retry in sync, while
+/// the wait time still grows.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    consecutive_errors: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Backoff {
+        Backoff {
+            base,
+            max,
+            consecutive_errors: 0,
+        }
+    }
+
+    /// Return the upper bound of the time to wait, or zero if there were no errors.
+    pub fn window(&self) -> Duration {
+        if self.consecutive_errors == 0 {
+            return Duration::from_secs(0);
+        }
+        let factor = 1_u32
+            .checked_shl(self.consecutive_errors - 1)
+            .unwrap_or(u32::MAX);
+        self.base
+            .checked_mul(factor)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// Record an error, and return how long to wait before trying again.
+    pub fn on_error<R: Rng>(&mut self, rng: &mut R) -> Duration {
+        self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+        let window = self.window();
+        if window == Duration::from_secs(0) {
+            return window;
+        }
+        rng.gen_range(window / 2..=window)
+    }
+
+    /// Record a success, so the next error starts from the base interval again.
+    pub fn reset(&mut self) {
+        self.consecutive_errors = 0;
+    }
+
+    /// Change the base and maximum wait time, without forgetting the errors so far.
+    pub fn set_limits(&mut self, base: Duration, max: Duration) {
+        self.base = base;
+        self.max = max;
+    }
+}
+
+/// How often we poll close to an epoch boundary, see `--epoch-boundary-slots`.
+const EPOCH_BOUNDARY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Return how long we may wait at most before the next poll, so we update the
+/// exchange rate soon after a new epoch starts.
+///
+/// In the last `boundary_slots` slots of an epoch, and in the first ones until
+/// the exchange rate is updated, we poll every second. Before that, we wake up
+/// when the end of the epoch gets close, estimated at the default slot time.
+fn get_epoch_boundary_sleep_limit(state: &SolidoState, boundary_slots: Slot) -> Duration {
+    let exchange_rate_outdated = state.solido.exchange_rate.computed_in_epoch < state.clock.epoch;
+    if exchange_rate_outdated && state.slots_into_epoch() < boundary_slots {
+        return EPOCH_BOUNDARY_POLL_INTERVAL;
+    }
+    let slots_until_window = state
+        .slots_until_next_epoch()
+        .saturating_sub(boundary_slots);
+    Duration::from_millis(slots_until_window * DEFAULT_MS_PER_SLOT)
+        .max(EPOCH_BOUNDARY_POLL_INTERVAL)
+}
+
+/// A Solido instance that the daemon maintains, and what we track about it.
+struct Instance<'a> {
+    /// Value of the `instance` label, `None` when the daemon maintains a single instance.
+    name: Option<String>,
+    solido_program_id: Pubkey,
+    solido_address: Pubkey,
+    config: SnapshotClientConfig<'a>,
+    maintenance_options: MaintenanceOptions,
+    fee_payer_top_up: Option<FeePayerTopUp<'a>>,
+    developer_fee_claim: Option<DeveloperFeeClaim<'a>>,
+    metrics: MaintenanceMetrics,
+    backoff: Backoff,
+    exporter: Option<EpochExporter>,
+    audit_log: Option<AuditLog>,
+    webhooks: Option<Webhooks>,
+    exchange_rate_feed: Option<ExchangeRateFeed>,
+    alerter: Option<Alerter>,
+
+    /// The latest metrics and state, for the http server.
+    snapshot: Option<Arc<InstanceSnapshot>>,
+
+    /// When to poll this instance again.
+    next_poll_at: SystemTime,
+}
+
+impl<'a> Instance<'a> {
+    /// Run one iteration for this instance, pass on what we observed, and schedule the next one.
+    fn poll<R: Rng>(
+        &mut self,
+        wall_clock: &dyn WallClock,
+        history: &mut dyn History,
+        max_poll_interval: Duration,
+        rng: &mut R,
+    ) {
+        let outcome = run_iteration(
+            &mut self.config,
+            wall_clock,
+            &self.solido_program_id,
+            &self.solido_address,
+            &self.maintenance_options,
+            self.fee_payer_top_up.as_ref(),
+            self.developer_fee_claim.as_ref(),
+            self.audit_log.as_ref(),
+            &mut self.metrics,
+        );
+        self.metrics.config = ConfigMetrics::new(
+            self.solido_program_id,
+            self.solido_address,
+            max_poll_interval,
+            &self.maintenance_options,
+        );
+
+        let error_sleep_time = match outcome.solido {
+            Some(_) => {
+                self.backoff.reset();
+                None
+            }
+            None => Some(self.backoff.on_error(rng)),
+        };
+        self.metrics.error_backoff_seconds = self.backoff.window().as_secs();
+
+        if let Some(ref solido) = outcome.solido {
+            history.record_state(solido);
+            if let Some(ref maintenance_output) = outcome.maintenance_output {
+                history.record_maintenance(solido, maintenance_output);
+            }
+            if let Some(ref exporter) = self.exporter {
+                match exporter.observe(solido) {
+                    Ok(Some(path)) => Event::info(
+                        "epoch_exported",
+                        format!("Exported epoch analytics to {}.", path.display()),
+                    )
+                    .field("path", path.display().to_string())
+                    .field("epoch", solido.clock.epoch)
+                    .emit(),
+                    Ok(None) => {}
+                    Err(err) => Event::error(
+                        "epoch_export_error",
+                        format!("Error while exporting epoch analytics: {}", err),
+                    )
+                    .field("epoch", solido.clock.epoch)
+                    .emit(),
+                }
+            }
+            if let Some(feed) = self.exchange_rate_feed.as_mut() {
+                feed.observe(&self.solido_address, wall_clock.now(), solido);
+            }
+        }
+
+        if let Some(webhooks) = self.webhooks.as_mut() {
+            webhooks.observe(
+                &self.solido_address,
+                wall_clock.now(),
+                outcome.solido.as_ref(),
+                outcome.maintenance_output.as_ref(),
+            );
+        }
+
+        if let Some(alerter) = self.alerter.as_mut() {
+            alerter.observe(&self.solido_address, outcome.solido.as_ref());
+        }
+
+        let sleep_limit = match (
+            self.maintenance_options.epoch_boundary_slots,
+            outcome.solido.as_ref(),
+        ) {
+            (Some(boundary_slots), Some(solido)) => {
+                Some(get_epoch_boundary_sleep_limit(solido, boundary_slots))
+            }
+            _ => None,
+        };
+
+        let maintenance = outcome
+            .solido
+            .as_ref()
+            .map(|solido| explain_maintenance(solido, &self.maintenance_options));
+        self.snapshot = Some(Arc::new(InstanceSnapshot {
+            name: self.name.clone(),
+            metrics: self.metrics.clone(),
+            solido: outcome.solido,
+            maintenance,
+        }));
+
+        // Sleep a random time, to avoid a thundering herd problem, in case
+        // multiple maintainer bots happened to run in sync. They would all
+        // try to create the same transaction, and only one would pass.
+        let sleep_time = match (outcome.do_wait, error_sleep_time) {
+            (false, _) => Duration::from_secs(0),
+            (true, Some(sleep_time)) => sleep_time,
+            (true, None) => {
+                let sleep_time = rng.gen_range(Duration::from_secs(0)..max_poll_interval);
+                sleep_limit.map_or(sleep_time, |limit| sleep_time.min(limit))
+            }
+        };
+        self.next_poll_at = wall_clock.now() + sleep_time;
+    }
+
+    fn restore(&mut self, state: DaemonState) {
+        self.config
+            .client
+            .restore_learned_state(&state.snapshot_client);
+        self.config.client.restore_rpc_metrics(&state.metrics.rpc);
+        self.metrics = state.metrics;
+    }
+
+    fn get_state(&self) -> DaemonState {
+        DaemonState {
+            metrics: self.metrics.clone(),
+            snapshot_client: self.config.client.learned_state(),
+            instances: BTreeMap::new(),
+        }
+    }
+}
+
+/// Return the maintenance options for the instance at `index`, 0 being --solido-address.
+fn get_instance_maintenance_options(opts: &RunMaintainerOpts, index: usize) -> MaintenanceOptions {
+    let mut options = get_maintenance_options(opts);
+    // The manager multisig and governance belong to the instance of --solido-address.
+    if index > 0 {
+        options.manager_multisig = None;
+        options.manager_governance = None;
+    }
+    options
+}
+
+fn get_maintenance_options(opts: &RunMaintainerOpts) -> MaintenanceOptions {
+    MaintenanceOptions {
+        publish_exchange_rate_max_age_slots: match *opts.publish_exchange_rate_interval_slots() {
+            0 => None,
+            interval => Some(interval),
+        },
+        keeper_grace_period: match *opts.keeper_grace_period_seconds() {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        },
+        epoch_boundary_slots: match *opts.epoch_boundary_slots() {
+            0 => None,
+            slots => Some(slots),
+        },
+        dry_run: *opts.dry_run(),
+        observe_only: *opts.observe_only(),
+        priority_fee: PriorityFeeOptions {
+            micro_lamports_per_compute_unit: *opts.priority_fee_micro_lamports(),
+            dynamic: *opts.dynamic_priority_fee(),
+        },
+        maintainer_lease_slots: match *opts.maintainer_lease_slots() {
+            0 => None,
+            slots => Some(slots),
+        },
+        deactivate_delinquent_epochs: match *opts.deactivate_delinquent_epochs() {
+            0 => None,
+            epochs => Some(epochs),
+        },
+        minimum_stake_delta: match *opts.minimum_stake_delta_sol() {
+            Lamports(0) => None,
+            amount => Some(amount),
+        },
+        max_state_lag_slots: match *opts.max_state_lag_slots() {
+            0 => None,
+            slots => Some(slots),
+        },
+        manager_multisig: if *opts.manager_multisig_address() == Pubkey::default() {
+            None
+        } else {
+            Some(ManagerMultisig {
+                program_id: *opts.multisig_program_id(),
+                address: *opts.manager_multisig_address(),
+            })
+        },
+        manager_governance: if *opts.manager_governance_address() == Pubkey::default() {
+            None
+        } else {
+            Some(ManagerGovernance {
+                program_id: *opts.governance_program_id(),
+                address: *opts.manager_governance_address(),
+                governing_token_mint: *opts.governing_token_mint(),
+            })
+        },
+        skip_duplicate_work: *opts.skip_duplicate_work(),
+        avoid_superminority: *opts.avoid_superminority(),
+    }
+}
+
+/// Check the options that clap cannot check for us.
+fn validate_opts(opts: &RunMaintainerOpts) -> Result<(), String> {
+    if *opts.maintainer_lease_slots() > lido::MAXIMUM_MAINTAINER_LEASE_SLOTS {
+        return Err(format!(
+            "--maintainer-lease-slots can be at most {}.",
+            lido::MAXIMUM_MAINTAINER_LEASE_SLOTS
+        ));
+    }
+    if *opts.manager_multisig_address() != Pubkey::default()
+        && *opts.multisig_program_id() == Pubkey::default()
+    {
+        return Err("--manager-multisig-address requires --multisig-program-id.".to_string());
+    }
+    if *opts.manager_governance_address() != Pubkey::default()
+        && (*opts.governance_program_id() == Pubkey::default()
+            || *opts.governing_token_mint() == Pubkey::default())
+    {
+        return Err(
+            "--manager-governance-address requires --governance-program-id and \
+            --governing-token-mint."
+                .to_string(),
+        );
+    }
+    if opts.http_tls_cert_path().is_empty() != opts.http_tls_key_path().is_empty() {
+        return Err(
+            "--http-tls-cert-path and --http-tls-key-path must be set together.".to_string(),
+        );
+    }
+    if opts.listen().starts_with(UNIX_SOCKET_PREFIX) && !opts.http_tls_cert_path().is_empty() {
+        return Err("--http-tls-cert-path cannot be used with a unix socket --listen.".to_string());
+    }
+    if *opts.top_up_funding_keypair() != PathBuf::default()
+        && (*opts.top_up_below_sol() == Lamports(0) || *opts.top_up_amount_sol() == Lamports(0))
+    {
+        return Err(
+            "--top-up-funding-keypair requires --top-up-below-sol and --top-up-amount-sol."
+                .to_string(),
+        );
+    }
+    if (*opts.developer_fee_keypair() == PathBuf::default())
+        != opts.developer_fee_splits().0.is_empty()
+    {
+        return Err(
+            "--developer-fee-keypair and --developer-fee-splits must be set together.".to_string(),
+        );
+    }
+    if *opts.observe_only() {
+        let signing_options = [
+            ("--dry-run", *opts.dry_run()),
+            (
+                "--top-up-funding-keypair",
+                *opts.top_up_funding_keypair() != PathBuf::default(),
+            ),
+            (
+                "--developer-fee-keypair",
+                *opts.developer_fee_keypair() != PathBuf::default(),
+            ),
+            (
+                "--alert-maintainer-balance-below-sol",
+                *opts.alert_maintainer_balance_below_sol() != Lamports(0),
+            ),
+        ];
+        if let Some((name, _)) = signing_options.iter().find(|(_, is_set)| *is_set) {
+            return Err(format!(
+                "--observe-only has no maintainer key, it cannot be used with {}.",
+                name
+            ));
+        }
+    }
+    if !opts.webhook_urls().is_empty() && opts.webhook_secret().is_empty() {
+        return Err("--webhook-urls requires --webhook-secret to be set.".to_string());
+    }
+    if !opts.instances().0.is_empty() {
+        let mut names: Vec<&str> = Vec::new();
+        let specs = opts.instances().0.iter().map(|spec| spec.name.as_str());
+        for name in std::iter::once(opts.instance_name().as_str()).chain(specs) {
+            let is_valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !is_valid {
+                return Err(format!(
+                    "Invalid instance name \"{}\", use only letters, digits, '-', and '_'.",
+                    name
+                ));
+            }
+            if names.contains(&name) {
+                return Err(format!("Instance name \"{}\" occurs more than once.", name));
+            }
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Return the options that we only read at startup, with their values.
+fn get_startup_options(opts: &RunMaintainerOpts) -> Vec<(&'static str, String)> {
+    vec![
+        ("solido_program_id", opts.solido_program_id().to_string()),
+        ("solido_address", opts.solido_address().to_string()),
+        ("instances", format!("{:?}", opts.instances())),
+        ("instance_name", opts.instance_name().clone()),
+        ("listen", opts.listen().clone()),
+        ("http_tls_cert_path", opts.http_tls_cert_path().clone()),
+        ("http_tls_key_path", opts.http_tls_key_path().clone()),
+        ("http_bearer_token", opts.http_bearer_token().clone()),
+        ("postgres_url", opts.postgres_url().clone()),
+        ("export_path", opts.export_path().clone()),
+        ("audit_log_path", opts.audit_log_path().clone()),
+        (
+            "audit_log_max_megabytes",
+            opts.audit_log_max_megabytes().to_string(),
+        ),
+        (
+            "audit_log_keep_files",
+            opts.audit_log_keep_files().to_string(),
+        ),
+        ("state_file", opts.state_file().clone()),
+        ("webhook_urls", opts.webhook_urls().clone()),
+        ("webhook_secret", opts.webhook_secret().clone()),
+        (
+            "exchange_rate_push_urls",
+            opts.exchange_rate_push_urls().clone(),
+        ),
+        (
+            "enable_quit_endpoint",
+            opts.enable_quit_endpoint().to_string(),
+        ),
+        (
+            "enable_reload_endpoint",
+            opts.enable_reload_endpoint().to_string(),
+        ),
+        ("alert_webhook_urls", opts.alert_webhook_urls().clone()),
+        (
+            "alert_webhook_format",
+            format!("{:?}", opts.alert_webhook_format()),
+        ),
+        (
+            "alert_consecutive_errors",
+            opts.alert_consecutive_errors().to_string(),
+        ),
+        (
+            "alert_exchange_rate_delay_slots",
+            opts.alert_exchange_rate_delay_slots().to_string(),
+        ),
+        (
+            "alert_maintainer_balance_below_sol",
+            opts.alert_maintainer_balance_below_sol().to_string(),
+        ),
+        (
+            "top_up_funding_keypair",
+            opts.top_up_funding_keypair().display().to_string(),
+        ),
+        ("top_up_below_sol", opts.top_up_below_sol().to_string()),
+        ("top_up_amount_sol", opts.top_up_amount_sol().to_string()),
+        (
+            "developer_fee_keypair",
+            opts.developer_fee_keypair().display().to_string(),
+        ),
+        (
+            "developer_fee_splits",
+            format!("{:?}", opts.developer_fee_splits().0),
+        ),
+        (
+            "developer_fee_claim_minimum_st_sol",
+            opts.developer_fee_claim_minimum_st_sol().to_string(),
+        ),
+        ("observe_only", opts.observe_only().to_string()),
+    ]
+}
+
+/// Check that reloading would not change options that we only read at startup.
+///
+/// We reject such a reload entirely, rather than applying only part of it.
+fn check_reload(current: &RunMaintainerOpts, reloaded: &RunMaintainerOpts) -> Result<(), String> {
+    let changed: Vec<&str> = get_startup_options(current)
+        .into_iter()
+        .zip(get_startup_options(reloaded))
+        .filter(|(current, reloaded)| current != reloaded)
+        .map(|((name, _), _)| name)
+        .collect();
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Changing {} requires a restart.",
+            changed.join(", ")
+        ))
+    }
+}
+
+/// The options of the daemon, as read again after SIGHUP.
+pub struct ReloadedOpts {
+    pub opts: RunMaintainerOpts,
+
+    /// A client for every url in the --cluster of the reloaded config.
+    pub rpc_clients: Vec<RpcClient>,
+
+    /// The --commitment in the reloaded config.
+    pub commitment: Commitment,
+
+    /// The maintainer keypair, loaded again from --keypair-path.
+    ///
+    /// `None` when the signer can not be reloaded, for a remote signer or an
+    /// inline --keypair, and with --observe-only, which has no key.
+    pub signer: Option<Box<dyn Signer>>,
+}
+
+/// Reads the options of the daemon again, see [`ReloadedOpts`].
+pub type ReloadFn<'a> = &'a dyn Fn() -> Result<ReloadedOpts, Error>;
+
+/// Reload the options and switch to the new RPC client and maintainer key, if
+/// the new options are valid.
+///
+/// The RPC urls of --instances can only change with a restart, but the
+/// commitment and the maintainer key apply to all instances.
+///
+/// Returns the new options, or `None` if we should keep the current ones.
+fn reload_opts(
+    instances: &mut [Instance],
+    signer: &ReloadableSigner,
+    current: &RunMaintainerOpts,
+    reload: ReloadFn,
+) -> Option<RunMaintainerOpts> {
+    let reloaded = match reload() {
+        Ok(reloaded) => reloaded,
+        Err(err) => {
+            Event::error(
+                "reload_error",
+                "Failed to reload the config, keeping the current options.",
+            )
+            .with_error(&*err)
+            .emit();
+            return None;
+        }
+    };
+    let validation =
+        validate_opts(&reloaded.opts).and_then(|()| check_reload(current, &reloaded.opts));
+    if let Err(message) = validation {
+        Event::error(
+            "reload_error",
+            format!("Invalid config, keeping the current options: {}", message),
+        )
+        .emit();
+        return None;
+    }
+    instances[0]
+        .config
+        .client
+        .set_endpoints(reloaded.rpc_clients);
+    for instance in instances.iter_mut() {
+        instance.config.client.set_commitment(reloaded.commitment);
+        instance
+            .config
+            .client
+            .set_simulate_before_send(*reloaded.opts.simulate_before_send());
+        instance
+            .config
+            .client
+            .set_broadcast_transactions(*reloaded.opts.broadcast_transactions());
+    }
+    if let Some(new_signer) = reloaded.signer {
+        let old_pubkey = signer.pubkey();
+        let new_pubkey = new_signer.pubkey();
+        if new_pubkey != old_pubkey {
+            signer.replace(new_signer);
+            Event::info(
+                "maintainer_key_rotated",
+                format!(
+                    "Switched the maintainer key from {} to {}.",
+                    old_pubkey, new_pubkey
+                ),
+            )
+            .field("old_maintainer", old_pubkey.to_string())
+            .field("new_maintainer", new_pubkey.to_string())
+            .emit();
+        }
+    }
+    Event::info("reloaded", "Reloaded the config.").emit();
+    Some(reloaded.opts)
+}
+
+/// Run the maintenance loop.
+#[allow(clippy::too_many_arguments)]
+fn run_main_loop(
+    instances: &mut [Instance],
+    wall_clock: &dyn WallClock,
+    mut opts: RunMaintainerOpts,
+    snapshot_mutex: &SnapshotMutex,
+    history: &mut dyn History,
+    state_file: Option<&Path>,
+    shutdown: &ShutdownFlag,
+    reload_requested: &ReloadFlag,
+    signer: &ReloadableSigner,
+    reload: ReloadFn,
+) {
+    if let Some(path) = state_file {
+        restore_state(instances, path);
+    }
+    let mut state_saved_at = wall_clock.now();
+    let mut rng = rand::thread_rng();
+    let mut max_poll_interval = Duration::from_secs(*opts.max_poll_interval_seconds());
+
+    // Check for shutdown only between iterations, so we never abandon an
+    // iteration halfway, e.g. after sending only one of its transactions.
+    while !shutdown.is_requested() {
+        // Swap the options only between iterations, so an iteration never
+        // mixes old and new options. The metrics carry over.
+        if reload_requested.take() {
+            if let Some(reloaded_opts) = reload_opts(instances, signer, &opts, reload) {
+                opts = reloaded_opts;
+                max_poll_interval = Duration::from_secs(*opts.max_poll_interval_seconds());
+                for (i, instance) in instances.iter_mut().enumerate() {
+                    instance.backoff.set_limits(
+                        max_poll_interval,
+                        Duration::from_secs(*opts.error_backoff_max_seconds()),
+                    );
+                    instance.maintenance_options = get_instance_maintenance_options(&opts, i);
+                }
+            }
+        }
+
+        // Poll the instance that is due first. When several are due, this is
+        // the one that has been waiting the longest.
+        let instance = instances
+            .iter_mut()
+            .min_by_key(|instance| instance.next_poll_at)
+            .expect("The daemon maintains at least one instance.");
+        logging::set_instance(instance.name.as_deref());
+        instance.poll(wall_clock, history, max_poll_interval, &mut rng);
+        logging::set_instance(None);
+
+        // Publish the new state and metrics, so the webserver can serve them.
+        let snapshot = Snapshot {
+            instances: instances
+                .iter()
+                .filter_map(|instance| instance.snapshot.clone())
+                .collect(),
+            metrics_labels: opts.metrics_labels().0.clone(),
+        };
+        snapshot_mutex.lock().unwrap().replace(Arc::new(snapshot));
+
+        if let Some(path) = state_file {
+            let now = wall_clock.now();
+            let elapsed = now.duration_since(state_saved_at).unwrap_or_default();
+            if elapsed >= STATE_FILE_INTERVAL {
+                save_state(instances, path);
+                state_saved_at = now;
+            }
+        }
+
+        let next_poll_at = instances
+            .iter()
+            .map(|instance| instance.next_poll_at)
+            .min()
+            .expect("The daemon maintains at least one instance.");
+        // When the next poll is due already, we don't sleep.
+        let sleep_time = next_poll_at
+            .duration_since(wall_clock.now())
+            .unwrap_or_default();
+        if sleep_time > Duration::from_secs(0) {
+            Event::info(
+                "sleeping",
+                format!("Sleeping {:?} until next iteration ...", sleep_time),
+            )
+            .field("sleep_seconds", sleep_time.as_secs_f64())
+            .emit();
+            sleep_unless_shutdown(wall_clock, sleep_time, shutdown);
+        }
+    }
+
+    if let Some(path) = state_file {
+        save_state(instances, path);
+    }
+
+    let polls: u64 = instances
+        .iter()
+        .map(|instance| instance.metrics.polls)
+        .sum();
+    Event::info(
+        "shutting_down",
+        format!("Shutting down after {} polls.", polls),
+    )
+    .field("polls", polls)
+    .emit();
+}
+
+/// Write the metrics of several instances, with an `instance` label to tell them apart.
+fn write_instances_prometheus<W: io::Write>(
+    out: &mut W,
+    instances: &[Arc<InstanceSnapshot>],
+) -> io::Result<()> {
+    let mut expositions = Vec::with_capacity(instances.len());
+    for instance in instances {
+        let mut exposition: Vec<u8> = Vec::new();
+        instance.write_prometheus(&mut exposition)?;
+        expositions.push((instance.name.as_deref().unwrap_or(""), exposition));
+    }
+    let sources: Vec<(&str, &[u8])> = expositions
+        .iter()
+        .map(|(name, exposition)| (*name, &exposition[..]))
+        .collect();
+    write_merged(out, "instance", &sources)
+}
+
+/// Compare in constant time, so the response time does not reveal how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Return whether the headers include an `Authorization` header with the bearer token.
+fn is_authorized(headers: &[Header], bearer_token: &str) -> bool {
+    headers
+        .iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .any(
+            |header| match header.value.as_str().strip_prefix("Bearer ") {
+                Some(token) => constant_time_eq(token.trim().as_bytes(), bearer_token.as_bytes()),
+                None => false,
+            },
+        )
+}
+
+/// Return the response to a request for `url`, on any of the http listeners.
+fn get_response(
+    method: &Method,
+    url: &str,
+    headers: &[Header],
+    snapshot_mutex: &SnapshotMutex,
+    quit: Option<&ShutdownFlag>,
+    reload: Option<&ReloadFlag>,
+    bearer_token: Option<&str>,
+) -> Response<io::Cursor<Vec<u8>>> {
+    // The token guards every endpoint, so check it before looking at the url.
+    if let Some(token) = bearer_token {
+        if !is_authorized(headers, token) {
+            let challenge = Header::from_bytes(&b"WWW-Authenticate"[..], &b"Bearer"[..])
+                .expect("Header is valid ASCII.");
+            return Response::from_string(
+                "Unauthorized\n\nProvide the token in an 'Authorization: Bearer' header.",
+            )
+            .with_status_code(401)
+            .with_header(challenge);
+        }
+    }
+
+    if url == "/quitquitquit" {
+        return match quit {
+            Some(shutdown) if *method == Method::Post => {
+                Event::info(
+                    "shutdown_requested",
+                    "Shutdown requested through /quitquitquit.",
+                )
+                .emit();
+                shutdown.request();
+                Response::from_string("Shutting down after the current iteration.\n")
+            }
+            Some(_) => {
+                Response::from_string("Method Not Allowed\n\nUse POST.").with_status_code(405)
+            }
+            None => Response::from_string(
+                "Not Found\n\nStart the daemon with --enable-quit-endpoint to enable this.",
+            )
+            .with_status_code(404),
+        };
+    }
+
+    if url == "/reload" {
+        return match reload {
+            Some(reload) if *method == Method::Post => {
+                Event::info("reload_requested", "Reload requested through /reload.").emit();
+                reload.request();
+                Response::from_string("Reloading the config before the next iteration.\n")
+            }
+            Some(_) => {
+                Response::from_string("Method Not Allowed\n\nUse POST.").with_status_code(405)
+            }
+            None => Response::from_string(
+                "Not Found\n\nStart the daemon with --enable-reload-endpoint to enable this.",
+            )
+            .with_status_code(404),
+        };
+    }
+
+    // Take the current snapshot. This only holds the lock briefly, and does
+    // not prevent other threads from updating the snapshot while this request
+    // handler is running.
+    let option_snapshot = snapshot_mutex.lock().unwrap().clone();
+
+    // It might be that no snapshot is available yet. This happens when we just
+    // started the server, and the main loop has not yet queried the RPC for the
+    // latest state.
+    let snapshot = match option_snapshot {
+        Some(arc_snapshot) => arc_snapshot,
+        None => {
+            return Response::from_string(
+                "Service Unavailable\n\nServer is still starting, try again shortly.",
+            )
+            .with_status_code(503);
+        }
+    };
+
+    if url == "/state" {
+        let instances: Vec<_> = snapshot
+            .instances
+            .iter()
+            .map(|instance| {
+                serde_json::json!({
+                    "instance": instance.name,
+                    "maintenance": instance.maintenance,
+                })
+            })
+            .collect();
+        return match serde_json::to_vec_pretty(&instances) {
+            Ok(json) => Response::from_data(json).with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                    .expect("Header is valid ASCII."),
+            ),
+            Err(_) => Response::from_string("error").with_status_code(500),
+        };
+    }
+
+    // For all other paths, we serve the metrics.
+
+    let mut out: Vec<u8> = Vec::new();
+    let mut is_ok = match &snapshot.instances[..] {
+        [instance] if instance.name.is_none() => instance.write_prometheus(&mut out).is_ok(),
+        instances => write_instances_prometheus(&mut out, instances).is_ok(),
+    };
+    if is_ok && !snapshot.metrics_labels.is_empty() {
+        let mut labeled: Vec<u8> = Vec::with_capacity(out.len());
+        is_ok = write_with_labels(&mut labeled, &snapshot.metrics_labels, &out).is_ok();
+        out = labeled;
+    }
+
+    if is_ok {
+        Response::from_data(out)
+    } else {
+        Response::from_string("error").with_status_code(500)
+    }
+}
+
+/// The http server on --listen, which is a TCP address or a unix socket.
+enum HttpServer {
+    Tcp(Server),
+    Unix(UnixHttpServer),
+}
+
+impl HttpServer {
+    /// Serve requests until `unblock` is called.
+    fn serve(
+        &self,
+        snapshot_mutex: &SnapshotMutex,
+        quit: Option<&ShutdownFlag>,
+        reload: Option<&ReloadFlag>,
+        bearer_token: Option<&str>,
+    ) {
+        // Ignore any errors; if we fail to respond, then there's little
+        // we can do about it here ... the client should just retry.
+        match self {
+            HttpServer::Tcp(server) => {
+                for request in server.incoming_requests() {
+                    let response = get_response(
+                        request.method(),
+                        request.url(),
+                        request.headers(),
+                        snapshot_mutex,
+                        quit,
+                        reload,
+                        bearer_token,
+                    );
+                    let _ = request.respond(response);
+                }
+            }
+            HttpServer::Unix(server) => {
+                while let Some(request) = server.recv() {
+                    let response = get_response(
+                        request.method(),
+                        request.url(),
+                        request.headers(),
+                        snapshot_mutex,
+                        quit,
+                        reload,
+                        bearer_token,
+                    );
+                    let _ = request.respond(response);
+                }
+            }
+        }
+    }
+
+    /// Stop one thread that is in `serve`, after it finishes its current request.
+    fn unblock(&self) {
+        match self {
+            HttpServer::Tcp(server) => server.unblock(),
+            HttpServer::Unix(server) => server.unblock(),
+        }
+    }
+}
+
+/// Bind the http server to --listen, serving https when a certificate is configured.
+fn new_http_server(
+    opts: &RunMaintainerOpts,
+) -> Result<HttpServer, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(path) = opts.listen().strip_prefix(UNIX_SOCKET_PREFIX) {
+        return Ok(HttpServer::Unix(UnixHttpServer::bind(Path::new(path))?));
+    }
+    let server = if opts.http_tls_cert_path().is_empty() {
+        Server::http(opts.listen().clone())?
+    } else {
+        new_https_server(opts)?
+    };
+    Ok(HttpServer::Tcp(server))
+}
+
+#[cfg(feature = "tls")]
+fn new_https_server(
+    opts: &RunMaintainerOpts,
+) -> Result<Server, Box<dyn std::error::Error + Send + Sync>> {
+    let ssl_config = tiny_http::SslConfig {
+        certificate: fs::read(opts.http_tls_cert_path())?,
+        private_key: fs::read(opts.http_tls_key_path())?,
+    };
+    Server::https(opts.listen().clone(), ssl_config)
+}
+
+#[cfg(not(feature = "tls"))]
+fn new_https_server(
+    _opts: &RunMaintainerOpts,
+) -> Result<Server, Box<dyn std::error::Error + Send + Sync>> {
+    Err(
+        "--http-tls-cert-path was provided, but this build of solido does not \
+        support TLS. Rebuild with '--features tls'."
+            .into(),
+    )
+}
+
+/// Spawn threads that run the http server.
+///
+/// The threads run until `HttpServer::unblock` is called once per thread.
+fn start_http_server(
+    opts: &RunMaintainerOpts,
+    snapshot_mutex: Arc<SnapshotMutex>,
+    shutdown: &ShutdownFlag,
+    reload_requested: &ReloadFlag,
+) -> (Arc<HttpServer>, Vec<JoinHandle<()>>) {
+    let server = match new_http_server(opts) {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+            eprintln!(
+                "Error: {}\nFailed to start http server on {}. Is the daemon already running?",
+                err,
+                opts.listen(),
+            );
+            std::process::exit(1);
+        }
+    };
+
+    Event::info(
+        "http_server_started",
+        format!("Http server listening on {}", opts.listen()),
+    )
+    .field("listen", opts.listen())
+    .field("tls", !opts.http_tls_cert_path().is_empty())
+    .emit();
+
+    // Spawn a number of http handler threads, so we can handle requests in
+    // parallel. This server is only used to serve metrics, it can be super basic,
+    // but some degree of parallelism is nice in case a client is slow to send
+    // its request or something like that.
+    let quit = if *opts.enable_quit_endpoint() {
+        Some(shutdown.clone())
+    } else {
+        None
+    };
+    let reload = if *opts.enable_reload_endpoint() {
+        Some(reload_requested.clone())
+    } else {
+        None
+    };
+    let bearer_token = if opts.http_bearer_token().is_empty() {
+        None
+    } else {
+        Some(opts.http_bearer_token().clone())
+    };
+    let threads = (0..num_cpus::get())
+        .map(|i| {
+            let server_clone = server.clone();
+            let snapshot_mutex_clone = snapshot_mutex.clone();
+            let quit_clone = quit.clone();
+            let reload_clone = reload.clone();
+            let bearer_token_clone = bearer_token.clone();
+            std::thread::Builder::new()
+                .name(format!("http_handler_{}", i))
+                .spawn(move || {
+                    server_clone.serve(
+                        &*snapshot_mutex_clone,
+                        quit_clone.as_ref(),
+                        reload_clone.as_ref(),
+                        bearer_token_clone.as_deref(),
+                    )
+                })
+                .expect("Failed to spawn http handler thread.")
+        })
+        .collect();
+    (server, threads)
+}
+
+fn new_audit_log(opts: &RunMaintainerOpts) -> Option<AuditLog> {
+    if opts.audit_log_path().is_empty() {
+        return None;
+    }
+    // Instances share the file, every record names its instance.
+    Some(AuditLog::new(
+        PathBuf::from(opts.audit_log_path()),
+        *opts.audit_log_max_megabytes() * 1_000_000,
+        *opts.audit_log_keep_files(),
+    ))
+}
+
+fn new_webhooks(opts: &RunMaintainerOpts) -> Option<Webhooks> {
+    if opts.webhook_urls().is_empty() {
+        return None;
+    }
+    let endpoints = opts
+        .webhook_urls()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .collect();
+    Some(Webhooks::new(
+        endpoints,
+        opts.webhook_secret().as_bytes().to_vec(),
+    ))
+}
+
+fn new_exchange_rate_feed(opts: &RunMaintainerOpts) -> Option<ExchangeRateFeed> {
+    if opts.exchange_rate_push_urls().is_empty() {
+        return None;
+    }
+    let endpoints = opts
+        .exchange_rate_push_urls()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .collect();
+    let secret = if opts.webhook_secret().is_empty() {
+        None
+    } else {
+        Some(opts.webhook_secret().as_bytes().to_vec())
+    };
+    Some(ExchangeRateFeed::new(endpoints, secret))
+}
+
+fn new_alerter(opts: &RunMaintainerOpts) -> Option<Alerter> {
+    if opts.alert_webhook_urls().is_empty() {
+        return None;
+    }
+    let endpoints = opts
+        .alert_webhook_urls()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .collect();
+    let alert_options = AlertOptions {
+        max_consecutive_errors: match *opts.alert_consecutive_errors() {
+            0 => None,
+            n => Some(n),
+        },
+        max_exchange_rate_delay_slots: match *opts.alert_exchange_rate_delay_slots() {
+            0 => None,
+            slots => Some(slots),
+        },
+        min_maintainer_balance: match *opts.alert_maintainer_balance_below_sol() {
+            Lamports(0) => None,
+            amount => Some(amount),
+        },
+    };
+    Some(Alerter::new(
+        endpoints,
+        *opts.alert_webhook_format(),
+        alert_options,
+    ))
+}
+
+/// Set up the instance at `index`, 0 being --solido-address, with its own webhook and alert state.
+fn new_instance<'a>(
+    opts: &RunMaintainerOpts,
+    index: usize,
+    name: Option<String>,
+    solido_program_id: Pubkey,
+    solido_address: Pubkey,
+    config: SnapshotClientConfig<'a>,
+    top_up_funding: Option<&'a dyn Signer>,
+    developer_fee_owner: Option<&'a dyn Signer>,
+) -> Instance<'a> {
+    let exporter = if opts.export_path().is_empty() {
+        None
+    } else {
+        // Instances get their own directory, because the files are per epoch.
+        let directory = PathBuf::from(opts.export_path());
+        Some(EpochExporter::new(match name {
+            Some(ref name) => directory.join(name),
+            None => directory,
+        }))
+    };
+    let max_poll_interval = Duration::from_secs(*opts.max_poll_interval_seconds());
+    Instance {
+        name,
+        solido_program_id,
+        solido_address,
+        config,
+        maintenance_options: get_instance_maintenance_options(opts, index),
+        fee_payer_top_up: top_up_funding.map(|funding| FeePayerTopUp {
+            funding,
+            below: *opts.top_up_below_sol(),
+            amount: *opts.top_up_amount_sol(),
+        }),
+        // The developer fee account belongs to --solido-address, not to the
+        // other instances.
+        developer_fee_claim: developer_fee_owner.filter(|_| index == 0).map(|owner| {
+            DeveloperFeeClaim {
+                owner,
+                minimum: *opts.developer_fee_claim_minimum_st_sol(),
+                splits: opts.developer_fee_splits().0.clone(),
+            }
+        }),
+        metrics: MaintenanceMetrics::default(),
+        backoff: Backoff::new(
+            max_poll_interval,
+            Duration::from_secs(*opts.error_backoff_max_seconds()),
+        ),
+        exporter,
+        audit_log: new_audit_log(opts),
+        webhooks: new_webhooks(opts),
+        exchange_rate_feed: new_exchange_rate_feed(opts),
+        alerter: new_alerter(opts),
+        snapshot: None,
+        next_poll_at: SystemTime::UNIX_EPOCH,
+    }
+}
+
+/// Run the maintenance daemon.
+///
+/// `instance_clients` holds a snapshot client for every instance in
+/// `--instances`, in the same order. On SIGHUP, the daemon calls `reload` to
+/// read its options again, see [`ReloadedOpts`]. `signer` must be the signer
+/// of `config`, so the daemon can switch it to a reloaded maintainer key.
+pub fn main(
+    mut config: SnapshotClientConfig,
+    opts: RunMaintainerOpts,
+    instance_clients: Vec<SnapshotClient>,
+    signer: &ReloadableSigner,
+    reload: ReloadFn,
+) {
+    if let Err(message) = validate_opts(&opts) {
+        eprintln!("Error: {}", message);
+        std::process::exit(1);
+    }
+    let mut history = history::connect(opts.postgres_url());
+    let state_file = if opts.state_file().is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(opts.state_file()))
+    };
+    config
+        .client
+        .set_simulate_before_send(*opts.simulate_before_send());
+    config
+        .client
+        .set_broadcast_transactions(*opts.broadcast_transactions());
+    // We read the same accounts on every poll, and they rarely change.
+    config.client.set_cache_parsed_accounts(true);
+    let top_up_funding = if *opts.top_up_funding_keypair() == PathBuf::default() {
+        None
+    } else {
+        Some(
+            get_signer_from_path(opts.top_up_funding_keypair().clone())
+                .ok_or_abort_with("Failed to load the --top-up-funding-keypair."),
+        )
+    };
+    let developer_fee_owner = if *opts.developer_fee_keypair() == PathBuf::default() {
+        None
+    } else {
+        Some(
+            get_signer_from_path(opts.developer_fee_keypair().clone())
+                .ok_or_abort_with("Failed to load the --developer-fee-keypair."),
+        )
+    };
+
+    // Only label the instances when there is more than one.
+    let specs = &opts.instances().0;
+    let primary_name = if specs.is_empty() {
+        None
+    } else {
+        Some(opts.instance_name().clone())
+    };
+    let mut instances = Vec::with_capacity(1 + specs.len());
+    for (i, (spec, client)) in specs.iter().zip(instance_clients).enumerate() {
+        let mut instance_config = Config {
+            client,
+            signer: config.signer,
+            fee_payer: config.fee_payer,
+            output_mode: config.output_mode,
+            output_transaction_path: config.output_transaction_path.clone(),
+            nonce: config.nonce,
+        };
+        instance_config
+            .client
+            .set_simulate_before_send(*opts.simulate_before_send());
+        instance_config
+            .client
+            .set_broadcast_transactions(*opts.broadcast_transactions());
+        instance_config.client.set_cache_parsed_accounts(true);
+        instances.push(new_instance(
+            &opts,
+            i + 1,
+            Some(spec.name.clone()),
+            spec.solido_program_id,
+            spec.solido_address,
+            instance_config,
+            top_up_funding.as_deref(),
+            developer_fee_owner.as_deref(),
+        ));
+    }
+    instances.insert(
+        0,
+        new_instance(
+            &opts,
+            0,
+            primary_name,
+            *opts.solido_program_id(),
+            *opts.solido_address(),
+            config,
+            top_up_funding.as_deref(),
+            developer_fee_owner.as_deref(),
+        ),
+    );
+
+    let shutdown = ShutdownFlag::default();
+    let reload_requested = ReloadFlag::default();
+    register_signal_handlers(&shutdown, &reload_requested);
+    let snapshot_mutex = Arc::new(Mutex::new(None));
+    let (server, http_threads) =
+        start_http_server(&opts, snapshot_mutex.clone(), &shutdown, &reload_requested);
+
+    run_main_loop(
+        &mut instances,
+        &SystemClock,
+        opts,
+        &*snapshot_mutex,
+        &mut *history,
+        state_file.as_deref(),
+        &shutdown,
+        &reload_requested,
+        signer,
+        reload,
+    );
+
+    // The main loop only returns when shutdown was requested. Every unblock
+    // call stops one handler thread, after it finishes its current request.
+    for _ in &http_threads {
+        server.unblock();
+    }
+    for thread in http_threads {
+        thread.join().unwrap();
+    }
+    Event::info("shutdown_complete", "Shutdown complete.").emit();
+}
+
+#[cfg(test)]
+mod test {
+    use borsh::BorshSerialize;
+    use solana_sdk::account::Account;
+    use solana_sdk::clock::{Clock, DEFAULT_SLOTS_PER_EPOCH};
+    use solana_sdk::instruction::InstructionError;
+    use solana_sdk::program_pack::Pack;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::signer::null_signer::NullSigner;
+    use solana_sdk::signer::Signer;
+    use solana_sdk::transaction::TransactionError;
+
+    use lido::error::LidoError;
+
+    use super::*;
+    use crate::config::{ConfigFile, FeeSplits, MetricsLabels, OutputMode};
+    use crate::mock_rpc::{new_solido_fixture, MockRpc, ReadFault, SendFault, SolidoFixture};
+    use crate::priority_fee::{compute_budget_program, set_compute_unit_price};
+    use crate::rpc::SignatureInfo;
+    use crate::simulation::FakeClock;
+    use crate::snapshot::SnapshotClient;
+    use crate::transaction_tracker::BroadcastCounts;
+    use crate::Config;
+
+    fn new_config(rpc: &MockRpc, signer: &Keypair) -> SnapshotClientConfig<'_> {
+        Config {
+            client: SnapshotClient::new(rpc.clone()),
+            signer,
+            fee_payer: signer,
+            // Json mode, so we don't print a spinner in the test output.
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: None,
+        }
+    }
+
+    fn run(
+        config: &mut SnapshotClientConfig,
+        fixture: &SolidoFixture,
+        metrics: &mut MaintenanceMetrics,
+    ) -> IterationOutcome {
+        run_iteration(
+            config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &MaintenanceOptions::default(),
+            None,
+            None,
+            None,
+            metrics,
+        )
+    }
+
+    #[test]
+    fn golden_prometheus_maintenance_metrics() {
+        let mut metrics = MaintenanceMetrics {
+            polls: 13,
+            errors: 2,
+            transactions_stake_deposit: 3,
+            transactions_update_exchange_rate: 4,
+            transactions_withdraw_inactive_stake: 5,
+            transactions_collect_validator_fee: 6,
+            transactions_merge_stake: 7,
+            transactions_claim_validator_fee: 8,
+            transactions_unstake_from_inactive_validator: 9,
+            transactions_remove_validator: 10,
+            transactions_publish_exchange_rate: 11,
+            transactions_acquire_maintainer_lease: 12,
+            transactions_rebalance: 14,
+            transactions_unstake_for_withdraw_tickets: 15,
+            transactions_fund_withdraw_ticket: 16,
+            transactions_deactivate_validator: 17,
+            transactions_sweep_to_reserve: 21,
+            deactivations_proposed: 18,
+            fee_payer_top_ups: 19,
+            developer_fee_claims: 24,
+            developer_fee_claimed_st_lamports: 2_500_000_000,
+            skipped_duplicate: 20,
+            stale_state_refusals: 23,
+            priority_fees_lamports: 12_000,
+            rejected_deposits: 22,
+            rejected_deposits_until: None,
+            error_backoff_seconds: 480,
+            deferred_stake_deposit: Lamports(1_500_000_000),
+            deferred_rebalance: Lamports(0),
+            observed_slot: Some(123_456),
+            clock_lag: Some(Duration::from_millis(1_500)),
+            iteration_duration: DurationHistogram::default(),
+            rpc: RpcMetrics::default(),
+            commitment: Commitment::Confirmed,
+            config: ConfigMetrics {
+                build: BuildInfo {
+                    version: "1.0.0",
+                    commit: "0123abcd",
+                    rustc: "rustc 1.53.0",
+                },
+                solido_program_id: Pubkey::new_from_array([1; 32]),
+                solido_address: Pubkey::new_from_array([2; 32]),
+                max_poll_interval_seconds: 120,
+                keeper_grace_period_seconds: 300,
+                minimum_stake_delta: Lamports(2_000_000_000),
+                dry_run: false,
+                observe_only: false,
+                tasks: vec![
+                    ("PublishExchangeRate", true),
+                    ("DeactivateValidator", false),
+                ],
+            },
+        };
+        metrics
+            .iteration_duration
+            .observe(Duration::from_millis(1_200));
+        metrics
+            .rpc
+            .get_multiple_accounts
+            .observe(Duration::from_millis(40));
+        metrics
+            .rpc
+            .get_multiple_accounts
+            .observe(Duration::from_millis(300));
+        metrics
+            .rpc
+            .send_and_confirm_transaction
+            .observe(Duration::from_millis(900));
+        metrics.rpc.transactions.landed = 7;
+        metrics.rpc.transactions.dropped = 2;
+        metrics.rpc.transactions.expired = 1;
+        metrics.rpc.active_endpoint = 1;
+        metrics.rpc.failovers = vec![3, 0];
+        metrics.rpc.rate_limited = vec![2, 0];
+        metrics.rpc.broadcasts = vec![
+            BroadcastCounts {
+                accepted: 5,
+                failed: 1,
+                confirmed_first: 4,
+            },
+            BroadcastCounts {
+                accepted: 6,
+                failed: 0,
+                confirmed_first: 2,
+            },
+        ];
+        metrics.rpc.last_round_trip = Some(Duration::from_millis(300));
+        let mut out: Vec<u8> = Vec::new();
+        metrics.write_prometheus(&mut out).unwrap();
+        crate::golden::assert_golden(
+            "maintenance_metrics.prom",
+            std::str::from_utf8(&out).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_sleep_unless_shutdown() {
+        let clock = FakeClock::new();
+        let shutdown = ShutdownFlag::default();
+
+        sleep_unless_shutdown(&clock, Duration::from_millis(1_200), &shutdown);
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_200)
+        );
+
+        // Once shutdown is requested, we don't sleep at all.
+        shutdown.request();
+        sleep_unless_shutdown(&clock, Duration::from_secs(60), &shutdown);
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_200)
+        );
+    }
+
+    #[test]
+    fn test_backoff_grows_until_max_and_resets() {
+        let mut rng = rand::thread_rng();
+        let mut backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(300));
+        assert_eq!(backoff.window(), Duration::from_secs(0));
+
+        for expected_window in [60, 120, 240, 300, 300].iter() {
+            let sleep_time = backoff.on_error(&mut rng);
+            let window = Duration::from_secs(*expected_window);
+            assert_eq!(backoff.window(), window);
+            assert!(sleep_time >= window / 2 && sleep_time <= window);
+        }
+
+        // Many errors in a row don't overflow.
+        for _ in 0..100 {
+            backoff.on_error(&mut rng);
+        }
+        assert_eq!(backoff.window(), Duration::from_secs(300));
+
+        backoff.reset();
+        assert_eq!(backoff.window(), Duration::from_secs(0));
+        backoff.on_error(&mut rng);
+        assert_eq!(backoff.window(), Duration::from_secs(60));
+    }
+
+    fn new_run_maintainer_opts(values: serde_json::Value) -> RunMaintainerOpts {
+        let mut opts = RunMaintainerOpts::default();
+        opts.try_merge_with_config_and_environment(Some(&ConfigFile { values }))
+            .unwrap();
+        opts
+    }
+
+    fn new_fixture_instance<'a>(
+        fixture: &SolidoFixture,
+        config: SnapshotClientConfig<'a>,
+        name: Option<&str>,
+    ) -> Instance<'a> {
+        let opts = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": fixture.solido_program_id.to_string(),
+            "solido_address": fixture.solido_address.to_string(),
+        }));
+        new_instance(
+            &opts,
+            0,
+            name.map(|name| name.to_string()),
+            fixture.solido_program_id,
+            fixture.solido_address,
+            config,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_check_reload_rejects_startup_options() {
+        let program_id = Pubkey::new_unique().to_string();
+        let solido_address = Pubkey::new_unique().to_string();
+        let current = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": program_id,
+            "solido_address": solido_address,
+        }));
+
+        let reloaded = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": program_id,
+            "solido_address": solido_address,
+            "max_poll_interval_seconds": "10",
+            "dry_run": "true",
+        }));
+        assert_eq!(check_reload(&current, &reloaded), Ok(()));
+
+        let reloaded = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": program_id,
+            "solido_address": Pubkey::new_unique().to_string(),
+            "listen": "127.0.0.1:1234",
+        }));
+        assert_eq!(
+            check_reload(&current, &reloaded),
+            Err("Changing solido_address, listen requires a restart.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_try_merge_reports_invalid_config() {
+        let mut opts = RunMaintainerOpts::default();
+        let values = serde_json::json!({ "max_poll_interval_seconds": "soon" });
+        let problems = opts
+            .try_merge_with_config_and_environment(Some(&ConfigFile { values }))
+            .unwrap_err();
+        assert!(problems.contains(
+            &"Invalid value \"soon\" for \"max_poll_interval_seconds\" in the config file."
+                .to_string()
+        ));
+        // The Solido addresses have no default.
+        assert!(problems
+            .iter()
+            .any(|problem| problem.contains("--solido-address")));
+    }
+
+    #[test]
+    fn test_performs_maintenance_until_nothing_left() {
+        let maintainer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(!outcome.do_wait);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.polls, 1);
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+
+        // The mock does not execute transactions, so apply the effect of the
+        // exchange rate update ourselves.
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.store_solido();
+
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.do_wait);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.polls, 2);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_polls_often_near_epoch_boundary() {
+        let maintainer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.store_solido();
+        let mut state_at_slot = |slot: Slot| {
+            fixture.set_clock(&Clock {
+                slot,
+                epoch: slot / DEFAULT_SLOTS_PER_EPOCH,
+                ..Clock::default()
+            });
+            run(&mut config, &fixture, &mut metrics).solido.unwrap()
+        };
+
+        // In the middle of the epoch, we can sleep until the end gets close.
+        let state = state_at_slot(DEFAULT_SLOTS_PER_EPOCH + 1_000);
+        assert_eq!(
+            get_epoch_boundary_sleep_limit(&state, 150),
+            Duration::from_millis((DEFAULT_SLOTS_PER_EPOCH - 1_150) * DEFAULT_MS_PER_SLOT),
+        );
+
+        // Just before the boundary, we poll every second.
+        let state = state_at_slot(2 * DEFAULT_SLOTS_PER_EPOCH - 100);
+        assert_eq!(
+            get_epoch_boundary_sleep_limit(&state, 150),
+            EPOCH_BOUNDARY_POLL_INTERVAL,
+        );
+
+        // After the boundary, until the exchange rate is updated.
+        let state = state_at_slot(2 * DEFAULT_SLOTS_PER_EPOCH + 10);
+        assert_eq!(
+            get_epoch_boundary_sleep_limit(&state, 150),
+            EPOCH_BOUNDARY_POLL_INTERVAL,
+        );
+    }
+
+    #[test]
+    fn test_pays_priority_fee() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+        fixture
+            .rpc
+            .set_recent_prioritization_fees(vec![0, 5_000, 20_000]);
+        let options = MaintenanceOptions {
+            priority_fee: PriorityFeeOptions {
+                micro_lamports_per_compute_unit: 1_000,
+                dynamic: true,
+            },
+            ..MaintenanceOptions::default()
+        };
+
+        run_iteration(
+            &mut config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &options,
+            None,
+            None,
+            None,
+            &mut metrics,
+        );
+
+        let sent = fixture.rpc.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        let message = &sent[0].message;
+        let program_ids: Vec<Pubkey> = message
+            .instructions
+            .iter()
+            .map(|instruction| *instruction.program_id(&message.account_keys))
+            .collect();
+        assert_eq!(
+            program_ids,
+            vec![compute_budget_program::id(), fixture.solido_program_id]
+        );
+        assert_eq!(
+            message.instructions[0].data,
+            set_compute_unit_price(5_000).data
+        );
+        // 5000 micro-lamports for 200k compute units.
+        assert_eq!(metrics.priority_fees_lamports, 1_000);
+    }
+
+    #[test]
+    fn test_tops_up_fee_payer_before_maintenance() {
+        let maintainer = Keypair::new();
+        let fee_payer = Keypair::new();
+        let funding = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        config.fee_payer = &fee_payer;
+        let mut metrics = MaintenanceMetrics::default();
+        let top_up = FeePayerTopUp {
+            funding: &funding,
+            below: Lamports(100_000_000),
+            amount: Lamports(1_000_000_000),
+        };
+
+        let outcome = run_iteration(
+            &mut config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &MaintenanceOptions::default(),
+            Some(&top_up),
+            None,
+            None,
+            &mut metrics,
+        );
+
+        // The fee payer does not exist yet, so we top it up first, and then
+        // poll again right away to perform maintenance.
+        assert!(!outcome.do_wait);
+        assert_eq!(outcome.solido.unwrap().fee_payer_balance, Lamports(0));
+        assert_eq!(metrics.fee_payer_top_ups, 1);
+        let sent = fixture.rpc.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        // The funding account pays the fee of the transfer.
+        assert_eq!(sent[0].message.account_keys[0], funding.pubkey());
+        assert_eq!(sent[0].message.account_keys[1], fee_payer.pubkey());
+        assert!(sent[0].verify().is_ok());
+    }
+
+    #[test]
+    fn test_dry_run_simulates_instead_of_sending() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+        let options = MaintenanceOptions {
+            dry_run: true,
+            ..MaintenanceOptions::default()
+        };
+
+        let outcome = run_iteration(
+            &mut config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &options,
+            None,
+            None,
+            None,
+            &mut metrics,
+        );
+        assert!(outcome.do_wait);
+        assert!(outcome.solido.is_some());
+        assert!(outcome.maintenance_output.is_none());
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.transactions_update_exchange_rate, 0);
+        assert_eq!(fixture.rpc.simulated_transactions().len(), 1);
+        assert!(fixture.rpc.sent_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_observe_only_signs_nothing() {
+        let fixture = new_solido_fixture(&Keypair::new().pubkey());
+        let observer = NullSigner::new(&Pubkey::default());
+        let mut config = Config {
+            client: SnapshotClient::new(fixture.rpc.clone()),
+            signer: &observer,
+            fee_payer: &observer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: None,
+        };
+        let mut metrics = MaintenanceMetrics::default();
+        let options = MaintenanceOptions {
+            observe_only: true,
+            ..MaintenanceOptions::default()
+        };
+
+        let outcome = run_iteration(
+            &mut config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &options,
+            None,
+            None,
+            None,
+            &mut metrics,
+        );
+        assert!(outcome.do_wait);
+        assert!(outcome.maintenance_output.is_none());
+        assert!(!outcome.solido.unwrap().has_maintainer());
+        assert_eq!(metrics.errors, 0);
+        assert!(fixture.rpc.simulated_transactions().is_empty());
+        assert!(fixture.rpc.sent_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_failed_simulation_is_not_sent() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        config.client.set_simulate_before_send(true);
+        let mut metrics = MaintenanceMetrics::default();
+
+        // Somebody else updated the exchange rate after we read the snapshot.
+        fixture
+            .rpc
+            .push_simulation_fault(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(LidoError::ExchangeRateAlreadyUpToDate as u32),
+            ));
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.solido.is_none());
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.transactions_update_exchange_rate, 0);
+        assert_eq!(fixture.rpc.simulated_transactions().len(), 1);
+        assert!(fixture.rpc.sent_transactions().is_empty());
+
+        // When the simulation succeeds, we send the transaction.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(fixture.rpc.simulated_transactions().len(), 2);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_counts_rejected_deposits_after_first_poll() {
+        let maintainer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.solido.is_depositor_allowlist_enabled = true;
+        fixture.store_solido();
+
+        let new_info = |signature: &str, error: Option<LidoError>| SignatureInfo {
+            signature: signature.to_string(),
+            slot: 1,
+            err: error.map(|error| {
+                serde_json::to_value(TransactionError::InstructionError(
+                    0,
+                    InstructionError::Custom(error as u32),
+                ))
+                .unwrap()
+            }),
+            block_time: None,
+        };
+
+        // A rejection before the daemon started does not count.
+        fixture.rpc.set_signatures(
+            fixture.solido_address,
+            vec![new_info("1", Some(LidoError::DepositorNotAllowed))],
+        );
+        run(&mut config, &fixture, &mut metrics);
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.rejected_deposits, 0);
+        assert_eq!(metrics.rejected_deposits_until.as_deref(), Some("1"));
+
+        // After that, only failures with `DepositorNotAllowed` count.
+        fixture.rpc.set_signatures(
+            fixture.solido_address,
+            vec![
+                new_info("4", Some(LidoError::DepositorNotAllowed)),
+                new_info("3", Some(LidoError::InvalidAmount)),
+                new_info("2", None),
+                new_info("1", Some(LidoError::DepositorNotAllowed)),
+            ],
+        );
+        run(&mut config, &fixture, &mut metrics);
+        assert_eq!(metrics.rejected_deposits, 1);
+        assert_eq!(metrics.rejected_deposits_until.as_deref(), Some("4"));
+
+        // Without new transactions, we keep the position.
+        fixture.rpc.set_signatures(
+            fixture.solido_address,
+            vec![new_info("4", Some(LidoError::DepositorNotAllowed))],
+        );
+        run(&mut config, &fixture, &mut metrics);
+        assert_eq!(metrics.rejected_deposits, 1);
+        assert_eq!(metrics.rejected_deposits_until.as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn test_counts_rejected_deposits_once_when_the_snapshot_retries() {
+        let maintainer = Keypair::new();
+        let developer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.solido.is_depositor_allowlist_enabled = true;
+        fixture.solido.fee_recipients.developer_account = Pubkey::new_unique();
+        fixture.store_solido();
+
+        let developer_account = spl_token::state::Account {
+            mint: fixture.solido.st_sol_mint,
+            owner: developer.pubkey(),
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(developer_account, &mut data).unwrap();
+        fixture.rpc.set_account(
+            fixture.solido.fee_recipients.developer_account,
+            Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: spl_token::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+
+        let new_info = |signature: &str, error: Option<LidoError>| SignatureInfo {
+            signature: signature.to_string(),
+            slot: 1,
+            err: error.map(|error| {
+                serde_json::to_value(TransactionError::InstructionError(
+                    0,
+                    InstructionError::Custom(error as u32),
+                ))
+                .unwrap()
+            }),
+            block_time: None,
+        };
+        fixture
+            .rpc
+            .set_signatures(fixture.solido_address, vec![new_info("1", None)]);
+        run(&mut config, &fixture, &mut metrics);
+        assert_eq!(metrics.rejected_deposits_until.as_deref(), Some("1"));
+
+        // Claiming reads the developer fee account, which the snapshot does
+        // not contain yet, after we read the rejected deposits. The retry
+        // must not count them again.
+        fixture.rpc.set_signatures(
+            fixture.solido_address,
+            vec![
+                new_info("2", Some(LidoError::DepositorNotAllowed)),
+                new_info("1", None),
+            ],
+        );
+        let claim = DeveloperFeeClaim {
+            owner: &developer,
+            minimum: StLamports(0),
+            splits: vec![(Pubkey::new_unique(), 100)],
+        };
+        let outcome = run_iteration(
+            &mut config,
+            &SystemClock,
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            &MaintenanceOptions::default(),
+            None,
+            Some(&claim),
+            None,
+            &mut metrics,
+        );
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.rejected_deposits, 1);
+        assert_eq!(metrics.rejected_deposits_until.as_deref(), Some("2"));
+        // The account is empty, so there is nothing to claim.
+        assert_eq!(metrics.developer_fee_claims, 0);
+    }
+
+    #[test]
+    fn test_audit_log_records_sent_and_failed_transactions() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        config.client.set_simulate_before_send(true);
+        let mut metrics = MaintenanceMetrics::default();
+        let directory = tempfile::tempdir().unwrap();
+        let audit_log = AuditLog::new(directory.path().join("audit.log"), 1_000_000, 1);
+
+        fixture
+            .rpc
+            .push_simulation_fault(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(LidoError::ExchangeRateAlreadyUpToDate as u32),
+            ));
+        for _ in 0..2 {
+            run_iteration(
+                &mut config,
+                &SystemClock,
+                &fixture.solido_program_id,
+                &fixture.solido_address,
+                &MaintenanceOptions::default(),
+                None,
+                None,
+                Some(&audit_log),
+                &mut metrics,
+            );
+        }
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(audit_log.path())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["operation"], "UpdateExchangeRate");
+        assert_eq!(records[0]["transaction_signature"], serde_json::Value::Null);
+        assert!(records[0]["result"]["failed"]["error"].is_string());
+        assert_eq!(records[1]["operation"], "UpdateExchangeRate");
+        assert_eq!(
+            records[1]["transaction_signature"],
+            fixture.rpc.sent_transactions()[0].signatures[0].to_string()
+        );
+        assert_eq!(records[1]["fee_lamports"], 5_000);
+        assert_eq!(records[1]["result"], "confirmed");
+    }
+
+    #[test]
+    fn test_rate_limit_counts_error_and_recovers() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+
+        fixture.rpc.push_read_fault(ReadFault::RateLimited);
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.do_wait);
+        assert!(outcome.solido.is_none());
+        assert_eq!(metrics.errors, 1);
+        assert!(fixture.rpc.sent_transactions().is_empty());
+
+        // Once the RPC stops rate limiting us, we continue where we left off.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(!outcome.do_wait);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_rate_limit_fails_over_to_next_endpoint() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        // Both endpoints serve the same state, like two nodes of one cluster.
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        config.client =
+            SnapshotClient::new_with_endpoints(vec![fixture.rpc.clone(), fixture.rpc.clone()]);
+        let mut metrics = MaintenanceMetrics::default();
+
+        fixture.rpc.push_read_fault(ReadFault::RateLimited);
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(metrics.rpc.active_endpoint, 1);
+        assert_eq!(metrics.rpc.failovers, vec![1, 0]);
+        assert_eq!(metrics.rpc.rate_limited, vec![1, 0]);
+
+        // When all endpoints fail, the iteration fails, we don't loop.
+        fixture.rpc.push_read_fault(ReadFault::RateLimited);
+        fixture.rpc.push_read_fault(ReadFault::RateLimited);
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.solido.is_none());
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.rpc.failovers, vec![1, 1]);
+        assert_eq!(metrics.rpc.rate_limited, vec![2, 1]);
+        assert_eq!(metrics.rpc.active_endpoint, 0);
+    }
+
+    #[test]
+    fn test_expired_transaction_is_retried() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+
+        fixture.rpc.push_send_fault(SendFault::Expired);
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.do_wait);
+        assert!(outcome.solido.is_none());
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.transactions_update_exchange_rate, 0);
+        assert_eq!(metrics.rpc.transactions.expired, 1);
+        assert!(fixture.rpc.sent_transactions().is_empty());
+
+        // The on-chain state did not change, so the next iteration tries again.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(!outcome.do_wait);
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(metrics.rpc.transactions.landed, 1);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_low_max_multiple_accounts_splits_reads() {
+        let maintainer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.store_solido();
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+
+        // The first iteration discovers which accounts we need.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.do_wait);
+        assert_eq!(metrics.errors, 0);
+
+        fixture.rpc.set_max_accounts_per_call(2);
+        let calls_before = fixture.rpc.get_multiple_accounts_calls();
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(outcome.do_wait);
+        assert!(outcome.solido.is_some());
+        assert_eq!(metrics.errors, 0);
+        assert!(fixture.rpc.get_multiple_accounts_calls() - calls_before > 2);
+    }
+
+    #[test]
+    fn test_torn_read_is_retried() {
+        let maintainer = Keypair::new();
+        let mut fixture = new_solido_fixture(&maintainer.pubkey());
+        let mut config = new_config(&fixture.rpc, &maintainer);
+        let mut metrics = MaintenanceMetrics::default();
+
+        // Perform one maintenance operation, so the snapshot client learns
+        // about all the accounts that we need, including the sysvar that holds
+        // the recent blockhashes.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        assert!(!outcome.do_wait);
+        fixture.solido.exchange_rate.computed_in_epoch = 1;
+        fixture.store_solido();
+
+        // Now a new epoch starts, and somebody else updates the exchange rate
+        // between our first and second `GetMultipleAccounts` call. The Solido
+        // account comes first, so we read the old Solido state, together with
+        // the new clock.
+        fixture.set_clock(&Clock {
+            epoch: 2,
+            ..Clock::default()
+        });
+        let mut updated_solido = fixture.solido.clone();
+        updated_solido.exchange_rate.computed_in_epoch = 2;
+        fixture.rpc.set_max_accounts_per_call(4);
+        fixture.rpc.push_read_fault(ReadFault::TornRead {
+            address: fixture.solido_address,
+            account: Account {
+                lamports: 1_000_000_000,
+                data: updated_solido.try_to_vec().unwrap(),
+                owner: fixture.solido_program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        });
+
+        // The calls report different slots, so we read all chunks again, and
+        // that time they observe the same slot, with the updated exchange rate.
+        let outcome = run(&mut config, &fixture, &mut metrics);
+        let state = outcome.solido.expect("Expected the iteration to succeed.");
+        assert_eq!(state.snapshot_slot, 1);
+        assert_eq!(state.clock.epoch, 2);
+        assert_eq!(state.solido.exchange_rate.computed_in_epoch, 2);
+
+        // Based on the consistent state, there is nothing to do.
+        assert!(outcome.do_wait);
+        assert_eq!(metrics.errors, 0);
+        assert_eq!(metrics.transactions_update_exchange_rate, 1);
+        assert_eq!(fixture.rpc.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_state_file_restores_metrics_and_learned_state() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let config = new_config(&fixture.rpc, &maintainer);
+        let mut instances = vec![new_fixture_instance(&fixture, config, None)];
+
+        // Learn the accounts to query, and then a limit on the accounts per call.
+        let instance = &mut instances[0];
+        run(&mut instance.config, &fixture, &mut instance.metrics);
+        fixture.rpc.set_max_accounts_per_call(2);
+        let outcome = run(&mut instance.config, &fixture, &mut instance.metrics);
+        assert!(outcome.solido.is_some());
+        let learned_state = instance.config.client.learned_state();
+        assert!(!learned_state.accounts_to_query.is_empty());
+        assert!(learned_state.max_items_per_call[0].unwrap() >= 2);
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state.json");
+        save_state(&instances, &path);
+
+        // After a restart, the counters continue, and the new client does not
+        // need to learn the limit again.
+        let config = new_config(&fixture.rpc, &maintainer);
+        let mut instances = vec![new_fixture_instance(&fixture, config, None)];
+        restore_state(&mut instances, &path);
+        let instance = &mut instances[0];
+        assert_eq!(instance.metrics.polls, 2);
+        assert_eq!(instance.metrics.transactions_update_exchange_rate, 2);
+        assert_eq!(instance.config.client.learned_state(), learned_state);
+
+        run(&mut instance.config, &fixture, &mut instance.metrics);
+        assert_eq!(instance.metrics.polls, 3);
+        assert_eq!(instance.metrics.errors, 0);
+
+        // A missing state file starts from scratch.
+        let config = new_config(&fixture.rpc, &maintainer);
+        let mut instances = vec![new_fixture_instance(&fixture, config, None)];
+        restore_state(&mut instances, &directory.path().join("missing.json"));
+        assert_eq!(instances[0].metrics.polls, 0);
+    }
+
+    #[test]
+    fn test_state_file_restores_every_instance() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let new_instances = || {
+            vec![
+                new_fixture_instance(
+                    &fixture,
+                    new_config(&fixture.rpc, &maintainer),
+                    Some("main"),
+                ),
+                new_fixture_instance(
+                    &fixture,
+                    new_config(&fixture.rpc, &maintainer),
+                    Some("testnet"),
+                ),
+            ]
+        };
+
+        let mut instances = new_instances();
+        instances[0].metrics.polls = 3;
+        instances[1].metrics.polls = 5;
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state.json");
+        save_state(&instances, &path);
+
+        let mut instances = new_instances();
+        restore_state(&mut instances, &path);
+        assert_eq!(instances[0].metrics.polls, 3);
+        assert_eq!(instances[1].metrics.polls, 5);
+    }
+
+    #[test]
+    fn test_instance_metrics_have_instance_label() {
+        let instances: Vec<Arc<InstanceSnapshot>> = ["main", "testnet"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                Arc::new(InstanceSnapshot {
+                    name: Some(name.to_string()),
+                    metrics: MaintenanceMetrics {
+                        polls: i as u64 + 1,
+                        ..MaintenanceMetrics::default()
+                    },
+                    solido: None,
+                    maintenance: None,
+                })
+            })
+            .collect();
+        let mut out: Vec<u8> = Vec::new();
+        write_instances_prometheus(&mut out, &instances).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            out.matches("# HELP solido_maintenance_polls_total ")
+                .count(),
+            1
+        );
+        assert!(out.contains("solido_maintenance_polls_total{instance=\"main\"} 1\n"));
+        assert!(out.contains("solido_maintenance_polls_total{instance=\"testnet\"} 2\n"));
+        assert!(out.contains(
+            "solido_maintenance_transactions_total{instance=\"testnet\",operation=\"StakeDeposit\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_validate_opts_rejects_duplicate_instance_names() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let new_opts = |instances: String| {
+            new_run_maintainer_opts(serde_json::json!({
+                "solido_program_id": program_id.to_string(),
+                "solido_address": solido_address.to_string(),
+                "instances": instances,
+            }))
+        };
+        let testnet = format!(
+            "testnet:{}:{}:http://localhost:8899",
+            program_id,
+            Pubkey::new_unique()
+        );
+
+        let opts = new_opts(testnet.clone());
+        assert_eq!(opts.instances().0[0].cluster, "http://localhost:8899");
+        assert_eq!(validate_opts(&opts), Ok(()));
+
+        let opts = new_opts(format!("{};{}", testnet, testnet));
+        assert_eq!(
+            validate_opts(&opts),
+            Err("Instance name \"testnet\" occurs more than once.".to_string())
+        );
+
+        let opts = new_opts(testnet.replace("testnet", "main"));
+        assert_eq!(
+            validate_opts(&opts),
+            Err("Instance name \"main\" occurs more than once.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_metrics_labels_parse() {
+        let labels: MetricsLabels = "cluster=mainnet-beta, deployment=solido-prod"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            labels.0,
+            vec![
+                ("cluster".to_string(), "mainnet-beta".to_string()),
+                ("deployment".to_string(), "solido-prod".to_string()),
+            ]
+        );
+        assert_eq!("".parse::<MetricsLabels>(), Ok(MetricsLabels::default()));
+
+        assert_eq!(
+            "cluster".parse::<MetricsLabels>(),
+            Err("Expected <name>=<value>, found \"cluster\".".to_string())
+        );
+        assert_eq!(
+            "cluster-name=mainnet-beta".parse::<MetricsLabels>(),
+            Err(
+                "Invalid label name \"cluster-name\", use only letters, digits, and '_'."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            "instance=solido-prod".parse::<MetricsLabels>(),
+            Err("Label name \"instance\" is reserved.".to_string())
+        );
+        assert_eq!(
+            "cluster=mainnet-beta,cluster=testnet".parse::<MetricsLabels>(),
+            Err("Label name \"cluster\" occurs more than once.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fee_splits_parse() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let splits: FeeSplits = format!("{}:70, {}:30", a, b).parse().unwrap();
+        assert_eq!(splits.0, vec![(a, 70), (b, 30)]);
+        assert_eq!("".parse::<FeeSplits>(), Ok(FeeSplits::default()));
+
+        assert_eq!(
+            format!("{}", a).parse::<FeeSplits>(),
+            Err(format!("Expected <address>:<percentage>, found \"{}\".", a))
+        );
+        assert_eq!(
+            format!("{}:0,{}:100", a, b).parse::<FeeSplits>(),
+            Err("Invalid percentage \"0\", expected a number from 1 to 100.".to_string())
+        );
+        assert_eq!(
+            format!("{}:70,{}:20", a, b).parse::<FeeSplits>(),
+            Err("The percentages add up to 90, they must add up to 100.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_authorized_requires_bearer_token() {
+        let header = |field: &str, value: &str| {
+            Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+        };
+        assert!(is_authorized(
+            &[header("Authorization", "Bearer hunter2")],
+            "hunter2"
+        ));
+        // Header names are case-insensitive.
+        assert!(is_authorized(
+            &[header("authorization", "Bearer hunter2")],
+            "hunter2"
+        ));
+        assert!(!is_authorized(&[], "hunter2"));
+        assert!(!is_authorized(
+            &[header("Authorization", "Bearer hunter")],
+            "hunter2"
+        ));
+        assert!(!is_authorized(
+            &[header("Authorization", "Basic hunter2")],
+            "hunter2"
+        ));
+    }
+
+    #[test]
+    fn test_validate_opts_requires_tls_cert_and_key_together() {
+        let opts = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": Pubkey::new_unique().to_string(),
+            "solido_address": Pubkey::new_unique().to_string(),
+            "http_tls_cert_path": "/etc/solido/cert.pem",
+        }));
+        assert_eq!(
+            validate_opts(&opts),
+            Err("--http-tls-cert-path and --http-tls-key-path must be set together.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_opts_rejects_tls_on_unix_socket() {
+        let opts = new_run_maintainer_opts(serde_json::json!({
+            "solido_program_id": Pubkey::new_unique().to_string(),
+            "solido_address": Pubkey::new_unique().to_string(),
+            "listen": "unix:/run/solido/metrics.sock",
+            "http_tls_cert_path": "/etc/solido/cert.pem",
+            "http_tls_key_path": "/etc/solido/key.pem",
+        }));
+        assert_eq!(
+            validate_opts(&opts),
+            Err("--http-tls-cert-path cannot be used with a unix socket --listen.".to_string())
+        );
     }
 }
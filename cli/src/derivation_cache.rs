@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Memoized derivation of program-derived addresses.
+//!
+//! `Pubkey::find_program_address` tries bump seeds until it finds an address
+//! that is not on the curve, and every attempt costs a hash and a curve point
+//! decompression. The maintainer derives the same stake accounts and
+//! authorities on every iteration, for every validator, and with a large
+//! validator set this shows up in profiles.
+//!
+//! The derivation is a pure function of the seeds and the program id, so we
+//! can remember the result for the lifetime of the process. The cache is per
+//! thread, so it needs no locking; the maintenance loop runs on one thread.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use solana_program::pubkey::Pubkey;
+
+use lido::processor::StakeType;
+use lido::{VALIDATOR_STAKE_ACCOUNT, VALIDATOR_UNSTAKE_ACCOUNT};
+
+/// Start over when the cache holds this many addresses.
+///
+/// Stake account seeds and withdraw ticket ids only go up, so without a bound,
+/// a long-running daemon would slowly accumulate addresses it no longer needs.
+const MAX_ENTRIES: usize = 100_000;
+
+type Key = (Vec<Vec<u8>>, Pubkey);
+
+thread_local! {
+    static CACHE: RefCell<HashMap<Key, (Pubkey, u8)>> = RefCell::new(HashMap::new());
+}
+
+/// Like `Pubkey::find_program_address`, but remember the result.
+pub fn find_program_address(seeds: &[&[u8]], program_id: &Pubkey) -> (Pubkey, u8) {
+    let key = (
+        seeds.iter().map(|seed| seed.to_vec()).collect(),
+        *program_id,
+    );
+    CACHE.with(|cache| {
+        if let Some(result) = cache.borrow().get(&key) {
+            return *result;
+        }
+        let result = Pubkey::find_program_address(seeds, program_id);
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= MAX_ENTRIES {
+            cache.clear();
+        }
+        cache.insert(key, result);
+        result
+    })
+}
+
+/// Like `lido::find_authority_program_address`, but memoized.
+pub fn find_authority_program_address(
+    program_id: &Pubkey,
+    lido_address: &Pubkey,
+    authority: &[u8],
+) -> (Pubkey, u8) {
+    find_program_address(&[&lido_address.to_bytes(), authority], program_id)
+}
+
+/// Like `PubkeyAndEntry::<Validator>::find_stake_account_address`, but memoized.
+pub fn find_stake_account_address(
+    validator_vote_account: &Pubkey,
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    seed: u64,
+    stake_type: StakeType,
+) -> (Pubkey, u8) {
+    let authority = match stake_type {
+        StakeType::Stake => VALIDATOR_STAKE_ACCOUNT,
+        StakeType::Unstake => VALIDATOR_UNSTAKE_ACCOUNT,
+    };
+    find_program_address(
+        &[
+            &solido_address.to_bytes(),
+            &validator_vote_account.to_bytes(),
+            authority,
+            &seed.to_le_bytes()[..],
+        ],
+        program_id,
+    )
+}
+
+/// Return the number of addresses in the cache of the current thread.
+pub fn len() -> usize {
+    CACHE.with(|cache| cache.borrow().len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lido::state::{PubkeyAndEntry, Validator};
+    use lido::STAKE_AUTHORITY;
+
+    #[test]
+    fn test_cached_addresses_match_lido() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let validator = PubkeyAndEntry {
+            pubkey: Pubkey::new_unique(),
+            entry: Validator::new(Pubkey::new_unique()),
+        };
+
+        for &stake_type in &[StakeType::Stake, StakeType::Unstake] {
+            for seed in 0..3 {
+                let expected = validator.find_stake_account_address(
+                    &program_id,
+                    &solido_address,
+                    seed,
+                    stake_type,
+                );
+                // Once to fill the cache, once to read from it.
+                for _ in 0..2 {
+                    assert_eq!(
+                        find_stake_account_address(
+                            &validator.pubkey,
+                            &program_id,
+                            &solido_address,
+                            seed,
+                            stake_type,
+                        ),
+                        expected,
+                    );
+                }
+            }
+        }
+
+        assert_eq!(
+            find_authority_program_address(&program_id, &solido_address, STAKE_AUTHORITY),
+            lido::find_authority_program_address(&program_id, &solido_address, STAKE_AUTHORITY),
+        );
+    }
+
+    #[test]
+    fn test_cache_does_not_derive_twice() {
+        let program_id = Pubkey::new_unique();
+        let before = len();
+        find_program_address(&[&b"seed"[..]], &program_id);
+        find_program_address(&[&b"seed"[..]], &program_id);
+        assert_eq!(len(), before + 1);
+
+        // Seeds are keyed individually, not concatenated.
+        find_program_address(&[&b"se"[..], &b"ed"[..]], &program_id);
+        assert_eq!(len(), before + 2);
+    }
+}
@@ -3,19 +3,27 @@
 
 //! Error type for use throughout the CLI program and daemon.
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use num_traits::cast::FromPrimitive;
+use serde::{Serialize, Serializer};
 use solana_client::client_error::{ClientError, ClientErrorKind};
 use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
 use solana_program::instruction::InstructionError;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::PubkeyError;
+use solana_program::stake::instruction::StakeError;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signer::presigner::PresignerError;
 use solana_sdk::signer::SignerError;
 use solana_sdk::transaction::TransactionError;
+use spl_token::error::TokenError;
 
 use lido::error::LidoError;
 
+use crate::config::OutputMode;
+use crate::keeper::serialize_b58_option;
+
 /// Return whether the transaction may have executed despite the client error.
 ///
 /// We observed one case on testnet where the RPC returned:
@@ -59,10 +67,128 @@ fn print_red(message: &'static str) {
     print!("\x1b[31m{}\x1b[0m", message);
 }
 
+/// What went wrong, in a form that scripts can act on.
+///
+/// Every kind maps to its own process exit code, and in json output mode, the
+/// kind and its fields are part of the error that we print.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// An error that none of the other kinds describes.
+    Other,
+
+    /// Invalid arguments or config, or a problem with a local resource such
+    /// as a keypair file or hardware wallet.
+    Cli,
+
+    /// We could not reach the RPC node, or it did not give a usable response.
+    RpcTransport,
+
+    /// We expected to read from an account, but it does not exist.
+    AccountMissing {
+        #[serde(serialize_with = "lido::util::serialize_b58")]
+        address: Pubkey,
+    },
+
+    /// An account or response does not hold the data that we expected.
+    Deserialization {
+        #[serde(serialize_with = "serialize_b58_option")]
+        address: Option<Pubkey>,
+    },
+
+    /// A transaction was executed, but a program in it returned an error.
+    Program {
+        /// The custom error code, if the program returned one.
+        error_code: Option<u32>,
+        /// The custom error code decoded as Solido error, if it is one.
+        #[serde(serialize_with = "serialize_lido_error")]
+        lido_error: Option<LidoError>,
+    },
+
+    /// Simulating a transaction showed that it would fail, so we did not send it.
+    Simulation {
+        error_code: Option<u32>,
+        #[serde(serialize_with = "serialize_lido_error")]
+        lido_error: Option<LidoError>,
+        logs: Vec<String>,
+    },
+
+    /// We failed to sign a transaction.
+    Signing,
+
+    /// Something went wrong while performing maintenance.
+    Maintenance,
+}
+
+impl ErrorKind {
+    /// The exit code of the process when it aborts with an error of this kind.
+    ///
+    /// These are part of the interface of the CLI, do not renumber them.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Cli => 2,
+            ErrorKind::RpcTransport => 3,
+            ErrorKind::AccountMissing { .. } => 4,
+            ErrorKind::Deserialization { .. } => 5,
+            ErrorKind::Program { .. } => 6,
+            ErrorKind::Simulation { .. } => 7,
+            ErrorKind::Signing => 8,
+            ErrorKind::Maintenance => 9,
+        }
+    }
+
+    /// Build a `Program` error from the custom error code, if there is one.
+    fn program(error_code: Option<u32>) -> ErrorKind {
+        ErrorKind::Program {
+            error_code,
+            lido_error: error_code.and_then(LidoError::from_u32),
+        }
+    }
+
+    /// Build a `Simulation` error for a transaction that failed with `error`.
+    fn simulation(error: Option<&TransactionError>, logs: Vec<String>) -> ErrorKind {
+        let error_code = error.and_then(get_custom_error_code);
+        ErrorKind::Simulation {
+            error_code,
+            lido_error: error_code.and_then(LidoError::from_u32),
+            logs,
+        }
+    }
+}
+
+fn serialize_lido_error<S: Serializer>(
+    error: &Option<LidoError>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match error {
+        Some(error) => serializer.serialize_some(&format!("{:?}", error)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Return the custom error code of the failed instruction, if it has one.
+fn get_custom_error_code(error: &TransactionError) -> Option<u32> {
+    match error {
+        TransactionError::InstructionError(_instr, InstructionError::Custom(error_code)) => {
+            Some(*error_code)
+        }
+        _ => None,
+    }
+}
+
 /// Trait for errors that can be printed to an ANSI terminal for human consumption.
 pub trait AsPrettyError {
     /// Pretty-print the error.
     fn print_pretty(&self);
+
+    /// Describe the error on a single line, for structured logs.
+    fn summary(&self) -> String;
+
+    /// Classify the error, for the exit code and json output.
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
 }
 
 pub type Error = Box<dyn AsPrettyError + 'static>;
@@ -84,6 +210,14 @@ impl AsPrettyError for MaintenanceError {
         print_red("Maintenance error:\n\n");
         println!("{}", self.message);
     }
+
+    fn summary(&self) -> String {
+        self.message.clone()
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Maintenance
+    }
 }
 
 /// Something went wrong either while reading CLI arguments, or while using them.
@@ -123,6 +257,17 @@ impl AsPrettyError for CliError {
             println!("{}", cause);
         }
     }
+
+    fn summary(&self) -> String {
+        match &self.cause {
+            Some(cause) => format!("{} Cause: {}", self.message, cause),
+            None => self.message.to_string(),
+        }
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Cli
+    }
 }
 
 /// We expected to read from the following account, but it doesn't exist on the network.
@@ -138,6 +283,16 @@ impl AsPrettyError for MissingAccountError {
             self.missing_account
         );
     }
+
+    fn summary(&self) -> String {
+        format!("Account {} does not exist.", self.missing_account)
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::AccountMissing {
+            address: self.missing_account,
+        }
+    }
 }
 
 pub struct SerializationError {
@@ -156,6 +311,55 @@ impl AsPrettyError for SerializationError {
         print_key("Cause:");
         self.cause.print_pretty();
     }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} Address: {}. Cause: {}",
+            self.context,
+            self.address,
+            self.cause.summary()
+        )
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Deserialization {
+            address: Some(self.address),
+        }
+    }
+}
+
+/// Simulating a transaction before sending it showed that it would fail.
+///
+/// We did not send the transaction, so it did not cost any fees.
+pub struct SimulationError {
+    pub error: TransactionError,
+    pub logs: Vec<String>,
+}
+
+impl AsPrettyError for SimulationError {
+    fn print_pretty(&self) {
+        print_red("Transaction simulation failed, not sending the transaction:\n\n");
+        print_key("Error:");
+        println!();
+        print_pretty_transaction_error(&self.error);
+        print_key("Logs:");
+        println!();
+        for line in &self.logs {
+            println!("    {}", line);
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "Transaction simulation failed, not sending the transaction. Error: {} Logs: {}",
+            describe_transaction_error(&self.error),
+            self.logs.join(" | ")
+        )
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::simulation(Some(&self.error), self.logs.clone())
+    }
 }
 
 fn print_pretty_transaction_error(err: &TransactionError) {
@@ -262,6 +466,49 @@ impl AsPrettyError for ClientError {
             }
         }
     }
+
+    fn summary(&self) -> String {
+        let mut summary = format!("Solana RPC client returned an error: {}", self);
+        match self.kind() {
+            ClientErrorKind::TransactionError(err) => {
+                summary.push_str(&format!(" Error: {}", describe_transaction_error(err)));
+            }
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+                ..
+            }) => {
+                if let Some(ref err) = result.err {
+                    summary.push_str(&format!(" Error: {}", describe_transaction_error(err)));
+                }
+                if let Some(ref logs) = result.logs {
+                    summary.push_str(&format!(" Logs: {}", logs.join(" | ")));
+                }
+            }
+            _ => {}
+        }
+        summary
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        match self.kind() {
+            ClientErrorKind::Io(..) | ClientErrorKind::Reqwest(..) => ErrorKind::RpcTransport,
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+                ..
+            }) => {
+                ErrorKind::simulation(result.err.as_ref(), result.logs.clone().unwrap_or_default())
+            }
+            // Also covers the "unable to confirm transaction" error, see
+            // `might_have_executed`, the transaction may still land.
+            ClientErrorKind::RpcError(..) => ErrorKind::RpcTransport,
+            ClientErrorKind::SerdeJson(..) => ErrorKind::Deserialization { address: None },
+            ClientErrorKind::SigningError(..) => ErrorKind::Signing,
+            ClientErrorKind::TransactionError(err) => {
+                ErrorKind::program(get_custom_error_code(err))
+            }
+            ClientErrorKind::FaucetError(..) | ClientErrorKind::Custom(..) => ErrorKind::Other,
+        }
+    }
 }
 
 /// Parse an error code back to a multisig error.
@@ -308,8 +555,16 @@ pub fn multisig_error_from_u32(error_code: u32) -> Option<serum_multisig::ErrorC
 
 #[cfg(test)]
 mod test {
-    use crate::error::multisig_error_from_u32;
+    use crate::error::{
+        decode_error_code, describe_error_code, multisig_error_from_u32, AsPrettyError, ErrorKind,
+        SimulationError,
+    };
+    use lido::error::LidoError;
+    use solana_client::client_error::{ClientError, ClientErrorKind};
+    use solana_client::rpc_request::RpcError;
+    use solana_program::instruction::InstructionError;
     use solana_program::program_error::ProgramError;
+    use solana_sdk::transaction::TransactionError;
 
     #[test]
     fn test_multisig_error_from_u32() {
@@ -324,6 +579,107 @@ mod test {
         );
         assert!(matches!(multisig_error_from_u32(u32::MAX), None));
     }
+
+    #[test]
+    fn test_decode_error_code() {
+        let names = decode_error_code(LidoError::DuplicatedEntry as u32);
+        assert!(names.contains(&("Solido", "DuplicatedEntry".to_string())));
+
+        // Code 1 is an error in every program, and we can't tell which one
+        // returned it, so we list all of them.
+        let names = decode_error_code(1);
+        assert!(names.contains(&("SPL token", "InsufficientFunds".to_string())));
+        assert!(names.contains(&("Stake", "LockupInForce".to_string())));
+
+        assert_eq!(decode_error_code(u32::MAX), Vec::new());
+        assert_eq!(describe_error_code(u32::MAX), "custom error 4294967295");
+    }
+
+    #[test]
+    fn test_error_kind_distinguishes_program_errors_from_rpc_errors() {
+        let duplicate = SimulationError {
+            error: TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(LidoError::DuplicatedEntry as u32),
+            ),
+            logs: vec!["Program log: Error: DuplicatedEntry".to_string()],
+        };
+        let rpc_down: ClientError =
+            ClientErrorKind::RpcError(RpcError::RpcRequestError("timeout".to_string())).into();
+
+        assert_eq!(
+            duplicate.error_kind(),
+            ErrorKind::Simulation {
+                error_code: Some(LidoError::DuplicatedEntry as u32),
+                lido_error: Some(LidoError::DuplicatedEntry),
+                logs: vec!["Program log: Error: DuplicatedEntry".to_string()],
+            }
+        );
+        assert_eq!(rpc_down.error_kind(), ErrorKind::RpcTransport);
+        assert_ne!(
+            duplicate.error_kind().exit_code(),
+            rpc_down.error_kind().exit_code()
+        );
+    }
+
+    #[test]
+    fn test_error_kind_serializes_decoded_lido_error() {
+        let kind = ProgramError::Custom(LidoError::Paused as u32).error_kind();
+        assert_eq!(
+            serde_json::to_value(&kind).unwrap(),
+            serde_json::json!({
+                "kind": "program",
+                "error_code": LidoError::Paused as u32,
+                "lido_error": "Paused",
+            })
+        );
+    }
+}
+
+/// Return what `error_code` means as custom error of every program that knows it.
+///
+/// Custom error codes are not namespaced, the same number means something
+/// different in every program. Besides Solido itself, a transaction can fail
+/// in the multisig, or in the SPL token or stake program that Solido calls
+/// into, and then the code surfaces as the error of the Solido instruction.
+/// Only the logs tell which program it came from, so we return every
+/// interpretation, as (program, error) pairs.
+pub fn decode_error_code(error_code: u32) -> Vec<(&'static str, String)> {
+    let mut names = Vec::new();
+    if let Some(err) = LidoError::from_u32(error_code) {
+        names.push(("Solido", format!("{:?}", err)));
+    }
+    if let Some(err) = multisig_error_from_u32(error_code) {
+        names.push(("Multisig", format!("{:?}", err)));
+    }
+    if let Some(err) = TokenError::from_u32(error_code) {
+        names.push(("SPL token", format!("{:?}", err)));
+    }
+    if let Some(err) = StakeError::from_u32(error_code) {
+        names.push(("Stake", format!("{:?}", err)));
+    }
+    names
+}
+
+/// Describe a custom error code on a single line, including its decoded names.
+pub fn describe_error_code(error_code: u32) -> String {
+    let names: Vec<String> = decode_error_code(error_code)
+        .into_iter()
+        .map(|(program, name)| format!("{} {}", program, name))
+        .collect();
+    if names.is_empty() {
+        format!("custom error {}", error_code)
+    } else {
+        format!("custom error {} ({})", error_code, names.join(" or "))
+    }
+}
+
+/// Describe a transaction error on a single line, decoding its custom error code.
+fn describe_transaction_error(err: &TransactionError) -> String {
+    match get_custom_error_code(err) {
+        Some(error_code) => format!("{}: {}.", err, describe_error_code(error_code)),
+        None => err.to_string(),
+    }
 }
 
 pub fn print_pretty_error_code(error_code: u32) {
@@ -344,6 +700,17 @@ pub fn print_pretty_error_code(error_code: u32) {
             println!("    Error {} is not a known Multisig error.", error_code);
         }
     }
+    match TokenError::from_u32(error_code) {
+        Some(err) => println!("    SPL token error {} is {:?}: {}", error_code, err, err),
+        None => println!("    Error {} is not a known SPL token error.", error_code),
+    }
+    match StakeError::from_u32(error_code) {
+        Some(err) => println!("    Stake error {} is {:?}: {}", error_code, err, err),
+        None => println!(
+            "    Error {} is not a known stake program error.",
+            error_code
+        ),
+    }
 }
 
 impl AsPrettyError for ProgramError {
@@ -357,12 +724,36 @@ impl AsPrettyError for ProgramError {
             predefined_error => println!(" {:?}", predefined_error),
         }
     }
+
+    fn summary(&self) -> String {
+        match self {
+            ProgramError::Custom(error_code) => {
+                format!("Program error: {}", describe_error_code(*error_code))
+            }
+            predefined_error => format!("Program error: {:?}", predefined_error),
+        }
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        match self {
+            ProgramError::Custom(error_code) => ErrorKind::program(Some(*error_code)),
+            _ => ErrorKind::program(None),
+        }
+    }
 }
 
 impl AsPrettyError for TransactionError {
     fn print_pretty(&self) {
-        println!("TODO: Add a nicer print_pretty impl for TransactionError.");
-        println!("Transaction error:\n{:?}", self);
+        print_red("Transaction error:\n\n");
+        print_pretty_transaction_error(self);
+    }
+
+    fn summary(&self) -> String {
+        format!("Transaction error: {}", describe_transaction_error(self))
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::program(get_custom_error_code(self))
     }
 }
 
@@ -371,6 +762,10 @@ impl AsPrettyError for std::io::Error {
         print_red("IO Error:");
         println!(" {:?}", self);
     }
+
+    fn summary(&self) -> String {
+        format!("IO error: {}", self)
+    }
 }
 
 impl AsPrettyError for bincode::ErrorKind {
@@ -378,6 +773,14 @@ impl AsPrettyError for bincode::ErrorKind {
         print_red("Bincode (de)serialization error:");
         println!(" {:?}", self);
     }
+
+    fn summary(&self) -> String {
+        format!("Bincode (de)serialization error: {}", self)
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Deserialization { address: None }
+    }
 }
 
 impl AsPrettyError for PubkeyError {
@@ -385,6 +788,10 @@ impl AsPrettyError for PubkeyError {
         print_red("Solana public key error:");
         println!(" {:?}", self);
     }
+
+    fn summary(&self) -> String {
+        format!("Solana public key error: {:?}", self)
+    }
 }
 
 impl AsPrettyError for SignerError {
@@ -452,12 +859,70 @@ impl AsPrettyError for SignerError {
             }
         }
     }
+
+    fn summary(&self) -> String {
+        format!("Failed to sign transaction: {}", self)
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        ErrorKind::Signing
+    }
 }
 
 impl AsPrettyError for Box<dyn AsPrettyError + 'static> {
     fn print_pretty(&self) {
         (**self).print_pretty()
     }
+
+    fn summary(&self) -> String {
+        (**self).summary()
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        (**self).error_kind()
+    }
+}
+
+/// Whether to print errors in json, we only have two modes, so a bool suffices.
+static PRINT_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Set the format in which `Abort` prints errors.
+pub fn set_output_mode(mode: OutputMode) {
+    PRINT_JSON.store(matches!(mode, OutputMode::Json), Ordering::SeqCst);
+}
+
+/// An error as we print it in json output mode.
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    /// What we were doing when the error occurred, if we know.
+    context: Option<&'a str>,
+    summary: String,
+    exit_code: i32,
+    #[serde(flatten)]
+    kind: ErrorKind,
+}
+
+/// Print the error in the configured output mode, then exit the process.
+fn abort<E: AsPrettyError>(context: Option<&'static str>, err: E) -> ! {
+    let kind = err.error_kind();
+    let exit_code = kind.exit_code();
+    if PRINT_JSON.load(Ordering::SeqCst) {
+        let output = ErrorOutput {
+            context,
+            summary: err.summary(),
+            exit_code,
+            kind,
+        };
+        let json = serde_json::to_string_pretty(&output)
+            .expect("Failed to serialize error output as json.");
+        println!("{}", json);
+    } else {
+        if let Some(message) = context {
+            println!("{}", message);
+        }
+        err.print_pretty();
+    }
+    std::process::exit(exit_code);
 }
 
 /// Trait for results that we can "unwrap" by pretty-printing and then aborting in case of error.
@@ -477,21 +942,14 @@ impl<T, E: AsPrettyError> Abort for std::result::Result<T, E> {
     fn ok_or_abort(self) -> T {
         match self {
             Ok(result) => result,
-            Err(err) => {
-                err.print_pretty();
-                std::process::exit(1);
-            }
+            Err(err) => abort(None, err),
         }
     }
 
     fn ok_or_abort_with(self, message: &'static str) -> T {
         match self {
             Ok(result) => result,
-            Err(err) => {
-                println!("{}", message);
-                err.print_pretty();
-                std::process::exit(1);
-            }
+            Err(err) => abort(Some(message), err),
         }
     }
 }
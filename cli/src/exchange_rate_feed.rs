@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Pushing the exchange rate to external consumers, whenever it is updated.
+//!
+//! The maintenance daemon reads the exchange rate anyway, so it can serve as a
+//! canonical rate feed for integrators that would rather receive the rate than
+//! poll the Solido account. We push the rate as a JSON object in a POST request,
+//! once after startup, and again whenever `UpdateExchangeRate` computed a new
+//! rate, regardless of whether this daemon, another maintainer, or a keeper
+//! performed the update. When a webhook secret is configured, the request
+//! carries the same `X-Solido-Signature` header as webhook requests.
+//!
+//! To publish the rate on-chain instead, see `try_publish_exchange_rate`.
+
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use solana_program::clock::{Epoch, Slot};
+use solana_program::pubkey::Pubkey;
+
+use lido::token::{Lamports, StLamports};
+use lido::util::serialize_b58;
+
+use crate::maintenance::SolidoState;
+use crate::webhook::sign;
+
+/// Version of the push format, incremented on changes that are not backwards compatible.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The body of a push request.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ExchangeRatePush {
+    pub schema_version: u32,
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido_address: Pubkey,
+
+    /// The epoch in which the rate was computed.
+    pub computed_in_epoch: Epoch,
+
+    /// The stSOL supply and SOL balance at the time; one stSOL is worth
+    /// `sol_balance / st_sol_supply` SOL.
+    #[serde(rename = "st_sol_supply_st_lamports")]
+    pub st_sol_supply: StLamports,
+    #[serde(rename = "sol_balance_lamports")]
+    pub sol_balance: Lamports,
+
+    /// Slot at which we observed the rate.
+    pub slot: Slot,
+    pub timestamp_unix_seconds: u64,
+}
+
+/// Pushes the exchange rate to a set of endpoints.
+pub struct ExchangeRateFeed {
+    endpoints: Vec<String>,
+
+    /// The webhook secret to sign requests with, if any.
+    secret: Option<Vec<u8>>,
+    client: reqwest::blocking::Client,
+
+    /// The epoch of the rate that we pushed last, `None` before the first push.
+    pushed_epoch: Option<Epoch>,
+}
+
+impl ExchangeRateFeed {
+    pub fn new(endpoints: Vec<String>, secret: Option<Vec<u8>>) -> ExchangeRateFeed {
+        let client = reqwest::blocking::Client::builder()
+            // Don't let a slow endpoint hold up maintenance for long.
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create http client.");
+        ExchangeRateFeed {
+            endpoints,
+            secret,
+            client,
+            pushed_epoch: None,
+        }
+    }
+
+    /// Return the push for this state, if its rate is one that we did not push yet.
+    fn get_push(
+        &self,
+        solido_address: &Pubkey,
+        now: SystemTime,
+        state: &SolidoState,
+    ) -> Option<ExchangeRatePush> {
+        let exchange_rate = &state.solido.exchange_rate;
+        if self.pushed_epoch == Some(exchange_rate.computed_in_epoch) {
+            return None;
+        }
+        Some(ExchangeRatePush {
+            schema_version: SCHEMA_VERSION,
+            solido_address: *solido_address,
+            computed_in_epoch: exchange_rate.computed_in_epoch,
+            st_sol_supply: exchange_rate.st_sol_supply,
+            sol_balance: exchange_rate.sol_balance,
+            slot: state.clock.slot,
+            timestamp_unix_seconds: now
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        })
+    }
+
+    /// Push the exchange rate, if it changed since the previous push.
+    ///
+    /// Delivery is best-effort per endpoint, errors are only printed. We only
+    /// push a rate again if no endpoint accepted it, so that an endpoint that
+    /// is down does not make us push to the others in every iteration.
+    pub fn observe(&mut self, solido_address: &Pubkey, now: SystemTime, state: &SolidoState) {
+        let push = match self.get_push(solido_address, now, state) {
+            Some(push) => push,
+            None => return,
+        };
+        let body = serde_json::to_vec(&push).expect("Pushes can always be serialized.");
+
+        let mut delivered = false;
+        for endpoint in &self.endpoints {
+            let mut request = self
+                .client
+                .post(endpoint)
+                .header("Content-Type", "application/json")
+                .header("X-Solido-Schema-Version", SCHEMA_VERSION.to_string());
+            if let Some(secret) = &self.secret {
+                request = request.header("X-Solido-Signature", sign(secret, &body));
+            }
+            let result = request
+                .body(body.clone())
+                .send()
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => delivered = true,
+                Err(err) => println!(
+                    "Error while pushing the exchange rate to {}: {}",
+                    endpoint, err
+                ),
+            }
+        }
+
+        if delivered {
+            self.pushed_epoch = Some(push.computed_in_epoch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn test_pushes_every_rate_once() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let solido_address = sim.fixture.solido_address;
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut feed = ExchangeRateFeed::new(Vec::new(), None);
+
+        // The first observation always pushes, so consumers get a rate on startup.
+        let mut state = sim.read_state();
+        state.solido.exchange_rate.computed_in_epoch = 7;
+        state.solido.exchange_rate.st_sol_supply = StLamports(1_000_000_000);
+        state.solido.exchange_rate.sol_balance = Lamports(1_100_000_000);
+        let push = feed.get_push(&solido_address, now, &state).unwrap();
+        assert_eq!(push.computed_in_epoch, 7);
+        assert_eq!(push.sol_balance, Lamports(1_100_000_000));
+        assert_eq!(push.timestamp_unix_seconds, 1_600_000_000);
+
+        // After a push, we wait for the next update.
+        feed.pushed_epoch = Some(7);
+        assert_eq!(feed.get_push(&solido_address, now, &state), None);
+        state.solido.exchange_rate.computed_in_epoch = 8;
+        assert_eq!(
+            feed.get_push(&solido_address, now, &state)
+                .map(|push| push.computed_in_epoch),
+            Some(8)
+        );
+    }
+}
@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Export of per-epoch analytics data as newline-delimited JSON.
+//!
+//! Once per epoch, the maintenance daemon writes a file `epoch-<epoch>.ndjson`
+//! that contains one record with the exchange rate, and one record per
+//! validator with its balances, rewards, and fees. The files are written
+//! atomically, so a loader that watches the directory (or an object store that
+//! is mounted there) never sees a partial file.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use solana_program::clock::{Epoch, Slot};
+use solana_program::pubkey::Pubkey;
+
+use lido::stake_account::StakeBalance;
+use lido::token::{Lamports, StLamports};
+use lido::util::serialize_b58;
+
+use crate::maintenance::SolidoState;
+
+/// The exchange rate and supply at the time of the export.
+#[derive(Serialize)]
+struct ExchangeRateRecord {
+    epoch: Epoch,
+    slot: Slot,
+    produced_at_unix_seconds: u64,
+    #[serde(serialize_with = "serialize_b58")]
+    solido_address: Pubkey,
+    exchange_rate_computed_in_epoch: Epoch,
+    #[serde(rename = "exchange_rate_st_sol_supply_st_lamports")]
+    exchange_rate_st_sol_supply: StLamports,
+    #[serde(rename = "exchange_rate_sol_balance_lamports")]
+    exchange_rate_sol_balance: Lamports,
+    #[serde(rename = "st_sol_supply_st_lamports")]
+    st_sol_supply: StLamports,
+    #[serde(rename = "reserve_lamports")]
+    reserve: Lamports,
+}
+
+/// Balances, rewards, and fees of a single validator.
+#[derive(Serialize)]
+struct ValidatorRecord {
+    epoch: Epoch,
+    #[serde(serialize_with = "serialize_b58")]
+    solido_address: Pubkey,
+    #[serde(serialize_with = "serialize_b58")]
+    validator_vote_account: Pubkey,
+    active: bool,
+
+    /// Balance of the stake accounts according to Solido's bookkeeping.
+    #[serde(rename = "stake_accounts_balance_lamports")]
+    stake_accounts_balance: Lamports,
+
+    /// Actual balances of the stake accounts.
+    #[serde(rename = "stake_inactive_lamports")]
+    stake_inactive: Lamports,
+    #[serde(rename = "stake_activating_lamports")]
+    stake_activating: Lamports,
+    #[serde(rename = "stake_active_lamports")]
+    stake_active: Lamports,
+    #[serde(rename = "stake_deactivating_lamports")]
+    stake_deactivating: Lamports,
+
+    /// Staking rewards that have not yet been withdrawn, the difference between
+    /// the actual and the recorded stake account balance.
+    #[serde(rename = "stake_rewards_lamports")]
+    stake_rewards: Lamports,
+
+    /// Vote account balance minus rent, the validation fee that can be collected.
+    #[serde(rename = "vote_account_rewards_lamports")]
+    vote_account_rewards: Lamports,
+
+    /// Fees that the validator can claim as stSOL.
+    #[serde(rename = "fee_credit_st_lamports")]
+    fee_credit: StLamports,
+}
+
+/// Writes one file per epoch to a directory.
+pub struct EpochExporter {
+    directory: PathBuf,
+}
+
+impl EpochExporter {
+    pub fn new(directory: PathBuf) -> EpochExporter {
+        EpochExporter { directory }
+    }
+
+    fn epoch_path(&self, epoch: Epoch) -> PathBuf {
+        self.directory.join(format!("epoch-{}.ndjson", epoch))
+    }
+
+    /// Export the state, if no export exists yet for its epoch.
+    ///
+    /// The first state that the daemon observes in a new epoch gets exported.
+    /// Because this checks for the file rather than remembering the last epoch,
+    /// a restarted daemon does not export an epoch twice.
+    ///
+    /// Returns the path of the new file, if one was written.
+    pub fn observe(&self, state: &SolidoState) -> io::Result<Option<PathBuf>> {
+        let path = self.epoch_path(state.clock.epoch);
+        if path.exists() {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.directory)?;
+        let tmp_path = path.with_extension("ndjson.tmp");
+        write_records(&tmp_path, state)?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(Some(path))
+    }
+}
+
+fn write_records(path: &Path, state: &SolidoState) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let epoch = state.clock.epoch;
+
+    let exchange_rate = &state.solido.exchange_rate;
+    let exchange_rate_record = ExchangeRateRecord {
+        epoch,
+        slot: state.clock.slot,
+        produced_at_unix_seconds: state
+            .produced_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        solido_address: state.solido_address,
+        exchange_rate_computed_in_epoch: exchange_rate.computed_in_epoch,
+        exchange_rate_st_sol_supply: exchange_rate.st_sol_supply,
+        exchange_rate_sol_balance: exchange_rate.sol_balance,
+        st_sol_supply: StLamports(state.st_sol_mint.supply),
+        reserve: state.get_effective_reserve(),
+    };
+    serde_json::to_writer(&mut out, &exchange_rate_record)?;
+    writeln!(out)?;
+
+    for ((validator, stake_accounts), vote_account_rewards) in state
+        .solido
+        .validators
+        .entries
+        .iter()
+        .zip(state.validator_stake_accounts.iter())
+        .zip(state.validator_vote_account_balances.iter())
+    {
+        let stake_balance: StakeBalance = stake_accounts
+            .iter()
+            .map(|(_addr, stake_account)| stake_account.balance)
+            .sum();
+        let stake_rewards = Lamports(
+            stake_balance
+                .total()
+                .0
+                .saturating_sub(validator.entry.stake_accounts_balance.0),
+        );
+        let record = ValidatorRecord {
+            epoch,
+            solido_address: state.solido_address,
+            validator_vote_account: validator.pubkey,
+            active: validator.entry.active,
+            stake_accounts_balance: validator.entry.stake_accounts_balance,
+            stake_inactive: stake_balance.inactive,
+            stake_activating: stake_balance.activating,
+            stake_active: stake_balance.active,
+            stake_deactivating: stake_balance.deactivating,
+            stake_rewards,
+            vote_account_rewards: *vote_account_rewards,
+            fee_credit: validator.entry.fee_credit,
+        };
+        serde_json::to_writer(&mut out, &record)?;
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn test_exports_once_per_epoch() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let vote_account = sim.add_validator();
+        sim.add_stake_account(&vote_account, 2_000_000_000);
+
+        let directory = tempfile::tempdir().unwrap();
+        let exporter = EpochExporter::new(directory.path().to_path_buf());
+
+        let state = sim.read_state();
+        let path = exporter
+            .observe(&state)
+            .unwrap()
+            .expect("Should export epoch 1.");
+        assert_eq!(path, directory.path().join("epoch-1.ndjson"));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["epoch"], 1);
+        assert_eq!(
+            records[1]["validator_vote_account"],
+            vote_account.to_string()
+        );
+
+        // Observing the same epoch again does not export anything.
+        assert_eq!(exporter.observe(&sim.read_state()).unwrap(), None);
+
+        sim.advance_epoch();
+        let path = exporter.observe(&sim.read_state()).unwrap();
+        assert_eq!(path, Some(directory.path().join("epoch-2.ndjson")));
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Golden-file testing for output formats that other tools consume.
+//!
+//! Dashboards and alerts depend on the exact Prometheus metric names and labels,
+//! and scripts depend on the json output. Golden-file tests render this output
+//! from fixed inputs, and compare it against the expectation committed in
+//! `testdata/golden`, so accidental format changes show up as a test failure.
+//!
+//! To accept an intentional change, run the tests with `UPDATE_GOLDEN=1`, and
+//! review the diff of the golden files before committing.
+
+use std::fs;
+use std::path::PathBuf;
+
+fn golden_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("testdata");
+    path.push("golden");
+    path.push(name);
+    path
+}
+
+/// Assert that `actual` equals the contents of the golden file `name`.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = golden_path(name);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).expect("Failed to write golden file.");
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "Failed to read golden file {}: {}. Run with UPDATE_GOLDEN=1 to create it.",
+            path.display(),
+            err
+        )
+    });
+
+    // Compare line by line first, so a failure points at the first difference,
+    // rather than printing two walls of text.
+    for (i, (expected_line, actual_line)) in expected.lines().zip(actual.lines()).enumerate() {
+        assert_eq!(
+            expected_line,
+            actual_line,
+            "Output differs from {} at line {}. Run with UPDATE_GOLDEN=1 to accept the change.",
+            path.display(),
+            i + 1,
+        );
+    }
+    assert_eq!(
+        expected,
+        actual,
+        "Output differs from {}. Run with UPDATE_GOLDEN=1 to accept the change.",
+        path.display(),
+    );
+}
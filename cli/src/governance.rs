@@ -0,0 +1,745 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Administering Solido through SPL Governance, as an alternative to the multisig.
+//!
+//! The manager of an instance can be an account governance of the SPL
+//! Governance program. The governance program signs the instructions of
+//! proposals that passed with the address of the governance, so that address
+//! is the manager. To change the instance, we create a proposal with the
+//! manager instruction, token holders vote on it, and once it passed, anybody
+//! can execute it.
+//!
+//! We only need a handful of the governance instructions and accounts, so we
+//! encode those ourselves, following version 1 of the program, rather than
+//! depending on the `spl-governance` crate.
+
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use clap::Clap;
+use serde::Serialize;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::Transaction;
+use solana_sdk::{system_program, sysvar};
+
+use lido::{
+    state::{FeeRecipients, RewardDistribution},
+    util::serialize_b58,
+};
+
+use crate::config::{
+    ConfigFile, GovernanceAddValidatorOpts, GovernanceChangeFeeRecipientsOpts,
+    GovernanceDeactivateValidatorOpts, GovernanceScheduleRewardDistributionOpts,
+};
+use crate::error::{Abort, CliError};
+use crate::manifest::ConfigOption;
+use crate::print_output;
+use crate::rpc::AccountFilter;
+use crate::snapshot::Result;
+use crate::{SnapshotClientConfig, SnapshotConfig};
+
+#[derive(Clap, Debug)]
+pub struct GovernanceOpts {
+    #[clap(subcommand)]
+    subcommand: SubCommand,
+}
+
+impl GovernanceOpts {
+    pub fn merge_with_config_and_environment(&mut self, config_file: Option<&ConfigFile>) {
+        match &mut self.subcommand {
+            SubCommand::ProposeAddValidator(opts) => {
+                opts.merge_with_config_and_environment(config_file)
+            }
+            SubCommand::ProposeDeactivateValidator(opts) => {
+                opts.merge_with_config_and_environment(config_file)
+            }
+            SubCommand::ProposeScheduleRewardDistribution(opts) => {
+                opts.merge_with_config_and_environment(config_file)
+            }
+            SubCommand::ProposeChangeFeeRecipients(opts) => {
+                opts.merge_with_config_and_environment(config_file)
+            }
+        }
+    }
+
+    /// Return the config options of the subcommand at `path`, see `crate::manifest`.
+    pub fn config_options(path: &[&str]) -> Vec<ConfigOption> {
+        match path {
+            ["propose-add-validator"] => GovernanceAddValidatorOpts::config_options(),
+            ["propose-deactivate-validator"] => GovernanceDeactivateValidatorOpts::config_options(),
+            ["propose-schedule-reward-distribution"] => {
+                GovernanceScheduleRewardDistributionOpts::config_options()
+            }
+            ["propose-change-fee-recipients"] => {
+                GovernanceChangeFeeRecipientsOpts::config_options()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clap, Debug)]
+enum SubCommand {
+    /// Propose to add a validator to the instance.
+    ProposeAddValidator(GovernanceAddValidatorOpts),
+
+    /// Propose to deactivate a validator, after which the maintainers unstake
+    /// from it and remove it.
+    ProposeDeactivateValidator(GovernanceDeactivateValidatorOpts),
+
+    /// Propose to change the fees, in a later epoch.
+    ///
+    /// The new distribution applies from the epoch that is `--delay-epochs`
+    /// after the epoch in which the proposal executes, so stSOL holders get
+    /// notice of the change.
+    ProposeScheduleRewardDistribution(GovernanceScheduleRewardDistributionOpts),
+
+    /// Propose to change the accounts that receive the treasury and developer fees.
+    ProposeChangeFeeRecipients(GovernanceChangeFeeRecipientsOpts),
+}
+
+pub fn main(config: &mut SnapshotClientConfig, governance_opts: GovernanceOpts) {
+    let output_mode = config.output_mode;
+    match governance_opts.subcommand {
+        SubCommand::ProposeAddValidator(cmd_opts) => {
+            let result = config.with_snapshot(|config| propose_add_validator(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to propose adding the validator.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ProposeDeactivateValidator(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| propose_deactivate_validator(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to propose deactivating the validator.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ProposeScheduleRewardDistribution(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| propose_schedule_reward_distribution(config, &cmd_opts));
+            let output =
+                result.ok_or_abort_with("Failed to propose scheduling the reward distribution.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ProposeChangeFeeRecipients(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| propose_change_fee_recipients(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to propose changing the fee recipients.");
+            print_output(output_mode, &output);
+        }
+    }
+}
+
+/// Seed of the addresses of governance program accounts, besides governances.
+const GOVERNANCE_SEED: &[u8] = b"governance";
+
+// The first byte of every governance program account is its account type.
+const ACCOUNT_TYPE_ACCOUNT_GOVERNANCE: u8 = 3;
+const ACCOUNT_TYPE_PROGRAM_GOVERNANCE: u8 = 4;
+const ACCOUNT_TYPE_PROPOSAL: u8 = 5;
+const ACCOUNT_TYPE_PROPOSAL_INSTRUCTION: u8 = 8;
+const ACCOUNT_TYPE_MINT_GOVERNANCE: u8 = 9;
+const ACCOUNT_TYPE_TOKEN_GOVERNANCE: u8 = 10;
+
+// Tags of the governance instructions that we use.
+const INSTRUCTION_CREATE_PROPOSAL: u8 = 6;
+const INSTRUCTION_ADD_SIGNATORY: u8 = 7;
+const INSTRUCTION_INSERT_INSTRUCTION: u8 = 9;
+const INSTRUCTION_SIGN_OFF_PROPOSAL: u8 = 12;
+
+// Proposal states that can still lead to executing its instructions:
+// draft, signing off, voting, succeeded, and executing.
+const PENDING_PROPOSAL_STATES: [u8; 5] = [0, 1, 2, 3, 4];
+
+/// A governance that is the manager of a Solido instance.
+#[derive(Clone, Copy, Debug)]
+pub struct ManagerGovernance {
+    pub program_id: Pubkey,
+
+    /// The governance account, which is also the manager.
+    pub address: Pubkey,
+
+    /// Mint of the tokens that vote on our proposals, the community or council
+    /// mint of the realm. The proposer must have deposited these tokens.
+    pub governing_token_mint: Pubkey,
+}
+
+/// The start of a governance account, up to the fields that we need.
+#[derive(BorshDeserialize, Debug)]
+pub struct Governance {
+    pub account_type: u8,
+    pub realm: Pubkey,
+    pub governed_account: Pubkey,
+    pub proposals_count: u32,
+
+    // The fields below are the start of the `GovernanceConfig`.
+    pub vote_threshold_percentage_type: u8,
+    pub vote_threshold_percentage: u8,
+    pub min_community_tokens_to_create_proposal: u64,
+    pub min_instruction_hold_up_time: u32,
+}
+
+/// A proposal starts with its account type, governance, and governing token
+/// mint, followed by its state.
+const PROPOSAL_STATE_OFFSET: usize = 1 + 32 + 32;
+
+/// The start of a proposal instruction account, up to the fields that we need.
+// We only read the instruction, the fields before it are there for its offset.
+#[allow(dead_code)]
+#[derive(BorshDeserialize)]
+struct ProposalInstructionHeader {
+    account_type: u8,
+    proposal: Pubkey,
+    instruction_index: u16,
+    hold_up_time: u32,
+    instruction: InstructionData,
+}
+
+/// An `Instruction`, in the form that the governance program stores it.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Eq, PartialEq)]
+struct InstructionData {
+    program_id: Pubkey,
+    accounts: Vec<AccountMetaData>,
+    data: Vec<u8>,
+}
+
+/// An `AccountMeta`, in the form that the governance program stores it.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Eq, PartialEq)]
+struct AccountMetaData {
+    pubkey: Pubkey,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+impl From<&Instruction> for InstructionData {
+    fn from(instruction: &Instruction) -> InstructionData {
+        InstructionData {
+            program_id: instruction.program_id,
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| AccountMetaData {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        }
+    }
+}
+
+#[derive(BorshSerialize)]
+struct CreateProposalArgs {
+    name: String,
+    description_link: String,
+    governing_token_mint: Pubkey,
+}
+
+#[derive(BorshSerialize)]
+struct AddSignatoryArgs {
+    signatory: Pubkey,
+}
+
+#[derive(BorshSerialize)]
+struct InsertInstructionArgs {
+    index: u16,
+    hold_up_time: u32,
+    instruction: InstructionData,
+}
+
+/// Return the data of a governance instruction: its tag, and then its arguments.
+fn instruction_data<T: BorshSerialize>(tag: u8, args: &T) -> Vec<u8> {
+    let mut data = vec![tag];
+    args.serialize(&mut data)
+        .expect("Serializing to a vec does not fail.");
+    data
+}
+
+pub fn get_token_owner_record_address(
+    program_id: &Pubkey,
+    realm: &Pubkey,
+    governing_token_mint: &Pubkey,
+    governing_token_owner: &Pubkey,
+) -> Pubkey {
+    let seeds = [
+        GOVERNANCE_SEED,
+        realm.as_ref(),
+        governing_token_mint.as_ref(),
+        governing_token_owner.as_ref(),
+    ];
+    Pubkey::find_program_address(&seeds, program_id).0
+}
+
+pub fn get_proposal_address(
+    program_id: &Pubkey,
+    governance: &Pubkey,
+    governing_token_mint: &Pubkey,
+    proposal_index: u32,
+) -> Pubkey {
+    let seeds = [
+        GOVERNANCE_SEED,
+        governance.as_ref(),
+        governing_token_mint.as_ref(),
+        &proposal_index.to_le_bytes(),
+    ];
+    Pubkey::find_program_address(&seeds, program_id).0
+}
+
+fn get_signatory_record_address(
+    program_id: &Pubkey,
+    proposal: &Pubkey,
+    signatory: &Pubkey,
+) -> Pubkey {
+    let seeds = [GOVERNANCE_SEED, proposal.as_ref(), signatory.as_ref()];
+    Pubkey::find_program_address(&seeds, program_id).0
+}
+
+fn get_proposal_instruction_address(program_id: &Pubkey, proposal: &Pubkey, index: u16) -> Pubkey {
+    let seeds = [GOVERNANCE_SEED, proposal.as_ref(), &index.to_le_bytes()];
+    Pubkey::find_program_address(&seeds, program_id).0
+}
+
+/// Read the governance account, and check that it is one.
+pub fn get_governance(
+    config: &mut SnapshotConfig,
+    governance: &ManagerGovernance,
+) -> Result<Governance> {
+    let account = config.client.get_account(&governance.address)?;
+    if account.owner != governance.program_id {
+        return Err(CliError::new("The governance is not owned by the governance program.").into());
+    }
+    let state = Governance::deserialize(&mut &account.data[..])
+        .map_err(|err| CliError::with_cause("Failed to deserialize governance.", err))?;
+    match state.account_type {
+        ACCOUNT_TYPE_ACCOUNT_GOVERNANCE
+        | ACCOUNT_TYPE_PROGRAM_GOVERNANCE
+        | ACCOUNT_TYPE_MINT_GOVERNANCE
+        | ACCOUNT_TYPE_TOKEN_GOVERNANCE => Ok(state),
+        _ => Err(CliError::new("The account is not a governance.").into()),
+    }
+}
+
+/// Build the instructions that create a proposal that executes `instruction`,
+/// and make it ready for voting.
+///
+/// The proposer signs off the proposal right away, as its only signatory. The
+/// proposer pays for the proposal accounts.
+fn get_propose_instructions(
+    governance: &ManagerGovernance,
+    state: &Governance,
+    proposer: &Pubkey,
+    name: String,
+    description_link: String,
+    instruction: &Instruction,
+) -> (Pubkey, Vec<Instruction>) {
+    let program_id = &governance.program_id;
+    let mint = &governance.governing_token_mint;
+    let proposal =
+        get_proposal_address(program_id, &governance.address, mint, state.proposals_count);
+    let token_owner_record =
+        get_token_owner_record_address(program_id, &state.realm, mint, proposer);
+    let signatory_record = get_signatory_record_address(program_id, &proposal, proposer);
+    let proposal_instruction = get_proposal_instruction_address(program_id, &proposal, 0);
+
+    let create_proposal = Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(proposal, false),
+            AccountMeta::new(governance.address, false),
+            AccountMeta::new(token_owner_record, false),
+            AccountMeta::new_readonly(*proposer, true),
+            AccountMeta::new(*proposer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: instruction_data(
+            INSTRUCTION_CREATE_PROPOSAL,
+            &CreateProposalArgs {
+                name,
+                description_link,
+                governing_token_mint: *mint,
+            },
+        ),
+    };
+    let add_signatory = Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(token_owner_record, false),
+            AccountMeta::new_readonly(*proposer, true),
+            AccountMeta::new(signatory_record, false),
+            AccountMeta::new(*proposer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: instruction_data(
+            INSTRUCTION_ADD_SIGNATORY,
+            &AddSignatoryArgs {
+                signatory: *proposer,
+            },
+        ),
+    };
+    let insert_instruction = Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(governance.address, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(token_owner_record, false),
+            AccountMeta::new_readonly(*proposer, true),
+            AccountMeta::new(proposal_instruction, false),
+            AccountMeta::new(*proposer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: instruction_data(
+            INSTRUCTION_INSERT_INSTRUCTION,
+            &InsertInstructionArgs {
+                index: 0,
+                hold_up_time: state.min_instruction_hold_up_time,
+                instruction: InstructionData::from(instruction),
+            },
+        ),
+    };
+    let sign_off_proposal = Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(proposal, false),
+            AccountMeta::new(signatory_record, false),
+            AccountMeta::new_readonly(*proposer, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: vec![INSTRUCTION_SIGN_OFF_PROPOSAL],
+    };
+
+    (
+        proposal,
+        vec![
+            create_proposal,
+            add_signatory,
+            insert_instruction,
+            sign_off_proposal,
+        ],
+    )
+}
+
+#[derive(Serialize)]
+pub struct ProposeGovernanceOutput {
+    #[serde(serialize_with = "serialize_b58")]
+    pub proposal_address: Pubkey,
+}
+
+impl fmt::Display for ProposeGovernanceOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Proposal address: {}", self.proposal_address)
+    }
+}
+
+/// Propose the given instruction to be voted on and executed by the governance.
+///
+/// The signer must have deposited enough governing tokens in the realm to
+/// create proposals.
+pub fn propose_instruction(
+    config: &mut SnapshotConfig,
+    governance: &ManagerGovernance,
+    name: String,
+    description_link: String,
+    instruction: Instruction,
+) -> Result<ProposeGovernanceOutput> {
+    let state = get_governance(config, governance)?;
+    let (proposal_address, instructions) = get_propose_instructions(
+        governance,
+        &state,
+        &config.signer.pubkey(),
+        name,
+        description_link,
+        &instruction,
+    );
+    config.sign_and_send_transaction(&instructions, &[config.signer])?;
+    Ok(ProposeGovernanceOutput { proposal_address })
+}
+
+/// Like [`propose_instruction`], but only sign the transaction, for the caller to send.
+pub fn sign_propose_instruction(
+    config: &mut SnapshotConfig,
+    governance: &ManagerGovernance,
+    name: String,
+    description_link: String,
+    instruction: Instruction,
+) -> Result<(Transaction, ProposeGovernanceOutput)> {
+    let state = get_governance(config, governance)?;
+    let (proposal_address, instructions) = get_propose_instructions(
+        governance,
+        &state,
+        &config.signer.pubkey(),
+        name,
+        description_link,
+        &instruction,
+    );
+    let transaction = config.sign_transaction(&instructions, &[config.signer])?;
+    Ok((transaction, ProposeGovernanceOutput { proposal_address }))
+}
+
+/// Return whether the governance has a pending proposal that executes `instruction`.
+pub fn has_pending_proposal(
+    config: &mut SnapshotConfig,
+    governance: &ManagerGovernance,
+    instruction: &Instruction,
+) -> Result<bool> {
+    let proposal_filters = [
+        AccountFilter::Memcmp {
+            offset: 0,
+            bytes: vec![ACCOUNT_TYPE_PROPOSAL],
+        },
+        AccountFilter::Memcmp {
+            offset: 1,
+            bytes: governance.address.to_bytes().to_vec(),
+        },
+    ];
+    let mut pending_proposals = Vec::new();
+    for (address, account) in config
+        .client
+        .get_program_accounts(&governance.program_id, &proposal_filters)?
+    {
+        match account.data.get(PROPOSAL_STATE_OFFSET) {
+            Some(state) if PENDING_PROPOSAL_STATES.contains(state) => {
+                pending_proposals.push(address)
+            }
+            _ => continue,
+        }
+    }
+
+    let expected = InstructionData::from(instruction);
+    for proposal in pending_proposals {
+        let instruction_filters = [
+            AccountFilter::Memcmp {
+                offset: 0,
+                bytes: vec![ACCOUNT_TYPE_PROPOSAL_INSTRUCTION],
+            },
+            AccountFilter::Memcmp {
+                offset: 1,
+                bytes: proposal.to_bytes().to_vec(),
+            },
+        ];
+        for (_address, account) in config
+            .client
+            .get_program_accounts(&governance.program_id, &instruction_filters)?
+        {
+            match ProposalInstructionHeader::deserialize(&mut &account.data[..]) {
+                Ok(header) if header.instruction == expected => return Ok(true),
+                _ => continue,
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn get_manager_governance(
+    governance_program_id: &Pubkey,
+    governance_address: &Pubkey,
+    governing_token_mint: &Pubkey,
+) -> ManagerGovernance {
+    ManagerGovernance {
+        program_id: *governance_program_id,
+        address: *governance_address,
+        governing_token_mint: *governing_token_mint,
+    }
+}
+
+fn propose_add_validator(
+    config: &mut SnapshotConfig,
+    opts: &GovernanceAddValidatorOpts,
+) -> Result<ProposeGovernanceOutput> {
+    let governance = get_manager_governance(
+        opts.governance_program_id(),
+        opts.governance_address(),
+        opts.governing_token_mint(),
+    );
+    let instruction = lido::instruction::add_validator(
+        opts.solido_program_id(),
+        &lido::instruction::AddValidatorMeta {
+            lido: *opts.solido_address(),
+            manager: governance.address,
+            validator_vote_account: *opts.validator_vote_account(),
+            validator_fee_st_sol_account: *opts.validator_fee_account(),
+        },
+    );
+    propose_instruction(
+        config,
+        &governance,
+        opts.proposal_name().clone(),
+        opts.description_link().clone(),
+        instruction,
+    )
+}
+
+fn propose_deactivate_validator(
+    config: &mut SnapshotConfig,
+    opts: &GovernanceDeactivateValidatorOpts,
+) -> Result<ProposeGovernanceOutput> {
+    let governance = get_manager_governance(
+        opts.governance_program_id(),
+        opts.governance_address(),
+        opts.governing_token_mint(),
+    );
+    let instruction = lido::instruction::deactivate_validator(
+        opts.solido_program_id(),
+        &lido::instruction::DeactivateValidatorMeta {
+            lido: *opts.solido_address(),
+            manager: governance.address,
+            validator_vote_account_to_deactivate: *opts.validator_vote_account(),
+        },
+    );
+    propose_instruction(
+        config,
+        &governance,
+        opts.proposal_name().clone(),
+        opts.description_link().clone(),
+        instruction,
+    )
+}
+
+fn propose_schedule_reward_distribution(
+    config: &mut SnapshotConfig,
+    opts: &GovernanceScheduleRewardDistributionOpts,
+) -> Result<ProposeGovernanceOutput> {
+    let governance = get_manager_governance(
+        opts.governance_program_id(),
+        opts.governance_address(),
+        opts.governing_token_mint(),
+    );
+    let instruction = lido::instruction::schedule_reward_distribution(
+        opts.solido_program_id(),
+        RewardDistribution {
+            treasury_fee: *opts.treasury_fee_share(),
+            validation_fee: *opts.validation_fee_share(),
+            developer_fee: *opts.developer_fee_share(),
+            st_sol_appreciation: *opts.st_sol_appreciation_share(),
+        },
+        *opts.delay_epochs(),
+        &lido::instruction::ScheduleRewardDistributionMeta {
+            lido: *opts.solido_address(),
+            manager: governance.address,
+        },
+    );
+    propose_instruction(
+        config,
+        &governance,
+        opts.proposal_name().clone(),
+        opts.description_link().clone(),
+        instruction,
+    )
+}
+
+fn propose_change_fee_recipients(
+    config: &mut SnapshotConfig,
+    opts: &GovernanceChangeFeeRecipientsOpts,
+) -> Result<ProposeGovernanceOutput> {
+    let governance = get_manager_governance(
+        opts.governance_program_id(),
+        opts.governance_address(),
+        opts.governing_token_mint(),
+    );
+    let solido = config.client.get_solido(opts.solido_address())?;
+
+    // Keep the current fee recipients, unless the proposal replaces them.
+    let fee_recipients = FeeRecipients {
+        treasury_account: match *opts.treasury_account() {
+            account if account == Pubkey::default() => solido.fee_recipients.treasury_account,
+            account => account,
+        },
+        developer_account: match *opts.developer_account() {
+            account if account == Pubkey::default() => solido.fee_recipients.developer_account,
+            account => account,
+        },
+    };
+    let instruction = lido::instruction::change_fee_recipients(
+        opts.solido_program_id(),
+        &lido::instruction::ChangeFeeRecipientsMeta {
+            lido: *opts.solido_address(),
+            manager: governance.address,
+            treasury_account: fee_recipients.treasury_account,
+            developer_account: fee_recipients.developer_account,
+        },
+    );
+    propose_instruction(
+        config,
+        &governance,
+        opts.proposal_name().clone(),
+        opts.description_link().clone(),
+        instruction,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_propose_instructions_embed_instruction() {
+        let governance = ManagerGovernance {
+            program_id: Pubkey::new_unique(),
+            address: Pubkey::new_unique(),
+            governing_token_mint: Pubkey::new_unique(),
+        };
+        let state = Governance {
+            account_type: ACCOUNT_TYPE_ACCOUNT_GOVERNANCE,
+            realm: Pubkey::new_unique(),
+            governed_account: Pubkey::new_unique(),
+            proposals_count: 3,
+            vote_threshold_percentage_type: 0,
+            vote_threshold_percentage: 60,
+            min_community_tokens_to_create_proposal: 1,
+            min_instruction_hold_up_time: 3600,
+        };
+        let manager_instruction = lido::instruction::deactivate_validator(
+            &Pubkey::new_unique(),
+            &lido::instruction::DeactivateValidatorMeta {
+                lido: Pubkey::new_unique(),
+                manager: governance.address,
+                validator_vote_account_to_deactivate: Pubkey::new_unique(),
+            },
+        );
+        let proposer = Pubkey::new_unique();
+        let (proposal, instructions) = get_propose_instructions(
+            &governance,
+            &state,
+            &proposer,
+            "Deactivate validator".to_string(),
+            "".to_string(),
+            &manager_instruction,
+        );
+
+        // The proposal gets the next index of the governance.
+        assert_eq!(
+            proposal,
+            get_proposal_address(
+                &governance.program_id,
+                &governance.address,
+                &governance.governing_token_mint,
+                3
+            )
+        );
+        let tags: Vec<u8> = instructions.iter().map(|i| i.data[0]).collect();
+        assert_eq!(
+            tags,
+            vec![
+                INSTRUCTION_CREATE_PROPOSAL,
+                INSTRUCTION_ADD_SIGNATORY,
+                INSTRUCTION_INSERT_INSTRUCTION,
+                INSTRUCTION_SIGN_OFF_PROPOSAL,
+            ]
+        );
+
+        // The inserted instruction is the manager instruction, after the index
+        // and hold up time.
+        let mut args = &instructions[2].data[1..];
+        assert_eq!(u16::deserialize(&mut args).unwrap(), 0);
+        assert_eq!(u32::deserialize(&mut args).unwrap(), 3600);
+        let embedded = InstructionData::deserialize(&mut args).unwrap();
+        assert_eq!(embedded, InstructionData::from(&manager_instruction));
+        assert!(args.is_empty());
+    }
+}
@@ -4,35 +4,54 @@
 use std::{fmt, path::PathBuf};
 
 use serde::Serialize;
-use solana_program::{pubkey::Pubkey, system_instruction};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, system_instruction};
 use solana_sdk::{
     account::ReadableAccount,
     signature::{Keypair, Signer},
 };
 
 use lido::{
-    balance::get_validator_to_withdraw,
-    find_authority_program_address,
+    balance::{get_withdraw_plan, get_withdraw_steps, WithdrawStep},
+    idl::{generate_idl, Idl},
     metrics::LamportsHistogram,
     processor::StakeType,
-    state::{Lido, RewardDistribution},
+    stake_account::{deserialize_stake_account, StakeAccount},
+    state::{DepositorAllowlist, ExchangeRate, Lido, RewardDistribution, WithdrawTicket},
     token::{Lamports, StLamports},
-    util::serialize_b58,
-    MINT_AUTHORITY, RESERVE_ACCOUNT, REWARDS_WITHDRAW_AUTHORITY, STAKE_AUTHORITY,
+    util::{serialize_b58, serialize_b58_slice},
+    MAXIMUM_ALLOWED_DEPOSITORS, MINT_AUTHORITY, RESERVE_ACCOUNT, REWARDS_WITHDRAW_AUTHORITY,
+    STAKE_AUTHORITY,
 };
 
 use crate::{
     config::{
-        AddRemoveMaintainerOpts, AddValidatorOpts, CreateSolidoOpts, DeactivateValidatorOpts,
-        DepositOpts, ShowSolidoAuthoritiesOpts, ShowSolidoOpts, WithdrawOpts,
+        AddRemoveAllowedDepositorOpts, AddRemoveMaintainerOpts, AddValidatorOpts,
+        ApplyValidatorPolicyOpts, ClaimWithdrawTicketOpts, CreateDepositorAllowlistOpts,
+        CreateSolidoOpts, CreateWithdrawTicketOpts, DeactivateValidatorOpts, DepositOpts,
+        DepositStakeAccountOpts, EnableDisableDepositorAllowlistOpts, ExportIdlOpts,
+        MigrateStateOpts, PauseResumeOpts, ReplaceValidatorVoteAccountOpts, RewardsReportOpts,
+        RotateMaintainerOpts, ScheduleRewardDistributionOpts, SetMaxStakePerValidatorOpts,
+        SetMaxTotalSolOpts, SetValidatorWeightOpts, ShowKeeperScheduleOpts,
+        ShowSolidoAuthoritiesOpts, ShowSolidoOpts, ShowStakeAccountsOpts, ShowStakePoolOpts,
+        ShowValidatorsOpts, VerifyOpts, WithdrawOpts,
     },
-    error::CliError,
+    derivation_cache::{self, find_authority_program_address},
+    error::{AsPrettyError, CliError},
     get_signer_from_path,
+    keeper::{serialize_b58_option, KeeperSchedule},
+    maintenance::get_program_derived_state,
+    mev::{get_validator_mev, ValidatorMev},
+    rewards_report::RewardsReport,
+    verify::Verification,
 };
 use crate::{
     multisig::{get_multisig_program_address, propose_instruction, ProposeInstructionOutput},
     snapshot::Result,
     spl_token_utils::{push_create_spl_token_account, push_create_spl_token_mint},
+    stake_accounts::StakeAccounts,
+    stake_pool::StakePoolView,
+    validator_policy::{Decision, Policy, ValidatorPolicyReport},
+    validators_report::ValidatorsReport,
     SnapshotClientConfig, SnapshotConfig,
 };
 
@@ -145,10 +164,12 @@ pub fn command_create_solido(
         min_balance_empty_data_account.0,
     ));
 
-    let st_sol_mint_pubkey = {
+    let (st_sol_mint_pubkey, st_sol_token_program) = {
         if opts.mint_address() != &Pubkey::default() {
-            // If we've been given a minter address, return its public key.
-            *opts.mint_address()
+            // If we've been given a minter address, return its public key, and
+            // the token program that owns it, which can be SPL token or Token-2022.
+            let mint_account = config.client.get_account(opts.mint_address())?;
+            (*opts.mint_address(), mint_account.owner)
         } else {
             // If not, set up the Lido stSOL SPL token mint account.
             let st_sol_mint_keypair =
@@ -161,7 +182,7 @@ pub fn command_create_solido(
             config.sign_and_send_transaction(&instructions[..], signers)?;
             instructions.clear();
             eprintln!("Did send mint init.");
-            st_sol_mint_keypair.pubkey()
+            (st_sol_mint_keypair.pubkey(), spl_token::id())
         }
     };
 
@@ -171,12 +192,14 @@ pub fn command_create_solido(
         &mut instructions,
         &st_sol_mint_pubkey,
         opts.treasury_account_owner(),
+        &st_sol_token_program,
     )?;
     let developer_keypair = push_create_spl_token_account(
         config,
         &mut instructions,
         &st_sol_mint_pubkey,
         opts.developer_account_owner(),
+        &st_sol_token_program,
     )?;
     config.sign_and_send_transaction(
         &instructions[..],
@@ -211,6 +234,7 @@ pub fn command_create_solido(
             treasury_account: treasury_keypair.pubkey(),
             developer_account: developer_keypair.pubkey(),
             reserve_account,
+            spl_token: st_sol_token_program,
         },
     ));
 
@@ -277,6 +301,277 @@ pub fn command_deactivate_validator(
     )
 }
 
+/// CLI entry point to move a validator and its stake to a new vote account.
+///
+/// The program decides for every stake account whether it can be delegated to
+/// the new vote account, or whether it must be deactivated, and it checks the
+/// destination accounts that we pass. We make the same decision here, based on
+/// the current epoch, so the proposal must be executed in the same epoch.
+pub fn command_replace_validator_vote_account(
+    config: &mut SnapshotConfig,
+    opts: &ReplaceValidatorVoteAccountOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let solido = config.client.get_solido(opts.solido_address())?;
+    let stake_authority =
+        solido.get_stake_authority(opts.solido_program_id(), opts.solido_address())?;
+    let clock = config.client.get_clock()?;
+    let stake_history = config.client.get_stake_history()?;
+    let validator = solido
+        .validators
+        .get(opts.old_validator_vote_account())
+        .map_err(|err| {
+            CliError::with_cause("The old vote account is not a Solido validator.", err)
+        })?;
+
+    let find_address = |vote_account: &Pubkey, seed: u64, stake_type: StakeType| {
+        derivation_cache::find_stake_account_address(
+            vote_account,
+            opts.solido_program_id(),
+            opts.solido_address(),
+            seed,
+            stake_type,
+        )
+        .0
+    };
+
+    let mut sources = Vec::new();
+    let mut destinations = Vec::new();
+    let mut new_stake_seed = validator.entry.stake_seeds.begin;
+    let mut new_unstake_seed = validator.entry.unstake_seeds.end;
+    for seed in &validator.entry.stake_seeds {
+        let source = find_address(&validator.pubkey, seed, StakeType::Stake);
+        let account = config.client.get_account(&source)?;
+        let stake = deserialize_stake_account(&account.data)?;
+        let stake_account = StakeAccount::from_delegated_account(
+            Lamports(account.lamports),
+            &stake,
+            &clock,
+            &stake_history,
+            seed,
+        );
+        // The stake program only allows delegating stake that is not effective yet.
+        let destination = if stake_account.balance.active == Lamports(0)
+            && stake_account.balance.deactivating == Lamports(0)
+        {
+            new_stake_seed += 1;
+            find_address(
+                opts.new_validator_vote_account(),
+                new_stake_seed - 1,
+                StakeType::Stake,
+            )
+        } else {
+            new_unstake_seed += 1;
+            find_address(
+                opts.new_validator_vote_account(),
+                new_unstake_seed - 1,
+                StakeType::Unstake,
+            )
+        };
+        sources.push(source);
+        destinations.push(destination);
+    }
+    for seed in &validator.entry.unstake_seeds {
+        sources.push(find_address(&validator.pubkey, seed, StakeType::Unstake));
+        destinations.push(find_address(
+            opts.new_validator_vote_account(),
+            seed,
+            StakeType::Unstake,
+        ));
+    }
+    sources.extend(destinations);
+
+    let instruction = lido::instruction::replace_validator_vote_account(
+        opts.solido_program_id(),
+        &lido::instruction::ReplaceValidatorVoteAccountMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+            old_validator_vote_account: *opts.old_validator_vote_account(),
+            new_validator_vote_account: *opts.new_validator_vote_account(),
+            stake_authority,
+            stake_accounts: sources,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to change the weight of a validator.
+pub fn command_set_validator_weight(
+    config: &mut SnapshotConfig,
+    opts: &SetValidatorWeightOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::set_validator_weight(
+        opts.solido_program_id(),
+        *opts.weight(),
+        &lido::instruction::SetValidatorWeightMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+            validator_vote_account: *opts.validator_vote_account(),
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to change the maximum stake per validator.
+pub fn command_set_max_stake_per_validator(
+    config: &mut SnapshotConfig,
+    opts: &SetMaxStakePerValidatorOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::set_max_stake_per_validator(
+        opts.solido_program_id(),
+        *opts.max_stake_per_validator_sol(),
+        &lido::instruction::SetMaxStakePerValidatorMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to change the maximum amount of SOL managed by Solido.
+pub fn command_set_max_total_sol(
+    config: &mut SnapshotConfig,
+    opts: &SetMaxTotalSolOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::set_max_total_lamports(
+        opts.solido_program_id(),
+        *opts.max_total_sol(),
+        &lido::instruction::SetMaxTotalLamportsMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to schedule a change of the reward distribution.
+pub fn command_schedule_reward_distribution(
+    config: &mut SnapshotConfig,
+    opts: &ScheduleRewardDistributionOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::schedule_reward_distribution(
+        opts.solido_program_id(),
+        RewardDistribution {
+            treasury_fee: *opts.treasury_fee_share(),
+            validation_fee: *opts.validation_fee_share(),
+            developer_fee: *opts.developer_fee_share(),
+            st_sol_appreciation: *opts.st_sol_appreciation_share(),
+        },
+        *opts.delay_epochs(),
+        &lido::instruction::ScheduleRewardDistributionMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// Propose to pause or resume deposits and withdrawals.
+fn propose_set_paused(
+    config: &mut SnapshotConfig,
+    opts: &PauseResumeOpts,
+    paused: bool,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::set_paused(
+        opts.solido_program_id(),
+        paused,
+        &lido::instruction::SetPausedMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to pause deposits and withdrawals, in case of an emergency.
+pub fn command_pause(
+    config: &mut SnapshotConfig,
+    opts: &PauseResumeOpts,
+) -> Result<ProposeInstructionOutput> {
+    propose_set_paused(config, opts, true)
+}
+
+/// CLI entry point to resume deposits and withdrawals after a pause.
+pub fn command_resume(
+    config: &mut SnapshotConfig,
+    opts: &PauseResumeOpts,
+) -> Result<ProposeInstructionOutput> {
+    propose_set_paused(config, opts, false)
+}
+
+/// CLI entry point to propose migrating the Solido state to the current version.
+///
+/// This does not read the Solido state, the current CLI cannot deserialize
+/// an instance that is not migrated yet.
+pub fn command_migrate_state(
+    config: &mut SnapshotConfig,
+    opts: &MigrateStateOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::migrate_state_to_v1(
+        opts.solido_program_id(),
+        &lido::instruction::MigrateStateToV1Meta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
 /// CLI entry point to to add a maintainer to Solido.
 pub fn command_add_maintainer(
     config: &mut SnapshotConfig,
@@ -323,6 +618,300 @@ pub fn command_remove_maintainer(
     )
 }
 
+#[derive(Serialize)]
+pub struct CreateDepositorAllowlistOutput {
+    /// The depositor allowlist account.
+    #[serde(serialize_with = "serialize_b58")]
+    pub depositor_allowlist: Pubkey,
+
+    /// The number of depositors that fit in the allowlist.
+    pub max_depositors: u32,
+}
+
+impl fmt::Display for CreateDepositorAllowlistOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Depositor allowlist: {}", self.depositor_allowlist)?;
+        writeln!(f, "Maximum depositors:  {}", self.max_depositors)?;
+        Ok(())
+    }
+}
+
+/// Return whether the depositor allowlist account of the Solido instance exists.
+fn depositor_allowlist_exists(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    depositor_allowlist: &Pubkey,
+) -> Result<bool> {
+    if !config.client.account_exists(depositor_allowlist)? {
+        return Ok(false);
+    }
+    // Anybody can send SOL to the address, it only holds the allowlist once
+    // the program owns it.
+    let account = config.client.get_account(depositor_allowlist)?;
+    Ok(&account.owner == solido_program_id)
+}
+
+/// CLI entry point to create the depositor allowlist, paid for by the fee payer.
+///
+/// This does not need the manager, the allowlist has no effect until the
+/// manager enables it.
+pub fn command_create_depositor_allowlist(
+    config: &mut SnapshotConfig,
+    opts: &CreateDepositorAllowlistOpts,
+) -> Result<CreateDepositorAllowlistOutput> {
+    let (depositor_allowlist, _bump_seed) =
+        DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
+    if depositor_allowlist_exists(config, opts.solido_program_id(), &depositor_allowlist)? {
+        return Err(CliError::new("The depositor allowlist already exists.").into());
+    }
+
+    let instruction = lido::instruction::create_depositor_allowlist(
+        opts.solido_program_id(),
+        &lido::instruction::CreateDepositorAllowlistMeta {
+            lido: *opts.solido_address(),
+            payer: config.fee_payer.pubkey(),
+            depositor_allowlist,
+        },
+    );
+    config.sign_and_send_transaction(&[instruction], &[config.fee_payer])?;
+
+    Ok(CreateDepositorAllowlistOutput {
+        depositor_allowlist,
+        max_depositors: MAXIMUM_ALLOWED_DEPOSITORS,
+    })
+}
+
+/// Confirm that the depositor allowlist exists, before we propose to change it.
+fn check_depositor_allowlist_exists(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    depositor_allowlist: &Pubkey,
+) -> Result<()> {
+    if !depositor_allowlist_exists(config, solido_program_id, depositor_allowlist)? {
+        return Err(CliError::new(
+            "The depositor allowlist does not exist yet, create it with \
+            `solido create-depositor-allowlist` first.",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// CLI entry point to add a depositor to the depositor allowlist.
+pub fn command_add_allowed_depositor(
+    config: &mut SnapshotConfig,
+    opts: &AddRemoveAllowedDepositorOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (depositor_allowlist, _bump_seed) =
+        DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
+    check_depositor_allowlist_exists(config, opts.solido_program_id(), &depositor_allowlist)?;
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+    let instruction = lido::instruction::add_allowed_depositor(
+        opts.solido_program_id(),
+        &lido::instruction::AddAllowedDepositorMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+            depositor: *opts.depositor_address(),
+            depositor_allowlist,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to remove a depositor from the depositor allowlist.
+pub fn command_remove_allowed_depositor(
+    config: &mut SnapshotConfig,
+    opts: &AddRemoveAllowedDepositorOpts,
+) -> Result<ProposeInstructionOutput> {
+    let (depositor_allowlist, _bump_seed) =
+        DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
+    check_depositor_allowlist_exists(config, opts.solido_program_id(), &depositor_allowlist)?;
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+    let instruction = lido::instruction::remove_allowed_depositor(
+        opts.solido_program_id(),
+        &lido::instruction::RemoveAllowedDepositorMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+            depositor: *opts.depositor_address(),
+            depositor_allowlist,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// Propose to enable or disable the depositor allowlist.
+fn propose_set_depositor_allowlist_enabled(
+    config: &mut SnapshotConfig,
+    opts: &EnableDisableDepositorAllowlistOpts,
+    enabled: bool,
+) -> Result<ProposeInstructionOutput> {
+    let (depositor_allowlist, _bump_seed) =
+        DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
+    if enabled {
+        check_depositor_allowlist_exists(config, opts.solido_program_id(), &depositor_allowlist)?;
+    }
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let instruction = lido::instruction::set_depositor_allowlist_enabled(
+        opts.solido_program_id(),
+        enabled,
+        &lido::instruction::SetDepositorAllowlistEnabledMeta {
+            lido: *opts.solido_address(),
+            manager: multisig_address,
+            depositor_allowlist,
+        },
+    );
+    propose_instruction(
+        config,
+        opts.multisig_program_id(),
+        *opts.multisig_address(),
+        instruction,
+    )
+}
+
+/// CLI entry point to only accept deposits from depositors on the allowlist.
+pub fn command_enable_depositor_allowlist(
+    config: &mut SnapshotConfig,
+    opts: &EnableDisableDepositorAllowlistOpts,
+) -> Result<ProposeInstructionOutput> {
+    propose_set_depositor_allowlist_enabled(config, opts, true)
+}
+
+/// CLI entry point to accept deposits from anybody again.
+pub fn command_disable_depositor_allowlist(
+    config: &mut SnapshotConfig,
+    opts: &EnableDisableDepositorAllowlistOpts,
+) -> Result<ProposeInstructionOutput> {
+    propose_set_depositor_allowlist_enabled(config, opts, false)
+}
+
+#[derive(Serialize)]
+pub struct RotateMaintainerOutput {
+    /// Multisig transaction that adds the new maintainer, if we proposed one.
+    #[serde(serialize_with = "serialize_b58_option")]
+    pub add_transaction_address: Option<Pubkey>,
+
+    /// Multisig transaction that removes the old maintainer, if we proposed one.
+    #[serde(serialize_with = "serialize_b58_option")]
+    pub remove_transaction_address: Option<Pubkey>,
+}
+
+impl fmt::Display for RotateMaintainerOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.add_transaction_address {
+            Some(address) => writeln!(f, "Add transaction address:    {}", address)?,
+            None => writeln!(f, "The new maintainer was already a maintainer.")?,
+        }
+        match self.remove_transaction_address {
+            Some(address) => writeln!(f, "Remove transaction address: {}", address)?,
+            None => writeln!(f, "The old maintainer was already removed.")?,
+        }
+        Ok(())
+    }
+}
+
+/// Return whether `maintainer` is currently a maintainer of the Solido instance.
+fn is_maintainer(
+    config: &mut SnapshotClientConfig,
+    solido_address: &Pubkey,
+    maintainer: &Pubkey,
+) -> std::result::Result<bool, crate::error::Error> {
+    config.with_snapshot(|config| {
+        let solido = config.client.get_solido(solido_address)?;
+        Ok(solido.maintainers.get(maintainer).is_ok())
+    })
+}
+
+/// CLI entry point to replace a maintainer key with a new one.
+///
+/// This proposes to add the new maintainer, waits until the multisig has
+/// executed that, and then proposes to remove the old maintainer. Reload the
+/// maintainer daemons with the new key in between (with SIGHUP, or a POST to
+/// `/reload`), so there is always a key that can perform maintenance. Steps
+/// that are already done are skipped, so the command can be run again after
+/// an interruption.
+pub fn command_rotate_maintainer(
+    config: &mut SnapshotClientConfig,
+    opts: &RotateMaintainerOpts,
+) -> std::result::Result<RotateMaintainerOutput, crate::error::Error> {
+    let (multisig_address, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+
+    let add_transaction_address =
+        if is_maintainer(config, opts.solido_address(), opts.new_maintainer_address())? {
+            None
+        } else {
+            let instruction = lido::instruction::add_maintainer(
+                opts.solido_program_id(),
+                &lido::instruction::AddMaintainerMeta {
+                    lido: *opts.solido_address(),
+                    manager: multisig_address,
+                    maintainer: *opts.new_maintainer_address(),
+                },
+            );
+            let output = config.with_snapshot(|config| {
+                propose_instruction(
+                    config,
+                    opts.multisig_program_id(),
+                    *opts.multisig_address(),
+                    instruction.clone(),
+                )
+            })?;
+            eprintln!(
+                "Proposed to add maintainer {} in transaction {}.",
+                opts.new_maintainer_address(),
+                output.transaction_address,
+            );
+            eprintln!("Waiting for the multisig to approve and execute it ...");
+            Some(output.transaction_address)
+        };
+
+    while !is_maintainer(config, opts.solido_address(), opts.new_maintainer_address())? {
+        std::thread::sleep(std::time::Duration::from_secs(*opts.poll_seconds()));
+    }
+
+    let remove_transaction_address =
+        if is_maintainer(config, opts.solido_address(), opts.old_maintainer_address())? {
+            let instruction = lido::instruction::remove_maintainer(
+                opts.solido_program_id(),
+                &lido::instruction::RemoveMaintainerMeta {
+                    lido: *opts.solido_address(),
+                    manager: multisig_address,
+                    maintainer: *opts.old_maintainer_address(),
+                },
+            );
+            let output = config.with_snapshot(|config| {
+                propose_instruction(
+                    config,
+                    opts.multisig_program_id(),
+                    *opts.multisig_address(),
+                    instruction.clone(),
+                )
+            })?;
+            Some(output.transaction_address)
+        } else {
+            None
+        };
+
+    Ok(RotateMaintainerOutput {
+        add_transaction_address,
+        remove_transaction_address,
+    })
+}
+
 #[derive(Serialize)]
 pub struct ShowSolidoOutput {
     pub solido: Lido,
@@ -344,6 +933,10 @@ pub struct ShowSolidoOutput {
 
     #[serde(serialize_with = "serialize_b58")]
     pub rewards_withdraw_authority: Pubkey,
+
+    /// For each validator, in the same order as in `solido.validators`, the
+    /// MEV tips it earned, if it runs Jito.
+    pub validator_mev: Vec<ValidatorMev>,
 }
 
 impl fmt::Display for ShowSolidoOutput {
@@ -354,6 +947,11 @@ impl fmt::Display for ShowSolidoOutput {
             "stSOL mint:                  {}",
             self.solido.st_sol_mint
         )?;
+        writeln!(
+            f,
+            "stSOL token program:         {}",
+            self.solido.st_sol_token_program
+        )?;
 
         writeln!(f, "\nExchange rate:")?;
         writeln!(
@@ -408,6 +1006,22 @@ impl fmt::Display for ShowSolidoOutput {
         print_reward("Validation fee", |d| d.validation_fee)?;
         print_reward("Developer fee", |d| d.developer_fee)?;
 
+        if let Some(pending) = self.solido.get_pending_reward_distribution() {
+            let distribution = &pending.reward_distribution;
+            writeln!(
+                f,
+                "\nScheduled reward distribution, from epoch {}:",
+                pending.activation_epoch
+            )?;
+            let mut print_pending = |name, share: u32| {
+                writeln!(f, "  {:4}/{:4} => {}", share, distribution.sum(), name)
+            };
+            print_pending("stSOL appreciation", distribution.st_sol_appreciation)?;
+            print_pending("Treasury", distribution.treasury_fee)?;
+            print_pending("Validation fee", distribution.validation_fee)?;
+            print_pending("Developer fee", distribution.developer_fee)?;
+        }
+
         writeln!(f, "\nFee recipients:")?;
         writeln!(
             f,
@@ -420,6 +1034,34 @@ impl fmt::Display for ShowSolidoOutput {
             self.solido.fee_recipients.developer_account
         )?;
 
+        if self.solido.is_paused {
+            writeln!(f, "\nDeposits and withdrawals are PAUSED by the manager.")?;
+        }
+        if self.solido.is_depositor_allowlist_enabled {
+            writeln!(
+                f,
+                "\nOnly depositors on the depositor allowlist can deposit."
+            )?;
+        }
+
+        match self.solido.get_max_stake_per_validator() {
+            None => writeln!(f, "\nMaximum stake per validator: none")?,
+            Some(max_stake) => writeln!(f, "\nMaximum stake per validator: {}", max_stake)?,
+        }
+        match self.solido.get_max_total_lamports() {
+            None => writeln!(f, "Maximum total SOL:           none")?,
+            Some(max_total) => writeln!(f, "Maximum total SOL:           {}", max_total)?,
+        }
+
+        let queue = &self.solido.withdraw_queue;
+        writeln!(f, "\nWithdraw queue:")?;
+        writeln!(
+            f,
+            "  Unfunded tickets: {}",
+            queue.next_ticket_id - queue.next_fund_ticket_id
+        )?;
+        writeln!(f, "  Outstanding:      {}", queue.get_outstanding())?;
+
         writeln!(f, "\nMetrics:")?;
         writeln!(
             f,
@@ -471,24 +1113,54 @@ impl fmt::Display for ShowSolidoOutput {
             self.solido.validators.len(),
             self.solido.validators.maximum_entries
         )?;
-        for pe in &self.solido.validators.entries {
+        for (pe, mev) in self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_mev.iter())
+        {
             writeln!(
                 f,
                 "\n  - \
                 Vote account:              {}\n    \
                 Fee address:               {}\n    \
+                Weight:                    {}\n    \
                 Unclaimed fee:             {}\n    \
                 Stake in all accounts:     {}\n    \
                 Stake in stake accounts:   {}\n    \
                 Stake in unstake accounts: {}",
                 pe.pubkey,
                 pe.entry.fee_address,
+                pe.entry.weight,
                 pe.entry.fee_credit,
                 pe.entry.stake_accounts_balance,
                 pe.entry.effective_stake_balance(),
                 pe.entry.unstake_accounts_balance,
             )?;
 
+            match (&mev.previous_epoch, &mev.current_epoch) {
+                (None, None) => {
+                    writeln!(f, "    MEV tips:                  none, not running Jito")?
+                }
+                (previous, current) => {
+                    if let Some(tips) = previous {
+                        writeln!(
+                            f,
+                            "    MEV tips in epoch {}:      {} earned, {} claimed",
+                            tips.epoch, tips.earned, tips.claimed
+                        )?;
+                    }
+                    if let Some(tips) = current {
+                        writeln!(
+                            f,
+                            "    MEV tips in epoch {}:      {} so far",
+                            tips.epoch, tips.earned
+                        )?;
+                    }
+                }
+            }
+
             writeln!(f, "    Stake accounts (seed, address):")?;
             if pe.entry.stake_seeds.begin == pe.entry.stake_seeds.end {
                 writeln!(f, "      This validator has no stake accounts.")?;
@@ -498,7 +1170,8 @@ impl fmt::Display for ShowSolidoOutput {
                     f,
                     "      - {}: {}",
                     seed,
-                    pe.find_stake_account_address(
+                    derivation_cache::find_stake_account_address(
+                        &pe.pubkey,
                         &self.solido_program_id,
                         &self.solido_address,
                         seed,
@@ -517,7 +1190,8 @@ impl fmt::Display for ShowSolidoOutput {
                     f,
                     "      - {}: {}",
                     seed,
-                    pe.find_stake_account_address(
+                    derivation_cache::find_stake_account_address(
+                        &pe.pubkey,
                         &self.solido_program_id,
                         &self.solido_address,
                         seed,
@@ -540,29 +1214,158 @@ impl fmt::Display for ShowSolidoOutput {
     }
 }
 
-pub fn command_show_solido(
+pub fn command_show_solido(
+    config: &mut SnapshotConfig,
+    opts: &ShowSolidoOpts,
+) -> Result<ShowSolidoOutput> {
+    let lido = config.client.get_solido(opts.solido_address())?;
+    let reserve_account =
+        lido.get_reserve_account(opts.solido_program_id(), opts.solido_address())?;
+    let stake_authority =
+        lido.get_stake_authority(opts.solido_program_id(), opts.solido_address())?;
+    let mint_authority =
+        lido.get_mint_authority(opts.solido_program_id(), opts.solido_address())?;
+    let rewards_withdraw_authority =
+        lido.get_rewards_withdraw_authority(opts.solido_program_id(), opts.solido_address())?;
+
+    let rent = config.client.get_rent()?;
+    let clock = config.client.get_clock()?;
+    let validator_mev = lido
+        .validators
+        .entries
+        .iter()
+        .map(|pe| get_validator_mev(&mut config.client, &rent, &pe.pubkey, clock.epoch))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ShowSolidoOutput {
+        solido_program_id: *opts.solido_program_id(),
+        solido_address: *opts.solido_address(),
+        solido: lido,
+        reserve_account,
+        stake_authority,
+        mint_authority,
+        rewards_withdraw_authority,
+        validator_mev,
+    })
+}
+
+pub fn command_show_stake_pool(
+    config: &mut SnapshotConfig,
+    opts: &ShowStakePoolOpts,
+) -> Result<StakePoolView> {
+    let lido = config.client.get_solido(opts.solido_address())?;
+    StakePoolView::new(opts.solido_program_id(), opts.solido_address(), &lido)
+}
+
+pub fn command_rewards_report(
+    config: &mut SnapshotConfig,
+    opts: &RewardsReportOpts,
+) -> Result<RewardsReport> {
+    let lido = config.client.get_solido(opts.solido_address())?;
+    RewardsReport::new(
+        &mut config.client,
+        opts.solido_program_id(),
+        opts.solido_address(),
+        &lido,
+        *opts.epochs(),
+    )
+}
+
+pub fn command_verify(config: &mut SnapshotConfig, opts: &VerifyOpts) -> Result<Verification> {
+    Verification::new(
+        &mut config.client,
+        opts.solido_program_id(),
+        opts.solido_address(),
+    )
+}
+
+pub fn command_show_stake_accounts(
+    config: &mut SnapshotConfig,
+    opts: &ShowStakeAccountsOpts,
+) -> Result<StakeAccounts> {
+    StakeAccounts::new(
+        &mut config.client,
+        opts.solido_program_id(),
+        opts.solido_address(),
+    )
+}
+
+pub fn command_show_validators(
+    config: &mut SnapshotConfig,
+    opts: &ShowValidatorsOpts,
+) -> Result<ValidatorsReport> {
+    let lido = config.client.get_solido(opts.solido_address())?;
+    ValidatorsReport::new(
+        &mut config.client,
+        opts.solido_program_id(),
+        opts.solido_address(),
+        &lido,
+        *opts.sort_by(),
+    )
+}
+
+pub fn command_show_keeper_schedule(
     config: &mut SnapshotConfig,
-    opts: &ShowSolidoOpts,
-) -> Result<ShowSolidoOutput> {
+    opts: &ShowKeeperScheduleOpts,
+) -> Result<KeeperSchedule> {
     let lido = config.client.get_solido(opts.solido_address())?;
-    let reserve_account =
-        lido.get_reserve_account(opts.solido_program_id(), opts.solido_address())?;
-    let stake_authority =
-        lido.get_stake_authority(opts.solido_program_id(), opts.solido_address())?;
-    let mint_authority =
-        lido.get_mint_authority(opts.solido_program_id(), opts.solido_address())?;
-    let rewards_withdraw_authority =
-        lido.get_rewards_withdraw_authority(opts.solido_program_id(), opts.solido_address())?;
+    KeeperSchedule::new(opts.solido_program_id(), opts.solido_address(), &lido)
+}
 
-    Ok(ShowSolidoOutput {
-        solido_program_id: *opts.solido_program_id(),
-        solido_address: *opts.solido_address(),
-        solido: lido,
-        reserve_account,
-        stake_authority,
-        mint_authority,
-        rewards_withdraw_authority,
-    })
+/// CLI entry point to evaluate the validator set against a curation policy.
+pub fn command_apply_validator_policy(
+    config: &mut SnapshotConfig,
+    opts: &ApplyValidatorPolicyOpts,
+) -> Result<ValidatorPolicyReport> {
+    let policy_toml = std::fs::read_to_string(opts.policy_path())
+        .map_err(|err| CliError::with_cause("Failed to read policy file.", err))?;
+    let policy = Policy::from_toml(&policy_toml)
+        .map_err(|err| CliError::with_cause("Failed to parse policy file.", err))?;
+
+    let mut report =
+        ValidatorPolicyReport::new(&mut config.client, opts.solido_address(), &policy)?;
+    if !*opts.propose() {
+        return Ok(report);
+    }
+    if *opts.multisig_address() == Pubkey::default() {
+        return Err(CliError::new(
+            "Proposing requires --multisig-address and --multisig-program-id.",
+        )
+        .into());
+    }
+
+    let (manager, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+    for validator in report.validators.iter_mut() {
+        let instruction = match (validator.decision, validator.fee_account) {
+            (Decision::Deactivate, _) => lido::instruction::deactivate_validator(
+                opts.solido_program_id(),
+                &lido::instruction::DeactivateValidatorMeta {
+                    lido: *opts.solido_address(),
+                    manager,
+                    validator_vote_account_to_deactivate: validator.vote_account,
+                },
+            ),
+            (Decision::Add, Some(fee_account)) => lido::instruction::add_validator(
+                opts.solido_program_id(),
+                &lido::instruction::AddValidatorMeta {
+                    lido: *opts.solido_address(),
+                    manager,
+                    validator_vote_account: validator.vote_account,
+                    validator_fee_st_sol_account: fee_account,
+                },
+            ),
+            _ => continue,
+        };
+        let output = propose_instruction(
+            config,
+            opts.multisig_program_id(),
+            *opts.multisig_address(),
+            instruction,
+        )?;
+        validator.proposal = Some(output.transaction_address.to_string());
+    }
+    Ok(report)
 }
 
 #[derive(Serialize)]
@@ -633,6 +1436,13 @@ pub fn command_show_solido_authorities(
     })
 }
 
+/// Generate the IDL of the Solido program, see `lido::idl`.
+pub fn command_export_idl(opts: &ExportIdlOpts) -> Idl {
+    let mut idl = generate_idl();
+    idl.address = Some(opts.solido_program_id().to_string());
+    idl
+}
+
 #[derive(Serialize)]
 pub struct DepositOutput {
     #[serde(serialize_with = "serialize_b58")]
@@ -657,6 +1467,13 @@ pub struct DepositOutput {
 
     /// Whether we had to create the associated stSOL account. False if one existed already.
     pub created_associated_st_sol_account: bool,
+
+    /// The exchange rate after the deposit.
+    ///
+    /// Deposits do not change the exchange rate, but `UpdateExchangeRate` may
+    /// have executed after we checked the rate, then this is the rate that
+    /// applied to our deposit.
+    pub exchange_rate: ExchangeRate,
 }
 
 impl fmt::Display for DepositOutput {
@@ -673,6 +1490,18 @@ impl fmt::Display for DepositOutput {
             "stSOL balance increase:  {}",
             self.st_sol_balance_increase
         )?;
+        writeln!(f, "\nExchange rate:")?;
+        writeln!(
+            f,
+            "  Computed in epoch: {}",
+            self.exchange_rate.computed_in_epoch
+        )?;
+        writeln!(f, "  SOL balance:       {}", self.exchange_rate.sol_balance)?;
+        writeln!(
+            f,
+            "  stSOL supply:      {}",
+            self.exchange_rate.st_sol_supply
+        )?;
         Ok(())
     }
 }
@@ -684,24 +1513,19 @@ pub fn command_deposit(
     let (recipient, created_recipient) = config.with_snapshot(|config| {
         let solido = config.client.get_solido(opts.solido_address())?;
 
-        let recipient = spl_associated_token_account::get_associated_token_address(
+        let mut instructions = Vec::new();
+        let (recipient, created_recipient) = config.client.get_or_create_associated_token_account(
+            &mut instructions,
+            &config.signer.pubkey(),
             &config.signer.pubkey(),
             &solido.st_sol_mint,
-        );
-
-        if !config.client.account_exists(&recipient)? {
-            let instr = spl_associated_token_account::create_associated_token_account(
-                &config.signer.pubkey(),
-                &config.signer.pubkey(),
-                &solido.st_sol_mint,
-            );
-
-            config.sign_and_send_transaction(&[instr], &[config.signer])?;
+        )?;
 
-            Ok((recipient, true))
-        } else {
-            Ok((recipient, false))
+        if created_recipient {
+            config.sign_and_send_transaction(&instructions, &[config.signer])?;
         }
+
+        Ok((recipient, created_recipient))
     })?;
 
     let (balance_before, exchange_rate) = config.with_snapshot(|config| {
@@ -714,6 +1538,8 @@ pub fn command_deposit(
             solido.get_reserve_account(opts.solido_program_id(), opts.solido_address())?;
         let mint_authority =
             solido.get_mint_authority(opts.solido_program_id(), opts.solido_address())?;
+        let (depositor_allowlist, _bump_seed) =
+            DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
 
         let instr = lido::instruction::deposit(
             opts.solido_program_id(),
@@ -724,6 +1550,8 @@ pub fn command_deposit(
                 st_sol_mint: solido.st_sol_mint,
                 mint_authority,
                 reserve_account: reserve,
+                spl_token: solido.st_sol_token_program,
+                depositor_allowlist,
             },
             *opts.amount_sol(),
         );
@@ -733,11 +1561,13 @@ pub fn command_deposit(
         Ok((balance_before, solido.exchange_rate))
     })?;
 
-    let balance_after = config.with_snapshot(|config| {
-        config
+    let (balance_after, exchange_rate_after) = config.with_snapshot(|config| {
+        let balance_after = config
             .client
             .get_spl_token_balance(&recipient)
-            .map(StLamports)
+            .map(StLamports)?;
+        let solido = config.client.get_solido(opts.solido_address())?;
+        Ok((balance_after, solido.exchange_rate))
     })?;
 
     let st_sol_balance_increase = StLamports(balance_after.0.saturating_sub(balance_before.0));
@@ -753,6 +1583,188 @@ pub fn command_deposit(
         expected_st_sol,
         st_sol_balance_increase,
         created_associated_st_sol_account: created_recipient,
+        exchange_rate: exchange_rate_after,
+    };
+    Ok(result)
+}
+
+#[derive(Serialize)]
+pub struct DepositStakeAccountOutput {
+    #[serde(serialize_with = "serialize_b58")]
+    pub stake_account: Pubkey,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pub validator_vote_account: Pubkey,
+
+    /// Balance of the stake account, all of which was deposited.
+    pub deposited_sol: Lamports,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pub recipient: Pubkey,
+
+    /// Amount of stSOL we expected to receive based on the exchange rate at the time of the deposit.
+    #[serde(rename = "expected_st_lamports")]
+    pub expected_st_sol: StLamports,
+
+    /// The difference in stSOL balance before and after our deposit.
+    #[serde(rename = "st_lamports_balance_increase")]
+    pub st_sol_balance_increase: StLamports,
+
+    /// Whether we had to create the associated stSOL account. False if one existed already.
+    pub created_associated_st_sol_account: bool,
+
+    /// The exchange rate after the deposit.
+    pub exchange_rate: ExchangeRate,
+}
+
+impl fmt::Display for DepositStakeAccountOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.created_associated_st_sol_account {
+            writeln!(f, "Created recipient stSOL account, it did not yet exist.")?;
+        } else {
+            writeln!(f, "Recipient stSOL account existed already before deposit.")?;
+        }
+        writeln!(f, "Deposited stake account: {}", self.stake_account)?;
+        writeln!(
+            f,
+            "Validator vote account:  {}",
+            self.validator_vote_account
+        )?;
+        writeln!(f, "Deposited SOL:           {}", self.deposited_sol)?;
+        writeln!(f, "Recipient stSOL account: {}", self.recipient)?;
+        writeln!(f, "Expected stSOL amount:   {}", self.expected_st_sol)?;
+        writeln!(
+            f,
+            "stSOL balance increase:  {}",
+            self.st_sol_balance_increase
+        )?;
+        writeln!(f, "\nExchange rate:")?;
+        writeln!(
+            f,
+            "  Computed in epoch: {}",
+            self.exchange_rate.computed_in_epoch
+        )?;
+        writeln!(f, "  SOL balance:       {}", self.exchange_rate.sol_balance)?;
+        writeln!(
+            f,
+            "  stSOL supply:      {}",
+            self.exchange_rate.st_sol_supply
+        )?;
+        Ok(())
+    }
+}
+
+pub fn command_deposit_stake_account(
+    config: &mut SnapshotClientConfig,
+    opts: &DepositStakeAccountOpts,
+) -> std::result::Result<DepositStakeAccountOutput, crate::error::Error> {
+    let (recipient, created_recipient) = config.with_snapshot(|config| {
+        let solido = config.client.get_solido(opts.solido_address())?;
+
+        let mut instructions = Vec::new();
+        let (recipient, created_recipient) = config.client.get_or_create_associated_token_account(
+            &mut instructions,
+            &config.signer.pubkey(),
+            &config.signer.pubkey(),
+            &solido.st_sol_mint,
+        )?;
+
+        if created_recipient {
+            config.sign_and_send_transaction(&instructions, &[config.signer])?;
+        }
+
+        Ok((recipient, created_recipient))
+    })?;
+
+    let (balance_before, validator_vote_account, deposited_sol, exchange_rate) = config
+        .with_snapshot(|config| {
+            let balance_before = config
+                .client
+                .get_spl_token_balance(&recipient)
+                .map(StLamports)?;
+            let solido = config.client.get_solido(opts.solido_address())?;
+            let mint_authority =
+                solido.get_mint_authority(opts.solido_program_id(), opts.solido_address())?;
+            let stake_authority =
+                solido.get_stake_authority(opts.solido_program_id(), opts.solido_address())?;
+            let reserve_account =
+                solido.get_reserve_account(opts.solido_program_id(), opts.solido_address())?;
+
+            let stake_account = config.client.get_account(opts.stake_account())?;
+            let deposited_sol = Lamports(stake_account.lamports);
+            let stake = deserialize_stake_account(&stake_account.data)?;
+            let validator_vote_account = stake.delegation.voter_pubkey;
+
+            let validator = solido
+                .validators
+                .get(&validator_vote_account)
+                .map_err(|err| {
+                    CliError::with_cause(
+                        "The stake account is not delegated to a Solido validator.",
+                        err,
+                    )
+                })?;
+            let (stake_account_merge_into, _) = derivation_cache::find_stake_account_address(
+                &validator.pubkey,
+                opts.solido_program_id(),
+                opts.solido_address(),
+                validator.entry.stake_seeds.begin,
+                StakeType::Stake,
+            );
+
+            let (depositor_allowlist, _bump_seed) =
+                DepositorAllowlist::find_address(opts.solido_program_id(), opts.solido_address());
+
+            let instr = lido::instruction::deposit_stake(
+                opts.solido_program_id(),
+                &lido::instruction::DepositStakeAccountsMeta {
+                    lido: *opts.solido_address(),
+                    user: config.signer.pubkey(),
+                    recipient,
+                    st_sol_mint: solido.st_sol_mint,
+                    mint_authority,
+                    validator_vote_account,
+                    stake_account: *opts.stake_account(),
+                    stake_account_merge_into,
+                    stake_authority,
+                    reserve_account,
+                    spl_token: solido.st_sol_token_program,
+                    depositor_allowlist,
+                },
+            );
+
+            config.sign_and_send_transaction(&[instr], &[config.signer])?;
+
+            Ok((
+                balance_before,
+                validator_vote_account,
+                deposited_sol,
+                solido.exchange_rate,
+            ))
+        })?;
+
+    let (balance_after, exchange_rate_after) = config.with_snapshot(|config| {
+        let balance_after = config
+            .client
+            .get_spl_token_balance(&recipient)
+            .map(StLamports)?;
+        let solido = config.client.get_solido(opts.solido_address())?;
+        Ok((balance_after, solido.exchange_rate))
+    })?;
+
+    let result = DepositStakeAccountOutput {
+        stake_account: *opts.stake_account(),
+        validator_vote_account,
+        deposited_sol,
+        recipient,
+        // See also `command_deposit`, if this is not `Ok`, the transaction
+        // should have failed.
+        expected_st_sol: exchange_rate
+            .exchange_sol(deposited_sol)
+            .unwrap_or(StLamports(0)),
+        st_sol_balance_increase: StLamports(balance_after.0.saturating_sub(balance_before.0)),
+        created_associated_st_sol_account: created_recipient,
+        exchange_rate: exchange_rate_after,
     };
     Ok(result)
 }
@@ -765,80 +1777,393 @@ pub struct WithdrawOutput {
     /// Amount of SOL that was withdrawn.
     pub withdrawn_sol: Lamports,
 
-    /// Newly created stake account, where the source stake account will be
-    /// split to.
-    #[serde(serialize_with = "serialize_b58")]
-    pub new_stake_account: Pubkey,
+    /// Newly created stake accounts, one per withdrawal, where the source
+    /// stake accounts were split to.
+    #[serde(serialize_with = "serialize_b58_slice")]
+    pub new_stake_accounts: Vec<Pubkey>,
+
+    /// Whether we deactivated the new stake accounts, see `--deactivate`.
+    pub deactivated: bool,
 }
 
 impl fmt::Display for WithdrawOutput {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Withdrawn from:          {}", self.from_token_address)?;
         writeln!(f, "Total SOL withdrawn:     {}", self.withdrawn_sol)?;
-        writeln!(f, "New stake account:       {}", self.new_stake_account)?;
+        for stake_account in &self.new_stake_accounts {
+            writeln!(f, "New stake account:       {}", stake_account)?;
+        }
+        if self.deactivated {
+            writeln!(
+                f,
+                "The stake accounts are deactivating. After the epoch ends, \
+                withdraw the SOL with `solana withdraw-stake`."
+            )?;
+        } else {
+            writeln!(
+                f,
+                "The stake accounts are still delegated. To get SOL, deactivate \
+                them with `solana deactivate-stake`, and withdraw the SOL with \
+                `solana withdraw-stake` after the epoch ends."
+            )?;
+        }
         Ok(())
     }
 }
 
+/// Convert the SOL amounts of withdraw steps to stSOL amounts that add up to `amount`.
+///
+/// Due to rounding down, the converted amounts can add up to slightly less
+/// than `amount`. The first steps that have room below their maximum take the
+/// difference. Converting back to SOL rounds down too, so no step exceeds its
+/// maximum. If no step has room, we withdraw a few stLamports less than `amount`.
+fn get_withdraw_amounts_st_sol(
+    exchange_rate: &ExchangeRate,
+    amount: StLamports,
+    steps: &[WithdrawStep],
+) -> lido::token::Result<Vec<StLamports>> {
+    let mut amounts = steps
+        .iter()
+        .map(|step| exchange_rate.exchange_sol(step.amount))
+        .collect::<lido::token::Result<Vec<StLamports>>>()?;
+    let total = amounts
+        .iter()
+        .cloned()
+        .sum::<lido::token::Result<StLamports>>()?;
+    let mut difference = (amount - total)?;
+    for (step, amount) in steps.iter().zip(amounts.iter_mut()) {
+        let room = (exchange_rate.exchange_sol(step.max_amount)? - *amount)?;
+        let extra = room.min(difference);
+        *amount = (*amount + extra)?;
+        difference = (difference - extra)?;
+    }
+    Ok(amounts)
+}
+
 pub fn command_withdraw(
     config: &mut SnapshotClientConfig,
     opts: &WithdrawOpts,
 ) -> std::result::Result<WithdrawOutput, crate::error::Error> {
-    let (st_sol_address, new_stake_account) = config.with_snapshot(|config| {
+    // Build all withdrawals from one snapshot, but send them after it:
+    // `with_snapshot` runs the closure again when it reads an account that was
+    // not in the snapshot, and that must not withdraw twice.
+    let (st_sol_address, withdrawals) = config.with_snapshot(|config| {
         let solido = config.client.get_solido(opts.solido_address())?;
 
-        let st_sol_address = spl_associated_token_account::get_associated_token_address(
-            &config.signer.pubkey(),
-            &solido.st_sol_mint,
-        );
+        let st_sol_address = config
+            .client
+            .get_associated_token_address(&config.signer.pubkey(), &solido.st_sol_mint)?;
 
         let stake_authority =
             solido.get_stake_authority(opts.solido_program_id(), opts.solido_address())?;
 
-        // Get heaviest validator.
-        let heaviest_validator = get_validator_to_withdraw(&solido.validators).map_err(|err| {
+        // `Withdraw` only accepts the validator with the most stake, so a
+        // large withdrawal may need to be split over multiple validators.
+        let sol_to_withdraw = solido
+            .exchange_rate
+            .exchange_st_sol(*opts.amount_st_sol())
+            .map_err(ProgramError::from)?;
+        let plan = get_withdraw_plan(&solido.validators, sol_to_withdraw).map_err(|err| {
             CliError::with_cause(
-                "The instance has no active validators to withdraw from.",
+                "The validators do not have enough stake to withdraw this amount.",
                 err,
             )
         })?;
 
-        let (stake_address, _bump_seed) = heaviest_validator.find_stake_account_address(
+        // `Withdraw` splits the validator's first stake account, and limits
+        // how much it splits off at once, so a validator's part of the plan
+        // may take multiple withdrawals.
+        let mut source_balances = vec![Lamports(0); solido.validators.len()];
+        for (validator_index, _) in &plan {
+            let validator = &solido.validators.entries[*validator_index];
+            let (stake_address, _bump_seed) = derivation_cache::find_stake_account_address(
+                &validator.pubkey,
+                opts.solido_program_id(),
+                opts.solido_address(),
+                validator.entry.stake_seeds.begin,
+                StakeType::Stake,
+            );
+            source_balances[*validator_index] =
+                Lamports(config.client.get_account(&stake_address)?.lamports());
+        }
+        let steps = get_withdraw_steps(&solido.validators, &source_balances, &plan).map_err(
+            |err| {
+                CliError::with_cause(
+                    "The stake accounts that Withdraw splits do not hold enough to withdraw this amount.",
+                    err,
+                )
+            },
+        )?;
+        let amounts_st_sol =
+            get_withdraw_amounts_st_sol(&solido.exchange_rate, *opts.amount_st_sol(), &steps)
+                .map_err(ProgramError::from)?;
+
+        let mut withdrawals = Vec::new();
+        for (step, amount_st_sol) in steps.iter().zip(amounts_st_sol) {
+            let validator = &solido.validators.entries[step.validator_index];
+            let (stake_address, _bump_seed) = derivation_cache::find_stake_account_address(
+                &validator.pubkey,
+                opts.solido_program_id(),
+                opts.solido_address(),
+                validator.entry.stake_seeds.begin,
+                StakeType::Stake,
+            );
+
+            let destination_stake_account = Keypair::new();
+
+            let instr = lido::instruction::withdraw(
+                opts.solido_program_id(),
+                &lido::instruction::WithdrawAccountsMeta {
+                    lido: *opts.solido_address(),
+                    st_sol_mint: solido.st_sol_mint,
+                    st_sol_account_owner: config.signer.pubkey(),
+                    st_sol_account: st_sol_address,
+                    validator_vote_account: validator.pubkey,
+                    source_stake_account: stake_address,
+                    destination_stake_account: destination_stake_account.pubkey(),
+                    stake_authority,
+                    spl_token: solido.st_sol_token_program,
+                },
+                amount_st_sol,
+            );
+            let mut instructions = vec![instr];
+            if *opts.deactivate() {
+                // The withdraw made us the stake authority of the new stake
+                // account, so we can deactivate it in the same transaction.
+                instructions.push(solana_program::stake::instruction::deactivate_stake(
+                    &destination_stake_account.pubkey(),
+                    &config.signer.pubkey(),
+                ));
+            }
+            withdrawals.push((instructions, destination_stake_account));
+        }
+
+        Ok((st_sol_address, withdrawals))
+    })?;
+
+    // Every withdrawal goes in its own transaction, they are executed in the
+    // order of the steps, which the program requires. If one fails, the
+    // earlier ones did go through, so we report those.
+    let mut new_stake_accounts = Vec::new();
+    for (instructions, destination_stake_account) in &withdrawals {
+        let result = config.with_snapshot(|config| {
+            config.sign_and_send_transaction(
+                instructions,
+                &[config.signer, destination_stake_account],
+            )
+        });
+        if let Err(err) = result {
+            if new_stake_accounts.is_empty() {
+                return Err(err);
+            }
+            let new_stake_accounts: Vec<String> =
+                new_stake_accounts.iter().map(Pubkey::to_string).collect();
+            return Err(CliError::with_cause(
+                "A withdrawal failed after earlier withdrawals succeeded. \
+                The new stake accounts of those hold the SOL withdrawn so far.",
+                format!(
+                    "{} of {} withdrawals succeeded, new stake accounts: {}. {}",
+                    new_stake_accounts.len(),
+                    withdrawals.len(),
+                    new_stake_accounts.join(", "),
+                    err.summary(),
+                ),
+            ));
+        }
+        new_stake_accounts.push(destination_stake_account.pubkey());
+    }
+
+    let stake_sol = config.with_snapshot(|config| {
+        let mut total = Lamports(0);
+        for stake_account in &new_stake_accounts {
+            let stake_account = config.client.get_account(stake_account)?;
+            total = (total + Lamports(stake_account.lamports()))
+                .expect("Does not overflow, the stake accounts hold less than the SOL supply.");
+        }
+        Ok(total)
+    })?;
+    let result = WithdrawOutput {
+        from_token_address: st_sol_address,
+        withdrawn_sol: stake_sol,
+        new_stake_accounts,
+        deactivated: *opts.deactivate(),
+    };
+    Ok(result)
+}
+
+#[derive(Serialize)]
+pub struct CreateWithdrawTicketOutput {
+    #[serde(serialize_with = "serialize_b58")]
+    pub from_token_address: Pubkey,
+
+    /// The withdraw ticket account.
+    #[serde(serialize_with = "serialize_b58")]
+    pub withdraw_ticket: Pubkey,
+
+    /// Id of the ticket, needed to claim it.
+    pub ticket_id: u64,
+
+    /// Amount of stSOL that was burned.
+    pub st_sol_burned: StLamports,
+
+    /// Amount of SOL that the ticket can claim once it is funded.
+    pub amount_sol: Lamports,
+
+    /// SOL owed to earlier tickets, that will be funded before this ticket.
+    pub queue_position_sol: Lamports,
+}
+
+impl fmt::Display for CreateWithdrawTicketOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Burned from:             {}", self.from_token_address)?;
+        writeln!(f, "Total stSOL burned:      {}", self.st_sol_burned)?;
+        writeln!(f, "Withdraw ticket:         {}", self.withdraw_ticket)?;
+        writeln!(f, "Ticket id:               {}", self.ticket_id)?;
+        writeln!(f, "SOL to claim:            {}", self.amount_sol)?;
+        writeln!(f, "SOL queued before it:    {}", self.queue_position_sol)?;
+        writeln!(
+            f,
+            "After the maintainers fund the ticket, claim the SOL with \
+            `solido claim-withdraw-ticket --ticket-id {}`.",
+            self.ticket_id
+        )?;
+        Ok(())
+    }
+}
+
+/// Read a withdraw ticket, or fail if it does not exist.
+fn get_withdraw_ticket(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    address: Pubkey,
+) -> Result<WithdrawTicket> {
+    let ticket = get_program_derived_state(
+        config,
+        solido_program_id,
+        address,
+        "Failed to deserialize withdraw ticket.",
+    )?;
+    match ticket {
+        Some(ticket) => Ok(ticket),
+        None => Err(CliError::new(
+            "The withdraw ticket does not exist, or it was already claimed.",
+        )
+        .into()),
+    }
+}
+
+pub fn command_create_withdraw_ticket(
+    config: &mut SnapshotClientConfig,
+    opts: &CreateWithdrawTicketOpts,
+) -> std::result::Result<CreateWithdrawTicketOutput, crate::error::Error> {
+    let (st_sol_address, withdraw_ticket) = config.with_snapshot(|config| {
+        let solido = config.client.get_solido(opts.solido_address())?;
+
+        let st_sol_address = config
+            .client
+            .get_associated_token_address(&config.signer.pubkey(), &solido.st_sol_mint)?;
+
+        // The program creates the ticket for the next id in the queue.
+        let (withdraw_ticket, _bump_seed) = WithdrawTicket::find_address(
             opts.solido_program_id(),
             opts.solido_address(),
-            heaviest_validator.entry.stake_seeds.begin,
-            StakeType::Stake,
+            solido.withdraw_queue.next_ticket_id,
         );
 
-        let destination_stake_account = Keypair::new();
-
-        let instr = lido::instruction::withdraw(
+        let instr = lido::instruction::create_withdraw_ticket(
             opts.solido_program_id(),
-            &lido::instruction::WithdrawAccountsMeta {
+            &lido::instruction::CreateWithdrawTicketAccountsMeta {
                 lido: *opts.solido_address(),
-                st_sol_mint: solido.st_sol_mint,
                 st_sol_account_owner: config.signer.pubkey(),
                 st_sol_account: st_sol_address,
-                validator_vote_account: heaviest_validator.pubkey,
-                source_stake_account: stake_address,
-                destination_stake_account: destination_stake_account.pubkey(),
-                stake_authority,
+                st_sol_mint: solido.st_sol_mint,
+                withdraw_ticket,
+                spl_token: solido.st_sol_token_program,
             },
             *opts.amount_st_sol(),
         );
-        config.sign_and_send_transaction(&[instr], &[config.signer, &destination_stake_account])?;
+        config.sign_and_send_transaction(&[instr], &[config.signer])?;
 
-        Ok((st_sol_address, destination_stake_account))
+        Ok((st_sol_address, withdraw_ticket))
     })?;
 
-    let stake_sol = config.with_snapshot(|config| {
-        let stake_account = config.client.get_account(&new_stake_account.pubkey())?;
-        Ok(Lamports(stake_account.lamports()))
+    let ticket = config.with_snapshot(|config| {
+        get_withdraw_ticket(config, opts.solido_program_id(), withdraw_ticket)
     })?;
-    let result = WithdrawOutput {
+    let result = CreateWithdrawTicketOutput {
         from_token_address: st_sol_address,
-        withdrawn_sol: stake_sol,
-        new_stake_account: new_stake_account.pubkey(),
+        withdraw_ticket,
+        ticket_id: ticket.ticket_id,
+        st_sol_burned: ticket.st_sol_burned,
+        amount_sol: ticket.amount,
+        queue_position_sol: ticket.queue_position,
     };
     Ok(result)
 }
+
+#[derive(Serialize)]
+pub struct ClaimWithdrawTicketOutput {
+    /// The withdraw ticket account, closed by the claim.
+    #[serde(serialize_with = "serialize_b58")]
+    pub withdraw_ticket: Pubkey,
+
+    /// Account that received the SOL.
+    #[serde(serialize_with = "serialize_b58")]
+    pub recipient: Pubkey,
+
+    /// Amount of SOL that was claimed, including the rent of the ticket.
+    pub claimed_sol: Lamports,
+}
+
+impl fmt::Display for ClaimWithdrawTicketOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Withdraw ticket:         {}", self.withdraw_ticket)?;
+        writeln!(f, "Recipient:               {}", self.recipient)?;
+        writeln!(f, "Total SOL claimed:       {}", self.claimed_sol)?;
+        Ok(())
+    }
+}
+
+pub fn command_claim_withdraw_ticket(
+    config: &mut SnapshotClientConfig,
+    opts: &ClaimWithdrawTicketOpts,
+) -> std::result::Result<ClaimWithdrawTicketOutput, crate::error::Error> {
+    let result = config.with_snapshot(|config| {
+        let (withdraw_ticket, _bump_seed) = WithdrawTicket::find_address(
+            opts.solido_program_id(),
+            opts.solido_address(),
+            *opts.ticket_id(),
+        );
+        let ticket = get_withdraw_ticket(config, opts.solido_program_id(), withdraw_ticket)?;
+        if !ticket.is_funded {
+            return Err(CliError::new(
+                "The withdraw ticket is not funded yet, try again after the maintainers fund it.",
+            )
+            .into());
+        }
+        let claimed_sol = Lamports(config.client.get_account(&withdraw_ticket)?.lamports);
+
+        let recipient = match *opts.recipient() {
+            recipient if recipient == Pubkey::default() => config.signer.pubkey(),
+            recipient => recipient,
+        };
+
+        let instr = lido::instruction::claim_withdraw_ticket(
+            opts.solido_program_id(),
+            &lido::instruction::ClaimWithdrawTicketAccountsMeta {
+                lido: *opts.solido_address(),
+                ticket_owner: config.signer.pubkey(),
+                withdraw_ticket,
+                recipient,
+            },
+        );
+        config.sign_and_send_transaction(&[instr], &[config.signer])?;
+
+        Ok(ClaimWithdrawTicketOutput {
+            withdraw_ticket,
+            recipient,
+            claimed_sol,
+        })
+    })?;
+    Ok(result)
+}
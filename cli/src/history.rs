@@ -0,0 +1,307 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Long-term history of the states that the maintenance daemon observed, and
+//! the maintenance that it performed.
+//!
+//! Prometheus only retains metrics for a limited time, and at a coarse
+//! resolution. To be able to answer questions about the past, the daemon can
+//! additionally record every observation into a Postgres database. This requires
+//! the `postgres` feature.
+
+use crate::maintenance::{MaintenanceOutput, SolidoState};
+
+/// A place where the daemon records what it observed and did.
+///
+/// Failing to record history should not stop the daemon from performing
+/// maintenance, so implementations report errors themselves.
+pub trait History {
+    /// Record the state that the daemon observed in one iteration.
+    fn record_state(&mut self, state: &SolidoState);
+
+    /// Record maintenance that the daemon performed, based on `state`.
+    fn record_maintenance(&mut self, state: &SolidoState, maintenance_output: &MaintenanceOutput);
+}
+
+/// History that does not record anything.
+pub struct NoHistory;
+
+impl History for NoHistory {
+    fn record_state(&mut self, _state: &SolidoState) {}
+    fn record_maintenance(&mut self, _state: &SolidoState, _output: &MaintenanceOutput) {}
+}
+
+/// Connect to the history database, or record no history if `postgres_url` is empty.
+///
+/// Exits the process if the database is configured but unreachable, so a
+/// misconfiguration does not go unnoticed.
+pub fn connect(postgres_url: &str) -> Box<dyn History> {
+    if postgres_url.is_empty() {
+        return Box::new(NoHistory);
+    }
+
+    connect_postgres(postgres_url)
+}
+
+#[cfg(feature = "postgres")]
+fn connect_postgres(postgres_url: &str) -> Box<dyn History> {
+    use crate::logging::Event;
+
+    match postgres_history::PostgresHistory::connect(postgres_url) {
+        Ok(history) => {
+            Event::info("history_connected", "Recording history to Postgres.").emit();
+            Box::new(history)
+        }
+        Err(err) => {
+            eprintln!("Error: {}\nFailed to connect to Postgres.", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+fn connect_postgres(_postgres_url: &str) -> Box<dyn History> {
+    eprintln!(
+        "Error: --postgres-url was provided, but this build of solido does not \
+        support Postgres. Rebuild with '--features postgres'."
+    );
+    std::process::exit(1);
+}
+
+#[cfg(feature = "postgres")]
+mod postgres_history {
+    use postgres::{Client, NoTls};
+    use serde_json::Value;
+
+    use lido::stake_account::StakeBalance;
+
+    use super::History;
+    use crate::logging::Event;
+    use crate::maintenance::{MaintenanceOutput, SolidoState};
+
+    /// Schema migrations, in order. Migration `i` brings the schema to version `i + 1`.
+    ///
+    /// Never edit a migration that has been released, add a new one instead.
+    const MIGRATIONS: &[&str] = &[r#"
+        CREATE TABLE solido_state (
+            id                                      bigserial PRIMARY KEY,
+            produced_at                             timestamptz NOT NULL,
+            solido_address                          text NOT NULL,
+            slot                                    bigint NOT NULL,
+            epoch                                   bigint NOT NULL,
+            reserve_lamports                        bigint NOT NULL,
+            stake_inactive_lamports                 bigint NOT NULL,
+            stake_activating_lamports               bigint NOT NULL,
+            stake_active_lamports                   bigint NOT NULL,
+            stake_deactivating_lamports             bigint NOT NULL,
+            st_sol_supply_st_lamports               bigint NOT NULL,
+            exchange_rate_computed_in_epoch         bigint NOT NULL,
+            exchange_rate_st_sol_supply_st_lamports bigint NOT NULL,
+            exchange_rate_sol_balance_lamports      bigint NOT NULL,
+            num_validators                          integer NOT NULL,
+            maintainer_address                      text NOT NULL,
+            maintainer_balance_lamports             bigint NOT NULL
+        );
+        CREATE INDEX solido_state_address_produced_at
+            ON solido_state (solido_address, produced_at);
+
+        CREATE TABLE solido_maintenance (
+            id                bigserial PRIMARY KEY,
+            observed_at       timestamptz NOT NULL,
+            solido_address    text NOT NULL,
+            slot              bigint NOT NULL,
+            kind              text NOT NULL,
+            details           jsonb NOT NULL
+        );
+        CREATE INDEX solido_maintenance_address_observed_at
+            ON solido_maintenance (solido_address, observed_at);
+    "#];
+
+    /// Postgres stores only signed integers. Amounts in lamports fit comfortably,
+    /// but saturate rather than wrap in case a value is ever out of range.
+    fn to_i64(x: u64) -> i64 {
+        if x > i64::MAX as u64 {
+            i64::MAX
+        } else {
+            x as i64
+        }
+    }
+
+    /// Return the name of the variant of a serialized `MaintenanceOutput`.
+    fn maintenance_kind(details: &Value) -> &str {
+        match details {
+            // Unit variants serialize as just their name.
+            Value::String(name) => name,
+            // Other variants serialize as an object with the name as only key.
+            Value::Object(map) => map.keys().next().map(|k| &k[..]).unwrap_or("Unknown"),
+            _ => "Unknown",
+        }
+    }
+
+    pub struct PostgresHistory {
+        client: Client,
+    }
+
+    impl PostgresHistory {
+        /// Connect, and bring the schema up to date.
+        pub fn connect(postgres_url: &str) -> Result<PostgresHistory, postgres::Error> {
+            let mut client = Client::connect(postgres_url, NoTls)?;
+            migrate(&mut client)?;
+            Ok(PostgresHistory { client })
+        }
+
+        fn insert_state(&mut self, state: &SolidoState) -> Result<(), postgres::Error> {
+            let stake_balance: StakeBalance = state
+                .validator_stake_accounts
+                .iter()
+                .flatten()
+                .map(|(_addr, stake_account)| stake_account.balance)
+                .sum();
+            let exchange_rate = &state.solido.exchange_rate;
+            self.client.execute(
+                "INSERT INTO solido_state (
+                    produced_at, solido_address, slot, epoch, reserve_lamports,
+                    stake_inactive_lamports, stake_activating_lamports,
+                    stake_active_lamports, stake_deactivating_lamports,
+                    st_sol_supply_st_lamports, exchange_rate_computed_in_epoch,
+                    exchange_rate_st_sol_supply_st_lamports,
+                    exchange_rate_sol_balance_lamports, num_validators,
+                    maintainer_address, maintainer_balance_lamports
+                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+                &[
+                    &state.produced_at,
+                    &state.solido_address.to_string(),
+                    &to_i64(state.clock.slot),
+                    &to_i64(state.clock.epoch),
+                    &to_i64(state.get_effective_reserve().0),
+                    &to_i64(stake_balance.inactive.0),
+                    &to_i64(stake_balance.activating.0),
+                    &to_i64(stake_balance.active.0),
+                    &to_i64(stake_balance.deactivating.0),
+                    &to_i64(state.st_sol_mint.supply),
+                    &to_i64(exchange_rate.computed_in_epoch),
+                    &to_i64(exchange_rate.st_sol_supply.0),
+                    &to_i64(exchange_rate.sol_balance.0),
+                    &(state.solido.validators.len() as i32),
+                    &state.maintainer_address.to_string(),
+                    &to_i64(state.maintainer_account.lamports),
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn insert_maintenance(
+            &mut self,
+            state: &SolidoState,
+            maintenance_output: &MaintenanceOutput,
+        ) -> Result<(), postgres::Error> {
+            let details = serde_json::to_value(maintenance_output)
+                .expect("MaintenanceOutput can always be serialized.");
+            self.client.execute(
+                "INSERT INTO solido_maintenance (observed_at, solido_address, slot, kind, details)
+                VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &state.produced_at,
+                    &state.solido_address.to_string(),
+                    &to_i64(state.clock.slot),
+                    &maintenance_kind(&details),
+                    &details,
+                ],
+            )?;
+            Ok(())
+        }
+    }
+
+    /// Apply all migrations that have not yet been applied.
+    ///
+    /// Multiple maintainers may share a database, so this takes a lock to ensure
+    /// that only one of them migrates at a time.
+    fn migrate(client: &mut Client) -> Result<(), postgres::Error> {
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS solido_schema_version (version integer NOT NULL)",
+        )?;
+        let mut transaction = client.transaction()?;
+        transaction.batch_execute("LOCK TABLE solido_schema_version IN EXCLUSIVE MODE")?;
+        let current_version: i32 =
+            match transaction.query_opt("SELECT version FROM solido_schema_version", &[])? {
+                Some(row) => row.get(0),
+                None => {
+                    transaction.execute("INSERT INTO solido_schema_version VALUES (0)", &[])?;
+                    0
+                }
+            };
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(current_version as usize) {
+            Event::info(
+                "history_schema_migrated",
+                format!("Migrating history schema to version {}.", i + 1),
+            )
+            .field("version", i + 1)
+            .emit();
+            transaction.batch_execute(migration)?;
+        }
+
+        transaction.execute(
+            "UPDATE solido_schema_version SET version = $1",
+            &[&(MIGRATIONS.len() as i32)],
+        )?;
+        transaction.commit()
+    }
+
+    impl History for PostgresHistory {
+        fn record_state(&mut self, state: &SolidoState) {
+            if let Err(err) = self.insert_state(state) {
+                Event::warning(
+                    "history_record_failed",
+                    "Error while recording state in Postgres.",
+                )
+                .field("record", "state")
+                .field("error", err.to_string())
+                .emit();
+            }
+        }
+
+        fn record_maintenance(
+            &mut self,
+            state: &SolidoState,
+            maintenance_output: &MaintenanceOutput,
+        ) {
+            if let Err(err) = self.insert_maintenance(state, maintenance_output) {
+                Event::warning(
+                    "history_record_failed",
+                    "Error while recording maintenance in Postgres.",
+                )
+                .field("record", "maintenance")
+                .field("error", err.to_string())
+                .emit();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        use lido::token::Lamports;
+        use solana_sdk::pubkey::Pubkey;
+
+        #[test]
+        fn maintenance_kind_is_variant_name() {
+            let unit = serde_json::to_value(MaintenanceOutput::UpdateExchangeRate).unwrap();
+            assert_eq!(maintenance_kind(&unit), "UpdateExchangeRate");
+
+            let with_fields = serde_json::to_value(MaintenanceOutput::CollectValidatorFee {
+                validator_vote_account: Pubkey::new_unique(),
+                fee_rewards: Lamports(1),
+            })
+            .unwrap();
+            assert_eq!(maintenance_kind(&with_fields), "CollectValidatorFee");
+        }
+
+        #[test]
+        fn to_i64_saturates() {
+            assert_eq!(to_i64(42), 42);
+            assert_eq!(to_i64(u64::MAX), i64::MAX);
+        }
+    }
+}
@@ -0,0 +1,707 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Indexing of deposits, withdrawals, and fee distributions, for `solido index`.
+//!
+//! We page through the transactions that involve the Solido instance with
+//! `getSignaturesForAddress`, read each of them with `getTransaction`, and
+//! decode the Solido instructions in it. Amounts that an instruction does not
+//! carry itself, such as the stSOL minted for a deposit, we take from the
+//! balance changes that the node recorded for the transaction.
+//!
+//! The events go to a CSV file, or to an SQLite database with the `sqlite`
+//! feature. Both are append-only, after a restart we continue after the
+//! transaction of the last event in the file. We only index top-level
+//! instructions, deposits that other programs make through a cross-program
+//! invocation are not included.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use borsh::BorshDeserialize;
+use solana_sdk::clock::{Slot, UnixTimestamp};
+use solana_sdk::pubkey::Pubkey;
+
+use lido::instruction::LidoInstruction;
+use lido::token::{Lamports, StLamports};
+
+use crate::config::IndexOpts;
+use crate::error::{Abort, AsPrettyError, CliError, Error};
+use crate::rpc::{SignatureInfo, TokenBalance, TransactionInfo};
+use crate::snapshot::Result;
+use crate::{SnapshotClientConfig, SnapshotConfig};
+
+/// Number of signatures per `getSignaturesForAddress` call, the maximum that nodes allow.
+const SIGNATURES_PER_PAGE: usize = 1000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IndexFormat {
+    /// One event per line, with a header line.
+    Csv,
+
+    /// An SQLite database with a `solido_events` table.
+    Sqlite,
+}
+
+impl FromStr for IndexFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<IndexFormat, &'static str> {
+        match s {
+            "csv" => Ok(IndexFormat::Csv),
+            "sqlite" => Ok(IndexFormat::Sqlite),
+            _ => Err("Invalid output format, expected 'csv' or 'sqlite'."),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    /// A deposit of SOL.
+    Deposit,
+
+    /// A deposit of an active stake account.
+    DepositStake,
+
+    /// A withdrawal of stSOL, into a stake account.
+    Withdraw,
+
+    /// stSOL minted to the treasury when collecting a validator's rewards.
+    TreasuryFee,
+
+    /// stSOL minted to the developer when collecting a validator's rewards.
+    DeveloperFee,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Deposit => "deposit",
+            EventKind::DepositStake => "deposit_stake",
+            EventKind::Withdraw => "withdraw",
+            EventKind::TreasuryFee => "treasury_fee",
+            EventKind::DeveloperFee => "developer_fee",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// Signature of the transaction that contains the instruction.
+    pub signature: String,
+    pub slot: Slot,
+    pub block_time: Option<UnixTimestamp>,
+    pub kind: EventKind,
+
+    /// The account that deposited or withdrew, or for fees, the validator's vote account.
+    pub owner: Pubkey,
+
+    /// The stSOL account that received or paid the stSOL.
+    pub st_sol_account: Pubkey,
+
+    /// SOL that went in or out, if known. For fees there is none.
+    pub amount_sol: Option<Lamports>,
+
+    /// stSOL that was minted or burned, if the node recorded token balances.
+    pub amount_st_sol: Option<StLamports>,
+}
+
+impl Event {
+    /// The SOL per stSOL of the event, when we know both amounts.
+    pub fn exchange_rate(&self) -> Option<f64> {
+        match (self.amount_sol, self.amount_st_sol) {
+            (Some(sol), Some(st_sol)) if st_sol.0 > 0 => Some(sol.0 as f64 / st_sol.0 as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Balances of the accounts of one transaction.
+struct Balances<'a> {
+    transaction: &'a TransactionInfo,
+}
+
+impl<'a> Balances<'a> {
+    fn lamports_before(&self, account_index: usize) -> Option<u64> {
+        self.transaction.pre_balances.get(account_index).cloned()
+    }
+
+    fn lamports_after(&self, account_index: usize) -> Option<u64> {
+        self.transaction.post_balances.get(account_index).cloned()
+    }
+
+    /// Tokens that the token account gained, or `None` if the node did not record it.
+    fn tokens_gained(&self, account_index: usize) -> Option<u64> {
+        let find = |balances: &[TokenBalance]| {
+            balances
+                .iter()
+                .find(|balance| balance.account_index == account_index)
+                .map(|balance| balance.amount)
+        };
+        let after = find(&self.transaction.post_token_balances)?;
+        // The account may be created in the same transaction, then it has no
+        // balance before.
+        let before = find(&self.transaction.pre_token_balances).unwrap_or(0);
+        Some(after.saturating_sub(before))
+    }
+}
+
+/// Return the events of the Solido instructions in a confirmed transaction.
+pub fn parse_events(
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    signature: &str,
+    transaction: &TransactionInfo,
+) -> Vec<Event> {
+    // A failed transaction did not change anything.
+    if transaction.err.is_some() {
+        return Vec::new();
+    }
+
+    let message = &transaction.transaction.message;
+    let balances = Balances { transaction };
+    let mut events = Vec::new();
+
+    for instruction in &message.instructions {
+        let key_index = |i: usize| instruction.accounts.get(i).map(|&index| index as usize);
+        let key =
+            |i: usize| key_index(i).and_then(|index| message.account_keys.get(index).cloned());
+        let program_id = message
+            .account_keys
+            .get(instruction.program_id_index as usize);
+        if program_id != Some(solido_program_id) || key(0) != Some(*solido_address) {
+            continue;
+        }
+        let lido_instruction = match LidoInstruction::try_from_slice(&instruction.data) {
+            Ok(lido_instruction) => lido_instruction,
+            // Not an instruction of this version of the program, it did not
+            // deposit or withdraw anything that we know of.
+            Err(..) => continue,
+        };
+
+        let mut push = |kind, owner: usize, st_sol_account: usize, amount_sol, amount_st_sol| {
+            if let (Some(owner), Some(st_sol_account)) = (key(owner), key(st_sol_account)) {
+                events.push(Event {
+                    signature: signature.to_string(),
+                    slot: transaction.slot,
+                    block_time: transaction.block_time,
+                    kind,
+                    owner,
+                    st_sol_account,
+                    amount_sol,
+                    amount_st_sol,
+                });
+            }
+        };
+        let st_sol_gained = |i: usize| {
+            key_index(i)
+                .and_then(|index| balances.tokens_gained(index))
+                .map(StLamports)
+        };
+
+        // The account positions below follow the `*AccountsMeta` structs in
+        // `lido::instruction`.
+        match lido_instruction {
+            LidoInstruction::Deposit { amount } => {
+                push(EventKind::Deposit, 1, 2, Some(amount), st_sol_gained(2));
+            }
+            LidoInstruction::DepositStake => {
+                // Solido takes the stake account with its full balance.
+                let amount = key_index(6)
+                    .and_then(|index| balances.lamports_before(index))
+                    .map(Lamports);
+                push(EventKind::DepositStake, 1, 2, amount, st_sol_gained(2));
+            }
+            LidoInstruction::Withdraw { amount } => {
+                // The destination stake account is new, it holds what was withdrawn.
+                let amount_sol = key_index(6)
+                    .and_then(|index| balances.lamports_after(index))
+                    .map(Lamports);
+                push(EventKind::Withdraw, 1, 2, amount_sol, Some(amount));
+            }
+            LidoInstruction::CollectValidatorFee => {
+                push(EventKind::TreasuryFee, 1, 4, None, st_sol_gained(4));
+                push(EventKind::DeveloperFee, 1, 5, None, st_sol_gained(5));
+            }
+            _ => {}
+        }
+    }
+
+    events
+}
+
+/// A place where the indexer writes events to.
+pub trait EventSink {
+    /// Return the signature of the transaction of the last event written, if any.
+    fn last_signature(&self) -> std::result::Result<Option<String>, Error>;
+
+    /// Write the events of one transaction.
+    fn write_events(&mut self, events: &[Event]) -> std::result::Result<(), Error>;
+}
+
+const CSV_HEADER: &str = "signature,slot,block_time,kind,owner,st_sol_account,\
+    amount_sol_lamports,amount_st_sol_st_lamports,exchange_rate";
+
+/// Writes events to a CSV file, one line per event.
+pub struct CsvSink {
+    file: File,
+    last_signature: Option<String>,
+}
+
+impl CsvSink {
+    /// Open the file for appending, or create it with a header if it does not exist.
+    pub fn open(path: &Path) -> std::result::Result<CsvSink, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if contents.is_empty() {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        let last_signature = contents
+            .lines()
+            .skip(1)
+            .filter(|line| !line.is_empty())
+            .last()
+            .and_then(|line| line.split(',').next())
+            .map(|signature| signature.to_string());
+        Ok(CsvSink {
+            file,
+            last_signature,
+        })
+    }
+}
+
+fn format_optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+impl EventSink for CsvSink {
+    fn last_signature(&self) -> std::result::Result<Option<String>, Error> {
+        Ok(self.last_signature.clone())
+    }
+
+    fn write_events(&mut self, events: &[Event]) -> std::result::Result<(), Error> {
+        // None of the fields contain commas or quotes, so they need no escaping.
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                event.signature,
+                event.slot,
+                format_optional(event.block_time),
+                event.kind.as_str(),
+                event.owner,
+                event.st_sol_account,
+                format_optional(event.amount_sol.map(|amount| amount.0)),
+                format_optional(event.amount_st_sol.map(|amount| amount.0)),
+                format_optional(event.exchange_rate()),
+            ));
+        }
+        // Write the events of a transaction at once, so an interruption is
+        // unlikely to leave half a transaction in the file.
+        self.file.write_all(lines.as_bytes())?;
+        self.file.flush()?;
+        if let Some(event) = events.last() {
+            self.last_signature = Some(event.signature.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &Path) -> std::result::Result<Box<dyn EventSink>, Error> {
+    Ok(Box::new(sqlite_sink::SqliteSink::open(path)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite(_path: &Path) -> std::result::Result<Box<dyn EventSink>, Error> {
+    Err(CliError::new(
+        "This build of solido does not support SQLite. Rebuild with '--features sqlite'.",
+    ))
+}
+
+pub fn open_sink(
+    format: IndexFormat,
+    path: &Path,
+) -> std::result::Result<Box<dyn EventSink>, Error> {
+    match format {
+        IndexFormat::Csv => Ok(Box::new(CsvSink::open(path)?)),
+        IndexFormat::Sqlite => open_sqlite(path),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite_sink {
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use super::{Event, EventSink};
+    use crate::error::{CliError, Error};
+
+    const SCHEMA: &str = r#"
+        CREATE TABLE IF NOT EXISTS solido_events (
+            id                        INTEGER PRIMARY KEY,
+            signature                 TEXT NOT NULL,
+            slot                      INTEGER NOT NULL,
+            block_time                INTEGER,
+            kind                      TEXT NOT NULL,
+            owner                     TEXT NOT NULL,
+            st_sol_account            TEXT NOT NULL,
+            amount_sol_lamports       INTEGER,
+            amount_st_sol_st_lamports INTEGER,
+            exchange_rate             REAL
+        );
+        CREATE INDEX IF NOT EXISTS solido_events_slot ON solido_events (slot);
+        CREATE INDEX IF NOT EXISTS solido_events_owner ON solido_events (owner);
+    "#;
+
+    /// SQLite stores only signed integers. Amounts in lamports fit comfortably,
+    /// but saturate rather than wrap in case a value is ever out of range.
+    fn to_i64(x: u64) -> i64 {
+        i64::try_from(x).unwrap_or(i64::MAX)
+    }
+
+    pub struct SqliteSink {
+        connection: Connection,
+    }
+
+    impl SqliteSink {
+        pub fn open(path: &Path) -> Result<SqliteSink, Error> {
+            let connection = Connection::open(path)
+                .map_err(|err| CliError::with_cause("Failed to open the SQLite database.", err))?;
+            connection
+                .execute_batch(SCHEMA)
+                .map_err(|err| CliError::with_cause("Failed to create the tables.", err))?;
+            Ok(SqliteSink { connection })
+        }
+    }
+
+    impl EventSink for SqliteSink {
+        fn last_signature(&self) -> Result<Option<String>, Error> {
+            self.connection
+                .query_row(
+                    "SELECT signature FROM solido_events ORDER BY id DESC LIMIT 1",
+                    params![],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|err| CliError::with_cause("Failed to read the last event.", err))
+        }
+
+        fn write_events(&mut self, events: &[Event]) -> Result<(), Error> {
+            let write = |connection: &mut Connection| -> rusqlite::Result<()> {
+                // One database transaction per Solana transaction, so we never
+                // record only part of it.
+                let transaction = connection.transaction()?;
+                for event in events {
+                    transaction.execute(
+                        "INSERT INTO solido_events (signature, slot, block_time, kind, owner, \
+                        st_sol_account, amount_sol_lamports, amount_st_sol_st_lamports, \
+                        exchange_rate) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![
+                            event.signature,
+                            to_i64(event.slot),
+                            event.block_time,
+                            event.kind.as_str(),
+                            event.owner.to_string(),
+                            event.st_sol_account.to_string(),
+                            event.amount_sol.map(|amount| to_i64(amount.0)),
+                            event.amount_st_sol.map(|amount| to_i64(amount.0)),
+                            event.exchange_rate(),
+                        ],
+                    )?;
+                }
+                transaction.commit()
+            };
+            write(&mut self.connection)
+                .map_err(|err| CliError::with_cause("Failed to write the events.", err))
+        }
+    }
+}
+
+/// Return the signatures of the transactions after `until`, oldest first.
+fn get_new_signatures(
+    config: &mut SnapshotConfig,
+    solido_address: &Pubkey,
+    until: Option<&str>,
+) -> Result<Vec<SignatureInfo>> {
+    let mut signatures: Vec<SignatureInfo> = Vec::new();
+    loop {
+        let before = signatures.last().map(|info| info.signature.clone());
+        let page = config.client.get_signatures_for_address(
+            solido_address,
+            before.as_deref(),
+            until,
+            SIGNATURES_PER_PAGE,
+        )?;
+        let is_last_page = page.len() < SIGNATURES_PER_PAGE;
+        signatures.extend(page);
+        if is_last_page {
+            break;
+        }
+    }
+    // The node returns the newest first, but we write events in the order
+    // in which they happened.
+    signatures.reverse();
+    Ok(signatures)
+}
+
+/// Index the transactions after `last_signature`, and return the number of events written.
+///
+/// Updates `last_signature` after every transaction, so a failure halfway
+/// does not cause us to index a transaction twice.
+pub fn index_new_transactions(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    sink: &mut dyn EventSink,
+    last_signature: &mut Option<String>,
+) -> Result<usize> {
+    let signatures = get_new_signatures(config, solido_address, last_signature.as_deref())?;
+    let mut num_events = 0;
+    for info in signatures {
+        // Failed transactions have no events, we need not read them.
+        if info.err.is_none() {
+            let transaction = match config.client.get_transaction(&info.signature)? {
+                Some(transaction) => transaction,
+                None => {
+                    return Err(CliError::with_cause(
+                        "The RPC node does not have the transaction, \
+                        indexing old transactions requires an archive node.",
+                        &info.signature,
+                    )
+                    .into())
+                }
+            };
+            let events = parse_events(
+                solido_program_id,
+                solido_address,
+                &info.signature,
+                &transaction,
+            );
+            sink.write_events(&events)?;
+            num_events += events.len();
+        }
+        *last_signature = Some(info.signature);
+    }
+    Ok(num_events)
+}
+
+/// Index all transactions so far, then poll for new ones every `--poll-seconds`.
+pub fn main(config: &mut SnapshotClientConfig, opts: &IndexOpts) {
+    let mut sink = open_sink(*opts.output_format(), opts.output_path())
+        .ok_or_abort_with("Failed to open the output file.");
+    let mut last_signature = sink
+        .last_signature()
+        .ok_or_abort_with("Failed to read the output file.");
+
+    loop {
+        let result = config.with_snapshot(|config| {
+            index_new_transactions(
+                config,
+                opts.solido_program_id(),
+                opts.solido_address(),
+                &mut *sink,
+                &mut last_signature,
+            )
+        });
+        if *opts.once() {
+            result.ok_or_abort_with("Failed to index transactions.");
+            return;
+        }
+        match result {
+            Ok(0) => {}
+            Ok(num_events) => println!("Indexed {} new events.", num_events),
+            Err(err) => {
+                // Keep going, the next attempt continues where this one stopped.
+                println!(
+                    "Failed to index transactions, retrying in {}s.",
+                    opts.poll_seconds()
+                );
+                err.print_pretty();
+            }
+        }
+        std::thread::sleep(Duration::from_secs(*opts.poll_seconds()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::{Keypair, Signature, Signer};
+    use solana_sdk::transaction::Transaction;
+
+    use lido::instruction::{deposit, DepositAccountsMeta};
+
+    use super::*;
+    use crate::config::OutputMode;
+    use crate::mock_rpc::MockRpc;
+    use crate::snapshot::SnapshotClient;
+    use crate::Config;
+
+    #[derive(Default)]
+    struct VecSink {
+        events: Vec<Event>,
+    }
+
+    impl EventSink for VecSink {
+        fn last_signature(&self) -> std::result::Result<Option<String>, Error> {
+            Ok(self.events.last().map(|event| event.signature.clone()))
+        }
+
+        fn write_events(&mut self, events: &[Event]) -> std::result::Result<(), Error> {
+            self.events.extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    /// A confirmed deposit of `amount` that minted `minted` stSOL, with signature `signature_byte`.
+    fn new_deposit(
+        program_id: &Pubkey,
+        accounts: &DepositAccountsMeta,
+        signature_byte: u8,
+        amount: u64,
+        minted: u64,
+    ) -> (SignatureInfo, TransactionInfo) {
+        let instruction = deposit(program_id, accounts, Lamports(amount));
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&accounts.user));
+        transaction.signatures[0] = Signature::new(&[signature_byte; 64]);
+        let recipient_index = transaction
+            .message
+            .account_keys
+            .iter()
+            .position(|key| *key == accounts.recipient)
+            .unwrap();
+        let num_accounts = transaction.message.account_keys.len();
+        let info = SignatureInfo {
+            signature: transaction.signatures[0].to_string(),
+            slot: signature_byte as Slot,
+            err: None,
+            block_time: Some(1_600_000_000),
+        };
+        let transaction = TransactionInfo {
+            slot: signature_byte as Slot,
+            block_time: Some(1_600_000_000),
+            transaction,
+            err: None,
+            pre_balances: vec![0; num_accounts],
+            post_balances: vec![0; num_accounts],
+            pre_token_balances: vec![TokenBalance {
+                account_index: recipient_index,
+                amount: 7,
+            }],
+            post_token_balances: vec![TokenBalance {
+                account_index: recipient_index,
+                amount: 7 + minted,
+            }],
+        };
+        (info, transaction)
+    }
+
+    #[test]
+    fn test_index_new_transactions_continues_after_last_signature() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let accounts = DepositAccountsMeta {
+            lido: solido_address,
+            user: Pubkey::new_unique(),
+            recipient: Pubkey::new_unique(),
+            st_sol_mint: Pubkey::new_unique(),
+            reserve_account: Pubkey::new_unique(),
+            mint_authority: Pubkey::new_unique(),
+            spl_token: spl_token::id(),
+            depositor_allowlist: Pubkey::new_unique(),
+        };
+        let (info_1, transaction_1) = new_deposit(&program_id, &accounts, 1, 2_000, 1_000);
+        let (info_2, transaction_2) = new_deposit(&program_id, &accounts, 2, 3_000, 1_500);
+
+        let rpc = MockRpc::new();
+        rpc.set_transaction(transaction_1);
+        rpc.set_transaction(transaction_2);
+        // The node returns the newest transaction first.
+        rpc.set_signatures(solido_address, vec![info_2.clone(), info_1.clone()]);
+
+        let signer = Keypair::new();
+        let mut config = Config {
+            client: SnapshotClient::new(rpc.clone()),
+            signer: &signer,
+            fee_payer: &signer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: None,
+        };
+        let mut sink = VecSink::default();
+        let mut last_signature = None;
+        let mut index = |sink: &mut VecSink, last_signature: &mut Option<String>| {
+            config
+                .with_snapshot(|config| {
+                    index_new_transactions(
+                        config,
+                        &program_id,
+                        &solido_address,
+                        sink,
+                        last_signature,
+                    )
+                })
+                .unwrap()
+        };
+
+        assert_eq!(index(&mut sink, &mut last_signature), 2);
+        assert_eq!(sink.events[0].signature, info_1.signature);
+        assert_eq!(sink.events[0].kind, EventKind::Deposit);
+        assert_eq!(sink.events[0].owner, accounts.user);
+        assert_eq!(sink.events[0].st_sol_account, accounts.recipient);
+        assert_eq!(sink.events[0].amount_sol, Some(Lamports(2_000)));
+        assert_eq!(sink.events[0].amount_st_sol, Some(StLamports(1_000)));
+        assert_eq!(sink.events[0].exchange_rate(), Some(2.0));
+        assert_eq!(sink.events[1].signature, info_2.signature);
+        assert_eq!(last_signature, Some(info_2.signature.clone()));
+
+        // Without new transactions, there is nothing to do.
+        assert_eq!(index(&mut sink, &mut last_signature), 0);
+        assert_eq!(sink.events.len(), 2);
+    }
+
+    #[test]
+    fn test_csv_sink_continues_after_last_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.csv");
+        let event = Event {
+            signature: "sig1".to_string(),
+            slot: 10,
+            block_time: None,
+            kind: EventKind::Withdraw,
+            owner: Pubkey::new_unique(),
+            st_sol_account: Pubkey::new_unique(),
+            amount_sol: Some(Lamports(3)),
+            amount_st_sol: Some(StLamports(2)),
+        };
+
+        let mut sink = CsvSink::open(&path).unwrap();
+        assert_eq!(sink.last_signature().unwrap(), None);
+        sink.write_events(&[event.clone()]).unwrap();
+
+        let mut sink = CsvSink::open(&path).unwrap();
+        assert_eq!(sink.last_signature().unwrap(), Some("sig1".to_string()));
+        sink.write_events(&[Event {
+            signature: "sig2".to_string(),
+            ..event
+        }])
+        .unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].starts_with("sig1,10,,withdraw,"));
+        assert!(lines[1].ends_with(",3,2,1.5"));
+        assert!(lines[2].starts_with("sig2,"));
+    }
+}
@@ -0,0 +1,291 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Support for executing maintenance through third-party keeper networks.
+//!
+//! Several maintenance instructions do not require a signature from a
+//! maintainer: anybody can execute them. Keeper networks (schedulers that run
+//! transactions on behalf of their users, like Clockwork threads) can execute
+//! these at the start of every epoch, so the exchange rate gets updated and
+//! validator fees get collected even when no maintainer is running.
+//!
+//! `solido show-keeper-schedule` prints the instructions to schedule, and the
+//! maintenance daemon can leave these tasks to keepers for a grace period at
+//! the start of the epoch, see `--keeper-grace-period-seconds`. If the keepers
+//! execute the tasks, the daemon observes that there is nothing left to do. If
+//! they don't, the daemon executes the tasks itself after the grace period.
+//!
+//! The schedule contains the accounts of the current validators and fee
+//! recipients, so it needs to be regenerated when those change.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+
+use lido::instruction::{
+    ClaimValidatorFeeMeta, CollectValidatorFeeMeta, UpdateExchangeRateAccountsMeta,
+};
+use lido::state::Lido;
+use lido::util::serialize_b58;
+
+use crate::maintenance::MaintenanceOutput;
+use crate::snapshot::Result;
+
+/// Return whether keepers can execute this maintenance, and it is part of the schedule.
+pub fn is_keeper_task(output: &MaintenanceOutput) -> bool {
+    matches!(
+        output,
+        MaintenanceOutput::UpdateExchangeRate
+            | MaintenanceOutput::CollectValidatorFee { .. }
+            | MaintenanceOutput::ClaimValidatorFee { .. }
+    )
+}
+
+/// When a keeper should execute a task.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub enum Trigger {
+    /// Once, as soon as possible after the start of every epoch.
+    EpochStart,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct KeeperAccount {
+    #[serde(serialize_with = "serialize_b58")]
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct KeeperInstruction {
+    #[serde(serialize_with = "serialize_b58")]
+    pub program_id: Pubkey,
+    pub accounts: Vec<KeeperAccount>,
+    /// Instruction data, base58-encoded.
+    pub data: String,
+}
+
+impl From<Instruction> for KeeperInstruction {
+    fn from(instruction: Instruction) -> KeeperInstruction {
+        KeeperInstruction {
+            program_id: instruction.program_id,
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta: &AccountMeta| KeeperAccount {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: bs58::encode(&instruction.data).into_string(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct KeeperTask {
+    /// Name of the Solido instruction.
+    pub operation: &'static str,
+
+    /// The validator that the task is for, if it is specific to one validator.
+    #[serde(serialize_with = "serialize_b58_option")]
+    pub validator_vote_account: Option<Pubkey>,
+
+    pub trigger: Trigger,
+
+    /// The operation that must have been executed in the same epoch before
+    /// this task, otherwise the task fails.
+    pub run_after: Option<&'static str>,
+
+    pub instruction: KeeperInstruction,
+}
+
+pub(crate) fn serialize_b58_option<S: serde::Serializer>(
+    value: &Option<Pubkey>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(pubkey) => serializer.serialize_some(&pubkey.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The maintenance tasks that keepers can execute, in the order to execute them.
+#[derive(Debug, Serialize)]
+pub struct KeeperSchedule {
+    pub tasks: Vec<KeeperTask>,
+}
+
+impl KeeperSchedule {
+    pub fn new(
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+        solido: &Lido,
+    ) -> Result<KeeperSchedule> {
+        let reserve = solido.get_reserve_account(solido_program_id, solido_address)?;
+        let mint_authority = solido.get_mint_authority(solido_program_id, solido_address)?;
+        let rewards_withdraw_authority =
+            solido.get_rewards_withdraw_authority(solido_program_id, solido_address)?;
+
+        let mut tasks = vec![KeeperTask {
+            operation: "UpdateExchangeRate",
+            validator_vote_account: None,
+            trigger: Trigger::EpochStart,
+            run_after: None,
+            instruction: lido::instruction::update_exchange_rate(
+                solido_program_id,
+                &UpdateExchangeRateAccountsMeta {
+                    lido: *solido_address,
+                    reserve,
+                    st_sol_mint: solido.st_sol_mint,
+                },
+            )
+            .into(),
+        }];
+
+        for validator in solido.validators.entries.iter() {
+            tasks.push(KeeperTask {
+                operation: "CollectValidatorFee",
+                validator_vote_account: Some(validator.pubkey),
+                trigger: Trigger::EpochStart,
+                run_after: Some("UpdateExchangeRate"),
+                instruction: lido::instruction::collect_validator_fee(
+                    solido_program_id,
+                    &CollectValidatorFeeMeta {
+                        lido: *solido_address,
+                        validator_vote_account: validator.pubkey,
+                        st_sol_mint: solido.st_sol_mint,
+                        mint_authority,
+                        treasury_st_sol_account: solido.fee_recipients.treasury_account,
+                        developer_st_sol_account: solido.fee_recipients.developer_account,
+                        reserve,
+                        rewards_withdraw_authority,
+                        spl_token_program: solido.st_sol_token_program,
+                    },
+                )
+                .into(),
+            });
+        }
+
+        for validator in solido.validators.entries.iter() {
+            tasks.push(KeeperTask {
+                operation: "ClaimValidatorFee",
+                validator_vote_account: Some(validator.pubkey),
+                trigger: Trigger::EpochStart,
+                run_after: Some("CollectValidatorFee"),
+                instruction: lido::instruction::claim_validator_fee(
+                    solido_program_id,
+                    &ClaimValidatorFeeMeta {
+                        lido: *solido_address,
+                        st_sol_mint: solido.st_sol_mint,
+                        mint_authority,
+                        validator_fee_st_sol_account: validator.entry.fee_address,
+                        spl_token: solido.st_sol_token_program,
+                    },
+                )
+                .into(),
+            });
+        }
+
+        Ok(KeeperSchedule { tasks })
+    }
+}
+
+impl fmt::Display for KeeperSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Keeper schedule, {} tasks:", self.tasks.len())?;
+        for task in &self.tasks {
+            writeln!(f, "\n{} ({:?})", task.operation, task.trigger)?;
+            if let Some(vote_account) = task.validator_vote_account {
+                writeln!(f, "  Validator vote account: {}", vote_account)?;
+            }
+            if let Some(run_after) = task.run_after {
+                writeln!(f, "  Run after:              {}", run_after)?;
+            }
+            writeln!(
+                f,
+                "  Program:                {}",
+                task.instruction.program_id
+            )?;
+            writeln!(f, "  Data:                   {}", task.instruction.data)?;
+            for account in &task.instruction.accounts {
+                writeln!(
+                    f,
+                    "  Account: [{}{}] {}",
+                    if account.is_writable { 'W' } else { '-' },
+                    if account.is_signer { 'S' } else { '-' },
+                    account.pubkey,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lido::state::{Validator, Validators};
+    use lido::token::Lamports;
+
+    use super::*;
+
+    #[test]
+    fn test_keeper_schedule_contains_no_signers() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let find = |seed: &[u8]| {
+            Pubkey::find_program_address(&[&solido_address.to_bytes(), seed], &program_id).1
+        };
+        let mut solido = Lido {
+            sol_reserve_account_bump_seed: find(lido::RESERVE_ACCOUNT),
+            mint_authority_bump_seed: find(lido::MINT_AUTHORITY),
+            rewards_withdraw_authority_bump_seed: find(lido::REWARDS_WITHDRAW_AUTHORITY),
+            validators: Validators::new(2),
+            ..Lido::default()
+        };
+        let vote_accounts = [Pubkey::new_unique(), Pubkey::new_unique()];
+        for vote_account in &vote_accounts {
+            solido
+                .validators
+                .add(*vote_account, Validator::new(Pubkey::new_unique()))
+                .unwrap();
+        }
+
+        let schedule = KeeperSchedule::new(&program_id, &solido_address, &solido).unwrap();
+        let operations: Vec<_> = schedule
+            .tasks
+            .iter()
+            .map(|task| (task.operation, task.validator_vote_account))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("UpdateExchangeRate", None),
+                ("CollectValidatorFee", Some(vote_accounts[0])),
+                ("CollectValidatorFee", Some(vote_accounts[1])),
+                ("ClaimValidatorFee", Some(vote_accounts[0])),
+                ("ClaimValidatorFee", Some(vote_accounts[1])),
+            ]
+        );
+
+        // Keepers can only execute tasks that need no signature from us.
+        for task in &schedule.tasks {
+            assert!(task.instruction.accounts.iter().all(|a| !a.is_signer));
+        }
+
+        // Every task in the schedule is one that the daemon defers.
+        assert!(is_keeper_task(&MaintenanceOutput::UpdateExchangeRate));
+        assert!(is_keeper_task(&MaintenanceOutput::CollectValidatorFee {
+            validator_vote_account: vote_accounts[0],
+            fee_rewards: Lamports(1),
+        }));
+        assert!(!is_keeper_task(&MaintenanceOutput::StakeDeposit {
+            validator_vote_account: vote_accounts[0],
+            stake_account: Pubkey::new_unique(),
+            amount: Lamports(1),
+        }));
+    }
+}
@@ -0,0 +1,621 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Library part of the `solido` command-line tool.
+//!
+//! The binary in `main.rs` only parses arguments and dispatches to the modules
+//! here. Keeping the logic in a library makes it available to the benchmarks.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use solana_remote_wallet::locator::Locator;
+use solana_remote_wallet::remote_keypair::generate_remote_keypair;
+use solana_remote_wallet::remote_wallet::maybe_wallet_manager;
+use solana_sdk::derivation_path::DerivationPath;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair, read_keypair_file, Signature};
+use solana_sdk::signer::{Signer, SignerError};
+use solana_sdk::signers::Signers;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+
+use crate::config::OutputMode;
+use crate::error::{CliError, Error};
+use crate::snapshot::{Snapshot, SnapshotClient};
+
+pub mod account_dump;
+pub mod alerts;
+pub mod audit_log;
+pub mod config;
+pub mod daemon;
+pub mod derivation_cache;
+pub mod error;
+pub mod exchange_rate_feed;
+pub mod export;
+#[cfg(test)]
+mod golden;
+pub mod governance;
+pub mod helpers;
+pub mod history;
+pub mod index;
+pub mod keeper;
+pub mod logging;
+pub mod maintenance;
+pub mod manifest;
+pub mod mev;
+#[cfg(test)]
+mod mock_rpc;
+pub mod multisig;
+pub mod offline;
+pub mod onboarding;
+pub mod priority_fee;
+pub mod prometheus;
+pub mod rate_limit;
+pub mod reloadable_signer;
+pub mod remote_signer;
+pub mod rewards_report;
+pub mod rpc;
+#[cfg(test)]
+mod simulation;
+pub mod snapshot;
+pub mod spl_token_utils;
+pub mod stake_accounts;
+pub mod stake_pool;
+pub mod state_diff;
+pub mod top;
+pub mod transaction_tracker;
+pub mod unix_http;
+pub mod validator_info;
+pub mod validator_policy;
+pub mod validators_report;
+pub mod verify;
+pub mod webhook;
+
+/// Determines which network to connect to, and who pays the fees.
+pub struct Config<'a, T> {
+    /// RPC client augmented with snapshot functionality.
+    pub client: T,
+    /// Reference to a signer, can be a keypair or ledger device.
+    pub signer: &'a dyn Signer,
+    /// Signer that pays the transaction fees, usually the same as `signer`.
+    ///
+    /// A separate fee payer lets the signer hold authority without holding
+    /// SOL, while a low-value key pays the fees.
+    pub fee_payer: &'a dyn Signer,
+    /// output mode, can be json or text.
+    pub output_mode: OutputMode,
+    /// If set, write transactions to this file instead of sending them.
+    pub output_transaction_path: Option<PathBuf>,
+    /// If set, use a durable nonce instead of a recent blockhash.
+    pub nonce: Option<NonceConfig>,
+}
+
+/// A durable nonce account to build transactions with.
+///
+/// A transaction that uses a recent blockhash expires after about two minutes.
+/// A transaction that uses the blockhash stored in a nonce account stays valid
+/// until the nonce is advanced, which gives multisig owners time to sign.
+#[derive(Copy, Clone, Debug)]
+pub struct NonceConfig {
+    /// The nonce account.
+    pub account: Pubkey,
+    /// The nonce authority, which must sign the transaction.
+    pub authority: Pubkey,
+}
+
+/// Program configuration, and a snapshot of accounts.
+///
+/// Accept this in functions that just want to read from a consistent chain
+/// state, without handling retry logic.
+pub type SnapshotConfig<'a> = Config<'a, Snapshot<'a>>;
+
+/// Program configuration, and a client for making snapshots.
+///
+/// Accept this in functions that need to take a snapshot of the on-chain state
+/// at different times. In practice, that's only the long-running maintenance
+/// daemon.
+pub type SnapshotClientConfig<'a> = Config<'a, SnapshotClient>;
+
+impl<'a> SnapshotClientConfig<'a> {
+    pub fn with_snapshot<F, T>(&mut self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(&mut SnapshotConfig) -> snapshot::Result<T>,
+    {
+        let signer = self.signer;
+        let fee_payer = self.fee_payer;
+        let output_mode = self.output_mode;
+        let output_transaction_path = &self.output_transaction_path;
+        let nonce = self.nonce;
+        self.client.with_snapshot(|snapshot| {
+            let mut config = SnapshotConfig {
+                client: snapshot,
+                signer,
+                fee_payer,
+                output_mode,
+                output_transaction_path: output_transaction_path.clone(),
+                nonce,
+            };
+            f(&mut config)
+        })
+    }
+
+    /// Send a transaction that was signed in an earlier snapshot, see
+    /// [`SnapshotConfig::send_transaction`].
+    ///
+    /// [`SnapshotClientConfig::with_snapshot`] may call its closure more than
+    /// once, so a closure that sends a transaction could send it twice. Sign
+    /// transactions in the snapshot instead, and send them with this after it.
+    pub fn send_transaction(&mut self, transaction: &Transaction) -> Result<Signature, Error> {
+        let signer = self.signer;
+        let fee_payer = self.fee_payer;
+        let output_mode = self.output_mode;
+        let output_transaction_path = &self.output_transaction_path;
+        let nonce = self.nonce;
+        self.client.without_snapshot(|snapshot| {
+            let mut config = SnapshotConfig {
+                client: snapshot,
+                signer,
+                fee_payer,
+                output_mode,
+                output_transaction_path: output_transaction_path.clone(),
+                nonce,
+            };
+            config.send_transaction(transaction)
+        })
+    }
+}
+
+impl<'a> SnapshotConfig<'a> {
+    /// Build an unsigned transaction, and return it with the blockhash to sign it with.
+    ///
+    /// When we use a durable nonce, this prepends the instruction that
+    /// advances the nonce, and the blockhash is the one stored in the nonce
+    /// account, rather than a recent one.
+    fn new_transaction(
+        &mut self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> snapshot::Result<(Transaction, Hash)> {
+        match self.nonce {
+            None => {
+                let tx = Transaction::new_with_payer(instructions, Some(payer));
+                let recent_blockhash = self.client.get_recent_blockhash()?;
+                Ok((tx, recent_blockhash))
+            }
+            Some(nonce) => {
+                let nonce_data = self.client.get_nonce_data(&nonce.account)?;
+                if nonce_data.authority != nonce.authority {
+                    let error: Error = CliError::with_cause(
+                        "The nonce authority does not match the authority of the nonce account.",
+                        format!(
+                            "Expected {}, but the nonce account has authority {}.",
+                            nonce.authority, nonce_data.authority
+                        ),
+                    );
+                    return Err(error.into());
+                }
+                // The advance instruction must be the first in the transaction.
+                let mut all_instructions = vec![system_instruction::advance_nonce_account(
+                    &nonce.account,
+                    &nonce.authority,
+                )];
+                all_instructions.extend_from_slice(instructions);
+                let tx = Transaction::new_with_payer(&all_instructions, Some(payer));
+                Ok((tx, nonce_data.blockhash))
+            }
+        }
+    }
+
+    pub fn sign_transaction<T: Signers>(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &T,
+    ) -> snapshot::Result<Transaction> {
+        let fee_payer = self.fee_payer;
+        self.sign_transaction_with_fee_payer(instructions, fee_payer, signers)
+    }
+
+    /// Like [`SnapshotConfig::sign_transaction`], but with a different fee
+    /// payer than the configured one.
+    pub fn sign_transaction_with_fee_payer<T: Signers>(
+        &mut self,
+        instructions: &[Instruction],
+        fee_payer: &dyn Signer,
+        signers: &T,
+    ) -> snapshot::Result<Transaction> {
+        let (mut tx, recent_blockhash) = self.new_transaction(instructions, &fee_payer.pubkey())?;
+        if self.signer.is_interactive() || fee_payer.is_interactive() {
+            // A hardware wallet does not sign until the user approves the
+            // transaction on the device, let them know that we are waiting.
+            // Print to stderr, so we don't break json output.
+            eprintln!("Please confirm the transaction on your hardware wallet.");
+        }
+        try_partial_sign(&mut tx, fee_payer, signers, recent_blockhash)?;
+        if !tx.is_signed() {
+            let error: Error = Box::new(SignerError::NotEnoughSigners);
+            return Err(error.into());
+        }
+        Ok(tx)
+    }
+
+    pub fn sign_and_send_transaction<T: Signers>(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &T,
+    ) -> snapshot::Result<Signature> {
+        let fee_payer = self.fee_payer;
+        self.sign_and_send_transaction_with_fee_payer(instructions, fee_payer, signers)
+    }
+
+    /// Like [`SnapshotConfig::sign_and_send_transaction`], but with a
+    /// different fee payer than the configured one.
+    pub fn sign_and_send_transaction_with_fee_payer<T: Signers>(
+        &mut self,
+        instructions: &[Instruction],
+        fee_payer: &dyn Signer,
+        signers: &T,
+    ) -> snapshot::Result<Signature> {
+        if let Some(path) = self.output_transaction_path.clone() {
+            return self.sign_and_write_transaction(&path, instructions, fee_payer, signers);
+        }
+        let transaction = self.sign_transaction_with_fee_payer(instructions, fee_payer, signers)?;
+        self.send_transaction(&transaction)
+    }
+
+    /// Sign the transaction with the signers that can sign, and write it to a
+    /// file, for `solido submit-transaction` to sign and submit later.
+    ///
+    /// Returns the signature of the fee payer, which is the default signature
+    /// if the fee payer did not sign yet.
+    fn sign_and_write_transaction<T: Signers>(
+        &mut self,
+        path: &Path,
+        instructions: &[Instruction],
+        fee_payer: &dyn Signer,
+        signers: &T,
+    ) -> snapshot::Result<Signature> {
+        let (mut tx, recent_blockhash) = self.new_transaction(instructions, &fee_payer.pubkey())?;
+        // When the signer is a public key, it produces a default signature,
+        // which leaves it to be signed later.
+        try_partial_sign(&mut tx, fee_payer, signers, recent_blockhash)?;
+        offline::write_transaction(path, &tx)?;
+        eprintln!(
+            "Wrote transaction to {}, sign and submit it with `solido submit-transaction`.",
+            path.display()
+        );
+        Ok(tx.signatures[0])
+    }
+
+    /// Send a fully signed transaction, and wait for it to be confirmed.
+    pub fn send_transaction(&mut self, transaction: &Transaction) -> snapshot::Result<Signature> {
+        self.client.simulate_before_send(transaction)?;
+        let signature_result = match self.output_mode {
+            OutputMode::Text => {
+                // In text mode, we can display a spinner.
+                self.client
+                    .send_and_confirm_transaction_with_spinner(transaction)
+            }
+            OutputMode::Json => {
+                // In json mode, printing a spinner to stdout would break the
+                // json that we also print to stdout, so opt for the silent
+                // version.
+                self.client.send_and_confirm_transaction(transaction)
+            }
+        };
+
+        // Warn the user for one particular footgun.
+        match signature_result {
+            Err(ref err) if error::might_have_executed(err) => {
+                eprintln!(
+                    "Warning: The RPC returned an error, but the transaction \
+                     might still have been executed. Check the signer's address \
+                     on a block explorer before continuing. Beware that timestamps \
+                     shown on explorer.solana.com can be off by weeks, check the slot \
+                     number to confirm whether a transaction is recent."
+                );
+            }
+            _ => {}
+        }
+
+        Ok(signature_result?)
+    }
+}
+
+/// Sign with `signers`, and with the fee payer if it is not one of them.
+fn try_partial_sign<T: Signers>(
+    tx: &mut Transaction,
+    fee_payer: &dyn Signer,
+    signers: &T,
+    recent_blockhash: Hash,
+) -> snapshot::Result<()> {
+    let fee_payer_signs_separately = !signers.pubkeys().contains(&fee_payer.pubkey());
+    tx.try_partial_sign(signers, recent_blockhash)
+        .and_then(|()| {
+            if fee_payer_signs_separately {
+                tx.try_partial_sign(&[fee_payer], recent_blockhash)
+            } else {
+                Ok(())
+            }
+        })
+        .map_err(|err| {
+            let boxed_error: Error = Box::new(err);
+            boxed_error.into()
+        })
+}
+
+/// The result of a command, which we print as text or as json, depending on `--output`.
+///
+/// Every command prints exactly one `Output` to stdout, so in json mode, stdout
+/// holds a single json document. Anything else, such as warnings and progress
+/// messages, goes to stderr.
+pub trait Output: fmt::Display + Serialize {}
+
+impl<T: fmt::Display + Serialize> Output for T {}
+
+pub fn print_output<T: Output>(mode: OutputMode, output: &T) {
+    match mode {
+        OutputMode::Text => println!("{}", output),
+        OutputMode::Json => {
+            let json_string =
+                serde_json::to_string_pretty(output).expect("Failed to serialize output as json.");
+            println!("{}", json_string);
+        }
+    }
+}
+
+/// Parse a keypair path of the form "usb://ledger?key=0".
+pub fn parse_remote_wallet_details(uri: &str) -> Result<(DerivationPath, Locator), Error> {
+    use std::convert::TryFrom;
+    use uriparse::uri_reference::URIReference;
+
+    let uri_invalid_msg =
+        "Failed to parse usb:// keypair path. It must be of the form 'usb://ledger?key=0'.";
+
+    let uri_ref =
+        URIReference::try_from(uri).map_err(|err| CliError::with_cause(uri_invalid_msg, err))?;
+
+    let derivation_path = DerivationPath::from_uri_key_query(&uri_ref)
+        .map_err(|err| CliError::with_cause(uri_invalid_msg, err))?
+        // If there is no ?key= query parameter, then use the default derivation path.
+        .unwrap_or_default();
+
+    let locator = Locator::new_from_uri(&uri_ref)
+        .map_err(|err| CliError::with_cause(uri_invalid_msg, err))?;
+
+    Ok((derivation_path, locator))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_wallet_details() {
+        use derivation_path::ChildIndex;
+
+        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger").ok().unwrap();
+        // /'44/'501 is added by default for all Solana derivation paths.
+        assert_eq!(
+            derivation_path.path(),
+            [ChildIndex::Hardened(44), ChildIndex::Hardened(501)]
+        );
+
+        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger?key=0")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            derivation_path.path(),
+            [
+                ChildIndex::Hardened(44),
+                ChildIndex::Hardened(501),
+                ChildIndex::Hardened(0)
+            ]
+        );
+
+        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger?key=0/1")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            derivation_path.path(),
+            [
+                ChildIndex::Hardened(44),
+                ChildIndex::Hardened(501),
+                ChildIndex::Hardened(0),
+                ChildIndex::Hardened(1)
+            ]
+        );
+
+        let (derivation_path, _) = parse_remote_wallet_details(
+            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(
+            derivation_path.path(),
+            [ChildIndex::Hardened(44), ChildIndex::Hardened(501)]
+        );
+
+        let (derivation_path, _) = parse_remote_wallet_details(
+            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK?key=2",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(
+            derivation_path.path(),
+            [
+                ChildIndex::Hardened(44),
+                ChildIndex::Hardened(501),
+                ChildIndex::Hardened(2)
+            ]
+        );
+
+        let (derivation_path, _) = parse_remote_wallet_details(
+            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK?key=2/3",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(
+            derivation_path.path(),
+            [
+                ChildIndex::Hardened(44),
+                ChildIndex::Hardened(501),
+                ChildIndex::Hardened(2),
+                ChildIndex::Hardened(3)
+            ]
+        );
+
+        assert!(parse_remote_wallet_details("usb://ledger?key=not-an-integer").is_err());
+        assert!(parse_remote_wallet_details("usb://ledger?foo=bar").is_err());
+        assert!(parse_remote_wallet_details("usb://ledger/not-a-key").is_err());
+    }
+
+    /// Return a mock RPC that holds a nonce account with the given authority.
+    fn new_rpc_with_nonce(authority: &Pubkey) -> (crate::mock_rpc::MockRpc, Pubkey, Hash) {
+        use solana_sdk::account::Account;
+        use solana_sdk::fee_calculator::FeeCalculator;
+        use solana_sdk::nonce;
+
+        let nonce_account = Pubkey::new_unique();
+        let nonce_blockhash = Hash::new_unique();
+        let nonce_state =
+            nonce::state::Versions::new_current(nonce::State::Initialized(nonce::state::Data {
+                authority: *authority,
+                blockhash: nonce_blockhash,
+                fee_calculator: FeeCalculator::default(),
+            }));
+        let rpc = crate::mock_rpc::MockRpc::new();
+        rpc.set_account(
+            nonce_account,
+            Account {
+                lamports: 1_000_000,
+                data: bincode::serialize(&nonce_state).unwrap(),
+                owner: solana_sdk::system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        (rpc, nonce_account, nonce_blockhash)
+    }
+
+    #[test]
+    fn test_durable_nonce_transaction() {
+        use solana_sdk::signature::Keypair;
+
+        let signer = Keypair::new();
+        let (rpc, nonce_account, nonce_blockhash) = new_rpc_with_nonce(&signer.pubkey());
+
+        let mut config = SnapshotClientConfig {
+            client: SnapshotClient::new(rpc),
+            signer: &signer,
+            fee_payer: &signer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: Some(NonceConfig {
+                account: nonce_account,
+                authority: signer.pubkey(),
+            }),
+        };
+        let transfer = system_instruction::transfer(&signer.pubkey(), &Pubkey::new_unique(), 1_000);
+        let transaction = config
+            .with_snapshot(|config| config.sign_transaction(&[transfer.clone()], &[config.signer]))
+            .unwrap();
+
+        assert_eq!(transaction.message.recent_blockhash, nonce_blockhash);
+        assert_eq!(transaction.message.instructions.len(), 2);
+        let advance = &transaction.message.instructions[0];
+        assert_eq!(
+            transaction.message.account_keys[advance.program_id_index as usize],
+            solana_sdk::system_program::id()
+        );
+        assert_eq!(
+            advance.data,
+            system_instruction::advance_nonce_account(&nonce_account, &signer.pubkey()).data
+        );
+        assert!(transaction.verify().is_ok());
+    }
+
+    #[test]
+    fn test_separate_fee_payer() {
+        use solana_sdk::signature::Keypair;
+
+        let signer = Keypair::new();
+        let fee_payer = Keypair::new();
+        let (rpc, nonce_account, _) = new_rpc_with_nonce(&signer.pubkey());
+
+        let mut config = SnapshotClientConfig {
+            client: SnapshotClient::new(rpc),
+            signer: &signer,
+            fee_payer: &fee_payer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: Some(NonceConfig {
+                account: nonce_account,
+                authority: signer.pubkey(),
+            }),
+        };
+        let transfer = system_instruction::transfer(&signer.pubkey(), &Pubkey::new_unique(), 1_000);
+        let transaction = config
+            .with_snapshot(|config| config.sign_transaction(&[transfer.clone()], &[config.signer]))
+            .unwrap();
+
+        // The fee payer is the first account, and it signs in addition to the signer.
+        assert_eq!(transaction.message.account_keys[0], fee_payer.pubkey());
+        assert_eq!(transaction.signatures.len(), 2);
+        assert!(transaction.verify().is_ok());
+    }
+}
+
+pub fn get_signer_from_key(key_json: String) -> Box<dyn Signer> {
+    use std::io::Cursor;
+    let mut cursor = Cursor::new(key_json.as_bytes());
+    Box::new(
+        read_keypair(&mut cursor)
+            .expect("Failed to deserialize keypair. Is it a json array of numbers?"),
+    )
+}
+
+// Get a boxed signer that lives long enough for us to use it in the Config.
+pub fn get_signer_from_path(payer_keypair_path: PathBuf) -> Result<Box<dyn Signer>, Error> {
+    let boxed_signer: Box<dyn Signer> = if payer_keypair_path.starts_with("usb://") {
+        let uri = payer_keypair_path
+            .into_os_string()
+            .into_string()
+            .map_err(|_| {
+                CliError::new("A keypair path that starts with usb:// must be valid UTF-8.")
+            })?;
+        // Parse the uri before we try to connect, so we can diagnose uri format issues early.
+        let (derivation_path, locator) = parse_remote_wallet_details(&uri)?;
+        let hw_wallet = maybe_wallet_manager()
+            .map_err(|err| CliError::with_cause("Remote wallet found, but failed to establish protocol. Maybe the Solana app is not open.", err))?
+            .ok_or_else(|| CliError::new("Failed to find a remote wallet, maybe Ledger is not connected or locked."))?;
+
+        // When using a Ledger hardware wallet, confirm the public key of the
+        // key to sign with on its display, so users can be sure that they
+        // selected the right key.
+        let confirm_public_key = true;
+
+        let remote_keypair = generate_remote_keypair(
+            locator,
+            derivation_path,
+            &hw_wallet,
+            confirm_public_key,
+            "Solido", /* When multiple wallets are connected, used to display a hint */
+        )
+        .map_err(|err| {
+            CliError::with_cause(
+                "Failed to get the public key from the remote wallet. Maybe it was rejected on the device.",
+                err,
+            )
+        })?;
+        Box::new(remote_keypair)
+    } else {
+        let keypair = read_keypair_file(&payer_keypair_path)
+            .map_err(|err| CliError::with_cause("Failed to read key pair from file.", err))?;
+        Box::new(keypair)
+    };
+    Ok(boxed_signer)
+}
@@ -0,0 +1,265 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Log events of the maintenance daemon, as human-readable text or as json lines.
+//!
+//! In text mode, an event prints only its message, like a plain `println!`. In
+//! json mode, every event is a single line with a timestamp, severity, event
+//! name, the message, and the fields of the event, so log aggregators such as
+//! Loki or Elasticsearch can ingest it without parsing free-form text. The
+//! format is global, it is set once at startup with `--log-format`.
+//!
+//! When the daemon maintains several Solido instances, events also name the
+//! instance that the thread was working on, see [`set_instance`].
+
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::AsPrettyError;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Print human-readable messages to stdout.
+    Text,
+
+    /// Print one json object per line to stdout.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<LogFormat, &'static str> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("Invalid log format, expected 'text' or 'json'."),
+        }
+    }
+}
+
+/// Whether to log in json, we only have two formats, so a bool suffices.
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: LogFormat) {
+    LOG_JSON.store(format == LogFormat::Json, Ordering::SeqCst);
+}
+
+pub fn get_format() -> LogFormat {
+    if LOG_JSON.load(Ordering::SeqCst) {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+thread_local! {
+    /// Name of the Solido instance that this thread works on, if we maintain several.
+    static INSTANCE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Attribute the events that this thread emits from now on to the named instance.
+///
+/// In text mode, the name prefixes the message, in json mode it is the
+/// `instance` field. Pass `None` when there is only one instance.
+pub fn set_instance(name: Option<&str>) {
+    INSTANCE.with(|instance| *instance.borrow_mut() = name.map(|n| n.to_string()));
+}
+
+pub(crate) fn get_instance() -> Option<String> {
+    INSTANCE.with(|instance| instance.borrow().clone())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A log event, build it with `info`, `warning`, or `error`, add fields, and `emit` it.
+pub struct Event<'a> {
+    severity: Severity,
+    /// Short machine-readable name of the event, e.g. `maintenance_performed`.
+    name: &'static str,
+    message: String,
+    fields: Map<String, Value>,
+    error: Option<&'a dyn AsPrettyError>,
+}
+
+impl<'a> Event<'a> {
+    fn new<M: Into<String>>(severity: Severity, name: &'static str, message: M) -> Event<'a> {
+        Event {
+            severity,
+            name,
+            message: message.into(),
+            fields: Map::new(),
+            error: None,
+        }
+    }
+
+    pub fn info<M: Into<String>>(name: &'static str, message: M) -> Event<'a> {
+        Event::new(Severity::Info, name, message)
+    }
+
+    pub fn warning<M: Into<String>>(name: &'static str, message: M) -> Event<'a> {
+        Event::new(Severity::Warning, name, message)
+    }
+
+    pub fn error<M: Into<String>>(name: &'static str, message: M) -> Event<'a> {
+        Event::new(Severity::Error, name, message)
+    }
+
+    /// Add a field, it is only printed in json mode.
+    pub fn field<T: Serialize>(mut self, key: &'static str, value: T) -> Event<'a> {
+        let value = serde_json::to_value(value).expect("Log fields can always be serialized.");
+        self.fields.insert(key.to_string(), value);
+        self
+    }
+
+    /// Attach an error. In text mode we pretty-print it after the message,
+    /// in json mode we include its summary in the `error` field.
+    pub fn with_error(mut self, error: &'a dyn AsPrettyError) -> Event<'a> {
+        self.error = Some(error);
+        self
+    }
+
+    fn to_json(&self, timestamp: SystemTime) -> Value {
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), format_rfc3339(timestamp).into());
+        object.insert("level".to_string(), self.severity.as_str().into());
+        object.insert("event".to_string(), self.name.into());
+        object.insert("message".to_string(), self.message.clone().into());
+        if let Some(instance) = get_instance() {
+            object.insert("instance".to_string(), instance.into());
+        }
+        if let Some(error) = self.error {
+            object.insert("error".to_string(), error.summary().into());
+        }
+        for (key, value) in &self.fields {
+            object.insert(key.clone(), value.clone());
+        }
+        Value::Object(object)
+    }
+
+    pub fn emit(self) {
+        match get_format() {
+            LogFormat::Text => {
+                let prefix = match get_instance() {
+                    Some(instance) => format!("[{}] ", instance),
+                    None => String::new(),
+                };
+                match self.severity {
+                    Severity::Warning => println!("{}Warning: {}", prefix, self.message),
+                    Severity::Info | Severity::Error => println!("{}{}", prefix, self.message),
+                }
+                if let Some(error) = self.error {
+                    error.print_pretty();
+                }
+            }
+            LogFormat::Json => println!("{}", self.to_json(SystemTime::now())),
+        }
+    }
+}
+
+/// Format the time as an RFC 3339 timestamp in UTC, with millisecond precision.
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Log timestamps are after 1970.");
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Convert days since 1970-01-01 to a date in the proleptic Gregorian
+    // calendar, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3_600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::error::CliError;
+
+    #[test]
+    fn test_format_rfc3339() {
+        let at = |millis: u64| SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+        assert_eq!(format_rfc3339(at(0)), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_rfc3339(at(1_600_000_000_123)),
+            "2020-09-13T12:26:40.123Z"
+        );
+        // 2024 is a leap year.
+        assert_eq!(
+            format_rfc3339(at(1_709_208_000_000)),
+            "2024-02-29T12:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_event_to_json() {
+        let error = CliError::with_cause("Failed to read file.", "not found");
+        let event = Event::error("maintenance_error", "Error in maintenance.")
+            .field("slot", 123_u64)
+            .with_error(&error);
+        let json = event.to_json(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "1970-01-01T00:00:00.000Z",
+                "level": "error",
+                "event": "maintenance_error",
+                "message": "Error in maintenance.",
+                "error": "Failed to read file. Cause: not found",
+                "slot": 123,
+            })
+        );
+    }
+
+    #[test]
+    fn test_event_to_json_includes_instance() {
+        set_instance(Some("testnet"));
+        let json = Event::info("sleeping", "Sleeping.").to_json(SystemTime::UNIX_EPOCH);
+        assert_eq!(json["instance"], "testnet");
+
+        set_instance(None);
+        let json = Event::info("sleeping", "Sleeping.").to_json(SystemTime::UNIX_EPOCH);
+        assert_eq!(json.get("instance"), None);
+    }
+}
@@ -1,43 +1,50 @@
 // SPDX-FileCopyrightText: 2021 Chorus One AG
 // SPDX-License-Identifier: GPL-3.0
 
-use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use clap::Clap;
-use helpers::command_show_solido_authorities;
-use helpers::command_withdraw;
-use serde::Serialize;
+use clap::{Clap, IntoApp};
 use solana_client::rpc_client::RpcClient;
-use solana_remote_wallet::locator::Locator;
-use solana_remote_wallet::remote_keypair::generate_remote_keypair;
-use solana_remote_wallet::remote_wallet::maybe_wallet_manager;
-use solana_sdk::commitment_config::CommitmentConfig;
-use solana_sdk::derivation_path::DerivationPath;
-use solana_sdk::instruction::Instruction;
-use solana_sdk::signature::{read_keypair, read_keypair_file, Signature};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::null_signer::NullSigner;
 use solana_sdk::signer::Signer;
-use solana_sdk::signers::Signers;
-use solana_sdk::transaction::Transaction;
-
-use crate::config::*;
-use crate::error::{Abort, CliError, Error};
-use crate::helpers::{
-    command_add_maintainer, command_add_validator, command_create_solido,
-    command_deactivate_validator, command_deposit, command_remove_maintainer, command_show_solido,
+
+use solido_cli::account_dump::{AccountDump, DumpRpc};
+use solido_cli::config::*;
+use solido_cli::error::{self, Abort, CliError, Error};
+use solido_cli::governance::{self, GovernanceOpts};
+use solido_cli::helpers::{
+    command_add_allowed_depositor, command_add_maintainer, command_add_validator,
+    command_apply_validator_policy, command_claim_withdraw_ticket,
+    command_create_depositor_allowlist, command_create_solido, command_create_withdraw_ticket,
+    command_deactivate_validator, command_deposit, command_deposit_stake_account,
+    command_disable_depositor_allowlist, command_enable_depositor_allowlist, command_export_idl,
+    command_migrate_state, command_pause, command_remove_allowed_depositor,
+    command_remove_maintainer, command_replace_validator_vote_account, command_resume,
+    command_rewards_report, command_rotate_maintainer, command_schedule_reward_distribution,
+    command_set_max_stake_per_validator, command_set_max_total_sol, command_set_validator_weight,
+    command_show_keeper_schedule, command_show_solido, command_show_solido_authorities,
+    command_show_stake_accounts, command_show_stake_pool, command_show_validators, command_verify,
+    command_withdraw,
+};
+use solido_cli::index;
+use solido_cli::logging::{self, LogFormat};
+use solido_cli::manifest::{self, ConfigOption};
+use solido_cli::multisig::{self, MultisigOpts};
+use solido_cli::offline::command_submit_transaction;
+use solido_cli::onboarding::{self, ValidatorOpts};
+use solido_cli::rate_limit::RequestBudget;
+use solido_cli::reloadable_signer::ReloadableSigner;
+use solido_cli::remote_signer::RemoteSigner;
+use solido_cli::rpc::Commitment;
+use solido_cli::snapshot::SnapshotClient;
+use solido_cli::state_diff::{SolidoAtSlot, StateDiff};
+use solido_cli::top;
+use solido_cli::{
+    daemon, get_signer_from_key, get_signer_from_path, maintenance, print_output, Config,
+    NonceConfig,
 };
-use crate::multisig::MultisigOpts;
-use crate::snapshot::{Snapshot, SnapshotClient};
-
-mod config;
-mod daemon;
-mod error;
-mod helpers;
-mod maintenance;
-mod multisig;
-mod prometheus;
-mod snapshot;
-mod spl_token_utils;
 
 /// Solido -- Interact with Lido for Solana.
 // While it is nice to have Clap handle all inputs, we also want to read
@@ -79,20 +86,133 @@ struct Opts {
     keypair: Option<String>,
 
     /// The keypair to sign and pay with. [default: ~/.config/solana/id.json]
+    ///
+    /// To sign with a Ledger hardware wallet, pass a uri like
+    /// usb://ledger?key=0/0, where the key parameter is the derivation
+    /// path (account/change) of the key to use.
     // Overwritten by `GeneralOpts` if None.
     #[clap(long)]
     keypair_path: Option<PathBuf>,
 
+    /// Url of an external service to sign with, instead of a keypair.
+    ///
+    /// This can be an http:// or https:// url, or unix:// followed by
+    /// the path of a Unix domain socket. The service holds the private key,
+    /// so it never has to be loaded into memory. This takes precedence
+    /// over --keypair and --keypair-path.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long)]
+    remote_signer_url: Option<String>,
+
+    /// The keypair to pay transaction fees with. [default: the signer]
+    ///
+    /// This lets the signer be an authority that holds no SOL, while a
+    /// separate low-value key pays the fees. Like --keypair-path, this
+    /// can be a usb://ledger uri, or a public key with --output-transaction.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "path")]
+    fee_payer_keypair: Option<PathBuf>,
+
+    /// Write transactions to this file instead of sending them.
+    ///
+    /// To build a transaction for a key that is not available on this
+    /// machine, pass its public key as --keypair-path. Use
+    /// `solido submit-transaction` to sign and submit the transaction later.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long)]
+    output_transaction: Option<String>,
+
+    /// Durable nonce account to use instead of a recent blockhash.
+    ///
+    /// Transactions that use a recent blockhash expire after about two
+    /// minutes; transactions that use a nonce stay valid until the nonce
+    /// is advanced. This is useful with --output-transaction, when signing
+    /// takes a while.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "address")]
+    nonce_account: Option<Pubkey>,
+
+    /// Authority of the nonce account. [default: the signer]
+    ///
+    /// If this is not the signer, it needs to sign separately, with
+    /// --output-transaction and `solido submit-transaction`.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "address")]
+    nonce_authority: Option<Pubkey>,
+
     /// URL of cluster to connect to (e.g., https://api.devnet.solana.com for solana devnet) [default: http://127.0.0.1:8899]
+    ///
+    /// To fail over to a different RPC node when one is unreachable or rate
+    /// limits us, pass multiple urls separated by commas.
     // Overwritten by `GeneralOpts` if None.
     #[clap(long)]
     cluster: Option<String>,
 
+    /// Commitment level at which to read accounts and confirm transactions. [default: "confirmed"]
+    ///
+    /// Use 'finalized' for safety, at the cost of latency, or 'processed'
+    /// for the lowest latency, at the risk of acting on state that is
+    /// rolled back.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, possible_values = &["processed", "confirmed", "finalized"])]
+    commitment: Option<Commitment>,
+
+    /// Maximum number of requests per second to each RPC endpoint. [default: 0, no limit]
+    ///
+    /// Public RPC endpoints rate limit their clients. With a limit, we
+    /// pace our requests to stay within it, and after a '429 Too Many
+    /// Requests' response, we pause before the next request.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "n")]
+    rpc_requests_per_second: Option<u32>,
+
+    /// Number of requests that we can make to an RPC endpoint at once, after an idle period. [default: 10]
+    ///
+    /// Only has an effect together with --rpc-requests-per-second.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "n")]
+    rpc_burst: Option<u32>,
+
     /// Whether to output text or json. [default: "text"]
     // Overwritten by `GeneralOpts` if None.
     #[clap(long = "output", possible_values = &["text", "json"])]
     output_mode: Option<OutputMode>,
 
+    /// Whether to log events as text or json. [default: "text"]
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, possible_values = &["text", "json"])]
+    log_format: Option<LogFormat>,
+
+    /// Read accounts from this account dump, instead of from the cluster.
+    ///
+    /// This inspects the state of an instance at the slot of the dump, see
+    /// --write-account-dump. Only 'show-solido', 'verify', 'rewards-report',
+    /// and 'explain-maintenance' support this. Inflation rewards are still read
+    /// from the cluster, which needs to be an archive node for past epochs.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "path")]
+    account_dump: Option<PathBuf>,
+
+    /// The slot that the --account-dump must be at. [default: any slot]
+    ///
+    /// RPC nodes only serve the current state of accounts, and archive
+    /// nodes keep blocks but not account state, so inspecting a past slot
+    /// requires a dump taken at that slot.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "slot")]
+    at_slot: Option<u64>,
+
+    /// Write the accounts that the command read to this file, as an account dump.
+    ///
+    /// Take dumps periodically, to be able to inspect the state at those
+    /// slots later with --account-dump. A dump written by 'explain-maintenance'
+    /// can also serve as a test fixture, see `MockRpc::from_account_dump`.
+    /// Only 'show-solido', 'verify', 'rewards-report', and
+    /// 'explain-maintenance' support this.
+    // Overwritten by `GeneralOpts` if None.
+    #[clap(long, value_name = "path")]
+    write_account_dump: Option<PathBuf>,
+
     #[clap(subcommand)]
     subcommand: SubCommand,
 
@@ -105,6 +225,14 @@ impl Opts {
     fn merge_with_config_and_environment(&mut self) -> Option<ConfigFile> {
         let mut general_opts = GeneralOpts::default();
         let config_file = self.config.as_ref().map(|p| read_config(p.as_path()));
+        if let Some(config_file) = &config_file {
+            if let Err(problems) = validate_config_file(config_file, &get_all_config_options()) {
+                for problem in problems {
+                    eprintln!("{}", problem);
+                }
+                std::process::exit(1);
+            }
+        }
         general_opts.merge_with_config_and_environment(config_file.as_ref());
 
         self.keypair = self
@@ -115,14 +243,62 @@ impl Opts {
             .keypair_path
             .take()
             .or_else(|| Some(general_opts.keypair_path().to_owned()));
+        self.remote_signer_url = self
+            .remote_signer_url
+            .take()
+            .or_else(|| Some(general_opts.remote_signer_url().to_owned()));
+        self.fee_payer_keypair = self
+            .fee_payer_keypair
+            .take()
+            .or_else(|| Some(general_opts.fee_payer_keypair().to_owned()));
+        self.output_transaction = self
+            .output_transaction
+            .take()
+            .or_else(|| Some(general_opts.output_transaction().to_owned()));
+        self.nonce_account = self
+            .nonce_account
+            .take()
+            .or_else(|| Some(general_opts.nonce_account().to_owned()));
+        self.nonce_authority = self
+            .nonce_authority
+            .take()
+            .or_else(|| Some(general_opts.nonce_authority().to_owned()));
         self.cluster = self
             .cluster
             .take()
             .or_else(|| Some(general_opts.cluster().to_owned()));
+        self.commitment = self
+            .commitment
+            .take()
+            .or_else(|| Some(general_opts.commitment().to_owned()));
+        self.rpc_requests_per_second = self
+            .rpc_requests_per_second
+            .take()
+            .or_else(|| Some(general_opts.rpc_requests_per_second().to_owned()));
+        self.rpc_burst = self
+            .rpc_burst
+            .take()
+            .or_else(|| Some(general_opts.rpc_burst().to_owned()));
         self.output_mode = self
             .output_mode
             .take()
             .or_else(|| Some(general_opts.output_mode().to_owned()));
+        self.log_format = self
+            .log_format
+            .take()
+            .or_else(|| Some(general_opts.log_format().to_owned()));
+        self.account_dump = self
+            .account_dump
+            .take()
+            .or_else(|| Some(general_opts.account_dump().to_owned()));
+        self.at_slot = self
+            .at_slot
+            .take()
+            .or_else(|| Some(general_opts.at_slot().to_owned()));
+        self.write_account_dump = self
+            .write_account_dump
+            .take()
+            .or_else(|| Some(general_opts.write_account_dump().to_owned()));
         config_file
     }
 }
@@ -164,148 +340,254 @@ REWARDS
     /// Deactivates a validator and initiates the removal process.
     DeactivateValidator(DeactivateValidatorOpts),
 
+    /// Moves a validator and its stake to a new vote account.
+    #[clap(after_help = r"STAKE ACCOUNTS
+
+    Stake accounts that are not active yet get delegated to the new vote
+    account, the stake program does not allow redelegating active stake, so
+    that is deactivated and returns to the reserve in the next epoch. The
+    program checks the destination stake accounts, which depend on the current
+    epoch, so execute the proposal in the epoch it was made.
+    ")]
+    ReplaceValidatorVoteAccount(ReplaceValidatorVoteAccountOpts),
+
+    /// Changes the weight of a validator, its share of the stake.
+    SetValidatorWeight(SetValidatorWeightOpts),
+
+    /// Changes the maximum amount of stake per validator.
+    SetMaxStakePerValidator(SetMaxStakePerValidatorOpts),
+
+    /// Changes the maximum amount of SOL managed by Solido, the deposit cap.
+    SetMaxTotalSol(SetMaxTotalSolOpts),
+
+    /// Schedules a change of the reward distribution, that takes effect in a
+    /// later epoch.
+    ///
+    /// The new distribution applies from the epoch that is `--delay-epochs`
+    /// after the epoch in which the multisig executes the transaction, so
+    /// stSOL holders get notice of the change.
+    ScheduleRewardDistribution(ScheduleRewardDistributionOpts),
+
+    /// Pauses deposits and withdrawals, in case of an emergency.
+    ///
+    /// Maintenance continues while paused.
+    Pause(PauseResumeOpts),
+
+    /// Resumes deposits and withdrawals after a pause.
+    Resume(PauseResumeOpts),
+
+    /// Migrates the Solido state from version 0 to the current version.
+    ///
+    /// The Solido account cannot grow, so the maximum number of validators
+    /// goes down to what fits in the current account.
+    MigrateState(MigrateStateOpts),
+
+    /// Creates the empty depositor allowlist, paid for by the fee payer.
+    ///
+    /// Anybody can create it, it has no effect until the manager enables it.
+    CreateDepositorAllowlist(CreateDepositorAllowlistOpts),
+
+    /// Adds a depositor to the depositor allowlist.
+    AddAllowedDepositor(AddRemoveAllowedDepositorOpts),
+
+    /// Removes a depositor from the depositor allowlist.
+    RemoveAllowedDepositor(AddRemoveAllowedDepositorOpts),
+
+    /// Only accepts deposits from depositors on the depositor allowlist.
+    EnableDepositorAllowlist(EnableDisableDepositorAllowlistOpts),
+
+    /// Accepts deposits from anybody again.
+    DisableDepositorAllowlist(EnableDisableDepositorAllowlistOpts),
+
     /// Adds a maintainer to the Solido instance.
     AddMaintainer(AddRemoveMaintainerOpts),
 
     /// Removes a maintainer from the Solido instance.
     RemoveMaintainer(AddRemoveMaintainerOpts),
 
+    /// Replaces a maintainer key with a new one, without a gap in maintenance.
+    ///
+    /// Proposes to add the new maintainer through the multisig, waits until
+    /// that is executed, and then proposes to remove the old maintainer.
+    /// Between the two steps, reload the daemons with the new key, by sending
+    /// them SIGHUP or a POST to /reload.
+    RotateMaintainer(RotateMaintainerOpts),
+
     /// Deposit some SOL, receive stSOL in return.
     ///
     /// The recipient will be set to the associated token account for the signer.
     /// If the associated token account does not yet exist, it will be created.
     Deposit(DepositOpts),
 
+    /// Deposit an active stake account, receive stSOL in return.
+    ///
+    /// The stake account must be delegated to one of the Solido validators.
+    /// Solido takes over the account and merges it into its own stake.
+    /// The recipient will be set to the associated token account for the signer.
+    /// If the associated token account does not yet exist, it will be created.
+    DepositStakeAccount(DepositStakeAccountOpts),
+
     /// Withdraw stSOL, receive a delegated stake account in return.
     ///
     /// The amount of SOL is calculated and stored in the returned stake.
     Withdraw(WithdrawOpts),
 
+    /// Burn stSOL for a withdraw ticket, to withdraw more SOL than the reserve holds.
+    ///
+    /// The SOL owed to the ticket is fixed at the current exchange rate. The
+    /// maintainers unstake to fund tickets in order of creation, claim the SOL
+    /// with 'claim-withdraw-ticket' once the ticket is funded.
+    CreateWithdrawTicket(CreateWithdrawTicketOpts),
+
+    /// Claim the SOL of a funded withdraw ticket, and close the ticket.
+    ClaimWithdrawTicket(ClaimWithdrawTicketOpts),
+
     /// Show an instance of Solido in detail
     ShowSolido(ShowSolidoOpts),
 
+    /// Compare the state of an instance at two slots.
+    #[clap(after_help = r"ACCOUNT DUMPS
+
+    RPC nodes only serve the current state of accounts, and archive nodes keep
+    blocks but not account state. To compare against a past slot, pass an
+    account dump taken at that slot, see --write-account-dump. The state to
+    compare to is the current state of the cluster, unless --to-dump is set.
+
+    This shows the change of the exchange rate, the validators that were added
+    or removed, and per validator, the change of the balances and fee credit
+    that Solido tracks.
+    ")]
+    DiffState(DiffStateOpts),
+
+    /// Show a live view of an instance in the terminal, until interrupted.
+    ///
+    /// The view refreshes every few seconds, and shows the exchange rate, the
+    /// reserve, the stake of every validator compared to its target, pending
+    /// maintenance, and the recent transactions of the maintainers.
+    Top(TopOpts),
+
+    /// Write the deposits, withdrawals, and fee distributions of an instance to CSV or SQLite.
+    ///
+    /// Indexes all past transactions of the instance, and then keeps polling
+    /// for new ones, unless --once is given.
+    Index(IndexOpts),
+
     /// Show Solido authorities, even if the instance is not initialized.
     ///
     /// This is useful for testing, and when setting up a token mint ahead of
     /// time, to be used later when initializing the Solido instance.
     ShowAuthorities(ShowSolidoAuthoritiesOpts),
 
+    /// Show an instance of Solido in the data model of the SPL stake pool program.
+    ///
+    /// This is intended for tools that already support SPL stake pools. The
+    /// field names match those of the stake pool and validator list accounts.
+    ShowStakePool(ShowStakePoolOpts),
+
+    /// Show the rewards of the last epochs, and the fees taken from them.
+    ///
+    /// For every completed epoch, this reads the inflation rewards of the
+    /// current stake accounts and vote accounts, splits them according to the
+    /// current reward distribution, and shows the APY that stSOL realized.
+    RewardsReport(RewardsReportOpts),
+
+    /// Check the accounting invariants of a Solido instance against the network.
+    ///
+    /// This checks that the stake accounts exist and hold the balance that
+    /// Solido recorded, that the stSOL supply is backed by the SOL under
+    /// management, and that validators can claim their fee credit. Prints
+    /// every violation, and exits with a nonzero status if there are any.
+    Verify(VerifyOpts),
+
+    /// Show the stake accounts of every validator.
+    ///
+    /// Lists the stake and unstake accounts in the seed ranges of every
+    /// validator, with their balance, activation state, and whether the
+    /// maintainer can merge them. Useful to find out why 'MergeStake' or
+    /// 'WithdrawInactiveStake' does not happen.
+    ShowStakeAccounts(ShowStakeAccountsOpts),
+
+    /// Show the validators, with their performance and estimated APY.
+    ///
+    /// Lists every validator with its stake, target balance, commission, skip
+    /// rate in the current epoch, and the APY estimated from the rewards of
+    /// the last completed epoch, together with its contribution to the APY of
+    /// stSOL. Use '--sort-by' to rank the validators.
+    ShowValidators(ShowValidatorsOpts),
+
+    /// Show the maintenance instructions that keepers can execute every epoch.
+    ///
+    /// These instructions need no signature from a maintainer, so a keeper
+    /// network can execute them on a schedule. The instructions reference the
+    /// current validators and fee recipients, regenerate the schedule when
+    /// those change. See also '--keeper-grace-period-seconds' of 'run-maintainer'.
+    ShowKeeperSchedule(ShowKeeperScheduleOpts),
+
+    /// Evaluate the validator set and candidate validators against a curation policy.
+    ///
+    /// Scores validators on commission, uptime, share of the cluster stake,
+    /// and datacenter, using the thresholds and candidates in a TOML policy
+    /// file, and reports which validators to deactivate and which candidates
+    /// to add. With '--propose', also proposes these changes as multisig
+    /// transactions.
+    ApplyValidatorPolicy(ApplyValidatorPolicyOpts),
+
+    /// Print the IDL of the Solido program as json.
+    ///
+    /// The IDL describes the instructions, account layouts, and error codes
+    /// in the format of Anchor IDLs, for frontends and indexers.
+    ExportIdl(ExportIdlOpts),
+
     /// Execute one iteration of periodic maintenance logic.
     ///
     /// This is mainly useful for testing. To perform maintenance continuously,
     /// use 'run-maintainer' instead.
     PerformMaintenance(PerformMaintenanceOpts),
 
+    /// Show every maintenance task, and why it would or would not be performed.
+    ///
+    /// Lists the tasks in the order in which the maintainer considers them,
+    /// with the task it would perform next, and for the other tasks why they
+    /// have nothing to do, for example because an amount is below a threshold,
+    /// the exchange rate was already updated in this epoch, or no stake
+    /// accounts can be merged. This sends no transactions. The maintainer
+    /// daemon serves the same explanation on its '/state' endpoint.
+    ExplainMaintenance(ExplainMaintenanceOpts),
+
     /// Start the maintainer daemon.
     RunMaintainer(RunMaintainerOpts),
 
     /// Interact with a deployed Multisig program for governance tasks.
     Multisig(MultisigOpts),
-}
-
-/// Determines which network to connect to, and who pays the fees.
-pub struct Config<'a, T> {
-    /// RPC client augmented with snapshot functionality.
-    client: T,
-    /// Reference to a signer, can be a keypair or ledger device.
-    signer: &'a dyn Signer,
-    /// output mode, can be json or text.
-    output_mode: OutputMode,
-}
-
-/// Program configuration, and a snapshot of accounts.
-///
-/// Accept this in functions that just want to read from a consistent chain
-/// state, without handling retry logic.
-pub type SnapshotConfig<'a> = Config<'a, Snapshot<'a>>;
 
-/// Program configuration, and a client for making snapshots.
-///
-/// Accept this in functions that need to take a snapshot of the on-chain state
-/// at different times. In practice, that's only the long-running maintenance
-/// daemon.
-pub type SnapshotClientConfig<'a> = Config<'a, SnapshotClient>;
-
-impl<'a> SnapshotClientConfig<'a> {
-    pub fn with_snapshot<F, T>(&mut self, mut f: F) -> Result<T, Error>
-    where
-        F: FnMut(&mut SnapshotConfig) -> snapshot::Result<T>,
-    {
-        let signer = self.signer;
-        let output_mode = self.output_mode;
-        self.client.with_snapshot(|snapshot| {
-            let mut config = SnapshotConfig {
-                client: snapshot,
-                signer,
-                output_mode,
-            };
-            f(&mut config)
-        })
-    }
-}
+    /// Propose manager instructions to an SPL Governance governance, as an
+    /// alternative to the multisig.
+    Governance(GovernanceOpts),
 
-impl<'a> SnapshotConfig<'a> {
-    pub fn sign_transaction<T: Signers>(
-        &mut self,
-        instructions: &[Instruction],
-        signers: &T,
-    ) -> snapshot::Result<Transaction> {
-        let mut tx = Transaction::new_with_payer(instructions, Some(&self.signer.pubkey()));
-        let recent_blockhash = self.client.get_recent_blockhash()?;
-        tx.try_sign(signers, recent_blockhash).map_err(|err| {
-            let boxed_error: Error = Box::new(err);
-            boxed_error
-        })?;
-        Ok(tx)
-    }
+    /// Tools for validators that want to join the instance: check the vote
+    /// account and fee account, and prepare the proposal to add the validator.
+    Validator(ValidatorOpts),
 
-    pub fn sign_and_send_transaction<T: Signers>(
-        &mut self,
-        instructions: &[Instruction],
-        signers: &T,
-    ) -> snapshot::Result<Signature> {
-        let transaction = self.sign_transaction(instructions, signers)?;
-        let signature_result = match self.output_mode {
-            OutputMode::Text => {
-                // In text mode, we can display a spinner.
-                self.client
-                    .send_and_confirm_transaction_with_spinner(&transaction)
-            }
-            OutputMode::Json => {
-                // In json mode, printing a spinner to stdout would break the
-                // json that we also print to stdout, so opt for the silent
-                // version.
-                self.client.send_and_confirm_transaction(&transaction)
-            }
-        };
-
-        // Warn the user for one particular footgun.
-        match signature_result {
-            Err(ref err) if error::might_have_executed(err) => {
-                eprintln!(
-                    "Warning: The RPC returned an error, but the transaction \
-                     might still have been executed. Check the signer's address \
-                     on a block explorer before continuing. Beware that timestamps \
-                     shown on explorer.solana.com can be off by weeks, check the slot \
-                     number to confirm whether a transaction is recent."
-                );
-            }
-            _ => {}
-        }
+    /// Sign and submit a transaction that was written with --output-transaction.
+    ///
+    /// If the signer needs to sign the transaction and did not sign yet, this
+    /// adds its signature. With --sign-only, this writes the signed transaction
+    /// back to the file, instead of submitting it, so the file can be moved to
+    /// the next signer, or to a machine that is online.
+    SubmitTransaction(SubmitTransactionOpts),
 
-        Ok(signature_result?)
-    }
-}
+    /// Print shell completions for the given shell.
+    ///
+    /// For example, for bash, add 'source <(solido completions bash)' to
+    /// ~/.bashrc.
+    Completions(CompletionsOpts),
 
-fn print_output<Output: fmt::Display + Serialize>(mode: OutputMode, output: &Output) {
-    match mode {
-        OutputMode::Text => println!("{}", output),
-        OutputMode::Json => {
-            let json_string =
-                serde_json::to_string_pretty(output).expect("Failed to serialize output as json.");
-            println!("{}", json_string);
-        }
-    }
+    /// Print a json description of all subcommands and their options.
+    ///
+    /// For every option, this includes the flag, the key in the config file,
+    /// the environment variable, and the default value if it has one, for
+    /// tooling that generates config files.
+    Manifest,
 }
 
 fn main() {
@@ -313,30 +595,136 @@ fn main() {
     let config_file = opts.merge_with_config_and_environment();
 
     solana_logger::setup_with_default("solana=info");
+    logging::set_format(opts.log_format.unwrap());
+    error::set_output_mode(opts.output_mode.unwrap());
 
     // Note, the unwraps below are safe, because `merge_with_config_and_environment`
     // ensures that all values are provided; it’s just that for the derived Clap
     // parser, the options are all optional.
-    let signer = if opts.keypair.as_ref().unwrap() == "" {
-        let payer_keypair_path = opts.keypair_path;
-        get_signer_from_path(payer_keypair_path.unwrap())
-            .ok_or_abort_with("Failed to load signer keypair.")
+    let output_transaction_path = match opts.output_transaction.unwrap() {
+        path if path.is_empty() => None,
+        path => Some(PathBuf::from(path)),
+    };
+    merge_with_config_and_environment(&mut opts.subcommand, config_file.as_ref());
+    // These describe the command line, they need no keypair or cluster.
+    match &opts.subcommand {
+        SubCommand::Completions(cmd_opts) => {
+            manifest::write_completions(
+                &mut Opts::into_app(),
+                "solido",
+                *cmd_opts.shell(),
+                &mut std::io::stdout(),
+            );
+            return;
+        }
+        SubCommand::Manifest => {
+            let manifest = manifest::get_command_manifest(&Opts::into_app(), &get_config_options);
+            let json = serde_json::to_string_pretty(&manifest)
+                .expect("Failed to serialize manifest as json.");
+            println!("{}", json);
+            return;
+        }
+        _ => {}
+    }
+    // An observer never signs, so it does not need a keypair, nor a fee payer.
+    let observe_only = matches!(
+        &opts.subcommand,
+        SubCommand::RunMaintainer(cmd_opts) if *cmd_opts.observe_only()
+    );
+    let boxed_signer: Box<dyn Signer> = if observe_only {
+        Box::new(NullSigner::new(&Pubkey::default()))
+    } else if opts.remote_signer_url.as_ref().unwrap() != "" {
+        Box::new(
+            RemoteSigner::connect(opts.remote_signer_url.as_ref().unwrap())
+                .ok_or_abort_with("Failed to connect to remote signer."),
+        )
+    } else if opts.keypair.as_ref().unwrap() == "" {
+        load_signer(
+            opts.keypair_path.unwrap(),
+            output_transaction_path.is_some(),
+        )
+        .ok_or_abort_with("Failed to load signer keypair.")
     } else {
         get_signer_from_key(opts.keypair.unwrap())
     };
+    // The daemon can switch to a new maintainer key when it reloads its config.
+    let signer = ReloadableSigner::new(boxed_signer);
+    let fee_payer: Option<Box<dyn Signer>> = match opts.fee_payer_keypair.unwrap() {
+        _ if observe_only => None,
+        path if path == PathBuf::default() => None,
+        path => Some(
+            load_signer(path, output_transaction_path.is_some())
+                .ok_or_abort_with("Failed to load fee payer keypair."),
+        ),
+    };
 
-    let rpc_client =
-        RpcClient::new_with_commitment(opts.cluster.unwrap(), CommitmentConfig::confirmed());
-    let snapshot_client = SnapshotClient::new(rpc_client);
+    let commitment = opts.commitment.unwrap();
+    let request_budget = RequestBudget {
+        requests_per_second: opts.rpc_requests_per_second.unwrap(),
+        burst: opts.rpc_burst.unwrap(),
+    };
+    let account_dump_path = opts.account_dump.unwrap();
+    let at_slot = opts.at_slot.unwrap();
+    let write_account_dump_path = opts.write_account_dump.unwrap();
+    let reads_account_dump = account_dump_path != PathBuf::default();
+    let writes_account_dump = write_account_dump_path != PathBuf::default();
+    // Account dumps only hold the accounts that a read-only command needs.
+    let supports_account_dump = matches!(
+        opts.subcommand,
+        SubCommand::ShowSolido(..)
+            | SubCommand::Verify(..)
+            | SubCommand::RewardsReport(..)
+            | SubCommand::ExplainMaintenance(..)
+    );
+    if (reads_account_dump || writes_account_dump || at_slot != 0) && !supports_account_dump {
+        Err(CliError::new(
+            "Only 'show-solido', 'verify', 'rewards-report', and 'explain-maintenance' \
+            support account dumps.",
+        ))
+        .ok_or_abort_with("Invalid options.")
+    }
+    if at_slot != 0 && !reads_account_dump {
+        Err(CliError::new(
+            "RPC nodes only serve the current state of accounts, and archive nodes \
+            keep blocks but not account state. To inspect the state at --at-slot, \
+            pass an --account-dump taken at that slot.",
+        ))
+        .ok_or_abort_with("Invalid options.")
+    }
+    let mut snapshot_client = if reads_account_dump {
+        new_dump_snapshot_client(
+            &account_dump_path,
+            at_slot,
+            opts.cluster.as_ref().unwrap(),
+            commitment,
+        )
+        .ok_or_abort_with("Failed to load account dump.")
+    } else {
+        new_snapshot_client(opts.cluster.as_ref().unwrap(), commitment, request_budget)
+    };
+    snapshot_client.set_record_accounts(writes_account_dump);
 
     let output_mode = opts.output_mode.unwrap();
+    let nonce = match opts.nonce_account.unwrap() {
+        account if account == Pubkey::default() => None,
+        account => Some(NonceConfig {
+            account,
+            authority: match opts.nonce_authority.unwrap() {
+                authority if authority == Pubkey::default() => signer.pubkey(),
+                authority => authority,
+            },
+        }),
+    };
+
     let mut config = Config {
         client: snapshot_client,
-        signer: &*signer,
+        signer: &signer,
+        fee_payer: fee_payer.as_deref().unwrap_or(&signer),
         output_mode,
+        output_transaction_path,
+        nonce,
     };
 
-    merge_with_config_and_environment(&mut opts.subcommand, config_file.as_ref());
     match opts.subcommand {
         SubCommand::CreateSolido(cmd_opts) => {
             let result = config.with_snapshot(|config| command_create_solido(config, &cmd_opts));
@@ -344,6 +732,17 @@ fn main() {
             print_output(output_mode, &output);
         }
         SubCommand::Multisig(cmd_opts) => multisig::main(&mut config, cmd_opts),
+        SubCommand::Governance(cmd_opts) => governance::main(&mut config, cmd_opts),
+        SubCommand::Validator(cmd_opts) => onboarding::main(&mut config, cmd_opts),
+        SubCommand::SubmitTransaction(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_submit_transaction(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to submit transaction.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::Completions(..) | SubCommand::Manifest => {
+            unreachable!("We handled these before loading the signer.")
+        }
         SubCommand::PerformMaintenance(cmd_opts) => {
             // This command only performs one iteration, `RunMaintainer` runs continuously.
             let result = config
@@ -358,8 +757,27 @@ fn main() {
                 (mode, Some(output)) => print_output(mode, &output),
             }
         }
+        SubCommand::ExplainMaintenance(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| maintenance::run_explain_maintenance(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to explain maintenance.");
+            write_account_dump(&mut config.client, &write_account_dump_path);
+            print_output(output_mode, &output);
+        }
         SubCommand::RunMaintainer(cmd_opts) => {
-            daemon::main(&mut config, &cmd_opts);
+            let instance_clients = cmd_opts
+                .instances()
+                .0
+                .iter()
+                .map(|instance| new_snapshot_client(&instance.cluster, commitment, request_budget))
+                .collect();
+            daemon::main(
+                config,
+                cmd_opts,
+                instance_clients,
+                &signer,
+                &reload_run_maintainer_opts,
+            );
         }
         SubCommand::AddValidator(cmd_opts) => {
             let result = config.with_snapshot(|config| command_add_validator(config, &cmd_opts));
@@ -372,6 +790,81 @@ fn main() {
             let output = result.ok_or_abort_with("Failed to deactivate validator.");
             print_output(output_mode, &output);
         }
+        SubCommand::ReplaceValidatorVoteAccount(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_replace_validator_vote_account(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to replace validator vote account.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::SetValidatorWeight(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_set_validator_weight(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to set validator weight.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::SetMaxStakePerValidator(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_set_max_stake_per_validator(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to set maximum stake per validator.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::SetMaxTotalSol(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_set_max_total_sol(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to set maximum total SOL.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ScheduleRewardDistribution(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_schedule_reward_distribution(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to schedule reward distribution.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::Pause(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_pause(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to pause Solido.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::Resume(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_resume(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to resume Solido.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::MigrateState(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_migrate_state(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to migrate Solido state.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::CreateDepositorAllowlist(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_create_depositor_allowlist(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to create depositor allowlist.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::AddAllowedDepositor(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_add_allowed_depositor(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to add allowed depositor.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::RemoveAllowedDepositor(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_remove_allowed_depositor(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to remove allowed depositor.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::EnableDepositorAllowlist(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_enable_depositor_allowlist(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to enable depositor allowlist.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::DisableDepositorAllowlist(cmd_opts) => {
+            let result = config
+                .with_snapshot(|config| command_disable_depositor_allowlist(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to disable depositor allowlist.");
+            print_output(output_mode, &output);
+        }
         SubCommand::AddMaintainer(cmd_opts) => {
             let result = config.with_snapshot(|config| command_add_maintainer(config, &cmd_opts));
             let output = result.ok_or_abort_with("Failed to add maintainer.");
@@ -383,9 +876,97 @@ fn main() {
             let output = result.ok_or_abort_with("Failed to remove maintainer.");
             print_output(output_mode, &output);
         }
+        SubCommand::RotateMaintainer(cmd_opts) => {
+            let result = command_rotate_maintainer(&mut config, &cmd_opts);
+            let output = result.ok_or_abort_with("Failed to rotate maintainer.");
+            print_output(output_mode, &output);
+        }
         SubCommand::ShowSolido(cmd_opts) => {
             let result = config.with_snapshot(|config| command_show_solido(config, &cmd_opts));
             let output = result.ok_or_abort_with("Failed to show Solido data.");
+            write_account_dump(&mut config.client, &write_account_dump_path);
+            print_output(output_mode, &output);
+        }
+        SubCommand::DiffState(cmd_opts) => {
+            let mut from_client = new_dump_snapshot_client(
+                cmd_opts.from_dump(),
+                *cmd_opts.from_slot(),
+                opts.cluster.as_ref().unwrap(),
+                commitment,
+            )
+            .ok_or_abort_with("Failed to load --from-dump.");
+            let mut to_client = if cmd_opts.to_dump() == &PathBuf::default() {
+                if *cmd_opts.to_slot() != 0 {
+                    Err(CliError::new(
+                        "RPC nodes only serve the current state of accounts. To compare to \
+                        the state at --to-slot, pass a --to-dump taken at that slot.",
+                    ))
+                    .ok_or_abort_with("Invalid options.")
+                }
+                None
+            } else {
+                Some(
+                    new_dump_snapshot_client(
+                        cmd_opts.to_dump(),
+                        *cmd_opts.to_slot(),
+                        opts.cluster.as_ref().unwrap(),
+                        commitment,
+                    )
+                    .ok_or_abort_with("Failed to load --to-dump."),
+                )
+            };
+            let from = SolidoAtSlot::read(&mut from_client, cmd_opts.solido_address())
+                .ok_or_abort_with("Failed to read the state to compare from.");
+            let to = SolidoAtSlot::read(
+                to_client.as_mut().unwrap_or(&mut config.client),
+                cmd_opts.solido_address(),
+            )
+            .ok_or_abort_with("Failed to read the state to compare to.");
+            print_output(output_mode, &StateDiff::new(&from, &to));
+        }
+        SubCommand::Top(cmd_opts) => top::main(&mut config, &cmd_opts),
+        SubCommand::Index(cmd_opts) => index::main(&mut config, &cmd_opts),
+        SubCommand::ShowStakePool(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_show_stake_pool(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to show stake pool view.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::RewardsReport(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_rewards_report(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to create rewards report.");
+            write_account_dump(&mut config.client, &write_account_dump_path);
+            print_output(output_mode, &output);
+        }
+        SubCommand::Verify(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_verify(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to verify Solido.");
+            write_account_dump(&mut config.client, &write_account_dump_path);
+            print_output(output_mode, &output);
+            if !output.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        SubCommand::ShowStakeAccounts(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_show_stake_accounts(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to show stake accounts.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ShowValidators(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_show_validators(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to show validators.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ShowKeeperSchedule(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_show_keeper_schedule(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to show keeper schedule.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ApplyValidatorPolicy(cmd_opts) => {
+            let result =
+                config.with_snapshot(|config| command_apply_validator_policy(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to apply validator policy.");
             print_output(output_mode, &output);
         }
         SubCommand::ShowAuthorities(solido_pubkey) => {
@@ -395,16 +976,162 @@ fn main() {
                 result.ok_or_abort_with("Failed to show authorities for Solido public key.");
             print_output(output_mode, &output);
         }
+        SubCommand::ExportIdl(cmd_opts) => {
+            // The IDL is a json document, regardless of `--output`.
+            let idl = command_export_idl(&cmd_opts);
+            let json =
+                serde_json::to_string_pretty(&idl).expect("Failed to serialize IDL as json.");
+            println!("{}", json);
+        }
         SubCommand::Deposit(cmd_opts) => {
             let result = command_deposit(&mut config, &cmd_opts);
             let output = result.ok_or_abort_with("Failed to deposit.");
             print_output(output_mode, &output);
         }
+        SubCommand::DepositStakeAccount(cmd_opts) => {
+            let result = command_deposit_stake_account(&mut config, &cmd_opts);
+            let output = result.ok_or_abort_with("Failed to deposit stake account.");
+            print_output(output_mode, &output);
+        }
         SubCommand::Withdraw(cmd_opts) => {
             let result = command_withdraw(&mut config, &cmd_opts);
             let output = result.ok_or_abort_with("Failed to withdraw.");
             print_output(output_mode, &output);
         }
+        SubCommand::CreateWithdrawTicket(cmd_opts) => {
+            let result = command_create_withdraw_ticket(&mut config, &cmd_opts);
+            let output = result.ok_or_abort_with("Failed to create withdraw ticket.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::ClaimWithdrawTicket(cmd_opts) => {
+            let result = command_claim_withdraw_ticket(&mut config, &cmd_opts);
+            let output = result.ok_or_abort_with("Failed to claim withdraw ticket.");
+            print_output(output_mode, &output);
+        }
+    }
+}
+
+/// Load a signer from a keypair path, or a usb://ledger uri.
+///
+/// When we only build a transaction, we don't need the private key, so then
+/// the path can also be a public key.
+fn load_signer(path: PathBuf, only_build_transaction: bool) -> Result<Box<dyn Signer>, Error> {
+    match path.to_str().and_then(|p| Pubkey::from_str(p).ok()) {
+        Some(pubkey) if only_build_transaction => Ok(Box::new(NullSigner::new(&pubkey))),
+        _ => get_signer_from_path(path),
+    }
+}
+
+/// Return a client for every url in the comma-separated `--cluster`.
+fn new_rpc_clients(cluster: &str, commitment: Commitment) -> Vec<RpcClient> {
+    cluster
+        .split(',')
+        .map(|url| RpcClient::new_with_commitment(url.trim().to_string(), commitment.to_config()))
+        .collect()
+}
+
+fn new_snapshot_client(
+    cluster: &str,
+    commitment: Commitment,
+    request_budget: RequestBudget,
+) -> SnapshotClient {
+    let mut snapshot_client =
+        SnapshotClient::new_with_endpoints(new_rpc_clients(cluster, commitment));
+    snapshot_client.set_commitment(commitment);
+    snapshot_client.set_request_budget(request_budget);
+    snapshot_client
+}
+
+/// Return a client that reads accounts from the dump at `path`, see `--account-dump`.
+fn new_dump_snapshot_client(
+    path: &Path,
+    at_slot: u64,
+    cluster: &str,
+    commitment: Commitment,
+) -> Result<SnapshotClient, Error> {
+    let dump = AccountDump::read(path)?;
+    if at_slot != 0 && dump.slot != at_slot {
+        return Err(CliError::with_cause(
+            "The account dump is not at the requested slot.",
+            format!("The dump is at slot {}, not at {}.", dump.slot, at_slot),
+        ));
+    }
+    // We only read inflation rewards from the cluster, the first endpoint will do.
+    let archive = new_rpc_clients(cluster, commitment).remove(0);
+    Ok(SnapshotClient::new(DumpRpc::new(&dump, archive)?))
+}
+
+/// Write the accounts of the last snapshot, if `--write-account-dump` is set.
+fn write_account_dump(client: &mut SnapshotClient, path: &Path) {
+    if let Some(dump) = client.take_account_dump() {
+        dump.write(path)
+            .ok_or_abort_with("Failed to write account dump.");
+    }
+}
+
+/// Read the options for `run-maintainer` again, when the daemon receives SIGHUP.
+///
+/// Like at startup, options on the command line take precedence over the
+/// config file and environment, but unlike at startup, we return an error
+/// instead of exiting when they are invalid.
+fn reload_run_maintainer_opts() -> Result<daemon::ReloadedOpts, Error> {
+    let opts = Opts::try_parse()
+        .map_err(|err| CliError::with_cause("Failed to parse the command line.", err))?;
+    let config_file = match opts.config.as_ref() {
+        Some(path) => Some(try_read_config(path)?),
+        None => None,
+    };
+    let merge_problems =
+        |problems: Vec<String>| CliError::with_cause("Invalid config.", problems.join(" "));
+    if let Some(config_file) = &config_file {
+        validate_config_file(config_file, &get_all_config_options()).map_err(merge_problems)?;
+    }
+
+    let mut general_opts = GeneralOpts::default();
+    general_opts
+        .try_merge_with_config_and_environment(config_file.as_ref())
+        .map_err(merge_problems)?;
+    let cluster = opts
+        .cluster
+        .unwrap_or_else(|| general_opts.cluster().to_owned());
+    let commitment = opts
+        .commitment
+        .unwrap_or_else(|| *general_opts.commitment());
+
+    // Only a keypair file can be loaded again; a remote signer or an inline
+    // --keypair keeps the key that the daemon started with.
+    let remote_signer_url = opts
+        .remote_signer_url
+        .unwrap_or_else(|| general_opts.remote_signer_url().to_owned());
+    let keypair = opts
+        .keypair
+        .unwrap_or_else(|| general_opts.keypair().to_owned());
+    let keypair_path = opts
+        .keypair_path
+        .unwrap_or_else(|| general_opts.keypair_path().to_owned());
+
+    match opts.subcommand {
+        SubCommand::RunMaintainer(mut cmd_opts) => {
+            cmd_opts
+                .try_merge_with_config_and_environment(config_file.as_ref())
+                .map_err(merge_problems)?;
+            // An observer has no key to load again.
+            let signer = if remote_signer_url.is_empty()
+                && keypair.is_empty()
+                && !*cmd_opts.observe_only()
+            {
+                Some(get_signer_from_path(keypair_path)?)
+            } else {
+                None
+            };
+            Ok(daemon::ReloadedOpts {
+                opts: cmd_opts,
+                rpc_clients: new_rpc_clients(&cluster, commitment),
+                commitment,
+                signer,
+            })
+        }
+        _ => unreachable!("Only run-maintainer reloads its options."),
     }
 }
 
@@ -418,169 +1145,127 @@ fn merge_with_config_and_environment(
         SubCommand::DeactivateValidator(opts) => {
             opts.merge_with_config_and_environment(config_file)
         }
+        SubCommand::ReplaceValidatorVoteAccount(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::SetValidatorWeight(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::SetMaxStakePerValidator(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::SetMaxTotalSol(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ScheduleRewardDistribution(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::Pause(opts) | SubCommand::Resume(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::MigrateState(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::CreateDepositorAllowlist(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::AddAllowedDepositor(opts) | SubCommand::RemoveAllowedDepositor(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::EnableDepositorAllowlist(opts)
+        | SubCommand::DisableDepositorAllowlist(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
         SubCommand::AddMaintainer(opts) | SubCommand::RemoveMaintainer(opts) => {
             opts.merge_with_config_and_environment(config_file)
         }
+        SubCommand::RotateMaintainer(opts) => opts.merge_with_config_and_environment(config_file),
         SubCommand::Deposit(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::DepositStakeAccount(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
         SubCommand::Withdraw(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::CreateWithdrawTicket(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::ClaimWithdrawTicket(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
         SubCommand::ShowSolido(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::DiffState(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Top(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Index(opts) => opts.merge_with_config_and_environment(config_file),
         SubCommand::ShowAuthorities(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ShowStakePool(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::RewardsReport(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Verify(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ShowStakeAccounts(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ShowValidators(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ShowKeeperSchedule(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ApplyValidatorPolicy(opts) => {
+            opts.merge_with_config_and_environment(config_file)
+        }
+        SubCommand::ExportIdl(opts) => opts.merge_with_config_and_environment(config_file),
         SubCommand::PerformMaintenance(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::ExplainMaintenance(opts) => opts.merge_with_config_and_environment(config_file),
         SubCommand::Multisig(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Governance(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Validator(opts) => opts.merge_with_config_and_environment(config_file),
         SubCommand::RunMaintainer(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::SubmitTransaction(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Completions(opts) => opts.merge_with_config_and_environment(config_file),
+        SubCommand::Manifest => {}
     }
 }
 
-/// Parse a keypair path of the form "usb://ledger?key=0".
-pub fn parse_remote_wallet_details(uri: &str) -> Result<(DerivationPath, Locator), Error> {
-    use std::convert::TryFrom;
-    use uriparse::uri_reference::URIReference;
-
-    let uri_invalid_msg =
-        "Failed to parse usb:// keypair path. It must be of the form 'usb://ledger?key=0'.";
-
-    let uri_ref =
-        URIReference::try_from(uri).map_err(|err| CliError::with_cause(uri_invalid_msg, err))?;
-
-    let derivation_path = DerivationPath::from_uri_key_query(&uri_ref)
-        .map_err(|err| CliError::with_cause(uri_invalid_msg, err))?
-        // If there is no ?key= query parameter, then use the default derivation path.
-        .unwrap_or_default();
-
-    let locator = Locator::new_from_uri(&uri_ref)
-        .map_err(|err| CliError::with_cause(uri_invalid_msg, err))?;
-
-    Ok((derivation_path, locator))
+/// Return the config options of all subcommands, to validate the config file against.
+fn get_all_config_options() -> Vec<ConfigOption> {
+    manifest::get_all_config_options(&Opts::into_app(), &get_config_options)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_parse_remote_wallet_details() {
-        use derivation_path::ChildIndex;
-
-        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger").ok().unwrap();
-        // /'44/'501 is added by default for all Solana derivation paths.
-        assert_eq!(
-            derivation_path.path(),
-            [ChildIndex::Hardened(44), ChildIndex::Hardened(501)]
-        );
-
-        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger?key=0")
-            .ok()
-            .unwrap();
-        assert_eq!(
-            derivation_path.path(),
-            [
-                ChildIndex::Hardened(44),
-                ChildIndex::Hardened(501),
-                ChildIndex::Hardened(0)
-            ]
-        );
-
-        let (derivation_path, _) = parse_remote_wallet_details("usb://ledger?key=0/1")
-            .ok()
-            .unwrap();
-        assert_eq!(
-            derivation_path.path(),
-            [
-                ChildIndex::Hardened(44),
-                ChildIndex::Hardened(501),
-                ChildIndex::Hardened(0),
-                ChildIndex::Hardened(1)
-            ]
-        );
-
-        let (derivation_path, _) = parse_remote_wallet_details(
-            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK",
-        )
-        .ok()
-        .unwrap();
-        assert_eq!(
-            derivation_path.path(),
-            [ChildIndex::Hardened(44), ChildIndex::Hardened(501)]
-        );
-
-        let (derivation_path, _) = parse_remote_wallet_details(
-            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK?key=2",
-        )
-        .ok()
-        .unwrap();
-        assert_eq!(
-            derivation_path.path(),
-            [
-                ChildIndex::Hardened(44),
-                ChildIndex::Hardened(501),
-                ChildIndex::Hardened(2)
-            ]
-        );
-
-        let (derivation_path, _) = parse_remote_wallet_details(
-            "usb://ledger/BsNsvfXqQTtJnagwFWdBS7FBXgnsK8VZ5CmuznN85swK?key=2/3",
-        )
-        .ok()
-        .unwrap();
-        assert_eq!(
-            derivation_path.path(),
-            [
-                ChildIndex::Hardened(44),
-                ChildIndex::Hardened(501),
-                ChildIndex::Hardened(2),
-                ChildIndex::Hardened(3)
-            ]
-        );
-
-        assert!(parse_remote_wallet_details("usb://ledger?key=not-an-integer").is_err());
-        assert!(parse_remote_wallet_details("usb://ledger?foo=bar").is_err());
-        assert!(parse_remote_wallet_details("usb://ledger/not-a-key").is_err());
+/// Return the config options of the subcommand at `path`, for `solido manifest`.
+fn get_config_options(path: &[&str]) -> Vec<ConfigOption> {
+    match path {
+        [] => GeneralOpts::config_options(),
+        ["create-solido"] => CreateSolidoOpts::config_options(),
+        ["add-validator"] => AddValidatorOpts::config_options(),
+        ["deactivate-validator"] => DeactivateValidatorOpts::config_options(),
+        ["replace-validator-vote-account"] => ReplaceValidatorVoteAccountOpts::config_options(),
+        ["set-validator-weight"] => SetValidatorWeightOpts::config_options(),
+        ["set-max-stake-per-validator"] => SetMaxStakePerValidatorOpts::config_options(),
+        ["set-max-total-sol"] => SetMaxTotalSolOpts::config_options(),
+        ["schedule-reward-distribution"] => ScheduleRewardDistributionOpts::config_options(),
+        ["pause"] | ["resume"] => PauseResumeOpts::config_options(),
+        ["migrate-state"] => MigrateStateOpts::config_options(),
+        ["create-depositor-allowlist"] => CreateDepositorAllowlistOpts::config_options(),
+        ["add-allowed-depositor"] | ["remove-allowed-depositor"] => {
+            AddRemoveAllowedDepositorOpts::config_options()
+        }
+        ["enable-depositor-allowlist"] | ["disable-depositor-allowlist"] => {
+            EnableDisableDepositorAllowlistOpts::config_options()
+        }
+        ["add-maintainer"] | ["remove-maintainer"] => AddRemoveMaintainerOpts::config_options(),
+        ["rotate-maintainer"] => RotateMaintainerOpts::config_options(),
+        ["deposit"] => DepositOpts::config_options(),
+        ["deposit-stake-account"] => DepositStakeAccountOpts::config_options(),
+        ["withdraw"] => WithdrawOpts::config_options(),
+        ["create-withdraw-ticket"] => CreateWithdrawTicketOpts::config_options(),
+        ["claim-withdraw-ticket"] => ClaimWithdrawTicketOpts::config_options(),
+        ["show-solido"] => ShowSolidoOpts::config_options(),
+        ["diff-state"] => DiffStateOpts::config_options(),
+        ["top"] => TopOpts::config_options(),
+        ["index"] => IndexOpts::config_options(),
+        ["show-authorities"] => ShowSolidoAuthoritiesOpts::config_options(),
+        ["show-stake-pool"] => ShowStakePoolOpts::config_options(),
+        ["rewards-report"] => RewardsReportOpts::config_options(),
+        ["verify"] => VerifyOpts::config_options(),
+        ["show-stake-accounts"] => ShowStakeAccountsOpts::config_options(),
+        ["show-validators"] => ShowValidatorsOpts::config_options(),
+        ["show-keeper-schedule"] => ShowKeeperScheduleOpts::config_options(),
+        ["apply-validator-policy"] => ApplyValidatorPolicyOpts::config_options(),
+        ["export-idl"] => ExportIdlOpts::config_options(),
+        ["perform-maintenance"] => PerformMaintenanceOpts::config_options(),
+        ["explain-maintenance"] => ExplainMaintenanceOpts::config_options(),
+        ["run-maintainer"] => RunMaintainerOpts::config_options(),
+        ["submit-transaction"] => SubmitTransactionOpts::config_options(),
+        ["completions"] => CompletionsOpts::config_options(),
+        ["multisig", rest @ ..] => MultisigOpts::config_options(rest),
+        ["governance", rest @ ..] => GovernanceOpts::config_options(rest),
+        ["validator", rest @ ..] => ValidatorOpts::config_options(rest),
+        _ => Vec::new(),
     }
 }
-
-fn get_signer_from_key(key_json: String) -> Box<dyn Signer> {
-    use std::io::Cursor;
-    let mut cursor = Cursor::new(key_json.as_bytes());
-    Box::new(
-        read_keypair(&mut cursor)
-            .expect("Failed to deserialize keypair. Is it a json array of numbers?"),
-    )
-}
-
-// Get a boxed signer that lives long enough for us to use it in the Config.
-fn get_signer_from_path(payer_keypair_path: PathBuf) -> Result<Box<dyn Signer>, Error> {
-    let boxed_signer: Box<dyn Signer> = if payer_keypair_path.starts_with("usb://") {
-        let uri = payer_keypair_path
-            .into_os_string()
-            .into_string()
-            .map_err(|_| {
-                CliError::new("A keypair path that starts with usb:// must be valid UTF-8.")
-            })?;
-        // Parse the uri before we try to connect, so we can diagnose uri format issues early.
-        let (derivation_path, locator) = parse_remote_wallet_details(&uri)?;
-        let hw_wallet = maybe_wallet_manager()
-            .map_err(|err| CliError::with_cause("Remote wallet found, but failed to establish protocol. Maybe the Solana app is not open.", err))?
-            .ok_or_else(|| CliError::new("Failed to find a remote wallet, maybe Ledger is not connected or locked."))?;
-
-        // When using a Ledger hardware wallet, confirm the public key of the
-        // key to sign with on its display, so users can be sure that they
-        // selected the right key.
-        let confirm_public_key = true;
-
-        Box::new(
-            generate_remote_keypair(
-                locator,
-                derivation_path,
-                &hw_wallet,
-                confirm_public_key,
-                "Solido", /* When multiple wallets are connected, used to display a hint */
-            )
-            .expect("Failed to contact remote wallet"),
-        )
-    } else {
-        Box::new(
-            read_keypair_file(&payer_keypair_path).expect("Failed to read key pair from file."),
-        )
-    };
-    Ok(boxed_signer)
-}
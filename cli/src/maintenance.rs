@@ -5,18 +5,32 @@
 
 use std::fmt;
 use std::io;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+use anchor_lang::AccountDeserialize;
+use borsh::BorshDeserialize;
 use itertools::izip;
 
+use lido::error::LidoError;
 use lido::processor::StakeType;
 use lido::token;
 use lido::REWARDS_WITHDRAW_AUTHORITY;
 use serde::Serialize;
-use solana_program::program_pack::Pack;
-use solana_program::{clock::Clock, pubkey::Pubkey, rent::Rent, stake_history::StakeHistory};
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_program::{
+    clock::{Clock, Epoch, Slot, DEFAULT_MS_PER_SLOT},
+    pubkey::Pubkey,
+    rent::Rent,
+    stake_history::StakeHistory,
+};
 use solana_sdk::account::ReadableAccount;
+use solana_sdk::epoch_schedule::EpochSchedule;
 use solana_sdk::fee_calculator::DEFAULT_TARGET_LAMPORTS_PER_SIGNATURE;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use solana_sdk::{account::Account, instruction::Instruction};
 use spl_token::state::Mint;
 
@@ -27,14 +41,29 @@ use lido::{
     util::serialize_b58,
 };
 use lido::{
-    state::{Lido, Validator},
+    state::{ExchangeRateOracle, Lido, MaintainerLease, Validator, WithdrawTicket},
     token::Lamports,
     MINIMUM_STAKE_ACCOUNT_BALANCE, STAKE_AUTHORITY,
 };
 
-use crate::error::MaintenanceError;
-use crate::snapshot::Result;
-use crate::{config::PerformMaintenanceOpts, SnapshotConfig};
+use crate::config::{ExplainMaintenanceOpts, PerformMaintenanceOpts};
+use crate::derivation_cache;
+use crate::error::{Error, MaintenanceError, SerializationError};
+use crate::governance::{self, ManagerGovernance};
+use crate::keeper::is_keeper_task;
+use crate::logging::Event;
+use crate::mev::{get_validator_mev, ValidatorMev};
+use crate::multisig::sign_propose_instruction;
+use crate::priority_fee::{
+    get_dynamic_price, get_priority_fee, get_writable_accounts, set_compute_unit_price,
+    PriorityFeeOptions,
+};
+use crate::rpc::AccountFilter;
+use crate::snapshot::{Result, SnapshotError};
+use crate::validator_info::{
+    get_validator_identity, get_vote_account_state, ValidatorIdentity, VoteAccountState,
+};
+use crate::{SnapshotClientConfig, SnapshotConfig};
 
 /// A brief description of the maintenance performed. Not relevant functionally,
 /// but helpful for automated testing, and just for info.
@@ -84,6 +113,9 @@ pub enum MaintenanceOutput {
         fee_rewards: StLamports,
     },
 
+    /// Merge the stake accounts with seeds `from_stake_seed` up to
+    /// `to_stake_seed` into the one at `to_stake_seed`, one `MergeStake`
+    /// instruction per account, in a single transaction.
     MergeStake {
         #[serde(serialize_with = "serialize_b58")]
         validator_vote_account: Pubkey,
@@ -106,10 +138,125 @@ pub enum MaintenanceOutput {
         to_unstake_seed: u64,
         amount: Lamports,
     },
+
+    RebalanceUnstake {
+        #[serde(serialize_with = "serialize_b58")]
+        validator_vote_account: Pubkey,
+        #[serde(serialize_with = "serialize_b58")]
+        from_stake_account: Pubkey,
+        #[serde(serialize_with = "serialize_b58")]
+        to_unstake_account: Pubkey,
+        from_stake_seed: u64,
+        to_unstake_seed: u64,
+        amount: Lamports,
+    },
+
     RemoveValidator {
         #[serde(serialize_with = "serialize_b58")]
         validator_vote_account: Pubkey,
     },
+
+    UnstakeForWithdrawTickets {
+        #[serde(serialize_with = "serialize_b58")]
+        validator_vote_account: Pubkey,
+        #[serde(serialize_with = "serialize_b58")]
+        from_stake_account: Pubkey,
+        #[serde(serialize_with = "serialize_b58")]
+        to_unstake_account: Pubkey,
+        from_stake_seed: u64,
+        to_unstake_seed: u64,
+        amount: Lamports,
+    },
+
+    FundWithdrawTicket {
+        #[serde(serialize_with = "serialize_b58")]
+        withdraw_ticket: Pubkey,
+        ticket_id: u64,
+        #[serde(rename = "amount_lamports")]
+        amount: Lamports,
+    },
+
+    PublishExchangeRate {
+        #[serde(serialize_with = "serialize_b58")]
+        exchange_rate_oracle: Pubkey,
+
+        /// The epoch in which the published exchange rate was computed.
+        computed_in_epoch: Epoch,
+    },
+
+    AcquireMaintainerLease {
+        #[serde(serialize_with = "serialize_b58")]
+        maintainer_lease: Pubkey,
+
+        /// The first slot in which the acquired or renewed lease is no longer valid.
+        expires_slot: Slot,
+    },
+
+    DeactivateValidator {
+        #[serde(serialize_with = "serialize_b58")]
+        validator_vote_account: Pubkey,
+
+        /// Number of consecutive epochs in which the validator earned no credits.
+        delinquent_epochs: u64,
+    },
+
+    SweepToReserve {
+        #[serde(serialize_with = "serialize_b58")]
+        account: Pubkey,
+        #[serde(rename = "amount_lamports")]
+        amount: Lamports,
+    },
+}
+
+impl MaintenanceOutput {
+    /// Return the name of the instruction that performs this maintenance.
+    pub fn operation_name(&self) -> &'static str {
+        match self {
+            MaintenanceOutput::StakeDeposit { .. } => "StakeDeposit",
+            MaintenanceOutput::UpdateExchangeRate => "UpdateExchangeRate",
+            MaintenanceOutput::WithdrawInactiveStake { .. } => "WithdrawInactiveStake",
+            MaintenanceOutput::CollectValidatorFee { .. } => "CollectValidatorFee",
+            MaintenanceOutput::ClaimValidatorFee { .. } => "ClaimValidatorFee",
+            MaintenanceOutput::MergeStake { .. } => "MergeStake",
+            MaintenanceOutput::UnstakeFromInactiveValidator { .. } => "Unstake",
+            MaintenanceOutput::RebalanceUnstake { .. } => "Rebalance",
+            MaintenanceOutput::RemoveValidator { .. } => "RemoveValidator",
+            MaintenanceOutput::UnstakeForWithdrawTickets { .. } => "Unstake",
+            MaintenanceOutput::FundWithdrawTicket { .. } => "FundWithdrawTicket",
+            MaintenanceOutput::PublishExchangeRate { .. } => "PublishExchangeRate",
+            MaintenanceOutput::AcquireMaintainerLease { .. } => "AcquireMaintainerLease",
+            MaintenanceOutput::DeactivateValidator { .. } => "DeactivateValidator",
+            MaintenanceOutput::SweepToReserve { .. } => "SweepToReserve",
+        }
+    }
+
+    /// Return the accounts that this maintenance closes, so we don't need to read them again.
+    pub fn get_closed_accounts(
+        &self,
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+    ) -> Vec<Pubkey> {
+        match self {
+            MaintenanceOutput::MergeStake {
+                validator_vote_account,
+                from_stake_seed,
+                to_stake_seed,
+                ..
+            } => (*from_stake_seed..*to_stake_seed)
+                .map(|seed| {
+                    derivation_cache::find_stake_account_address(
+                        validator_vote_account,
+                        solido_program_id,
+                        solido_address,
+                        seed,
+                        StakeType::Stake,
+                    )
+                    .0
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl fmt::Display for MaintenanceOutput {
@@ -176,6 +323,11 @@ impl fmt::Display for MaintenanceOutput {
             } => {
                 writeln!(f, "Stake accounts merged")?;
                 writeln!(f, "  Validator vote account: {}", validator_vote_account)?;
+                writeln!(
+                    f,
+                    "  Merges:                 {}",
+                    to_stake_seed - from_stake_seed
+                )?;
                 writeln!(
                     f,
                     "  From stake:             {}, seed: {}",
@@ -209,12 +361,98 @@ impl fmt::Display for MaintenanceOutput {
                 )?;
                 writeln!(f, "  Amount:              {}", amount)?;
             }
+            MaintenanceOutput::RebalanceUnstake {
+                validator_vote_account,
+                from_stake_account,
+                to_unstake_account,
+                from_stake_seed,
+                to_unstake_seed,
+                amount,
+            } => {
+                writeln!(f, "Unstake from validator above its target")?;
+                writeln!(f, "  Validator vote account: {}", validator_vote_account)?;
+                writeln!(
+                    f,
+                    "  Stake account:          {}, seed: {}",
+                    from_stake_account, from_stake_seed
+                )?;
+                writeln!(
+                    f,
+                    "  Unstake account:        {}, seed: {}",
+                    to_unstake_account, to_unstake_seed
+                )?;
+                writeln!(f, "  Amount:                 {}", amount)?;
+            }
             MaintenanceOutput::RemoveValidator {
                 validator_vote_account,
             } => {
                 writeln!(f, "Remove validator")?;
                 writeln!(f, "  Validator vote account: {}", validator_vote_account)?;
             }
+            MaintenanceOutput::UnstakeForWithdrawTickets {
+                validator_vote_account,
+                from_stake_account,
+                to_unstake_account,
+                from_stake_seed,
+                to_unstake_seed,
+                amount,
+            } => {
+                writeln!(f, "Unstake for withdraw tickets")?;
+                writeln!(f, "  Validator vote account: {}", validator_vote_account)?;
+                writeln!(
+                    f,
+                    "  Stake account:          {}, seed: {}",
+                    from_stake_account, from_stake_seed
+                )?;
+                writeln!(
+                    f,
+                    "  Unstake account:        {}, seed: {}",
+                    to_unstake_account, to_unstake_seed
+                )?;
+                writeln!(f, "  Amount:                 {}", amount)?;
+            }
+            MaintenanceOutput::FundWithdrawTicket {
+                withdraw_ticket,
+                ticket_id,
+                amount,
+            } => {
+                writeln!(f, "Fund withdraw ticket")?;
+                writeln!(
+                    f,
+                    "  Withdraw ticket: {}, id: {}",
+                    withdraw_ticket, ticket_id
+                )?;
+                writeln!(f, "  Amount:          {}", amount)?;
+            }
+            MaintenanceOutput::PublishExchangeRate {
+                exchange_rate_oracle,
+                computed_in_epoch,
+            } => {
+                writeln!(f, "Publish exchange rate")?;
+                writeln!(f, "  Exchange rate oracle: {}", exchange_rate_oracle)?;
+                writeln!(f, "  Computed in epoch:    {}", computed_in_epoch)?;
+            }
+            MaintenanceOutput::AcquireMaintainerLease {
+                maintainer_lease,
+                expires_slot,
+            } => {
+                writeln!(f, "Acquire maintainer lease")?;
+                writeln!(f, "  Maintainer lease: {}", maintainer_lease)?;
+                writeln!(f, "  Expires in slot:  {}", expires_slot)?;
+            }
+            MaintenanceOutput::DeactivateValidator {
+                validator_vote_account,
+                delinquent_epochs,
+            } => {
+                writeln!(f, "Deactivate delinquent validator")?;
+                writeln!(f, "  Validator vote account: {}", validator_vote_account)?;
+                writeln!(f, "  Epochs without credits: {}", delinquent_epochs)?;
+            }
+            MaintenanceOutput::SweepToReserve { account, amount } => {
+                writeln!(f, "Sweep to reserve")?;
+                writeln!(f, "  Account: {}", account)?;
+                writeln!(f, "  Amount:  {}", amount)?;
+            }
         }
         Ok(())
     }
@@ -250,14 +488,49 @@ pub struct SolidoState {
     /// For each validator, in the same order as in `solido.validators`, holds
     /// the number of Lamports of the validator's vote account.
     pub validator_vote_account_balances: Vec<Lamports>,
+    /// For each validator, in the same order as in `solido.validators`, holds
+    /// the MEV tips that the validator earned, if it runs Jito.
+    pub validator_mev: Vec<ValidatorMev>,
+    /// For each validator, in the same order as in `solido.validators`, holds
+    /// the identity, commission, and name that the validator published.
+    pub validator_identities: Vec<ValidatorIdentity>,
+    /// For each validator, in the same order as in `solido.validators`, holds
+    /// the settings and recent voting record from the vote account, if we
+    /// could parse it.
+    pub validator_vote_accounts: Vec<Option<VoteAccountState>>,
+
+    /// The vote accounts of the cluster's superminority, when we avoid
+    /// staking with it. See `MaintenanceOptions::avoid_superminority`.
+    pub superminority: Option<Vec<Pubkey>>,
 
     /// SPL token mint for stSOL, to know the current supply.
     pub st_sol_mint: Mint,
 
+    /// The exchange rate as last published with `PublishExchangeRate`, if it
+    /// has ever been published.
+    pub exchange_rate_oracle: Option<ExchangeRateOracle>,
+
+    /// The lease as last written with `AcquireMaintainerLease`, if any
+    /// maintainer ever acquired it. It may have expired.
+    pub maintainer_lease: Option<MaintainerLease>,
+
+    /// The next ticket in the withdraw queue to fund, and its address, if
+    /// there are unfunded tickets.
+    pub withdraw_ticket_to_fund: Option<(Pubkey, WithdrawTicket)>,
+
+    /// The auxiliary accounts that `SweepToReserve` accepts, and the SOL that
+    /// they hold above their rent-exempt balance. See `get_sweepable_balances`.
+    pub sweepable_balances: Vec<(Pubkey, Lamports)>,
+
     pub reserve_address: Pubkey,
     pub reserve_account: Account,
     pub rent: Rent,
     pub clock: Clock,
+    pub epoch_schedule: EpochSchedule,
+
+    /// The slot at which we read the accounts, according to the RPC, see
+    /// [`Snapshot::slot`](crate::snapshot::Snapshot::slot).
+    pub snapshot_slot: Slot,
 
     /// Public key of the maintainer executing the maintenance.
     /// Must be a member of `solido.maintainers`.
@@ -265,6 +538,13 @@ pub struct SolidoState {
 
     /// Current state of the maintainer account.
     pub maintainer_account: Account,
+
+    /// Public key of the account that pays the transaction fees. This is the
+    /// maintainer, unless a separate fee payer is configured.
+    pub fee_payer_address: Pubkey,
+
+    /// Balance of the fee payer, zero if the account does not exist.
+    pub fee_payer_balance: Lamports,
 }
 
 fn get_validator_stake_accounts(
@@ -282,7 +562,8 @@ fn get_validator_stake_accounts(
         StakeType::Unstake => &validator.entry.unstake_seeds,
     };
     for seed in seeds {
-        let (addr, _bump_seed) = validator.find_stake_account_address(
+        let (addr, _bump_seed) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
             solido_program_id,
             solido_address,
             seed,
@@ -324,11 +605,140 @@ fn get_vote_account_balance_except_rent(
     )
 }
 
+/// Read a program-derived account that holds `T`, if it exists.
+pub fn get_program_derived_state<T: BorshDeserialize>(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    address: Pubkey,
+    context: &str,
+) -> Result<Option<T>> {
+    if !config.client.account_exists(&address)? {
+        return Ok(None);
+    }
+    let account = config.client.get_account(&address)?;
+    // Until the program creates the account, anybody could send lamports to
+    // the address, but it only contains state once the program owns it.
+    if &account.owner != solido_program_id {
+        return Ok(None);
+    }
+    match T::try_from_slice(&account.data) {
+        Ok(state) => Ok(Some(state)),
+        Err(err) => {
+            let error: Error = Box::new(SerializationError {
+                cause: err.into(),
+                address,
+                context: context.to_string(),
+            });
+            Err(error.into())
+        }
+    }
+}
+
+/// Read the exchange rate oracle account, if it exists.
+fn get_exchange_rate_oracle(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+) -> Result<Option<ExchangeRateOracle>> {
+    let (address, _bump_seed) = ExchangeRateOracle::find_address(solido_program_id, solido_address);
+    get_program_derived_state(
+        config,
+        solido_program_id,
+        address,
+        "Failed to deserialize exchange rate oracle.",
+    )
+}
+
+/// Read the maintainer lease account, if it exists.
+fn get_maintainer_lease(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+) -> Result<Option<MaintainerLease>> {
+    let (address, _bump_seed) = MaintainerLease::find_address(solido_program_id, solido_address);
+    get_program_derived_state(
+        config,
+        solido_program_id,
+        address,
+        "Failed to deserialize maintainer lease.",
+    )
+}
+
+/// Read the balance above rent of the auxiliary accounts that `SweepToReserve`
+/// can move into the reserve, for the ones that exist.
+///
+/// These accounts need no SOL besides their rent, but anybody can send SOL to
+/// them, and it would otherwise sit there without earning rewards.
+fn get_sweepable_balances(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    solido: &Lido,
+    rent: &Rent,
+) -> Result<Vec<(Pubkey, Lamports)>> {
+    let authorities = [
+        solido.get_stake_authority(solido_program_id, solido_address)?,
+        solido.get_mint_authority(solido_program_id, solido_address)?,
+        solido.get_rewards_withdraw_authority(solido_program_id, solido_address)?,
+    ];
+    let program_accounts = [
+        ExchangeRateOracle::find_address(solido_program_id, solido_address).0,
+        MaintainerLease::find_address(solido_program_id, solido_address).0,
+    ];
+
+    let mut balances = Vec::new();
+    for address in authorities.iter().chain(program_accounts.iter()) {
+        if !config.client.account_exists(address)? {
+            continue;
+        }
+        let account = config.client.get_account(address)?;
+        // The program can only take SOL out of the oracle and the lease once
+        // it owns them.
+        if program_accounts.contains(address) && &account.owner != solido_program_id {
+            continue;
+        }
+        let rent_exempt_balance = Lamports(rent.minimum_balance(account.data.len()));
+        let amount = (Lamports(account.lamports) - rent_exempt_balance).unwrap_or(Lamports(0));
+        balances.push((*address, amount));
+    }
+    Ok(balances)
+}
+
+/// Read the next withdraw ticket to fund, if there is an unfunded ticket.
+fn get_withdraw_ticket_to_fund(
+    config: &mut SnapshotConfig,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    solido: &Lido,
+) -> Result<Option<(Pubkey, WithdrawTicket)>> {
+    let queue = &solido.withdraw_queue;
+    if queue.next_fund_ticket_id >= queue.next_ticket_id {
+        return Ok(None);
+    }
+    let (address, _bump_seed) =
+        WithdrawTicket::find_address(solido_program_id, solido_address, queue.next_fund_ticket_id);
+    let ticket = get_program_derived_state(
+        config,
+        solido_program_id,
+        address,
+        "Failed to deserialize withdraw ticket.",
+    )?;
+    Ok(ticket.map(|ticket| (address, ticket)))
+}
+
 impl SolidoState {
     // Set the minimum withdraw from stake accounts and validator's vote
     // accounts, the cost of validating signatures seems to dominate the
     // transaction cost.
     const MINIMUM_WITHDRAW_AMOUNT: Lamports = Lamports(DEFAULT_TARGET_LAMPORTS_PER_SIGNATURE * 100);
+
+    /// The maximum number of `MergeStake` instructions in one transaction.
+    ///
+    /// Every merge adds one stake account to the transaction, the other
+    /// accounts are shared, so this stays well within the size limit of a
+    /// transaction, and within its compute budget.
+    const MAXIMUM_MERGES_PER_TRANSACTION: usize = 5;
+
     /// Read the state from the on-chain data.
     pub fn new(
         config: &mut SnapshotConfig,
@@ -340,22 +750,47 @@ impl SolidoState {
         let reserve_address = solido.get_reserve_account(solido_program_id, solido_address)?;
         let reserve_account = config.client.get_account(&reserve_address)?;
 
-        let st_sol_mint_account = config.client.get_account(&solido.st_sol_mint)?;
-        let st_sol_mint = Mint::unpack(&st_sol_mint_account.data)?;
+        let st_sol_mint: Mint = config.client.get_token_state(&solido.st_sol_mint)?;
 
         let rent = config.client.get_rent()?;
         let clock = config.client.get_clock()?;
-        let stake_history = config.client.get_stake_history()?;
+        let epoch_schedule = config.client.get_epoch_schedule()?;
+        let stake_history = config.client.get_stake_history_shared()?;
+        let exchange_rate_oracle =
+            get_exchange_rate_oracle(config, solido_program_id, solido_address)?;
+        let maintainer_lease = get_maintainer_lease(config, solido_program_id, solido_address)?;
+        let withdraw_ticket_to_fund =
+            get_withdraw_ticket_to_fund(config, solido_program_id, solido_address, &solido)?;
+        let sweepable_balances =
+            get_sweepable_balances(config, solido_program_id, solido_address, &solido, &rent)?;
 
         let mut validator_stake_accounts = Vec::new();
         let mut validator_unstake_accounts = Vec::new();
         let mut validator_vote_account_balances = Vec::new();
+        let mut validator_mev = Vec::new();
+        let mut validator_identities = Vec::new();
+        let mut validator_vote_accounts = Vec::new();
         for validator in solido.validators.entries.iter() {
+            validator_mev.push(get_validator_mev(
+                &mut config.client,
+                &rent,
+                &validator.pubkey,
+                clock.epoch,
+            )?);
+
             validator_vote_account_balances.push(get_vote_account_balance_except_rent(
                 config,
                 &rent,
                 &validator.pubkey,
             )?);
+            validator_identities.push(get_validator_identity(
+                &mut config.client,
+                &validator.pubkey,
+            )?);
+            validator_vote_accounts.push(get_vote_account_state(
+                &mut config.client,
+                &validator.pubkey,
+            )?);
 
             validator_stake_accounts.push(get_validator_stake_accounts(
                 config,
@@ -381,7 +816,23 @@ impl SolidoState {
         // We don't verify here if it is part of the maintainer set, the on-chain
         // program does that anyway.
         let maintainer_address = config.signer.pubkey();
-        let maintainer_account = config.client.get_account(&maintainer_address)?;
+        let fee_payer_address = config.fee_payer.pubkey();
+        // An observer has no maintainer or fee payer to read, see `has_maintainer`.
+        let has_maintainer = maintainer_address != Pubkey::default();
+        let maintainer_account = if has_maintainer {
+            config.client.get_account(&maintainer_address)?.clone()
+        } else {
+            Account::default()
+        };
+
+        // A fee payer that ran out of SOL no longer exists, but we still want
+        // to report its balance, and top it up.
+        let fee_payer_balance =
+            if has_maintainer && config.client.account_exists(&fee_payer_address)? {
+                Lamports(config.client.get_account(&fee_payer_address)?.lamports)
+            } else {
+                Lamports(0)
+            };
 
         Ok(SolidoState {
             produced_at: SystemTime::now(),
@@ -391,16 +842,107 @@ impl SolidoState {
             validator_stake_accounts,
             validator_unstake_accounts,
             validator_vote_account_balances,
+            validator_mev,
+            validator_identities,
+            validator_vote_accounts,
+            superminority: None,
             reserve_address,
             reserve_account: reserve_account.clone(),
             st_sol_mint,
+            exchange_rate_oracle,
+            maintainer_lease,
+            withdraw_ticket_to_fund,
+            sweepable_balances,
             rent,
             clock,
+            epoch_schedule,
+            snapshot_slot: config.client.slot(),
             maintainer_address,
-            maintainer_account: maintainer_account.clone(),
+            maintainer_account,
+            fee_payer_address,
+            fee_payer_balance,
         })
     }
 
+    /// Return whether there is a maintainer key that signs for us.
+    ///
+    /// With `--observe-only`, the daemon signs with a `NullSigner` for the
+    /// default address instead, and nobody pays fees.
+    pub fn has_maintainer(&self) -> bool {
+        self.maintainer_address != Pubkey::default()
+    }
+
+    /// Read the stake of every vote account in the cluster, and store its superminority.
+    ///
+    /// This is not part of `new`, because it is only needed when we avoid the
+    /// superminority, and it costs a large RPC call.
+    pub fn load_superminority(&mut self, config: &mut SnapshotConfig) -> Result<()> {
+        let cluster_stakes: Vec<(Pubkey, Lamports)> = config
+            .client
+            .get_vote_account_stakes()?
+            .into_iter()
+            .map(|(vote_account, stake)| (vote_account, Lamports(stake)))
+            .collect();
+        self.superminority = Some(lido::balance::find_superminority(&cluster_stakes));
+        Ok(())
+    }
+
+    /// Return how many slots ago the current epoch started.
+    pub fn slots_into_epoch(&self) -> Slot {
+        let first_slot = self
+            .epoch_schedule
+            .get_first_slot_in_epoch(self.clock.epoch);
+        self.clock.slot.saturating_sub(first_slot)
+    }
+
+    /// Return how many slots are left until the next epoch starts.
+    pub fn slots_until_next_epoch(&self) -> Slot {
+        let last_slot = self.epoch_schedule.get_last_slot_in_epoch(self.clock.epoch);
+        (last_slot + 1).saturating_sub(self.clock.slot)
+    }
+
+    /// Return how long ago the current epoch started, according to the clock sysvar.
+    pub fn time_into_epoch(&self) -> Duration {
+        let seconds = self.clock.unix_timestamp - self.clock.epoch_start_timestamp;
+        Duration::from_secs(seconds.max(0) as u64)
+    }
+
+    /// Return how far the clock sysvar lagged behind local time when we read
+    /// the state, or zero if it was ahead.
+    pub fn get_clock_lag(&self) -> Duration {
+        let cluster_time =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(self.clock.unix_timestamp.max(0) as u64);
+        self.produced_at
+            .duration_since(cluster_time)
+            .unwrap_or_default()
+    }
+
+    /// Return how many slots the state appears to lag behind the cluster.
+    ///
+    /// We estimate this from the clock lag, at the target slot time. This
+    /// relies on the local clock being accurate, like the clock lag does.
+    pub fn get_lag_slots(&self) -> Slot {
+        self.get_clock_lag().as_millis() as u64 / DEFAULT_MS_PER_SLOT
+    }
+
+    /// Return the stake movements that maintenance would perform, but refuses,
+    /// because the state lags more than `max_state_lag_slots` behind.
+    pub fn get_stale_stake_movements(
+        &self,
+        options: &MaintenanceOptions,
+    ) -> Vec<MaintenanceOutput> {
+        match options.max_state_lag_slots {
+            Some(max_lag_slots) if self.get_lag_slots() > max_lag_slots => {}
+            _ => return Vec::new(),
+        }
+        get_task_order(self, options)
+            .into_iter()
+            .filter_map(|task| self.try_task(task, options))
+            .map(|(_instruction, output)| output)
+            .filter(moves_stake)
+            .collect()
+    }
+
     /// Return the amount of SOL in the reserve account that could be spent
     /// while still keeping the reserve account rent-exempt.
     pub fn get_effective_reserve(&self) -> Lamports {
@@ -411,31 +953,58 @@ impl SolidoState {
         )
     }
 
+    /// Return the SOL in the reserve that is not owed to withdraw tickets.
+    pub fn get_stakeable_reserve(&self) -> Lamports {
+        Lamports(
+            self.get_effective_reserve()
+                .0
+                .saturating_sub(self.solido.withdraw_queue.get_outstanding().0),
+        )
+    }
+
     /// If there is a deposit that can be staked, return the instructions to do so.
     pub fn try_stake_deposit(&self) -> Option<(Instruction, MaintenanceOutput)> {
-        // We can only stake if there is an active validator. If there is none,
-        // this will short-circuit and return None.
-        self.solido.validators.iter_active().next()?;
+        // We can only stake if there is an active validator with a nonzero
+        // weight, that has room below the maximum stake per validator. If there
+        // is none, this will short-circuit and return None.
+        let max_stake_per_validator = self.solido.get_max_stake_per_validator();
+        self.solido.validators.iter_entries().find(|v| {
+            lido::balance::receives_stake(v)
+                && lido::balance::has_room_for_stake(v, max_stake_per_validator)
+        })?;
 
-        let reserve_balance = self.get_effective_reserve();
+        // SOL that we owe to withdraw tickets is not ours to stake.
+        let reserve_balance = self.get_stakeable_reserve();
 
         // If there is enough reserve, we can make a deposit. To keep the pool
         // balanced, find the validator furthest below its target balance, and
         // deposit to that validator. If we get here there is at least one active
-        // validator, so computing the target balance should not fail.
+        // validator with a nonzero weight, so computing the target balance
+        // should not fail.
+        //
+        // When we avoid the superminority, its validators target no additional
+        // stake. The program still only accepts the validators with the least
+        // stake relative to their weight, so among those, we prefer the ones
+        // outside the superminority, and stake with them up to their target.
         let undelegated_lamports = reserve_balance;
-        let targets =
-            lido::balance::get_target_balance(undelegated_lamports, &self.solido.validators)
-                .expect("Failed to compute target balance.");
+        let targets = lido::balance::get_target_balance(
+            undelegated_lamports,
+            &self.solido.validators,
+            max_stake_per_validator,
+            self.superminority.as_deref(),
+        )
+        .expect("Failed to compute target balance.");
 
         let (validator_index, amount_below_target) =
             lido::balance::get_validator_furthest_below_target(
                 &self.solido.validators,
                 &targets[..],
+                max_stake_per_validator,
             );
         let validator = &self.solido.validators.entries[validator_index];
 
-        let (stake_account_end, _bump_seed_end) = validator.find_stake_account_address(
+        let (stake_account_end, _bump_seed_end) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
             &self.solido_program_id,
             &self.solido_address,
             validator.entry.stake_seeds.end,
@@ -454,6 +1023,15 @@ impl SolidoState {
         // temporarily, and future deposits will restore the balance.
         amount_to_deposit = amount_to_deposit.max(MINIMUM_STAKE_ACCOUNT_BALANCE);
 
+        // But we can never overshoot the maximum stake per validator, the
+        // program would reject that. The validator has room for at least the
+        // minimum stake account balance, so we still stake at least that.
+        if let Some(max_stake) = max_stake_per_validator {
+            let room = (max_stake - validator.entry.effective_stake_balance())
+                .expect("Does not underflow, the validator has room below the maximum.");
+            amount_to_deposit = amount_to_deposit.min(room);
+        }
+
         // The minimum stake account balance might be more than what's in the
         // reserve. If so, we cannot stake.
         if amount_to_deposit > reserve_balance {
@@ -492,6 +1070,23 @@ impl SolidoState {
         Some((instruction, task))
     }
 
+    /// Return the stake deposit and rebalance that maintenance would perform,
+    /// but defers because they move less than `minimum_stake_delta`.
+    pub fn get_deferred_stake_movements(
+        &self,
+        minimum_stake_delta: Lamports,
+    ) -> Vec<MaintenanceOutput> {
+        vec![self.try_stake_deposit(), self.try_rebalance_unstake()]
+            .into_iter()
+            .flatten()
+            .map(|(_instruction, output)| output)
+            .filter(|output| match get_deferrable_stake_delta(output) {
+                Some(amount) => amount < minimum_stake_delta,
+                None => false,
+            })
+            .collect()
+    }
+
     /// If there is a validator being deactivated, try to unstake its funds.
     pub fn try_unstake_from_inactive_validator(&self) -> Option<(Instruction, MaintenanceOutput)> {
         for (validator, stake_accounts) in self
@@ -516,7 +1111,8 @@ impl SolidoState {
             if stake_accounts.first().is_none() {
                 continue;
             }
-            let (validator_unstake_account, _) = validator.find_stake_account_address(
+            let (validator_unstake_account, _) = derivation_cache::find_stake_account_address(
+                &validator.pubkey,
                 &self.solido_program_id,
                 &self.solido_address,
                 validator.entry.unstake_seeds.end,
@@ -551,24 +1147,99 @@ impl SolidoState {
         None
     }
 
-    /// If there is a validator ready for removal, try to remove it.
-    pub fn try_remove_validator(&self) -> Option<(Instruction, MaintenanceOutput)> {
-        for validator in &self.solido.validators.entries {
-            // We are only interested in validators that can be removed.
-            if validator.entry.check_can_be_removed().is_err() {
+    /// If an active validator has more stake than its target by more than the
+    /// rebalance threshold, unstake the excess, so it can be staked with
+    /// validators below their target.
+    pub fn try_rebalance_unstake(&self) -> Option<(Instruction, MaintenanceOutput)> {
+        // If there is no active validator with a nonzero weight, there are no
+        // targets, and nothing to rebalance. We use the same targets as when
+        // staking, otherwise we would unstake what we staked outside the
+        // superminority. The program cannot observe the superminority, so we
+        // pass the members among our validators along.
+        let targets = lido::balance::get_target_balance(
+            self.get_stakeable_reserve(),
+            &self.solido.validators,
+            self.solido.get_max_stake_per_validator(),
+            self.superminority.as_deref(),
+        )
+        .ok()?;
+        let superminority: Vec<Pubkey> = match &self.superminority {
+            None => Vec::new(),
+            Some(superminority) => self
+                .solido
+                .validators
+                .entries
+                .iter()
+                .map(|v| v.pubkey)
+                .filter(|pubkey| superminority.contains(pubkey))
+                .collect(),
+        };
+
+        for ((validator, stake_accounts), target) in self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_stake_accounts.iter())
+            .zip(targets.iter())
+        {
+            // Unstaking needs a free unstake account slot.
+            if validator.entry.unstake_seeds.end - validator.entry.unstake_seeds.begin
+                >= lido::MAXIMUM_UNSTAKE_ACCOUNTS
+            {
                 continue;
             }
-            let task = MaintenanceOutput::RemoveValidator {
+            let excess = match lido::balance::get_rebalance_amount(validator, *target) {
+                Some(excess) => excess,
+                None => continue,
+            };
+            let (stake_account_address, stake_account) = match stake_accounts.first() {
+                Some(first) => first,
+                None => continue,
+            };
+
+            // The stake account of an active validator has to keep the minimum
+            // balance, so we may not be able to unstake the full excess at once.
+            // A future maintenance run can unstake the rest.
+            let available = match stake_account.balance.total() - MINIMUM_STAKE_ACCOUNT_BALANCE {
+                Ok(available) => available,
+                Err(_) => continue,
+            };
+            let amount = excess.min(available);
+            if amount < MINIMUM_STAKE_ACCOUNT_BALANCE {
+                continue;
+            }
+
+            let (validator_unstake_account, _) = derivation_cache::find_stake_account_address(
+                &validator.pubkey,
+                &self.solido_program_id,
+                &self.solido_address,
+                validator.entry.unstake_seeds.end,
+                StakeType::Unstake,
+            );
+            let task = MaintenanceOutput::RebalanceUnstake {
                 validator_vote_account: validator.pubkey,
+                from_stake_account: *stake_account_address,
+                to_unstake_account: validator_unstake_account,
+                from_stake_seed: validator.entry.stake_seeds.begin,
+                to_unstake_seed: validator.entry.unstake_seeds.end,
+                amount,
             };
 
             return Some((
-                lido::instruction::remove_validator(
+                lido::instruction::rebalance(
                     &self.solido_program_id,
-                    &lido::instruction::RemoveValidatorMeta {
+                    &lido::instruction::RebalanceAccountsMeta {
                         lido: self.solido_address,
-                        validator_vote_account_to_remove: validator.pubkey,
+                        maintainer: self.maintainer_address,
+                        validator_vote_account: validator.pubkey,
+                        source_stake_account: *stake_account_address,
+                        destination_unstake_account: validator_unstake_account,
+                        stake_authority: self.get_stake_authority(),
+                        reserve_account: self.reserve_address,
                     },
+                    amount,
+                    superminority,
                 ),
                 task,
             ));
@@ -576,7 +1247,302 @@ impl SolidoState {
         None
     }
 
-    /// Get an instruction to merge accounts.
+    /// If the next withdraw ticket can be funded from the reserve, fund it.
+    pub fn try_fund_withdraw_ticket(&self) -> Option<(Instruction, MaintenanceOutput)> {
+        let (withdraw_ticket, ticket) = self.withdraw_ticket_to_fund.as_ref()?;
+        if ticket.amount > self.get_effective_reserve() {
+            return None;
+        }
+
+        let instruction = lido::instruction::fund_withdraw_ticket(
+            &self.solido_program_id,
+            &lido::instruction::FundWithdrawTicketAccountsMeta {
+                lido: self.solido_address,
+                maintainer: self.maintainer_address,
+                reserve_account: self.reserve_address,
+                withdraw_ticket: *withdraw_ticket,
+            },
+        );
+        let task = MaintenanceOutput::FundWithdrawTicket {
+            withdraw_ticket: *withdraw_ticket,
+            ticket_id: ticket.ticket_id,
+            amount: ticket.amount,
+        };
+        Some((instruction, task))
+    }
+
+    /// If the reserve, together with the SOL that is already being unstaked,
+    /// cannot cover the withdraw tickets, unstake from the validator with the
+    /// most stake.
+    ///
+    /// Large tickets may need stake from several validators. Every run unstakes
+    /// from one validator, and the next run sees the unstake accounts, so it
+    /// continues with the next validator.
+    pub fn try_unstake_for_withdraw_tickets(&self) -> Option<(Instruction, MaintenanceOutput)> {
+        let unstaking: token::Result<Lamports> = self
+            .solido
+            .validators
+            .iter_entries()
+            .map(|v| v.unstake_accounts_balance)
+            .sum();
+        let covered = (unstaking.ok()? + self.get_effective_reserve()).ok()?;
+        let needed = (self.solido.withdraw_queue.get_outstanding() - covered).ok()?;
+        if needed == Lamports(0) {
+            return None;
+        }
+
+        let (validator, stake_accounts) = self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_stake_accounts.iter())
+            .filter(|(validator, stake_accounts)| {
+                validator.entry.active
+                    && !stake_accounts.is_empty()
+                    && validator.entry.unstake_seeds.end - validator.entry.unstake_seeds.begin
+                        < lido::MAXIMUM_UNSTAKE_ACCOUNTS
+            })
+            .max_by_key(|(validator, _)| validator.entry.effective_stake_balance())?;
+
+        // The stake account of an active validator has to keep the minimum
+        // balance, and we do not split off less than the minimum either, the
+        // excess returns to the reserve and gets staked again.
+        let (stake_account_address, stake_account) = &stake_accounts[0];
+        let available = (stake_account.balance.total() - MINIMUM_STAKE_ACCOUNT_BALANCE).ok()?;
+        let amount = needed.max(MINIMUM_STAKE_ACCOUNT_BALANCE).min(available);
+        if amount < MINIMUM_STAKE_ACCOUNT_BALANCE {
+            return None;
+        }
+
+        let (validator_unstake_account, _) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
+            &self.solido_program_id,
+            &self.solido_address,
+            validator.entry.unstake_seeds.end,
+            StakeType::Unstake,
+        );
+        let task = MaintenanceOutput::UnstakeForWithdrawTickets {
+            validator_vote_account: validator.pubkey,
+            from_stake_account: *stake_account_address,
+            to_unstake_account: validator_unstake_account,
+            from_stake_seed: validator.entry.stake_seeds.begin,
+            to_unstake_seed: validator.entry.unstake_seeds.end,
+            amount,
+        };
+        let instruction = lido::instruction::unstake(
+            &self.solido_program_id,
+            &lido::instruction::UnstakeAccountsMeta {
+                lido: self.solido_address,
+                maintainer: self.maintainer_address,
+                validator_vote_account: validator.pubkey,
+                source_stake_account: *stake_account_address,
+                destination_unstake_account: validator_unstake_account,
+                stake_authority: self.get_stake_authority(),
+            },
+            amount,
+        );
+        Some((instruction, task))
+    }
+
+    /// Return for how many consecutive epochs the validator at index `i` earned no credits.
+    ///
+    /// When we could not parse the vote account, we don't know, and we assume
+    /// that the validator is not delinquent.
+    pub fn get_delinquent_epochs(&self, i: usize) -> u64 {
+        match &self.validator_vote_accounts[i] {
+            Some(vote_account) => vote_account.history.delinquent_epochs(self.clock.epoch),
+            None => 0,
+        }
+    }
+
+    /// Return the first active validator that earned no credits in at least
+    /// `min_epochs` consecutive epochs, and for how many epochs.
+    pub fn get_delinquent_validator(
+        &self,
+        min_epochs: u64,
+    ) -> Option<(&PubkeyAndEntry<Validator>, u64)> {
+        self.solido
+            .validators
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, validator)| validator.entry.active)
+            .map(|(i, validator)| (validator, self.get_delinquent_epochs(i)))
+            .find(|&(_, delinquent_epochs)| delinquent_epochs >= min_epochs)
+    }
+
+    /// Return the instruction to deactivate the validator, signed by `manager`.
+    fn get_deactivate_validator_instruction(
+        &self,
+        manager: Pubkey,
+        validator_vote_account: Pubkey,
+    ) -> Instruction {
+        lido::instruction::deactivate_validator(
+            &self.solido_program_id,
+            &lido::instruction::DeactivateValidatorMeta {
+                lido: self.solido_address,
+                manager,
+                validator_vote_account_to_deactivate: validator_vote_account,
+            },
+        )
+    }
+
+    /// Deactivate a validator that has been delinquent for `min_epochs`, if
+    /// the maintainer is the manager of the instance.
+    ///
+    /// Deactivation needs the manager. When the maintainer is not the manager,
+    /// see [`prepare_propose_deactivation`] instead.
+    pub fn try_deactivate_delinquent_validator(
+        &self,
+        min_epochs: u64,
+    ) -> Option<(Instruction, MaintenanceOutput)> {
+        if self.solido.manager != self.maintainer_address {
+            return None;
+        }
+        let (validator, delinquent_epochs) = self.get_delinquent_validator(min_epochs)?;
+        let task = MaintenanceOutput::DeactivateValidator {
+            validator_vote_account: validator.pubkey,
+            delinquent_epochs,
+        };
+        Some((
+            self.get_deactivate_validator_instruction(self.maintainer_address, validator.pubkey),
+            task,
+        ))
+    }
+
+    /// If there is a validator ready for removal, try to remove it.
+    pub fn try_remove_validator(&self) -> Option<(Instruction, MaintenanceOutput)> {
+        for validator in &self.solido.validators.entries {
+            // We are only interested in validators that can be removed.
+            if validator.entry.check_can_be_removed().is_err() {
+                continue;
+            }
+            let task = MaintenanceOutput::RemoveValidator {
+                validator_vote_account: validator.pubkey,
+            };
+
+            return Some((
+                lido::instruction::remove_validator(
+                    &self.solido_program_id,
+                    &lido::instruction::RemoveValidatorMeta {
+                        lido: self.solido_address,
+                        validator_vote_account_to_remove: validator.pubkey,
+                    },
+                ),
+                task,
+            ));
+        }
+        None
+    }
+
+    /// Publish the exchange rate to the oracle account, if the published rate is
+    /// outdated, or if it was published more than `max_age_slots` ago.
+    ///
+    /// We only publish after the exchange rate has been updated in the current
+    /// epoch, the program rejects publishing an outdated rate.
+    pub fn try_publish_exchange_rate(
+        &self,
+        max_age_slots: Slot,
+    ) -> Option<(Instruction, MaintenanceOutput)> {
+        if self.solido.exchange_rate.computed_in_epoch < self.clock.epoch {
+            return None;
+        }
+
+        if let Some(oracle) = &self.exchange_rate_oracle {
+            let is_rate_current = oracle.exchange_rate == self.solido.exchange_rate;
+            let age_slots = self.clock.slot.saturating_sub(oracle.published_slot);
+            if is_rate_current && age_slots < max_age_slots {
+                return None;
+            }
+        }
+
+        let (exchange_rate_oracle, _bump_seed) =
+            ExchangeRateOracle::find_address(&self.solido_program_id, &self.solido_address);
+        let instruction = lido::instruction::publish_exchange_rate(
+            &self.solido_program_id,
+            &lido::instruction::PublishExchangeRateMeta {
+                lido: self.solido_address,
+                maintainer: self.maintainer_address,
+                exchange_rate_oracle,
+            },
+        );
+        let task = MaintenanceOutput::PublishExchangeRate {
+            exchange_rate_oracle,
+            computed_in_epoch: self.solido.exchange_rate.computed_in_epoch,
+        };
+
+        Some((instruction, task))
+    }
+
+    /// Move SOL from one of the auxiliary accounts into the reserve, if one of
+    /// them holds enough above its rent-exempt balance to be worth the fee.
+    pub fn try_sweep_to_reserve(&self) -> Option<(Instruction, MaintenanceOutput)> {
+        let (account, amount) = self
+            .sweepable_balances
+            .iter()
+            .find(|(_, amount)| *amount > SolidoState::MINIMUM_WITHDRAW_AMOUNT)?;
+
+        let instruction = lido::instruction::sweep_to_reserve(
+            &self.solido_program_id,
+            &lido::instruction::SweepToReserveAccountsMeta {
+                lido: self.solido_address,
+                maintainer: self.maintainer_address,
+                reserve_account: self.reserve_address,
+                sweep_account: *account,
+            },
+        );
+        let task = MaintenanceOutput::SweepToReserve {
+            account: *account,
+            amount: *amount,
+        };
+
+        Some((instruction, task))
+    }
+
+    /// Return the lease if another maintainer holds it, and it has not expired.
+    pub fn get_lease_held_by_other(&self) -> Option<&MaintainerLease> {
+        self.maintainer_lease.as_ref().filter(|lease| {
+            lease.holder != self.maintainer_address && lease.is_valid_at(self.clock.slot)
+        })
+    }
+
+    /// Acquire the maintainer lease for `lease_slots` slots if nobody holds it,
+    /// or renew it if we hold it and less than half of the lease remains.
+    ///
+    /// This assumes that no other maintainer holds the lease, see
+    /// `get_lease_held_by_other`.
+    pub fn try_acquire_maintainer_lease(
+        &self,
+        lease_slots: u64,
+    ) -> Option<(Instruction, MaintenanceOutput)> {
+        if let Some(lease) = &self.maintainer_lease {
+            let remaining_slots = lease.expires_slot.saturating_sub(self.clock.slot);
+            if lease.holder == self.maintainer_address && remaining_slots > lease_slots / 2 {
+                return None;
+            }
+        }
+
+        let (maintainer_lease, _bump_seed) =
+            MaintainerLease::find_address(&self.solido_program_id, &self.solido_address);
+        let instruction = lido::instruction::acquire_maintainer_lease(
+            &self.solido_program_id,
+            lease_slots,
+            &lido::instruction::AcquireMaintainerLeaseMeta {
+                lido: self.solido_address,
+                maintainer: self.maintainer_address,
+                maintainer_lease,
+            },
+        );
+        let task = MaintenanceOutput::AcquireMaintainerLease {
+            maintainer_lease,
+            expires_slot: self.clock.slot + lease_slots,
+        };
+
+        Some((instruction, task))
+    }
+
+    /// Get an instruction to merge accounts.
     fn get_merge_instruction(
         &self,
         validator: &PubkeyAndEntry<Validator>,
@@ -584,14 +1550,16 @@ impl SolidoState {
         to_seed: u64,
     ) -> Instruction {
         // Stake Account created by this transaction.
-        let (from_stake, _bump_seed_end) = validator.find_stake_account_address(
+        let (from_stake, _bump_seed_end) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
             &self.solido_program_id,
             &self.solido_address,
             from_seed,
             StakeType::Stake,
         );
         // Stake Account created by this transaction.
-        let (to_stake, _bump_seed_end) = validator.find_stake_account_address(
+        let (to_stake, _bump_seed_end) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
             &self.solido_program_id,
             &self.solido_address,
             to_seed,
@@ -609,9 +1577,48 @@ impl SolidoState {
         )
     }
 
-    // Tries to merge accounts from the beginning of the validator's
-    // stake accounts.  May return None or one instruction.
-    pub fn try_merge_on_all_stakes(&self) -> Option<(Instruction, MaintenanceOutput)> {
+    /// Return how many merges `MergeStake` can do in a row, from the first
+    /// stake account of a validator.
+    ///
+    /// `MergeStake` always merges the first stake account into the second one,
+    /// so the merges in one transaction form a chain: the first account into
+    /// the second, the result into the third, and so on. We check every merge
+    /// against the combined account that the previous merges produce, and we
+    /// stop at the first one that would fail, so the transaction as a whole
+    /// does not fail.
+    pub fn plan_stake_merges(stake_accounts: &[(Pubkey, StakeAccount)]) -> usize {
+        let mut merged = match stake_accounts.first() {
+            Some((_, stake_account)) => *stake_account,
+            None => return 0,
+        };
+        let mut num_merges = 0;
+        for (_, next) in stake_accounts[1..]
+            .iter()
+            .take(SolidoState::MAXIMUM_MERGES_PER_TRANSACTION)
+        {
+            if !next.can_merge(&merged) {
+                break;
+            }
+            let balance = match merged.balance + next.balance {
+                Ok(balance) => balance,
+                Err(_) => break,
+            };
+            // An inactive account that absorbs an activating one takes over
+            // its delegation, in all other cases the destination keeps its own.
+            let destination = if next.is_inactive() { merged } else { *next };
+            merged = StakeAccount {
+                balance,
+                seed: next.seed,
+                ..destination
+            };
+            num_merges += 1;
+        }
+        num_merges
+    }
+
+    /// Merge as many stake accounts as we can from the beginning of the stake
+    /// accounts of the first validator that has any to merge, in one transaction.
+    pub fn try_merge_on_all_stakes(&self) -> Option<(Vec<Instruction>, MaintenanceOutput)> {
         for (validator, stake_accounts) in self
             .solido
             .validators
@@ -619,23 +1626,24 @@ impl SolidoState {
             .iter()
             .zip(self.validator_stake_accounts.iter())
         {
-            // Try to merge from beginning
-            if stake_accounts.len() > 1 {
-                let from_stake = stake_accounts[0];
-                let to_stake = stake_accounts[1];
-                if to_stake.1.can_merge(&from_stake.1) {
-                    let instruction =
-                        self.get_merge_instruction(validator, from_stake.1.seed, to_stake.1.seed);
-                    let task = MaintenanceOutput::MergeStake {
-                        validator_vote_account: validator.pubkey,
-                        from_stake: from_stake.0,
-                        to_stake: to_stake.0,
-                        from_stake_seed: from_stake.1.seed,
-                        to_stake_seed: to_stake.1.seed,
-                    };
-                    return Some((instruction, task));
-                }
+            let num_merges = SolidoState::plan_stake_merges(stake_accounts);
+            if num_merges == 0 {
+                continue;
             }
+            let instructions = stake_accounts[..=num_merges]
+                .windows(2)
+                .map(|pair| self.get_merge_instruction(validator, pair[0].1.seed, pair[1].1.seed))
+                .collect();
+            let from_stake = &stake_accounts[0];
+            let to_stake = &stake_accounts[num_merges];
+            let task = MaintenanceOutput::MergeStake {
+                validator_vote_account: validator.pubkey,
+                from_stake: from_stake.0,
+                to_stake: to_stake.0,
+                from_stake_seed: from_stake.1.seed,
+                to_stake_seed: to_stake.1.seed,
+            };
+            return Some((instructions, task));
         }
         None
     }
@@ -660,40 +1668,54 @@ impl SolidoState {
         Some((instruction, task))
     }
 
+    /// Return the balance that `WithdrawInactiveStake` would process for the
+    /// validator at index `i`: the difference between the stake accounts and the
+    /// balance that Solido recorded, and the balance of the unstake accounts
+    /// that are fully inactive, and can be withdrawn to the reserve.
+    fn get_inactive_stake_to_withdraw(&self, i: usize) -> (Lamports, Lamports) {
+        let validator = &self.solido.validators.entries[i];
+        let current_stake_balance = self.validator_stake_accounts[i]
+            .iter()
+            .map(|(_addr, detail)| detail.balance.total())
+            .sum::<token::Result<Lamports>>()
+            .expect("If this overflows, there would be more than u64::MAX staked.");
+
+        let expected_difference_stake =
+            if current_stake_balance > validator.entry.effective_stake_balance() {
+                (current_stake_balance - validator.entry.effective_stake_balance())
+                    .expect("Does not overflow because current > entry.balance.")
+            } else {
+                Lamports(0)
+            };
+
+        let mut removed_unstake = Lamports(0);
+
+        for (_addr, unstake_account) in self.validator_unstake_accounts[i].iter() {
+            if unstake_account.balance.inactive != unstake_account.balance.total() {
+                break;
+            }
+            removed_unstake = (removed_unstake + unstake_account.balance.total())
+                .expect("Summing unstake accounts should not overflow.");
+        }
+
+        (expected_difference_stake, removed_unstake)
+    }
+
     /// Check if any validator's balance is outdated, and if so, update it.
     ///
     /// Merging stakes generates inactive stake that could be withdrawn with this transaction,
     /// or if some joker donates to one of the stake accounts we can use the same function
     /// to claim these rewards back to the reserve account so they can be re-staked.
     pub fn try_withdraw_inactive_stake(&self) -> Option<(Instruction, MaintenanceOutput)> {
-        for (validator, stake_accounts, unstake_accounts) in izip!(
+        for (i, (validator, stake_accounts, unstake_accounts)) in izip!(
             self.solido.validators.entries.iter(),
             self.validator_stake_accounts.iter(),
             self.validator_unstake_accounts.iter()
-        ) {
-            let current_stake_balance = stake_accounts
-                .iter()
-                .map(|(_addr, detail)| detail.balance.total())
-                .sum::<token::Result<Lamports>>()
-                .expect("If this overflows, there would be more than u64::MAX staked.");
-
-            let expected_difference_stake =
-                if current_stake_balance > validator.entry.effective_stake_balance() {
-                    (current_stake_balance - validator.entry.effective_stake_balance())
-                        .expect("Does not overflow because current > entry.balance.")
-                } else {
-                    Lamports(0)
-                };
-
-            let mut removed_unstake = Lamports(0);
-
-            for (_addr, unstake_account) in unstake_accounts.iter() {
-                if unstake_account.balance.inactive != unstake_account.balance.total() {
-                    break;
-                }
-                removed_unstake = (removed_unstake + unstake_account.balance.total())
-                    .expect("Summing unstake accounts should not overflow.");
-            }
+        )
+        .enumerate()
+        {
+            let (expected_difference_stake, removed_unstake) =
+                self.get_inactive_stake_to_withdraw(i);
 
             // If the expected difference is less than some defined amount
             // of Lamports, we don't bother withdrawing. We try to do this
@@ -756,6 +1778,7 @@ impl SolidoState {
                         developer_st_sol_account: self.solido.fee_recipients.developer_account,
                         reserve: self.reserve_address,
                         rewards_withdraw_authority: self.get_rewards_withdraw_authority(),
+                        spl_token_program: self.solido.st_sol_token_program,
                     },
                 );
                 let task = MaintenanceOutput::CollectValidatorFee {
@@ -784,6 +1807,7 @@ impl SolidoState {
                     st_sol_mint: self.solido.st_sol_mint,
                     mint_authority: self.get_mint_authority(),
                     validator_fee_st_sol_account: validator.entry.fee_address,
+                    spl_token: self.solido.st_sol_token_program,
                 },
             );
             let task = MaintenanceOutput::ClaimValidatorFee {
@@ -797,10 +1821,149 @@ impl SolidoState {
         None
     }
 
+    /// Return the instructions for the task, if it has something to do.
+    ///
+    /// Tasks that are not enabled in `options` have nothing to do. This does
+    /// not apply any deferral, see `get_deferral`.
+    pub fn try_task(
+        &self,
+        task: MaintenanceTask,
+        options: &MaintenanceOptions,
+    ) -> Option<(Vec<Instruction>, MaintenanceOutput)> {
+        let result = match task {
+            // Merging is the only task that can take several instructions.
+            MaintenanceTask::MergeStake => return self.try_merge_on_all_stakes(),
+            MaintenanceTask::AcquireMaintainerLease => {
+                let lease_slots = options.maintainer_lease_slots?;
+                if self.get_lease_held_by_other().is_some() {
+                    return None;
+                }
+                self.try_acquire_maintainer_lease(lease_slots)
+            }
+            MaintenanceTask::UpdateExchangeRate => self.try_update_exchange_rate(),
+            MaintenanceTask::DeactivateValidator => options
+                .deactivate_delinquent_epochs
+                .and_then(|min_epochs| self.try_deactivate_delinquent_validator(min_epochs)),
+            MaintenanceTask::UnstakeFromInactiveValidator => {
+                self.try_unstake_from_inactive_validator()
+            }
+            MaintenanceTask::CollectValidatorFee => self.try_collect_validator_fee(),
+            MaintenanceTask::WithdrawInactiveStake => self.try_withdraw_inactive_stake(),
+            MaintenanceTask::FundWithdrawTicket => self.try_fund_withdraw_ticket(),
+            MaintenanceTask::UnstakeForWithdrawTickets => self.try_unstake_for_withdraw_tickets(),
+            MaintenanceTask::StakeDeposit => self.try_stake_deposit(),
+            MaintenanceTask::RebalanceUnstake => self.try_rebalance_unstake(),
+            MaintenanceTask::ClaimValidatorFee => self.try_claim_validator_fee(),
+            MaintenanceTask::RemoveValidator => self.try_remove_validator(),
+            MaintenanceTask::SweepToReserve => self.try_sweep_to_reserve(),
+            MaintenanceTask::PublishExchangeRate => options
+                .publish_exchange_rate_max_age_slots
+                .and_then(|max_age_slots| self.try_publish_exchange_rate(max_age_slots)),
+        };
+        result.map(|(instruction, output)| (vec![instruction], output))
+    }
+
+    /// Return why the enabled task has nothing to do, for when `try_task` returns `None`.
+    pub fn get_idle_reason(&self, task: MaintenanceTask, options: &MaintenanceOptions) -> String {
+        match task {
+            MaintenanceTask::AcquireMaintainerLease => match &self.maintainer_lease {
+                Some(lease) if lease.holder == self.maintainer_address => format!(
+                    "we hold the lease until slot {}, it needs no renewal yet",
+                    lease.expires_slot
+                ),
+                Some(lease) => format!(
+                    "maintainer {} holds the lease until slot {}",
+                    lease.holder, lease.expires_slot
+                ),
+                None => "the lease does not exist".to_string(),
+            },
+            MaintenanceTask::MergeStake => {
+                "no validator has two stake accounts that can be merged".to_string()
+            }
+            MaintenanceTask::UpdateExchangeRate => format!(
+                "the exchange rate was already updated in epoch {}",
+                self.solido.exchange_rate.computed_in_epoch
+            ),
+            MaintenanceTask::DeactivateValidator => {
+                if self.solido.manager != self.maintainer_address {
+                    "the maintainer is not the manager, deactivations are proposed instead"
+                        .to_string()
+                } else {
+                    format!(
+                        "no active validator earned no credits in {} consecutive epochs",
+                        options.deactivate_delinquent_epochs.unwrap_or_default()
+                    )
+                }
+            }
+            MaintenanceTask::UnstakeFromInactiveValidator => {
+                "no inactive validator has stake, and a free unstake account".to_string()
+            }
+            MaintenanceTask::CollectValidatorFee => format!(
+                "no vote account holds more than {} of rewards",
+                SolidoState::MINIMUM_WITHDRAW_AMOUNT
+            ),
+            MaintenanceTask::WithdrawInactiveStake => format!(
+                "no validator has more than {} of inactive stake, or a fully inactive unstake account",
+                SolidoState::MINIMUM_WITHDRAW_AMOUNT
+            ),
+            MaintenanceTask::FundWithdrawTicket => match &self.withdraw_ticket_to_fund {
+                Some((_, ticket)) => format!(
+                    "the reserve of {} cannot fund ticket {} of {}",
+                    self.get_effective_reserve(),
+                    ticket.ticket_id,
+                    ticket.amount
+                ),
+                None => "there is no unfunded withdraw ticket".to_string(),
+            },
+            MaintenanceTask::UnstakeForWithdrawTickets => {
+                "the reserve and the stake being unstaked cover the withdraw tickets, \
+                or no active validator has stake to unstake"
+                    .to_string()
+            }
+            MaintenanceTask::StakeDeposit => {
+                let max_stake_per_validator = self.solido.get_max_stake_per_validator();
+                let can_receive_stake = self.solido.validators.iter_entries().any(|v| {
+                    lido::balance::receives_stake(v)
+                        && lido::balance::has_room_for_stake(v, max_stake_per_validator)
+                });
+                if can_receive_stake {
+                    format!(
+                        "the stakeable reserve of {} is less than the stake account needs",
+                        self.get_stakeable_reserve()
+                    )
+                } else {
+                    "no active validator can receive stake".to_string()
+                }
+            }
+            MaintenanceTask::RebalanceUnstake => {
+                "every validator is within the rebalance threshold of its target, \
+                or cannot unstake the excess"
+                    .to_string()
+            }
+            MaintenanceTask::ClaimValidatorFee => {
+                "no validator has fee credit to claim".to_string()
+            }
+            MaintenanceTask::RemoveValidator => {
+                "no validator is ready to be removed".to_string()
+            }
+            MaintenanceTask::SweepToReserve => format!(
+                "no auxiliary account holds more than {} above its rent-exempt balance",
+                SolidoState::MINIMUM_WITHDRAW_AMOUNT
+            ),
+            MaintenanceTask::PublishExchangeRate => {
+                if self.solido.exchange_rate.computed_in_epoch < self.clock.epoch {
+                    "the exchange rate is not updated in this epoch yet".to_string()
+                } else {
+                    "the oracle holds the current exchange rate, published recently".to_string()
+                }
+            }
+        }
+    }
+
     /// Write metrics about the current Solido instance in Prometheus format.
     pub fn write_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
         use crate::prometheus::{
-            write_metric, write_solido_metrics_as_prometheus, Metric, MetricFamily,
+            write_metric, write_solido_metrics_as_prometheus, Metric, MetricFamily, MetricValue,
         };
 
         write_metric(
@@ -813,21 +1976,130 @@ impl SolidoState {
             },
         )?;
 
-        // Include the maintainer balance, so maintainers can alert on it getting too low.
+        // Alert on this, a pause should be short-lived.
         write_metric(
             out,
             &MetricFamily {
-                name: "solido_maintainer_balance_sol",
-                help: "Balance of the maintainer account, in SOL.",
+                name: "solido_paused",
+                help: "1 if deposits and withdrawals are paused by the manager, 0 otherwise.",
                 type_: "gauge",
-                metrics: vec![Metric::new_sol(Lamports(self.maintainer_account.lamports))
-                    .at(self.produced_at)
-                    // Include the maintainer address, to prevent any confusion
-                    // about which account this is monitoring.
-                    .with_label("maintainer_address", self.maintainer_address.to_string())],
+                metrics: vec![Metric::new(self.solido.is_paused as u64).at(self.produced_at)],
             },
         )?;
 
+        if let Some(max_total) = self.solido.get_max_total_lamports() {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_max_total_sol",
+                    help: "Maximum amount of SOL managed by Solido, above which deposits fail.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new_sol(max_total).at(self.produced_at)],
+                },
+            )?;
+            // Count the balance the same way as the on-chain check in `Deposit`.
+            let balance = self
+                .solido
+                .get_deposit_cap_balance(Lamports(self.reserve_account.lamports))
+                .expect("Outstanding withdraw tickets never exceed the SOL balance.");
+            let utilization = (balance.0 as u128 * 1_000_000_000) / max_total.0 as u128;
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_deposit_cap_utilization",
+                    help: "Fraction of the maximum total SOL that is in use.",
+                    type_: "gauge",
+                    metrics: vec![
+                        Metric::new(MetricValue::Nano(utilization as u64)).at(self.produced_at)
+                    ],
+                },
+            )?;
+        }
+
+        // A scheduled fee change is public on-chain, but exporting it lets us
+        // alert before it takes effect.
+        if let Some(pending) = self.solido.get_pending_reward_distribution() {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_pending_reward_distribution_activation_epoch",
+                    help: "Epoch from which the scheduled reward distribution applies.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new(pending.activation_epoch).at(self.produced_at)],
+                },
+            )?;
+            let distribution = &pending.reward_distribution;
+            let fraction = |share: u32| match distribution.sum() {
+                0 => 0,
+                sum => (share as u128 * 1_000_000_000 / sum as u128) as u64,
+            };
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_pending_reward_distribution_fraction",
+                    help: "Fraction of the rewards that goes to each party under the scheduled reward distribution.",
+                    type_: "gauge",
+                    metrics: [
+                        ("treasury", distribution.treasury_fee),
+                        ("validation", distribution.validation_fee),
+                        ("developer", distribution.developer_fee),
+                        ("st_sol_appreciation", distribution.st_sol_appreciation),
+                    ]
+                    .iter()
+                    .map(|(party, share)| {
+                        Metric::new(MetricValue::Nano(fraction(*share)))
+                            .at(self.produced_at)
+                            .with_label("party", party.to_string())
+                    })
+                    .collect(),
+                },
+            )?;
+        }
+
+        if self.has_maintainer() {
+            // Include the maintainer balance, so maintainers can alert on it getting too low.
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_maintainer_balance_sol",
+                    help: "Balance of the maintainer account, in SOL.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new_sol(Lamports(self.maintainer_account.lamports))
+                        .at(self.produced_at)
+                        // Include the maintainer address, to prevent any confusion
+                        // about which account this is monitoring.
+                        .with_label("maintainer_address", self.maintainer_address.to_string())],
+                },
+            )?;
+
+            // When there is a separate fee payer, that is the account that needs SOL.
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_fee_payer_balance_sol",
+                    help: "Balance of the account that pays transaction fees, in SOL.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new_sol(self.fee_payer_balance)
+                        .at(self.produced_at)
+                        .with_label("fee_payer_address", self.fee_payer_address.to_string())],
+                },
+            )?;
+        }
+
+        if let Some(lease) = &self.maintainer_lease {
+            write_metric(
+                out,
+                &MetricFamily {
+                    name: "solido_maintainer_lease_expires_slot",
+                    help: "First slot in which the maintainer lease is no longer valid.",
+                    type_: "gauge",
+                    metrics: vec![Metric::new(lease.expires_slot)
+                        .at(self.produced_at)
+                        .with_label("holder", lease.holder.to_string())],
+                },
+            )?;
+        }
+
         // Gather the different components that make up Solido's SOL balance.
         let mut balance_sol_metrics = vec![Metric::new_sol(self.get_effective_reserve())
             .at(self.produced_at)
@@ -925,84 +2197,1606 @@ impl SolidoState {
             },
         )?;
 
+        let withdraw_queue = &self.solido.withdraw_queue;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_withdraw_queue_outstanding_sol",
+                help: "SOL owed to withdraw tickets that are not funded yet.",
+                type_: "gauge",
+                metrics: vec![
+                    Metric::new_sol(withdraw_queue.get_outstanding()).at(self.produced_at)
+                ],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_withdraw_queue_unfunded_tickets",
+                help: "Number of withdraw tickets that are not funded yet.",
+                type_: "gauge",
+                metrics: vec![Metric::new(
+                    withdraw_queue.next_ticket_id - withdraw_queue.next_fund_ticket_id,
+                )
+                .at(self.produced_at)],
+            },
+        )?;
+
         write_solido_metrics_as_prometheus(&self.solido.metrics, self.produced_at, out)?;
 
+        self.write_validator_prometheus(out)?;
+        self.write_mev_prometheus(out)?;
+        self.write_pending_work_prometheus(out)?;
+
         Ok(())
     }
-    fn get_stake_authority(&self) -> Pubkey {
-        let (stake_authority, _bump_seed_authority) = lido::find_authority_program_address(
-            &self.solido_program_id,
-            &self.solido_address,
-            STAKE_AUTHORITY,
-        );
-        stake_authority
-    }
 
-    fn get_rewards_withdraw_authority(&self) -> Pubkey {
-        let (rewards_withdraw_authority, _bump_seed_authority) =
-            lido::find_authority_program_address(
-                &self.solido_program_id,
-                &self.solido_address,
+    /// Write the per-validator metrics, so we can alert on a single validator.
+    ///
+    /// Every series is labeled with the vote account, and with the name from
+    /// validator-info if the validator published one.
+    fn write_validator_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        use crate::prometheus::{write_metric, Metric, MetricFamily};
+
+        // Computing the targets fails when no validator receives stake, in
+        // that case there is no target to report.
+        let targets = lido::balance::get_target_balance(
+            self.get_stakeable_reserve(),
+            &self.solido.validators,
+            self.solido.get_max_stake_per_validator(),
+            self.superminority.as_deref(),
+        )
+        .ok();
+
+        let mut stake_metrics = Vec::new();
+        let mut unstake_metrics = Vec::new();
+        let mut target_metrics = Vec::new();
+        let mut commission_metrics = Vec::new();
+        let mut fee_credit_metrics = Vec::new();
+        let mut num_stake_accounts_metrics = Vec::new();
+        let mut delinquent_metrics = Vec::new();
+
+        for (i, (validator, identity)) in self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_identities.iter())
+            .enumerate()
+        {
+            let labeled = |metric: Metric<'static>| {
+                let metric = metric
+                    .at(self.produced_at)
+                    .with_label("vote_account", validator.pubkey.to_string());
+                match &identity.name {
+                    Some(name) => metric.with_label("name", name.clone()),
+                    None => metric,
+                }
+            };
+            stake_metrics.push(labeled(Metric::new_sol(
+                validator.entry.effective_stake_balance(),
+            )));
+            unstake_metrics.push(labeled(Metric::new_sol(
+                validator.entry.unstake_accounts_balance,
+            )));
+            if let Some(targets) = &targets {
+                target_metrics.push(labeled(Metric::new_sol(targets[i])));
+            }
+            if let Some(commission) = identity.commission {
+                commission_metrics.push(labeled(Metric::new(commission as u64)));
+            }
+            fee_credit_metrics.push(labeled(Metric::new_st_sol(validator.entry.fee_credit)));
+            num_stake_accounts_metrics.push(labeled(Metric::new(
+                self.validator_stake_accounts[i].len() as u64,
+            )));
+            if self.validator_vote_accounts[i].is_some() {
+                delinquent_metrics.push(labeled(Metric::new(self.get_delinquent_epochs(i))));
+            }
+        }
+
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_stake_sol",
+                help:
+                    "Balance of the validator's stake accounts, excluding unstake accounts, in SOL.",
+                type_: "gauge",
+                metrics: stake_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_unstake_sol",
+                help: "Balance of the validator's unstake accounts, in SOL.",
+                type_: "gauge",
+                metrics: unstake_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_target_sol",
+                help: "Stake balance that the maintainer aims for the validator to have, in SOL.",
+                type_: "gauge",
+                metrics: target_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_commission_percent",
+                help: "Commission of the validator's vote account, in percent.",
+                type_: "gauge",
+                metrics: commission_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_fee_credit_st_sol",
+                help: "Validation fees that the validator can claim, in stSOL.",
+                type_: "gauge",
+                metrics: fee_credit_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_stake_accounts",
+                help: "Number of stake accounts of the validator, excluding unstake accounts.",
+                type_: "gauge",
+                metrics: num_stake_accounts_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_delinquent",
+                help: "Number of consecutive completed epochs in which the validator earned no vote credits, 0 when it is voting.",
+                type_: "gauge",
+                metrics: delinquent_metrics,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Write the MEV tips metrics, if any of the validators earned tips.
+    fn write_mev_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        use crate::prometheus::{write_metric, Metric, MetricFamily};
+
+        let mut validator_metrics = Vec::new();
+        let mut earned_total = Lamports(0);
+        let mut claimed_total = Lamports(0);
+        let mut accruing_total = Lamports(0);
+
+        for (validator, mev) in self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_mev.iter())
+        {
+            let metric = |amount: Lamports, status: &'static str| {
+                Metric::new_sol(amount)
+                    .at(self.produced_at)
+                    .with_label("status", status.to_string())
+                    .with_label("vote_account", validator.pubkey.to_string())
+            };
+            if let Some(tips) = &mev.previous_epoch {
+                validator_metrics.push(metric(tips.earned, "earned"));
+                validator_metrics.push(metric(tips.claimed, "claimed"));
+                earned_total = (earned_total + tips.earned).expect("SOL supply fits in a u64.");
+                claimed_total = (claimed_total + tips.claimed).expect("SOL supply fits in a u64.");
+            }
+            if let Some(tips) = &mev.current_epoch {
+                validator_metrics.push(metric(tips.earned, "accruing"));
+                accruing_total = (accruing_total + tips.earned).expect("SOL supply fits in a u64.");
+            }
+        }
+
+        // Validators that do not run Jito have no tip distribution accounts,
+        // don't report zeros for instances where no validator runs Jito.
+        if validator_metrics.is_empty() {
+            return Ok(());
+        }
+
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_validator_mev_tips_sol",
+                help: "MEV tips of the validator: earned and claimed in the previous epoch, and accruing in the current epoch.",
+                type_: "gauge",
+                metrics: validator_metrics,
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_mev_tips_sol",
+                help: "MEV tips of all validators: earned and claimed in the previous epoch, and accruing in the current epoch.",
+                type_: "gauge",
+                metrics: vec![
+                    Metric::new_sol(earned_total)
+                        .at(self.produced_at)
+                        .with_label("status", "earned".to_string()),
+                    Metric::new_sol(claimed_total)
+                        .at(self.produced_at)
+                        .with_label("status", "claimed".to_string()),
+                    Metric::new_sol(accruing_total)
+                        .at(self.produced_at)
+                        .with_label("status", "accruing".to_string()),
+                ],
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Write gauges for the maintenance work that is pending, so we can alert
+    /// on maintenance being stuck, not only on errors of the daemon.
+    fn write_pending_work_prometheus<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        use crate::prometheus::{write_metric, Metric, MetricFamily};
+
+        let mut mergeable_stake_accounts = 0_u64;
+        let mut unrecorded_stake = Lamports(0);
+        let mut inactive_unstake = Lamports(0);
+        let mut fee_credit = StLamports(0);
+        for (i, (validator, stake_accounts)) in self
+            .solido
+            .validators
+            .entries
+            .iter()
+            .zip(self.validator_stake_accounts.iter())
+            .enumerate()
+        {
+            mergeable_stake_accounts += stake_accounts
+                .windows(2)
+                .filter(|pair| pair[1].1.can_merge(&pair[0].1))
+                .count() as u64;
+            let (difference, unstake) = self.get_inactive_stake_to_withdraw(i);
+            unrecorded_stake = (unrecorded_stake + difference).expect("SOL supply fits in a u64.");
+            inactive_unstake = (inactive_unstake + unstake).expect("SOL supply fits in a u64.");
+            fee_credit = (fee_credit + validator.entry.fee_credit)
+                .expect("There shouldn't be so many fees to cause stSOL overflow.");
+        }
+
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_pending_reserve_sol",
+                help: "SOL in the reserve that is not staked yet.",
+                type_: "gauge",
+                metrics: vec![Metric::new_sol(self.get_effective_reserve()).at(self.produced_at)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_pending_mergeable_stake_accounts",
+                help: "Number of pairs of consecutive stake accounts that can be merged.",
+                type_: "gauge",
+                metrics: vec![Metric::new(mergeable_stake_accounts).at(self.produced_at)],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_pending_inactive_stake_sol",
+                help: "SOL that WithdrawInactiveStake has yet to process: stake account balance that Solido did not record yet, and fully inactive unstake accounts.",
+                type_: "gauge",
+                metrics: vec![
+                    Metric::new_sol(unrecorded_stake)
+                        .at(self.produced_at)
+                        .with_label("status", "unrecorded_stake".to_string()),
+                    Metric::new_sol(inactive_unstake)
+                        .at(self.produced_at)
+                        .with_label("status", "inactive_unstake".to_string()),
+                ],
+            },
+        )?;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_pending_fee_credit_st_sol",
+                help: "Validation fees that validators did not claim yet, in stSOL.",
+                type_: "gauge",
+                metrics: vec![Metric::new_st_sol(fee_credit).at(self.produced_at)],
+            },
+        )?;
+        let exchange_rate_stale = self.solido.exchange_rate.computed_in_epoch < self.clock.epoch;
+        write_metric(
+            out,
+            &MetricFamily {
+                name: "solido_pending_exchange_rate_update",
+                help:
+                    "1 if the exchange rate was not updated in the current epoch yet, 0 otherwise.",
+                type_: "gauge",
+                metrics: vec![Metric::new(exchange_rate_stale as u64).at(self.produced_at)],
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_stake_authority(&self) -> Pubkey {
+        let (stake_authority, _bump_seed_authority) =
+            derivation_cache::find_authority_program_address(
+                &self.solido_program_id,
+                &self.solido_address,
+                STAKE_AUTHORITY,
+            );
+        stake_authority
+    }
+
+    fn get_rewards_withdraw_authority(&self) -> Pubkey {
+        let (rewards_withdraw_authority, _bump_seed_authority) =
+            derivation_cache::find_authority_program_address(
+                &self.solido_program_id,
+                &self.solido_address,
                 REWARDS_WITHDRAW_AUTHORITY,
             );
         rewards_withdraw_authority
     }
 
-    fn get_mint_authority(&self) -> Pubkey {
-        let (mint_authority, _bump_seed_authority) = lido::find_authority_program_address(
-            &self.solido_program_id,
-            &self.solido_address,
-            MINT_AUTHORITY,
+    fn get_mint_authority(&self) -> Pubkey {
+        let (mint_authority, _bump_seed_authority) =
+            derivation_cache::find_authority_program_address(
+                &self.solido_program_id,
+                &self.solido_address,
+                MINT_AUTHORITY,
+            );
+        mint_authority
+    }
+}
+
+/// Options that change which maintenance the daemon performs.
+#[derive(Default)]
+pub struct MaintenanceOptions {
+    /// When set, publishing the exchange rate to the oracle account is one of
+    /// the tasks, with the lowest priority. See `try_publish_exchange_rate`.
+    pub publish_exchange_rate_max_age_slots: Option<Slot>,
+
+    /// When set, leave the epoch-boundary tasks that anybody can execute to
+    /// keepers, until this much time has passed since the start of the epoch.
+    /// See `crate::keeper`.
+    pub keeper_grace_period: Option<Duration>,
+
+    /// When set, updating the exchange rate goes before all other maintenance
+    /// in the first this many slots of an epoch, and the daemon polls more
+    /// often this close to an epoch boundary.
+    pub epoch_boundary_slots: Option<Slot>,
+
+    /// Simulate the maintenance transaction and print it, instead of sending it.
+    pub dry_run: bool,
+
+    /// Only report which maintenance is due, without signing or simulating
+    /// anything. There is no maintainer then, see `SolidoState::has_maintainer`.
+    pub observe_only: bool,
+
+    /// The priority fee to pay for maintenance transactions, see `crate::priority_fee`.
+    pub priority_fee: PriorityFeeOptions,
+
+    /// When set, only perform maintenance while we hold the maintainer lease,
+    /// and acquire or renew it for this many slots. This way, redundant
+    /// maintainers don't race each other with duplicate transactions.
+    pub maintainer_lease_slots: Option<u64>,
+
+    /// When set, deactivate validators that earned no vote credits in this many
+    /// consecutive epochs. See `try_deactivate_delinquent_validator`.
+    pub deactivate_delinquent_epochs: Option<u64>,
+
+    /// When set, defer stake deposits and rebalancing that would move less
+    /// than this amount, until the amount grows. See `get_deferred_stake_movements`.
+    pub minimum_stake_delta: Option<Lamports>,
+
+    /// When set, don't move stake while the state appears to lag more than
+    /// this many slots behind the cluster. See `SolidoState::get_lag_slots`.
+    pub max_state_lag_slots: Option<Slot>,
+
+    /// The multisig that manages the instance, to propose deactivations to
+    /// when the maintainer is not the manager. See `prepare_propose_deactivation`.
+    pub manager_multisig: Option<ManagerMultisig>,
+
+    /// The SPL Governance governance that manages the instance, as an
+    /// alternative to `manager_multisig`.
+    pub manager_governance: Option<ManagerGovernance>,
+
+    /// Before sending a maintenance transaction, check whether another
+    /// maintainer already performed the same task. See `find_duplicate_transaction`.
+    pub skip_duplicate_work: bool,
+
+    /// When staking deposits and rebalancing, give validators in the cluster's
+    /// superminority no additional stake where the program allows. See `SolidoState::load_superminority`.
+    pub avoid_superminority: bool,
+}
+
+/// A multisig of the Serum multisig program.
+#[derive(Clone, Copy, Debug)]
+pub struct ManagerMultisig {
+    pub program_id: Pubkey,
+    pub address: Pubkey,
+}
+
+impl ManagerMultisig {
+    /// Return the address that signs for the multisig, the manager of the instance.
+    pub fn signer(&self) -> Pubkey {
+        let (signer, _bump_seed) =
+            crate::multisig::get_multisig_program_address(&self.program_id, &self.address);
+        signer
+    }
+}
+
+/// Maintenance that we performed, and what we paid for it besides the base fee.
+pub struct PerformedMaintenance {
+    pub output: MaintenanceOutput,
+    pub priority_fee: Lamports,
+    pub transaction_signature: Signature,
+}
+
+/// Maintenance that we selected and signed in a snapshot, see [`prepare_maintenance`].
+pub enum PreparedMaintenance {
+    /// A signed transaction, to send with [`send_maintenance`] after the snapshot.
+    Signed {
+        output: MaintenanceOutput,
+        priority_fee: Lamports,
+        transaction: Transaction,
+    },
+
+    /// There is nothing to send, because another maintainer did the task
+    /// already, or because signing failed.
+    Attempted(MaintenanceAttempt),
+}
+
+/// The outcome of [`send_maintenance`].
+pub enum MaintenanceAttempt {
+    Performed(PerformedMaintenance),
+
+    /// Another maintainer performed the same task after we read the state, so
+    /// we did not send our transaction.
+    SkippedDuplicate {
+        output: MaintenanceOutput,
+
+        /// The transaction of the other maintainer, base58-encoded.
+        duplicate_signature: String,
+    },
+
+    /// Signing or sending the transaction failed. It might still have been
+    /// executed, see `error::might_have_executed`.
+    Failed {
+        output: MaintenanceOutput,
+        priority_fee: Lamports,
+        error: Error,
+    },
+}
+
+/// How many of the most recent transactions to inspect for duplicate work.
+const DUPLICATE_WORK_SIGNATURES: usize = 10;
+
+/// Return whether the transaction executes `instruction`, signed by anybody.
+///
+/// Instructions of different maintainers differ in the maintainer account that
+/// signs them, so we ignore the signer accounts when comparing.
+fn contains_instruction(transaction: &Transaction, instruction: &Instruction) -> bool {
+    let keys = &transaction.message.account_keys;
+    transaction.message.instructions.iter().any(|compiled| {
+        keys.get(compiled.program_id_index as usize) == Some(&instruction.program_id)
+            && compiled.data == instruction.data
+            && compiled.accounts.len() == instruction.accounts.len()
+            && compiled
+                .accounts
+                .iter()
+                .zip(&instruction.accounts)
+                .all(|(&index, meta)| {
+                    meta.is_signer || keys.get(index as usize) == Some(&meta.pubkey)
+                })
+    })
+}
+
+/// Return the signature of a transaction that executed `instruction` after the
+/// slot at which we read `state`, if there is one.
+///
+/// The state does not reflect such a transaction yet, so when another
+/// maintainer performed the same task in that window, our transaction would
+/// duplicate it, and it would at best fail, but we'd pay the fee regardless.
+/// We inspect the recent transactions on the first account that the
+/// instruction writes to, which for Solido instructions is the Solido account.
+fn find_duplicate_transaction(
+    config: &mut SnapshotConfig,
+    state: &SolidoState,
+    instruction: &Instruction,
+) -> Result<Option<String>> {
+    let account = match instruction.accounts.iter().find(|meta| meta.is_writable) {
+        Some(meta) => meta.pubkey,
+        None => return Ok(None),
+    };
+    let signatures = config.client.get_signatures_for_address(
+        &account,
+        None,
+        None,
+        DUPLICATE_WORK_SIGNATURES,
+    )?;
+    // Signatures are newest first, so we can stop at the snapshot slot.
+    for info in signatures
+        .iter()
+        .take_while(|info| info.slot > state.snapshot_slot)
+        .filter(|info| info.err.is_none())
+    {
+        if let Some(transaction) = config.client.get_transaction(&info.signature)? {
+            if contains_instruction(&transaction.transaction, instruction) {
+                return Ok(Some(info.signature.clone()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// How many signatures we inspect per iteration when counting rejected deposits.
+const REJECTED_DEPOSITS_SIGNATURES: usize = 1_000;
+
+/// Deposits that failed because the depositor is not on the depositor allowlist.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RejectedDeposits {
+    /// Number of rejected deposits after the signature that we started from.
+    pub count: u64,
+
+    /// The most recent signature on the Solido account, to start from next time.
+    pub newest_signature: Option<String>,
+}
+
+/// Return whether a transaction failed because the depositor is not allowed.
+fn is_rejected_deposit(err: &serde_json::Value) -> bool {
+    match serde_json::from_value::<TransactionError>(err.clone()) {
+        Ok(TransactionError::InstructionError(_instr, InstructionError::Custom(error_code))) => {
+            error_code == LidoError::DepositorNotAllowed as u32
+        }
+        _ => false,
+    }
+}
+
+/// Count the transactions on the Solido account after `until` that failed
+/// because the depositor is not on the depositor allowlist.
+///
+/// Failed transactions leave no trace in the on-chain state, so we inspect
+/// the recent transactions on the Solido account, which every deposit reads.
+/// Without `until`, we only look up the newest signature, so we don't count
+/// the history of the account. When more than [`REJECTED_DEPOSITS_SIGNATURES`]
+/// transactions happened since `until`, we only count the most recent ones.
+pub fn get_rejected_deposits(
+    config: &mut SnapshotConfig,
+    solido_address: &Pubkey,
+    until: Option<&str>,
+) -> Result<RejectedDeposits> {
+    let limit = match until {
+        Some(_) => REJECTED_DEPOSITS_SIGNATURES,
+        None => 1,
+    };
+    let signatures =
+        config
+            .client
+            .get_signatures_for_address(solido_address, None, until, limit)?;
+    let count = match until {
+        Some(_) => signatures
+            .iter()
+            .filter_map(|info| info.err.as_ref())
+            .filter(|err| is_rejected_deposit(err))
+            .count() as u64,
+        None => 0,
+    };
+    Ok(RejectedDeposits {
+        count,
+        newest_signature: signatures
+            .first()
+            .map(|info| info.signature.clone())
+            .or_else(|| until.map(|signature| signature.to_string())),
+    })
+}
+
+/// Return the price per compute unit to pay for a transaction with these instructions.
+fn get_compute_unit_price(
+    config: &mut SnapshotConfig,
+    options: &PriorityFeeOptions,
+    instructions: &[Instruction],
+) -> Result<u64> {
+    if !options.dynamic {
+        return Ok(options.micro_lamports_per_compute_unit);
+    }
+    let writable_accounts = get_writable_accounts(instructions);
+    let recent_fees = config
+        .client
+        .get_recent_prioritization_fees(&writable_accounts)?;
+    Ok(get_dynamic_price(options, &recent_fees))
+}
+
+/// One of the maintenance tasks, see [`SolidoState::try_task`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MaintenanceTask {
+    AcquireMaintainerLease,
+    MergeStake,
+    UpdateExchangeRate,
+    DeactivateValidator,
+    UnstakeFromInactiveValidator,
+    CollectValidatorFee,
+    WithdrawInactiveStake,
+    FundWithdrawTicket,
+    UnstakeForWithdrawTickets,
+    StakeDeposit,
+    RebalanceUnstake,
+    ClaimValidatorFee,
+    RemoveValidator,
+    SweepToReserve,
+    PublishExchangeRate,
+}
+
+impl MaintenanceTask {
+    /// All tasks, in the order in which maintenance performs them.
+    pub const ALL: [MaintenanceTask; 15] = [
+        // When maintainers coordinate through the lease, only the holder
+        // performs maintenance, and holding the lease goes before anything else.
+        MaintenanceTask::AcquireMaintainerLease,
+        // Merging stake accounts goes before updating validator balance, to
+        // ensure that the balance update needs to reference as few accounts
+        // as possible.
+        MaintenanceTask::MergeStake,
+        MaintenanceTask::UpdateExchangeRate,
+        // Deactivating a delinquent validator goes before anything that
+        // stakes, so it gets no new stake, and its stake gets unstaked next.
+        MaintenanceTask::DeactivateValidator,
+        MaintenanceTask::UnstakeFromInactiveValidator,
+        // Collecting validator fees goes after updating the exchange rate,
+        // because it may be rejected if the exchange rate is outdated.
+        MaintenanceTask::CollectValidatorFee,
+        // Same for updating the validator balance.
+        MaintenanceTask::WithdrawInactiveStake,
+        // Withdraw tickets get the reserve before staking does.
+        MaintenanceTask::FundWithdrawTicket,
+        MaintenanceTask::UnstakeForWithdrawTickets,
+        MaintenanceTask::StakeDeposit,
+        // Only rebalance once the reserve is staked, otherwise the deposit
+        // could bring validators closer to their target for free.
+        MaintenanceTask::RebalanceUnstake,
+        MaintenanceTask::ClaimValidatorFee,
+        MaintenanceTask::RemoveValidator,
+        // Swept SOL gets staked in a later iteration, so this goes after
+        // anything that keeps the pool healthy.
+        MaintenanceTask::SweepToReserve,
+        MaintenanceTask::PublishExchangeRate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaintenanceTask::AcquireMaintainerLease => "AcquireMaintainerLease",
+            MaintenanceTask::MergeStake => "MergeStake",
+            MaintenanceTask::UpdateExchangeRate => "UpdateExchangeRate",
+            MaintenanceTask::DeactivateValidator => "DeactivateValidator",
+            MaintenanceTask::UnstakeFromInactiveValidator => "UnstakeFromInactiveValidator",
+            MaintenanceTask::CollectValidatorFee => "CollectValidatorFee",
+            MaintenanceTask::WithdrawInactiveStake => "WithdrawInactiveStake",
+            MaintenanceTask::FundWithdrawTicket => "FundWithdrawTicket",
+            MaintenanceTask::UnstakeForWithdrawTickets => "UnstakeForWithdrawTickets",
+            MaintenanceTask::StakeDeposit => "StakeDeposit",
+            MaintenanceTask::RebalanceUnstake => "RebalanceUnstake",
+            MaintenanceTask::ClaimValidatorFee => "ClaimValidatorFee",
+            MaintenanceTask::RemoveValidator => "RemoveValidator",
+            MaintenanceTask::SweepToReserve => "SweepToReserve",
+            MaintenanceTask::PublishExchangeRate => "PublishExchangeRate",
+        }
+    }
+
+    /// Return whether the task is part of maintenance with these options.
+    pub fn is_enabled(&self, options: &MaintenanceOptions) -> bool {
+        match self {
+            MaintenanceTask::AcquireMaintainerLease => options.maintainer_lease_slots.is_some(),
+            MaintenanceTask::DeactivateValidator => options.deactivate_delinquent_epochs.is_some(),
+            MaintenanceTask::PublishExchangeRate => {
+                options.publish_exchange_rate_max_age_slots.is_some()
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Return the tasks in the order in which `select_maintenance` considers them.
+pub fn get_task_order(state: &SolidoState, options: &MaintenanceOptions) -> Vec<MaintenanceTask> {
+    let mut tasks = MaintenanceTask::ALL.to_vec();
+
+    // Until the exchange rate is updated, deposits and withdrawals use the
+    // rate of the previous epoch, so early in the epoch it goes first, right
+    // after the lease.
+    if let Some(boundary_slots) = options.epoch_boundary_slots {
+        if state.slots_into_epoch() < boundary_slots {
+            tasks.retain(|task| *task != MaintenanceTask::UpdateExchangeRate);
+            tasks.insert(1, MaintenanceTask::UpdateExchangeRate);
+        }
+    }
+
+    tasks
+}
+
+/// Why maintenance leaves a task that has something to do for later.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Deferral {
+    /// Another maintainer holds the lease, see `MaintenanceOptions::maintainer_lease_slots`.
+    StandingBy {
+        #[serde(serialize_with = "serialize_b58")]
+        lease_holder: Pubkey,
+        lease_expires_slot: Slot,
+    },
+
+    /// Keepers execute the task early in the epoch, see `MaintenanceOptions::keeper_grace_period`.
+    LeftToKeepers { seconds_into_epoch: u64 },
+
+    /// The stake movement is too small, see `MaintenanceOptions::minimum_stake_delta`.
+    BelowMinimum { amount: Lamports, minimum: Lamports },
+
+    /// The state is too old to move stake, see `MaintenanceOptions::max_state_lag_slots`.
+    StaleState {
+        lag_slots: Slot,
+        max_lag_slots: Slot,
+    },
+}
+
+impl fmt::Display for Deferral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Deferral::StandingBy {
+                lease_holder,
+                lease_expires_slot,
+            } => write!(
+                f,
+                "maintainer {} holds the lease until slot {}",
+                lease_holder, lease_expires_slot
+            ),
+            Deferral::LeftToKeepers { seconds_into_epoch } => {
+                write!(f, "left to keepers, {}s into the epoch", seconds_into_epoch)
+            }
+            Deferral::BelowMinimum { amount, minimum } => {
+                write!(f, "moves {}, less than the minimum of {}", amount, minimum)
+            }
+            Deferral::StaleState {
+                lag_slots,
+                max_lag_slots,
+            } => write!(
+                f,
+                "the state lags {} slots behind, more than the maximum of {}",
+                lag_slots, max_lag_slots
+            ),
+        }
+    }
+}
+
+/// Return why maintenance leaves the task for later, if it does.
+///
+/// This does not cover the lease, when another maintainer holds the lease,
+/// all tasks wait.
+pub fn get_deferral(
+    state: &SolidoState,
+    options: &MaintenanceOptions,
+    output: &MaintenanceOutput,
+) -> Option<Deferral> {
+    // Moving stake based on an old state can undo a movement that already
+    // happened, and then we pay to move the stake back once we catch up.
+    if let Some(max_lag_slots) = options.max_state_lag_slots {
+        let lag_slots = state.get_lag_slots();
+        if lag_slots > max_lag_slots && moves_stake(output) {
+            return Some(Deferral::StaleState {
+                lag_slots,
+                max_lag_slots,
+            });
+        }
+    }
+
+    // Tasks that keepers execute, we skip during the grace period. If the
+    // keepers do their job, the task will be done by the time the period ends,
+    // and the on-chain state tells us that there is nothing left to do.
+    if let Some(grace_period) = options.keeper_grace_period {
+        let time_into_epoch = state.time_into_epoch();
+        if time_into_epoch < grace_period && is_keeper_task(output) {
+            return Some(Deferral::LeftToKeepers {
+                seconds_into_epoch: time_into_epoch.as_secs(),
+            });
+        }
+    }
+
+    // Small stake movements cost the same fee as large ones, and every stake
+    // deposit uses up a stake account seed, so we wait for them to add up.
+    // Unstaking for withdraw tickets and from inactive validators is never
+    // deferred, because users or the manager are waiting for those.
+    if let (Some(amount), Some(minimum)) = (
+        get_deferrable_stake_delta(output),
+        options.minimum_stake_delta,
+    ) {
+        if amount < minimum {
+            return Some(Deferral::BelowMinimum { amount, minimum });
+        }
+    }
+
+    None
+}
+
+/// Log that we leave a task for later, for a deferral returned by `get_deferral`.
+fn emit_deferral(state: &SolidoState, output: &MaintenanceOutput, deferral: &Deferral) {
+    let event = match deferral {
+        Deferral::LeftToKeepers { seconds_into_epoch } => Event::info(
+            "deferred_to_keepers",
+            format!(
+                "Leaving {} to keepers, {}s into the epoch.",
+                output.operation_name(),
+                seconds_into_epoch,
+            ),
+        )
+        .field("seconds_into_epoch", *seconds_into_epoch),
+        Deferral::BelowMinimum { amount, minimum } => Event::info(
+            "deferred_below_minimum",
+            format!(
+                "Deferring {} of {}, less than the minimum of {}.",
+                output.operation_name(),
+                amount,
+                minimum,
+            ),
+        )
+        .field("amount_lamports", amount.0)
+        .field("minimum_lamports", minimum.0),
+        Deferral::StaleState {
+            lag_slots,
+            max_lag_slots,
+        } => Event::warning(
+            "deferred_stale_state",
+            format!(
+                "Not performing {}, the state lags {} slots behind the cluster, more than the maximum of {}.",
+                output.operation_name(),
+                lag_slots,
+                max_lag_slots,
+            ),
+        )
+        .field("lag_slots", *lag_slots)
+        .field("max_lag_slots", *max_lag_slots),
+        // We stand by before we consider any task, see `select_maintenance`.
+        Deferral::StandingBy { .. } => return,
+    };
+    event
+        .field("operation", output.operation_name())
+        .field("slot", state.clock.slot)
+        .emit();
+}
+
+/// Return the deferral that applies to every task, because another maintainer holds the lease.
+fn get_standing_by(state: &SolidoState, options: &MaintenanceOptions) -> Option<Deferral> {
+    if options.maintainer_lease_slots.is_none() {
+        return None;
+    }
+    state
+        .get_lease_held_by_other()
+        .map(|lease| Deferral::StandingBy {
+            lease_holder: lease.holder,
+            lease_expires_slot: lease.expires_slot,
+        })
+}
+
+/// Return the first maintenance task that there is to do, if any.
+pub fn select_maintenance(
+    state: &SolidoState,
+    options: &MaintenanceOptions,
+) -> Option<(Vec<Instruction>, MaintenanceOutput)> {
+    // When maintainers coordinate through the lease, only the holder performs
+    // maintenance.
+    if options.maintainer_lease_slots.is_some() {
+        if let Some(lease) = state.get_lease_held_by_other() {
+            Event::info(
+                "standing_by",
+                format!(
+                    "Maintainer {} holds the lease until slot {}, standing by.",
+                    lease.holder, lease.expires_slot,
+                ),
+            )
+            .field("lease_holder", lease.holder.to_string())
+            .field("lease_expires_slot", lease.expires_slot)
+            .field("slot", state.clock.slot)
+            .emit();
+            return None;
+        }
+    }
+
+    // Try all tasks one by one, and select the first one that produces
+    // instructions, and that we don't leave for later.
+    for task in get_task_order(state, options) {
+        if let Some((instructions, output)) = state.try_task(task, options) {
+            match get_deferral(state, options, &output) {
+                Some(deferral) => emit_deferral(state, &output, &deferral),
+                None => return Some((instructions, output)),
+            }
+        }
+    }
+
+    None
+}
+
+/// What maintenance concluded about one task.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// This is the task that maintenance performs next.
+    Selected { output: MaintenanceOutput },
+
+    /// The task has something to do, after the selected task.
+    Waiting { output: MaintenanceOutput },
+
+    /// The task has something to do, but maintenance leaves it for later.
+    Deferred {
+        output: MaintenanceOutput,
+        deferral: Deferral,
+    },
+
+    /// The task has nothing to do.
+    Idle { reason: String },
+
+    /// The task is not enabled in the maintenance options.
+    Disabled,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct TaskExplanation {
+    pub task: &'static str,
+
+    #[serde(flatten)]
+    pub status: TaskStatus,
+}
+
+/// Every task that maintenance considers, and why it does or does not perform it.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct MaintenanceExplanation {
+    pub slot: Slot,
+    pub epoch: Epoch,
+    pub slots_into_epoch: Slot,
+
+    /// The tasks in the order in which maintenance considers them.
+    pub tasks: Vec<TaskExplanation>,
+}
+
+impl MaintenanceExplanation {
+    /// Return the task that maintenance performs next, if any.
+    pub fn get_selected(&self) -> Option<&MaintenanceOutput> {
+        self.tasks.iter().find_map(|task| match &task.status {
+            TaskStatus::Selected { output } => Some(output),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for MaintenanceExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Maintenance at slot {}, {} slots into epoch {}:",
+            self.slot, self.slots_into_epoch, self.epoch
+        )?;
+        for task in &self.tasks {
+            let (status, detail) = match &task.status {
+                TaskStatus::Selected { .. } => ("selected", String::new()),
+                TaskStatus::Waiting { .. } => ("waiting", String::new()),
+                TaskStatus::Deferred { deferral, .. } => ("deferred", deferral.to_string()),
+                TaskStatus::Idle { reason } => ("idle", reason.clone()),
+                TaskStatus::Disabled => ("disabled", String::new()),
+            };
+            writeln!(f, "  {:<30} {:<9} {}", task.task, status, detail)?;
+            if let TaskStatus::Selected { output } = &task.status {
+                for line in output.to_string().lines() {
+                    writeln!(f, "    {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Consider every maintenance task, and explain what maintenance would do.
+///
+/// Unlike `select_maintenance`, this does not stop at the first task that has
+/// something to do, and it logs nothing.
+pub fn explain_maintenance(
+    state: &SolidoState,
+    options: &MaintenanceOptions,
+) -> MaintenanceExplanation {
+    let standing_by = get_standing_by(state, options);
+    let mut has_selected = false;
+    let tasks = get_task_order(state, options)
+        .into_iter()
+        .map(|task| {
+            let status = if !task.is_enabled(options) {
+                TaskStatus::Disabled
+            } else {
+                match state.try_task(task, options) {
+                    None => TaskStatus::Idle {
+                        reason: state.get_idle_reason(task, options),
+                    },
+                    Some((_, output)) => {
+                        let deferral = standing_by
+                            .clone()
+                            .or_else(|| get_deferral(state, options, &output));
+                        match deferral {
+                            Some(deferral) => TaskStatus::Deferred { output, deferral },
+                            None if has_selected => TaskStatus::Waiting { output },
+                            None => {
+                                has_selected = true;
+                                TaskStatus::Selected { output }
+                            }
+                        }
+                    }
+                }
+            };
+            TaskExplanation {
+                task: task.name(),
+                status,
+            }
+        })
+        .collect();
+
+    MaintenanceExplanation {
+        slot: state.clock.slot,
+        epoch: state.clock.epoch,
+        slots_into_epoch: state.slots_into_epoch(),
+        tasks,
+    }
+}
+
+/// Read the state, and explain what maintenance would do.
+pub fn run_explain_maintenance(
+    config: &mut SnapshotConfig,
+    opts: &ExplainMaintenanceOpts,
+) -> Result<MaintenanceExplanation> {
+    let state = SolidoState::new(config, opts.solido_program_id(), opts.solido_address())?;
+    Ok(explain_maintenance(&state, &MaintenanceOptions::default()))
+}
+
+/// Return the amount of stake that the task moves, if it is a stake movement
+/// that `MaintenanceOptions::minimum_stake_delta` applies to.
+pub fn get_deferrable_stake_delta(output: &MaintenanceOutput) -> Option<Lamports> {
+    match output {
+        MaintenanceOutput::StakeDeposit { amount, .. } => Some(*amount),
+        MaintenanceOutput::RebalanceUnstake { amount, .. } => Some(*amount),
+        _ => None,
+    }
+}
+
+/// Return whether the maintenance moves stake to, from, or between validators.
+pub fn moves_stake(output: &MaintenanceOutput) -> bool {
+    matches!(
+        output,
+        MaintenanceOutput::StakeDeposit { .. }
+            | MaintenanceOutput::RebalanceUnstake { .. }
+            | MaintenanceOutput::UnstakeForWithdrawTickets { .. }
+            | MaintenanceOutput::UnstakeFromInactiveValidator { .. }
+    )
+}
+
+/// Sign a transaction for the first maintenance task that there is to do, if any.
+///
+/// This does not send the transaction, so it is safe to call in a snapshot
+/// that may be retried. Send it with [`send_maintenance`] afterwards.
+pub fn prepare_maintenance(
+    config: &mut SnapshotConfig,
+    state: &SolidoState,
+    options: &MaintenanceOptions,
+) -> Result<Option<PreparedMaintenance>> {
+    // To prevent the maintenance transactions failing with mysterious errors
+    // that are difficult to debug, before we do any maintenance, do a sanity
+    // check to ensure that the fee payer, usually the maintainer, has at least
+    // some SOL to pay the transaction fees.
+    let fee_payer_name = if state.fee_payer_address == state.maintainer_address {
+        "maintainer account"
+    } else {
+        "fee payer"
+    };
+    let minimum_fee_payer_balance = Lamports(100_000_000);
+    if state.fee_payer_balance < minimum_fee_payer_balance {
+        let message = format!(
+            "Balance of the {} {} is less than {}. Please fund the {}.",
+            fee_payer_name, state.fee_payer_address, minimum_fee_payer_balance, fee_payer_name,
+        );
+        // A dry run sends no transactions, so an unfunded fee payer is fine
+        // there. The simulation will still fail though, if it can't pay fees.
+        if options.dry_run {
+            Event::warning("maintainer_balance_low", message)
+                .field("maintainer", state.maintainer_address.to_string())
+                .field("fee_payer", state.fee_payer_address.to_string())
+                .emit();
+        } else {
+            return Err(MaintenanceError::new(message).into());
+        }
+    }
+
+    let (task_instructions, output) = match select_maintenance(state, options) {
+        Some(task) => task,
+        None => return Ok(None),
+    };
+
+    let mut instructions = Vec::new();
+    let price = get_compute_unit_price(config, &options.priority_fee, &task_instructions)?;
+    if price > 0 {
+        instructions.push(set_compute_unit_price(price));
+    }
+    instructions.extend(task_instructions.iter().cloned());
+    let priority_fee = get_priority_fee(price, task_instructions.len());
+
+    if options.dry_run {
+        let transaction = config.sign_transaction(&instructions, &[config.signer])?;
+        let simulation = config.client.simulate_transaction(&transaction)?;
+        log_dry_run(
+            &task_instructions,
+            &output,
+            &simulation,
+            price,
+            priority_fee,
         );
-        mint_authority
+        // We did not change anything, so there is no point in trying
+        // again right away, report that there was nothing to do.
+        return Ok(None);
+    }
+
+    if options.skip_duplicate_work {
+        // Another maintainer who performed the same task started with the
+        // same first instruction.
+        if let Some(duplicate_signature) =
+            find_duplicate_transaction(config, state, &task_instructions[0])?
+        {
+            return Ok(Some(PreparedMaintenance::Attempted(
+                MaintenanceAttempt::SkippedDuplicate {
+                    output,
+                    duplicate_signature,
+                },
+            )));
+        }
+    }
+
+    // For maintenance operations, the maintainer is the only signer,
+    // and that should be sufficient.
+    let transaction = match config.sign_transaction(&instructions, &[config.signer]) {
+        Ok(transaction) => transaction,
+        // A missing account is not a failed transaction, the snapshot
+        // retries with the account.
+        Err(SnapshotError::MissingAccount) => return Err(SnapshotError::MissingAccount),
+        Err(SnapshotError::OtherError(error)) => {
+            return Ok(Some(PreparedMaintenance::Attempted(
+                MaintenanceAttempt::Failed {
+                    output,
+                    priority_fee,
+                    error,
+                },
+            )))
+        }
+    };
+    Ok(Some(PreparedMaintenance::Signed {
+        output,
+        priority_fee,
+        transaction,
+    }))
+}
+
+/// Send the transaction of maintenance that [`prepare_maintenance`] signed.
+pub fn send_maintenance(
+    config: &mut SnapshotClientConfig,
+    prepared: PreparedMaintenance,
+) -> MaintenanceAttempt {
+    match prepared {
+        PreparedMaintenance::Signed {
+            output,
+            priority_fee,
+            transaction,
+        } => match config.send_transaction(&transaction) {
+            Ok(transaction_signature) => MaintenanceAttempt::Performed(PerformedMaintenance {
+                output,
+                priority_fee,
+                transaction_signature,
+            }),
+            Err(error) => MaintenanceAttempt::Failed {
+                output,
+                priority_fee,
+                error,
+            },
+        },
+        PreparedMaintenance::Attempted(attempt) => attempt,
     }
 }
 
-pub fn try_perform_maintenance(
+/// Transfers SOL to the fee payer when its balance runs low.
+pub struct FeePayerTopUp<'a> {
+    /// The account to transfer from. It also pays the fee of the transfer,
+    /// so a top-up works even when the fee payer has nothing left.
+    pub funding: &'a dyn Signer,
+
+    /// Top up when the balance of the fee payer is below this amount.
+    pub below: Lamports,
+
+    /// The amount to transfer.
+    pub amount: Lamports,
+}
+
+/// A top-up of the fee payer that we performed.
+pub struct PerformedTopUp {
+    pub funding_address: Pubkey,
+    pub fee_payer_address: Pubkey,
+    pub amount: Lamports,
+    pub transaction_signature: Signature,
+}
+
+/// Sign a transfer of SOL from the funding account to the fee payer, if the
+/// balance of the fee payer dropped below the floor.
+///
+/// The caller sends the transaction, with [`SnapshotClientConfig::send_transaction`].
+pub fn prepare_top_up_fee_payer(
     config: &mut SnapshotConfig,
     state: &SolidoState,
-) -> Result<Option<MaintenanceOutput>> {
-    // To prevent the maintenance transactions failing with mysterious errors
-    // that are difficult to debug, before we do any maintenance, do a sanity
-    // check to ensure that the maintainer has at least some SOL to pay the
-    // transaction fees.
-    let minimum_maintainer_balance = Lamports(100_000_000);
-    if Lamports(state.maintainer_account.lamports) < minimum_maintainer_balance {
+    top_up: &FeePayerTopUp,
+) -> Result<Option<(Transaction, PerformedTopUp)>> {
+    if state.fee_payer_balance >= top_up.below {
+        return Ok(None);
+    }
+    let funding_address = top_up.funding.pubkey();
+    let instruction =
+        system_instruction::transfer(&funding_address, &state.fee_payer_address, top_up.amount.0);
+    let transaction = config.sign_transaction_with_fee_payer(
+        &[instruction],
+        top_up.funding,
+        &[top_up.funding],
+    )?;
+    let performed = PerformedTopUp {
+        funding_address,
+        fee_payer_address: state.fee_payer_address,
+        amount: top_up.amount,
+        transaction_signature: transaction.signatures[0],
+    };
+    Ok(Some((transaction, performed)))
+}
+
+/// Transfers the developer fee stSOL to a list of destinations, in fixed shares.
+pub struct DeveloperFeeClaim<'a> {
+    /// The owner of the developer fee account, it signs the transfers.
+    pub owner: &'a dyn Signer,
+
+    /// Claim only when the developer fee account holds at least this much.
+    pub minimum: StLamports,
+
+    /// The stSOL accounts to transfer to, with their share in percent. The
+    /// shares add up to 100.
+    pub splits: Vec<(Pubkey, u8)>,
+}
+
+/// One transfer of a developer fee claim.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct DeveloperFeeTransfer {
+    #[serde(serialize_with = "serialize_b58")]
+    pub destination: Pubkey,
+
+    #[serde(rename = "amount_st_lamports")]
+    pub amount: StLamports,
+}
+
+/// A claim of the developer fee that we performed.
+#[derive(Debug, Serialize)]
+pub struct PerformedDeveloperFeeClaim {
+    #[serde(serialize_with = "serialize_b58")]
+    pub developer_account: Pubkey,
+
+    /// The balance of the developer fee account, which we transferred in full.
+    #[serde(rename = "amount_st_lamports")]
+    pub amount: StLamports,
+
+    pub transfers: Vec<DeveloperFeeTransfer>,
+
+    #[serde(skip)]
+    pub transaction_signature: Signature,
+}
+
+/// Split `amount` in shares of `splits`, in percent.
+///
+/// The shares round down, and the last destination gets what rounding leaves,
+/// so the transfers add up to `amount` exactly.
+pub fn split_developer_fee(
+    amount: StLamports,
+    splits: &[(Pubkey, u8)],
+) -> Vec<DeveloperFeeTransfer> {
+    let mut remaining = amount.0;
+    splits
+        .iter()
+        .enumerate()
+        .map(|(i, &(destination, percentage))| {
+            let share = if i + 1 == splits.len() {
+                remaining
+            } else {
+                // The share is at most the amount, so it fits in a u64.
+                (amount.0 as u128 * percentage as u128 / 100) as u64
+            };
+            remaining -= share;
+            DeveloperFeeTransfer {
+                destination,
+                amount: StLamports(share),
+            }
+        })
+        .collect()
+}
+
+/// Sign a transfer of the stSOL in the developer fee account to the
+/// destinations of `claim`, if it holds at least the minimum.
+///
+/// The caller sends the transaction, with [`SnapshotClientConfig::send_transaction`].
+pub fn prepare_claim_developer_fee(
+    config: &mut SnapshotConfig,
+    state: &SolidoState,
+    claim: &DeveloperFeeClaim,
+) -> Result<Option<(Transaction, PerformedDeveloperFeeClaim)>> {
+    let developer_account = state.solido.fee_recipients.developer_account;
+    let account: spl_token::state::Account = config.client.get_token_state(&developer_account)?;
+    if account.owner != claim.owner.pubkey() {
         return Err(MaintenanceError::new(format!(
-            "Balance of the maintainer account {} is less than {}. \
-            Please fund the maintainer account.",
-            state.maintainer_address, minimum_maintainer_balance,
+            "The developer fee account {} is owned by {}, not by the developer fee keypair {}.",
+            developer_account,
+            account.owner,
+            claim.owner.pubkey(),
         ))
         .into());
     }
+    let amount = StLamports(account.amount);
+    if amount == StLamports(0) || amount < claim.minimum {
+        return Ok(None);
+    }
 
-    // Try all of these operations one by one, and select the first one that
-    // produces an instruction.
-    let instruction_output: Option<(Instruction, MaintenanceOutput)> = None
-        // Merging stake accounts goes before updating validator balance, to
-        // ensure that the balance update needs to reference as few accounts
-        // as possible.
-        .or_else(|| state.try_merge_on_all_stakes())
-        .or_else(|| state.try_update_exchange_rate())
-        .or_else(|| state.try_unstake_from_inactive_validator())
-        // Collecting validator fees goes after updating the exchange rate,
-        // because it may be rejected if the exchange rate is outdated.
-        .or_else(|| state.try_collect_validator_fee())
-        // Same for updating the validator balance.
-        .or_else(|| state.try_withdraw_inactive_stake())
-        .or_else(|| state.try_stake_deposit())
-        .or_else(|| state.try_claim_validator_fee())
-        .or_else(|| state.try_remove_validator());
-
-    match instruction_output {
-        Some((instruction, output)) => {
-            // For maintenance operations, the maintainer is the only signer,
-            // and that should be sufficient.
-            config.sign_and_send_transaction(&[instruction], &[config.signer])?;
-            Ok(Some(output))
+    let transfers = split_developer_fee(amount, &claim.splits);
+    let mut instructions = Vec::with_capacity(transfers.len());
+    for transfer in transfers.iter().filter(|t| t.amount > StLamports(0)) {
+        // The builders of `spl_token` refuse other program ids, but Token-2022
+        // encodes this instruction the same way, so we only swap the program.
+        let mut instruction = spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            &developer_account,
+            &state.solido.st_sol_mint,
+            &transfer.destination,
+            &account.owner,
+            &[],
+            transfer.amount.0,
+            state.st_sol_mint.decimals,
+        )?;
+        instruction.program_id = state.solido.st_sol_token_program;
+        instructions.push(instruction);
+    }
+    let transaction = config.sign_transaction(&instructions, &[claim.owner])?;
+    let performed = PerformedDeveloperFeeClaim {
+        developer_account,
+        amount,
+        transfers,
+        transaction_signature: transaction.signatures[0],
+    };
+    Ok(Some((transaction, performed)))
+}
+
+/// A deactivation that we proposed to the manager multisig or governance.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ProposedDeactivation {
+    #[serde(serialize_with = "serialize_b58")]
+    pub validator_vote_account: Pubkey,
+
+    /// Number of consecutive epochs in which the validator earned no credits.
+    pub delinquent_epochs: u64,
+
+    /// What `transaction_address` is, "multisig transaction" or "governance proposal".
+    pub proposal_kind: &'static str,
+
+    /// The multisig transaction that the owners need to approve, or the
+    /// governance proposal that token holders need to vote on.
+    #[serde(serialize_with = "serialize_b58")]
+    pub transaction_address: Pubkey,
+}
+
+/// Return whether the multisig has a pending transaction that executes `instruction`.
+fn has_pending_multisig_transaction(
+    config: &mut SnapshotConfig,
+    multisig: &ManagerMultisig,
+    instruction: &Instruction,
+) -> Result<bool> {
+    let multisig_state: serum_multisig::Multisig =
+        config.client.get_account_deserialize(&multisig.address)?;
+
+    // Transaction accounts start with the Anchor discriminator, and then the multisig.
+    let filters = [AccountFilter::Memcmp {
+        offset: 8,
+        bytes: multisig.address.to_bytes().to_vec(),
+    }];
+    let accounts = config
+        .client
+        .get_program_accounts(&multisig.program_id, &filters)?;
+    for (_address, account) in accounts {
+        let transaction = match serum_multisig::Transaction::try_deserialize(&mut &account.data[..])
+        {
+            Ok(transaction) => transaction,
+            // The multisig account itself, for example.
+            Err(..) => continue,
+        };
+        // After the owners change, old transactions can no longer execute.
+        let is_pending = !transaction.did_execute
+            && transaction.owner_set_seqno == multisig_state.owner_set_seqno;
+        let is_same_instruction = transaction.program_id == instruction.program_id
+            && transaction.data == instruction.data
+            && transaction
+                .accounts
+                .iter()
+                .map(|account| account.pubkey)
+                .eq(instruction.accounts.iter().map(|account| account.pubkey));
+        if is_pending && is_same_instruction {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Sign a proposal to deactivate a delinquent validator to the manager
+/// multisig or governance, if the maintainer cannot deactivate it itself.
+///
+/// We propose once per validator: while a proposal to deactivate it is
+/// pending, we don't propose again. The maintainer must be an owner of the
+/// multisig, or hold enough governing tokens to create governance proposals.
+/// In a dry run, we only log that we would propose. The caller sends the
+/// transaction, with [`SnapshotClientConfig::send_transaction`].
+pub fn prepare_propose_deactivation(
+    config: &mut SnapshotConfig,
+    state: &SolidoState,
+    options: &MaintenanceOptions,
+) -> Result<Option<(Transaction, ProposedDeactivation)>> {
+    let min_epochs = match options.deactivate_delinquent_epochs {
+        Some(min_epochs) => min_epochs,
+        None => return Ok(None),
+    };
+    if options.manager_multisig.is_none() && options.manager_governance.is_none() {
+        return Ok(None);
+    }
+    // If we are the manager, we deactivate without proposal.
+    if state.solido.manager == state.maintainer_address {
+        return Ok(None);
+    }
+    let (validator, delinquent_epochs) = match state.get_delinquent_validator(min_epochs) {
+        Some(delinquent) => delinquent,
+        None => return Ok(None),
+    };
+
+    let manager = state.solido.manager;
+    let multisig = options
+        .manager_multisig
+        .filter(|multisig| multisig.signer() == manager);
+    let governance = options
+        .manager_governance
+        .filter(|governance| governance.address == manager);
+    if multisig.is_none() && governance.is_none() {
+        return Err(MaintenanceError::new(format!(
+            "Cannot propose to deactivate validator {}, the manager of the instance is {}, \
+            not the configured multisig or governance.",
+            validator.pubkey, manager,
+        ))
+        .into());
+    }
+
+    let instruction = state.get_deactivate_validator_instruction(manager, validator.pubkey);
+    let is_pending = match (&multisig, &governance) {
+        (Some(multisig), _) => has_pending_multisig_transaction(config, multisig, &instruction)?,
+        (None, Some(governance)) => {
+            governance::has_pending_proposal(config, governance, &instruction)?
+        }
+        (None, None) => unreachable!("We checked above that one of them is the manager."),
+    };
+    if is_pending {
+        return Ok(None);
+    }
+
+    if options.dry_run {
+        Event::info(
+            "dry_run",
+            format!(
+                "Dry run, not proposing to deactivate validator {}, delinquent for {} epochs.",
+                validator.pubkey, delinquent_epochs,
+            ),
+        )
+        .field("operation", "DeactivateValidator")
+        .field("validator_vote_account", validator.pubkey.to_string())
+        .field("delinquent_epochs", delinquent_epochs)
+        .emit();
+        return Ok(None);
+    }
+
+    let (transaction, proposal_kind, transaction_address) = match (multisig, governance) {
+        (Some(multisig), _) => {
+            let (transaction, output) = sign_propose_instruction(
+                config,
+                &multisig.program_id,
+                multisig.address,
+                instruction,
+            )?;
+            (
+                transaction,
+                "multisig transaction",
+                output.transaction_address,
+            )
+        }
+        (None, Some(governance)) => {
+            let (transaction, output) = governance::sign_propose_instruction(
+                config,
+                &governance,
+                format!("Deactivate delinquent validator {}", validator.pubkey),
+                String::new(),
+                instruction,
+            )?;
+            (transaction, "governance proposal", output.proposal_address)
+        }
+        (None, None) => unreachable!("We checked above that one of them is the manager."),
+    };
+    let proposed = ProposedDeactivation {
+        validator_vote_account: validator.pubkey,
+        delinquent_epochs,
+        proposal_kind,
+        transaction_address,
+    };
+    Ok(Some((transaction, proposed)))
+}
+
+/// Log the maintenance that we would perform, and the outcome of its simulation.
+fn log_dry_run(
+    instructions: &[Instruction],
+    output: &MaintenanceOutput,
+    simulation: &RpcSimulateTransactionResult,
+    price: u64,
+    priority_fee: Lamports,
+) {
+    use std::fmt::Write;
+
+    // Writing to a string does not fail, so we ignore the results.
+    let mut message = String::new();
+    let _ = writeln!(
+        message,
+        "Dry run, not sending the transaction for:\n{}",
+        output
+    );
+    for instruction in instructions {
+        let _ = writeln!(message, "  Program: {}", instruction.program_id);
+        let _ = writeln!(
+            message,
+            "  Data:    {}",
+            bs58::encode(&instruction.data).into_string()
+        );
+        for account in &instruction.accounts {
+            let _ = writeln!(
+                message,
+                "  Account: [{}{}] {}",
+                if account.is_writable { 'W' } else { '-' },
+                if account.is_signer { 'S' } else { '-' },
+                account.pubkey,
+            );
+        }
+    }
+    match &simulation.err {
+        None => {
+            let _ = write!(message, "Simulation succeeded.");
+        }
+        Some(err) => {
+            let _ = write!(message, "Simulation failed: {}", err);
         }
-        None => Ok(None),
     }
+    for line in simulation.logs.iter().flatten() {
+        let _ = write!(message, "\n  {}", line);
+    }
+    if price > 0 {
+        let _ = write!(
+            message,
+            "\n  Priority fee: {} micro-lamports per compute unit, {} in total.",
+            price, priority_fee
+        );
+    }
+
+    Event::info("dry_run", message)
+        .field("operation", output.operation_name())
+        .field("output", output)
+        .field(
+            "simulation_error",
+            simulation.err.as_ref().map(|err| err.to_string()),
+        )
+        .field("simulation_logs", &simulation.logs)
+        .field("priority_fee_lamports", priority_fee.0)
+        .emit();
 }
 
 /// Inspect the on-chain Solido state, and if there is maintenance that can be
@@ -1016,13 +3810,37 @@ pub fn run_perform_maintenance(
     opts: &PerformMaintenanceOpts,
 ) -> Result<Option<MaintenanceOutput>> {
     let state = SolidoState::new(config, opts.solido_program_id(), opts.solido_address())?;
-    try_perform_maintenance(config, &state)
+    let prepared = prepare_maintenance(config, &state, &MaintenanceOptions::default())?;
+    match prepared {
+        // This command takes a single snapshot, so we can send in it.
+        Some(PreparedMaintenance::Signed {
+            output,
+            transaction,
+            ..
+        }) => {
+            config.send_transaction(&transaction)?;
+            Ok(Some(output))
+        }
+        Some(PreparedMaintenance::Attempted(MaintenanceAttempt::Failed { error, .. })) => {
+            Err(SnapshotError::OtherError(error))
+        }
+        Some(PreparedMaintenance::Attempted(..)) | None => Ok(None),
+    }
 }
 
 #[cfg(test)]
 mod test {
 
+    use solana_sdk::signature::Keypair;
+
     use super::*;
+    use crate::account_dump::AccountDump;
+    use crate::config::OutputMode;
+    use crate::mock_rpc::{new_solido_fixture, MockRpc};
+    use crate::snapshot::SnapshotClient;
+    use crate::validator_info::VoteHistory;
+    use crate::Config;
+    use lido::state::{PendingRewardDistribution, RewardDistribution, DEFAULT_VALIDATOR_WEIGHT};
 
     /// Produce a new state with `default` Solido instance in it, and random pubkeys.
     fn new_empty_solido() -> SolidoState {
@@ -1034,19 +3852,491 @@ mod test {
             validator_stake_accounts: vec![],
             validator_unstake_accounts: vec![],
             validator_vote_account_balances: vec![],
+            validator_mev: vec![],
+            validator_identities: vec![],
+            validator_vote_accounts: vec![],
+            superminority: None,
             st_sol_mint: Mint::default(),
+            exchange_rate_oracle: None,
+            maintainer_lease: None,
+            withdraw_ticket_to_fund: None,
+            sweepable_balances: vec![],
             reserve_address: Pubkey::new_unique(),
             reserve_account: Account::default(),
             rent: Rent::default(),
             clock: Clock::default(),
+            epoch_schedule: EpochSchedule::without_warmup(),
+            snapshot_slot: 0,
             maintainer_address: Pubkey::new_unique(),
             maintainer_account: Account::default(),
+            fee_payer_address: Pubkey::new_unique(),
+            fee_payer_balance: Lamports(0),
         };
 
-        // The reserve should be rent-exempt.
-        state.reserve_account.lamports = state.rent.minimum_balance(0);
+        // The reserve should be rent-exempt.
+        state.reserve_account.lamports = state.rent.minimum_balance(0);
+
+        state
+    }
+
+    #[test]
+    fn publish_exchange_rate_only_when_outdated_or_old() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.clock.slot = 1_000;
+        let max_age_slots = 100;
+
+        // Before the exchange rate is updated in this epoch, there is nothing to publish.
+        state.solido.exchange_rate.computed_in_epoch = 2;
+        assert_eq!(state.try_publish_exchange_rate(max_age_slots), None);
+
+        // Once it is updated, we publish, because nothing was published yet.
+        state.solido.exchange_rate.computed_in_epoch = 3;
+        let (_, output) = state.try_publish_exchange_rate(max_age_slots).unwrap();
+        let (exchange_rate_oracle, _) =
+            ExchangeRateOracle::find_address(&state.solido_program_id, &state.solido_address);
+        assert_eq!(
+            output,
+            MaintenanceOutput::PublishExchangeRate {
+                exchange_rate_oracle,
+                computed_in_epoch: 3,
+            }
+        );
+
+        // A recent publication of the current rate is fresh enough.
+        state.exchange_rate_oracle = Some(ExchangeRateOracle {
+            exchange_rate: state.solido.exchange_rate.clone(),
+            published_slot: 950,
+            ..ExchangeRateOracle::default()
+        });
+        assert_eq!(state.try_publish_exchange_rate(max_age_slots), None);
+
+        // But not once it gets too old.
+        state.clock.slot = 1_050;
+        assert!(state.try_publish_exchange_rate(max_age_slots).is_some());
+
+        // Or when the published rate differs from the current one.
+        state.clock.slot = 1_000;
+        state.solido.exchange_rate.sol_balance = Lamports(1);
+        assert!(state.try_publish_exchange_rate(max_age_slots).is_some());
+    }
+
+    #[test]
+    fn sweep_to_reserve_only_above_minimum() {
+        let mut state = new_empty_solido();
+        let mint_authority = Pubkey::new_unique();
+        let oracle = Pubkey::new_unique();
+        state.sweepable_balances = vec![
+            (mint_authority, SolidoState::MINIMUM_WITHDRAW_AMOUNT),
+            (oracle, Lamports(0)),
+        ];
+        // Sweeping dust would cost more in fees than it brings in.
+        assert_eq!(state.try_sweep_to_reserve(), None);
+
+        state.sweepable_balances[1].1 = Lamports(2_000_000_000);
+        let (instruction, output) = state.try_sweep_to_reserve().unwrap();
+        assert_eq!(
+            output,
+            MaintenanceOutput::SweepToReserve {
+                account: oracle,
+                amount: Lamports(2_000_000_000),
+            }
+        );
+        assert_eq!(instruction.accounts[3].pubkey, oracle);
+    }
+
+    #[test]
+    fn only_the_lease_holder_performs_maintenance() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.clock.slot = 1_000;
+        state.solido.exchange_rate.computed_in_epoch = 2;
+        let options = MaintenanceOptions {
+            maintainer_lease_slots: Some(100),
+            ..MaintenanceOptions::default()
+        };
+        let (maintainer_lease, _) =
+            MaintainerLease::find_address(&state.solido_program_id, &state.solido_address);
+
+        // Without a lease, we acquire it before anything else.
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(
+            output,
+            MaintenanceOutput::AcquireMaintainerLease {
+                maintainer_lease,
+                expires_slot: 1_100,
+            }
+        );
+
+        // When we hold it, we perform maintenance.
+        state.maintainer_lease = Some(MaintainerLease {
+            holder: state.maintainer_address,
+            expires_slot: 1_080,
+            ..MaintainerLease::default()
+        });
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output, MaintenanceOutput::UpdateExchangeRate);
+
+        // Until less than half of the lease remains, then we renew it first.
+        state.clock.slot = 1_030;
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output.operation_name(), "AcquireMaintainerLease");
+
+        // When somebody else holds the lease, we stand by.
+        state.maintainer_lease = Some(MaintainerLease {
+            holder: Pubkey::new_unique(),
+            expires_slot: 1_080,
+            ..MaintainerLease::default()
+        });
+        assert_eq!(select_maintenance(&state, &options), None);
+
+        // Until their lease expires, then we take over.
+        state.clock.slot = 1_080;
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output.operation_name(), "AcquireMaintainerLease");
+
+        // Without coordination, the lease does not matter.
+        state.clock.slot = 1_000;
+        assert_eq!(
+            select_maintenance(&state, &MaintenanceOptions::default()).map(|(_, output)| output),
+            Some(MaintenanceOutput::UpdateExchangeRate)
+        );
+    }
+
+    #[test]
+    fn keeper_tasks_are_deferred_during_grace_period() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.clock.epoch_start_timestamp = 1_000;
+        state.solido.exchange_rate.computed_in_epoch = 2;
+        let options = MaintenanceOptions {
+            keeper_grace_period: Some(Duration::from_secs(300)),
+            ..MaintenanceOptions::default()
+        };
+
+        // Early in the epoch, we leave the exchange rate update to keepers.
+        state.clock.unix_timestamp = 1_100;
+        assert_eq!(select_maintenance(&state, &options), None);
+
+        // If they did not update it by the end of the grace period, we do it ourselves.
+        state.clock.unix_timestamp = 1_300;
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output, MaintenanceOutput::UpdateExchangeRate);
+
+        // Without a grace period, we don't wait.
+        state.clock.unix_timestamp = 1_100;
+        let (_, output) = select_maintenance(&state, &MaintenanceOptions::default()).unwrap();
+        assert_eq!(output, MaintenanceOutput::UpdateExchangeRate);
+    }
+
+    fn get_status<'a>(explanation: &'a MaintenanceExplanation, task: &str) -> &'a TaskStatus {
+        &explanation
+            .tasks
+            .iter()
+            .find(|explained| explained.task == task)
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn explain_maintenance_covers_every_task() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.solido.exchange_rate.computed_in_epoch = 2;
+
+        let explanation = explain_maintenance(&state, &MaintenanceOptions::default());
+        assert_eq!(explanation.tasks.len(), MaintenanceTask::ALL.len());
+        assert_eq!(
+            explanation.get_selected(),
+            select_maintenance(&state, &MaintenanceOptions::default())
+                .map(|(_, output)| output)
+                .as_ref()
+        );
+        assert_eq!(
+            get_status(&explanation, "UpdateExchangeRate"),
+            &TaskStatus::Selected {
+                output: MaintenanceOutput::UpdateExchangeRate
+            }
+        );
+        assert_eq!(
+            get_status(&explanation, "AcquireMaintainerLease"),
+            &TaskStatus::Disabled
+        );
+        assert_eq!(
+            get_status(&explanation, "StakeDeposit"),
+            &TaskStatus::Idle {
+                reason: "no active validator can receive stake".to_string()
+            }
+        );
+
+        // Once the exchange rate is updated, there is nothing to do, and every
+        // enabled task says why.
+        state.solido.exchange_rate.computed_in_epoch = 3;
+        let explanation = explain_maintenance(&state, &MaintenanceOptions::default());
+        assert_eq!(explanation.get_selected(), None);
+        assert_eq!(
+            get_status(&explanation, "UpdateExchangeRate"),
+            &TaskStatus::Idle {
+                reason: "the exchange rate was already updated in epoch 3".to_string()
+            }
+        );
+        for explained in &explanation.tasks {
+            assert!(matches!(
+                explained.status,
+                TaskStatus::Idle { .. } | TaskStatus::Disabled
+            ));
+        }
+    }
+
+    #[test]
+    fn explain_maintenance_reports_deferrals() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.clock.slot = 1_000;
+        state.clock.epoch_start_timestamp = 1_000;
+        state.clock.unix_timestamp = 1_100;
+        state.solido.exchange_rate.computed_in_epoch = 2;
+
+        let options = MaintenanceOptions {
+            keeper_grace_period: Some(Duration::from_secs(300)),
+            ..MaintenanceOptions::default()
+        };
+        let explanation = explain_maintenance(&state, &options);
+        assert_eq!(explanation.get_selected(), None);
+        assert_eq!(
+            get_status(&explanation, "UpdateExchangeRate"),
+            &TaskStatus::Deferred {
+                output: MaintenanceOutput::UpdateExchangeRate,
+                deferral: Deferral::LeftToKeepers {
+                    seconds_into_epoch: 100
+                },
+            }
+        );
+
+        // When another maintainer holds the lease, every task waits for it.
+        let holder = Pubkey::new_unique();
+        state.maintainer_lease = Some(MaintainerLease {
+            holder,
+            expires_slot: 1_080,
+            ..MaintainerLease::default()
+        });
+        let options = MaintenanceOptions {
+            maintainer_lease_slots: Some(100),
+            ..MaintenanceOptions::default()
+        };
+        let explanation = explain_maintenance(&state, &options);
+        assert_eq!(explanation.get_selected(), None);
+        assert_eq!(select_maintenance(&state, &options), None);
+        assert_eq!(
+            get_status(&explanation, "AcquireMaintainerLease"),
+            &TaskStatus::Idle {
+                reason: format!("maintainer {} holds the lease until slot 1080", holder)
+            }
+        );
+        assert_eq!(
+            get_status(&explanation, "UpdateExchangeRate"),
+            &TaskStatus::Deferred {
+                output: MaintenanceOutput::UpdateExchangeRate,
+                deferral: Deferral::StandingBy {
+                    lease_holder: holder,
+                    lease_expires_slot: 1_080,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn explain_maintenance_replays_from_account_dump() {
+        let maintainer = Keypair::new();
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let explain = |rpc: MockRpc| {
+            let mut config = Config {
+                client: SnapshotClient::new(rpc),
+                signer: &maintainer,
+                fee_payer: &maintainer,
+                output_mode: OutputMode::Json,
+                output_transaction_path: None,
+                nonce: None,
+            };
+            config.client.set_record_accounts(true);
+            let explanation = config
+                .with_snapshot(|config| {
+                    let state = SolidoState::new(
+                        config,
+                        &fixture.solido_program_id,
+                        &fixture.solido_address,
+                    )?;
+                    Ok(explain_maintenance(&state, &MaintenanceOptions::default()))
+                })
+                .ok()
+                .unwrap();
+            (explanation, config.client.take_account_dump().unwrap())
+        };
+
+        // Record the accounts that maintenance reads, as --write-account-dump
+        // does, and replay them without the original accounts.
+        let (recorded, dump) = explain(fixture.rpc.clone());
+        let json = serde_json::to_string(&dump).unwrap();
+        let dump: AccountDump = serde_json::from_str(&json).unwrap();
+        let (replayed, replayed_dump) = explain(MockRpc::from_account_dump(&dump));
+
+        assert_eq!(replayed, recorded);
+        assert_eq!(
+            replayed.get_selected(),
+            Some(&MaintenanceOutput::UpdateExchangeRate)
+        );
+        // The dump holds exactly the accounts that maintenance needs.
+        assert_eq!(serde_json::to_string(&replayed_dump).unwrap(), json);
+    }
+
+    #[test]
+    fn exchange_rate_update_goes_first_at_the_start_of_the_epoch() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 3;
+        state.clock.slot = state.epoch_schedule.get_first_slot_in_epoch(3) + 10;
+        state.solido.exchange_rate.computed_in_epoch = 2;
+
+        // Two active stake accounts of a validator that can be merged.
+        state.solido.validators.maximum_entries = 1;
+        state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        let active_stake = |seed| {
+            (
+                Pubkey::new_unique(),
+                StakeAccount {
+                    balance: StakeBalance {
+                        inactive: Lamports(0),
+                        activating: Lamports(0),
+                        active: Lamports(5_000_000_000),
+                        deactivating: Lamports(0),
+                    },
+                    credits_observed: 0,
+                    activation_epoch: 0,
+                    seed,
+                },
+            )
+        };
+        state.validator_stake_accounts = vec![vec![active_stake(0), active_stake(1)]];
+
+        // Usually, merging goes first.
+        let (_, output) = select_maintenance(&state, &MaintenanceOptions::default()).unwrap();
+        assert_eq!(output.operation_name(), "MergeStake");
+
+        // But not in the first slots of the epoch.
+        let options = MaintenanceOptions {
+            epoch_boundary_slots: Some(150),
+            ..MaintenanceOptions::default()
+        };
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output, MaintenanceOutput::UpdateExchangeRate);
+
+        state.clock.slot += 150;
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(output.operation_name(), "MergeStake");
+        assert_eq!(
+            state.slots_until_next_epoch(),
+            state.epoch_schedule.slots_per_epoch - 160
+        );
+    }
+
+    #[test]
+    fn plan_stake_merges_stops_at_first_unmergeable_account() {
+        let stake = |seed, inactive, activating, active| {
+            (
+                Pubkey::new_unique(),
+                StakeAccount {
+                    balance: StakeBalance {
+                        inactive: Lamports(inactive),
+                        activating: Lamports(activating),
+                        active: Lamports(active),
+                        deactivating: Lamports(0),
+                    },
+                    credits_observed: 0,
+                    activation_epoch: 0,
+                    seed,
+                },
+            )
+        };
+
+        assert_eq!(SolidoState::plan_stake_merges(&[]), 0);
+        assert_eq!(SolidoState::plan_stake_merges(&[stake(0, 0, 0, 5)]), 0);
+
+        // The active accounts merge, the activating one has to wait.
+        let accounts = vec![
+            stake(0, 0, 0, 5),
+            stake(1, 0, 0, 5),
+            stake(2, 0, 0, 5),
+            stake(3, 0, 5, 0),
+            stake(4, 0, 0, 5),
+        ];
+        assert_eq!(SolidoState::plan_stake_merges(&accounts), 2);
+
+        // An inactive account merged into an activating one is activating.
+        let accounts = vec![stake(0, 5, 0, 0), stake(1, 0, 5, 0), stake(2, 0, 5, 0)];
+        assert_eq!(SolidoState::plan_stake_merges(&accounts), 2);
+
+        // We don't put more merges in one transaction than fit.
+        let accounts: Vec<_> = (0..8).map(|seed| stake(seed, 0, 0, 5)).collect();
+        assert_eq!(
+            SolidoState::plan_stake_merges(&accounts),
+            SolidoState::MAXIMUM_MERGES_PER_TRANSACTION
+        );
+    }
 
+    #[test]
+    fn try_merge_on_all_stakes_merges_a_chain_in_one_transaction() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 1;
         state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        let active_stake = |seed| {
+            (
+                Pubkey::new_unique(),
+                StakeAccount {
+                    balance: StakeBalance {
+                        inactive: Lamports(0),
+                        activating: Lamports(0),
+                        active: Lamports(5_000_000_000),
+                        deactivating: Lamports(0),
+                    },
+                    credits_observed: 0,
+                    activation_epoch: 0,
+                    seed,
+                },
+            )
+        };
+        let stake_accounts = vec![active_stake(3), active_stake(4), active_stake(5)];
+        state.validator_stake_accounts = vec![stake_accounts.clone()];
+
+        let (instructions, output) = state.try_merge_on_all_stakes().unwrap();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(
+            output,
+            MaintenanceOutput::MergeStake {
+                validator_vote_account: state.solido.validators.entries[0].pubkey,
+                from_stake: stake_accounts[0].0,
+                to_stake: stake_accounts[2].0,
+                from_stake_seed: 3,
+                to_stake_seed: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn clock_lag_is_zero_when_the_cluster_clock_is_ahead() {
+        let mut state = new_empty_solido();
+        state.clock.unix_timestamp = 1_000;
+        state.produced_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_030);
+        assert_eq!(state.get_clock_lag(), Duration::from_secs(30));
+
+        state.produced_at = SystemTime::UNIX_EPOCH + Duration::from_secs(990);
+        assert_eq!(state.get_clock_lag(), Duration::from_secs(0));
     }
 
     /// This is a regression test. In the past we checked for the minimum stake
@@ -1083,6 +4373,110 @@ mod test {
         assert!(state.try_stake_deposit().is_some());
     }
 
+    #[test]
+    fn stake_deposit_below_minimum_stake_delta_is_deferred() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 1;
+        state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        state.validator_stake_accounts.push(vec![]);
+        state.reserve_account.lamports += 2 * MINIMUM_STAKE_ACCOUNT_BALANCE.0;
+
+        let minimum = (MINIMUM_STAKE_ACCOUNT_BALANCE * 3).unwrap();
+        let options = MaintenanceOptions {
+            minimum_stake_delta: Some(minimum),
+            ..MaintenanceOptions::default()
+        };
+
+        // The deposit is less than the minimum, so we wait, but we do report it.
+        assert_eq!(select_maintenance(&state, &options), None);
+        let deferred = state.get_deferred_stake_movements(minimum);
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(
+            get_deferrable_stake_delta(&deferred[0]),
+            Some((MINIMUM_STAKE_ACCOUNT_BALANCE * 2).unwrap()),
+        );
+
+        // Once enough deposits add up, we stake them.
+        state.reserve_account.lamports += MINIMUM_STAKE_ACCOUNT_BALANCE.0;
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(get_deferrable_stake_delta(&output), Some(minimum));
+        assert!(state.get_deferred_stake_movements(minimum).is_empty());
+    }
+
+    #[test]
+    fn stake_deposit_on_stale_state_is_deferred() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 1;
+        state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        state.validator_stake_accounts.push(vec![]);
+        state.reserve_account.lamports += 2 * MINIMUM_STAKE_ACCOUNT_BALANCE.0;
+        let options = MaintenanceOptions {
+            max_state_lag_slots: Some(50),
+            ..MaintenanceOptions::default()
+        };
+
+        // We read the state 20 seconds after the clock sysvar, which is 50 slots.
+        state.produced_at = SystemTime::UNIX_EPOCH + Duration::from_secs(20);
+        assert_eq!(state.get_lag_slots(), 50);
+        let (_, output) = select_maintenance(&state, &options).unwrap();
+        assert!(moves_stake(&output));
+        assert!(state.get_stale_stake_movements(&options).is_empty());
+
+        // When it lags more, we don't move stake.
+        state.produced_at = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        assert_eq!(select_maintenance(&state, &options), None);
+        assert_eq!(
+            get_deferral(&state, &options, &output),
+            Some(Deferral::StaleState {
+                lag_slots: 150,
+                max_lag_slots: 50,
+            }),
+        );
+        assert_eq!(state.get_stale_stake_movements(&options), vec![output]);
+    }
+
+    #[test]
+    fn stake_deposit_avoids_superminority_among_equal_validators() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 2;
+        for _ in 0..2 {
+            state
+                .solido
+                .validators
+                .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+                .unwrap();
+        }
+        state.validator_stake_accounts = vec![vec![], vec![]];
+        state.reserve_account.lamports += 4 * MINIMUM_STAKE_ACCOUNT_BALANCE.0;
+        state.superminority = Some(vec![state.solido.validators.entries[0].pubkey]);
+
+        // Both validators have no stake, so the program accepts either. The
+        // first one is in the superminority, so the full deposit goes to the
+        // second one, rather than half of it to the first one.
+        let stake_account_1 = state.solido.validators.entries[1].find_stake_account_address(
+            &state.solido_program_id,
+            &state.solido_address,
+            0,
+            StakeType::Stake,
+        );
+        assert_eq!(
+            state.try_stake_deposit().unwrap().1,
+            MaintenanceOutput::StakeDeposit {
+                validator_vote_account: state.solido.validators.entries[1].pubkey,
+                amount: (MINIMUM_STAKE_ACCOUNT_BALANCE * 4).unwrap(),
+                stake_account: stake_account_1.0,
+            }
+        );
+    }
+
     #[test]
     fn stake_deposit_splits_evenly_if_possible() {
         use std::ops::Add;
@@ -1151,4 +4545,566 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn rebalance_unstakes_the_excess_above_target() {
+        let mut state = new_empty_solido();
+
+        // Add two validators, the first one has all of the stake.
+        state.solido.validators.maximum_entries = 2;
+        state
+            .solido
+            .validators
+            .add(
+                Pubkey::new_unique(),
+                Validator {
+                    stake_accounts_balance: Lamports(10_000_000_000),
+                    ..Validator::new(Pubkey::new_unique())
+                },
+            )
+            .unwrap();
+        state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        let stake_account = Pubkey::new_unique();
+        state.validator_stake_accounts = vec![
+            vec![(
+                stake_account,
+                StakeAccount {
+                    balance: StakeBalance {
+                        inactive: Lamports(0),
+                        activating: Lamports(0),
+                        active: Lamports(10_000_000_000),
+                        deactivating: Lamports(0),
+                    },
+                    credits_observed: 0,
+                    activation_epoch: 0,
+                    seed: 0,
+                },
+            )],
+            vec![],
+        ];
+
+        let (unstake_account, _) = state.solido.validators.entries[0].find_stake_account_address(
+            &state.solido_program_id,
+            &state.solido_address,
+            0,
+            StakeType::Unstake,
+        );
+
+        // The target of both validators is 5 SOL, so the first one should
+        // unstake its excess.
+        assert_eq!(
+            state.try_rebalance_unstake().unwrap().1,
+            MaintenanceOutput::RebalanceUnstake {
+                validator_vote_account: state.solido.validators.entries[0].pubkey,
+                from_stake_account: stake_account,
+                to_unstake_account: unstake_account,
+                from_stake_seed: 0,
+                to_unstake_seed: 0,
+                amount: Lamports(5_000_000_000),
+            }
+        );
+
+        // A validator with weight 0 targets no stake, but it keeps what it has.
+        state.solido.validators.entries[0].entry.weight = 0;
+        assert_eq!(state.try_rebalance_unstake(), None);
+        state.solido.validators.entries[0].entry.weight = DEFAULT_VALIDATOR_WEIGHT;
+
+        // SOL in the reserve that is owed to withdraw tickets does not raise
+        // the targets, so the excess stays the same.
+        state.reserve_account.lamports += 10_000_000_000;
+        state.solido.withdraw_queue.requested_total = Lamports(10_000_000_000);
+        assert_eq!(
+            state.try_rebalance_unstake().unwrap().1,
+            MaintenanceOutput::RebalanceUnstake {
+                validator_vote_account: state.solido.validators.entries[0].pubkey,
+                from_stake_account: stake_account,
+                to_unstake_account: unstake_account,
+                from_stake_seed: 0,
+                to_unstake_seed: 0,
+                amount: Lamports(5_000_000_000),
+            }
+        );
+
+        // When the second validator is in the superminority, it targets no
+        // additional stake, so the first one is not overweight.
+        state.superminority = Some(vec![state.solido.validators.entries[1].pubkey]);
+        assert_eq!(state.try_rebalance_unstake(), None);
+        state.superminority = None;
+
+        // Pretend that the excess was unstaked, then there is nothing to do.
+        state.solido.validators.entries[0]
+            .entry
+            .unstake_accounts_balance = Lamports(5_000_000_000);
+        state.solido.validators.entries[0].entry.unstake_seeds.end = 1;
+        assert_eq!(state.try_rebalance_unstake(), None);
+    }
+
+    #[test]
+    fn withdraw_tickets_are_funded_before_staking() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 1;
+        state
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        state.validator_stake_accounts.push(vec![]);
+        state.reserve_account.lamports += 5 * MINIMUM_STAKE_ACCOUNT_BALANCE.0;
+
+        // A ticket that is owed 2 of the 5 SOL in the reserve.
+        let ticket_address = Pubkey::new_unique();
+        let ticket = WithdrawTicket {
+            amount: (MINIMUM_STAKE_ACCOUNT_BALANCE * 2).unwrap(),
+            ..WithdrawTicket::default()
+        };
+        state.solido.withdraw_queue.next_ticket_id = 1;
+        state.solido.withdraw_queue.requested_total = ticket.amount;
+        state.withdraw_ticket_to_fund = Some((ticket_address, ticket.clone()));
+
+        assert_eq!(
+            state.try_fund_withdraw_ticket().unwrap().1,
+            MaintenanceOutput::FundWithdrawTicket {
+                withdraw_ticket: ticket_address,
+                ticket_id: 0,
+                amount: ticket.amount,
+            }
+        );
+
+        // Staking leaves the SOL for the ticket in the reserve.
+        match state.try_stake_deposit().unwrap().1 {
+            MaintenanceOutput::StakeDeposit { amount, .. } => {
+                assert_eq!(amount, (MINIMUM_STAKE_ACCOUNT_BALANCE * 3).unwrap())
+            }
+            output => panic!("Expected a StakeDeposit, got {:?}.", output),
+        }
+
+        // Nothing needs to be unstaked, the reserve covers the ticket.
+        assert_eq!(state.try_unstake_for_withdraw_tickets(), None);
+    }
+
+    #[test]
+    fn unstake_for_withdraw_tickets_covers_the_outstanding_amount() {
+        let mut state = new_empty_solido();
+        state.solido.validators.maximum_entries = 2;
+        for stake in [10_000_000_000, 20_000_000_000].iter() {
+            state
+                .solido
+                .validators
+                .add(
+                    Pubkey::new_unique(),
+                    Validator {
+                        stake_accounts_balance: Lamports(*stake),
+                        ..Validator::new(Pubkey::new_unique())
+                    },
+                )
+                .unwrap();
+            state.validator_stake_accounts.push(vec![(
+                Pubkey::new_unique(),
+                StakeAccount {
+                    balance: StakeBalance {
+                        inactive: Lamports(0),
+                        activating: Lamports(0),
+                        active: Lamports(*stake),
+                        deactivating: Lamports(0),
+                    },
+                    credits_observed: 0,
+                    activation_epoch: 0,
+                    seed: 0,
+                },
+            )]);
+        }
+
+        // The reserve holds 1 SOL, and a ticket is owed 26 SOL.
+        state.reserve_account.lamports += 1_000_000_000;
+        state.solido.withdraw_queue.next_ticket_id = 1;
+        state.solido.withdraw_queue.requested_total = Lamports(26_000_000_000);
+        state.withdraw_ticket_to_fund = Some((
+            Pubkey::new_unique(),
+            WithdrawTicket {
+                amount: Lamports(26_000_000_000),
+                ..WithdrawTicket::default()
+            },
+        ));
+        assert_eq!(state.try_fund_withdraw_ticket(), None);
+
+        // The validator with the most stake goes first, and it keeps the minimum.
+        let validator = &state.solido.validators.entries[1];
+        match state.try_unstake_for_withdraw_tickets().unwrap().1 {
+            MaintenanceOutput::UnstakeForWithdrawTickets {
+                validator_vote_account,
+                amount,
+                ..
+            } => {
+                assert_eq!(validator_vote_account, validator.pubkey);
+                assert_eq!(amount, Lamports(19_000_000_000));
+            }
+            output => panic!("Expected an unstake, got {:?}.", output),
+        }
+
+        // Pretend that it was unstaked, then the other validator covers the rest.
+        let validator = &mut state.solido.validators.entries[1].entry;
+        validator.unstake_accounts_balance = Lamports(19_000_000_000);
+        validator.unstake_seeds.end = 1;
+        let validator = &state.solido.validators.entries[0];
+        match state.try_unstake_for_withdraw_tickets().unwrap().1 {
+            MaintenanceOutput::UnstakeForWithdrawTickets {
+                validator_vote_account,
+                amount,
+                ..
+            } => {
+                assert_eq!(validator_vote_account, validator.pubkey);
+                assert_eq!(amount, Lamports(6_000_000_000));
+            }
+            output => panic!("Expected an unstake, got {:?}.", output),
+        }
+    }
+
+    #[test]
+    fn delinquent_validator_is_deactivated_when_maintainer_is_manager() {
+        let mut state = new_empty_solido();
+        state.clock.epoch = 10;
+        state.solido.exchange_rate.computed_in_epoch = 10;
+        state.solido.validators.maximum_entries = 2;
+        let voting = Pubkey::new_unique();
+        let delinquent = Pubkey::new_unique();
+        for vote_account in [voting, delinquent].iter() {
+            state
+                .solido
+                .validators
+                .add(*vote_account, Validator::new(Pubkey::new_unique()))
+                .unwrap();
+            state.validator_stake_accounts.push(vec![]);
+            state.validator_unstake_accounts.push(vec![]);
+        }
+        state.validator_vote_accounts = vec![
+            Some(VoteAccountState {
+                history: VoteHistory {
+                    last_vote_slot: None,
+                    epoch_credits: vec![(9, 100, 50), (10, 120, 100)],
+                },
+                ..VoteAccountState::default()
+            }),
+            Some(VoteAccountState {
+                history: VoteHistory {
+                    last_vote_slot: None,
+                    epoch_credits: vec![(6, 100, 50)],
+                },
+                ..VoteAccountState::default()
+            }),
+        ];
+        let options = MaintenanceOptions {
+            deactivate_delinquent_epochs: Some(3),
+            ..MaintenanceOptions::default()
+        };
+
+        // Only the manager can deactivate validators.
+        assert_eq!(state.try_deactivate_delinquent_validator(3), None);
+
+        state.solido.manager = state.maintainer_address;
+        let (instructions, output) = select_maintenance(&state, &options).unwrap();
+        assert_eq!(
+            output,
+            MaintenanceOutput::DeactivateValidator {
+                validator_vote_account: delinquent,
+                delinquent_epochs: 3,
+            }
+        );
+        assert_eq!(instructions[0].accounts[1].pubkey, state.maintainer_address);
+        assert!(instructions[0].accounts[1].is_signer);
+
+        // A validator that is delinquent for fewer epochs stays active.
+        assert_eq!(state.try_deactivate_delinquent_validator(4), None);
+
+        // And we deactivate a validator only once.
+        state.solido.validators.entries[1].entry.active = false;
+        assert_eq!(state.try_deactivate_delinquent_validator(3), None);
+    }
+
+    /// Produce a state with fixed values, to render it in golden-file tests.
+    fn new_golden_solido() -> SolidoState {
+        use std::time::Duration;
+
+        let mut state = new_empty_solido();
+        state.produced_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        state.solido_program_id = Pubkey::new(&[1; 32]);
+        state.solido_address = Pubkey::new(&[2; 32]);
+        state.clock.slot = 123_456;
+        state.maintainer_address = Pubkey::new(&[6; 32]);
+        state.maintainer_account.lamports = 5_000_000_000;
+        state.fee_payer_address = Pubkey::new(&[7; 32]);
+        state.fee_payer_balance = Lamports(800_000_000);
+        state.reserve_account.lamports += 3_000_000_000;
+        state.st_sol_mint.supply = 9_500_000_000;
+
+        state.solido.exchange_rate.computed_in_epoch = 7;
+        state.solido.withdraw_queue.next_ticket_id = 3;
+        state.solido.withdraw_queue.next_fund_ticket_id = 1;
+        state.solido.withdraw_queue.requested_total = Lamports(4_000_000_000);
+        state.solido.withdraw_queue.funded_total = Lamports(1_500_000_000);
+        state.solido.exchange_rate.st_sol_supply = StLamports(9_000_000_000);
+        state.solido.exchange_rate.sol_balance = Lamports(9_900_000_000);
+        state.solido.max_total_lamports = Lamports(10_000_000_000);
+
+        let metrics = &mut state.solido.metrics;
+        metrics.fee_treasury_sol_total = Lamports(110_000_000);
+        metrics.fee_treasury_st_sol_total = StLamports(100_000_000);
+        metrics.fee_validation_sol_total = Lamports(55_000_000);
+        metrics.fee_validation_st_sol_total = StLamports(50_000_000);
+        metrics.fee_developer_sol_total = Lamports(22_000_000);
+        metrics.fee_developer_st_sol_total = StLamports(20_000_000);
+        metrics.st_sol_appreciation_sol_total = Lamports(880_000_000);
+        metrics.observe_deposit(Lamports(500_000_000)).unwrap();
+        metrics.observe_deposit(Lamports(2_000_000_000)).unwrap();
+
+        state.solido.validators.maximum_entries = 1;
+        state
+            .solido
+            .validators
+            .add(
+                Pubkey::new(&[3; 32]),
+                Validator {
+                    fee_credit: StLamports(1_500_000_000),
+                    ..Validator::new(Pubkey::new(&[4; 32]))
+                },
+            )
+            .unwrap();
+        state.validator_stake_accounts = vec![vec![(
+            Pubkey::new(&[5; 32]),
+            StakeAccount {
+                balance: StakeBalance {
+                    inactive: Lamports(2_282_880),
+                    activating: Lamports(0),
+                    active: Lamports(10_000_000_000),
+                    deactivating: Lamports(0),
+                },
+                credits_observed: 0,
+                activation_epoch: 0,
+                seed: 0,
+            },
+        )]];
+        state.validator_unstake_accounts = vec![vec![]];
+        state.validator_vote_account_balances = vec![Lamports(0)];
+        state.validator_mev = vec![ValidatorMev::default()];
+        state.validator_identities = vec![ValidatorIdentity {
+            node_pubkey: Some(Pubkey::new(&[8; 32])),
+            commission: Some(100),
+            name: Some("Golden Validator".to_string()),
+        }];
+        state.validator_vote_accounts = vec![Some(VoteAccountState {
+            node_pubkey: Pubkey::new(&[8; 32]),
+            authorized_withdrawer: Pubkey::new(&[9; 32]),
+            commission: 100,
+            history: VoteHistory {
+                last_vote_slot: Some(123_400),
+                epoch_credits: vec![(0, 1_000, 0)],
+            },
+        })];
+
+        state
+    }
+
+    #[test]
+    fn golden_prometheus_solido_state() {
+        let state = new_golden_solido();
+        let mut out: Vec<u8> = Vec::new();
+        state.write_prometheus(&mut out).unwrap();
+        crate::golden::assert_golden("solido_state.prom", std::str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn mev_metrics_are_reported_only_for_jito_validators() {
+        use crate::mev::MevTips;
+
+        let mut state = new_golden_solido();
+        let mut out: Vec<u8> = Vec::new();
+        state.write_prometheus(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(!text.contains("solido_mev_tips_sol"));
+
+        state.validator_mev[0].previous_epoch = Some(MevTips {
+            epoch: 6,
+            earned: Lamports(2_000_000_000),
+            claimed: Lamports(500_000_000),
+            validator_commission_bps: 800,
+        });
+        let mut out: Vec<u8> = Vec::new();
+        state.write_prometheus(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("solido_validator_mev_tips_sol{"));
+        assert!(text.contains("solido_mev_tips_sol{status=\"earned\"} 2"));
+    }
+
+    #[test]
+    fn write_prometheus_exports_pending_reward_distribution() {
+        let mut state = new_empty_solido();
+        let mut out = Vec::new();
+        state.write_prometheus(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(!text.contains("solido_pending_reward_distribution"));
+
+        state.solido.pending_reward_distribution = PendingRewardDistribution {
+            reward_distribution: RewardDistribution {
+                treasury_fee: 1,
+                validation_fee: 2,
+                developer_fee: 1,
+                st_sol_appreciation: 16,
+            },
+            activation_epoch: 42,
+        };
+        let mut out = Vec::new();
+        state.write_prometheus(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("solido_pending_reward_distribution_activation_epoch 42"));
+        assert!(
+            text.contains("solido_pending_reward_distribution_fraction{party=\"validation\"} 0.1")
+        );
+        assert!(text.contains(
+            "solido_pending_reward_distribution_fraction{party=\"st_sol_appreciation\"} 0.8"
+        ));
+    }
+
+    #[test]
+    fn golden_json_maintenance_output() {
+        let vote_account = Pubkey::new(&[3; 32]);
+        let stake_account = Pubkey::new(&[5; 32]);
+        let other_stake_account = Pubkey::new(&[7; 32]);
+        let outputs = vec![
+            MaintenanceOutput::StakeDeposit {
+                validator_vote_account: vote_account,
+                stake_account,
+                amount: Lamports(2_000_000_000),
+            },
+            MaintenanceOutput::UpdateExchangeRate,
+            MaintenanceOutput::WithdrawInactiveStake {
+                validator_vote_account: vote_account,
+                expected_difference_stake: Lamports(500_000_000),
+                unstake_withdrawn_to_reserve: Lamports(1_000_000_000),
+            },
+            MaintenanceOutput::CollectValidatorFee {
+                validator_vote_account: vote_account,
+                fee_rewards: Lamports(30_000_000),
+            },
+            MaintenanceOutput::ClaimValidatorFee {
+                validator_vote_account: vote_account,
+                fee_rewards: StLamports(25_000_000),
+            },
+            MaintenanceOutput::MergeStake {
+                validator_vote_account: vote_account,
+                from_stake: stake_account,
+                to_stake: other_stake_account,
+                from_stake_seed: 0,
+                to_stake_seed: 1,
+            },
+            MaintenanceOutput::UnstakeFromInactiveValidator {
+                validator_vote_account: vote_account,
+                from_stake_account: stake_account,
+                to_unstake_account: other_stake_account,
+                from_stake_seed: 0,
+                to_unstake_seed: 2,
+                amount: Lamports(10_002_282_880),
+            },
+            MaintenanceOutput::RebalanceUnstake {
+                validator_vote_account: vote_account,
+                from_stake_account: stake_account,
+                to_unstake_account: other_stake_account,
+                from_stake_seed: 1,
+                to_unstake_seed: 3,
+                amount: Lamports(4_000_000_000),
+            },
+            MaintenanceOutput::RemoveValidator {
+                validator_vote_account: vote_account,
+            },
+            MaintenanceOutput::UnstakeForWithdrawTickets {
+                validator_vote_account: vote_account,
+                from_stake_account: stake_account,
+                to_unstake_account: other_stake_account,
+                from_stake_seed: 1,
+                to_unstake_seed: 4,
+                amount: Lamports(6_000_000_000),
+            },
+            MaintenanceOutput::FundWithdrawTicket {
+                withdraw_ticket: Pubkey::new(&[11; 32]),
+                ticket_id: 2,
+                amount: Lamports(6_000_000_000),
+            },
+            MaintenanceOutput::PublishExchangeRate {
+                exchange_rate_oracle: Pubkey::new(&[9; 32]),
+                computed_in_epoch: 7,
+            },
+            MaintenanceOutput::AcquireMaintainerLease {
+                maintainer_lease: Pubkey::new(&[10; 32]),
+                expires_slot: 123_456,
+            },
+            MaintenanceOutput::DeactivateValidator {
+                validator_vote_account: vote_account,
+                delinquent_epochs: 5,
+            },
+            MaintenanceOutput::SweepToReserve {
+                account: Pubkey::new(&[12; 32]),
+                amount: Lamports(1_500_000_000),
+            },
+        ];
+        let json = serde_json::to_string_pretty(&outputs).unwrap();
+        crate::golden::assert_golden("maintenance_output.json", &format!("{}\n", json));
+    }
+
+    #[test]
+    fn test_contains_instruction_ignores_signers() {
+        use solana_program::instruction::AccountMeta;
+
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let new_instruction = |maintainer: Pubkey, data: Vec<u8>| Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(solido_address, false),
+                AccountMeta::new_readonly(maintainer, true),
+            ],
+            data,
+        };
+        let other_maintainer = Pubkey::new_unique();
+        let transaction = Transaction::new_with_payer(
+            &[new_instruction(other_maintainer, vec![1, 2])],
+            Some(&other_maintainer),
+        );
+
+        let ours = new_instruction(Pubkey::new_unique(), vec![1, 2]);
+        assert!(contains_instruction(&transaction, &ours));
+
+        let different_data = new_instruction(Pubkey::new_unique(), vec![1, 3]);
+        assert!(!contains_instruction(&transaction, &different_data));
+    }
+
+    #[test]
+    fn test_split_developer_fee_gives_rounding_leftover_to_last() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        let transfers = split_developer_fee(StLamports(1_000), &[(a, 33), (b, 33), (c, 34)]);
+        assert_eq!(
+            transfers,
+            vec![
+                DeveloperFeeTransfer {
+                    destination: a,
+                    amount: StLamports(330),
+                },
+                DeveloperFeeTransfer {
+                    destination: b,
+                    amount: StLamports(330),
+                },
+                DeveloperFeeTransfer {
+                    destination: c,
+                    amount: StLamports(340),
+                },
+            ]
+        );
+
+        let transfers = split_developer_fee(StLamports(7), &[(a, 50), (b, 50)]);
+        assert_eq!(transfers[0].amount, StLamports(3));
+        assert_eq!(transfers[1].amount, StLamports(4));
+    }
 }
@@ -0,0 +1,256 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Machine-readable descriptions of the command line, for deployment tooling.
+//!
+//! `solido manifest` describes every subcommand and option as json, including
+//! the config file key, environment variable, and default of the options, so
+//! tooling can generate config templates without parsing `--help`. `solido
+//! completions` generates shell completions from the same clap definitions.
+
+use std::fmt::Debug;
+use std::io::Write;
+use std::str::FromStr;
+
+use clap::{App, Arg, ArgSettings};
+use clap_generate::generators::{Bash, Fish, Zsh};
+use serde::Serialize;
+
+/// An option that can also be set in the config file, see `cli_opt_struct!`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConfigOption {
+    /// Name of the field, which is also the config file key.
+    pub key: &'static str,
+
+    /// The Rust type of the option, as written in the struct.
+    pub type_name: &'static str,
+
+    /// The default value, when the option is optional.
+    pub default: Option<String>,
+}
+
+/// Format the default value of an option, for the manifest.
+///
+/// This uses `Debug`, because not all option types implement `Display`, but
+/// for strings and paths we omit the quotes.
+pub fn format_default<T: Debug>(value: &T) -> String {
+    let formatted = format!("{:?}", value);
+    match serde_json::from_str::<String>(&formatted) {
+        Ok(unquoted) if formatted.starts_with('"') => unquoted,
+        _ => formatted,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OptionManifest {
+    /// Name of the option, which is the config file key for config options.
+    pub name: String,
+
+    /// The flag on the command line, e.g. `--cluster`, or `None` for a positional argument.
+    pub flag: Option<String>,
+
+    pub about: Option<String>,
+
+    /// Whether the option takes a value, as opposed to being a switch.
+    pub takes_value: bool,
+
+    pub possible_values: Vec<String>,
+
+    /// The key in the config file, if the option can be set there.
+    pub config_key: Option<String>,
+
+    /// The environment variable that can set the option, if any.
+    pub env_var: Option<String>,
+
+    pub type_name: Option<String>,
+
+    /// Whether the option must be set, because it has no default.
+    pub required: bool,
+
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandManifest {
+    pub name: String,
+    pub about: Option<String>,
+    pub options: Vec<OptionManifest>,
+    pub subcommands: Vec<CommandManifest>,
+}
+
+fn get_option_manifest(arg: &Arg, config_options: &[ConfigOption]) -> OptionManifest {
+    let name = arg.get_name().to_string();
+    let config_option = config_options.iter().find(|option| option.key == name);
+    OptionManifest {
+        flag: arg.get_long().map(|long| format!("--{}", long)),
+        about: arg.get_about().map(|about| about.to_string()),
+        takes_value: arg.is_set(ArgSettings::TakesValue),
+        possible_values: arg
+            .get_possible_values()
+            .unwrap_or(&[])
+            .iter()
+            .map(|value| value.to_string())
+            .collect(),
+        config_key: config_option.map(|option| option.key.to_string()),
+        env_var: config_option.map(|option| format!("SOLIDO_{}", option.key.to_ascii_uppercase())),
+        type_name: config_option.map(|option| option.type_name.to_string()),
+        required: config_option.map_or(false, |option| option.default.is_none()),
+        default: config_option.and_then(|option| option.default.clone()),
+        name,
+    }
+}
+
+/// Describe the command `app` and its subcommands.
+///
+/// `get_config_options` returns the config options of the command at the
+/// given path of subcommand names, the empty path being the general options.
+pub fn get_command_manifest(
+    app: &App,
+    get_config_options: &dyn Fn(&[&str]) -> Vec<ConfigOption>,
+) -> CommandManifest {
+    get_command_manifest_at(app, &[], get_config_options)
+}
+
+fn get_command_manifest_at(
+    app: &App,
+    path: &[&str],
+    get_config_options: &dyn Fn(&[&str]) -> Vec<ConfigOption>,
+) -> CommandManifest {
+    let config_options = get_config_options(path);
+    CommandManifest {
+        name: app.get_name().to_string(),
+        about: app.get_about().map(|about| about.to_string()),
+        options: app
+            .get_arguments()
+            // Clap adds these itself, they are not options of ours.
+            .filter(|arg| arg.get_name() != "help" && arg.get_name() != "version")
+            .map(|arg| get_option_manifest(arg, &config_options))
+            .collect(),
+        subcommands: app
+            .get_subcommands()
+            .filter(|subcommand| subcommand.get_name() != "help")
+            .map(|subcommand| {
+                let mut subcommand_path = path.to_vec();
+                subcommand_path.push(subcommand.get_name());
+                get_command_manifest_at(subcommand, &subcommand_path, get_config_options)
+            })
+            .collect(),
+    }
+}
+
+/// Return the config options of the command `app` and all of its subcommands.
+///
+/// An option that several commands share occurs once for every command.
+pub fn get_all_config_options(
+    app: &App,
+    get_config_options: &dyn Fn(&[&str]) -> Vec<ConfigOption>,
+) -> Vec<ConfigOption> {
+    let mut options = Vec::new();
+    collect_config_options(app, &[], get_config_options, &mut options);
+    options
+}
+
+fn collect_config_options(
+    app: &App,
+    path: &[&str],
+    get_config_options: &dyn Fn(&[&str]) -> Vec<ConfigOption>,
+    options: &mut Vec<ConfigOption>,
+) {
+    options.extend(get_config_options(path));
+    for subcommand in app.get_subcommands() {
+        let mut subcommand_path = path.to_vec();
+        subcommand_path.push(subcommand.get_name());
+        collect_config_options(subcommand, &subcommand_path, get_config_options, options);
+    }
+}
+
+/// A shell to generate completions for, see `solido completions`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Shell, &'static str> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err("Invalid shell, expected 'bash', 'zsh', or 'fish'."),
+        }
+    }
+}
+
+/// Write completions of the command `app`, installed as `bin_name`, for `shell`.
+pub fn write_completions(app: &mut App, bin_name: &str, shell: Shell, out: &mut dyn Write) {
+    match shell {
+        Shell::Bash => clap_generate::generate::<Bash, _>(app, bin_name, out),
+        Shell::Zsh => clap_generate::generate::<Zsh, _>(app, bin_name, out),
+        Shell::Fish => clap_generate::generate::<Fish, _>(app, bin_name, out),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use clap::IntoApp;
+
+    use super::*;
+    use crate::config::{CreateSolidoOpts, GeneralOpts};
+
+    #[test]
+    fn test_manifest_includes_config_keys_and_defaults() {
+        let app = GeneralOpts::into_app();
+        let manifest = get_command_manifest(&app, &|path| {
+            assert!(path.is_empty());
+            GeneralOpts::config_options()
+        });
+        let output = manifest
+            .options
+            .iter()
+            .find(|option| option.name == "output_mode")
+            .unwrap();
+        assert_eq!(output.flag.as_deref(), Some("--output"));
+        assert_eq!(output.config_key.as_deref(), Some("output_mode"));
+        assert_eq!(output.env_var.as_deref(), Some("SOLIDO_OUTPUT_MODE"));
+        assert_eq!(output.type_name.as_deref(), Some("OutputMode"));
+        assert_eq!(output.possible_values, vec!["text", "json"]);
+        assert_eq!(output.default.as_deref(), Some("Text"));
+        assert!(output.takes_value);
+        assert!(!output.required);
+
+        let cluster = manifest
+            .options
+            .iter()
+            .find(|option| option.name == "cluster")
+            .unwrap();
+        assert_eq!(cluster.default.as_deref(), Some("http://127.0.0.1:8899"));
+    }
+
+    #[test]
+    fn test_manifest_marks_options_without_default_as_required() {
+        let app = CreateSolidoOpts::into_app();
+        let manifest = get_command_manifest(&app, &|_path| CreateSolidoOpts::config_options());
+        let max_validators = manifest
+            .options
+            .iter()
+            .find(|option| option.name == "max_validators")
+            .unwrap();
+        assert!(max_validators.required);
+        assert_eq!(max_validators.default, None);
+        assert_eq!(max_validators.type_name.as_deref(), Some("u32"));
+    }
+
+    #[test]
+    fn test_format_default_omits_quotes_of_strings() {
+        assert_eq!(format_default(&"".to_string()), "");
+        assert_eq!(format_default(&PathBuf::from("a \"b\"")), "a \"b\"");
+        assert_eq!(format_default(&150_u64), "150");
+        assert_eq!(format_default(&false), "false");
+    }
+}
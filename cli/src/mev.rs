@@ -0,0 +1,314 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Reading MEV tips from Jito's tip distribution program.
+//!
+//! Validators that run the Jito client collect MEV tips into a tip distribution
+//! account, one per validator per epoch. After the epoch ends, a merkle root is
+//! uploaded, and stakers (including Solido's stake accounts) can claim their
+//! share. These tips are separate from inflation rewards, so we report them
+//! separately, to track how much MEV contributes to stSOL yield.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use solana_program::clock::Epoch;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+
+use lido::token::Lamports;
+
+use crate::derivation_cache;
+use crate::logging::Event;
+use crate::snapshot::{Result, Snapshot};
+
+/// The tip distribution program on mainnet-beta.
+pub mod tip_distribution_program {
+    solana_program::declare_id!("4R3gSG8BpU4t19KYj8CfnbtRpnT8gtk4dvTHxVRwc2r7");
+}
+
+/// Seed of the tip distribution account, together with the vote account and epoch.
+const TIP_DISTRIBUTION_SEED: &[u8] = b"TIP_DISTRIBUTION_ACCOUNT";
+
+/// Mirror of `MerkleRoot` in the tip distribution program.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct MerkleRoot {
+    _root: [u8; 32],
+    max_total_claim: u64,
+    _max_num_nodes: u64,
+    total_funds_claimed: u64,
+    _num_nodes_claimed: u64,
+}
+
+/// Mirror of `TipDistributionAccount` in the tip distribution program.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct TipDistributionAccount {
+    _validator_vote_account: Pubkey,
+    _merkle_root_upload_authority: Pubkey,
+    merkle_root: Option<MerkleRoot>,
+    _epoch_created_at: u64,
+    validator_commission_bps: u16,
+    _expires_at: u64,
+    _bump: u8,
+}
+
+/// The Anchor discriminator of the account, the first 8 bytes of its data.
+fn tip_distribution_discriminator() -> [u8; 8] {
+    let hash = Sha256::digest(b"account:TipDistributionAccount");
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+pub fn find_tip_distribution_account(vote_account: &Pubkey, epoch: Epoch) -> Pubkey {
+    derivation_cache::find_program_address(
+        &[
+            TIP_DISTRIBUTION_SEED,
+            &vote_account.to_bytes(),
+            &epoch.to_le_bytes(),
+        ],
+        &tip_distribution_program::id(),
+    )
+    .0
+}
+
+/// MEV tips that a validator earned in one epoch.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct MevTips {
+    /// The epoch in which the tips were earned.
+    pub epoch: Epoch,
+
+    /// Tips earned. While the epoch is ongoing, this is the amount collected so far.
+    #[serde(rename = "earned_lamports")]
+    pub earned: Lamports,
+
+    /// Tips that stakers and the validator claimed so far.
+    #[serde(rename = "claimed_lamports")]
+    pub claimed: Lamports,
+
+    /// The commission that the validator takes from the tips, in basis points.
+    pub validator_commission_bps: u16,
+}
+
+/// MEV tips of a validator in the current and the previous epoch.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct ValidatorMev {
+    /// Tips of the previous epoch, which can be claimed now.
+    pub previous_epoch: Option<MevTips>,
+
+    /// Tips collected so far in the current epoch.
+    pub current_epoch: Option<MevTips>,
+}
+
+/// Read the tips of a validator for an epoch, if it has a tip distribution account.
+///
+/// Returns `None` when the account does not exist, or holds data that we cannot parse.
+pub fn get_mev_tips(
+    snapshot: &mut Snapshot,
+    rent: &Rent,
+    vote_account: &Pubkey,
+    epoch: Epoch,
+) -> Result<Option<MevTips>> {
+    let address = find_tip_distribution_account(vote_account, epoch);
+    if !snapshot.account_exists(&address)? {
+        return Ok(None);
+    }
+    let account = snapshot.get_account(&address)?;
+    if account.owner != tip_distribution_program::id() {
+        return Ok(None);
+    }
+
+    // The tips are only reported, so a tip distribution account that we cannot
+    // parse, for example after a change to its layout, should not stop maintenance.
+    let data = &account.data[..];
+    if data.len() < 8 || data[..8] != tip_distribution_discriminator() {
+        Event::warning(
+            "tip_distribution_account_invalid",
+            format!(
+                "Account {} is not a tip distribution account, ignoring its MEV tips.",
+                address
+            ),
+        )
+        .field("vote_account", vote_account.to_string())
+        .field("epoch", epoch)
+        .emit();
+        return Ok(None);
+    }
+    let tip_distribution = match TipDistributionAccount::deserialize(&mut &data[8..]) {
+        Ok(tip_distribution) => tip_distribution,
+        Err(err) => {
+            Event::warning(
+                "tip_distribution_account_invalid",
+                format!(
+                    "Failed to deserialize tip distribution account {}, ignoring its MEV tips.",
+                    address
+                ),
+            )
+            .field("vote_account", vote_account.to_string())
+            .field("epoch", epoch)
+            .field("data_len", data.len())
+            .field("error", err.to_string())
+            .emit();
+            return Ok(None);
+        }
+    };
+
+    let (earned, claimed) = match tip_distribution.merkle_root {
+        // Once the merkle root is uploaded, it records the total amount to distribute.
+        Some(root) => (root.max_total_claim, root.total_funds_claimed),
+        // Before that, the account balance above rent is what was collected so far.
+        None => (
+            account
+                .lamports
+                .saturating_sub(rent.minimum_balance(data.len())),
+            0,
+        ),
+    };
+
+    Ok(Some(MevTips {
+        epoch,
+        earned: Lamports(earned),
+        claimed: Lamports(claimed),
+        validator_commission_bps: tip_distribution.validator_commission_bps,
+    }))
+}
+
+/// Read the tips of a validator for the current and the previous epoch.
+pub fn get_validator_mev(
+    snapshot: &mut Snapshot,
+    rent: &Rent,
+    vote_account: &Pubkey,
+    current_epoch: Epoch,
+) -> Result<ValidatorMev> {
+    let previous_epoch = match current_epoch.checked_sub(1) {
+        Some(epoch) => get_mev_tips(snapshot, rent, vote_account, epoch)?,
+        None => None,
+    };
+    Ok(ValidatorMev {
+        previous_epoch,
+        current_epoch: get_mev_tips(snapshot, rent, vote_account, current_epoch)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::account::Account;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+    use crate::snapshot::SnapshotClient;
+
+    #[test]
+    fn test_tip_distribution_discriminator() {
+        // Computed with `sha256("account:TipDistributionAccount")[..8]`.
+        assert_eq!(
+            tip_distribution_discriminator(),
+            [85, 64, 113, 198, 234, 94, 120, 123]
+        );
+    }
+
+    fn new_tip_distribution_account(
+        rent: &Rent,
+        merkle_root: Option<MerkleRoot>,
+        tips: u64,
+    ) -> Account {
+        let tip_distribution = TipDistributionAccount {
+            _validator_vote_account: Pubkey::new_unique(),
+            _merkle_root_upload_authority: Pubkey::new_unique(),
+            merkle_root,
+            _epoch_created_at: 0,
+            validator_commission_bps: 800,
+            _expires_at: 10,
+            _bump: 255,
+        };
+        let mut data = tip_distribution_discriminator().to_vec();
+        tip_distribution.serialize(&mut data).unwrap();
+        Account {
+            lamports: rent.minimum_balance(data.len()) + tips,
+            data,
+            owner: tip_distribution_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_get_validator_mev() {
+        let rent = Rent::default();
+        let vote_account = Pubkey::new_unique();
+        let rpc = MockRpc::new();
+
+        // In the previous epoch, the merkle root was uploaded, and some tips claimed.
+        let root = MerkleRoot {
+            _root: [0; 32],
+            max_total_claim: 5_000,
+            _max_num_nodes: 3,
+            total_funds_claimed: 2_000,
+            _num_nodes_claimed: 1,
+        };
+        rpc.set_account(
+            find_tip_distribution_account(&vote_account, 6),
+            new_tip_distribution_account(&rent, Some(root), 3_000),
+        );
+        // In the current epoch, tips are still being collected.
+        rpc.set_account(
+            find_tip_distribution_account(&vote_account, 7),
+            new_tip_distribution_account(&rent, None, 1_234),
+        );
+
+        let mut client = SnapshotClient::new(rpc);
+        let mev = client
+            .with_snapshot(|mut snapshot| get_validator_mev(&mut snapshot, &rent, &vote_account, 7))
+            .unwrap();
+        assert_eq!(
+            mev.previous_epoch,
+            Some(MevTips {
+                epoch: 6,
+                earned: Lamports(5_000),
+                claimed: Lamports(2_000),
+                validator_commission_bps: 800,
+            })
+        );
+        assert_eq!(
+            mev.current_epoch,
+            Some(MevTips {
+                epoch: 7,
+                earned: Lamports(1_234),
+                claimed: Lamports(0),
+                validator_commission_bps: 800,
+            })
+        );
+
+        // A validator that does not run Jito has no tips.
+        let other = Pubkey::new_unique();
+        let mev = client
+            .with_snapshot(|mut snapshot| get_validator_mev(&mut snapshot, &rent, &other, 7))
+            .unwrap();
+        assert_eq!(mev, ValidatorMev::default());
+    }
+
+    #[test]
+    fn test_get_mev_tips_ignores_corrupt_tip_distribution_account() {
+        let rent = Rent::default();
+        let vote_account = Pubkey::new_unique();
+        let rpc = MockRpc::new();
+
+        // An account with a different discriminator.
+        let mut wrong_discriminator = new_tip_distribution_account(&rent, None, 1_000);
+        wrong_discriminator.data[0] ^= 0xff;
+        rpc.set_account(
+            find_tip_distribution_account(&vote_account, 6),
+            wrong_discriminator,
+        );
+        // An account with the right discriminator, but truncated data.
+        let mut truncated = new_tip_distribution_account(&rent, None, 1_000);
+        truncated.data.truncate(20);
+        rpc.set_account(find_tip_distribution_account(&vote_account, 7), truncated);
+
+        let mut client = SnapshotClient::new(rpc);
+        let mev = client
+            .with_snapshot(|mut snapshot| get_validator_mev(&mut snapshot, &rent, &vote_account, 7))
+            .unwrap();
+        assert_eq!(mev, ValidatorMev::default());
+    }
+}
@@ -0,0 +1,592 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A scripted [`Rpc`] implementation for tests.
+//!
+//! The mock serves accounts from an in-memory map, and it can be scripted to
+//! inject the kind of failures that we see from real RPC nodes: rate limits,
+//! a low `--rpc-max-multiple-accounts` limit, writes that land between two
+//! `GetMultipleAccounts` calls (torn reads), and transactions that get dropped.
+//! It can also serve the accounts of an account dump, see [`MockRpc::from_account_dump`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use borsh::BorshSerialize;
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_request::RpcError;
+use solana_client::rpc_response::{
+    Response, RpcResponseContext, RpcResult, RpcSimulateTransactionResult,
+};
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Clock, Epoch, Slot};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_sdk::fee_calculator::FeeCalculator;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::Signature;
+use solana_sdk::sysvar::recent_blockhashes::{IterItem, RecentBlockhashes};
+use solana_sdk::sysvar::{self, stake_history::StakeHistory};
+use solana_sdk::transaction::{self, Transaction, TransactionError};
+
+use lido::state::{Lido, Maintainers, Validators, LIDO_VERSION};
+use lido::RESERVE_ACCOUNT;
+
+use crate::account_dump::AccountDump;
+use crate::rpc::{
+    AccountFilter, BlockProduction, InflationReward, Rpc, SignatureInfo, TransactionInfo,
+};
+
+/// A failure to inject into a `GetMultipleAccounts` call.
+pub enum ReadFault {
+    /// The call fails with an HTTP 429, as public RPC nodes do when rate limiting.
+    RateLimited,
+
+    /// The call succeeds, but afterwards the account at `address` is replaced,
+    /// and the slot advances.
+    ///
+    /// If the snapshot client needs more than one call to read all accounts,
+    /// the calls after this one observe the new value, at the new slot.
+    TornRead { address: Pubkey, account: Account },
+}
+
+/// A failure to inject when broadcasting a transaction.
+pub enum SendFault {
+    /// This broadcast is lost, but broadcasting the transaction again works.
+    Dropped,
+
+    /// This broadcast is lost, and the blockhash of the transaction expires,
+    /// so it can no longer land.
+    Expired,
+
+    /// The transaction lands, but the first status check does not see it yet,
+    /// and by then the blockhash of the transaction has expired.
+    LandedLate,
+}
+
+#[derive(Default)]
+struct MockState {
+    accounts: HashMap<Pubkey, Account>,
+
+    /// The slot that `GetMultipleAccounts` reports in its response context.
+    slot: Slot,
+
+    read_faults: VecDeque<ReadFault>,
+    send_faults: VecDeque<SendFault>,
+
+    /// Blockhashes of transactions that can no longer land, see [`SendFault`].
+    expired_blockhashes: HashSet<Hash>,
+
+    /// Transactions whose status the next status check does not report yet.
+    late_signatures: HashSet<Signature>,
+
+    /// Errors that the next simulations report, simulations succeed after that.
+    simulation_faults: VecDeque<TransactionError>,
+
+    /// Like `--rpc-max-multiple-accounts` on a real node.
+    max_accounts_per_call: Option<usize>,
+
+    /// What `getRecentPrioritizationFees` returns, regardless of the accounts.
+    recent_prioritization_fees: Vec<u64>,
+
+    /// What `getInflationReward` returns, per account and epoch.
+    inflation_rewards: HashMap<(Pubkey, Epoch), InflationReward>,
+
+    /// What `getVoteAccounts` returns, the activated stake per vote account.
+    vote_account_stakes: Vec<(Pubkey, u64)>,
+
+    /// What `getBlockProduction` returns, per validator identity.
+    block_production: Vec<(Pubkey, BlockProduction)>,
+
+    /// What `getSignaturesForAddress` returns, per address, newest first.
+    signatures: HashMap<Pubkey, Vec<SignatureInfo>>,
+
+    /// What `getTransaction` returns, per signature.
+    transactions: HashMap<String, TransactionInfo>,
+
+    get_multiple_accounts_calls: usize,
+    simulated_transactions: Vec<Transaction>,
+    sent_transactions: Vec<Transaction>,
+}
+
+/// Mock RPC that serves accounts from memory.
+///
+/// The mock is a cheap handle to shared state, so a test can keep a clone to
+/// inspect or modify the state after handing the mock to a `SnapshotClient`.
+#[derive(Clone, Default)]
+pub struct MockRpc {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockRpc {
+    pub fn new() -> MockRpc {
+        MockRpc::default()
+    }
+
+    /// Create a mock that serves the accounts of an account dump, at the slot of the dump.
+    pub fn from_account_dump(dump: &AccountDump) -> MockRpc {
+        let accounts = dump
+            .decode()
+            .unwrap_or_else(|_| panic!("Account dump holds an account that we cannot decode."));
+        let rpc = MockRpc::new();
+        {
+            let mut state = rpc.state.lock().unwrap();
+            state.slot = dump.slot;
+            state.accounts = accounts;
+        }
+        rpc
+    }
+
+    pub fn set_account(&self, address: Pubkey, account: Account) {
+        self.state.lock().unwrap().accounts.insert(address, account);
+    }
+
+    pub fn remove_account(&self, address: &Pubkey) {
+        self.state.lock().unwrap().accounts.remove(address);
+    }
+
+    pub fn set_max_accounts_per_call(&self, max: usize) {
+        self.state.lock().unwrap().max_accounts_per_call = Some(max);
+    }
+
+    pub fn set_recent_prioritization_fees(&self, fees: Vec<u64>) {
+        self.state.lock().unwrap().recent_prioritization_fees = fees;
+    }
+
+    pub fn set_inflation_reward(&self, address: Pubkey, reward: InflationReward) {
+        self.state
+            .lock()
+            .unwrap()
+            .inflation_rewards
+            .insert((address, reward.epoch), reward);
+    }
+
+    pub fn set_vote_account_stakes(&self, stakes: Vec<(Pubkey, u64)>) {
+        self.state.lock().unwrap().vote_account_stakes = stakes;
+    }
+
+    pub fn set_block_production(&self, production: Vec<(Pubkey, BlockProduction)>) {
+        self.state.lock().unwrap().block_production = production;
+    }
+
+    pub fn set_signatures(&self, address: Pubkey, signatures: Vec<SignatureInfo>) {
+        self.state
+            .lock()
+            .unwrap()
+            .signatures
+            .insert(address, signatures);
+    }
+
+    /// Make `getTransaction` return `transaction` for its first signature.
+    pub fn set_transaction(&self, transaction: TransactionInfo) {
+        let signature = transaction.transaction.signatures[0].to_string();
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .insert(signature, transaction);
+    }
+
+    /// Inject a fault into the next `GetMultipleAccounts` call that has no fault yet.
+    pub fn push_read_fault(&self, fault: ReadFault) {
+        self.state.lock().unwrap().read_faults.push_back(fault);
+    }
+
+    /// Inject a fault into the next transaction that has no fault yet.
+    pub fn push_send_fault(&self, fault: SendFault) {
+        self.state.lock().unwrap().send_faults.push_back(fault);
+    }
+
+    /// Make the next simulation that has no fault yet fail with `error`.
+    pub fn push_simulation_fault(&self, error: TransactionError) {
+        self.state
+            .lock()
+            .unwrap()
+            .simulation_faults
+            .push_back(error);
+    }
+
+    pub fn get_multiple_accounts_calls(&self) -> usize {
+        self.state.lock().unwrap().get_multiple_accounts_calls
+    }
+
+    /// Return the transactions that were simulated.
+    pub fn simulated_transactions(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().simulated_transactions.clone()
+    }
+
+    /// Return the transactions that were sent and not dropped.
+    pub fn sent_transactions(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().sent_transactions.clone()
+    }
+}
+
+impl Rpc for MockRpc {
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        _commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>> {
+        let mut state = self.state.lock().unwrap();
+        state.get_multiple_accounts_calls += 1;
+
+        if let Some(max) = state.max_accounts_per_call {
+            if pubkeys.len() > max {
+                // This is the message that a real node returns, the snapshot
+                // client matches on it.
+                let message = format!(
+                    "Failed to deserialize RPC error response: {{\"code\":-32602,\
+                    \"message\":\"Too many inputs provided; max {}\"}} [missing field `data`]",
+                    max
+                );
+                return Err(ClientErrorKind::RpcError(RpcError::RpcRequestError(message)).into());
+            }
+        }
+
+        let fault = state.read_faults.pop_front();
+        if let Some(ReadFault::RateLimited) = fault {
+            return Err(ClientError::from(ClientErrorKind::Custom(
+                "HTTP status client error (429 Too Many Requests)".to_string(),
+            )));
+        }
+
+        let result = Response {
+            context: RpcResponseContext { slot: state.slot },
+            value: pubkeys
+                .iter()
+                .map(|k| state.accounts.get(k).cloned())
+                .collect(),
+        };
+
+        if let Some(ReadFault::TornRead { address, account }) = fault {
+            state.accounts.insert(address, account);
+            state.slot += 1;
+        }
+
+        Ok(result)
+    }
+
+    fn simulate_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        // The mock does not execute transactions, so every simulation
+        // succeeds, unless we injected a fault.
+        let mut state = self.state.lock().unwrap();
+        state.simulated_transactions.push(transaction.clone());
+        let err = state.simulation_faults.pop_front();
+        let logs = match err {
+            None => Vec::new(),
+            Some(ref err) => vec![format!("Program failed to complete: {}", err)],
+        };
+        Ok(Response {
+            context: RpcResponseContext { slot: state.slot },
+            value: RpcSimulateTransactionResult {
+                err,
+                logs: Some(logs),
+            },
+        })
+    }
+
+    fn send_transaction(
+        &self,
+        transaction: &Transaction,
+        _commitment: CommitmentConfig,
+        _skip_preflight: bool,
+    ) -> ClientResult<Signature> {
+        let mut state = self.state.lock().unwrap();
+        let signature = transaction.signatures[0];
+        let blockhash = transaction.message.recent_blockhash;
+        // A transaction lands only once, no matter how often we broadcast it.
+        let landed = state
+            .sent_transactions
+            .iter()
+            .any(|tx| tx.signatures[0] == signature);
+        match state.send_faults.pop_front() {
+            Some(SendFault::Dropped) => {}
+            Some(SendFault::Expired) => {
+                state.expired_blockhashes.insert(blockhash);
+            }
+            Some(SendFault::LandedLate) => {
+                state.expired_blockhashes.insert(blockhash);
+                state.late_signatures.insert(signature);
+                state.sent_transactions.push(transaction.clone());
+            }
+            None if landed => {}
+            None => state.sent_transactions.push(transaction.clone()),
+        }
+        Ok(signature)
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        let mut state = self.state.lock().unwrap();
+        if state.late_signatures.remove(signature) {
+            return Ok(None);
+        }
+        let landed = state
+            .sent_transactions
+            .iter()
+            .any(|tx| tx.signatures[0] == *signature);
+        Ok(if landed { Some(Ok(())) } else { None })
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> ClientResult<bool> {
+        Ok(!self
+            .state
+            .lock()
+            .unwrap()
+            .expired_blockhashes
+            .contains(blockhash))
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(Hash::default())
+    }
+
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Signature> {
+        let signature = self.send_transaction(transaction, commitment, false)?;
+        match self.get_signature_status(&signature, commitment)? {
+            Some(result) => result.map(|()| signature).map_err(|err| err.into()),
+            None => Err(ClientErrorKind::RpcError(RpcError::ForUser(
+                "unable to confirm transaction. This can happen in situations such as \
+                transaction expiration and insufficient fee-payer funds"
+                    .to_string(),
+            ))
+            .into()),
+        }
+    }
+
+    fn get_recent_prioritization_fees(&self, _pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .recent_prioritization_fees
+            .clone())
+    }
+
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .accounts
+            .iter()
+            .filter(|(_, account)| &account.owner == program_id)
+            .filter(|(_, account)| filters.iter().all(|filter| filter.matches(account)))
+            .map(|(address, account)| (*address, account.clone()))
+            .collect())
+    }
+
+    fn get_inflation_reward(
+        &self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>> {
+        let state = self.state.lock().unwrap();
+        Ok(pubkeys
+            .iter()
+            .map(|address| state.inflation_rewards.get(&(*address, epoch)).cloned())
+            .collect())
+    }
+
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>> {
+        Ok(self.state.lock().unwrap().vote_account_stakes.clone())
+    }
+
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>> {
+        Ok(self.state.lock().unwrap().block_production.clone())
+    }
+
+    fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+        limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>> {
+        let state = self.state.lock().unwrap();
+        let signatures = match state.signatures.get(address) {
+            Some(signatures) => signatures,
+            None => return Ok(Vec::new()),
+        };
+        let start = before
+            .and_then(|before| signatures.iter().position(|info| info.signature == before))
+            .map_or(0, |i| i + 1);
+        Ok(signatures[start..]
+            .iter()
+            .take_while(|info| Some(&info.signature[..]) != until)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn get_transaction(&self, signature: &str) -> ClientResult<Option<TransactionInfo>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .transactions
+            .get(signature)
+            .cloned())
+    }
+}
+
+/// Addresses of the accounts created by [`new_solido_fixture`].
+pub struct SolidoFixture {
+    pub rpc: MockRpc,
+    pub solido_program_id: Pubkey,
+    pub solido_address: Pubkey,
+    pub reserve_address: Pubkey,
+    pub solido: Lido,
+}
+
+fn new_sysvar_account<T: serde::Serialize>(value: &T) -> Account {
+    Account {
+        lamports: 1,
+        data: bincode::serialize(value).expect("Sysvars can be serialized."),
+        owner: sysvar::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+impl SolidoFixture {
+    /// Store `self.solido` in the mock, after a test modified it.
+    pub fn store_solido(&self) {
+        self.rpc.set_account(
+            self.solido_address,
+            Account {
+                lamports: 1_000_000_000,
+                data: self.solido.try_to_vec().unwrap(),
+                owner: self.solido_program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+
+    pub fn set_clock(&self, clock: &Clock) {
+        self.rpc
+            .set_account(sysvar::clock::id(), new_sysvar_account(clock));
+    }
+}
+
+/// Create a mock that holds a Solido instance without validators, in epoch 1.
+///
+/// The exchange rate has not been updated yet in that epoch, so the only
+/// maintenance to perform is `UpdateExchangeRate`.
+pub fn new_solido_fixture(maintainer: &Pubkey) -> SolidoFixture {
+    let rpc = MockRpc::new();
+    let solido_program_id = Pubkey::new_unique();
+    let solido_address = Pubkey::new_unique();
+    let rent = Rent::default();
+
+    let (reserve_address, reserve_bump_seed) = Pubkey::find_program_address(
+        &[&solido_address.to_bytes(), RESERVE_ACCOUNT],
+        &solido_program_id,
+    );
+    let st_sol_mint_address = Pubkey::new_unique();
+
+    let mut maintainers = Maintainers::new(1);
+    maintainers.add(*maintainer, ()).unwrap();
+    let solido = Lido {
+        lido_version: LIDO_VERSION,
+        st_sol_mint: st_sol_mint_address,
+        st_sol_token_program: spl_token::id(),
+        sol_reserve_account_bump_seed: reserve_bump_seed,
+        validators: Validators::new(1),
+        maintainers,
+        ..Lido::default()
+    };
+
+    let mint = spl_token::state::Mint {
+        mint_authority: COption::Some(Pubkey::new_unique()),
+        supply: 0,
+        decimals: 9,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    let mut mint_data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+    rpc.set_account(
+        st_sol_mint_address,
+        Account {
+            lamports: rent.minimum_balance(mint_data.len()),
+            data: mint_data,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+
+    rpc.set_account(
+        reserve_address,
+        Account {
+            lamports: rent.minimum_balance(0),
+            data: Vec::new(),
+            owner: solana_sdk::system_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    rpc.set_account(
+        *maintainer,
+        Account {
+            lamports: 10_000_000_000,
+            data: Vec::new(),
+            owner: solana_sdk::system_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+
+    let blockhash = Hash::new_unique();
+    let fee_calculator = FeeCalculator::default();
+    let recent_blockhashes: RecentBlockhashes = vec![IterItem(0, &blockhash, &fee_calculator)]
+        .into_iter()
+        .collect();
+
+    rpc.set_account(sysvar::rent::id(), new_sysvar_account(&rent));
+    // Without warmup, like on mainnet, so epoch `n` starts at slot `n * DEFAULT_SLOTS_PER_EPOCH`.
+    rpc.set_account(
+        sysvar::epoch_schedule::id(),
+        new_sysvar_account(&EpochSchedule::without_warmup()),
+    );
+    rpc.set_account(
+        sysvar::stake_history::id(),
+        new_sysvar_account(&StakeHistory::default()),
+    );
+    rpc.set_account(
+        sysvar::recent_blockhashes::id(),
+        new_sysvar_account(&recent_blockhashes),
+    );
+
+    let fixture = SolidoFixture {
+        rpc,
+        solido_program_id,
+        solido_address,
+        reserve_address,
+        solido,
+    };
+    fixture.set_clock(&Clock {
+        epoch: 1,
+        ..Clock::default()
+    });
+    fixture.store_solido();
+    fixture
+}
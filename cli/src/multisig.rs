@@ -18,13 +18,19 @@ use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{Keypair, Signature, Signer};
 use solana_sdk::system_instruction;
 use solana_sdk::sysvar;
+use solana_sdk::transaction::Transaction;
 
 use lido::{
+    idl::generate_idl,
     instruction::{
-        AddMaintainerMeta, AddValidatorMeta, ChangeRewardDistributionMeta, DeactivateValidatorMeta,
-        LidoInstruction, RemoveMaintainerMeta,
+        AddAllowedDepositorMeta, AddMaintainerMeta, AddValidatorMeta, ChangeFeeRecipientsMeta,
+        DeactivateValidatorMeta, LidoInstruction, MigrateStateToV1Meta, RemoveAllowedDepositorMeta,
+        RemoveMaintainerMeta, ReplaceValidatorVoteAccountMeta, ScheduleRewardDistributionMeta,
+        SetDepositorAllowlistEnabledMeta, SetMaxStakePerValidatorMeta, SetMaxTotalLamportsMeta,
+        SetPausedMeta, SetValidatorWeightMeta,
     },
     state::{FeeRecipients, Lido, RewardDistribution},
+    token::Lamports,
     util::{serialize_b58, serialize_b58_slice},
 };
 
@@ -33,7 +39,8 @@ use crate::config::{
     ProposeChangeMultisigOpts, ProposeUpgradeOpts, ShowMultisigOpts, ShowTransactionOpts,
     TransferTokenOpts,
 };
-use crate::error::{Abort, AsPrettyError};
+use crate::error::{Abort, AsPrettyError, CliError};
+use crate::manifest::ConfigOption;
 use crate::print_output;
 use crate::snapshot::{Result, SnapshotError};
 use crate::{SnapshotClientConfig, SnapshotConfig};
@@ -74,6 +81,22 @@ impl MultisigOpts {
             },
         }
     }
+
+    /// Return the config options of the subcommand at `path`, see `crate::manifest`.
+    pub fn config_options(path: &[&str]) -> Vec<ConfigOption> {
+        match path {
+            ["create-multisig"] => CreateMultisigOpts::config_options(),
+            ["show-multisig"] => ShowMultisigOpts::config_options(),
+            ["show-transaction"] => ShowTransactionOpts::config_options(),
+            ["propose-upgrade"] => ProposeUpgradeOpts::config_options(),
+            ["propose-change-multisig"] => ProposeChangeMultisigOpts::config_options(),
+            ["approve"] => ApproveOpts::config_options(),
+            ["execute-transaction"] => ExecuteTransactionOpts::config_options(),
+            ["approve-batch"] => ApproveBatchOpts::config_options(),
+            ["token", "transfer"] => TransferTokenOpts::config_options(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[derive(Clap, Debug)]
@@ -404,6 +427,66 @@ enum SolidoInstruction {
         #[serde(serialize_with = "serialize_b58")]
         validator_vote_account: Pubkey,
     },
+    ReplaceValidatorVoteAccount {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        old_validator_vote_account: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        new_validator_vote_account: Pubkey,
+    },
+    SetValidatorWeight {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        validator_vote_account: Pubkey,
+
+        weight: u32,
+    },
+    SetMaxStakePerValidator {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        #[serde(rename = "max_stake_per_validator_lamports")]
+        max_stake_per_validator: Lamports,
+    },
+    SetMaxTotalLamports {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        max_total_lamports: Lamports,
+    },
+    SetPaused {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        paused: bool,
+    },
+    MigrateStateToV1 {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+    },
     AddMaintainer {
         #[serde(serialize_with = "serialize_b58")]
         solido_instance: Pubkey,
@@ -424,9 +507,8 @@ enum SolidoInstruction {
         #[serde(serialize_with = "serialize_b58")]
         maintainer: Pubkey,
     },
-    ChangeRewardDistribution {
+    ChangeFeeRecipients {
         current_solido: Box<Lido>,
-        reward_distribution: RewardDistribution,
 
         #[serde(serialize_with = "serialize_b58")]
         solido_instance: Pubkey,
@@ -436,6 +518,72 @@ enum SolidoInstruction {
 
         fee_recipients: FeeRecipients,
     },
+    ScheduleRewardDistribution {
+        current_solido: Box<Lido>,
+        reward_distribution: RewardDistribution,
+        delay_epochs: u64,
+
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+    },
+    AddAllowedDepositor {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        depositor: Pubkey,
+    },
+    RemoveAllowedDepositor {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        depositor: Pubkey,
+    },
+    SetDepositorAllowlistEnabled {
+        #[serde(serialize_with = "serialize_b58")]
+        solido_instance: Pubkey,
+
+        #[serde(serialize_with = "serialize_b58")]
+        manager: Pubkey,
+
+        enabled: bool,
+    },
+    /// A valid instruction that has no summary, only its decoded form.
+    Other,
+}
+
+/// A Solido instruction, decoded with the IDL of the program.
+#[derive(Serialize)]
+struct DecodedInstruction {
+    /// Name of the instruction, as in the IDL.
+    name: String,
+
+    /// The arguments of the instruction, in Rust syntax, empty if there are none.
+    arguments: String,
+
+    accounts: Vec<DecodedAccount>,
+}
+
+/// An account of a decoded instruction, with its role in the instruction.
+#[derive(Serialize)]
+struct DecodedAccount {
+    role: &'static str,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pubkey: Pubkey,
+
+    is_signer: bool,
+    is_writable: bool,
 }
 
 #[derive(Serialize)]
@@ -465,6 +613,8 @@ struct ShowTransactionOutput {
     // `Instruction` duplicate just for this purpose right now, we can create
     // one when needed.
     instruction: Instruction,
+    /// The instruction with its accounts labeled, if it is a Solido instruction.
+    decoded_instruction: Option<DecodedInstruction>,
     parsed_instruction: ParsedInstruction,
 }
 
@@ -504,13 +654,32 @@ impl fmt::Display for ShowTransactionOutput {
 
         writeln!(f, "\nInstruction:")?;
         writeln!(f, "  Program to call: {}", self.instruction.program_id)?;
-        writeln!(f, "  Accounts:\n")?;
-        for account in &self.instruction.accounts {
-            writeln!(
-                f,
-                "    * {}\n      signer: {}, writable: {}\n",
-                account.pubkey, account.is_signer, account.is_writable,
-            )?;
+        match &self.decoded_instruction {
+            Some(decoded) => {
+                writeln!(
+                    f,
+                    "  Instruction:     {} {}",
+                    decoded.name, decoded.arguments
+                )?;
+                writeln!(f, "  Accounts:\n")?;
+                for account in &decoded.accounts {
+                    writeln!(
+                        f,
+                        "    * {}: {}\n      signer: {}, writable: {}\n",
+                        account.role, account.pubkey, account.is_signer, account.is_writable,
+                    )?;
+                }
+            }
+            None => {
+                writeln!(f, "  Accounts:\n")?;
+                for account in &self.instruction.accounts {
+                    writeln!(
+                        f,
+                        "    * {}\n      signer: {}, writable: {}\n",
+                        account.pubkey, account.is_signer, account.is_writable,
+                    )?;
+                }
+            }
         }
 
         match &self.parsed_instruction {
@@ -574,6 +743,90 @@ impl fmt::Display for ShowTransactionOutput {
                         writeln!(f, "    Manager:                {}", manager)?;
                         writeln!(f, "    Validator vote account: {}", validator_vote_account)?;
                     }
+                    SolidoInstruction::ReplaceValidatorVoteAccount {
+                        solido_instance,
+                        manager,
+                        old_validator_vote_account,
+                        new_validator_vote_account,
+                    } => {
+                        writeln!(
+                            f,
+                            "It moves a validator and its stake to a new vote account."
+                        )?;
+                        writeln!(f, "    Solido instance:        {}", solido_instance)?;
+                        writeln!(f, "    Manager:                {}", manager)?;
+                        writeln!(
+                            f,
+                            "    Old vote account:       {}",
+                            old_validator_vote_account
+                        )?;
+                        writeln!(
+                            f,
+                            "    New vote account:       {}",
+                            new_validator_vote_account
+                        )?;
+                    }
+                    SolidoInstruction::SetValidatorWeight {
+                        solido_instance,
+                        manager,
+                        validator_vote_account,
+                        weight,
+                    } => {
+                        writeln!(f, "It changes the weight of a validator.")?;
+                        writeln!(f, "    Solido instance:        {}", solido_instance)?;
+                        writeln!(f, "    Manager:                {}", manager)?;
+                        writeln!(f, "    Validator vote account: {}", validator_vote_account)?;
+                        writeln!(f, "    New weight:             {}", weight)?;
+                    }
+                    SolidoInstruction::SetMaxStakePerValidator {
+                        solido_instance,
+                        manager,
+                        max_stake_per_validator,
+                    } => {
+                        writeln!(f, "It changes the maximum stake per validator.")?;
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                        if *max_stake_per_validator == Lamports(0) {
+                            writeln!(f, "    New maximum:     none")?;
+                        } else {
+                            writeln!(f, "    New maximum:     {}", max_stake_per_validator)?;
+                        }
+                    }
+                    SolidoInstruction::SetMaxTotalLamports {
+                        solido_instance,
+                        manager,
+                        max_total_lamports,
+                    } => {
+                        writeln!(f, "It changes the maximum amount of SOL managed by Solido.")?;
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                        if *max_total_lamports == Lamports(0) {
+                            writeln!(f, "    New maximum:     none")?;
+                        } else {
+                            writeln!(f, "    New maximum:     {}", max_total_lamports)?;
+                        }
+                    }
+                    SolidoInstruction::SetPaused {
+                        solido_instance,
+                        manager,
+                        paused,
+                    } => {
+                        if *paused {
+                            writeln!(f, "It pauses deposits and withdrawals.")?;
+                        } else {
+                            writeln!(f, "It resumes deposits and withdrawals.")?;
+                        }
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                    }
+                    SolidoInstruction::MigrateStateToV1 {
+                        solido_instance,
+                        manager,
+                    } => {
+                        writeln!(f, "It migrates the Solido state to version 1.")?;
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                    }
                     SolidoInstruction::AddMaintainer {
                         solido_instance,
                         manager,
@@ -594,20 +847,75 @@ impl fmt::Display for ShowTransactionOutput {
                         writeln!(f, "    Manager:         {}", manager)?;
                         writeln!(f, "    Maintainer:      {}", maintainer)?;
                     }
-                    SolidoInstruction::ChangeRewardDistribution {
+                    SolidoInstruction::ChangeFeeRecipients {
                         current_solido,
-                        reward_distribution,
                         solido_instance,
                         manager,
                         fee_recipients,
                     } => {
-                        writeln!(f, "It changes the reward distribution")?;
+                        writeln!(f, "It changes the fee recipients")?;
                         writeln!(f, "    Solido instance:       {}", solido_instance)?;
                         writeln!(f, "    Manager:               {}", manager)?;
                         writeln!(f)?;
-                        print_changed_reward_distribution(f, current_solido, reward_distribution)?;
                         print_changed_recipients(f, current_solido, fee_recipients)?;
                     }
+                    SolidoInstruction::ScheduleRewardDistribution {
+                        current_solido,
+                        reward_distribution,
+                        delay_epochs,
+                        solido_instance,
+                        manager,
+                    } => {
+                        writeln!(f, "It schedules a change of the reward distribution")?;
+                        writeln!(f, "    Solido instance:       {}", solido_instance)?;
+                        writeln!(f, "    Manager:               {}", manager)?;
+                        writeln!(
+                            f,
+                            "    Takes effect:          {} epochs after execution",
+                            delay_epochs
+                        )?;
+                        writeln!(f)?;
+                        print_changed_reward_distribution(f, current_solido, reward_distribution)?;
+                    }
+                    SolidoInstruction::AddAllowedDepositor {
+                        solido_instance,
+                        manager,
+                        depositor,
+                    } => {
+                        writeln!(f, "It adds a depositor to the depositor allowlist")?;
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                        writeln!(f, "    Depositor:       {}", depositor)?;
+                    }
+                    SolidoInstruction::RemoveAllowedDepositor {
+                        solido_instance,
+                        manager,
+                        depositor,
+                    } => {
+                        writeln!(f, "It removes a depositor from the depositor allowlist")?;
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                        writeln!(f, "    Depositor:       {}", depositor)?;
+                    }
+                    SolidoInstruction::SetDepositorAllowlistEnabled {
+                        solido_instance,
+                        manager,
+                        enabled,
+                    } => {
+                        if *enabled {
+                            writeln!(
+                                f,
+                                "It only accepts deposits from depositors on the allowlist."
+                            )?;
+                        } else {
+                            writeln!(f, "It accepts deposits from anybody again.")?;
+                        }
+                        writeln!(f, "    Solido instance: {}", solido_instance)?;
+                        writeln!(f, "    Manager:         {}", manager)?;
+                    }
+                    SolidoInstruction::Other => {
+                        writeln!(f, "See the decoded instruction above.")?;
+                    }
                 }
             }
             ParsedInstruction::Unrecognized => {
@@ -822,8 +1130,11 @@ fn show_transaction(
             Ok(instr) => instr,
             Err(SnapshotError::MissingAccount) => return Err(SnapshotError::MissingAccount),
             Err(SnapshotError::OtherError(err)) => {
-                println!("Warning: Failed to parse Solido instruction.");
-                err.print_pretty();
+                // Print to stderr, so we don't break json output.
+                eprintln!(
+                    "Warning: Failed to parse Solido instruction: {}",
+                    err.summary()
+                );
                 ParsedInstruction::InvalidSolidoInstruction
             }
         }
@@ -832,8 +1143,11 @@ fn show_transaction(
             Ok(instr) => instr,
             Err(SnapshotError::MissingAccount) => return Err(SnapshotError::MissingAccount),
             Err(SnapshotError::OtherError(err)) => {
-                println!("Warning: Failed to parse Token instruction.");
-                err.print_pretty();
+                // Print to stderr, so we don't break json output.
+                eprintln!(
+                    "Warning: Failed to parse Token instruction: {}",
+                    err.summary()
+                );
                 ParsedInstruction::InvalidSolidoInstruction
             }
         }
@@ -841,11 +1155,18 @@ fn show_transaction(
         ParsedInstruction::Unrecognized
     };
 
+    let decoded_instr = if &instr.program_id == solido_program_id {
+        decode_solido_instruction(&instr)
+    } else {
+        None
+    };
+
     let result = ShowTransactionOutput {
         multisig_address: transaction.multisig,
         did_execute: transaction.did_execute,
         signers,
         instruction: instr,
+        decoded_instruction: decoded_instr,
         parsed_instruction: parsed_instr,
     };
     Ok(result)
@@ -857,14 +1178,11 @@ fn try_parse_solido_instruction(
 ) -> Result<ParsedInstruction> {
     let instruction: LidoInstruction = BorshDeserialize::deserialize(&mut instr.data.as_slice())?;
     Ok(match instruction {
-        LidoInstruction::ChangeRewardDistribution {
-            new_reward_distribution,
-        } => {
-            let accounts = ChangeRewardDistributionMeta::try_from_slice(&instr.accounts)?;
+        LidoInstruction::ChangeFeeRecipients => {
+            let accounts = ChangeFeeRecipientsMeta::try_from_slice(&instr.accounts)?;
             let current_solido = config.client.get_solido(&accounts.lido)?;
-            ParsedInstruction::SolidoInstruction(SolidoInstruction::ChangeRewardDistribution {
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::ChangeFeeRecipients {
                 current_solido: Box::new(current_solido),
-                reward_distribution: new_reward_distribution,
                 solido_instance: accounts.lido,
                 manager: accounts.manager,
                 fee_recipients: FeeRecipients {
@@ -890,6 +1208,57 @@ fn try_parse_solido_instruction(
                 validator_vote_account: accounts.validator_vote_account_to_deactivate,
             })
         }
+        LidoInstruction::ReplaceValidatorVoteAccount => {
+            let accounts = ReplaceValidatorVoteAccountMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::ReplaceValidatorVoteAccount {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                old_validator_vote_account: accounts.old_validator_vote_account,
+                new_validator_vote_account: accounts.new_validator_vote_account,
+            })
+        }
+        LidoInstruction::SetValidatorWeight { weight } => {
+            let accounts = SetValidatorWeightMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::SetValidatorWeight {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                validator_vote_account: accounts.validator_vote_account,
+                weight,
+            })
+        }
+        LidoInstruction::SetMaxStakePerValidator {
+            max_stake_per_validator,
+        } => {
+            let accounts = SetMaxStakePerValidatorMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::SetMaxStakePerValidator {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                max_stake_per_validator,
+            })
+        }
+        LidoInstruction::SetMaxTotalLamports { max_total_lamports } => {
+            let accounts = SetMaxTotalLamportsMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::SetMaxTotalLamports {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                max_total_lamports,
+            })
+        }
+        LidoInstruction::SetPaused { paused } => {
+            let accounts = SetPausedMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::SetPaused {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                paused,
+            })
+        }
+        LidoInstruction::MigrateStateToV1 => {
+            let accounts = MigrateStateToV1Meta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::MigrateStateToV1 {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+            })
+        }
         LidoInstruction::AddMaintainer => {
             let accounts = AddMaintainerMeta::try_from_slice(&instr.accounts)?;
             ParsedInstruction::SolidoInstruction(SolidoInstruction::AddMaintainer {
@@ -906,7 +1275,94 @@ fn try_parse_solido_instruction(
                 maintainer: accounts.maintainer,
             })
         }
-        _ => ParsedInstruction::InvalidSolidoInstruction,
+        LidoInstruction::ScheduleRewardDistribution {
+            new_reward_distribution,
+            delay_epochs,
+        } => {
+            let accounts = ScheduleRewardDistributionMeta::try_from_slice(&instr.accounts)?;
+            let current_solido = config.client.get_solido(&accounts.lido)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::ScheduleRewardDistribution {
+                current_solido: Box::new(current_solido),
+                reward_distribution: new_reward_distribution,
+                delay_epochs,
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+            })
+        }
+        LidoInstruction::AddAllowedDepositor => {
+            let accounts = AddAllowedDepositorMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::AddAllowedDepositor {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                depositor: accounts.depositor,
+            })
+        }
+        LidoInstruction::RemoveAllowedDepositor => {
+            let accounts = RemoveAllowedDepositorMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::RemoveAllowedDepositor {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                depositor: accounts.depositor,
+            })
+        }
+        LidoInstruction::SetDepositorAllowlistEnabled { enabled } => {
+            let accounts = SetDepositorAllowlistEnabledMeta::try_from_slice(&instr.accounts)?;
+            ParsedInstruction::SolidoInstruction(SolidoInstruction::SetDepositorAllowlistEnabled {
+                solido_instance: accounts.lido,
+                manager: accounts.manager,
+                enabled,
+            })
+        }
+        _ => ParsedInstruction::SolidoInstruction(SolidoInstruction::Other),
+    })
+}
+
+/// Decode a Solido instruction with the IDL of the program, so owners can
+/// review what they approve without reading raw instruction data.
+///
+/// Returns `None` if the data is not a valid `LidoInstruction`.
+fn decode_solido_instruction(instr: &Instruction) -> Option<DecodedInstruction> {
+    let instruction: LidoInstruction =
+        BorshDeserialize::deserialize(&mut instr.data.as_slice()).ok()?;
+    let idl = generate_idl();
+    let idl_instruction = idl
+        .instructions
+        .iter()
+        .find(|idl_instruction| idl_instruction.discriminator[..] == instr.data[..1])?;
+
+    // The `Debug` format is `Name { argument: value, .. }`, or only `Name`
+    // for instructions without arguments.
+    let debug = format!("{:?}", instruction);
+    let arguments = match debug.find('{') {
+        Some(start) => debug[start..].to_string(),
+        None => String::new(),
+    };
+
+    let accounts = instr
+        .accounts
+        .iter()
+        .enumerate()
+        .map(|(i, meta)| {
+            // A variadic account at the end stands for all remaining accounts.
+            let idl_account = idl_instruction.accounts.get(i).or_else(|| {
+                idl_instruction
+                    .accounts
+                    .last()
+                    .filter(|account| account.variadic)
+            });
+            DecodedAccount {
+                role: idl_account.map_or("unknown", |account| account.name),
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            }
+        })
+        .collect();
+
+    Some(DecodedInstruction {
+        name: idl_instruction.name.clone(),
+        arguments,
+        accounts,
     })
 }
 
@@ -939,7 +1395,7 @@ fn try_parse_token_instruction(
 #[derive(Serialize)]
 pub struct ProposeInstructionOutput {
     #[serde(serialize_with = "serialize_b58")]
-    transaction_address: Pubkey,
+    pub transaction_address: Pubkey,
 }
 
 impl fmt::Display for ProposeInstructionOutput {
@@ -955,6 +1411,42 @@ pub fn propose_instruction(
     multisig_address: Pubkey,
     instruction: Instruction,
 ) -> Result<ProposeInstructionOutput> {
+    let (transaction_account, instructions) =
+        get_propose_instructions(config, multisig_program_id, multisig_address, instruction)?;
+    config.sign_and_send_transaction(&instructions, &[config.signer, &transaction_account])?;
+
+    let result = ProposeInstructionOutput {
+        transaction_address: transaction_account.pubkey(),
+    };
+    Ok(result)
+}
+
+/// Like [`propose_instruction`], but only sign the transaction, for the caller to send.
+pub fn sign_propose_instruction(
+    config: &mut SnapshotConfig,
+    multisig_program_id: &Pubkey,
+    multisig_address: Pubkey,
+    instruction: Instruction,
+) -> Result<(Transaction, ProposeInstructionOutput)> {
+    let (transaction_account, instructions) =
+        get_propose_instructions(config, multisig_program_id, multisig_address, instruction)?;
+    let transaction =
+        config.sign_transaction(&instructions, &[config.signer, &transaction_account])?;
+
+    let result = ProposeInstructionOutput {
+        transaction_address: transaction_account.pubkey(),
+    };
+    Ok((transaction, result))
+}
+
+/// Return the instructions that create the multisig transaction for `instruction`,
+/// and the new account that holds it, which must sign them.
+fn get_propose_instructions(
+    config: &mut SnapshotConfig,
+    multisig_program_id: &Pubkey,
+    multisig_address: Pubkey,
+    instruction: Instruction,
+) -> Result<(Keypair, Vec<Instruction>)> {
     // The transaction is stored by the Multisig program in yet another account,
     // that we create just for this transaction. We don't save the private key
     // because the account will be owned by the multisig program later; its
@@ -1046,15 +1538,10 @@ pub fn propose_instruction(
         accounts: multisig_accounts,
     };
 
-    config.sign_and_send_transaction(
-        &[create_instruction, multisig_instruction],
-        &[config.signer, &transaction_account],
-    )?;
-
-    let result = ProposeInstructionOutput {
-        transaction_address: transaction_account.pubkey(),
-    };
-    Ok(result)
+    Ok((
+        transaction_account,
+        vec![create_instruction, multisig_instruction],
+    ))
 }
 
 fn propose_upgrade(
@@ -1193,8 +1680,9 @@ fn approve_batch(
 
     match config.output_mode {
         OutputMode::Json => {
-            println!("Json output mode is not supported for batch approval; it requires an interactive TTY.");
-            return Ok(());
+            return Err(CliError::new(
+                "Json output mode is not supported for batch approval; it requires an interactive TTY.",
+            ));
         }
         OutputMode::Text => { /* This is fine. */ }
     }
@@ -1416,3 +1904,33 @@ fn transfer_token(
         instruction,
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_solido_instruction() {
+        let accounts = SetValidatorWeightMeta {
+            lido: Pubkey::new_unique(),
+            manager: Pubkey::new_unique(),
+            validator_vote_account: Pubkey::new_unique(),
+        };
+        let instruction =
+            lido::instruction::set_validator_weight(&Pubkey::new_unique(), 7, &accounts);
+
+        let decoded = decode_solido_instruction(&instruction).unwrap();
+        assert_eq!(decoded.name, "set_validator_weight");
+        assert_eq!(decoded.arguments, "{ weight: 7 }");
+        let roles: Vec<&str> = decoded.accounts.iter().map(|a| a.role).collect();
+        assert_eq!(roles, vec!["lido", "manager", "validator_vote_account"]);
+        assert_eq!(decoded.accounts[1].pubkey, accounts.manager);
+        assert!(decoded.accounts[1].is_signer);
+
+        let invalid = Instruction {
+            data: vec![255],
+            ..instruction
+        };
+        assert!(decode_solido_instruction(&invalid).is_none());
+    }
+}
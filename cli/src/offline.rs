@@ -0,0 +1,260 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Building transactions on one machine, and signing and submitting them elsewhere.
+//!
+//! With `--output-transaction <file>`, commands write the transaction that they
+//! would send to a file instead, signed only by the signers that are available.
+//! When `--keypair-path` is a public key rather than a keypair, nothing signs
+//! on behalf of that key. A multisig owner on an air-gapped machine can then
+//! add their signature with `solido submit-transaction --sign-only true`, and
+//! any machine can submit the transaction with `solido submit-transaction`.
+//!
+//! The transaction includes a recent blockhash, so it has to be submitted
+//! within about two minutes after it was built.
+
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use solana_program::hash::Hash;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::Transaction;
+
+use lido::util::serialize_b58_slice;
+
+use crate::config::SubmitTransactionOpts;
+use crate::error::{CliError, Error};
+use crate::snapshot::Result;
+use crate::SnapshotConfig;
+
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OfflineSigner {
+    pub pubkey: String,
+    pub signed: bool,
+}
+
+/// The contents of a file written by `--output-transaction`.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OfflineTransaction {
+    /// The transaction, bincode-serialized and base64-encoded.
+    pub transaction: String,
+
+    /// The blockhash that the transaction uses, for information only.
+    pub recent_blockhash: String,
+
+    /// The accounts that need to sign, and whether they signed, for information only.
+    pub signers: Vec<OfflineSigner>,
+}
+
+/// Return the accounts that need to sign the transaction, but did not sign yet.
+pub fn get_missing_signers(transaction: &Transaction) -> Vec<Pubkey> {
+    let num_signers = transaction.message.header.num_required_signatures as usize;
+    transaction.message.account_keys[..num_signers]
+        .iter()
+        .zip(transaction.signatures.iter())
+        .filter(|(_, signature)| **signature == Signature::default())
+        .map(|(pubkey, _)| *pubkey)
+        .collect()
+}
+
+impl OfflineTransaction {
+    pub fn new(transaction: &Transaction) -> OfflineTransaction {
+        let num_signers = transaction.message.header.num_required_signatures as usize;
+        let signers = transaction.message.account_keys[..num_signers]
+            .iter()
+            .zip(transaction.signatures.iter())
+            .map(|(pubkey, signature)| OfflineSigner {
+                pubkey: pubkey.to_string(),
+                signed: *signature != Signature::default(),
+            })
+            .collect();
+        let transaction_bytes =
+            bincode::serialize(transaction).expect("Transactions can always be serialized.");
+        OfflineTransaction {
+            transaction: base64::encode(transaction_bytes),
+            recent_blockhash: transaction.message.recent_blockhash.to_string(),
+            signers,
+        }
+    }
+
+    pub fn to_transaction(&self) -> std::result::Result<Transaction, Error> {
+        let transaction_bytes = base64::decode(&self.transaction)
+            .map_err(|err| CliError::with_cause("Transaction is not valid base64.", err))?;
+        let transaction: Transaction = bincode::deserialize(&transaction_bytes)
+            .map_err(|err| CliError::with_cause("Failed to deserialize transaction.", err))?;
+        // The blockhash is informational, but if it does not match, then
+        // somebody edited the file, and the signers are likely wrong too.
+        if Hash::from_str(&self.recent_blockhash).ok() != Some(transaction.message.recent_blockhash)
+        {
+            return Err(CliError::new(
+                "The recent_blockhash in the file does not match the transaction.",
+            ));
+        }
+        Ok(transaction)
+    }
+}
+
+fn save(path: &Path, transaction: &Transaction, overwrite: bool) -> std::result::Result<(), Error> {
+    // Refuse to overwrite a file that we did not read, so when a command
+    // sends multiple transactions, it fails on the second one, instead of
+    // silently discarding the first one.
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(overwrite)
+        .create_new(!overwrite)
+        .open(path)
+        .map_err(|err| CliError::with_cause("Failed to create transaction file.", err))?;
+    serde_json::to_writer_pretty(file, &OfflineTransaction::new(transaction))
+        .map_err(|err| CliError::with_cause("Failed to write transaction file.", err))?;
+    Ok(())
+}
+
+/// Write a transaction to a new file. Fails if the file already exists.
+pub fn write_transaction(path: &Path, transaction: &Transaction) -> std::result::Result<(), Error> {
+    save(path, transaction, false)
+}
+
+pub fn read_transaction(path: &Path) -> std::result::Result<Transaction, Error> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| CliError::with_cause("Failed to open transaction file.", err))?;
+    let offline_transaction: OfflineTransaction = serde_json::from_reader(file)
+        .map_err(|err| CliError::with_cause("Failed to read transaction file.", err))?;
+    offline_transaction.to_transaction()
+}
+
+#[derive(Serialize)]
+pub struct SubmitTransactionOutput {
+    /// Whether we added our signature to the transaction.
+    pub signed: bool,
+
+    /// The accounts that still need to sign.
+    #[serde(serialize_with = "serialize_b58_slice")]
+    pub missing_signers: Vec<Pubkey>,
+
+    /// The signature of the submitted transaction, if we submitted it.
+    pub transaction_signature: Option<String>,
+}
+
+impl std::fmt::Display for SubmitTransactionOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.signed {
+            writeln!(f, "Added signature to the transaction.")?;
+        }
+        for pubkey in &self.missing_signers {
+            writeln!(f, "Missing signature of {}.", pubkey)?;
+        }
+        if let Some(signature) = &self.transaction_signature {
+            writeln!(f, "Transaction signature: {}", signature)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sign a transaction written by `--output-transaction`, and submit it unless `--sign-only`.
+pub fn command_submit_transaction(
+    config: &mut SnapshotConfig,
+    opts: &SubmitTransactionOpts,
+) -> Result<SubmitTransactionOutput> {
+    let path = opts.transaction_path();
+    let mut transaction = read_transaction(path)?;
+
+    // Sign with the signer, and with the fee payer if that is a separate key.
+    let missing_signers = get_missing_signers(&transaction);
+    let mut signers: Vec<&dyn Signer> = vec![config.signer];
+    if config.fee_payer.pubkey() != config.signer.pubkey() {
+        signers.push(config.fee_payer);
+    }
+    signers.retain(|signer| missing_signers.contains(&signer.pubkey()));
+    let signed = !signers.is_empty();
+    if signed {
+        let recent_blockhash = transaction.message.recent_blockhash;
+        transaction
+            .try_partial_sign(&signers, recent_blockhash)
+            .map_err(|err| {
+                let boxed_error: Error = Box::new(err);
+                boxed_error
+            })?;
+    }
+    let missing_signers = get_missing_signers(&transaction);
+
+    if *opts.sign_only() {
+        save(path, &transaction, true)?;
+        return Ok(SubmitTransactionOutput {
+            signed,
+            missing_signers,
+            transaction_signature: None,
+        });
+    }
+
+    if !missing_signers.is_empty() {
+        for pubkey in &missing_signers {
+            eprintln!("Missing signature of {}.", pubkey);
+        }
+        let error: Error = CliError::new("The transaction is not fully signed yet.");
+        return Err(error.into());
+    }
+
+    let signature = config.send_transaction(&transaction)?;
+    Ok(SubmitTransactionOutput {
+        signed,
+        missing_signers,
+        transaction_signature: Some(signature.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use solana_program::system_instruction;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::signer::null_signer::NullSigner;
+
+    use super::*;
+
+    #[test]
+    fn test_offline_transaction_roundtrip_and_partial_signing() {
+        let payer = Keypair::new();
+        let other = Keypair::new();
+        let instruction = system_instruction::create_account(
+            &payer.pubkey(),
+            &other.pubkey(),
+            1_000_000,
+            0,
+            &Pubkey::new_unique(),
+        );
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+        let blockhash = Hash::new_unique();
+
+        // The payer is offline, only the other account signs.
+        let offline_payer = NullSigner::new(&payer.pubkey());
+        transaction
+            .try_partial_sign(&[&offline_payer as &dyn Signer, &other], blockhash)
+            .unwrap();
+        assert_eq!(get_missing_signers(&transaction), vec![payer.pubkey()]);
+
+        let offline_transaction = OfflineTransaction::new(&transaction);
+        assert_eq!(
+            offline_transaction.signers,
+            vec![
+                OfflineSigner {
+                    pubkey: payer.pubkey().to_string(),
+                    signed: false,
+                },
+                OfflineSigner {
+                    pubkey: other.pubkey().to_string(),
+                    signed: true,
+                },
+            ]
+        );
+        let mut decoded = offline_transaction.to_transaction().unwrap();
+        assert_eq!(decoded, transaction);
+
+        decoded.try_partial_sign(&[&payer], blockhash).unwrap();
+        assert_eq!(get_missing_signers(&decoded), vec![]);
+        assert!(decoded.verify().is_ok());
+    }
+}
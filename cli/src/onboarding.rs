@@ -0,0 +1,548 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Self-service onboarding of validators that want to join a Solido instance.
+//!
+//! Only the manager can add a validator, but the program rejects the vote
+//! account unless the validator configured it in the right way first: the
+//! commission must be 100%, and the withdraw authority must be the rewards
+//! withdraw authority of the instance. The fee account must be an stSOL
+//! account. `solido validator join` checks all of these before a multisig
+//! owner proposes anything, and `solido validator status` checks them again
+//! afterwards.
+
+use std::fmt;
+
+use clap::Clap;
+use serde::Serialize;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_sdk::account::Account;
+use solana_sdk::signer::Signer;
+
+use lido::{
+    state::{Lido, Validator},
+    util::serialize_b58,
+    REWARDS_WITHDRAW_AUTHORITY,
+};
+
+use crate::config::{ConfigFile, ValidatorJoinOpts, ValidatorStatusOpts};
+use crate::error::{Abort, CliError};
+use crate::manifest::ConfigOption;
+use crate::multisig::get_multisig_program_address;
+use crate::print_output;
+use crate::snapshot::Result;
+use crate::{SnapshotClientConfig, SnapshotConfig};
+
+/// The commission that the program requires of vote accounts, in percent.
+///
+/// Validators get their share of the rewards as validation fee in stSOL
+/// instead, see `RewardDistribution`.
+const REQUIRED_COMMISSION: u8 = 100;
+
+#[derive(Clap, Debug)]
+pub struct ValidatorOpts {
+    #[clap(subcommand)]
+    subcommand: SubCommand,
+}
+
+impl ValidatorOpts {
+    pub fn merge_with_config_and_environment(&mut self, config_file: Option<&ConfigFile>) {
+        match &mut self.subcommand {
+            SubCommand::Join(opts) => opts.merge_with_config_and_environment(config_file),
+            SubCommand::Status(opts) => opts.merge_with_config_and_environment(config_file),
+        }
+    }
+
+    /// Return the config options of the subcommand at `path`, see `crate::manifest`.
+    pub fn config_options(path: &[&str]) -> Vec<ConfigOption> {
+        match path {
+            ["join"] => ValidatorJoinOpts::config_options(),
+            ["status"] => ValidatorStatusOpts::config_options(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clap, Debug)]
+enum SubCommand {
+    /// Check that a vote account meets the requirements of the instance,
+    /// create the stSOL fee account if needed, and print the command that
+    /// proposes adding the validator.
+    Join(ValidatorJoinOpts),
+
+    /// Show whether a validator is part of the instance, and whether its
+    /// vote account and fee account still meet the requirements.
+    Status(ValidatorStatusOpts),
+}
+
+pub fn main(config: &mut SnapshotClientConfig, validator_opts: ValidatorOpts) {
+    let output_mode = config.output_mode;
+    match validator_opts.subcommand {
+        SubCommand::Join(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_join(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to prepare joining the instance.");
+            print_output(output_mode, &output);
+        }
+        SubCommand::Status(cmd_opts) => {
+            let result = config.with_snapshot(|config| command_status(config, &cmd_opts));
+            let output = result.ok_or_abort_with("Failed to read the validator status.");
+            print_output(output_mode, &output);
+        }
+    }
+}
+
+/// A reason for the program to reject adding the validator.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Problem {
+    VoteAccountWrongOwner { owner: Pubkey },
+    VoteAccountInvalid,
+    VoteAccountNotRentExempt,
+    WrongWithdrawer { actual: Pubkey, expected: Pubkey },
+    WrongCommission { actual: u8 },
+    FeeAccountMissing { address: Pubkey },
+    FeeAccountWrongOwner { owner: Pubkey, expected: Pubkey },
+    FeeAccountInvalid,
+    FeeAccountWrongMint { mint: Pubkey, expected: Pubkey },
+    AlreadyAdded,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::VoteAccountWrongOwner { owner } => write!(
+                f,
+                "The vote account is owned by {}, not by the vote program.",
+                owner
+            ),
+            Problem::VoteAccountInvalid => {
+                write!(f, "The vote account does not hold a version 1 vote state.")
+            }
+            Problem::VoteAccountNotRentExempt => write!(f, "The vote account is not rent exempt."),
+            Problem::WrongWithdrawer { actual, expected } => write!(
+                f,
+                "The withdraw authority of the vote account is {}, but it must be {}. \
+                 Change it with 'solana vote-authorize-withdrawer'.",
+                actual, expected
+            ),
+            Problem::WrongCommission { actual } => write!(
+                f,
+                "The commission of the vote account is {}%, but it must be {}%. \
+                 Change it with 'solana vote-update-commission'.",
+                actual, REQUIRED_COMMISSION
+            ),
+            Problem::FeeAccountMissing { address } => {
+                write!(f, "The fee account {} does not exist.", address)
+            }
+            Problem::FeeAccountWrongOwner { owner, expected } => write!(
+                f,
+                "The fee account is owned by {}, but it must be owned by the token program {}.",
+                owner, expected
+            ),
+            Problem::FeeAccountInvalid => write!(f, "The fee account is not a token account."),
+            Problem::FeeAccountWrongMint { mint, expected } => write!(
+                f,
+                "The fee account holds tokens of mint {}, but it must hold stSOL, mint {}.",
+                mint, expected
+            ),
+            Problem::AlreadyAdded => write!(f, "The validator is already part of the instance."),
+        }
+    }
+}
+
+/// Check the vote account in the same way that `AddValidator` does.
+pub fn check_vote_account(
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    rent: &Rent,
+    vote_account: &Account,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if vote_account.owner != solana_vote_program::id() {
+        problems.push(Problem::VoteAccountWrongOwner {
+            owner: vote_account.owner,
+        });
+    }
+    if !rent.is_exempt(vote_account.lamports, vote_account.data.len()) {
+        problems.push(Problem::VoteAccountNotRentExempt);
+    }
+
+    // The program only reads the start of the vote state, and it only accepts
+    // version 1 of the layout.
+    let data = &vote_account.data;
+    if data.len() <= 69 || data[0..4] != 1_u32.to_le_bytes() {
+        problems.push(Problem::VoteAccountInvalid);
+        return problems;
+    }
+    let authorized_withdrawer = Pubkey::new(&data[36..68]);
+    let (expected_withdrawer, _) = lido::find_authority_program_address(
+        solido_program_id,
+        solido_address,
+        REWARDS_WITHDRAW_AUTHORITY,
+    );
+    if authorized_withdrawer != expected_withdrawer {
+        problems.push(Problem::WrongWithdrawer {
+            actual: authorized_withdrawer,
+            expected: expected_withdrawer,
+        });
+    }
+    let commission = data[68];
+    if commission != REQUIRED_COMMISSION {
+        problems.push(Problem::WrongCommission { actual: commission });
+    }
+    problems
+}
+
+/// Check that the fee account is an stSOL account, like `AddValidator` does.
+pub fn check_fee_account(solido: &Lido, fee_account: &Account) -> Vec<Problem> {
+    if fee_account.owner != solido.st_sol_token_program {
+        return vec![Problem::FeeAccountWrongOwner {
+            owner: fee_account.owner,
+            expected: solido.st_sol_token_program,
+        }];
+    }
+    if fee_account.data.len() < spl_token::state::Account::LEN {
+        return vec![Problem::FeeAccountInvalid];
+    }
+    match spl_token::state::Account::unpack_from_slice(&fee_account.data) {
+        Ok(token_account) if token_account.mint != solido.st_sol_mint => {
+            vec![Problem::FeeAccountWrongMint {
+                mint: token_account.mint,
+                expected: solido.st_sol_mint,
+            }]
+        }
+        Ok(_) => Vec::new(),
+        Err(_) => vec![Problem::FeeAccountInvalid],
+    }
+}
+
+/// Read the fee account and check it, see `check_fee_account`.
+fn get_fee_account_problems(
+    config: &mut SnapshotConfig,
+    solido: &Lido,
+    fee_account: &Pubkey,
+) -> Result<Vec<Problem>> {
+    if !config.client.account_exists(fee_account)? {
+        return Ok(vec![Problem::FeeAccountMissing {
+            address: *fee_account,
+        }]);
+    }
+    let account = config.client.get_account(fee_account)?;
+    Ok(check_fee_account(solido, account))
+}
+
+fn print_problems(f: &mut fmt::Formatter, problems: &[String]) -> fmt::Result {
+    if problems.is_empty() {
+        writeln!(f, "The vote account and fee account meet all requirements.")?;
+    } else {
+        writeln!(f, "Problems:")?;
+        for problem in problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ValidatorJoinOutput {
+    #[serde(serialize_with = "serialize_b58")]
+    pub validator_vote_account: Pubkey,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pub validator_fee_account: Pubkey,
+
+    /// Whether we created the fee account.
+    pub created_fee_account: bool,
+
+    /// Why the program would reject adding the validator, if it would.
+    pub problems: Vec<String>,
+
+    /// The command that proposes adding the validator, for a multisig owner to
+    /// run. Only set when there are no problems.
+    pub propose_command: Option<String>,
+}
+
+impl fmt::Display for ValidatorJoinOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vote account: {}", self.validator_vote_account)?;
+        write!(f, "Fee account:  {}", self.validator_fee_account)?;
+        if self.created_fee_account {
+            write!(f, " (created)")?;
+        }
+        writeln!(f)?;
+        print_problems(f, &self.problems)?;
+        if let Some(command) = &self.propose_command {
+            writeln!(
+                f,
+                "\nAsk an owner of the manager multisig to propose adding the validator with:\n"
+            )?;
+            writeln!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command_join(
+    config: &mut SnapshotConfig,
+    opts: &ValidatorJoinOpts,
+) -> Result<ValidatorJoinOutput> {
+    let solido = config.client.get_solido(opts.solido_address())?;
+    let (manager, _) =
+        get_multisig_program_address(opts.multisig_program_id(), opts.multisig_address());
+    if manager != solido.manager {
+        return Err(CliError::with_cause(
+            "The multisig is not the manager of the Solido instance.",
+            format!(
+                "The manager is {}, the multisig signs as {}.",
+                solido.manager, manager
+            ),
+        )
+        .into());
+    }
+
+    let rent = config.client.get_rent()?;
+    let vote_account = config.client.get_account(opts.validator_vote_account())?;
+    let mut problems = check_vote_account(
+        opts.solido_program_id(),
+        opts.solido_address(),
+        &rent,
+        vote_account,
+    );
+    if solido.validators.get(opts.validator_vote_account()).is_ok() {
+        problems.push(Problem::AlreadyAdded);
+    }
+
+    let mut validator_fee_account = *opts.validator_fee_account();
+    let mut created_fee_account = false;
+    if validator_fee_account == Pubkey::default() {
+        // Only create the account when we can propose the validator, so a
+        // validator that still has to fix its vote account pays for nothing.
+        let mut instructions = Vec::new();
+        let (address, needs_creating) = config.client.get_or_create_associated_token_account(
+            &mut instructions,
+            &config.signer.pubkey(),
+            &config.signer.pubkey(),
+            &solido.st_sol_mint,
+        )?;
+        if needs_creating && problems.is_empty() {
+            config.sign_and_send_transaction(&instructions, &[config.signer])?;
+            created_fee_account = true;
+        } else if !needs_creating {
+            problems.extend(get_fee_account_problems(config, &solido, &address)?);
+        }
+        validator_fee_account = address;
+    } else {
+        problems.extend(get_fee_account_problems(
+            config,
+            &solido,
+            &validator_fee_account,
+        )?);
+    }
+
+    let propose_command = if problems.is_empty() {
+        Some(format!(
+            "solido add-validator \\\n  --solido-program-id {} \\\n  --solido-address {} \\\n  \
+             --validator-vote-account {} \\\n  --validator-fee-account {} \\\n  \
+             --multisig-program-id {} \\\n  --multisig-address {}",
+            opts.solido_program_id(),
+            opts.solido_address(),
+            opts.validator_vote_account(),
+            validator_fee_account,
+            opts.multisig_program_id(),
+            opts.multisig_address(),
+        ))
+    } else {
+        None
+    };
+
+    Ok(ValidatorJoinOutput {
+        validator_vote_account: *opts.validator_vote_account(),
+        validator_fee_account,
+        created_fee_account,
+        problems: problems.iter().map(|problem| problem.to_string()).collect(),
+        propose_command,
+    })
+}
+
+#[derive(Serialize)]
+pub struct ValidatorStatusOutput {
+    #[serde(serialize_with = "serialize_b58")]
+    pub validator_vote_account: Pubkey,
+
+    /// The validator in the instance, if it is part of it.
+    pub validator: Option<Validator>,
+
+    /// Why the program would reject the vote account or fee account, if it
+    /// would. For a validator that is part of the instance, a problem means
+    /// that it changed its vote account after it was added.
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ValidatorStatusOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vote account: {}", self.validator_vote_account)?;
+        match &self.validator {
+            None => writeln!(f, "The validator is not part of the instance.")?,
+            Some(validator) => {
+                writeln!(f, "Fee account:              {}", validator.fee_address)?;
+                writeln!(f, "Active:                   {}", validator.active)?;
+                writeln!(f, "Weight:                   {}", validator.weight)?;
+                writeln!(f, "Unclaimed fee:            {}", validator.fee_credit)?;
+                writeln!(
+                    f,
+                    "Stake accounts balance:   {}",
+                    validator.stake_accounts_balance
+                )?;
+                writeln!(
+                    f,
+                    "Unstake accounts balance: {}",
+                    validator.unstake_accounts_balance
+                )?;
+            }
+        }
+        print_problems(f, &self.problems)
+    }
+}
+
+pub fn command_status(
+    config: &mut SnapshotConfig,
+    opts: &ValidatorStatusOpts,
+) -> Result<ValidatorStatusOutput> {
+    let solido = config.client.get_solido(opts.solido_address())?;
+    let rent = config.client.get_rent()?;
+    let vote_account = config.client.get_account(opts.validator_vote_account())?;
+    let mut problems = check_vote_account(
+        opts.solido_program_id(),
+        opts.solido_address(),
+        &rent,
+        vote_account,
+    );
+
+    let validator = solido
+        .validators
+        .get(opts.validator_vote_account())
+        .ok()
+        .map(|pe| pe.entry.clone());
+    if let Some(validator) = &validator {
+        problems.extend(get_fee_account_problems(
+            config,
+            &solido,
+            &validator.fee_address,
+        )?);
+    }
+
+    Ok(ValidatorStatusOutput {
+        validator_vote_account: *opts.validator_vote_account(),
+        validator,
+        problems: problems.iter().map(|problem| problem.to_string()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_vote_account(withdrawer: &Pubkey, commission: u8) -> Account {
+        let mut data = vec![0; 3731];
+        data[0..4].copy_from_slice(&1_u32.to_le_bytes());
+        data[4..36].copy_from_slice(Pubkey::new_unique().as_ref());
+        data[36..68].copy_from_slice(withdrawer.as_ref());
+        data[68] = commission;
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: solana_vote_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_vote_account() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let (withdrawer, _) = lido::find_authority_program_address(
+            &program_id,
+            &solido_address,
+            REWARDS_WITHDRAW_AUTHORITY,
+        );
+        let rent = Rent::default();
+
+        let account = new_vote_account(&withdrawer, 100);
+        assert_eq!(
+            check_vote_account(&program_id, &solido_address, &rent, &account),
+            vec![]
+        );
+
+        // The most common mistakes: the validator kept its own withdrawer, and
+        // a commission below 100%.
+        let own_withdrawer = Pubkey::new_unique();
+        let account = new_vote_account(&own_withdrawer, 8);
+        assert_eq!(
+            check_vote_account(&program_id, &solido_address, &rent, &account),
+            vec![
+                Problem::WrongWithdrawer {
+                    actual: own_withdrawer,
+                    expected: withdrawer,
+                },
+                Problem::WrongCommission { actual: 8 },
+            ]
+        );
+
+        let mut account = new_vote_account(&withdrawer, 100);
+        account.data[0..4].copy_from_slice(&2_u32.to_le_bytes());
+        assert_eq!(
+            check_vote_account(&program_id, &solido_address, &rent, &account),
+            vec![Problem::VoteAccountInvalid]
+        );
+    }
+
+    #[test]
+    fn test_check_fee_account() {
+        let mut solido = Lido::default();
+        solido.st_sol_mint = Pubkey::new_unique();
+        solido.st_sol_token_program = spl_token::id();
+
+        let new_token_account = |mint: Pubkey| {
+            let token_account = spl_token::state::Account {
+                mint,
+                owner: Pubkey::new_unique(),
+                state: spl_token::state::AccountState::Initialized,
+                ..spl_token::state::Account::default()
+            };
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            token_account.pack_into_slice(&mut data);
+            Account {
+                lamports: 1,
+                data,
+                owner: spl_token::id(),
+                executable: false,
+                rent_epoch: 0,
+            }
+        };
+
+        let account = new_token_account(solido.st_sol_mint);
+        assert_eq!(check_fee_account(&solido, &account), vec![]);
+
+        let other_mint = Pubkey::new_unique();
+        let account = new_token_account(other_mint);
+        assert_eq!(
+            check_fee_account(&solido, &account),
+            vec![Problem::FeeAccountWrongMint {
+                mint: other_mint,
+                expected: solido.st_sol_mint,
+            }]
+        );
+
+        let mut account = new_token_account(solido.st_sol_mint);
+        account.owner = Pubkey::new_unique();
+        assert_eq!(
+            check_fee_account(&solido, &account),
+            vec![Problem::FeeAccountWrongOwner {
+                owner: account.owner,
+                expected: spl_token::id(),
+            }]
+        );
+    }
+}
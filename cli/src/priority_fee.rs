@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Priority fees for maintenance transactions.
+//!
+//! During congestion, leaders prioritize transactions that pay a higher price
+//! per compute unit. We set the price with the `SetComputeUnitPrice` instruction
+//! of the compute budget program. The Solana version that we build against
+//! predates that instruction, so we construct it by hand.
+//!
+//! We do not request a compute unit limit, so the limit is the default per
+//! instruction, and the priority fee is the price times that limit.
+
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+
+use lido::token::Lamports;
+
+/// The compute budget program.
+pub mod compute_budget_program {
+    solana_program::declare_id!("ComputeBudget111111111111111111111111111111");
+}
+
+/// Tag of `ComputeBudgetInstruction::SetComputeUnitPrice`.
+const SET_COMPUTE_UNIT_PRICE_TAG: u8 = 3;
+
+/// Compute units that a transaction may use per instruction, if it requests no limit.
+pub const DEFAULT_COMPUTE_UNITS_PER_INSTRUCTION: u64 = 200_000;
+
+/// Percentile of the recent prioritization fees to pay in dynamic mode.
+const DYNAMIC_FEE_PERCENTILE: usize = 75;
+
+/// How much priority fee to pay for maintenance transactions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PriorityFeeOptions {
+    /// Price per compute unit, in micro-lamports. In dynamic mode, we pay at least this.
+    pub micro_lamports_per_compute_unit: u64,
+
+    /// Whether to derive the price from the fees that recently landed
+    /// transactions paid, which we get through `getRecentPrioritizationFees`.
+    pub dynamic: bool,
+}
+
+/// Build the instruction that sets the price per compute unit, in micro-lamports.
+pub fn set_compute_unit_price(micro_lamports: u64) -> Instruction {
+    let mut data = vec![SET_COMPUTE_UNIT_PRICE_TAG];
+    data.extend_from_slice(&micro_lamports.to_le_bytes());
+    Instruction {
+        program_id: compute_budget_program::id(),
+        accounts: Vec::new(),
+        data,
+    }
+}
+
+/// Return the priority fee that a transaction pays, given the price per compute
+/// unit, and the number of instructions other than compute budget instructions.
+pub fn get_priority_fee(micro_lamports: u64, num_instructions: usize) -> Lamports {
+    let compute_units = DEFAULT_COMPUTE_UNITS_PER_INSTRUCTION as u128 * num_instructions as u128;
+    let micro_lamports_total = micro_lamports as u128 * compute_units;
+    // Round up, like the runtime does.
+    let lamports = (micro_lamports_total + 999_999) / 1_000_000;
+    Lamports(lamports as u64)
+}
+
+/// Return the price to pay, given the prioritization fees of recent slots.
+pub fn get_dynamic_price(options: &PriorityFeeOptions, recent_fees: &[u64]) -> u64 {
+    let mut fees = recent_fees.to_vec();
+    fees.sort_unstable();
+    let recent_price = match fees.len() {
+        0 => 0,
+        n => fees[(n - 1) * DYNAMIC_FEE_PERCENTILE / 100],
+    };
+    recent_price.max(options.micro_lamports_per_compute_unit)
+}
+
+/// Return the accounts that the instructions write to.
+///
+/// The recent prioritization fees of these accounts are the fees that we
+/// compete with.
+pub fn get_writable_accounts(instructions: &[Instruction]) -> Vec<Pubkey> {
+    let mut accounts: Vec<Pubkey> = instructions
+        .iter()
+        .flat_map(|instruction| instruction.accounts.iter())
+        .filter(|account| account.is_writable)
+        .map(|account| account.pubkey)
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+    accounts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_compute_unit_price_matches_compute_budget_program() {
+        // Serialized by `ComputeBudgetInstruction::set_compute_unit_price(1_000)`
+        // from a newer solana-sdk.
+        let instruction = set_compute_unit_price(1_000);
+        assert_eq!(instruction.data, vec![3, 232, 3, 0, 0, 0, 0, 0, 0]);
+        assert!(instruction.accounts.is_empty());
+    }
+
+    #[test]
+    fn test_get_priority_fee() {
+        assert_eq!(get_priority_fee(0, 1), Lamports(0));
+        // 200k compute units at 1 micro-lamport per unit is 0.2 lamports, round up.
+        assert_eq!(get_priority_fee(1, 1), Lamports(1));
+        assert_eq!(get_priority_fee(10_000, 1), Lamports(2_000));
+        assert_eq!(get_priority_fee(10_000, 2), Lamports(4_000));
+    }
+
+    #[test]
+    fn test_get_dynamic_price() {
+        let options = PriorityFeeOptions {
+            micro_lamports_per_compute_unit: 100,
+            dynamic: true,
+        };
+        // Without data, we pay the configured minimum.
+        assert_eq!(get_dynamic_price(&options, &[]), 100);
+        assert_eq!(get_dynamic_price(&options, &[0, 0, 0, 50]), 100);
+        assert_eq!(get_dynamic_price(&options, &[400, 0, 300, 200, 1_000]), 400);
+    }
+}
@@ -7,9 +7,10 @@
 
 use lido::metrics::{LamportsHistogram, Metrics};
 use lido::token::{Lamports, StLamports};
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::io::Write;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 pub struct MetricFamily<'a> {
     /// Name of the metric, e.g. [`goats_teleported_total`](https://crbug.com/31482).
@@ -71,6 +72,12 @@ impl<'a> Metric<'a> {
         Metric::new(MetricValue::Nano(amount.0))
     }
 
+    /// Construct a metric that measures a duration, in seconds.
+    pub fn new_seconds(duration: Duration) -> Metric<'a> {
+        // Nanosecond precision is plenty for our durations.
+        Metric::new(MetricValue::Nano(duration.as_nanos() as u64))
+    }
+
     /// Construct a metric that measures an amount of stSOL.
     pub fn new_st_sol(amount: StLamports) -> Metric<'a> {
         // One stLamport is 1e-9 stSOL, so we use nano here.
@@ -95,6 +102,26 @@ impl<'a> Metric<'a> {
     }
 }
 
+/// Quote a label value, escaping it the way the text format expects.
+///
+/// Some label values, such as validator names, come from third parties. Rust's
+/// `{:?}` escapes more than backslash, double quote, and newline, and Prometheus
+/// rejects the entire exposition if it finds any other escape sequence.
+fn quote_label_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
 pub fn write_metric<W: Write>(out: &mut W, family: &MetricFamily) -> io::Result<()> {
     writeln!(out, "# HELP {} {}", family.name, family.help)?;
     writeln!(out, "# TYPE {} {}", family.name, family.type_)?;
@@ -102,15 +129,11 @@ pub fn write_metric<W: Write>(out: &mut W, family: &MetricFamily) -> io::Result<
         write!(out, "{}{}", family.name, metric.suffix)?;
 
         // If there are labels, write the key-value pairs between {}.
-        // Escaping of the value uses Rust's string syntax, which is
-        // not exactly what Prometheus wants, but it is identical for
-        // all of the values that we use it with; this is not a general
-        // Prometheus formatter, just a quick one for our use.
         if !metric.labels.is_empty() {
             write!(out, "{{")?;
             let mut separator = "";
             for (key, value) in &metric.labels {
-                write!(out, "{}{}={:?}", separator, key, value)?;
+                write!(out, "{}{}={}", separator, key, quote_label_value(value))?;
                 separator = ",";
             }
             write!(out, "}}")?;
@@ -141,6 +164,108 @@ pub fn write_metric<W: Write>(out: &mut W, family: &MetricFamily) -> io::Result<
     writeln!(out)
 }
 
+/// Merge the output of [`write_metric`] for several sources, with a label that tells them apart.
+///
+/// Every source is the exposition of one source, e.g. one Solido instance. A
+/// metric family may only occur once in an exposition, so we write every family
+/// once, with the metrics of all sources that have it, in the order in which
+/// the families first occur. Like `write_metric`, this only handles the output
+/// that we produce ourselves, it is not a general Prometheus parser.
+pub fn write_merged<W: Write>(
+    out: &mut W,
+    label_key: &str,
+    sources: &[(&str, &[u8])],
+) -> io::Result<()> {
+    // Per family: the name, the HELP and TYPE lines, and the metric lines.
+    let mut families: Vec<(String, Vec<String>, Vec<String>)> = Vec::new();
+
+    for &(label_value, exposition) in sources {
+        let exposition = String::from_utf8_lossy(exposition);
+        let label = format!("{}={}", label_key, quote_label_value(label_value));
+        let mut current = None;
+        for line in exposition.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let name = help.split(' ').next().unwrap_or("");
+                let i = match families.iter().position(|(n, _, _)| n == name) {
+                    Some(i) => i,
+                    None => {
+                        families.push((name.to_string(), Vec::new(), Vec::new()));
+                        families.len() - 1
+                    }
+                };
+                current = Some(i);
+            }
+            let (_, header, metrics) = match current {
+                Some(i) => &mut families[i],
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Expected a HELP line before the first metric.",
+                    ))
+                }
+            };
+            if line.starts_with('#') {
+                // The first source that has the family provides its header.
+                if header.len() < 2 {
+                    header.push(line.to_string());
+                }
+                continue;
+            }
+
+            metrics.push(insert_labels(line, &label));
+        }
+    }
+
+    for (_, header, metrics) in &families {
+        for line in header.iter().chain(metrics.iter()) {
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Insert formatted labels, e.g. `a="b",c="d"`, before the labels of a metric line.
+fn insert_labels(line: &str, labels: &str) -> String {
+    // Metric names contain neither braces nor spaces, so the first of
+    // those tells us whether the metric has labels already.
+    match line.find(|c: char| c == '{' || c == ' ') {
+        Some(i) if line[i..].starts_with('{') => {
+            format!("{}{},{}", &line[..=i], labels, &line[i + 1..])
+        }
+        Some(i) => format!("{}{{{}}}{}", &line[..i], labels, &line[i..]),
+        None => line.to_string(),
+    }
+}
+
+/// Copy the output of [`write_metric`] or [`write_merged`], with the same labels on every metric.
+///
+/// This is for constant labels that describe the deployment rather than the
+/// metric, such as the cluster. The labels go before the labels that the
+/// metric has already, so they must not use the same names.
+pub fn write_with_labels<W: Write>(
+    out: &mut W,
+    labels: &[(String, String)],
+    exposition: &[u8],
+) -> io::Result<()> {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, quote_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    for line in String::from_utf8_lossy(exposition).lines() {
+        if labels.is_empty() || line.is_empty() || line.starts_with('#') {
+            writeln!(out, "{}", line)?;
+        } else {
+            writeln!(out, "{}", insert_labels(line, &labels))?;
+        }
+    }
+    Ok(())
+}
+
 pub fn solido_histogram_to_metrics(at: SystemTime, histogram: &LamportsHistogram) -> Vec<Metric> {
     let mut metrics = Vec::with_capacity(histogram.counts.len() + 2);
 
@@ -175,6 +300,64 @@ pub fn solido_histogram_to_metrics(at: SystemTime, histogram: &LamportsHistogram
     metrics
 }
 
+/// A histogram of durations that the daemon measures itself, such as RPC latencies.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct DurationHistogram {
+    /// Number of observations per bucket, not cumulative.
+    ///
+    /// There is one more bucket than there are upper bounds, the last one is
+    /// the `+Inf` bucket.
+    counts: [u64; 13],
+
+    /// Sum of all observed durations.
+    total: Duration,
+}
+
+impl DurationHistogram {
+    /// Upper bounds of the buckets, in milliseconds.
+    pub const BUCKET_UPPER_BOUNDS_MS: [u64; 12] = [
+        10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000,
+    ];
+
+    pub fn observe(&mut self, duration: Duration) {
+        let bucket = Self::BUCKET_UPPER_BOUNDS_MS
+            .iter()
+            .position(|&upper_bound| duration <= Duration::from_millis(upper_bound))
+            .unwrap_or(Self::BUCKET_UPPER_BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+        self.total += duration;
+    }
+
+    pub fn num_observations(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Return the `_bucket`, `_sum`, and `_count` metrics, with the bounds in seconds.
+    pub fn to_metrics<'a>(&self) -> Vec<Metric<'a>> {
+        let mut metrics = Vec::with_capacity(self.counts.len() + 2);
+
+        let mut cumulative_count = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative_count += count;
+            let le = match Self::BUCKET_UPPER_BOUNDS_MS.get(i) {
+                Some(ms) => format!("{}.{:03}", ms / 1_000, ms % 1_000),
+                None => "+Inf".to_string(),
+            };
+            metrics.push(
+                Metric::new(cumulative_count)
+                    .with_suffix("_bucket")
+                    .with_label("le", le),
+            );
+        }
+
+        metrics
+            .push(Metric::new(MetricValue::Nano(self.total.as_nanos() as u64)).with_suffix("_sum"));
+        metrics.push(Metric::new(cumulative_count).with_suffix("_count"));
+
+        metrics
+    }
+}
+
 pub fn write_solido_metrics_as_prometheus<W: io::Write>(
     metrics: &Metrics,
     at: SystemTime,
@@ -259,7 +442,12 @@ pub fn write_solido_metrics_as_prometheus<W: io::Write>(
 mod test {
     use std::str;
 
-    use super::{write_metric, Metric, MetricFamily, MetricValue};
+    use std::time::Duration;
+
+    use super::{
+        write_merged, write_metric, write_with_labels, DurationHistogram, Metric, MetricFamily,
+        MetricValue,
+    };
 
     #[test]
     fn write_metric_without_labels() {
@@ -418,4 +606,183 @@ mod test {
                 ")
         )
     }
+
+    #[test]
+    fn write_duration_histogram() {
+        let mut histogram = DurationHistogram::default();
+        histogram.observe(Duration::from_millis(8));
+        histogram.observe(Duration::from_millis(10));
+        histogram.observe(Duration::from_millis(400));
+        histogram.observe(Duration::from_secs(90));
+        assert_eq!(histogram.num_observations(), 4);
+
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "goat_teleport_duration_seconds",
+                help: "Time it took to teleport a goat.",
+                type_: "histogram",
+                metrics: histogram.to_metrics(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP goat_teleport_duration_seconds Time it took to teleport a goat.\n\
+                 # TYPE goat_teleport_duration_seconds histogram\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.010\"} 2\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.025\"} 2\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.050\"} 2\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.100\"} 2\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.250\"} 2\n\
+                 goat_teleport_duration_seconds_bucket{le=\"0.500\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"1.000\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"2.500\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"5.000\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"10.000\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"30.000\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"60.000\"} 3\n\
+                 goat_teleport_duration_seconds_bucket{le=\"+Inf\"} 4\n\
+                 goat_teleport_duration_seconds_sum 90.418000000\n\
+                 goat_teleport_duration_seconds_count 4\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_merged_labels_every_metric() {
+        let write = |goats: u64, bucket: u64| {
+            let mut out: Vec<u8> = Vec::new();
+            for family in vec![
+                MetricFamily {
+                    name: "goats_teleported_total",
+                    help: "Number of goats teleported since launch.",
+                    type_: "counter",
+                    metrics: vec![Metric::new(goats)],
+                },
+                MetricFamily {
+                    name: "teleported_goat_weight_kg",
+                    help: "Histogram of the weight of teleported goats.",
+                    type_: "histogram",
+                    metrics: vec![Metric::new(bucket)
+                        .with_suffix("_bucket")
+                        .with_label("le", "+Inf".to_string())],
+                },
+            ] {
+                write_metric(&mut out, &family).unwrap();
+            }
+            out
+        };
+        let ams = write(10, 1);
+        let zrh = write(53, 2);
+
+        let mut out: Vec<u8> = Vec::new();
+        write_merged(&mut out, "site", &[("AMS", &ams[..]), ("ZRH", &zrh[..])]).unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP goats_teleported_total Number of goats teleported since launch.\n\
+                 # TYPE goats_teleported_total counter\n\
+                 goats_teleported_total{site=\"AMS\"} 10\n\
+                 goats_teleported_total{site=\"ZRH\"} 53\n\n\
+                 # HELP teleported_goat_weight_kg Histogram of the weight of teleported goats.\n\
+                 # TYPE teleported_goat_weight_kg histogram\n\
+                 teleported_goat_weight_kg_bucket{site=\"AMS\",le=\"+Inf\"} 1\n\
+                 teleported_goat_weight_kg_bucket{site=\"ZRH\",le=\"+Inf\"} 2\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_with_labels_labels_every_metric() {
+        let mut exposition: Vec<u8> = Vec::new();
+        write_metric(
+            &mut exposition,
+            &MetricFamily {
+                name: "goats_teleported_total",
+                help: "Number of goats teleported since launch.",
+                type_: "counter",
+                metrics: vec![
+                    Metric::new(10_u64),
+                    Metric::new(53_u64).with_label("site", "ZRH".to_string()),
+                ],
+            },
+        )
+        .unwrap();
+
+        let labels = vec![
+            ("cluster".to_string(), "mainnet-beta".to_string()),
+            ("instance_name".to_string(), "goat-prod".to_string()),
+        ];
+        let mut out: Vec<u8> = Vec::new();
+        write_with_labels(&mut out, &labels, &exposition).unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP goats_teleported_total Number of goats teleported since launch.\n\
+                 # TYPE goats_teleported_total counter\n\
+                 goats_teleported_total{cluster=\"mainnet-beta\",instance_name=\"goat-prod\"} 10\n\
+                 goats_teleported_total{cluster=\"mainnet-beta\",instance_name=\"goat-prod\",site=\"ZRH\"} 53\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_metric_escapes_label_values() {
+        // Validators choose their own names, so a name can contain anything.
+        let name = "Goat\t\"Teleport\"\r\\\n\u{0}e\u{301}";
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "goats_teleported_total",
+                help: "Number of goats teleported since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(10).with_label("name", name.to_string())],
+            },
+        )
+        .unwrap();
+
+        let expected_labels = "name=\"Goat\t\\\"Teleport\\\"\r\\\\\\n\u{0}e\u{301}\"";
+        assert_eq!(
+            str::from_utf8(&out[..]).unwrap(),
+            format!(
+                "# HELP goats_teleported_total Number of goats teleported since launch.\n\
+                 # TYPE goats_teleported_total counter\n\
+                 goats_teleported_total{{{}}} 10\n\n",
+                expected_labels
+            ),
+        );
+
+        let mut merged: Vec<u8> = Vec::new();
+        write_merged(&mut merged, "site", &[(name, &out[..])]).unwrap();
+        let mut labeled: Vec<u8> = Vec::new();
+        write_with_labels(
+            &mut labeled,
+            &[("site".to_string(), name.to_string())],
+            &out,
+        )
+        .unwrap();
+        let expected_line = format!(
+            "goats_teleported_total{{{},{}}} 10",
+            expected_labels.replacen("name", "site", 1),
+            expected_labels,
+        );
+        assert_eq!(
+            str::from_utf8(&merged).unwrap().lines().nth(2),
+            Some(&expected_line[..])
+        );
+        assert_eq!(
+            str::from_utf8(&labeled).unwrap().lines().nth(2),
+            Some(&expected_line[..])
+        );
+    }
 }
@@ -0,0 +1,344 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Limiting the rate of the requests that we make to an RPC endpoint.
+//!
+//! Public RPC endpoints rate limit their clients, and respond with
+//! `429 Too Many Requests` when a client exceeds the limit. Rather than
+//! running into that and failing an iteration of the maintenance daemon, we
+//! budget our requests. The budget is a token bucket: it holds at most `burst`
+//! requests, and it refills at `requests_per_second`. A request that finds the
+//! bucket empty waits until there is a token again.
+//!
+//! Every request to the endpoint draws from the same bucket, including the
+//! `GetMultipleAccounts` chunks that the snapshot client sends concurrently,
+//! so together they never exceed the burst. The http endpoints of the daemon
+//! only serve the snapshot that the main loop published, they never call the
+//! RPC, so they cannot cause a burst of their own.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_response::{RpcResult, RpcSimulateTransactionResult};
+use solana_sdk::account::Account;
+use solana_sdk::clock::Epoch;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction};
+
+use crate::rpc::{
+    AccountFilter, BlockProduction, InflationReward, Rpc, SignatureInfo, TransactionInfo,
+};
+
+/// After the endpoint rate limited us, wait this long before the next request.
+///
+/// We only pause when there is a budget. Without one, the snapshot client
+/// fails over to a different endpoint instead.
+const RATE_LIMITED_PAUSE: Duration = Duration::from_secs(1);
+
+/// How many requests we can make to an endpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RequestBudget {
+    /// The rate at which the budget refills, 0 for no limit.
+    pub requests_per_second: u32,
+
+    /// The number of requests that we can make at once, after a pause.
+    pub burst: u32,
+}
+
+impl RequestBudget {
+    pub fn unlimited() -> RequestBudget {
+        RequestBudget {
+            requests_per_second: 0,
+            burst: 0,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_second == 0
+    }
+}
+
+impl Default for RequestBudget {
+    fn default() -> RequestBudget {
+        RequestBudget::unlimited()
+    }
+}
+
+/// Return whether the endpoint responded with `429 Too Many Requests`.
+pub fn is_rate_limit_error(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Reqwest(err) => err.status().map(|status| status.as_u16()) == Some(429),
+        ClientErrorKind::Custom(message) => message.contains("429 Too Many Requests"),
+        _ => false,
+    }
+}
+
+struct Bucket {
+    /// The number of requests that we can make right now.
+    tokens: f64,
+
+    /// The last time that we added tokens.
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full(budget: RequestBudget) -> Bucket {
+        Bucket {
+            tokens: budget.burst.max(1) as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+}
+
+/// An [`Rpc`] that makes requests within a [`RequestBudget`].
+pub struct RateLimitedRpc {
+    inner: Box<dyn Rpc>,
+    budget: RequestBudget,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimitedRpc {
+    pub fn new(inner: Box<dyn Rpc>, budget: RequestBudget) -> RateLimitedRpc {
+        RateLimitedRpc {
+            inner,
+            budget,
+            bucket: Mutex::new(Bucket::full(budget)),
+        }
+    }
+
+    pub fn budget(&self) -> RequestBudget {
+        self.budget
+    }
+
+    /// Switch to a different budget, starting with a full bucket.
+    pub fn set_budget(&mut self, budget: RequestBudget) {
+        self.budget = budget;
+        self.bucket = Mutex::new(Bucket::full(budget));
+    }
+
+    /// Wait until the budget allows one more request, and take it from the budget.
+    fn acquire(&self) {
+        if self.budget.is_unlimited() {
+            return;
+        }
+        let rate = self.budget.requests_per_second as f64;
+        let capacity = self.budget.burst.max(1) as f64;
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                // After a 429 we set `refilled_at` into the future, and then
+                // there is nothing to add yet.
+                if now > bucket.refilled_at {
+                    let elapsed = (now - bucket.refilled_at).as_secs_f64();
+                    bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+                    bucket.refilled_at = now;
+                }
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                let until_refilled = bucket.refilled_at.saturating_duration_since(now);
+                until_refilled + Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+            };
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Pass through the result of a request, and back off if we were rate limited.
+    fn check<T>(&self, result: ClientResult<T>) -> ClientResult<T> {
+        match result {
+            Err(ref err) if !self.budget.is_unlimited() && is_rate_limit_error(err) => {
+                let mut bucket = self.bucket.lock().unwrap();
+                bucket.tokens = 0.0;
+                bucket.refilled_at = Instant::now() + RATE_LIMITED_PAUSE;
+                result
+            }
+            _ => result,
+        }
+    }
+}
+
+impl Rpc for RateLimitedRpc {
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>> {
+        self.acquire();
+        self.check(
+            self.inner
+                .get_multiple_accounts_with_commitment(pubkeys, commitment),
+        )
+    }
+
+    fn simulate_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        self.acquire();
+        self.check(self.inner.simulate_transaction(transaction))
+    }
+
+    fn send_transaction(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+        skip_preflight: bool,
+    ) -> ClientResult<Signature> {
+        self.acquire();
+        self.check(
+            self.inner
+                .send_transaction(transaction, commitment, skip_preflight),
+        )
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        self.acquire();
+        self.check(self.inner.get_signature_status(signature, commitment))
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> ClientResult<bool> {
+        self.acquire();
+        self.check(self.inner.is_blockhash_valid(blockhash))
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        self.acquire();
+        self.check(self.inner.get_latest_blockhash())
+    }
+
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Signature> {
+        // This makes several requests internally, we can only budget the first.
+        self.acquire();
+        self.check(
+            self.inner
+                .send_and_confirm_transaction_with_spinner(transaction, commitment),
+        )
+    }
+
+    fn get_recent_prioritization_fees(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>> {
+        self.acquire();
+        self.check(self.inner.get_recent_prioritization_fees(pubkeys))
+    }
+
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        self.acquire();
+        self.check(self.inner.get_program_accounts(program_id, filters))
+    }
+
+    fn get_inflation_reward(
+        &self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>> {
+        self.acquire();
+        self.check(self.inner.get_inflation_reward(pubkeys, epoch))
+    }
+
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>> {
+        self.acquire();
+        self.check(self.inner.get_vote_account_stakes())
+    }
+
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>> {
+        self.acquire();
+        self.check(self.inner.get_block_production())
+    }
+
+    fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+        limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>> {
+        self.acquire();
+        self.check(
+            self.inner
+                .get_signatures_for_address(address, before, until, limit),
+        )
+    }
+
+    fn get_transaction(&self, signature: &str) -> ClientResult<Option<TransactionInfo>> {
+        self.acquire();
+        self.check(self.inner.get_transaction(signature))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_rpc::{MockRpc, ReadFault};
+
+    #[test]
+    fn test_budget_limits_requests_after_burst() {
+        let rpc = MockRpc::new();
+        let budget = RequestBudget {
+            requests_per_second: 20,
+            burst: 3,
+        };
+        let limited = RateLimitedRpc::new(Box::new(rpc.clone()), budget);
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            limited
+                .get_multiple_accounts_with_commitment(&[], CommitmentConfig::confirmed())
+                .unwrap();
+        }
+        // The first three requests are free, the other two wait 50ms each.
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_eq!(rpc.get_multiple_accounts_calls(), 5);
+    }
+
+    #[test]
+    fn test_rate_limited_response_pauses_requests() {
+        let rpc = MockRpc::new();
+        let budget = RequestBudget {
+            requests_per_second: 1_000,
+            burst: 10,
+        };
+        let limited = RateLimitedRpc::new(Box::new(rpc.clone()), budget);
+
+        rpc.push_read_fault(ReadFault::RateLimited);
+        let result =
+            limited.get_multiple_accounts_with_commitment(&[], CommitmentConfig::confirmed());
+        assert!(is_rate_limit_error(&result.unwrap_err()));
+
+        let start = Instant::now();
+        limited
+            .get_multiple_accounts_with_commitment(&[], CommitmentConfig::confirmed())
+            .unwrap();
+        assert!(start.elapsed() >= RATE_LIMITED_PAUSE);
+    }
+
+    #[test]
+    fn test_unlimited_budget_does_not_wait() {
+        let rpc = MockRpc::new();
+        let limited = RateLimitedRpc::new(Box::new(rpc.clone()), RequestBudget::unlimited());
+
+        rpc.push_read_fault(ReadFault::RateLimited);
+        let start = Instant::now();
+        for _ in 0..100 {
+            let _ =
+                limited.get_multiple_accounts_with_commitment(&[], CommitmentConfig::confirmed());
+        }
+        assert!(start.elapsed() < RATE_LIMITED_PAUSE);
+    }
+}
@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A signer that can be replaced at runtime, for rotating the maintainer key.
+//!
+//! [`Config`](crate::Config) borrows its signer for as long as the daemon runs,
+//! so to switch to a new key without a restart, the config borrows this wrapper,
+//! and the daemon swaps the signer inside it when it reloads its options.
+
+use std::cell::RefCell;
+
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::{Signer, SignerError};
+
+pub struct ReloadableSigner {
+    inner: RefCell<Box<dyn Signer>>,
+}
+
+impl ReloadableSigner {
+    pub fn new(signer: Box<dyn Signer>) -> ReloadableSigner {
+        ReloadableSigner {
+            inner: RefCell::new(signer),
+        }
+    }
+
+    /// Sign with `signer` from now on, and return the previous signer.
+    pub fn replace(&self, signer: Box<dyn Signer>) -> Box<dyn Signer> {
+        self.inner.replace(signer)
+    }
+}
+
+impl Signer for ReloadableSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        self.inner.borrow().try_pubkey()
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        self.inner.borrow().try_sign_message(message)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.inner.borrow().is_interactive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+
+    #[test]
+    fn test_replace_signs_with_new_key() {
+        let old_key = Keypair::new();
+        let old_pubkey = old_key.pubkey();
+        let new_key = Keypair::new();
+        let new_pubkey = new_key.pubkey();
+        let signer = ReloadableSigner::new(Box::new(old_key));
+        assert_eq!(signer.pubkey(), old_pubkey);
+
+        let previous = signer.replace(Box::new(new_key));
+        assert_eq!(previous.pubkey(), old_pubkey);
+        assert_eq!(signer.pubkey(), new_pubkey);
+        let signature = signer.sign_message(b"maintain");
+        assert!(signature.verify(new_pubkey.as_ref(), b"maintain"));
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A signer that delegates signing to an external service.
+//!
+//! This way, the maintenance daemon does not need to hold the maintainer's
+//! private key in memory; the key can live in e.g. an HSM-backed service that
+//! applies its own policy before it signs. The service is reachable either over
+//! http(s), or over a Unix domain socket on the same machine, see
+//! `--remote-signer-url`.
+//!
+//! The protocol is the same for both transports. Every request is a json
+//! object with a `method`, and every response is a json object:
+//!
+//! * `{"method": "get_pubkey"}` returns `{"pubkey": "<base58>"}`.
+//! * `{"method": "sign_message", "pubkey": "<base58>", "message": "<base58>"}`
+//!   returns `{"signature": "<base58>"}`.
+//!
+//! When the service refuses a request, it returns `{"error": "<reason>"}`.
+//! Over http, requests are POSTed to the url. Over a Unix socket, the client
+//! writes the request as a single line, and the service responds with a single
+//! line, after which the connection is closed.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::{Signer, SignerError};
+
+use crate::error::{CliError, Error};
+
+/// Time after which we consider a request to the signer failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of times we try a request before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Time to wait before the first retry, it doubles for every next retry.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+enum Transport {
+    Http {
+        url: String,
+        client: reqwest::blocking::Client,
+    },
+    Unix {
+        path: PathBuf,
+    },
+}
+
+#[derive(Deserialize)]
+struct Response {
+    pubkey: Option<String>,
+    signature: Option<String>,
+    error: Option<String>,
+}
+
+impl Transport {
+    /// Perform a single request, without retrying.
+    fn request_once(&self, request: &serde_json::Value) -> Result<Response, SignerError> {
+        let body = serde_json::to_vec(request).expect("Requests can always be serialized.");
+        let response_bytes = match self {
+            Transport::Http { url, client } => {
+                let response = client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .send()
+                    .map_err(|err| SignerError::Connection(err.to_string()))?;
+                // A server error may be temporary, so treat it like a failure
+                // to connect, which we retry. Don't use `error_for_status` for
+                // client errors, a signer that refuses to sign may still return
+                // an `error` field that we want to report.
+                if response.status().is_server_error() {
+                    return Err(SignerError::Connection(format!(
+                        "Remote signer responded with status {}.",
+                        response.status()
+                    )));
+                }
+                response
+                    .bytes()
+                    .map_err(|err| SignerError::Connection(err.to_string()))?
+                    .to_vec()
+            }
+            Transport::Unix { path } => {
+                let connection_error =
+                    |err: std::io::Error| SignerError::Connection(err.to_string());
+                let mut stream = UnixStream::connect(path).map_err(connection_error)?;
+                stream
+                    .set_read_timeout(Some(REQUEST_TIMEOUT))
+                    .map_err(connection_error)?;
+                stream
+                    .set_write_timeout(Some(REQUEST_TIMEOUT))
+                    .map_err(connection_error)?;
+                stream.write_all(&body).map_err(connection_error)?;
+                stream.write_all(b"\n").map_err(connection_error)?;
+                let mut line = String::new();
+                BufReader::new(stream)
+                    .read_line(&mut line)
+                    .map_err(connection_error)?;
+                line.into_bytes()
+            }
+        };
+        let response: Response = serde_json::from_slice(&response_bytes).map_err(|err| {
+            SignerError::Protocol(format!("Invalid response from remote signer: {}", err))
+        })?;
+        match response.error {
+            Some(reason) => Err(SignerError::Custom(format!(
+                "Remote signer refused the request: {}",
+                reason
+            ))),
+            None => Ok(response),
+        }
+    }
+
+    /// Perform a request, and retry if we fail to reach the signer.
+    ///
+    /// When the signer responds, but the response is invalid or an error, we
+    /// do not retry, because a retry would likely have the same outcome.
+    fn request(&self, request: &serde_json::Value) -> Result<Response, SignerError> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            match self.request_once(request) {
+                Err(SignerError::Connection(err)) if attempt < MAX_ATTEMPTS => {
+                    eprintln!(
+                        "Failed to reach remote signer (attempt {} of {}), retrying in {:?}: {}",
+                        attempt, MAX_ATTEMPTS, delay, err,
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A signer that sends messages to an external service to sign.
+pub struct RemoteSigner {
+    transport: Transport,
+    pubkey: Pubkey,
+}
+
+impl RemoteSigner {
+    /// Connect to the signer at the given url, and ask it for its public key.
+    ///
+    /// The url is either an `http://` or `https://` url, or `unix://` followed
+    /// by the path of a Unix domain socket.
+    pub fn connect(url: &str) -> Result<RemoteSigner, Error> {
+        let transport = if let Some(path) = url.strip_prefix("unix://") {
+            Transport::Unix {
+                path: PathBuf::from(path),
+            }
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to create http client.");
+            Transport::Http {
+                url: url.to_string(),
+                client,
+            }
+        } else {
+            return Err(CliError::new(
+                "The remote signer url must start with http://, https://, or unix://.",
+            ));
+        };
+
+        let response = transport
+            .request(&json!({ "method": "get_pubkey" }))
+            .map_err(|err| {
+                CliError::with_cause("Failed to get public key from remote signer.", err)
+            })?;
+        let pubkey = response
+            .pubkey
+            .as_deref()
+            .and_then(|pubkey| Pubkey::from_str(pubkey).ok())
+            .ok_or_else(|| CliError::new("Remote signer did not return a valid public key."))?;
+
+        Ok(RemoteSigner { transport, pubkey })
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let response = self.transport.request(&json!({
+            "method": "sign_message",
+            "pubkey": self.pubkey.to_string(),
+            "message": bs58::encode(message).into_string(),
+        }))?;
+        let signature = response
+            .signature
+            .as_deref()
+            .and_then(|signature| Signature::from_str(signature).ok())
+            .ok_or_else(|| {
+                SignerError::Protocol("Remote signer did not return a valid signature.".to_string())
+            })?;
+        // Check the signature here, so a misbehaving signer gets diagnosed as
+        // such, instead of as an invalid transaction.
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(SignerError::Protocol(
+                "Remote signer returned a signature that does not match its public key."
+                    .to_string(),
+            ));
+        }
+        Ok(signature)
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixListener;
+
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+
+    /// Serve `num_requests` requests on the socket, signing with `keypair`.
+    fn serve(listener: UnixListener, keypair: Keypair, num_requests: usize) {
+        for stream in listener.incoming().take(num_requests) {
+            let mut stream = stream.unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+            let response = match request["method"].as_str().unwrap() {
+                "get_pubkey" => json!({ "pubkey": keypair.pubkey().to_string() }),
+                "sign_message" => {
+                    let message = bs58::decode(request["message"].as_str().unwrap())
+                        .into_vec()
+                        .unwrap();
+                    if message == b"refuse" {
+                        json!({ "error": "policy violation" })
+                    } else {
+                        json!({ "signature": keypair.sign_message(&message).to_string() })
+                    }
+                }
+                method => panic!("Unexpected method {}.", method),
+            };
+            writeln!(stream, "{}", response).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remote_signer_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("signer.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let keypair = Keypair::new();
+        let expected_pubkey = keypair.pubkey();
+        let server = std::thread::spawn(move || serve(listener, keypair, 3));
+
+        let url = format!("unix://{}", path.display());
+        let signer = RemoteSigner::connect(&url).unwrap();
+        assert_eq!(signer.pubkey(), expected_pubkey);
+
+        let signature = signer.try_sign_message(b"hello").unwrap();
+        assert!(signature.verify(expected_pubkey.as_ref(), b"hello"));
+
+        match signer.try_sign_message(b"refuse") {
+            Err(SignerError::Custom(reason)) => assert!(reason.contains("policy violation")),
+            other => panic!("Expected the signer to refuse, got {:?}.", other),
+        }
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_remote_signer_rejects_unknown_scheme() {
+        assert!(RemoteSigner::connect("ftp://example.com").is_err());
+    }
+}
@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A per-epoch breakdown of the rewards that Solido earned, and the fees it paid.
+//!
+//! The network pays inflation rewards at the start of the next epoch, and
+//! `getInflationReward` returns them per account. Solido's validators charge a
+//! 100% commission, so most rewards land in the vote accounts, and
+//! `CollectValidatorFee` splits those according to the reward distribution. The
+//! rewards that land in the stake accounts go to stSOL value appreciation
+//! directly. Appreciation relative to the stake that earned it is the growth
+//! of the exchange rate in that epoch, compounding it over a year gives the
+//! realized APY.
+//!
+//! We can only ask for the accounts that Solido has now. Rewards of stake
+//! accounts that were merged or removed since are missing, and the fees follow
+//! the current reward distribution, not the one at the time.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::clock::{Epoch, DEFAULT_MS_PER_SLOT};
+use solana_program::epoch_schedule::EpochSchedule;
+use solana_program::pubkey::Pubkey;
+
+use lido::processor::StakeType;
+use lido::state::{Lido, RewardDistribution};
+use lido::token::{self, Lamports};
+
+use crate::derivation_cache;
+use crate::rpc::InflationReward;
+use crate::snapshot::{Result, Snapshot};
+
+const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+/// Return how many epochs as long as `epoch` fit in a year, at the target slot time.
+pub fn epochs_per_year(epoch_schedule: &EpochSchedule, epoch: Epoch) -> f64 {
+    let epoch_seconds =
+        epoch_schedule.get_slots_in_epoch(epoch) as f64 * DEFAULT_MS_PER_SLOT as f64 / 1000.0;
+    SECONDS_PER_YEAR / epoch_seconds
+}
+
+/// Rewards and fees of a single epoch.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct EpochRewards {
+    pub epoch: Epoch,
+
+    /// Balance of the stake accounts before the rewards were paid.
+    #[serde(rename = "stake_lamports")]
+    pub stake: Lamports,
+
+    /// Rewards paid to the stake accounts and the vote accounts together.
+    #[serde(rename = "gross_rewards_lamports")]
+    pub gross_rewards: Lamports,
+
+    #[serde(rename = "treasury_fee_lamports")]
+    pub treasury_fee: Lamports,
+
+    #[serde(rename = "developer_fee_lamports")]
+    pub developer_fee: Lamports,
+
+    /// The fee of all validators together.
+    #[serde(rename = "validation_fee_lamports")]
+    pub validation_fee: Lamports,
+
+    /// Rewards that increase the value of stSOL, gross rewards minus fees.
+    #[serde(rename = "st_sol_appreciation_lamports")]
+    pub st_sol_appreciation: Lamports,
+
+    /// Appreciation relative to the stake, compounded over a year.
+    pub apy: f64,
+}
+
+impl EpochRewards {
+    /// Break down the rewards that the stake accounts and vote accounts received.
+    pub fn new(
+        epoch: Epoch,
+        reward_distribution: &RewardDistribution,
+        num_validators: u64,
+        epochs_per_year: f64,
+        stake_rewards: &[Option<InflationReward>],
+        vote_rewards: &[Option<InflationReward>],
+    ) -> token::Result<EpochRewards> {
+        let stake: token::Result<Lamports> = stake_rewards
+            .iter()
+            .flatten()
+            .map(|r| Lamports(r.post_balance - r.amount))
+            .sum();
+        let stake_rewards: token::Result<Lamports> = stake_rewards
+            .iter()
+            .flatten()
+            .map(|r| Lamports(r.amount))
+            .sum();
+        let vote_rewards: token::Result<Lamports> = vote_rewards
+            .iter()
+            .flatten()
+            .map(|r| Lamports(r.amount))
+            .sum();
+        let (stake, stake_rewards, vote_rewards) = (stake?, stake_rewards?, vote_rewards?);
+
+        // Without validators there are no vote accounts, and nothing to split.
+        let (treasury_fee, developer_fee, validation_fee, vote_appreciation) =
+            if num_validators == 0 {
+                (Lamports(0), Lamports(0), Lamports(0), vote_rewards)
+            } else {
+                let fees = reward_distribution.split_reward(vote_rewards, num_validators)?;
+                (
+                    fees.treasury_amount,
+                    fees.developer_amount,
+                    (fees.reward_per_validator * num_validators)?,
+                    fees.st_sol_appreciation_amount,
+                )
+            };
+        let st_sol_appreciation = (stake_rewards + vote_appreciation)?;
+
+        let apy = if stake == Lamports(0) {
+            0.0
+        } else {
+            let growth = st_sol_appreciation.0 as f64 / stake.0 as f64;
+            (1.0 + growth).powf(epochs_per_year) - 1.0
+        };
+
+        Ok(EpochRewards {
+            epoch,
+            stake,
+            gross_rewards: (stake_rewards + vote_rewards)?,
+            treasury_fee,
+            developer_fee,
+            validation_fee,
+            st_sol_appreciation,
+            apy,
+        })
+    }
+}
+
+/// Rewards of Solido over a range of past epochs.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RewardsReport {
+    /// The completed epochs, oldest first.
+    pub epochs: Vec<EpochRewards>,
+}
+
+impl RewardsReport {
+    /// Read the rewards of the last `num_epochs` completed epochs.
+    pub fn new(
+        snapshot: &mut Snapshot,
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+        solido: &Lido,
+        num_epochs: u64,
+    ) -> Result<RewardsReport> {
+        let clock = snapshot.get_clock()?;
+        let epoch_schedule = snapshot.get_epoch_schedule()?;
+
+        let vote_accounts: Vec<Pubkey> =
+            solido.validators.entries.iter().map(|v| v.pubkey).collect();
+        let mut stake_accounts = Vec::new();
+        for validator in solido.validators.entries.iter() {
+            let seeds = [
+                (&validator.entry.stake_seeds, StakeType::Stake),
+                (&validator.entry.unstake_seeds, StakeType::Unstake),
+            ];
+            for &(seeds, stake_type) in seeds.iter() {
+                for seed in seeds {
+                    let (address, _bump_seed) = derivation_cache::find_stake_account_address(
+                        &validator.pubkey,
+                        solido_program_id,
+                        solido_address,
+                        seed,
+                        stake_type,
+                    );
+                    stake_accounts.push(address);
+                }
+            }
+        }
+
+        // The rewards of an epoch are paid when the next epoch starts, so the
+        // current epoch has no rewards yet.
+        let first_epoch = clock.epoch.saturating_sub(num_epochs);
+        let mut epochs = Vec::new();
+        for epoch in first_epoch..clock.epoch {
+            let stake_rewards = snapshot.get_inflation_reward(&stake_accounts, epoch)?;
+            let vote_rewards = snapshot.get_inflation_reward(&vote_accounts, epoch)?;
+            let rewards = EpochRewards::new(
+                epoch,
+                &solido.reward_distribution,
+                solido.validators.len() as u64,
+                epochs_per_year(&epoch_schedule, epoch),
+                &stake_rewards,
+                &vote_rewards,
+            )
+            .expect("The rewards of an epoch do not overflow a u64.");
+            epochs.push(rewards);
+        }
+
+        Ok(RewardsReport { epochs })
+    }
+}
+
+impl fmt::Display for RewardsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>22} {:>18} {:>18} {:>18} {:>18} {:>18} {:>8}",
+            "Epoch",
+            "Stake",
+            "Gross rewards",
+            "Treasury fee",
+            "Developer fee",
+            "Validation fee",
+            "Appreciation",
+            "APY",
+        )?;
+        for e in &self.epochs {
+            writeln!(
+                f,
+                "{:>6} {:>22} {:>18} {:>18} {:>18} {:>18} {:>18} {:>7.2}%",
+                e.epoch,
+                e.stake.to_string(),
+                e.gross_rewards.to_string(),
+                e.treasury_fee.to_string(),
+                e.developer_fee.to_string(),
+                e.validation_fee.to_string(),
+                e.st_sol_appreciation.to_string(),
+                e.apy * 100.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::clock::Clock;
+
+    use lido::state::{SeedRange, Validator};
+
+    use super::*;
+    use crate::mock_rpc::new_solido_fixture;
+    use crate::snapshot::SnapshotClient;
+
+    fn reward(epoch: Epoch, amount: u64, post_balance: u64) -> Option<InflationReward> {
+        Some(InflationReward {
+            epoch,
+            amount,
+            post_balance,
+        })
+    }
+
+    #[test]
+    fn test_epoch_rewards_splits_vote_account_rewards() {
+        let reward_distribution = RewardDistribution {
+            treasury_fee: 4,
+            validation_fee: 5,
+            developer_fee: 1,
+            st_sol_appreciation: 90,
+        };
+        let stake_rewards = [reward(7, 100, 1_000_100), None, reward(7, 0, 500_000)];
+        let vote_rewards = [reward(7, 1_000, 1_001_000), reward(7, 1_000, 2_000_000)];
+        let rewards = EpochRewards::new(
+            7,
+            &reward_distribution,
+            2,
+            1.0,
+            &stake_rewards,
+            &vote_rewards,
+        )
+        .unwrap();
+
+        assert_eq!(rewards.stake, Lamports(1_500_000));
+        assert_eq!(rewards.gross_rewards, Lamports(2_100));
+        assert_eq!(rewards.treasury_fee, Lamports(80));
+        assert_eq!(rewards.developer_fee, Lamports(20));
+        assert_eq!(rewards.validation_fee, Lamports(100));
+        // The stake account rewards, and 90% of the vote account rewards.
+        assert_eq!(rewards.st_sol_appreciation, Lamports(1_900));
+        // With one epoch per year, the APY is the growth in that epoch.
+        assert!((rewards.apy - 1_900.0 / 1_500_000.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_epoch_rewards_without_stake() {
+        let rewards =
+            EpochRewards::new(7, &RewardDistribution::default(), 0, 180.0, &[], &[]).unwrap();
+        assert_eq!(rewards.gross_rewards, Lamports(0));
+        assert_eq!(rewards.apy, 0.0);
+    }
+
+    #[test]
+    fn test_rewards_report_covers_completed_epochs() {
+        let mut fixture = new_solido_fixture(&Pubkey::new_unique());
+        fixture.solido.reward_distribution = RewardDistribution {
+            treasury_fee: 5,
+            validation_fee: 3,
+            developer_fee: 2,
+            st_sol_appreciation: 90,
+        };
+        let vote_account = Pubkey::new_unique();
+        fixture
+            .solido
+            .validators
+            .add(
+                vote_account,
+                Validator {
+                    stake_seeds: SeedRange { begin: 0, end: 1 },
+                    ..Validator::new(Pubkey::new_unique())
+                },
+            )
+            .unwrap();
+        fixture.store_solido();
+        fixture.set_clock(&Clock {
+            epoch: 3,
+            ..Clock::default()
+        });
+
+        let validator = &fixture.solido.validators.entries[0];
+        let (stake_account, _) = validator.find_stake_account_address(
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            0,
+            StakeType::Stake,
+        );
+        for epoch in 1..3 {
+            fixture
+                .rpc
+                .set_inflation_reward(vote_account, reward(epoch, 1_000, 0).unwrap());
+        }
+        fixture
+            .rpc
+            .set_inflation_reward(stake_account, reward(2, 0, 1_000_000_000).unwrap());
+
+        let mut client = SnapshotClient::new(fixture.rpc.clone());
+        let report = client
+            .with_snapshot(|mut snapshot| {
+                RewardsReport::new(
+                    &mut snapshot,
+                    &fixture.solido_program_id,
+                    &fixture.solido_address,
+                    &fixture.solido,
+                    5,
+                )
+            })
+            .unwrap();
+
+        // Epoch 3 is still in progress, and there is no epoch before 0.
+        let epochs: Vec<Epoch> = report.epochs.iter().map(|e| e.epoch).collect();
+        assert_eq!(epochs, vec![0, 1, 2]);
+        assert_eq!(report.epochs[0].gross_rewards, Lamports(0));
+        assert_eq!(report.epochs[1].treasury_fee, Lamports(50));
+        assert_eq!(report.epochs[1].validation_fee, Lamports(30));
+        assert_eq!(report.epochs[1].developer_fee, Lamports(20));
+        assert_eq!(report.epochs[2].stake, Lamports(1_000_000_000));
+        assert_eq!(report.epochs[2].st_sol_appreciation, Lamports(900));
+        assert!(report.epochs[2].apy > 0.0);
+    }
+}
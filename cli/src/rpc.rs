@@ -0,0 +1,588 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! The subset of the Solana RPC that the CLI and daemon depend on.
+//!
+//! [`SnapshotClient`](crate::snapshot::SnapshotClient) talks to the network
+//! only through this trait, so we can substitute the real [`RpcClient`] with a
+//! different implementation, for example a scripted mock in tests.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::json;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSendTransactionConfig,
+};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_response::{Response, RpcResult, RpcSimulateTransactionResult};
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Epoch, Slot, UnixTimestamp};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction};
+
+/// The commitment level at which we read accounts and confirm transactions.
+///
+/// `Processed` has the lowest latency, but the state may be rolled back.
+/// `Finalized` is the safest, but lags about 30 slots behind.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl Default for Commitment {
+    fn default() -> Commitment {
+        Commitment::Confirmed
+    }
+}
+
+impl Commitment {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+
+    pub fn to_config(self) -> CommitmentConfig {
+        match self {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+impl FromStr for Commitment {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Commitment, &'static str> {
+        match s {
+            "processed" => Ok(Commitment::Processed),
+            "confirmed" => Ok(Commitment::Confirmed),
+            "finalized" => Ok(Commitment::Finalized),
+            _ => Err("Invalid commitment, expected 'processed', 'confirmed', or 'finalized'."),
+        }
+    }
+}
+
+/// The snapshot client calls `GetMultipleAccounts` from multiple threads at
+/// once, therefore implementations must be `Sync`.
+pub trait Rpc: Sync {
+    /// Call `GetMultipleAccounts` at the given commitment level.
+    ///
+    /// Unlike [`RpcClient::get_multiple_accounts`], this returns the response
+    /// context too, which holds the slot at which the accounts were read.
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>>;
+
+    /// Simulate a transaction, without submitting it to the cluster.
+    fn simulate_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult>;
+
+    /// Submit a transaction to the cluster, without waiting for it to land.
+    ///
+    /// Unless `skip_preflight` is set, the RPC node first simulates the
+    /// transaction at the given commitment level, and returns an error if the
+    /// simulation fails. We skip that when we broadcast a transaction again.
+    fn send_transaction(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+        skip_preflight: bool,
+    ) -> ClientResult<Signature>;
+
+    /// Return the result of the transaction, or `None` if it did not reach the
+    /// given commitment level (yet).
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>>;
+
+    /// Return whether transactions with this recent blockhash can still land.
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> ClientResult<bool>;
+
+    /// Return the most recent blockhash, at the `processed` commitment level.
+    fn get_latest_blockhash(&self) -> ClientResult<Hash>;
+
+    /// Send a transaction and wait for it to reach the given commitment level,
+    /// and show a spinner on stdout.
+    ///
+    /// Without a spinner, use [`send_and_confirm_transaction`](crate::transaction_tracker::send_and_confirm_transaction).
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Signature>;
+
+    /// Call `getRecentPrioritizationFees`, and return the fee per recent slot,
+    /// in micro-lamports per compute unit, for transactions that write to all
+    /// of the given accounts.
+    fn get_recent_prioritization_fees(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>>;
+
+    /// Call `getProgramAccounts`, and return the accounts owned by `program_id`
+    /// that match all of the `filters`.
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>>;
+
+    /// Call `getInflationReward`, and return the inflation reward that every
+    /// account received for `epoch`, or `None` if it received none.
+    fn get_inflation_reward(
+        &self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>>;
+
+    /// Call `getVoteAccounts`, and return the activated stake in lamports of
+    /// every vote account, current and delinquent.
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>>;
+
+    /// Call `getBlockProduction`, and return the leader slots and produced
+    /// blocks of every validator identity in the current epoch so far.
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>>;
+
+    /// Call `getSignaturesForAddress`, and return at most `limit` of the most
+    /// recent transactions that involve `address`, newest first.
+    ///
+    /// When `before` is set, start at the transaction before that signature
+    /// rather than at the most recent one. When `until` is set, stop before
+    /// reaching that signature.
+    fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+        limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>>;
+
+    /// Call `getTransaction`, and return the confirmed transaction with the
+    /// given signature, or `None` if the node does not know it.
+    fn get_transaction(&self, signature: &str) -> ClientResult<Option<TransactionInfo>>;
+}
+
+/// A filter for `getProgramAccounts`.
+///
+/// Like [`RpcFilterType`], but we can also apply it ourselves, to accounts
+/// that we read in a different way.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AccountFilter {
+    /// The account data is exactly this many bytes long.
+    DataSize(u64),
+
+    /// The account data contains `bytes` at `offset`.
+    Memcmp { offset: usize, bytes: Vec<u8> },
+}
+
+impl AccountFilter {
+    /// Return whether the account matches the filter.
+    pub fn matches(&self, account: &Account) -> bool {
+        match self {
+            AccountFilter::DataSize(size) => account.data.len() as u64 == *size,
+            AccountFilter::Memcmp { offset, bytes } => {
+                account.data.get(*offset..*offset + bytes.len()) == Some(&bytes[..])
+            }
+        }
+    }
+
+    fn to_rpc_filter(&self) -> RpcFilterType {
+        match self {
+            AccountFilter::DataSize(size) => RpcFilterType::DataSize(*size),
+            AccountFilter::Memcmp { offset, bytes } => RpcFilterType::Memcmp(Memcmp {
+                offset: *offset,
+                bytes: MemcmpEncodedBytes::Binary(bs58::encode(bytes).into_string()),
+                encoding: None,
+            }),
+        }
+    }
+}
+
+/// An element of the `getRecentPrioritizationFees` response.
+///
+/// The version of `solana-client` that we use predates this call, so we
+/// define the response type ourselves.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcPrioritizationFee {
+    prioritization_fee: u64,
+}
+
+/// An element of the `getInflationReward` response.
+///
+/// We define the type ourselves rather than using the one of `solana-client`,
+/// so the mock can construct it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct InflationReward {
+    /// The epoch for which the reward was paid, at the start of the next epoch.
+    pub epoch: Epoch,
+
+    /// The reward in lamports.
+    pub amount: u64,
+
+    /// The balance of the account after the reward was paid.
+    pub post_balance: u64,
+}
+
+/// The block production of a validator identity, from `getBlockProduction`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockProduction {
+    /// The number of slots in which the validator was leader.
+    pub leader_slots: u64,
+
+    /// The number of those slots in which the validator produced a block.
+    pub blocks_produced: u64,
+}
+
+impl BlockProduction {
+    /// Return the fraction of leader slots that the validator skipped, or
+    /// `None` if it was not leader yet.
+    pub fn skip_rate(&self) -> Option<f64> {
+        if self.leader_slots == 0 {
+            None
+        } else {
+            let skipped = self.leader_slots.saturating_sub(self.blocks_produced);
+            Some(skipped as f64 / self.leader_slots as f64)
+        }
+    }
+}
+
+/// The `getBlockProduction` response, without the slot range.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcBlockProduction {
+    /// Leader slots and blocks produced, per base58-encoded identity.
+    by_identity: HashMap<String, (u64, u64)>,
+}
+
+/// An element of the `getSignaturesForAddress` response.
+///
+/// Like [`InflationReward`], we define the type ourselves so the mock can
+/// construct it.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureInfo {
+    /// The transaction signature, base58-encoded.
+    pub signature: String,
+
+    pub slot: Slot,
+
+    /// The error of the transaction if it failed, `None` if it succeeded.
+    pub err: Option<serde_json::Value>,
+
+    /// Estimated production time of the block, as Unix timestamp, if known.
+    pub block_time: Option<UnixTimestamp>,
+}
+
+/// A balance in the `preTokenBalances` or `postTokenBalances` of a transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenBalance {
+    /// Index of the token account in the account keys of the transaction.
+    pub account_index: usize,
+
+    /// The balance in the smallest unit of the token.
+    pub amount: u64,
+}
+
+/// A confirmed transaction and its status, from the `getTransaction` response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionInfo {
+    pub slot: Slot,
+
+    /// Estimated production time of the block, as Unix timestamp, if known.
+    pub block_time: Option<UnixTimestamp>,
+
+    pub transaction: Transaction,
+
+    /// The error of the transaction if it failed, `None` if it succeeded.
+    pub err: Option<serde_json::Value>,
+
+    /// Balances in lamports of the accounts of the transaction, by account index.
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+
+    /// Balances of the token accounts of the transaction.
+    pub pre_token_balances: Vec<TokenBalance>,
+    pub post_token_balances: Vec<TokenBalance>,
+}
+
+/// The `getTransaction` response with `base64` encoding, as it is on the wire.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcTransaction {
+    slot: Slot,
+    block_time: Option<UnixTimestamp>,
+    /// The encoded transaction, and the name of the encoding.
+    transaction: (String, String),
+    meta: Option<RpcTransactionMeta>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcTransactionMeta {
+    err: Option<serde_json::Value>,
+    pre_balances: Vec<u64>,
+    post_balances: Vec<u64>,
+    // Nodes that do not record token balances return null.
+    pre_token_balances: Option<Vec<RpcTokenBalance>>,
+    post_token_balances: Option<Vec<RpcTokenBalance>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcTokenBalance {
+    account_index: usize,
+    ui_token_amount: RpcTokenAmount,
+}
+
+#[derive(Deserialize)]
+struct RpcTokenAmount {
+    /// The raw amount, as a string because it may not fit a json number.
+    amount: String,
+}
+
+fn invalid_response_error(message: String) -> ClientError {
+    ClientErrorKind::Custom(format!("Invalid getTransaction response: {}", message)).into()
+}
+
+impl RpcTokenBalance {
+    fn decode(&self) -> ClientResult<TokenBalance> {
+        Ok(TokenBalance {
+            account_index: self.account_index,
+            amount: self
+                .ui_token_amount
+                .amount
+                .parse()
+                .map_err(|err| invalid_response_error(format!("token amount: {}", err)))?,
+        })
+    }
+}
+
+impl RpcTransaction {
+    fn decode(self) -> ClientResult<TransactionInfo> {
+        let meta = self
+            .meta
+            .ok_or_else(|| invalid_response_error("the transaction status is missing.".into()))?;
+        let bytes = base64::decode(&self.transaction.0)
+            .map_err(|err| invalid_response_error(format!("transaction: {}", err)))?;
+        let transaction = bincode::deserialize(&bytes)
+            .map_err(|err| invalid_response_error(format!("transaction: {}", err)))?;
+        let decode_balances = |balances: Option<Vec<RpcTokenBalance>>| {
+            balances
+                .unwrap_or_default()
+                .iter()
+                .map(RpcTokenBalance::decode)
+                .collect::<ClientResult<Vec<TokenBalance>>>()
+        };
+        Ok(TransactionInfo {
+            slot: self.slot,
+            block_time: self.block_time,
+            transaction,
+            err: meta.err,
+            pre_balances: meta.pre_balances,
+            post_balances: meta.post_balances,
+            pre_token_balances: decode_balances(meta.pre_token_balances)?,
+            post_token_balances: decode_balances(meta.post_token_balances)?,
+        })
+    }
+}
+
+impl Rpc for RpcClient {
+    fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        commitment: CommitmentConfig,
+    ) -> RpcResult<Vec<Option<Account>>> {
+        RpcClient::get_multiple_accounts_with_commitment(self, pubkeys, commitment)
+    }
+
+    fn simulate_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> RpcResult<RpcSimulateTransactionResult> {
+        RpcClient::simulate_transaction(self, transaction)
+    }
+
+    fn send_transaction(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+        skip_preflight: bool,
+    ) -> ClientResult<Signature> {
+        let config = RpcSendTransactionConfig {
+            skip_preflight,
+            preflight_commitment: Some(commitment.commitment),
+            ..RpcSendTransactionConfig::default()
+        };
+        self.send_transaction_with_config(transaction, config)
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        self.get_signature_status_with_commitment(signature, commitment)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> ClientResult<bool> {
+        // The node only knows the fee calculator of blockhashes that did not expire.
+        let fee_calculator = self
+            .get_fee_calculator_for_blockhash_with_commitment(
+                blockhash,
+                CommitmentConfig::processed(),
+            )?
+            .value;
+        Ok(fee_calculator.is_some())
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(self
+            .get_recent_blockhash_with_commitment(CommitmentConfig::processed())?
+            .value
+            .0)
+    }
+
+    fn send_and_confirm_transaction_with_spinner(
+        &self,
+        transaction: &Transaction,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Signature> {
+        RpcClient::send_and_confirm_transaction_with_spinner_and_commitment(
+            self,
+            transaction,
+            commitment,
+        )
+    }
+
+    fn get_recent_prioritization_fees(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<u64>> {
+        let addresses: Vec<String> = pubkeys.iter().map(|pubkey| pubkey.to_string()).collect();
+        let fees: Vec<RpcPrioritizationFee> = self.send(
+            RpcRequest::Custom {
+                method: "getRecentPrioritizationFees",
+            },
+            json!([addresses]),
+        )?;
+        Ok(fees.iter().map(|fee| fee.prioritization_fee).collect())
+    }
+
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters.iter().map(AccountFilter::to_rpc_filter).collect()),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        RpcClient::get_program_accounts_with_config(self, program_id, config)
+    }
+
+    fn get_inflation_reward(
+        &self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> ClientResult<Vec<Option<InflationReward>>> {
+        let addresses: Vec<String> = pubkeys.iter().map(|pubkey| pubkey.to_string()).collect();
+        self.send(
+            RpcRequest::Custom {
+                method: "getInflationReward",
+            },
+            json!([addresses, { "epoch": epoch }]),
+        )
+    }
+
+    fn get_vote_account_stakes(&self) -> ClientResult<Vec<(Pubkey, u64)>> {
+        let status = RpcClient::get_vote_accounts(self)?;
+        Ok(status
+            .current
+            .iter()
+            .chain(status.delinquent.iter())
+            // The node returns valid pubkeys, skip anything else rather than fail.
+            .filter_map(|vote_account| {
+                Pubkey::from_str(&vote_account.vote_pubkey)
+                    .ok()
+                    .map(|pubkey| (pubkey, vote_account.activated_stake))
+            })
+            .collect())
+    }
+
+    fn get_block_production(&self) -> ClientResult<Vec<(Pubkey, BlockProduction)>> {
+        let response: Response<RpcBlockProduction> = self.send(
+            RpcRequest::Custom {
+                method: "getBlockProduction",
+            },
+            json!([]),
+        )?;
+        Ok(response
+            .value
+            .by_identity
+            .iter()
+            // Like for `getVoteAccounts`, skip anything that is not a pubkey.
+            .filter_map(|(identity, &(leader_slots, blocks_produced))| {
+                Pubkey::from_str(identity).ok().map(|pubkey| {
+                    let production = BlockProduction {
+                        leader_slots,
+                        blocks_produced,
+                    };
+                    (pubkey, production)
+                })
+            })
+            .collect())
+    }
+
+    fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+        limit: usize,
+    ) -> ClientResult<Vec<SignatureInfo>> {
+        let mut config = json!({ "limit": limit });
+        if let Some(before) = before {
+            config["before"] = json!(before);
+        }
+        if let Some(until) = until {
+            config["until"] = json!(until);
+        }
+        self.send(
+            RpcRequest::Custom {
+                method: "getSignaturesForAddress",
+            },
+            json!([address.to_string(), config]),
+        )
+    }
+
+    fn get_transaction(&self, signature: &str) -> ClientResult<Option<TransactionInfo>> {
+        let response: Option<RpcTransaction> = self.send(
+            RpcRequest::Custom {
+                method: "getTransaction",
+            },
+            json!([signature, { "encoding": "base64" }]),
+        )?;
+        response.map(RpcTransaction::decode).transpose()
+    }
+}
@@ -0,0 +1,490 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Deterministic simulation of the maintenance daemon.
+//!
+//! The simulation runs the daemon's iterations against a [`MockRpc`], with a
+//! fake wall clock, and it controls the clock sysvar, so tests can fast-forward
+//! across epoch boundaries without waiting for real epochs. The mock does not
+//! execute transactions, so the simulation applies the effect of the
+//! maintenance that the daemon performed to its own model of the on-chain
+//! state. Only the operations that the simulation needs are modelled.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use solana_program::stake::state::{Delegation, Meta, Stake, StakeState};
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Clock, Epoch, DEFAULT_MS_PER_SLOT, DEFAULT_SLOTS_PER_EPOCH};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+
+use lido::processor::StakeType;
+use lido::state::Validator;
+use lido::token::Lamports;
+
+use crate::config::OutputMode;
+use crate::daemon::{run_iteration, MaintenanceMetrics, WallClock};
+use crate::maintenance::{MaintenanceOptions, MaintenanceOutput, SolidoState};
+use crate::mock_rpc::{new_solido_fixture, SolidoFixture};
+use crate::snapshot::SnapshotClient;
+use crate::{Config, SnapshotClientConfig};
+
+/// A wall clock that only moves when the simulation advances it.
+pub struct FakeClock {
+    now: Cell<SystemTime>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock {
+            now: Cell::new(SystemTime::UNIX_EPOCH),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl WallClock for FakeClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        // Sleeping in the simulation returns immediately, the time just jumps.
+        self.advance(duration);
+    }
+}
+
+/// Wall-clock duration of an epoch, when every slot takes the target slot time.
+fn epoch_duration() -> Duration {
+    Duration::from_millis(DEFAULT_SLOTS_PER_EPOCH * DEFAULT_MS_PER_SLOT)
+}
+
+/// Model of a delegated stake account, which we serialize into the mock.
+struct SimStakeAccount {
+    validator_vote_account: Pubkey,
+    lamports: u64,
+    stake: u64,
+    activation_epoch: Epoch,
+}
+
+pub struct Simulation<'a> {
+    pub fixture: SolidoFixture,
+    pub wall_clock: FakeClock,
+    config: SnapshotClientConfig<'a>,
+    metrics: MaintenanceMetrics,
+    epoch: Epoch,
+    rent: Rent,
+    reserve_lamports: u64,
+    stake_accounts: HashMap<Pubkey, SimStakeAccount>,
+}
+
+impl<'a> Simulation<'a> {
+    /// Upper bound on the number of maintenance operations in one call to
+    /// [`Simulation::run_until_idle`], to fail tests that loop forever.
+    const MAX_ITERATIONS: usize = 100;
+
+    /// Simulate a Solido instance without validators, starting in epoch 1.
+    pub fn new(maintainer: &'a Keypair) -> Simulation<'a> {
+        let fixture = new_solido_fixture(&maintainer.pubkey());
+        let config = Config {
+            client: SnapshotClient::new(fixture.rpc.clone()),
+            signer: maintainer,
+            fee_payer: maintainer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: None,
+        };
+        let rent = Rent::default();
+        Simulation {
+            fixture,
+            wall_clock: FakeClock::new(),
+            config,
+            metrics: MaintenanceMetrics::default(),
+            epoch: 1,
+            rent,
+            reserve_lamports: rent.minimum_balance(0),
+            stake_accounts: HashMap::new(),
+        }
+    }
+
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// Read the state like the daemon would, without performing maintenance.
+    pub fn read_state(&mut self) -> SolidoState {
+        let solido_program_id = self.fixture.solido_program_id;
+        let solido_address = self.fixture.solido_address;
+        let mut state = self
+            .config
+            .with_snapshot(|config| SolidoState::new(config, &solido_program_id, &solido_address))
+            .expect("Failed to read the simulated state.");
+        state.produced_at = self.wall_clock.now();
+        state
+    }
+
+    /// Start the next epoch, and advance the wall clock by one epoch.
+    pub fn advance_epoch(&mut self) {
+        self.epoch += 1;
+        self.wall_clock.advance(epoch_duration());
+        let unix_timestamp = self
+            .wall_clock
+            .now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("The fake clock starts at the Unix epoch.")
+            .as_secs();
+        self.fixture.set_clock(&Clock {
+            slot: self.epoch * DEFAULT_SLOTS_PER_EPOCH,
+            epoch: self.epoch,
+            leader_schedule_epoch: self.epoch + 1,
+            unix_timestamp: unix_timestamp as i64,
+            ..Clock::default()
+        });
+    }
+
+    /// Add an active validator with a vote account that holds no rewards.
+    pub fn add_validator(&mut self) -> Pubkey {
+        let vote_account = Pubkey::new_unique();
+        self.fixture.rpc.set_account(
+            vote_account,
+            Account {
+                lamports: self.rent.minimum_balance(0),
+                data: Vec::new(),
+                owner: solana_vote_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        self.fixture.solido.validators.maximum_entries += 1;
+        self.fixture
+            .solido
+            .validators
+            .add(vote_account, Validator::new(Pubkey::new_unique()))
+            .expect("We just made room for the validator.");
+        self.fixture.store_solido();
+        vote_account
+    }
+
+    /// Create a stake account at the validator's end seed, activated in the current epoch.
+    ///
+    /// This models the effect of a `StakeDeposit` that did not merge.
+    pub fn add_stake_account(&mut self, validator_vote_account: &Pubkey, amount: u64) -> Pubkey {
+        let validator = self
+            .fixture
+            .solido
+            .validators
+            .get_mut(validator_vote_account)
+            .expect("Validator must exist.");
+        let (address, _bump_seed) = validator.find_stake_account_address(
+            &self.fixture.solido_program_id,
+            &self.fixture.solido_address,
+            validator.entry.stake_seeds.end,
+            StakeType::Stake,
+        );
+        validator.entry.stake_seeds.end += 1;
+        validator.entry.stake_accounts_balance = (validator.entry.stake_accounts_balance
+            + Lamports(amount))
+        .expect("Test amounts do not overflow.");
+
+        let rent_exempt_reserve = self.rent.minimum_balance(StakeState::size_of());
+        self.stake_accounts.insert(
+            address,
+            SimStakeAccount {
+                validator_vote_account: *validator_vote_account,
+                lamports: amount,
+                stake: amount - rent_exempt_reserve,
+                activation_epoch: self.epoch,
+            },
+        );
+        self.store_stake_account(&address);
+        self.fixture.store_solido();
+        address
+    }
+
+    /// Transfer lamports to a stake account, as some joker might do.
+    pub fn donate(&mut self, stake_account: &Pubkey, amount: u64) {
+        self.stake_accounts
+            .get_mut(stake_account)
+            .expect("Stake account must exist.")
+            .lamports += amount;
+        self.store_stake_account(stake_account);
+    }
+
+    fn store_stake_account(&self, address: &Pubkey) {
+        let account = &self.stake_accounts[address];
+        let rent_exempt_reserve = self.rent.minimum_balance(StakeState::size_of());
+        let state = StakeState::Stake(
+            Meta {
+                rent_exempt_reserve,
+                ..Meta::default()
+            },
+            Stake {
+                delegation: Delegation {
+                    voter_pubkey: account.validator_vote_account,
+                    stake: account.stake,
+                    activation_epoch: account.activation_epoch,
+                    ..Delegation::default()
+                },
+                credits_observed: 0,
+            },
+        );
+        let mut data = vec![0; StakeState::size_of()];
+        bincode::serialize_into(&mut data[..], &state).expect("Stake state fits in the account.");
+        self.fixture.rpc.set_account(
+            *address,
+            Account {
+                lamports: account.lamports,
+                data,
+                owner: solana_program::stake::program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+
+    fn store_reserve(&self) {
+        self.fixture.rpc.set_account(
+            self.fixture.reserve_address,
+            Account {
+                lamports: self.reserve_lamports,
+                data: Vec::new(),
+                owner: solana_sdk::system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+
+    /// Run daemon iterations until there is no more maintenance to perform.
+    ///
+    /// Returns the maintenance performed, in order. Panics if an iteration fails.
+    pub fn run_until_idle(&mut self) -> Vec<MaintenanceOutput> {
+        let mut outputs = Vec::new();
+        for _ in 0..Self::MAX_ITERATIONS {
+            let outcome = run_iteration(
+                &mut self.config,
+                &self.wall_clock,
+                &self.fixture.solido_program_id,
+                &self.fixture.solido_address,
+                &MaintenanceOptions::default(),
+                None,
+                None,
+                None,
+                &mut self.metrics,
+            );
+            assert!(outcome.solido.is_some(), "Maintenance iteration failed.");
+            match outcome.maintenance_output {
+                Some(output) => {
+                    self.apply(&output);
+                    outputs.push(output);
+                }
+                None => {
+                    // Like the daemon, wait before we poll again. The fake clock
+                    // does not block, so use the maximum interval.
+                    self.wall_clock.sleep(Duration::from_secs(120));
+                    return outputs;
+                }
+            }
+        }
+        panic!(
+            "Daemon did not become idle after {} iterations.",
+            Self::MAX_ITERATIONS
+        );
+    }
+
+    /// Apply the effect that a maintenance transaction would have on-chain.
+    fn apply(&mut self, output: &MaintenanceOutput) {
+        match output {
+            MaintenanceOutput::UpdateExchangeRate => {
+                self.fixture.solido.exchange_rate.computed_in_epoch = self.epoch;
+            }
+            MaintenanceOutput::MergeStake {
+                validator_vote_account,
+                to_stake,
+                ..
+            } => {
+                let from_stakes = output.get_closed_accounts(
+                    &self.fixture.solido_program_id,
+                    &self.fixture.solido_address,
+                );
+                for from_stake in &from_stakes {
+                    let from = self
+                        .stake_accounts
+                        .remove(from_stake)
+                        .expect("Merge source must exist.");
+                    let to = self
+                        .stake_accounts
+                        .get_mut(to_stake)
+                        .expect("Merge destination must exist.");
+                    to.lamports += from.lamports;
+                    to.stake += from.stake;
+                    self.fixture.rpc.remove_account(from_stake);
+                }
+                self.store_stake_account(to_stake);
+                self.fixture
+                    .solido
+                    .validators
+                    .get_mut(validator_vote_account)
+                    .expect("Validator must exist.")
+                    .entry
+                    .stake_seeds
+                    .begin += from_stakes.len() as u64;
+            }
+            MaintenanceOutput::WithdrawInactiveStake {
+                validator_vote_account,
+                ..
+            } => {
+                // Withdraw everything except the stake and the rent-exempt
+                // reserve, and track the remaining balance, like the program does.
+                let rent_exempt_reserve = self.rent.minimum_balance(StakeState::size_of());
+                let mut remaining = 0;
+                let addresses: Vec<Pubkey> = self
+                    .stake_accounts
+                    .iter()
+                    .filter(|(_, account)| {
+                        account.validator_vote_account == *validator_vote_account
+                    })
+                    .map(|(address, _)| *address)
+                    .collect();
+                for address in addresses {
+                    let account = self.stake_accounts.get_mut(&address).unwrap();
+                    let excess = account.lamports - account.stake - rent_exempt_reserve;
+                    account.lamports -= excess;
+                    remaining += account.lamports;
+                    self.reserve_lamports += excess;
+                    self.store_stake_account(&address);
+                }
+                self.store_reserve();
+                self.fixture
+                    .solido
+                    .validators
+                    .get_mut(validator_vote_account)
+                    .expect("Validator must exist.")
+                    .entry
+                    .stake_accounts_balance = Lamports(remaining);
+            }
+            unsupported => panic!("The simulation does not model {:?}.", unsupported),
+        }
+        self.fixture.store_solido();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_merge(output: &MaintenanceOutput) -> bool {
+        matches!(output, MaintenanceOutput::MergeStake { .. })
+    }
+
+    #[test]
+    fn test_updates_exchange_rate_once_per_epoch() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+
+        for epoch in 1..5 {
+            assert_eq!(sim.epoch(), epoch);
+            assert_eq!(
+                sim.run_until_idle(),
+                vec![MaintenanceOutput::UpdateExchangeRate]
+            );
+            // Polling again in the same epoch finds nothing to do.
+            assert_eq!(sim.run_until_idle(), vec![]);
+            sim.advance_epoch();
+        }
+    }
+
+    #[test]
+    fn test_merge_goes_before_exchange_rate_update_and_withdrawal() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let validator = sim.add_validator();
+
+        sim.add_stake_account(&validator, 10_000_000_000);
+        assert_eq!(
+            sim.run_until_idle(),
+            vec![MaintenanceOutput::UpdateExchangeRate]
+        );
+
+        // In epoch 2, the first account is active, and the second one is
+        // activating. Those cannot be merged yet.
+        sim.advance_epoch();
+        let second_stake_account = sim.add_stake_account(&validator, 10_000_000_000);
+        assert_eq!(
+            sim.run_until_idle(),
+            vec![MaintenanceOutput::UpdateExchangeRate]
+        );
+
+        // In epoch 3, both accounts are active, so we can merge them. Someone
+        // also made a donation, which we should withdraw to the reserve, but
+        // only after the merge, and after updating the exchange rate.
+        sim.advance_epoch();
+        sim.donate(&second_stake_account, 500_000_000);
+        let outputs = sim.run_until_idle();
+        assert_eq!(outputs.len(), 3);
+        assert!(is_merge(&outputs[0]));
+        assert_eq!(outputs[1], MaintenanceOutput::UpdateExchangeRate);
+        assert!(matches!(
+            outputs[2],
+            MaintenanceOutput::WithdrawInactiveStake { .. }
+        ));
+
+        // After that, nothing happens until the next epoch starts.
+        assert_eq!(sim.run_until_idle(), vec![]);
+        sim.advance_epoch();
+        assert_eq!(
+            sim.run_until_idle(),
+            vec![MaintenanceOutput::UpdateExchangeRate]
+        );
+    }
+
+    #[test]
+    fn test_merges_several_stake_accounts_in_one_transaction() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let validator = sim.add_validator();
+
+        // Accounts that activate in the same epoch can all be merged.
+        let first_stake_account = sim.add_stake_account(&validator, 10_000_000_000);
+        sim.add_stake_account(&validator, 10_000_000_000);
+        let last_stake_account = sim.add_stake_account(&validator, 10_000_000_000);
+        let outputs = sim.run_until_idle();
+        assert_eq!(outputs.len(), 2);
+        match &outputs[0] {
+            MaintenanceOutput::MergeStake {
+                from_stake,
+                to_stake,
+                from_stake_seed,
+                to_stake_seed,
+                ..
+            } => {
+                assert_eq!(*from_stake, first_stake_account);
+                assert_eq!(*to_stake, last_stake_account);
+                assert_eq!(to_stake_seed - from_stake_seed, 2);
+            }
+            unexpected => panic!("Expected a merge, got {:?}.", unexpected),
+        }
+        assert_eq!(outputs[1], MaintenanceOutput::UpdateExchangeRate);
+        assert_eq!(sim.stake_accounts.len(), 1);
+    }
+
+    #[test]
+    fn test_fake_clock_advances_with_epochs() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let start = sim.wall_clock.now();
+
+        sim.advance_epoch();
+        sim.advance_epoch();
+
+        let elapsed = sim.wall_clock.now().duration_since(start).unwrap();
+        assert_eq!(elapsed, epoch_duration() * 2);
+    }
+}
@@ -22,28 +22,50 @@
 //! This is a pathological example though, for Solido we expect retries to be
 //! rare, and when they do happen, they shouldn’t happen repeatedly.
 
+use std::any::{Any, TypeId};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash as _, Hasher};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anchor_lang::AccountDeserialize;
+use serde::{Deserialize, Serialize};
 use solana_client::client_error::{ClientError, ClientErrorKind};
-use solana_client::rpc_client::RpcClient;
 use solana_client::rpc_request::RpcError;
+use solana_client::rpc_response::{RpcResult, RpcSimulateTransactionResult};
 use solana_sdk::account::Account;
 use solana_sdk::borsh::try_from_slice_unchecked;
+use solana_sdk::clock::{Epoch, Slot};
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::nonce;
 use solana_sdk::program_pack::{IsInitialized, Pack};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
+use solana_sdk::system_program;
 use solana_sdk::sysvar::stake_history::StakeHistory;
 use solana_sdk::sysvar::{
     self, clock::Clock, recent_blockhashes::RecentBlockhashes, rent::Rent, Sysvar,
 };
 use solana_sdk::transaction::Transaction;
 
-use lido::state::Lido;
+use lido::state::{Lido, LIDO_VERSION};
 use lido::token::Lamports;
 use spl_token::solana_program::hash::Hash;
 
-use crate::error::{Error, MissingAccountError, SerializationError};
+use crate::account_dump::AccountDump;
+use crate::error::{CliError, Error, MissingAccountError, SerializationError, SimulationError};
+use crate::prometheus::DurationHistogram;
+use crate::rate_limit::{is_rate_limit_error, RateLimitedRpc, RequestBudget};
+use crate::rpc::{
+    AccountFilter, BlockProduction, Commitment, InflationReward, Rpc, SignatureInfo,
+    TransactionInfo,
+};
+use crate::spl_token_utils;
+use crate::transaction_tracker::{self, BroadcastCounts, TrackerOptions, TransactionCounts};
+use crate::validator_info;
 
 pub enum SnapshotError {
     /// We tried to access an account, but it was not present in the snapshot.
@@ -92,6 +114,13 @@ impl<T: std::hash::Hash + Copy + Eq> OrderedSet<T> {
         }
     }
 
+    /// Remove an element, if it is in the set.
+    pub fn remove(&mut self, element: &T) {
+        if self.elements_set.remove(element) {
+            self.elements_vec.retain(|e| e != element);
+        }
+    }
+
     /// Merge `other` into `self`.
     ///
     /// This preserves the order of `self`, and adds additional elements at the
@@ -103,6 +132,12 @@ impl<T: std::hash::Hash + Copy + Eq> OrderedSet<T> {
     }
 }
 
+impl<T: std::hash::Hash + Copy + Eq> Default for OrderedSet<T> {
+    fn default() -> OrderedSet<T> {
+        OrderedSet::new()
+    }
+}
+
 // Deref impl so we get `.len()`, `.iter()`, `.chunks()`, etc.
 // This is the same Deref impl that `Vec` has.
 impl<T> std::ops::Deref for OrderedSet<T> {
@@ -113,8 +148,134 @@ impl<T> std::ops::Deref for OrderedSet<T> {
     }
 }
 
+/// Values parsed from account data, reused as long as the data does not change.
+///
+/// We read the `Lido` account and the stake history sysvar on every poll, but
+/// they change at most a few times per epoch. For an instance with many
+/// validators, deserializing the `Lido` account from scratch every time costs
+/// more CPU than comparing a hash of the data. See
+/// [`SnapshotClient::set_cache_parsed_accounts`].
+#[derive(Default)]
+pub struct ParsedAccountCache {
+    /// Per address and type of the parsed value, the most recent value.
+    entries: HashMap<(Pubkey, TypeId), ParsedAccount>,
+
+    /// Number of times we returned a cached value.
+    hits: u64,
+
+    /// Number of times we had to parse the data.
+    misses: u64,
+}
+
+struct ParsedAccount {
+    /// Hash of the account data that `value` was parsed from.
+    data_hash: u64,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+fn hash_account_data(data: &[u8]) -> u64 {
+    // This does not need to be a cryptographic hash: the data is what the RPC
+    // returned for one address, and the parsed value is only a cache of it.
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ParsedAccountCache {
+    /// Return the value that `parse` produces from `data`, or the value that it
+    /// produced before for the same address, if the data is the same.
+    ///
+    /// Failures are not cached, we parse again next time.
+    fn get_or_parse<T, F>(&mut self, address: &Pubkey, data: &[u8], parse: F) -> Result<Arc<T>>
+    where
+        T: Any + Send + Sync,
+        F: FnOnce() -> Result<T>,
+    {
+        let key = (*address, TypeId::of::<T>());
+        let data_hash = hash_account_data(data);
+        if let Some(entry) = self.entries.get(&key) {
+            if entry.data_hash == data_hash {
+                if let Ok(value) = entry.value.clone().downcast::<T>() {
+                    self.hits += 1;
+                    return Ok(value);
+                }
+            }
+        }
+
+        self.misses += 1;
+        let value = Arc::new(parse()?);
+        self.entries.insert(
+            key,
+            ParsedAccount {
+                data_hash,
+                value: value.clone(),
+            },
+        );
+        Ok(value)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+/// Deserialize the Solido struct, and check that the program could have written it.
+fn parse_solido(solido_address: &Pubkey, account: &Account) -> Result<Lido> {
+    // Other versions deserialize into garbage, or not at all.
+    let version = account.data.first().copied().unwrap_or_default();
+    if version != LIDO_VERSION {
+        let error: Error = Box::new(SerializationError {
+            cause: CliError::new(
+                "The Solido state is stored in a different version than this CLI supports. \
+                If it is version 0, propose migrating it with 'solido migrate-state'.",
+            ),
+            address: *solido_address,
+            context: format!(
+                "Lido struct has version {}, expected version {}.",
+                version, LIDO_VERSION
+            ),
+        });
+        return Err(error.into());
+    }
+    let solido = match try_from_slice_unchecked::<Lido>(&account.data) {
+        Ok(solido) => solido,
+        Err(err) => {
+            let error: Error = Box::new(SerializationError {
+                cause: err.into(),
+                address: *solido_address,
+                context: format!(
+                    "Failed to deserialize Lido struct, data length is {} bytes.",
+                    account.data.len()
+                ),
+            });
+            return Err(error.into());
+        }
+    };
+    // Data that is not a Lido account can still deserialize as one, but it
+    // would break invariants that the rest of the CLI relies on.
+    if let Err(violation) = solido.check_well_formed() {
+        let error: Error = Box::new(SerializationError {
+            cause: CliError::with_cause("The Lido struct is malformed.", violation),
+            address: *solido_address,
+            context: format!(
+                "Deserialized a Lido struct that the program cannot have written, data length is {} bytes.",
+                account.data.len()
+            ),
+        });
+        return Err(error.into());
+    }
+    Ok(solido)
+}
+
 /// A snapshot of one or more accounts.
 pub struct Snapshot<'a> {
+    /// The slot at which the accounts were read, see [`Snapshot::slot`].
+    slot: Slot,
+
     /// Addresses, and their values, at the time of the snapshot.
     ///
     /// The value holds an `Option`, so we can distinguish two "absent" cases:
@@ -138,7 +299,26 @@ pub struct Snapshot<'a> {
     accounts_referenced: &'a mut OrderedSet<Pubkey>,
 
     /// The wrapped client, so we can still send transactions.
-    rpc_client: &'a RpcClient,
+    rpc_client: &'a dyn Rpc,
+
+    /// All endpoints, in order, if we broadcast transactions to all of them,
+    /// see [`SnapshotClient::set_broadcast_transactions`].
+    broadcast_rpc_clients: Option<Vec<&'a dyn Rpc>>,
+
+    /// Latencies of the calls to `rpc_client`.
+    rpc_metrics: &'a mut RpcMetrics,
+
+    /// The commitment level at which to confirm transactions.
+    commitment: Commitment,
+
+    /// Whether to simulate transactions before sending them, see [`Snapshot::simulate_before_send`].
+    simulate_before_send: bool,
+
+    /// Validator names by identity, see [`Snapshot::get_validator_name`].
+    validator_names: &'a mut HashMap<Pubkey, Option<String>>,
+
+    /// Parsed accounts from previous snapshots, if the client caches them.
+    parsed_accounts: Option<&'a mut ParsedAccountCache>,
 
     /// Whether we sent at least one transaction.
     ///
@@ -149,6 +329,15 @@ pub struct Snapshot<'a> {
 }
 
 impl<'a> Snapshot<'a> {
+    /// Return the slot at which the accounts in the snapshot were read.
+    ///
+    /// This is the slot from the RPC response context. If the snapshot had to
+    /// be read in multiple calls that did not all observe the same slot, this
+    /// is the most recent one.
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
     /// Return whether an account with the given address exists.
     pub fn account_exists(&mut self, address: &Pubkey) -> Result<bool> {
         self.accounts_referenced.push(*address);
@@ -182,6 +371,53 @@ impl<'a> Snapshot<'a> {
         }
     }
 
+    /// Return the accounts owned by `program_id` that match all of the `filters`.
+    ///
+    /// We find the addresses with `getProgramAccounts`, but we return the
+    /// accounts as they are in the snapshot, so they are consistent with the
+    /// other accounts that we read. The addresses count as referenced, so if
+    /// one of them is not in the snapshot yet, we retry with it included.
+    /// Accounts that no longer match in the snapshot are not returned, accounts
+    /// that start to match after the call are not returned either.
+    pub fn get_program_accounts(
+        &mut self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> Result<Vec<(Pubkey, &'a Account)>> {
+        let mut addresses: Vec<Pubkey> = self
+            .rpc_client
+            .get_program_accounts(program_id, filters)?
+            .into_iter()
+            .map(|(address, _account)| address)
+            .collect();
+        addresses.sort();
+
+        let mut is_complete = true;
+        let mut result = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            self.accounts_referenced.push(address);
+            match self.accounts.get(&address) {
+                Some(Some(account)) => {
+                    if &account.owner == program_id
+                        && filters.iter().all(|filter| filter.matches(account))
+                    {
+                        result.push((address, account));
+                    }
+                }
+                // The account was closed after the call, it no longer matches.
+                Some(None) => {}
+                // Keep going, so we reference all missing accounts at once.
+                None => is_complete = false,
+            }
+        }
+
+        if is_complete {
+            Ok(result)
+        } else {
+            Err(SnapshotError::MissingAccount)
+        }
+    }
+
     /// Read an account and immediately bincode-deserialize it.
     pub fn get_bincode<T: Sysvar>(&mut self, address: &Pubkey) -> Result<T> {
         let account = self.get_account(address)?;
@@ -217,11 +453,39 @@ impl<'a> Snapshot<'a> {
         self.get_bincode(&sysvar::clock::id())
     }
 
+    /// Read `sysvar::epoch_schedule`.
+    pub fn get_epoch_schedule(&mut self) -> Result<EpochSchedule> {
+        self.get_bincode(&sysvar::epoch_schedule::id())
+    }
+
     /// Read `sysvar::stake_history`.
     pub fn get_stake_history(&mut self) -> Result<StakeHistory> {
         self.get_bincode(&sysvar::stake_history::id())
     }
 
+    /// Read `sysvar::stake_history`, without deserializing it again if it is
+    /// the same as in a previous snapshot.
+    pub fn get_stake_history_shared(&mut self) -> Result<Arc<StakeHistory>> {
+        self.get_parsed(&sysvar::stake_history::id(), |account| {
+            Ok(bincode::deserialize(&account.data)?)
+        })
+    }
+
+    /// Read an account and parse it with `parse`, or reuse the value that we
+    /// parsed before, if the client caches parsed accounts and the data did
+    /// not change since.
+    fn get_parsed<T, F>(&mut self, address: &Pubkey, parse: F) -> Result<Arc<T>>
+    where
+        T: Any + Send + Sync,
+        F: FnOnce(&Account) -> Result<T>,
+    {
+        let account = self.get_account(address)?;
+        match self.parsed_accounts.as_deref_mut() {
+            Some(cache) => cache.get_or_parse(address, &account.data, || parse(account)),
+            None => parse(account).map(Arc::new),
+        }
+    }
+
     /// Read `sysvar::recent_blockhashes`.
     pub fn get_recent_blockhashes(&mut self) -> Result<RecentBlockhashes> {
         self.get_bincode(&sysvar::recent_blockhashes::id())
@@ -234,6 +498,31 @@ impl<'a> Snapshot<'a> {
         Ok(blockhashes[0].blockhash)
     }
 
+    /// Read a durable nonce account, and return its stored blockhash and authority.
+    pub fn get_nonce_data(&mut self, nonce_account: &Pubkey) -> Result<nonce::state::Data> {
+        let account = self.get_account(nonce_account)?;
+        if account.owner != system_program::id() {
+            let error: Error = Box::new(SerializationError {
+                cause: CliError::new("Nonce account is not owned by the system program."),
+                address: *nonce_account,
+                context: format!("Owner is {}.", account.owner),
+            });
+            return Err(error.into());
+        }
+        let versions: nonce::state::Versions = bincode::deserialize(&account.data)?;
+        match versions.convert_to_current() {
+            nonce::State::Initialized(data) => Ok(data),
+            nonce::State::Uninitialized => {
+                let error: Error = Box::new(SerializationError {
+                    cause: CliError::new("Nonce account is not initialized."),
+                    address: *nonce_account,
+                    context: "Create it with `solana create-nonce-account`.".to_string(),
+                });
+                Err(error.into())
+            }
+        }
+    }
+
     /// Return the minimum rent-exempt balance for an account with `data_len` bytes of data.
     pub fn get_minimum_balance_for_rent_exemption(&mut self, data_len: usize) -> Result<Lamports> {
         let rent = self.get_rent()?;
@@ -242,29 +531,241 @@ impl<'a> Snapshot<'a> {
 
     /// Read the account and deserialize the Solido struct.
     pub fn get_solido(&mut self, solido_address: &Pubkey) -> Result<Lido> {
-        let account = self.get_account(solido_address)?;
-        match try_from_slice_unchecked::<Lido>(&account.data) {
-            Ok(solido) => Ok(solido),
-            Err(err) => {
-                let error: Error = Box::new(SerializationError {
-                    cause: err.into(),
-                    address: *solido_address,
-                    context: format!(
-                        "Failed to deserialize Lido struct, data length is {} bytes.",
-                        account.data.len()
-                    ),
-                });
-                Err(error.into())
-            }
+        let solido = self.get_solido_shared(solido_address)?;
+        // Without a cache, we hold the only reference, and this does not copy.
+        Ok(Arc::try_unwrap(solido).unwrap_or_else(|solido| (*solido).clone()))
+    }
+
+    /// Read the account and deserialize the Solido struct, without
+    /// deserializing it again if it is the same as in a previous snapshot.
+    pub fn get_solido_shared(&mut self, solido_address: &Pubkey) -> Result<Arc<Lido>> {
+        self.get_parsed(solido_address, |account| {
+            parse_solido(solido_address, account)
+        })
+    }
+
+    /// Read an account owned by SPL token or Token-2022, and deserialize its base state.
+    ///
+    /// Token-2022 accounts can carry extensions after the base state, so unlike
+    /// [`get_unpack`](Self::get_unpack), this does not require an exact length.
+    pub fn get_token_state<T: Pack + IsInitialized>(&mut self, address: &Pubkey) -> Result<T> {
+        let account = self.get_account(address)?;
+        if !lido::is_token_program(&account.owner) {
+            let error: Error = Box::new(SerializationError {
+                cause: CliError::new("Account is not owned by a token program."),
+                address: *address,
+                context: format!("Owner is {}.", account.owner),
+            });
+            return Err(error.into());
+        }
+        if account.data.len() < T::LEN {
+            let error: Error = Box::new(SerializationError {
+                cause: CliError::new("Token account data is too short."),
+                address: *address,
+                context: format!(
+                    "Expected at least {} bytes, but data length is {} bytes.",
+                    T::LEN,
+                    account.data.len()
+                ),
+            });
+            return Err(error.into());
+        }
+        let result = T::unpack_from_slice(&account.data[..T::LEN])?;
+        if !result.is_initialized() {
+            let error: Error = Box::new(SerializationError {
+                cause: CliError::new("Token account is not initialized."),
+                address: *address,
+                context: format!("Owner is {}.", account.owner),
+            });
+            return Err(error.into());
         }
+        Ok(result)
     }
 
-    /// Return the amount in an SPL token account.
+    /// Return the amount in an SPL token or Token-2022 account.
     pub fn get_spl_token_balance(&mut self, address: &Pubkey) -> Result<u64> {
-        let account: spl_token::state::Account = self.get_unpack(address)?;
+        let account: spl_token::state::Account = self.get_token_state(address)?;
         Ok(account.amount)
     }
 
+    /// Return the associated token account address of `owner` for `mint`.
+    ///
+    /// The address depends on the token program, which we read from the owner
+    /// of the mint, so this works for SPL token and Token-2022 mints alike.
+    pub fn get_associated_token_address(
+        &mut self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Pubkey> {
+        let token_program = self.get_account(mint)?.owner;
+        if !lido::is_token_program(&token_program) {
+            let error: Error = Box::new(SerializationError {
+                cause: CliError::new("Mint is not owned by a token program."),
+                address: *mint,
+                context: format!("Owner is {}.", token_program),
+            });
+            return Err(error.into());
+        }
+        Ok(spl_token_utils::get_associated_token_address(
+            owner,
+            mint,
+            &token_program,
+        ))
+    }
+
+    /// Return the associated token account of `owner` for `mint`, creating it if needed.
+    ///
+    /// If the account does not exist in the snapshot, this pushes an
+    /// instruction that creates it, paid for by `payer`, onto `instructions`.
+    /// Returns the address, and whether we pushed the instruction.
+    pub fn get_or_create_associated_token_account(
+        &mut self,
+        instructions: &mut Vec<Instruction>,
+        payer: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<(Pubkey, bool)> {
+        let address = self.get_associated_token_address(owner, mint)?;
+        if self.account_exists(&address)? {
+            return Ok((address, false));
+        }
+        let token_program = self.get_account(mint)?.owner;
+        instructions.push(spl_token_utils::create_associated_token_account(
+            payer,
+            owner,
+            mint,
+            &token_program,
+        ));
+        Ok((address, true))
+    }
+
+    /// Return the prioritization fees of recent slots for transactions that write to the accounts.
+    ///
+    /// The fees are not part of the snapshot, they are read at the time of the call.
+    pub fn get_recent_prioritization_fees(
+        &mut self,
+        pubkeys: &[Pubkey],
+    ) -> solana_client::client_error::Result<Vec<u64>> {
+        self.rpc_client.get_recent_prioritization_fees(pubkeys)
+    }
+
+    /// Return the inflation reward that every account received for `epoch`.
+    ///
+    /// Rewards are not part of the snapshot, they are read at the time of the call.
+    pub fn get_inflation_reward(
+        &mut self,
+        pubkeys: &[Pubkey],
+        epoch: Epoch,
+    ) -> solana_client::client_error::Result<Vec<Option<InflationReward>>> {
+        self.rpc_client.get_inflation_reward(pubkeys, epoch)
+    }
+
+    /// Return the activated stake of every vote account in the cluster.
+    ///
+    /// Like rewards, this is not part of the snapshot.
+    pub fn get_vote_account_stakes(
+        &mut self,
+    ) -> solana_client::client_error::Result<Vec<(Pubkey, u64)>> {
+        self.rpc_client.get_vote_account_stakes()
+    }
+
+    /// Return the leader slots and produced blocks of every validator identity
+    /// in the current epoch so far.
+    ///
+    /// Like rewards, this is not part of the snapshot.
+    pub fn get_block_production(
+        &mut self,
+    ) -> solana_client::client_error::Result<Vec<(Pubkey, BlockProduction)>> {
+        self.rpc_client.get_block_production()
+    }
+
+    /// Return at most `limit` of the most recent transactions that involve
+    /// `address`, newest first, see [`Rpc::get_signatures_for_address`].
+    ///
+    /// Like rewards, this is not part of the snapshot.
+    pub fn get_signatures_for_address(
+        &mut self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+        limit: usize,
+    ) -> solana_client::client_error::Result<Vec<SignatureInfo>> {
+        self.rpc_client
+            .get_signatures_for_address(address, before, until, limit)
+    }
+
+    /// Return the confirmed transaction with the given signature, if the node knows it.
+    ///
+    /// Like rewards, this is not part of the snapshot.
+    pub fn get_transaction(
+        &mut self,
+        signature: &str,
+    ) -> solana_client::client_error::Result<Option<TransactionInfo>> {
+        self.rpc_client.get_transaction(signature)
+    }
+
+    /// Return the name that the validator with the given identity published
+    /// in its validator-info account, if it published one.
+    ///
+    /// Validator info is not part of the snapshot. We can't derive the address
+    /// of the account from the identity, so we search for it with
+    /// `getProgramAccounts` on the first call for an identity. Names rarely
+    /// change, so the client remembers them, also when there is no name. If the
+    /// search fails, we remember nothing, so the next call tries again.
+    pub fn get_validator_name(
+        &mut self,
+        identity: &Pubkey,
+    ) -> solana_client::client_error::Result<Option<String>> {
+        if let Some(name) = self.validator_names.get(identity) {
+            return Ok(name.clone());
+        }
+        let filters = [AccountFilter::Memcmp {
+            offset: validator_info::VALIDATOR_INFO_IDENTITY_OFFSET,
+            bytes: identity.as_ref().to_vec(),
+        }];
+        let accounts = self
+            .rpc_client
+            .get_program_accounts(&validator_info::config_program::id(), &filters)?;
+        let name = accounts
+            .iter()
+            .find_map(|(_, account)| validator_info::parse_validator_name(&account.data, identity));
+        self.validator_names.insert(*identity, name.clone());
+        Ok(name)
+    }
+
+    /// Simulate a transaction, without sending it.
+    ///
+    /// Unlike sending, this has no side effects, so reading accounts afterwards is fine.
+    pub fn simulate_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> solana_client::client_error::Result<RpcSimulateTransactionResult> {
+        Ok(self.rpc_client.simulate_transaction(transaction)?.value)
+    }
+
+    /// Simulate the transaction if simulating before sending is enabled, and
+    /// return an error with the simulation logs if the simulation fails.
+    ///
+    /// Callers of [`Snapshot::send_and_confirm_transaction`] can call this
+    /// first, to not pay fees for transactions that are doomed to fail, for
+    /// example because the state changed after we read the snapshot.
+    pub fn simulate_before_send(&mut self, transaction: &Transaction) -> Result<()> {
+        if !self.simulate_before_send {
+            return Ok(());
+        }
+        let simulation = self.simulate_transaction(transaction)?;
+        match simulation.err {
+            None => Ok(()),
+            Some(error) => {
+                let error: Error = Box::new(SimulationError {
+                    error,
+                    logs: simulation.logs.unwrap_or_default(),
+                });
+                Err(error.into())
+            }
+        }
+    }
+
     /// Send a transaction without printing to stdout.
     ///
     /// After this, avoid reads from accounts not accessed before. Note, you
@@ -275,7 +776,28 @@ impl<'a> Snapshot<'a> {
         transaction: &Transaction,
     ) -> solana_client::client_error::Result<Signature> {
         *self.sent_transaction = true;
-        self.rpc_client.send_and_confirm_transaction(transaction)
+        let start = Instant::now();
+        let result = match self.broadcast_rpc_clients {
+            Some(ref rpc_clients) => transaction_tracker::broadcast_and_confirm_transaction(
+                rpc_clients,
+                transaction,
+                self.commitment.to_config(),
+                &TrackerOptions::default(),
+                &mut self.rpc_metrics.transactions,
+                &mut self.rpc_metrics.broadcasts,
+            ),
+            None => transaction_tracker::send_and_confirm_transaction(
+                self.rpc_client,
+                transaction,
+                self.commitment.to_config(),
+                &TrackerOptions::default(),
+                &mut self.rpc_metrics.transactions,
+            ),
+        };
+        self.rpc_metrics
+            .send_and_confirm_transaction
+            .observe(start.elapsed());
+        result
     }
 
     /// Send a transaction, show a spinner on stdout.
@@ -288,21 +810,68 @@ impl<'a> Snapshot<'a> {
         transaction: &Transaction,
     ) -> solana_client::client_error::Result<Signature> {
         *self.sent_transaction = true;
-        self.rpc_client
-            .send_and_confirm_transaction_with_spinner(transaction)
+        let start = Instant::now();
+        let result = self
+            .rpc_client
+            .send_and_confirm_transaction_with_spinner(transaction, self.commitment.to_config());
+        self.rpc_metrics
+            .send_and_confirm_transaction
+            .observe(start.elapsed());
+        result
     }
 }
 
-/// A wrapper around [`RpcClient`] that enables reading consistent snapshots of multiple accounts.
-pub struct SnapshotClient {
-    rpc_client: RpcClient,
+/// Latencies of the RPC calls that a [`SnapshotClient`] made, and its endpoint failovers and broadcasts, since it was created.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct RpcMetrics {
+    /// Duration of `GetMultipleAccounts` calls.
+    pub get_multiple_accounts: DurationHistogram,
 
-    /// The initial set of accounts to query.
+    /// Time from sending a transaction until it was confirmed, or failed to confirm.
+    pub send_and_confirm_transaction: DurationHistogram,
+
+    /// What happened to the transactions that we sent, see [`transaction_tracker`].
+    #[serde(default)]
+    pub transactions: TransactionCounts,
+
+    /// Index of the endpoint that we currently use, see [`SnapshotClient::new_with_endpoints`].
+    pub active_endpoint: usize,
+
+    /// Per endpoint, how often it failed, and we failed over to the next one.
+    pub failovers: Vec<u64>,
+
+    /// Per endpoint, how often a `GetMultipleAccounts` call failed with `429 Too Many Requests`.
+    #[serde(default)]
+    pub rate_limited: Vec<u64>,
+
+    /// Per endpoint, what happened to the transactions that we broadcast to it,
+    /// see [`SnapshotClient::set_broadcast_transactions`].
+    #[serde(default)]
+    pub broadcasts: Vec<BroadcastCounts>,
+
+    /// Duration of the most recent successful `GetMultipleAccounts` call.
     ///
-    /// We store the set here to reuse it between `with_snapshot` calls, so that
-    /// a next call doesn't need to learn from scratch which accounts we are
-    /// going to access.
-    accounts_to_query: OrderedSet<Pubkey>,
+    /// This says how the RPC node is doing right now, so we don't save it.
+    #[serde(skip)]
+    pub last_round_trip: Option<Duration>,
+}
+
+/// What a [`SnapshotClient`] learned, that a new client can reuse after a restart.
+///
+/// See [`SnapshotClient::learned_state`] and [`SnapshotClient::restore_learned_state`].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LearnedState {
+    /// The accounts to query, in base58, in the order in which we query them.
+    pub accounts_to_query: Vec<String>,
+
+    /// Per endpoint, the `max_items_per_call` that we learned, or `None` if we
+    /// did not hit a limit yet.
+    pub max_items_per_call: Vec<Option<usize>>,
+}
+
+/// An RPC endpoint, and what we learned about it.
+struct Endpoint {
+    rpc_client: RateLimitedRpc,
 
     /// The maximum number of accounts that we can request per `GetMultipleAccounts` call.
     ///
@@ -310,8 +879,87 @@ pub struct SnapshotClient {
     /// and when we get a too-many-accounts error when requesting `n` accounts,
     /// we set this to `n - 1`, so we should quickly learn an upper bound.
     max_items_per_call: usize,
+
+    /// Number of times in a row that we failed over from this endpoint.
+    consecutive_failures: u32,
+
+    /// After we fail over from this endpoint, avoid it until this time.
+    retry_after: Option<Instant>,
+}
+
+impl Endpoint {
+    fn new(rpc_client: Box<dyn Rpc>, budget: RequestBudget) -> Endpoint {
+        Endpoint {
+            rpc_client: RateLimitedRpc::new(rpc_client, budget),
+            max_items_per_call: usize::MAX,
+            consecutive_failures: 0,
+            retry_after: None,
+        }
+    }
+}
+
+/// A wrapper around an [`Rpc`] that enables reading consistent snapshots of multiple accounts.
+///
+/// The client is blocking. There is no async variant, because the version of
+/// `solana-client` that we depend on (1.7) has no nonblocking `RpcClient`; it
+/// was only added in 1.10. When we upgrade, an async variant can reuse the
+/// retry logic of [`SnapshotClient::with_snapshot`]. Until then, a slow RPC call
+/// does not block the maintenance daemon's metrics endpoint: the http handler
+/// threads serve the most recently published snapshot, see `daemon.rs`, only
+/// the main loop waits for the RPC.
+pub struct SnapshotClient {
+    /// The RPC endpoints, we read from one at a time, see [`SnapshotClient::fail_over`].
+    endpoints: Vec<Endpoint>,
+
+    /// Index into `endpoints` of the endpoint that we currently use.
+    active_endpoint: usize,
+
+    /// The initial set of accounts to query.
+    ///
+    /// We store the set here to reuse it between `with_snapshot` calls, so that
+    /// a next call doesn't need to learn from scratch which accounts we are
+    /// going to access.
+    accounts_to_query: OrderedSet<Pubkey>,
+
+    rpc_metrics: RpcMetrics,
+
+    /// The commitment level at which we read accounts and confirm transactions.
+    commitment: Commitment,
+
+    /// Whether to simulate transactions before sending them.
+    simulate_before_send: bool,
+
+    /// Whether to send transactions to all endpoints, instead of only the active one.
+    broadcast_transactions: bool,
+
+    /// Validator names that we looked up, see [`Snapshot::get_validator_name`].
+    validator_names: HashMap<Pubkey, Option<String>>,
+
+    /// Whether to keep the accounts of the last snapshot, see [`SnapshotClient::take_account_dump`].
+    record_accounts: bool,
+    account_dump: Option<AccountDump>,
+
+    /// How many requests we can make to each endpoint, see [`crate::rate_limit`].
+    request_budget: RequestBudget,
+
+    /// Parsed accounts to reuse between snapshots, if enabled, see
+    /// [`SnapshotClient::set_cache_parsed_accounts`].
+    parsed_accounts: Option<ParsedAccountCache>,
 }
 
+/// How often we read all chunks of a snapshot to get them all at the same slot.
+///
+/// See [`SnapshotClient::get_multiple_accounts_chunked`].
+const MAX_CONSISTENT_READ_ATTEMPTS: usize = 5;
+
+/// How long to avoid an endpoint after we failed over from it for the first time.
+///
+/// This doubles after every consecutive failure, up to [`FAILOVER_BACKOFF_MAX`].
+const FAILOVER_BACKOFF_BASE: Duration = Duration::from_secs(5);
+
+/// The maximum time to avoid an endpoint after we failed over from it.
+const FAILOVER_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
 /// Return whether a call to `GetMultipleAccounts` failed due to the RPC account limit.
 ///
 /// If this happens, the RPC operator must increase `--rpc-max-multiple-accounts`
@@ -333,89 +981,418 @@ fn is_too_many_inputs_error(error: &ClientError) -> bool {
     }
 }
 
+/// Split `range` into as few parts as possible of at most `max_len` elements,
+/// of roughly equal length. The range must not be empty.
+fn split_evenly(range: Range<usize>, max_len: usize) -> Vec<Range<usize>> {
+    let num_parts = 1 + (range.len() - 1) / max_len;
+    let len_per_part = 1 + (range.len() - 1) / num_parts;
+    range
+        .clone()
+        .step_by(len_per_part)
+        .map(|start| start..range.end.min(start + len_per_part))
+        .collect()
+}
+
+/// Call `GetMultipleAccounts` for every range of `pubkeys` on its own thread.
+///
+/// Returns the responses in the order of `ranges`, together with the duration
+/// of every call.
+fn get_multiple_accounts_concurrently(
+    rpc_client: &dyn Rpc,
+    pubkeys: &[Pubkey],
+    ranges: &[Range<usize>],
+    commitment: Commitment,
+) -> Vec<(Duration, RpcResult<Vec<Option<Account>>>)> {
+    let get_chunk = |range: &Range<usize>| {
+        let start = Instant::now();
+        let response = rpc_client
+            .get_multiple_accounts_with_commitment(&pubkeys[range.clone()], commitment.to_config());
+        (start.elapsed(), response)
+    };
+
+    // In the common case, everything fits in one call, and we don't need a thread.
+    if ranges.len() == 1 {
+        return vec![get_chunk(&ranges[0])];
+    }
+
+    crossbeam_utils::thread::scope(|scope| {
+        let handles: Vec<_> = ranges
+            .iter()
+            .map(|range| scope.spawn(move |_| get_chunk(range)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("GetMultipleAccounts thread panicked."))
+            .collect()
+    })
+    .expect("GetMultipleAccounts thread panicked.")
+}
+
+/// Return whether an error means that the endpoint is unreachable or rate limits us.
+///
+/// In that case, a different endpoint might still work.
+fn is_endpoint_error(error: &ClientError) -> bool {
+    match error.kind() {
+        // Connection errors, timeouts, and http errors, including 429 Too Many Requests.
+        ClientErrorKind::Io(..) | ClientErrorKind::Reqwest(..) => true,
+        ClientErrorKind::Custom(..) => is_rate_limit_error(error),
+        _ => false,
+    }
+}
+
 impl SnapshotClient {
-    pub fn new(rpc_client: RpcClient) -> SnapshotClient {
-        SnapshotClient {
-            rpc_client,
+    pub fn new<R: Rpc + 'static>(rpc_client: R) -> SnapshotClient {
+        SnapshotClient::new_with_endpoints(vec![rpc_client])
+    }
+
+    /// Create a client that reads from the first endpoint, and fails over to
+    /// the next one when an endpoint is unreachable or rate limits us.
+    pub fn new_with_endpoints<R: Rpc + 'static>(rpc_clients: Vec<R>) -> SnapshotClient {
+        let mut client = SnapshotClient {
+            endpoints: Vec::new(),
+            active_endpoint: 0,
             accounts_to_query: OrderedSet::new(),
-            max_items_per_call: usize::MAX,
+            rpc_metrics: RpcMetrics::default(),
+            commitment: Commitment::default(),
+            simulate_before_send: false,
+            broadcast_transactions: false,
+            validator_names: HashMap::new(),
+            record_accounts: false,
+            account_dump: None,
+            request_budget: RequestBudget::unlimited(),
+            parsed_accounts: None,
+        };
+        client.set_endpoints(rpc_clients);
+        client
+    }
+
+    pub fn rpc_metrics(&self) -> &RpcMetrics {
+        &self.rpc_metrics
+    }
+
+    pub fn commitment(&self) -> Commitment {
+        self.commitment
+    }
+
+    /// Set the commitment level at which we read accounts and confirm transactions.
+    pub fn set_commitment(&mut self, commitment: Commitment) {
+        self.commitment = commitment;
+    }
+
+    /// Simulate transactions before sending them, and don't send them if the simulation fails.
+    ///
+    /// See [`Snapshot::simulate_before_send`].
+    pub fn set_simulate_before_send(&mut self, simulate_before_send: bool) {
+        self.simulate_before_send = simulate_before_send;
+    }
+
+    /// Send transactions to all endpoints at once, and confirm them through
+    /// whichever endpoint sees them land first.
+    ///
+    /// We still read from one endpoint at a time. This only makes a
+    /// difference with more than one endpoint, see
+    /// [`transaction_tracker::broadcast_and_confirm_transaction`].
+    pub fn set_broadcast_transactions(&mut self, broadcast_transactions: bool) {
+        self.broadcast_transactions = broadcast_transactions;
+    }
+
+    /// Limit the rate of requests to each endpoint.
+    ///
+    /// The budget applies to every endpoint separately, and it carries over
+    /// to new endpoints in [`SnapshotClient::set_endpoints`].
+    pub fn set_request_budget(&mut self, budget: RequestBudget) {
+        self.request_budget = budget;
+        for endpoint in self.endpoints.iter_mut() {
+            endpoint.rpc_client.set_budget(budget);
         }
     }
 
-    /// Call `GetMultipleAccounts` to get `self.accounts_to_query`.
+    /// Keep the accounts of every successful snapshot, so they can be written
+    /// to an account dump afterwards.
+    pub fn set_record_accounts(&mut self, record_accounts: bool) {
+        self.record_accounts = record_accounts;
+    }
+
+    /// Reuse the parsed `Lido` account and stake history between snapshots, as
+    /// long as their data does not change, see [`ParsedAccountCache`].
+    ///
+    /// This is worth it for a client that reads the same accounts over and
+    /// over, like the maintenance daemon.
+    pub fn set_cache_parsed_accounts(&mut self, cache_parsed_accounts: bool) {
+        self.parsed_accounts = if cache_parsed_accounts {
+            Some(self.parsed_accounts.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    /// Return the cache of parsed accounts, if enabled.
+    pub fn parsed_accounts(&self) -> Option<&ParsedAccountCache> {
+        self.parsed_accounts.as_ref()
+    }
+
+    /// Return the accounts of the last successful snapshot, if we record them.
+    pub fn take_account_dump(&mut self) -> Option<AccountDump> {
+        self.account_dump.take()
+    }
+
+    /// Return what the client learned about the accounts to query and the endpoints.
+    pub fn learned_state(&self) -> LearnedState {
+        LearnedState {
+            accounts_to_query: self
+                .accounts_to_query
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            max_items_per_call: self
+                .endpoints
+                .iter()
+                .map(|endpoint| match endpoint.max_items_per_call {
+                    usize::MAX => None,
+                    max => Some(max),
+                })
+                .collect(),
+        }
+    }
+
+    /// Stop querying accounts that we know no longer exist, such as the stake
+    /// accounts that a merge closed.
+    ///
+    /// The next successful snapshot drops accounts that it does not use
+    /// anyway, but without this, that snapshot would still read them.
+    pub fn forget_accounts(&mut self, addresses: &[Pubkey]) {
+        for address in addresses {
+            self.accounts_to_query.remove(address);
+        }
+    }
+
+    /// Reuse what a previous client learned, so we don't have to learn it from scratch.
+    ///
+    /// The limits apply to the endpoints by position. If the endpoints changed
+    /// in the meantime, a limit can be wrong: we learn a lower limit when it is
+    /// too high, and a limit that is too low only costs an extra call.
+    pub fn restore_learned_state(&mut self, state: &LearnedState) {
+        let mut accounts_to_query = OrderedSet::new();
+        for address in &state.accounts_to_query {
+            if let Ok(address) = address.parse::<Pubkey>() {
+                accounts_to_query.push(address);
+            }
+        }
+        self.accounts_to_query = accounts_to_query;
+        for (endpoint, max) in self.endpoints.iter_mut().zip(&state.max_items_per_call) {
+            endpoint.max_items_per_call = max.unwrap_or(usize::MAX);
+        }
+    }
+
+    /// Continue counting from the RPC metrics of a previous client.
+    ///
+    /// We keep the failover counts only if we have the same number of endpoints.
+    pub fn restore_rpc_metrics(&mut self, rpc_metrics: &RpcMetrics) {
+        self.rpc_metrics.get_multiple_accounts = rpc_metrics.get_multiple_accounts.clone();
+        self.rpc_metrics.send_and_confirm_transaction =
+            rpc_metrics.send_and_confirm_transaction.clone();
+        self.rpc_metrics.transactions = rpc_metrics.transactions.clone();
+        if rpc_metrics.failovers.len() == self.endpoints.len() {
+            self.rpc_metrics.failovers = rpc_metrics.failovers.clone();
+        }
+        if rpc_metrics.rate_limited.len() == self.endpoints.len() {
+            self.rpc_metrics.rate_limited = rpc_metrics.rate_limited.clone();
+        }
+        if rpc_metrics.broadcasts.len() == self.endpoints.len() {
+            self.rpc_metrics.broadcasts = rpc_metrics.broadcasts.clone();
+        }
+    }
+
+    /// Switch to different RPC endpoints, e.g. after the daemon reloaded its config.
+    ///
+    /// We keep the accounts to query and the latency metrics, but we forget
+    /// what we learned about the previous endpoints.
+    pub fn set_endpoints<R: Rpc + 'static>(&mut self, rpc_clients: Vec<R>) {
+        assert!(!rpc_clients.is_empty(), "Need at least one RPC endpoint.");
+        self.endpoints = rpc_clients
+            .into_iter()
+            .map(|rpc_client| Endpoint::new(Box::new(rpc_client), self.request_budget))
+            .collect();
+        self.active_endpoint = 0;
+        self.rpc_metrics.active_endpoint = 0;
+        self.rpc_metrics.failovers = vec![0; self.endpoints.len()];
+        self.rpc_metrics.rate_limited = vec![0; self.endpoints.len()];
+        self.rpc_metrics.broadcasts = vec![BroadcastCounts::default(); self.endpoints.len()];
+    }
+
+    /// Switch from the active endpoint to the next one that we are not avoiding.
+    ///
+    /// After a failure, we avoid the endpoint for some time, which grows with
+    /// every consecutive failure. If we are avoiding all other endpoints, we
+    /// switch to the one that we can retry the soonest.
+    fn fail_over(&mut self) {
+        let now = Instant::now();
+        let endpoint = &mut self.endpoints[self.active_endpoint];
+        let backoff = FAILOVER_BACKOFF_BASE
+            .checked_mul(
+                1_u32
+                    .checked_shl(endpoint.consecutive_failures)
+                    .unwrap_or(u32::MAX),
+            )
+            .unwrap_or(FAILOVER_BACKOFF_MAX)
+            .min(FAILOVER_BACKOFF_MAX);
+        endpoint.consecutive_failures = endpoint.consecutive_failures.saturating_add(1);
+        endpoint.retry_after = Some(now + backoff);
+        self.rpc_metrics.failovers[self.active_endpoint] += 1;
+
+        let n = self.endpoints.len();
+        let active = self.active_endpoint;
+        let endpoints = &self.endpoints;
+        // `min_by_key` returns the first minimum, so of the endpoints that we
+        // are not avoiding, this picks the next one in order.
+        self.active_endpoint = (1..=n)
+            .map(|offset| (active + offset) % n)
+            .min_by_key(|&i| endpoints[i].retry_after.map_or(now, |t| t.max(now)))
+            .expect("There is at least one endpoint.");
+        self.rpc_metrics.active_endpoint = self.active_endpoint;
+    }
+
+    /// Call `GetMultipleAccounts` to get `self.accounts_to_query`, failing
+    /// over to the next endpoint if the active one does not respond.
+    ///
+    /// We try every endpoint at most once, if they all fail, we return the
+    /// error of the last one, and the caller can retry later.
+    fn get_multiple_accounts_chunked(
+        &mut self,
+    ) -> std::result::Result<(Slot, Vec<Option<Account>>), crate::error::Error> {
+        for attempts_left in (0..self.endpoints.len()).rev() {
+            let result = self.get_multiple_accounts_chunked_from_active_endpoint();
+            if let Err(ref err) = result {
+                if is_rate_limit_error(err) {
+                    self.rpc_metrics.rate_limited[self.active_endpoint] += 1;
+                }
+            }
+            match result {
+                Ok(result) => {
+                    self.endpoints[self.active_endpoint].consecutive_failures = 0;
+                    return Ok(result);
+                }
+                Err(ref err) if attempts_left > 0 && is_endpoint_error(err) => {
+                    eprintln!(
+                        "Warning: RPC endpoint {} failed, failing over to the next one. Error: {}",
+                        self.active_endpoint, err
+                    );
+                    self.fail_over();
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        unreachable!("The last attempt returns.");
+    }
+
+    /// Call `GetMultipleAccounts` on the active endpoint to get `self.accounts_to_query`.
     ///
     /// Ideally, we do a single `GetMultipleAccounts` call for the accounts we
     /// need, and then we have a consistent snapshot. But unfortunately, the
     /// default limit on the number of accounts that you can query in one call
     /// is quite low. This means that in somme cases, we may need to resort to
-    /// doing multiple calls. This can result in torn reads, and observing an
-    /// inconsistent state, but unfortunately there is no other way. If this
-    /// happens, we print a warning to stderr.
+    /// doing multiple calls. This can result in torn reads, so we make the
+    /// calls concurrently to keep the window between them small, and we compare
+    /// the slots in the response contexts of the calls. If they differ, we read
+    /// all chunks again, up to `MAX_CONSISTENT_READ_ATTEMPTS` times. A
+    /// difference in slots does not mean that any of the accounts changed, but
+    /// we can't tell. If we still fail to get all chunks at the same slot, we
+    /// accept the inconsistent read, and print a warning to stderr.
     ///
     /// Uses the known upper bound on the number of items that we can get per
-    /// call, `max_items_per_call` (set to `usize::MAX` initially, when this is
-    /// unknown). If we learn a tighter upper bound, this function updates the
-    /// maximum.
-    fn get_multiple_accounts_chunked(
+    /// call from the endpoint, `max_items_per_call` (set to `usize::MAX`
+    /// initially, when this is unknown). If we learn a tighter upper bound,
+    /// this function updates the maximum, and splits up only the chunks that
+    /// were too large.
+    ///
+    /// Returns the slot at which the accounts were read, or if the chunks were
+    /// read at different slots, the most recent one. If there are no accounts
+    /// to query, we don't make any calls, and the slot is 0.
+    fn get_multiple_accounts_chunked_from_active_endpoint(
         &mut self,
-    ) -> std::result::Result<Vec<Option<Account>>, crate::error::Error> {
-        let mut result = Vec::new();
+    ) -> std::result::Result<(Slot, Vec<Option<Account>>), ClientError> {
+        let endpoint = &mut self.endpoints[self.active_endpoint];
 
         // Handle the empty case first, because otherwise we try to make chunks
         // of length 0 below.
         if self.accounts_to_query.is_empty() {
-            return Ok(result);
+            return Ok((0, Vec::new()));
         }
 
-        'num_chunks: for num_chunks in 1.. {
-            result.clear();
-
-            let items_per_chunk = self.accounts_to_query.len() / num_chunks;
-            assert!(
-                items_per_chunk > 0,
-                "We should be able to get at least *one* account with GetMultipleAccounts."
-            );
-
-            if items_per_chunk > self.max_items_per_call {
-                // We already know that this would fail, try again with more chunks.
-                continue;
-            }
+        for attempt in 1..=MAX_CONSISTENT_READ_ATTEMPTS {
+            let mut pending =
+                split_evenly(0..self.accounts_to_query.len(), endpoint.max_items_per_call);
+            let mut chunks = Vec::new();
+            let mut min_slot = Slot::MAX;
+            let mut max_slot = 0;
 
-            for chunk in self.accounts_to_query.chunks(items_per_chunk) {
-                match self.rpc_client.get_multiple_accounts(chunk) {
-                    Ok(accounts) => {
-                        result.extend(accounts);
-                    }
-                    Err(ref err) if is_too_many_inputs_error(err) => {
-                        self.max_items_per_call = chunk.len() - 1;
-                        continue 'num_chunks;
+            while !pending.is_empty() {
+                let responses = get_multiple_accounts_concurrently(
+                    &endpoint.rpc_client,
+                    &self.accounts_to_query,
+                    &pending,
+                    self.commitment,
+                );
+                let mut too_large = Vec::new();
+                for (range, (duration, response)) in pending.into_iter().zip(responses) {
+                    self.rpc_metrics.get_multiple_accounts.observe(duration);
+                    match response {
+                        Ok(response) => {
+                            self.rpc_metrics.last_round_trip = Some(duration);
+                            min_slot = min_slot.min(response.context.slot);
+                            max_slot = max_slot.max(response.context.slot);
+                            chunks.push((range.start, response.value));
+                        }
+                        Err(ref err) if is_too_many_inputs_error(err) => {
+                            endpoint.max_items_per_call =
+                                endpoint.max_items_per_call.min(range.len() - 1);
+                            too_large.push(range);
+                        }
+                        Err(err) => return Err(err),
                     }
-                    Err(err) => return Err(err.into()),
-                };
+                }
+                assert!(
+                    endpoint.max_items_per_call > 0,
+                    "We should be able to get at least *one* account with GetMultipleAccounts."
+                );
+                pending = too_large
+                    .into_iter()
+                    .flat_map(|range| split_evenly(range, endpoint.max_items_per_call))
+                    .collect();
             }
 
+            let num_chunks = chunks.len();
+            chunks.sort_by_key(|(start, _)| *start);
+            let result: Vec<Option<Account>> = chunks
+                .into_iter()
+                .flat_map(|(_, accounts)| accounts)
+                .collect();
             assert_eq!(result.len(), self.accounts_to_query.len());
 
-            // Warn every time if this was not a consistent read, but only warn
-            // once per successful read.
-            if num_chunks > 1 {
+            if min_slot == max_slot {
+                return Ok((max_slot, result));
+            }
+
+            if attempt == MAX_CONSISTENT_READ_ATTEMPTS {
+                // Warn every time if this was not a consistent read, but
+                // only warn once per successful read.
                 eprintln!(
-                    "Warning: Failed to retrieve all accounts in a single \
-                        GetMultipleAccounts call. The resulting snapshot may be \
-                        inconsistent."
+                    "Warning: Failed to retrieve all accounts at the same slot \
+                    in {} attempts, the {} GetMultipleAccounts calls observed \
+                    slots {} to {}. The resulting snapshot may be inconsistent.",
+                    MAX_CONSISTENT_READ_ATTEMPTS, num_chunks, min_slot, max_slot,
                 );
                 eprintln!(
                     "Please ask the RPC node operator to bump \
-                        --rpc-max-multiple-accounts to {}, or connect to a \
-                        different RPC node.",
+                    --rpc-max-multiple-accounts to {}, or connect to a \
+                    different RPC node.",
                     self.accounts_to_query.len()
                 );
+                return Ok((max_slot, result));
             }
-
-            return Ok(result);
         }
 
-        unreachable!("Above loop fails the assertion when items_per_chunk > accounts_to_query.len");
+        unreachable!("The last attempt returns.");
     }
 
     /// Run the function `f`, which has access to a consistent snapshot of accounts.
@@ -436,7 +1413,7 @@ impl SnapshotClient {
         F: FnMut(Snapshot) -> Result<T>,
     {
         loop {
-            let account_values = self.get_multiple_accounts_chunked()?;
+            let (slot, account_values) = self.get_multiple_accounts_chunked()?;
             let accounts: HashMap<_, _> = self
                 .accounts_to_query
                 .iter()
@@ -446,11 +1423,28 @@ impl SnapshotClient {
 
             let mut accounts_referenced = OrderedSet::new();
             let mut sent_transaction = false;
+            let broadcast_rpc_clients = if self.broadcast_transactions && self.endpoints.len() > 1 {
+                Some(
+                    self.endpoints
+                        .iter()
+                        .map(|endpoint| &endpoint.rpc_client as &dyn Rpc)
+                        .collect(),
+                )
+            } else {
+                None
+            };
 
             let snapshot = Snapshot {
+                slot,
                 accounts: &accounts,
                 accounts_referenced: &mut accounts_referenced,
-                rpc_client: &self.rpc_client,
+                rpc_client: &self.endpoints[self.active_endpoint].rpc_client,
+                broadcast_rpc_clients,
+                rpc_metrics: &mut self.rpc_metrics,
+                commitment: self.commitment,
+                simulate_before_send: self.simulate_before_send,
+                validator_names: &mut self.validator_names,
+                parsed_accounts: self.parsed_accounts.as_mut(),
                 sent_transaction: &mut sent_transaction,
             };
 
@@ -463,6 +1457,9 @@ impl SnapshotClient {
                     // needed, update our accounts to query to be only what `f`
                     // actually used this time.
                     self.accounts_to_query = accounts_referenced;
+                    if self.record_accounts {
+                        self.account_dump = Some(AccountDump::new(slot, &accounts));
+                    }
                     return Ok(result);
                 }
                 Err(SnapshotError::OtherError(err)) => return Err(err),
@@ -499,4 +1496,264 @@ impl SnapshotClient {
             }
         }
     }
+
+    /// Run the function `f` once, with a snapshot that contains no accounts.
+    ///
+    /// This is for sending transactions that were signed in an earlier
+    /// snapshot. Unlike [`SnapshotClient::with_snapshot`], this never retries,
+    /// so `f` can have side effects, but it cannot read accounts. It does not
+    /// change the accounts that the next snapshot will query either.
+    pub fn without_snapshot<T, F>(&mut self, f: F) -> std::result::Result<T, crate::error::Error>
+    where
+        F: FnOnce(Snapshot) -> Result<T>,
+    {
+        let accounts = HashMap::new();
+        let mut accounts_referenced = OrderedSet::new();
+        let mut sent_transaction = false;
+        let broadcast_rpc_clients = if self.broadcast_transactions && self.endpoints.len() > 1 {
+            Some(
+                self.endpoints
+                    .iter()
+                    .map(|endpoint| &endpoint.rpc_client as &dyn Rpc)
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        let snapshot = Snapshot {
+            slot: 0,
+            accounts: &accounts,
+            accounts_referenced: &mut accounts_referenced,
+            rpc_client: &self.endpoints[self.active_endpoint].rpc_client,
+            broadcast_rpc_clients,
+            rpc_metrics: &mut self.rpc_metrics,
+            commitment: self.commitment,
+            simulate_before_send: self.simulate_before_send,
+            validator_names: &mut self.validator_names,
+            parsed_accounts: None,
+            sent_transaction: &mut sent_transaction,
+        };
+
+        match f(snapshot) {
+            Ok(result) => Ok(result),
+            Err(SnapshotError::OtherError(err)) => Err(err),
+            Err(SnapshotError::MissingAccount) => panic!(
+                "Tried to read an account without a snapshot. \
+                Read it in `with_snapshot` instead."
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_rpc::{new_solido_fixture, MockRpc};
+
+    #[test]
+    fn test_split_evenly() {
+        assert_eq!(split_evenly(0..7, usize::MAX), vec![0..7]);
+        assert_eq!(split_evenly(0..7, 7), vec![0..7]);
+        assert_eq!(split_evenly(0..7, 6), vec![0..4, 4..7]);
+        assert_eq!(split_evenly(0..7, 3), vec![0..3, 3..6, 6..7]);
+        assert_eq!(
+            split_evenly(10..14, 1),
+            vec![10..11, 11..12, 12..13, 13..14]
+        );
+    }
+
+    #[test]
+    fn test_get_program_accounts_includes_accounts_in_snapshot() {
+        let rpc = MockRpc::new();
+        let program_id = Pubkey::new_unique();
+        let new_account = |owner: Pubkey, data: Vec<u8>| Account {
+            lamports: 1,
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        let matching = Pubkey::new_unique();
+        let other_data = Pubkey::new_unique();
+        let other_owner = Pubkey::new_unique();
+        rpc.set_account(matching, new_account(program_id, vec![1, 2, 3]));
+        rpc.set_account(other_data, new_account(program_id, vec![1, 3, 3]));
+        rpc.set_account(
+            other_owner,
+            new_account(Pubkey::new_unique(), vec![1, 2, 3]),
+        );
+
+        let filters = [
+            AccountFilter::DataSize(3),
+            AccountFilter::Memcmp {
+                offset: 1,
+                bytes: vec![2],
+            },
+        ];
+        let mut client = SnapshotClient::new(rpc);
+        let accounts = client
+            .with_snapshot(|mut snapshot| {
+                let accounts = snapshot.get_program_accounts(&program_id, &filters)?;
+                // The accounts are in the snapshot now, reading them directly
+                // returns the same account.
+                assert_eq!(snapshot.get_account(&matching)?, accounts[0].1);
+                Ok(accounts
+                    .into_iter()
+                    .map(|(address, account)| (address, account.clone()))
+                    .collect::<Vec<_>>())
+            })
+            .unwrap();
+
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, matching);
+        assert_eq!(accounts[0].1.data, vec![1, 2, 3]);
+
+        // The next snapshot includes the account from the start.
+        let learned = client.learned_state();
+        assert_eq!(learned.accounts_to_query, vec![matching.to_string()]);
+    }
+
+    #[test]
+    fn test_forget_accounts_preserves_order_of_the_rest() {
+        let addresses: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let mut client = SnapshotClient::new(MockRpc::new());
+        client.restore_learned_state(&LearnedState {
+            accounts_to_query: addresses.iter().map(|a| a.to_string()).collect(),
+            max_items_per_call: Vec::new(),
+        });
+
+        client.forget_accounts(&[addresses[1], addresses[2], Pubkey::new_unique()]);
+        assert_eq!(
+            client.learned_state().accounts_to_query,
+            vec![addresses[0].to_string(), addresses[3].to_string()]
+        );
+    }
+
+    #[test]
+    fn test_get_or_create_associated_token_account() {
+        let rpc = MockRpc::new();
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let new_account = |owner: Pubkey| Account {
+            lamports: 1,
+            data: Vec::new(),
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        rpc.set_account(mint, new_account(spl_token::id()));
+        let address =
+            spl_token_utils::get_associated_token_address(&owner, &mint, &spl_token::id());
+
+        let mut client = SnapshotClient::new(rpc.clone());
+        let mut instructions = Vec::new();
+        let result = client
+            .with_snapshot(|mut snapshot| {
+                snapshot.get_or_create_associated_token_account(
+                    &mut instructions,
+                    &owner,
+                    &owner,
+                    &mint,
+                )
+            })
+            .unwrap();
+        assert_eq!(result, (address, true));
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].accounts[1].pubkey, address);
+
+        // Once the account exists, there is nothing to create.
+        rpc.set_account(address, new_account(spl_token::id()));
+        let mut instructions = Vec::new();
+        let result = client
+            .with_snapshot(|mut snapshot| {
+                snapshot.get_or_create_associated_token_account(
+                    &mut instructions,
+                    &owner,
+                    &owner,
+                    &mint,
+                )
+            })
+            .unwrap();
+        assert_eq!(result, (address, false));
+        assert!(instructions.is_empty());
+    }
+
+    #[test]
+    fn test_get_solido_rejects_malformed_data() {
+        use borsh::BorshSerialize;
+        use lido::state::Validator;
+
+        let mut fixture = new_solido_fixture(&Pubkey::new_unique());
+        let solido_address = fixture.solido_address;
+        let mut client = SnapshotClient::new(fixture.rpc.clone());
+        let data = fixture.solido.try_to_vec().unwrap();
+
+        // Truncated data fails to deserialize, at any length.
+        for len in 0..data.len() {
+            fixture.rpc.set_account(
+                solido_address,
+                Account {
+                    lamports: 1_000_000_000,
+                    data: data[..len].to_vec(),
+                    owner: fixture.solido_program_id,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+            let result = client.with_snapshot(|mut snapshot| snapshot.get_solido(&solido_address));
+            assert!(
+                result.is_err(),
+                "Data of {} bytes should not deserialize.",
+                len
+            );
+        }
+
+        // Data that deserializes, but breaks an invariant, is an error too.
+        fixture
+            .solido
+            .validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        fixture.solido.validators.entries[0]
+            .entry
+            .unstake_accounts_balance = Lamports(1);
+        fixture.store_solido();
+        let result = client.with_snapshot(|mut snapshot| snapshot.get_solido(&solido_address));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parsed_accounts_are_reused_until_data_changes() {
+        let mut fixture = new_solido_fixture(&Pubkey::new_unique());
+        let solido_address = fixture.solido_address;
+        let mut client = SnapshotClient::new(fixture.rpc.clone());
+        client.set_cache_parsed_accounts(true);
+        let read_solido = |client: &mut SnapshotClient| {
+            client
+                .with_snapshot(|mut snapshot| snapshot.get_solido_shared(&solido_address))
+                .unwrap()
+        };
+
+        let first = read_solido(&mut client);
+        let second = read_solido(&mut client);
+        assert!(Arc::ptr_eq(&first, &second));
+        let cache = client.parsed_accounts().unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // When the data changes, we parse it again.
+        fixture.solido.is_paused = true;
+        fixture.store_solido();
+        let third = read_solido(&mut client);
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert!(third.is_paused);
+        let cache = client.parsed_accounts().unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        // Without the cache, every read parses the data.
+        client.set_cache_parsed_accounts(false);
+        let fourth = read_solido(&mut client);
+        assert!(!Arc::ptr_eq(&third, &fourth));
+        assert_eq!(*third, *fourth);
+    }
 }
@@ -1,9 +1,11 @@
 // SPDX-FileCopyrightText: 2021 Chorus One AG
 // SPDX-License-Identifier: GPL-3.0
 
-use solana_program::{program_pack::Pack, pubkey::Pubkey, system_instruction};
+use solana_program::{
+    program_pack::Pack, pubkey::Pubkey, system_instruction, system_program, sysvar,
+};
 use solana_sdk::{
-    instruction::Instruction,
+    instruction::{AccountMeta, Instruction},
     signature::{Keypair, Signer},
 };
 
@@ -49,15 +51,17 @@ pub fn push_create_spl_token_mint(
     Ok(keypair)
 }
 
-/// Push instructions to create and initialize an SPL token account.
+/// Push instructions to create and initialize a token account.
 ///
-/// Returns the keypair for the account. This keypair needs to sign the
-/// transaction.
+/// The account is created for `token_program`, which must be the program that
+/// owns `mint`, either SPL token or Token-2022. Returns the keypair for the
+/// account. This keypair needs to sign the transaction.
 pub fn push_create_spl_token_account(
     config: &mut SnapshotConfig,
     instructions: &mut Vec<Instruction>,
     mint: &Pubkey,
     owner: &Pubkey,
+    token_program: &Pubkey,
 ) -> Result<Keypair> {
     let spl_token_min_sol_balance = config
         .client
@@ -71,15 +75,65 @@ pub fn push_create_spl_token_account(
         // Deposit enough SOL to make it rent-exempt.
         spl_token_min_sol_balance.0,
         spl_token::state::Account::LEN as u64,
-        // The new account should be owned by the SPL token program.
-        &spl_token::id(),
+        // The new account should be owned by the token program of the mint.
+        token_program,
     ));
-    instructions.push(spl_token::instruction::initialize_account(
+    // Token-2022 shares the instruction layout with SPL token, but the
+    // `spl_token` builders reject other program ids, so build for SPL token and
+    // retarget the instruction afterwards.
+    let mut initialize = spl_token::instruction::initialize_account(
         &spl_token::id(),
         &keypair.pubkey(),
         mint,
         owner,
-    )?);
+    )?;
+    initialize.program_id = *token_program;
+    instructions.push(initialize);
 
     Ok(keypair)
 }
+
+/// Return the associated token account address of `wallet` for `mint`.
+///
+/// Unlike `spl_associated_token_account::get_associated_token_address`, this
+/// takes the token program into account, so it works for Token-2022 mints too.
+pub fn get_associated_token_address(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    let (address, _bump_seed) = Pubkey::find_program_address(
+        &[
+            &wallet.to_bytes(),
+            &token_program.to_bytes(),
+            &mint.to_bytes(),
+        ],
+        &spl_associated_token_account::id(),
+    );
+    address
+}
+
+/// Return an instruction that creates the associated token account of `wallet` for `mint`.
+///
+/// `token_program` must be the program that owns `mint`, either SPL token or Token-2022.
+pub fn create_associated_token_account(
+    payer: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    let address = get_associated_token_address(wallet, mint, token_program);
+    Instruction {
+        program_id: spl_associated_token_account::id(),
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(address, false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: vec![],
+    }
+}
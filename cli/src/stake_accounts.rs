@@ -0,0 +1,344 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A listing of the stake accounts of every validator, as the maintainer sees them.
+//!
+//! Stake accounts are program-derived from the validator's vote account and a
+//! seed, so they do not show up in a normal account listing. This module
+//! derives the addresses in the seed ranges that Solido recorded, and shows
+//! the state that decides whether `MergeStake` and `WithdrawInactiveStake`
+//! apply: the maintainer merges the first stake account of a validator into
+//! the second one when the two are mergeable, and it withdraws unstake
+//! accounts once they are fully inactive.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::clock::{Clock, Epoch};
+use solana_program::pubkey::Pubkey;
+use solana_program::stake;
+use solana_program::stake_history::StakeHistory;
+
+use lido::account_map::PubkeyAndEntry;
+use lido::processor::StakeType;
+use lido::stake_account::{deserialize_stake_account, StakeAccount};
+use lido::state::Validator;
+use lido::token::Lamports;
+use lido::util::serialize_b58;
+
+use crate::derivation_cache;
+use crate::snapshot::{Result, Snapshot};
+
+/// A stake account that is delegated to the validator it belongs to.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct DelegatedStake {
+    #[serde(rename = "inactive_lamports")]
+    pub inactive: Lamports,
+    #[serde(rename = "activating_lamports")]
+    pub activating: Lamports,
+    #[serde(rename = "active_lamports")]
+    pub active: Lamports,
+    #[serde(rename = "deactivating_lamports")]
+    pub deactivating: Lamports,
+
+    pub activation_epoch: Epoch,
+
+    /// The epoch in which deactivation started, if it did.
+    pub deactivation_epoch: Option<Epoch>,
+
+    pub credits_observed: u64,
+}
+
+/// A stake account at a seed in the seed range of a validator.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct StakeAccountInfo {
+    #[serde(serialize_with = "serialize_b58")]
+    pub address: Pubkey,
+
+    /// Either "stake" or "unstake".
+    pub stake_type: &'static str,
+
+    pub seed: u64,
+
+    /// Balance of the account, `None` if it does not exist.
+    #[serde(rename = "lamports")]
+    pub balance: Option<Lamports>,
+
+    /// The delegation, `None` if the account is not a stake account delegated
+    /// to the validator.
+    pub delegation: Option<DelegatedStake>,
+
+    /// Whether `MergeStake` can merge this account into the next stake account.
+    /// Always false for unstake accounts.
+    pub mergeable_into_next: bool,
+}
+
+/// The stake accounts of a single validator.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ValidatorStakeAccounts {
+    #[serde(serialize_with = "serialize_b58")]
+    pub vote_account: Pubkey,
+
+    /// Balance of the stake accounts according to Solido, excluding unstake accounts.
+    #[serde(rename = "recorded_stake_balance_lamports")]
+    pub recorded_stake_balance: Lamports,
+
+    /// Stake accounts first, then unstake accounts, both in seed order.
+    pub stake_accounts: Vec<StakeAccountInfo>,
+}
+
+/// The stake accounts of all validators of a Solido instance.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct StakeAccounts {
+    pub epoch: Epoch,
+    pub validators: Vec<ValidatorStakeAccounts>,
+}
+
+impl StakeAccounts {
+    /// Read the stake accounts in the seed ranges of every validator.
+    pub fn new(
+        snapshot: &mut Snapshot,
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+    ) -> Result<StakeAccounts> {
+        let solido = snapshot.get_solido(solido_address)?;
+        let clock = snapshot.get_clock()?;
+        let stake_history = snapshot.get_stake_history()?;
+
+        let mut validators = Vec::new();
+        for validator in solido.validators.entries.iter() {
+            let mut stake_accounts = Vec::new();
+            for &stake_type in [StakeType::Stake, StakeType::Unstake].iter() {
+                let accounts = get_stake_accounts(
+                    snapshot,
+                    solido_program_id,
+                    solido_address,
+                    &clock,
+                    &stake_history,
+                    validator,
+                    stake_type,
+                )?;
+                stake_accounts.extend(accounts);
+            }
+            validators.push(ValidatorStakeAccounts {
+                vote_account: validator.pubkey,
+                recorded_stake_balance: validator.entry.effective_stake_balance(),
+                stake_accounts,
+            });
+        }
+
+        Ok(StakeAccounts {
+            epoch: clock.epoch,
+            validators,
+        })
+    }
+}
+
+/// Read the stake accounts of one type of a validator, in seed order.
+fn get_stake_accounts(
+    snapshot: &mut Snapshot,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    clock: &Clock,
+    stake_history: &StakeHistory,
+    validator: &PubkeyAndEntry<Validator>,
+    stake_type: StakeType,
+) -> Result<Vec<StakeAccountInfo>> {
+    let seeds = match stake_type {
+        StakeType::Stake => &validator.entry.stake_seeds,
+        StakeType::Unstake => &validator.entry.unstake_seeds,
+    };
+
+    // Alongside the infos, keep the parsed balances, to check mergeability.
+    let mut result = Vec::new();
+    let mut balances = Vec::new();
+    for seed in seeds {
+        let (address, _bump_seed) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
+            solido_program_id,
+            solido_address,
+            seed,
+            stake_type,
+        );
+        let mut info = StakeAccountInfo {
+            address,
+            stake_type: match stake_type {
+                StakeType::Stake => "stake",
+                StakeType::Unstake => "unstake",
+            },
+            seed,
+            balance: None,
+            delegation: None,
+            mergeable_into_next: false,
+        };
+        let mut balance = None;
+
+        if snapshot.account_exists(&address)? {
+            let account = snapshot.get_account(&address)?;
+            info.balance = Some(Lamports(account.lamports));
+            let stake = if account.owner == stake::program::id() {
+                deserialize_stake_account(&account.data).ok()
+            } else {
+                None
+            };
+            if let Some(stake) = stake.filter(|s| s.delegation.voter_pubkey == validator.pubkey) {
+                let stake_account = StakeAccount::from_delegated_account(
+                    Lamports(account.lamports),
+                    &stake,
+                    clock,
+                    stake_history,
+                    seed,
+                );
+                info.delegation = Some(DelegatedStake {
+                    inactive: stake_account.balance.inactive,
+                    activating: stake_account.balance.activating,
+                    active: stake_account.balance.active,
+                    deactivating: stake_account.balance.deactivating,
+                    activation_epoch: stake.delegation.activation_epoch,
+                    deactivation_epoch: Some(stake.delegation.deactivation_epoch)
+                        .filter(|&epoch| epoch != Epoch::MAX),
+                    credits_observed: stake.credits_observed,
+                });
+                balance = Some(stake_account);
+            }
+        }
+
+        result.push(info);
+        balances.push(balance);
+    }
+
+    // Only stake accounts get merged, unstake accounts get withdrawn when inactive.
+    if stake_type == StakeType::Stake {
+        for (i, pair) in balances.windows(2).enumerate() {
+            if let (Some(from_stake), Some(to_stake)) = (&pair[0], &pair[1]) {
+                result[i].mergeable_into_next = to_stake.can_merge(from_stake);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+impl fmt::Display for StakeAccounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Epoch: {}", self.epoch)?;
+        for validator in &self.validators {
+            writeln!(f, "\nValidator {}", validator.vote_account)?;
+            writeln!(
+                f,
+                "  Recorded stake balance: {}",
+                validator.recorded_stake_balance
+            )?;
+            if validator.stake_accounts.is_empty() {
+                writeln!(f, "  No stake accounts.")?;
+            }
+            for account in &validator.stake_accounts {
+                writeln!(
+                    f,
+                    "  - {} account {} (seed {})",
+                    account.stake_type, account.address, account.seed
+                )?;
+                let (balance, delegation) = match (&account.balance, &account.delegation) {
+                    (None, _) => {
+                        writeln!(f, "    Account does not exist.")?;
+                        continue;
+                    }
+                    (Some(balance), None) => {
+                        writeln!(f, "    Balance:      {}", balance)?;
+                        writeln!(f, "    Not a stake account delegated to this validator.")?;
+                        continue;
+                    }
+                    (Some(balance), Some(delegation)) => (balance, delegation),
+                };
+                writeln!(f, "    Balance:      {}", balance)?;
+                writeln!(f, "    Inactive:     {}", delegation.inactive)?;
+                writeln!(f, "    Activating:   {}", delegation.activating)?;
+                writeln!(f, "    Active:       {}", delegation.active)?;
+                writeln!(f, "    Deactivating: {}", delegation.deactivating)?;
+                writeln!(f, "    Activation epoch:   {}", delegation.activation_epoch)?;
+                match delegation.deactivation_epoch {
+                    Some(epoch) => writeln!(f, "    Deactivation epoch: {}", epoch)?,
+                    None => writeln!(f, "    Deactivation epoch: not deactivated")?,
+                }
+                if account.stake_type == "stake" {
+                    writeln!(
+                        f,
+                        "    Mergeable into next: {}",
+                        if account.mergeable_into_next {
+                            "yes"
+                        } else {
+                            "no"
+                        }
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::snapshot::SnapshotClient;
+
+    fn show_stake_accounts(simulation: &Simulation) -> StakeAccounts {
+        let solido_program_id = simulation.fixture.solido_program_id;
+        let solido_address = simulation.fixture.solido_address;
+        let mut client = SnapshotClient::new(simulation.fixture.rpc.clone());
+        client
+            .with_snapshot(|mut snapshot| {
+                StakeAccounts::new(&mut snapshot, &solido_program_id, &solido_address)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stake_accounts_activating_in_same_epoch_are_mergeable() {
+        let maintainer = Keypair::new();
+        let mut simulation = Simulation::new(&maintainer);
+        let vote_account = simulation.add_validator();
+        let first = simulation.add_stake_account(&vote_account, 10_000_000_000);
+        let second = simulation.add_stake_account(&vote_account, 20_000_000_000);
+
+        let stake_accounts = show_stake_accounts(&simulation);
+        assert_eq!(stake_accounts.validators.len(), 1);
+        let validator = &stake_accounts.validators[0];
+        assert_eq!(validator.vote_account, vote_account);
+        assert_eq!(validator.recorded_stake_balance, Lamports(30_000_000_000));
+
+        let addresses: Vec<Pubkey> = validator.stake_accounts.iter().map(|a| a.address).collect();
+        assert_eq!(addresses, vec![first, second]);
+        assert_eq!(
+            validator.stake_accounts[0].balance,
+            Some(Lamports(10_000_000_000))
+        );
+        let delegation = validator.stake_accounts[0].delegation.as_ref().unwrap();
+        assert_eq!(delegation.activation_epoch, simulation.epoch());
+        assert_eq!(delegation.deactivation_epoch, None);
+
+        // The last stake account has nothing to merge into.
+        assert!(validator.stake_accounts[0].mergeable_into_next);
+        assert!(!validator.stake_accounts[1].mergeable_into_next);
+    }
+
+    #[test]
+    fn test_stake_accounts_shows_missing_account() {
+        let maintainer = Keypair::new();
+        let mut simulation = Simulation::new(&maintainer);
+        let vote_account = simulation.add_validator();
+        let first = simulation.add_stake_account(&vote_account, 10_000_000_000);
+        simulation.add_stake_account(&vote_account, 10_000_000_000);
+        simulation.fixture.rpc.remove_account(&first);
+
+        let stake_accounts = show_stake_accounts(&simulation);
+        let accounts = &stake_accounts.validators[0].stake_accounts;
+        assert_eq!(accounts[0].balance, None);
+        assert_eq!(accounts[0].delegation, None);
+        assert!(!accounts[0].mergeable_into_next);
+        assert!(accounts[1].delegation.is_some());
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A view of a Solido instance in the data model of the SPL stake pool program.
+//!
+//! Tools that list liquid staking pools often already support SPL stake pools,
+//! which store a `StakePool` account and a `ValidatorList` account. This module
+//! maps Solido's state onto the read side of those accounts, with the same
+//! field names, so these tools can support Solido with little extra work.
+//!
+//! Like in the stake pool program, the balances are the pool's bookkeeping,
+//! which gets updated once per epoch, not live stake account balances. In
+//! particular, `total_lamports / pool_token_supply` is the exchange rate that
+//! Solido uses for deposits and withdrawals.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::clock::Epoch;
+use solana_program::pubkey::Pubkey;
+
+use lido::state::Lido;
+use lido::token::{Lamports, StLamports};
+use lido::util::serialize_b58;
+
+use crate::snapshot::Result;
+
+/// A fee as a fraction, like `spl_stake_pool::state::Fee`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Fee {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+/// Read-side fields of `spl_stake_pool::state::StakePool`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct StakePool {
+    #[serde(serialize_with = "serialize_b58")]
+    pub manager: Pubkey,
+
+    /// The stSOL mint.
+    #[serde(serialize_with = "serialize_b58")]
+    pub pool_mint: Pubkey,
+
+    /// Solido's reserve, which holds SOL that has not yet been staked.
+    #[serde(serialize_with = "serialize_b58")]
+    pub reserve_stake: Pubkey,
+
+    /// The authority of all stake accounts.
+    #[serde(serialize_with = "serialize_b58")]
+    pub stake_withdraw_authority: Pubkey,
+
+    /// SOL under management, as of `last_update_epoch`.
+    pub total_lamports: Lamports,
+
+    /// stSOL supply, as of `last_update_epoch`.
+    pub pool_token_supply: StLamports,
+
+    pub last_update_epoch: Epoch,
+
+    /// The share of rewards that goes to the treasury, validators, and developer.
+    pub epoch_fee: Fee,
+}
+
+/// Like `spl_stake_pool::state::StakeStatus`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub enum StakeStatus {
+    /// The validator receives new stake.
+    Active,
+    /// The validator was deactivated, but Solido still has stake with it.
+    DeactivatingTransient,
+    /// The validator was deactivated, and all stake has been withdrawn.
+    ReadyForRemoval,
+}
+
+/// Read-side fields of `spl_stake_pool::state::ValidatorStakeInfo`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ValidatorStakeInfo {
+    /// Stake in the validator's stake accounts.
+    pub active_stake_lamports: Lamports,
+
+    /// Stake in the validator's unstake accounts, on its way back to the reserve.
+    pub transient_stake_lamports: Lamports,
+
+    pub last_update_epoch: Epoch,
+
+    pub status: StakeStatus,
+
+    #[serde(serialize_with = "serialize_b58")]
+    pub vote_account_address: Pubkey,
+}
+
+/// Read-side fields of `spl_stake_pool::state::ValidatorList`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ValidatorList {
+    pub max_validators: u32,
+    pub validators: Vec<ValidatorStakeInfo>,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct StakePoolView {
+    pub stake_pool: StakePool,
+    pub validator_list: ValidatorList,
+}
+
+impl StakePoolView {
+    pub fn new(
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+        solido: &Lido,
+    ) -> Result<StakePoolView> {
+        let last_update_epoch = solido.exchange_rate.computed_in_epoch;
+        let distribution = &solido.reward_distribution;
+
+        let stake_pool = StakePool {
+            manager: solido.manager,
+            pool_mint: solido.st_sol_mint,
+            reserve_stake: solido.get_reserve_account(solido_program_id, solido_address)?,
+            stake_withdraw_authority: solido
+                .get_stake_authority(solido_program_id, solido_address)?,
+            total_lamports: solido.exchange_rate.sol_balance,
+            pool_token_supply: solido.exchange_rate.st_sol_supply,
+            last_update_epoch,
+            epoch_fee: Fee {
+                numerator: distribution.treasury_fee as u64
+                    + distribution.validation_fee as u64
+                    + distribution.developer_fee as u64,
+                denominator: distribution.sum(),
+            },
+        };
+
+        let validators = solido
+            .validators
+            .entries
+            .iter()
+            .map(|pe| {
+                let validator = &pe.entry;
+                let status = if validator.active {
+                    StakeStatus::Active
+                } else if validator.stake_accounts_balance > Lamports(0) {
+                    StakeStatus::DeactivatingTransient
+                } else {
+                    StakeStatus::ReadyForRemoval
+                };
+                ValidatorStakeInfo {
+                    active_stake_lamports: validator.effective_stake_balance(),
+                    transient_stake_lamports: validator.unstake_accounts_balance,
+                    last_update_epoch,
+                    status,
+                    vote_account_address: pe.pubkey,
+                }
+            })
+            .collect();
+
+        Ok(StakePoolView {
+            stake_pool,
+            validator_list: ValidatorList {
+                max_validators: solido.validators.maximum_entries,
+                validators,
+            },
+        })
+    }
+}
+
+impl fmt::Display for StakePoolView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pool = &self.stake_pool;
+        writeln!(f, "Stake pool:")?;
+        writeln!(f, "  Manager:                  {}", pool.manager)?;
+        writeln!(f, "  Pool mint:                {}", pool.pool_mint)?;
+        writeln!(f, "  Reserve stake:            {}", pool.reserve_stake)?;
+        writeln!(
+            f,
+            "  Stake withdraw authority: {}",
+            pool.stake_withdraw_authority
+        )?;
+        writeln!(f, "  Total lamports:           {}", pool.total_lamports)?;
+        writeln!(f, "  Pool token supply:        {}", pool.pool_token_supply)?;
+        writeln!(f, "  Last update epoch:        {}", pool.last_update_epoch)?;
+        writeln!(
+            f,
+            "  Epoch fee:                {}/{}",
+            pool.epoch_fee.numerator, pool.epoch_fee.denominator
+        )?;
+
+        writeln!(
+            f,
+            "\nValidator list: {} out of at most {}",
+            self.validator_list.validators.len(),
+            self.validator_list.max_validators
+        )?;
+        for v in &self.validator_list.validators {
+            writeln!(
+                f,
+                "  - Vote account:             {}\n    \
+                Status:                   {:?}\n    \
+                Active stake:             {}\n    \
+                Transient stake:          {}",
+                v.vote_account_address,
+                v.status,
+                v.active_stake_lamports,
+                v.transient_stake_lamports,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lido::state::{RewardDistribution, Validator, Validators};
+    use lido::{RESERVE_ACCOUNT, STAKE_AUTHORITY};
+
+    use super::*;
+
+    #[test]
+    fn test_stake_pool_view_uses_bookkeeping() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let find = |seed: &[u8]| {
+            Pubkey::find_program_address(&[&solido_address.to_bytes(), seed], &program_id)
+        };
+        let (reserve, reserve_bump_seed) = find(RESERVE_ACCOUNT);
+        let (_, stake_authority_bump_seed) = find(STAKE_AUTHORITY);
+
+        let mut solido = Lido {
+            sol_reserve_account_bump_seed: reserve_bump_seed,
+            stake_authority_bump_seed,
+            validators: Validators::new(3),
+            reward_distribution: RewardDistribution {
+                treasury_fee: 3,
+                validation_fee: 2,
+                developer_fee: 1,
+                st_sol_appreciation: 94,
+            },
+            ..Lido::default()
+        };
+        solido.exchange_rate.computed_in_epoch = 5;
+        solido.exchange_rate.sol_balance = Lamports(11_000);
+        solido.exchange_rate.st_sol_supply = StLamports(10_000);
+
+        let active = Pubkey::new_unique();
+        let deactivating = Pubkey::new_unique();
+        let removable = Pubkey::new_unique();
+        let new_validator = |active: bool, stake: u64, unstake: u64| Validator {
+            active,
+            stake_accounts_balance: Lamports(stake),
+            unstake_accounts_balance: Lamports(unstake),
+            ..Validator::new(Pubkey::new_unique())
+        };
+        solido
+            .validators
+            .add(active, new_validator(true, 5_000, 1_000))
+            .unwrap();
+        solido
+            .validators
+            .add(deactivating, new_validator(false, 2_000, 2_000))
+            .unwrap();
+        solido
+            .validators
+            .add(removable, new_validator(false, 0, 0))
+            .unwrap();
+
+        let view = StakePoolView::new(&program_id, &solido_address, &solido).unwrap();
+
+        assert_eq!(view.stake_pool.reserve_stake, reserve);
+        assert_eq!(view.stake_pool.total_lamports, Lamports(11_000));
+        assert_eq!(view.stake_pool.pool_token_supply, StLamports(10_000));
+        assert_eq!(view.stake_pool.last_update_epoch, 5);
+        assert_eq!(
+            view.stake_pool.epoch_fee,
+            Fee {
+                numerator: 6,
+                denominator: 100
+            }
+        );
+
+        let validators = &view.validator_list.validators;
+        assert_eq!(view.validator_list.max_validators, 3);
+        assert_eq!(validators[0].status, StakeStatus::Active);
+        assert_eq!(validators[0].active_stake_lamports, Lamports(4_000));
+        assert_eq!(validators[0].transient_stake_lamports, Lamports(1_000));
+        assert_eq!(validators[1].status, StakeStatus::DeactivatingTransient);
+        assert_eq!(validators[1].active_stake_lamports, Lamports(0));
+        assert_eq!(validators[2].status, StakeStatus::ReadyForRemoval);
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Compare the state of a Solido instance at two slots.
+//!
+//! RPC nodes only serve the current state of accounts, so at least one side
+//! of the comparison comes from an account dump, see `--write-account-dump`.
+//! The Solido account itself holds the exchange rate, the validator set, and
+//! the balances and fee credit that the program tracks per validator, so this
+//! only needs that one account, and it works with dumps of any command that
+//! supports them.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::clock::{Epoch, Slot};
+use solana_program::pubkey::Pubkey;
+
+use lido::state::Lido;
+use lido::token::{Lamports, StLamports};
+use lido::util::{serialize_b58, serialize_b58_slice};
+
+use crate::error::Error;
+use crate::snapshot::SnapshotClient;
+
+/// The Solido state, and the slot at which we read it.
+pub struct SolidoAtSlot {
+    pub slot: Slot,
+    pub solido: Lido,
+}
+
+impl SolidoAtSlot {
+    pub fn read(
+        client: &mut SnapshotClient,
+        solido_address: &Pubkey,
+    ) -> Result<SolidoAtSlot, Error> {
+        client.with_snapshot(|mut snapshot| {
+            let solido = snapshot.get_solido(solido_address)?;
+            Ok(SolidoAtSlot {
+                slot: snapshot.slot(),
+                solido,
+            })
+        })
+    }
+}
+
+/// An amount of SOL or stSOL.
+pub trait Amount: Copy + fmt::Display {
+    fn to_u64(self) -> u64;
+    fn from_u64(amount: u64) -> Self;
+}
+
+impl Amount for Lamports {
+    fn to_u64(self) -> u64 {
+        self.0
+    }
+    fn from_u64(amount: u64) -> Self {
+        Lamports(amount)
+    }
+}
+
+impl Amount for StLamports {
+    fn to_u64(self) -> u64 {
+        self.0
+    }
+    fn from_u64(amount: u64) -> Self {
+        StLamports(amount)
+    }
+}
+
+/// A value at the start and at the end of the range.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    pub fn new(from: T, to: T) -> Change<T> {
+        Change { from, to }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Change<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)
+    }
+}
+
+/// An amount at the start and at the end of the range, and the difference.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct AmountChange<T> {
+    pub from: T,
+    pub to: T,
+
+    /// `to - from`, in the smallest unit. Amounts are bounded by the supply of
+    /// SOL, which is far below `i64::MAX` lamports, so this does not overflow.
+    pub delta: i64,
+}
+
+impl<T: Amount> AmountChange<T> {
+    pub fn new(from: T, to: T) -> AmountChange<T> {
+        AmountChange {
+            from,
+            to,
+            delta: to.to_u64() as i64 - from.to_u64() as i64,
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.delta != 0
+    }
+}
+
+impl<T: Amount> fmt::Display for AmountChange<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.delta < 0 { '-' } else { '+' };
+        write!(
+            f,
+            "{} -> {} ({}{})",
+            self.from,
+            self.to,
+            sign,
+            T::from_u64(self.delta.unsigned_abs())
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeRateDiff {
+    pub computed_in_epoch: Change<Epoch>,
+    pub sol_balance: AmountChange<Lamports>,
+    pub st_sol_supply: AmountChange<StLamports>,
+
+    /// SOL per stSOL, `None` while the stSOL supply is zero.
+    pub sol_per_st_sol: Change<Option<f64>>,
+}
+
+/// Changes of a validator that is in the list at both slots.
+#[derive(Debug, Serialize)]
+pub struct ValidatorDiff {
+    #[serde(serialize_with = "serialize_b58")]
+    pub vote_account: Pubkey,
+
+    pub stake_accounts_balance: AmountChange<Lamports>,
+    pub unstake_accounts_balance: AmountChange<Lamports>,
+    pub effective_stake_balance: AmountChange<Lamports>,
+    pub fee_credit: AmountChange<StLamports>,
+    pub weight: Change<u32>,
+    pub active: Change<bool>,
+}
+
+impl ValidatorDiff {
+    pub fn is_changed(&self) -> bool {
+        self.stake_accounts_balance.is_changed()
+            || self.unstake_accounts_balance.is_changed()
+            || self.fee_credit.is_changed()
+            || self.weight.is_changed()
+            || self.active.is_changed()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StateDiff {
+    pub slot: Change<Slot>,
+    pub exchange_rate: ExchangeRateDiff,
+
+    #[serde(serialize_with = "serialize_b58_slice")]
+    pub validators_added: Vec<Pubkey>,
+
+    #[serde(serialize_with = "serialize_b58_slice")]
+    pub validators_removed: Vec<Pubkey>,
+
+    /// Validators that are in the list at both slots, including unchanged ones.
+    pub validators: Vec<ValidatorDiff>,
+
+    /// Fee credit of all validators at both slots, including added and removed ones.
+    pub total_fee_credit: AmountChange<StLamports>,
+}
+
+fn get_sol_per_st_sol(solido: &Lido) -> Option<f64> {
+    let rate = &solido.exchange_rate;
+    if rate.st_sol_supply == StLamports(0) {
+        None
+    } else {
+        Some(rate.sol_balance.0 as f64 / rate.st_sol_supply.0 as f64)
+    }
+}
+
+fn get_total_fee_credit(solido: &Lido) -> StLamports {
+    StLamports(
+        solido
+            .validators
+            .entries
+            .iter()
+            .map(|pe| pe.entry.fee_credit.0)
+            .sum(),
+    )
+}
+
+impl StateDiff {
+    pub fn new(from: &SolidoAtSlot, to: &SolidoAtSlot) -> StateDiff {
+        let rate_from = &from.solido.exchange_rate;
+        let rate_to = &to.solido.exchange_rate;
+        let exchange_rate = ExchangeRateDiff {
+            computed_in_epoch: Change::new(rate_from.computed_in_epoch, rate_to.computed_in_epoch),
+            sol_balance: AmountChange::new(rate_from.sol_balance, rate_to.sol_balance),
+            st_sol_supply: AmountChange::new(rate_from.st_sol_supply, rate_to.st_sol_supply),
+            sol_per_st_sol: Change::new(
+                get_sol_per_st_sol(&from.solido),
+                get_sol_per_st_sol(&to.solido),
+            ),
+        };
+
+        let validators_added = to
+            .solido
+            .validators
+            .entries
+            .iter()
+            .filter(|pe| from.solido.validators.get(&pe.pubkey).is_err())
+            .map(|pe| pe.pubkey)
+            .collect();
+
+        let mut validators_removed = Vec::new();
+        let mut validators = Vec::new();
+        for validator_from in from.solido.validators.entries.iter() {
+            let validator_to = match to.solido.validators.get(&validator_from.pubkey) {
+                Ok(validator_to) => validator_to,
+                Err(..) => {
+                    validators_removed.push(validator_from.pubkey);
+                    continue;
+                }
+            };
+            let (v_from, v_to) = (&validator_from.entry, &validator_to.entry);
+            validators.push(ValidatorDiff {
+                vote_account: validator_from.pubkey,
+                stake_accounts_balance: AmountChange::new(
+                    v_from.stake_accounts_balance,
+                    v_to.stake_accounts_balance,
+                ),
+                unstake_accounts_balance: AmountChange::new(
+                    v_from.unstake_accounts_balance,
+                    v_to.unstake_accounts_balance,
+                ),
+                effective_stake_balance: AmountChange::new(
+                    v_from.effective_stake_balance(),
+                    v_to.effective_stake_balance(),
+                ),
+                fee_credit: AmountChange::new(v_from.fee_credit, v_to.fee_credit),
+                weight: Change::new(v_from.weight, v_to.weight),
+                active: Change::new(v_from.active, v_to.active),
+            });
+        }
+
+        StateDiff {
+            slot: Change::new(from.slot, to.slot),
+            exchange_rate,
+            validators_added,
+            validators_removed,
+            validators,
+            total_fee_credit: AmountChange::new(
+                get_total_fee_credit(&from.solido),
+                get_total_fee_credit(&to.solido),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Slot: {}", self.slot)?;
+
+        let rate = &self.exchange_rate;
+        writeln!(f, "\nExchange rate:")?;
+        writeln!(f, "  Computed in epoch: {}", rate.computed_in_epoch)?;
+        writeln!(f, "  SOL balance:       {}", rate.sol_balance)?;
+        writeln!(f, "  stSOL supply:      {}", rate.st_sol_supply)?;
+        match (rate.sol_per_st_sol.from, rate.sol_per_st_sol.to) {
+            (Some(from), Some(to)) => writeln!(
+                f,
+                "  SOL per stSOL:     {:.9} -> {:.9} ({:+.9})",
+                from,
+                to,
+                to - from
+            )?,
+            (from, to) => writeln!(f, "  SOL per stSOL:     {:?} -> {:?}", from, to)?,
+        }
+
+        writeln!(f, "\nValidators added:")?;
+        for vote_account in &self.validators_added {
+            writeln!(f, "  {}", vote_account)?;
+        }
+        writeln!(f, "\nValidators removed:")?;
+        for vote_account in &self.validators_removed {
+            writeln!(f, "  {}", vote_account)?;
+        }
+
+        writeln!(f, "\nTotal fee credit: {}", self.total_fee_credit)?;
+
+        for validator in self.validators.iter().filter(|v| v.is_changed()) {
+            writeln!(f, "\nValidator {}:", validator.vote_account)?;
+            writeln!(
+                f,
+                "  Stake accounts balance:   {}",
+                validator.stake_accounts_balance
+            )?;
+            writeln!(
+                f,
+                "  Unstake accounts balance: {}",
+                validator.unstake_accounts_balance
+            )?;
+            writeln!(
+                f,
+                "  Effective stake balance:  {}",
+                validator.effective_stake_balance
+            )?;
+            writeln!(f, "  Fee credit:               {}", validator.fee_credit)?;
+            if validator.weight.is_changed() {
+                writeln!(f, "  Weight:                   {}", validator.weight)?;
+            }
+            if validator.active.is_changed() {
+                writeln!(f, "  Active:                   {}", validator.active)?;
+            }
+        }
+        let n_unchanged = self.validators.iter().filter(|v| !v.is_changed()).count();
+        if n_unchanged > 0 {
+            writeln!(f, "\n{} other validators did not change.", n_unchanged)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lido::state::{Validator, Validators};
+
+    fn new_solido(validators: &[(Pubkey, Lamports, StLamports)]) -> Lido {
+        let mut solido = Lido {
+            validators: Validators::new(5),
+            ..Lido::default()
+        };
+        for &(vote_account, stake, fee_credit) in validators {
+            let mut validator = Validator::new(Pubkey::new_unique());
+            validator.stake_accounts_balance = stake;
+            validator.fee_credit = fee_credit;
+            solido.validators.add(vote_account, validator).unwrap();
+        }
+        solido
+    }
+
+    #[test]
+    fn test_diff_reports_validator_set_and_balance_changes() {
+        let kept = Pubkey::new_unique();
+        let removed = Pubkey::new_unique();
+        let added = Pubkey::new_unique();
+
+        let mut from = new_solido(&[
+            (kept, Lamports(10), StLamports(1)),
+            (removed, Lamports(5), StLamports(2)),
+        ]);
+        from.exchange_rate.sol_balance = Lamports(100);
+        from.exchange_rate.st_sol_supply = StLamports(100);
+        let mut to = new_solido(&[
+            (kept, Lamports(7), StLamports(4)),
+            (added, Lamports(3), StLamports(0)),
+        ]);
+        to.exchange_rate.computed_in_epoch = 1;
+        to.exchange_rate.sol_balance = Lamports(110);
+        to.exchange_rate.st_sol_supply = StLamports(100);
+
+        let diff = StateDiff::new(
+            &SolidoAtSlot {
+                slot: 10,
+                solido: from,
+            },
+            &SolidoAtSlot {
+                slot: 20,
+                solido: to,
+            },
+        );
+
+        assert_eq!(diff.slot, Change::new(10, 20));
+        assert_eq!(diff.exchange_rate.sol_balance.delta, 10);
+        assert_eq!(
+            diff.exchange_rate.sol_per_st_sol,
+            Change::new(Some(1.0), Some(1.1))
+        );
+        assert_eq!(diff.validators_added, vec![added]);
+        assert_eq!(diff.validators_removed, vec![removed]);
+        assert_eq!(diff.validators.len(), 1);
+        assert_eq!(diff.validators[0].vote_account, kept);
+        assert_eq!(diff.validators[0].stake_accounts_balance.delta, -3);
+        assert_eq!(diff.validators[0].fee_credit.delta, 3);
+        assert!(!diff.validators[0].weight.is_changed());
+        assert_eq!(
+            diff.total_fee_credit,
+            AmountChange::new(StLamports(3), StLamports(4))
+        );
+        assert_eq!(
+            diff.validators[0].stake_accounts_balance.to_string(),
+            "0.000000010 SOL -> 0.000000007 SOL (-0.000000003 SOL)"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A live view of a Solido instance in the terminal, for `solido top`.
+//!
+//! Every few seconds we read a new [`SolidoState`], the same state that the
+//! maintenance daemon works with, and redraw the screen with ANSI escape
+//! sequences. The view shows the exchange rate, the reserve, the stake of every
+//! validator compared to its target, the maintenance that is pending, and the
+//! most recent transactions of the maintainers.
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lido::token::Lamports;
+
+use crate::config::TopOpts;
+use crate::error::AsPrettyError;
+use crate::maintenance::{select_maintenance, MaintenanceOptions, SolidoState};
+use crate::rpc::SignatureInfo;
+use crate::snapshot::Result;
+use crate::{SnapshotClientConfig, SnapshotConfig};
+
+/// Width in characters of the stake bars.
+const BAR_WIDTH: usize = 30;
+
+/// What we show on one screen.
+pub struct Frame {
+    pub state: SolidoState,
+
+    /// Recent transactions of all maintainers, newest first.
+    pub recent_transactions: Vec<SignatureInfo>,
+}
+
+fn format_sol(amount: Lamports) -> String {
+    format!("{:.2} SOL", amount.0 as f64 / 1e9)
+}
+
+/// Draw a bar of `BAR_WIDTH` characters, filled up to `value`, with a `|` at `target`.
+fn format_bar(value: Lamports, target: Option<Lamports>, scale: Lamports) -> String {
+    let position = |amount: Lamports| {
+        if scale.0 == 0 {
+            0
+        } else {
+            ((amount.0 as u128 * BAR_WIDTH as u128) / scale.0 as u128) as usize
+        }
+    };
+    let filled = position(value);
+    let target_position = target.map(|target| position(target).min(BAR_WIDTH - 1));
+    (0..BAR_WIDTH)
+        .map(|i| {
+            if Some(i) == target_position {
+                '|'
+            } else if i < filled {
+                '#'
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Read the state, and the recent transactions of the maintainers.
+fn get_frame(config: &mut SnapshotConfig, opts: &TopOpts) -> Result<Frame> {
+    let state = SolidoState::new(config, opts.solido_program_id(), opts.solido_address())?;
+
+    let mut recent_transactions = Vec::new();
+    for maintainer in state.solido.maintainers.entries.iter() {
+        recent_transactions.extend(config.client.get_signatures_for_address(
+            &maintainer.pubkey,
+            None,
+            None,
+            *opts.recent_transactions(),
+        )?);
+    }
+    recent_transactions.sort_by(|a, b| b.slot.cmp(&a.slot));
+    recent_transactions.truncate(*opts.recent_transactions());
+
+    Ok(Frame {
+        state,
+        recent_transactions,
+    })
+}
+
+/// Render the frame as text, with `now` to show how long ago transactions were.
+pub fn render(frame: &Frame, now: SystemTime) -> String {
+    let state = &frame.state;
+    let solido = &state.solido;
+
+    // Writing to a string does not fail, so we ignore the results.
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Solido {}   slot {}   epoch {}, {}s in",
+        state.solido_address,
+        state.clock.slot,
+        state.clock.epoch,
+        state.time_into_epoch().as_secs(),
+    );
+    let _ = writeln!(out);
+
+    let rate = &solido.exchange_rate;
+    if rate.st_sol_supply.0 == 0 {
+        let _ = writeln!(out, "Exchange rate:   not computed yet");
+    } else {
+        let _ = writeln!(
+            out,
+            "Exchange rate:   1 stSOL = {:.6} SOL, computed in epoch {}",
+            rate.sol_balance.0 as f64 / rate.st_sol_supply.0 as f64,
+            rate.computed_in_epoch,
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Reserve:         {}, of which {} can be staked",
+        format_sol(state.get_effective_reserve()),
+        format_sol(state.get_stakeable_reserve()),
+    );
+    let _ = writeln!(out);
+
+    // Computing the targets fails when no validator receives stake, in that
+    // case we only show the stake.
+    let targets = lido::balance::get_target_balance(
+        state.get_effective_reserve(),
+        &solido.validators,
+        solido.get_max_stake_per_validator(),
+        None,
+    )
+    .ok();
+    let scale = solido
+        .validators
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, validator)| {
+            let target = targets.as_ref().map_or(Lamports(0), |targets| targets[i]);
+            validator.entry.effective_stake_balance().max(target)
+        })
+        .max()
+        .unwrap_or(Lamports(0));
+    let _ = writeln!(out, "Validators: stake #, target |");
+    for (i, (validator, identity)) in solido
+        .validators
+        .entries
+        .iter()
+        .zip(state.validator_identities.iter())
+        .enumerate()
+    {
+        let stake = validator.entry.effective_stake_balance();
+        let target = targets.as_ref().map(|targets| targets[i]);
+        let name = match &identity.name {
+            Some(name) => name.clone(),
+            None => validator.pubkey.to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "  {:<24.24} [{}] {} / {}{}",
+            name,
+            format_bar(stake, target, scale),
+            format_sol(stake),
+            target.map_or("-".to_string(), format_sol),
+            if validator.entry.active {
+                ""
+            } else {
+                "  (inactive)"
+            },
+        );
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "Pending maintenance:");
+    match select_maintenance(state, &MaintenanceOptions::default()) {
+        Some((_instructions, output)) => {
+            let _ = writeln!(out, "  Next: {}", output.operation_name());
+        }
+        None => {
+            let _ = writeln!(out, "  Nothing to do.");
+        }
+    }
+    if rate.computed_in_epoch < state.clock.epoch {
+        let _ = writeln!(out, "  The exchange rate is not updated in this epoch yet.");
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "Recent maintainer transactions:");
+    if frame.recent_transactions.is_empty() {
+        let _ = writeln!(out, "  None.");
+    }
+    let now_timestamp = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    for transaction in &frame.recent_transactions {
+        let age = match transaction.block_time {
+            Some(block_time) => format!("{}s ago", (now_timestamp - block_time).max(0)),
+            None => "?".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "  slot {:<10} {:>10}  {:<6} {}",
+            transaction.slot,
+            age,
+            if transaction.err.is_none() {
+                "ok"
+            } else {
+                "failed"
+            },
+            transaction.signature,
+        );
+    }
+    out
+}
+
+/// Redraw the view every `--refresh-seconds`, until interrupted.
+pub fn main(config: &mut SnapshotClientConfig, opts: &TopOpts) {
+    loop {
+        let text = match config.with_snapshot(|config| get_frame(config, opts)) {
+            Ok(frame) => render(&frame, SystemTime::now()),
+            // Keep going, the next refresh may succeed.
+            Err(err) => format!("Failed to read the Solido state: {}\n", err.summary()),
+        };
+        // 2J clears the screen, H moves the cursor to the top left.
+        print!("\x1b[2J\x1b[H{}", text);
+        std::thread::sleep(Duration::from_secs(*opts.refresh_seconds()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_bar_marks_target() {
+        let bar = format_bar(Lamports(50), Some(Lamports(80)), Lamports(100));
+        assert_eq!(bar.len(), BAR_WIDTH);
+        assert_eq!(&bar[..15], "###############");
+        assert_eq!(&bar[15..24], ".........");
+        assert_eq!(&bar[24..25], "|");
+
+        // Without stake, the bar is empty.
+        assert_eq!(
+            format_bar(Lamports(0), None, Lamports(0)),
+            ".".repeat(BAR_WIDTH)
+        );
+
+        // The target marker stays inside the bar when it is the maximum.
+        assert!(format_bar(Lamports(100), Some(Lamports(100)), Lamports(100)).ends_with("#|"));
+    }
+}
@@ -0,0 +1,391 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Send a transaction, and keep track of it until it lands or expires.
+//!
+//! RPC nodes forward transactions to the leader on a best-effort basis, and
+//! sometimes a transaction gets dropped along the way. Other times, the
+//! transaction lands, but the node reports its status only after we stopped
+//! waiting. If we would report an error in both cases, the daemon would try
+//! again with a new transaction in the next iteration, and for the second case,
+//! it would count the operation twice.
+//!
+//! Instead, we remember the signature and recent blockhash of the transaction,
+//! and broadcast it again until it lands, or until its blockhash expires. A
+//! transaction with an expired blockhash can no longer land, so then we check
+//! its status one last time, and only report an error if it did not land.
+//!
+//! With `--broadcast-transactions`, we send every broadcast to all RPC
+//! endpoints at once, see [`broadcast_and_confirm_transaction`].
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use solana_client::client_error::Result as ClientResult;
+use solana_client::rpc_request::RpcError;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{uses_durable_nonce, Result as TransactionResult, Transaction};
+
+use crate::rpc::Rpc;
+
+/// How often to check the status of a transaction that we sent.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait for a transaction to land before we broadcast it again.
+pub const DEFAULT_REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Timing of [`send_and_confirm_transaction`].
+#[derive(Copy, Clone, Debug)]
+pub struct TrackerOptions {
+    pub poll_interval: Duration,
+    pub rebroadcast_interval: Duration,
+}
+
+impl Default for TrackerOptions {
+    fn default() -> TrackerOptions {
+        TrackerOptions {
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            rebroadcast_interval: DEFAULT_REBROADCAST_INTERVAL,
+        }
+    }
+}
+
+/// What happened to the transactions that we sent, since launch.
+///
+/// Fields that are missing in a state file of an older version default to zero.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TransactionCounts {
+    /// Transactions that landed, including transactions that failed on-chain.
+    pub landed: u64,
+
+    /// Broadcasts that did not land within the rebroadcast interval, after
+    /// which we broadcast the transaction again.
+    pub dropped: u64,
+
+    /// Transactions whose blockhash expired before they landed.
+    pub expired: u64,
+}
+
+/// Per endpoint, what happened when we broadcast transactions to it.
+///
+/// We only count this for [`broadcast_and_confirm_transaction`] with multiple
+/// endpoints, see `--broadcast-transactions`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct BroadcastCounts {
+    /// Broadcasts that the endpoint accepted.
+    pub accepted: u64,
+
+    /// Broadcasts that the endpoint rejected, or that did not reach it.
+    pub failed: u64,
+
+    /// Transactions for which this endpoint was the first to report that they landed.
+    pub confirmed_first: u64,
+}
+
+/// Send a transaction, and wait for it to reach the given commitment level.
+///
+/// See the module documentation for how we deal with dropped transactions.
+pub fn send_and_confirm_transaction(
+    rpc: &dyn Rpc,
+    transaction: &Transaction,
+    commitment: CommitmentConfig,
+    options: &TrackerOptions,
+    counts: &mut TransactionCounts,
+) -> ClientResult<Signature> {
+    broadcast_and_confirm_transaction(
+        &[rpc],
+        transaction,
+        commitment,
+        options,
+        counts,
+        &mut [BroadcastCounts::default()],
+    )
+}
+
+/// Send the transaction to all endpoints at once, and count per endpoint whether it accepted it.
+///
+/// The signature is the same for every endpoint. We only return an error when
+/// no endpoint accepted the transaction, then it is the error of the first one.
+fn broadcast(
+    rpcs: &[&dyn Rpc],
+    transaction: &Transaction,
+    commitment: CommitmentConfig,
+    skip_preflight: bool,
+    broadcast_counts: &mut [BroadcastCounts],
+) -> ClientResult<Signature> {
+    let send = |rpc: &&dyn Rpc| rpc.send_transaction(transaction, commitment, skip_preflight);
+
+    // With a single endpoint, we don't need a thread.
+    let results: Vec<ClientResult<Signature>> = if rpcs.len() == 1 {
+        vec![send(&rpcs[0])]
+    } else {
+        crossbeam_utils::thread::scope(|scope| {
+            let handles: Vec<_> = rpcs
+                .iter()
+                .map(|rpc| scope.spawn(move |_| send(rpc)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("SendTransaction thread panicked."))
+                .collect()
+        })
+        .expect("SendTransaction thread panicked.")
+    };
+
+    let mut signature = None;
+    let mut first_error = None;
+    for (result, counts) in results.into_iter().zip(broadcast_counts.iter_mut()) {
+        match result {
+            Ok(accepted_signature) => {
+                counts.accepted += 1;
+                signature = Some(accepted_signature);
+            }
+            Err(err) => {
+                counts.failed += 1;
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    match signature {
+        Some(signature) => Ok(signature),
+        None => Err(first_error.expect("There is at least one endpoint.")),
+    }
+}
+
+/// Return the status of the transaction from the first endpoint that knows it,
+/// together with the index of that endpoint.
+///
+/// An endpoint that fails is as good as one that does not know the status yet,
+/// unless all of them fail, then we return the error of the last one.
+fn get_signature_status(
+    rpcs: &[&dyn Rpc],
+    signature: &Signature,
+    commitment: CommitmentConfig,
+) -> ClientResult<Option<(usize, TransactionResult<()>)>> {
+    let mut num_failed = 0;
+    let mut last_error = None;
+    for (i, rpc) in rpcs.iter().enumerate() {
+        match rpc.get_signature_status(signature, commitment) {
+            Ok(Some(result)) => return Ok(Some((i, result))),
+            Ok(None) => {}
+            Err(err) => {
+                num_failed += 1;
+                last_error = Some(err);
+            }
+        }
+    }
+    match last_error {
+        Some(err) if num_failed == rpcs.len() => Err(err),
+        _ => Ok(None),
+    }
+}
+
+/// Return whether any endpoint considers the blockhash valid.
+///
+/// An endpoint that lags behind may not know the blockhash yet, so one
+/// endpoint that knows it is enough. Errors are like in [`get_signature_status`].
+fn is_blockhash_valid(rpcs: &[&dyn Rpc], blockhash: &Hash) -> ClientResult<bool> {
+    let mut num_failed = 0;
+    let mut last_error = None;
+    for rpc in rpcs {
+        match rpc.is_blockhash_valid(blockhash) {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            Err(err) => {
+                num_failed += 1;
+                last_error = Some(err);
+            }
+        }
+    }
+    match last_error {
+        Some(err) if num_failed == rpcs.len() => Err(err),
+        _ => Ok(false),
+    }
+}
+
+/// Send a transaction to multiple endpoints, and wait for it to reach the
+/// given commitment level on any of them.
+///
+/// A transaction lands at most once, the leader rejects duplicates, so it is
+/// safe to submit it through several RPC nodes. This way, one node that is
+/// slow to forward transactions, or that drops them, does not hold us up. The
+/// first endpoint that reports the status of the transaction wins. Otherwise
+/// this is like [`send_and_confirm_transaction`], which is the case of a
+/// single endpoint. `broadcast_counts` has an entry per endpoint.
+pub fn broadcast_and_confirm_transaction(
+    rpcs: &[&dyn Rpc],
+    transaction: &Transaction,
+    commitment: CommitmentConfig,
+    options: &TrackerOptions,
+    counts: &mut TransactionCounts,
+    broadcast_counts: &mut [BroadcastCounts],
+) -> ClientResult<Signature> {
+    assert!(!rpcs.is_empty(), "Need at least one RPC endpoint.");
+    assert_eq!(rpcs.len(), broadcast_counts.len());
+    let signature = broadcast(rpcs, transaction, commitment, false, broadcast_counts)?;
+    let mut broadcast_at = Instant::now();
+
+    let recent_blockhash = if uses_durable_nonce(transaction).is_some() {
+        // The blockhash is a nonce, which does not expire. Check whether the
+        // transaction could still land by the current blockhash instead.
+        rpcs[0].get_latest_blockhash()?
+    } else {
+        transaction.message.recent_blockhash
+    };
+
+    loop {
+        if let Some((i, result)) = get_signature_status(rpcs, &signature, commitment)? {
+            counts.landed += 1;
+            broadcast_counts[i].confirmed_first += 1;
+            return result.map(|()| signature).map_err(|err| err.into());
+        }
+
+        if !is_blockhash_valid(rpcs, &recent_blockhash)? {
+            // The transaction can no longer land, but it may have landed right
+            // before the blockhash expired, so check one last time.
+            if let Some((i, result)) = get_signature_status(rpcs, &signature, commitment)? {
+                counts.landed += 1;
+                broadcast_counts[i].confirmed_first += 1;
+                return result.map(|()| signature).map_err(|err| err.into());
+            }
+            counts.expired += 1;
+            return Err(RpcError::ForUser(
+                "unable to confirm transaction. This can happen in situations \
+                such as transaction expiration and insufficient fee-payer funds"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        if broadcast_at.elapsed() >= options.rebroadcast_interval {
+            // At a higher commitment level, a transaction that landed can take
+            // a while to confirm, we should not broadcast that again.
+            let processed = get_signature_status(rpcs, &signature, CommitmentConfig::processed())?;
+            if processed.is_none() {
+                counts.dropped += 1;
+                // If this fails, the next status check tells us what happened.
+                let _ = broadcast(rpcs, transaction, commitment, true, broadcast_counts);
+            }
+            broadcast_at = Instant::now();
+        }
+
+        std::thread::sleep(options.poll_interval);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::system_instruction;
+
+    use super::*;
+    use crate::mock_rpc::{MockRpc, SendFault};
+
+    const FAST: TrackerOptions = TrackerOptions {
+        poll_interval: Duration::from_millis(0),
+        rebroadcast_interval: Duration::from_millis(0),
+    };
+
+    fn new_transaction() -> Transaction {
+        let payer = Keypair::new();
+        let instruction =
+            system_instruction::transfer(&payer.pubkey(), &Keypair::new().pubkey(), 1);
+        Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::new_unique(),
+        )
+    }
+
+    fn send(rpc: &MockRpc, counts: &mut TransactionCounts) -> ClientResult<Signature> {
+        send_and_confirm_transaction(
+            rpc,
+            &new_transaction(),
+            CommitmentConfig::confirmed(),
+            &FAST,
+            counts,
+        )
+    }
+
+    #[test]
+    fn test_dropped_transaction_is_broadcast_again() {
+        let rpc = MockRpc::new();
+        let mut counts = TransactionCounts::default();
+        rpc.push_send_fault(SendFault::Dropped);
+        assert!(send(&rpc, &mut counts).is_ok());
+        assert_eq!(rpc.sent_transactions().len(), 1);
+        assert_eq!(
+            counts,
+            TransactionCounts {
+                landed: 1,
+                dropped: 1,
+                expired: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_expired_transaction_fails() {
+        let rpc = MockRpc::new();
+        let mut counts = TransactionCounts::default();
+        rpc.push_send_fault(SendFault::Expired);
+        assert!(send(&rpc, &mut counts).is_err());
+        assert!(rpc.sent_transactions().is_empty());
+        assert_eq!(counts.landed, 0);
+        assert_eq!(counts.expired, 1);
+    }
+
+    #[test]
+    fn test_transaction_that_landed_late_is_not_an_error() {
+        let rpc = MockRpc::new();
+        let mut counts = TransactionCounts::default();
+        rpc.push_send_fault(SendFault::LandedLate);
+        assert!(send(&rpc, &mut counts).is_ok());
+        assert_eq!(rpc.sent_transactions().len(), 1);
+        assert_eq!(counts.landed, 1);
+        assert_eq!(counts.expired, 0);
+    }
+
+    #[test]
+    fn test_broadcast_confirms_through_any_endpoint() {
+        let slow = MockRpc::new();
+        let fast = MockRpc::new();
+        let mut counts = TransactionCounts::default();
+        let mut broadcast_counts = vec![BroadcastCounts::default(); 2];
+        slow.push_send_fault(SendFault::Dropped);
+        let result = broadcast_and_confirm_transaction(
+            &[&slow, &fast],
+            &new_transaction(),
+            CommitmentConfig::confirmed(),
+            &FAST,
+            &mut counts,
+            &mut broadcast_counts,
+        );
+        assert!(result.is_ok());
+        assert!(slow.sent_transactions().is_empty());
+        assert_eq!(fast.sent_transactions().len(), 1);
+        assert_eq!(counts.landed, 1);
+        assert_eq!(counts.dropped, 0);
+        assert_eq!(
+            broadcast_counts,
+            vec![
+                BroadcastCounts {
+                    accepted: 1,
+                    failed: 0,
+                    confirmed_first: 0,
+                },
+                BroadcastCounts {
+                    accepted: 1,
+                    failed: 0,
+                    confirmed_first: 1,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Serving http on a unix domain socket, for `--listen unix:<path>`.
+//!
+//! tiny_http only binds TCP sockets, so for a unix socket we accept the
+//! connections ourselves, parse the request line and headers, and let
+//! tiny_http write the response. We serve one request per connection, and
+//! ignore request bodies, which is all that the daemon's endpoints need.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tiny_http::{HTTPVersion, Header, Method, Response};
+
+/// Prefix of `--listen` addresses that are the path of a unix socket.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Upper bound on the size of the request line and headers that we read.
+const MAX_REQUEST_HEAD_BYTES: u64 = 16 * 1024;
+
+/// Time after which we give up on a client that does not finish its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct UnixHttpServer {
+    listener: UnixListener,
+    path: PathBuf,
+    stop: AtomicBool,
+}
+
+impl UnixHttpServer {
+    /// Bind a socket at `path`, replacing the socket of a previous run, if any.
+    pub fn bind(path: &Path) -> io::Result<UnixHttpServer> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(UnixHttpServer {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+            stop: AtomicBool::new(false),
+        })
+    }
+
+    /// Block until the next request arrives, or return `None` after `unblock`.
+    pub fn recv(&self) -> Option<UnixRequest> {
+        loop {
+            let accepted = self.listener.accept();
+            if self.stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            match accepted.and_then(|(stream, _addr)| UnixRequest::read(stream)) {
+                Ok(Some(request)) => return Some(request),
+                // If the client sends garbage or hangs up, there is nobody to
+                // report the error to, move on to the next connection.
+                Ok(None) | Err(..) => continue,
+            }
+        }
+    }
+
+    /// Make one thread that is blocked in `recv` return `None`, like `Server::unblock`.
+    pub fn unblock(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Connecting wakes up a thread that is blocked in `accept`.
+        let _ = UnixStream::connect(&self.path);
+    }
+}
+
+impl Drop for UnixHttpServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct UnixRequest {
+    method: Method,
+    url: String,
+    headers: Vec<Header>,
+    stream: UnixStream,
+}
+
+impl UnixRequest {
+    /// Read the request line and headers, or return `None` if they are malformed.
+    fn read(stream: UnixStream) -> io::Result<Option<UnixRequest>> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?).take(MAX_REQUEST_HEAD_BYTES);
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, url) = match (parts.next().map(Method::from_str), parts.next()) {
+            (Some(Ok(method)), Some(url)) => (method, url.to_string()),
+            _ => return Ok(None),
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                // The connection ended, or the head is too large, before the
+                // empty line that ends the headers.
+                return Ok(None);
+            }
+            let header_line = line.trim_end();
+            if header_line.is_empty() {
+                break;
+            }
+            let mut parts = header_line.splitn(2, ':');
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                if let Ok(header) = Header::from_bytes(name.trim(), value.trim()) {
+                    headers.push(header);
+                }
+            }
+        }
+
+        Ok(Some(UnixRequest {
+            method,
+            url,
+            headers,
+            stream,
+        }))
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+
+    /// Write the response, and close the connection.
+    pub fn respond<R: Read>(self, response: Response<R>) -> io::Result<()> {
+        let is_head = self.method == Method::Head;
+        // Http 1.0 has no keep-alive by default, which matches that we close
+        // the connection after one request.
+        response.raw_print(self.stream, HTTPVersion(1, 0), &self.headers, is_head, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_serves_request_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.sock");
+        let server = UnixHttpServer::bind(&path).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nAuthorization: Bearer hunter2\r\n\r\n")
+            .unwrap();
+
+        let request = server.recv().unwrap();
+        assert_eq!(*request.method(), Method::Get);
+        assert_eq!(request.url(), "/metrics");
+        assert_eq!(request.headers().len(), 1);
+        assert!(request.headers()[0].field.equiv("Authorization"));
+        assert_eq!(request.headers()[0].value.as_str(), "Bearer hunter2");
+        request.respond(Response::from_string("ok")).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        // Unblocking stops a thread that waits for the next request.
+        server.unblock();
+        assert!(server.recv().is_none());
+    }
+}
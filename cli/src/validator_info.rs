@@ -0,0 +1,449 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Reading what validators publish about themselves: the settings in their
+//! vote account, their voting record, and their name from validator-info.
+//!
+//! Validators publish their name with `solana validator-info publish`, which
+//! stores it in an account of the config program, signed by the validator
+//! identity. We only use this to label metrics, so a validator that publishes
+//! nothing, or a vote account that we cannot parse, is not an error.
+
+use solana_program::clock::{Epoch, Slot};
+use solana_program::pubkey::Pubkey;
+
+use crate::logging::Event;
+use crate::snapshot::{Result, Snapshot};
+
+/// The config program, that owns the validator-info accounts.
+pub mod config_program {
+    solana_program::declare_id!("Config1111111111111111111111111111111111111");
+}
+
+/// The key that the config program stores first in every validator-info account.
+pub mod validator_info_key {
+    solana_program::declare_id!("Va1idator1nfo111111111111111111111111111111");
+}
+
+/// Offset of the validator identity in a validator-info account.
+///
+/// The account starts with the config keys: a compact-u16 length (one byte
+/// for two keys), then `(Pubkey, bool)` pairs. The first pair holds
+/// [`validator_info_key`], the second one the identity.
+pub const VALIDATOR_INFO_IDENTITY_OFFSET: usize = 1 + 32 + 1;
+
+/// Number of epochs that the vote program keeps in `epoch_credits`.
+const MAX_EPOCH_CREDITS_HISTORY: u64 = 64;
+
+/// What a validator publishes about itself, as far as we could read it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidatorIdentity {
+    /// The validator identity, the node pubkey in the vote account.
+    pub node_pubkey: Option<Pubkey>,
+
+    /// The commission in the vote account, in percent.
+    pub commission: Option<u8>,
+
+    /// The name that the validator published in its validator-info account.
+    pub name: Option<String>,
+}
+
+/// Read the node pubkey and commission from vote account data.
+///
+/// All vote state versions since 1.14.11 start with the same fields, we do not
+/// support the older layout, there should be no such vote accounts left.
+pub fn parse_vote_account(data: &[u8]) -> Option<(Pubkey, u8)> {
+    if data.len() < 69 {
+        return None;
+    }
+    let mut version = [0_u8; 4];
+    version.copy_from_slice(&data[0..4]);
+    match u32::from_le_bytes(version) {
+        1 | 2 => {
+            let node_pubkey = Pubkey::new(&data[4..36]);
+            // Skip over the authorized withdrawer at 36..68.
+            let commission = data[68];
+            Some((node_pubkey, commission))
+        }
+        _ => None,
+    }
+}
+
+/// The recent voting record of a validator, from its vote account.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VoteHistory {
+    /// The slot that the validator voted on most recently, if it voted at all.
+    pub last_vote_slot: Option<Slot>,
+
+    /// Per epoch, the total credits at the end of the epoch, and at its start.
+    ///
+    /// The vote program adds an entry in the first epoch that earns credits,
+    /// so an epoch without entry is an epoch without credits.
+    pub epoch_credits: Vec<(Epoch, u64, u64)>,
+}
+
+impl VoteHistory {
+    /// Return the credits that the validator earned over the lifetime of the vote account.
+    pub fn credits(&self) -> u64 {
+        self.epoch_credits
+            .last()
+            .map(|&(_epoch, credits, _prev_credits)| credits)
+            .unwrap_or(0)
+    }
+
+    fn earned_credits_in(&self, epoch: Epoch) -> bool {
+        self.epoch_credits
+            .iter()
+            .any(|&(e, credits, prev_credits)| e == epoch && credits > prev_credits)
+    }
+
+    /// Return in how many of the `num_epochs` completed epochs before
+    /// `current_epoch` the validator earned credits, and how many epochs we
+    /// looked at. That is fewer than `num_epochs` when the cluster is young,
+    /// or when the vote account does not keep that much history.
+    pub fn epochs_with_credits(&self, current_epoch: Epoch, num_epochs: u64) -> (u64, u64) {
+        let first_epoch = current_epoch.saturating_sub(num_epochs.min(MAX_EPOCH_CREDITS_HISTORY));
+        let with_credits = (first_epoch..current_epoch)
+            .filter(|&epoch| self.earned_credits_in(epoch))
+            .count() as u64;
+        (with_credits, current_epoch - first_epoch)
+    }
+
+    /// Return for how many consecutive epochs the validator earned no credits.
+    ///
+    /// Only completed epochs count, the current epoch can only reset the count
+    /// to zero, when the validator already earned credits in it. We look back
+    /// at most as far as the vote account keeps history.
+    pub fn delinquent_epochs(&self, current_epoch: Epoch) -> u64 {
+        if self.earned_credits_in(current_epoch) {
+            return 0;
+        }
+        let first_epoch = current_epoch.saturating_sub(MAX_EPOCH_CREDITS_HISTORY);
+        (first_epoch..current_epoch)
+            .rev()
+            .take_while(|&epoch| !self.earned_credits_in(epoch))
+            .count() as u64
+    }
+}
+
+/// Read the last vote and the epoch credits from vote account data.
+///
+/// Like [`parse_vote_account`], this supports the layouts of 1.14.11 and later,
+/// they differ only in the size of a vote.
+pub fn parse_vote_history(data: &[u8]) -> Option<VoteHistory> {
+    fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if data.len() < n {
+            return None;
+        }
+        let (result, rest) = data.split_at(n);
+        *data = rest;
+        Some(result)
+    }
+    fn take_u64(data: &mut &[u8]) -> Option<u64> {
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(take(data, 8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    parse_vote_account(data)?;
+    let vote_size = match data[0] {
+        // A `Lockout`: the slot and the confirmation count.
+        1 => 12,
+        // A `LandedVote`: the latency, and a `Lockout`.
+        2 => 13,
+        _ => return None,
+    };
+    // The version, node pubkey, authorized withdrawer, and commission.
+    let mut data = &data[69..];
+
+    let num_votes = take_u64(&mut data)? as usize;
+    let votes = take(&mut data, num_votes.checked_mul(vote_size)?)?;
+    let last_vote_slot = votes.chunks_exact(vote_size).last().map(|vote| {
+        let mut slot = [0_u8; 8];
+        slot.copy_from_slice(&vote[vote_size - 12..vote_size - 4]);
+        u64::from_le_bytes(slot)
+    });
+
+    // The root slot is an `Option<Slot>`.
+    if take(&mut data, 1)?[0] == 1 {
+        take_u64(&mut data)?;
+    }
+
+    // The authorized voters map epochs to pubkeys.
+    let num_authorized_voters = take_u64(&mut data)? as usize;
+    take(&mut data, num_authorized_voters.checked_mul(8 + 32)?)?;
+
+    // The prior voters are a circular buffer of 32 `(Pubkey, Epoch, Epoch)`,
+    // followed by the index and a bool.
+    take(&mut data, 32 * (32 + 8 + 8) + 8 + 1)?;
+
+    let num_epoch_credits = take_u64(&mut data)? as usize;
+    let mut epoch_credits = Vec::with_capacity(num_epoch_credits.min(64));
+    for _ in 0..num_epoch_credits {
+        let epoch = take_u64(&mut data)?;
+        let credits = take_u64(&mut data)?;
+        let prev_credits = take_u64(&mut data)?;
+        epoch_credits.push((epoch, credits, prev_credits));
+    }
+
+    Some(VoteHistory {
+        last_vote_slot,
+        epoch_credits,
+    })
+}
+
+/// Everything we read from a vote account.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VoteAccountState {
+    /// The validator identity.
+    pub node_pubkey: Pubkey,
+
+    /// The key that can withdraw from the vote account, and change its commission.
+    pub authorized_withdrawer: Pubkey,
+
+    /// The commission, in percent.
+    pub commission: u8,
+
+    /// The recent votes and the credits per epoch.
+    pub history: VoteHistory,
+}
+
+/// Parse vote account data, see [`parse_vote_account`] for the supported layouts.
+pub fn parse_vote_account_state(data: &[u8]) -> Option<VoteAccountState> {
+    let (node_pubkey, commission) = parse_vote_account(data)?;
+    let history = parse_vote_history(data)?;
+    Some(VoteAccountState {
+        node_pubkey,
+        authorized_withdrawer: Pubkey::new(&data[36..68]),
+        commission,
+        history,
+    })
+}
+
+/// Read a vote account through the snapshot.
+///
+/// Returns `None` when the account holds data that we cannot parse. It is an
+/// error when the account does not exist.
+pub fn get_vote_account_state(
+    snapshot: &mut Snapshot,
+    vote_account: &Pubkey,
+) -> Result<Option<VoteAccountState>> {
+    let account = snapshot.get_account(vote_account)?;
+    Ok(parse_vote_account_state(&account.data))
+}
+
+/// Extract the validator name from a validator-info account, if it is signed by `identity`.
+pub fn parse_validator_name(data: &[u8], identity: &Pubkey) -> Option<String> {
+    let info_offset = VALIDATOR_INFO_IDENTITY_OFFSET + 32 + 1;
+    if data.len() < info_offset {
+        return None;
+    }
+    let num_keys = data[0];
+    let key = &data[1..33];
+    let signer = &data[VALIDATOR_INFO_IDENTITY_OFFSET..info_offset - 1];
+    let is_signer = data[info_offset - 1] == 1;
+    if num_keys != 2
+        || key != validator_info_key::id().as_ref()
+        || signer != identity.as_ref()
+        || !is_signer
+    {
+        return None;
+    }
+
+    // After the keys follows the info as a json string, serialized with bincode.
+    let info: String = bincode::deserialize(&data[info_offset..]).ok()?;
+    let info: serde_json::Value = serde_json::from_str(&info).ok()?;
+    info.get("name")?.as_str().map(|name| name.to_string())
+}
+
+/// Read the identity and commission of a validator, and the name it published.
+pub fn get_validator_identity(
+    snapshot: &mut Snapshot,
+    vote_account: &Pubkey,
+) -> Result<ValidatorIdentity> {
+    let account = snapshot.get_account(vote_account)?;
+    let (node_pubkey, commission) = match parse_vote_account(&account.data) {
+        Some(result) => result,
+        None => return Ok(ValidatorIdentity::default()),
+    };
+    let name = match snapshot.get_validator_name(&node_pubkey) {
+        Ok(name) => name,
+        Err(err) => {
+            Event::warning(
+                "validator_info_unavailable",
+                format!("Failed to read validator info of {}.", node_pubkey),
+            )
+            .field("vote_account", vote_account.to_string())
+            .field("error", err.to_string())
+            .emit();
+            None
+        }
+    };
+    Ok(ValidatorIdentity {
+        node_pubkey: Some(node_pubkey),
+        commission: Some(commission),
+        name,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+    use solana_sdk::account::Account;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+    use crate::snapshot::SnapshotClient;
+
+    /// Serialize a validator-info account the way the config program stores it.
+    fn new_validator_info_data(identity: &Pubkey, info: &str) -> Vec<u8> {
+        #[derive(Serialize)]
+        struct ConfigKeys {
+            #[serde(with = "solana_sdk::short_vec")]
+            keys: Vec<(Pubkey, bool)>,
+        }
+        let keys = ConfigKeys {
+            keys: vec![(validator_info_key::id(), false), (*identity, true)],
+        };
+        let mut data = bincode::serialize(&keys).unwrap();
+        data.extend(bincode::serialize(info).unwrap());
+        // The account is allocated larger than the info, the rest is zero.
+        data.resize(data.len() + 100, 0);
+        data
+    }
+
+    fn new_vote_account_data(node_pubkey: &Pubkey, commission: u8) -> Vec<u8> {
+        let mut data = vec![0; 3762];
+        data[0..4].copy_from_slice(&2_u32.to_le_bytes());
+        data[4..36].copy_from_slice(node_pubkey.as_ref());
+        data[36..68].copy_from_slice(Pubkey::new_unique().as_ref());
+        data[68] = commission;
+        data
+    }
+
+    #[test]
+    fn test_parse_validator_name() {
+        let identity = Pubkey::new_unique();
+        let data = new_validator_info_data(&identity, r#"{"name":"Chorus One","website":""}"#);
+        assert_eq!(
+            parse_validator_name(&data, &identity),
+            Some("Chorus One".to_string())
+        );
+
+        // Info signed by a different identity does not name this validator.
+        assert_eq!(parse_validator_name(&data, &Pubkey::new_unique()), None);
+
+        // Info without a name has no name.
+        let data = new_validator_info_data(&identity, r#"{"website":""}"#);
+        assert_eq!(parse_validator_name(&data, &identity), None);
+    }
+
+    #[test]
+    fn test_parse_vote_account() {
+        let node_pubkey = Pubkey::new_unique();
+        let data = new_vote_account_data(&node_pubkey, 5);
+        assert_eq!(parse_vote_account(&data), Some((node_pubkey, 5)));
+        assert_eq!(parse_vote_account(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_vote_history() {
+        let node_pubkey = Pubkey::new_unique();
+        let mut data = new_vote_account_data(&node_pubkey, 5);
+        assert_eq!(parse_vote_history(&data), Some(VoteHistory::default()));
+
+        // Overwrite the empty state after the commission with two votes, a
+        // root slot, one authorized voter, and two epochs of credits.
+        let mut state = Vec::new();
+        state.extend(2_u64.to_le_bytes().iter());
+        for &slot in [100_u64, 101].iter() {
+            state.push(0);
+            state.extend(slot.to_le_bytes().iter());
+            state.extend(1_u32.to_le_bytes().iter());
+        }
+        state.push(1);
+        state.extend(99_u64.to_le_bytes().iter());
+        state.extend(1_u64.to_le_bytes().iter());
+        state.extend([0_u8; 8 + 32].iter());
+        state.extend([0_u8; 32 * 48 + 8 + 1].iter());
+        state.extend(2_u64.to_le_bytes().iter());
+        for &value in [3_u64, 10, 5, 4, 20, 10].iter() {
+            state.extend(value.to_le_bytes().iter());
+        }
+        data[69..69 + state.len()].copy_from_slice(&state);
+
+        let history = parse_vote_history(&data).unwrap();
+        assert_eq!(history.last_vote_slot, Some(101));
+        assert_eq!(history.epoch_credits, vec![(3, 10, 5), (4, 20, 10)]);
+
+        // Data that ends in the middle of the state is not a vote account.
+        assert_eq!(parse_vote_history(&data[..100]), None);
+
+        let state = parse_vote_account_state(&data).unwrap();
+        assert_eq!(state.node_pubkey, node_pubkey);
+        assert_eq!(state.authorized_withdrawer, Pubkey::new(&data[36..68]));
+        assert_eq!(state.commission, 5);
+        assert_eq!(state.history, history);
+        assert_eq!(state.history.credits(), 20);
+        assert_eq!(parse_vote_account_state(&data[..100]), None);
+    }
+
+    #[test]
+    fn test_delinquent_epochs() {
+        let history = VoteHistory {
+            last_vote_slot: None,
+            epoch_credits: vec![(3, 10, 5), (4, 20, 10), (5, 20, 20)],
+        };
+        // Credits in the current epoch reset the count.
+        assert_eq!(history.delinquent_epochs(4), 0);
+        // The current epoch without credits does not count.
+        assert_eq!(history.delinquent_epochs(5), 0);
+        // An epoch with an entry but without new credits does.
+        assert_eq!(history.delinquent_epochs(6), 1);
+        assert_eq!(history.delinquent_epochs(8), 3);
+
+        // A validator that never voted is delinquent as far as we can tell.
+        assert_eq!(VoteHistory::default().delinquent_epochs(3), 3);
+        assert_eq!(VoteHistory::default().delinquent_epochs(100), 64);
+    }
+
+    #[test]
+    fn test_get_validator_identity() {
+        let rpc = MockRpc::new();
+        let vote_account = Pubkey::new_unique();
+        let node_pubkey = Pubkey::new_unique();
+        rpc.set_account(
+            vote_account,
+            Account {
+                lamports: 1,
+                data: new_vote_account_data(&node_pubkey, 100),
+                owner: solana_vote_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        rpc.set_account(
+            Pubkey::new_unique(),
+            Account {
+                lamports: 1,
+                data: new_validator_info_data(&node_pubkey, r#"{"name":"Validator 1"}"#),
+                owner: config_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+
+        let mut client = SnapshotClient::new(rpc);
+        let identity = client
+            .with_snapshot(|mut snapshot| get_validator_identity(&mut snapshot, &vote_account))
+            .unwrap();
+        assert_eq!(
+            identity,
+            ValidatorIdentity {
+                node_pubkey: Some(node_pubkey),
+                commission: Some(100),
+                name: Some("Validator 1".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,498 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Evaluating the validator set, and candidate validators, against a curation policy.
+//!
+//! The policy is a TOML file with the thresholds that a validator has to meet,
+//! and the candidates that we consider adding:
+//!
+//! ```toml
+//! max_commission = 10
+//! uptime_epochs = 10
+//! min_uptime_percent = 90
+//! max_cluster_stake_percent = 1.0
+//! max_validators_per_datacenter = 2
+//!
+//! # Validator info does not say where a validator runs, so the policy maps
+//! # validator identities to datacenters.
+//! [datacenters]
+//! "4rd2dt1ZqoRR8CVtJvdwZm7jJWDYzv2rZSMn7kwWjNHb" = "AS24940-FRA"
+//!
+//! [[candidates]]
+//! vote_account = "Ct5CWvnhVJVCL5fHiPJ6ETFt4cxUmHTVzdhzBZ7k7ZAA"
+//! fee_account = "DJ7uiRpTC1RJVoWnbLPm5ze4f7dSwYBQjDUkp5MJF4Py"
+//! ```
+//!
+//! Active validators that violate the policy should be deactivated. Candidates
+//! that meet it should be added, best score first, as long as there is room in
+//! the validator list, and their datacenter is not full. The score combines
+//! uptime and commission, see [`ValidatorEvaluation::score`]. Rules that are
+//! not in the policy always pass.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use solana_program::clock::Epoch;
+use solana_program::pubkey::Pubkey;
+
+use lido::util::serialize_b58;
+
+use crate::snapshot::{Result, Snapshot};
+use crate::validator_info::{get_validator_identity, get_vote_account_state, VoteHistory};
+
+fn deserialize_b58<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Pubkey, D::Error> {
+    let address = String::deserialize(deserializer)?;
+    Pubkey::from_str(&address).map_err(serde::de::Error::custom)
+}
+
+fn default_uptime_epochs() -> u64 {
+    10
+}
+
+/// A validator that the policy considers adding.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Candidate {
+    #[serde(deserialize_with = "deserialize_b58")]
+    pub vote_account: Pubkey,
+
+    /// The stSOL account that receives the validation fee, needed to add the validator.
+    #[serde(deserialize_with = "deserialize_b58")]
+    pub fee_account: Pubkey,
+}
+
+/// The curation policy, as read from the TOML file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// The highest commission that a validator may charge, in percent.
+    pub max_commission: Option<u8>,
+
+    /// The number of completed epochs to compute the uptime over.
+    #[serde(default = "default_uptime_epochs")]
+    pub uptime_epochs: u64,
+
+    /// The lowest share of those epochs in which the validator must have earned vote credits.
+    pub min_uptime_percent: Option<u8>,
+
+    /// The highest share of the activated stake of the cluster that a
+    /// validator may have, to not concentrate stake further.
+    pub max_cluster_stake_percent: Option<f64>,
+
+    /// The highest number of Solido validators per datacenter. This only
+    /// limits additions, we do not deactivate validators for it.
+    pub max_validators_per_datacenter: Option<u32>,
+
+    /// The datacenter of every validator identity that we know it for.
+    #[serde(default)]
+    pub datacenters: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+}
+
+impl Policy {
+    pub fn from_toml(toml: &str) -> std::result::Result<Policy, toml::de::Error> {
+        toml::from_str(toml)
+    }
+}
+
+/// What the policy says to do with a validator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// An active validator that meets the policy.
+    Keep,
+    /// An active validator that violates the policy.
+    Deactivate,
+    /// A candidate that meets the policy, and fits in the validator set.
+    Add,
+    /// A candidate that violates the policy, or that does not fit.
+    Reject,
+}
+
+/// A validator, what we know about it, and how it fares against the policy.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ValidatorEvaluation {
+    #[serde(serialize_with = "serialize_b58")]
+    pub vote_account: Pubkey,
+
+    /// The identity, or `None` if we could not read the vote account.
+    pub identity: Option<String>,
+    pub name: Option<String>,
+    pub datacenter: Option<String>,
+
+    /// The commission, in percent.
+    pub commission: Option<u8>,
+
+    /// The share of the last `uptime_epochs` completed epochs in which the
+    /// validator earned vote credits.
+    pub uptime_percent: u8,
+
+    /// The share of the activated stake of the cluster that the validator has.
+    pub cluster_stake_percent: f64,
+
+    /// Uptime times the share of rewards that the validator passes on, from 0 to 100.
+    pub score: u8,
+
+    /// The rules that the validator violates, empty if it meets the policy.
+    pub violations: Vec<String>,
+
+    pub decision: Decision,
+
+    /// The fee account to add a candidate with, `None` for validators of the instance.
+    #[serde(skip)]
+    pub fee_account: Option<Pubkey>,
+
+    /// The multisig transaction that proposes the decision, if we proposed it.
+    pub proposal: Option<String>,
+}
+
+/// What we read about a validator, to evaluate it.
+struct ValidatorFacts {
+    vote_account: Pubkey,
+    identity: Option<Pubkey>,
+    name: Option<String>,
+    commission: Option<u8>,
+    vote_history: Option<VoteHistory>,
+    activated_stake: u64,
+}
+
+impl Policy {
+    /// Evaluate a validator against the rules, except for the datacenter limit.
+    fn evaluate(
+        &self,
+        facts: ValidatorFacts,
+        current_epoch: Epoch,
+        total_cluster_stake: u64,
+        decision: Decision,
+    ) -> ValidatorEvaluation {
+        let mut violations = Vec::new();
+
+        let (with_credits, num_epochs) = match &facts.vote_history {
+            Some(history) => history.epochs_with_credits(current_epoch, self.uptime_epochs),
+            None => (0, 0),
+        };
+        let uptime_percent = match (&facts.vote_history, num_epochs) {
+            (None, _) => 0,
+            // Without completed epochs, there is nothing to hold against the validator.
+            (Some(_), 0) => 100,
+            (Some(_), n) => (with_credits * 100 / n) as u8,
+        };
+        let cluster_stake_percent = if total_cluster_stake == 0 {
+            0.0
+        } else {
+            facts.activated_stake as f64 * 100.0 / total_cluster_stake as f64
+        };
+
+        if facts.vote_history.is_none() {
+            violations.push("Vote account does not exist, or cannot be parsed.".to_string());
+        }
+        if let (Some(max_commission), Some(commission)) = (self.max_commission, facts.commission) {
+            if commission > max_commission {
+                violations.push(format!(
+                    "Commission of {}% is more than {}%.",
+                    commission, max_commission
+                ));
+            }
+        }
+        if let Some(min_uptime_percent) = self.min_uptime_percent {
+            if facts.vote_history.is_some() && uptime_percent < min_uptime_percent {
+                violations.push(format!(
+                    "Earned credits in {} of the last {} epochs, less than {}%.",
+                    with_credits, num_epochs, min_uptime_percent
+                ));
+            }
+        }
+        if let Some(max_cluster_stake_percent) = self.max_cluster_stake_percent {
+            if cluster_stake_percent > max_cluster_stake_percent {
+                violations.push(format!(
+                    "Has {:.2}% of the cluster stake, more than {}%.",
+                    cluster_stake_percent, max_cluster_stake_percent
+                ));
+            }
+        }
+
+        let score = match facts.commission {
+            Some(commission) => {
+                (uptime_percent as u32 * (100 - commission.min(100)) as u32 / 100) as u8
+            }
+            None => 0,
+        };
+        let decision = match (decision, violations.is_empty()) {
+            (Decision::Keep, false) => Decision::Deactivate,
+            (Decision::Add, false) => Decision::Reject,
+            (decision, _) => decision,
+        };
+
+        ValidatorEvaluation {
+            vote_account: facts.vote_account,
+            identity: facts.identity.map(|identity| identity.to_string()),
+            name: facts.name,
+            datacenter: facts
+                .identity
+                .and_then(|identity| self.datacenters.get(&identity.to_string()).cloned()),
+            commission: facts.commission,
+            uptime_percent,
+            cluster_stake_percent,
+            score,
+            violations,
+            decision,
+            fee_account: None,
+            proposal: None,
+        }
+    }
+}
+
+/// Read what we need to know about a validator to evaluate it.
+fn get_validator_facts(
+    snapshot: &mut Snapshot,
+    vote_account: &Pubkey,
+    cluster_stakes: &HashMap<Pubkey, u64>,
+) -> Result<ValidatorFacts> {
+    let activated_stake = cluster_stakes.get(vote_account).cloned().unwrap_or(0);
+    if !snapshot.account_exists(vote_account)? {
+        return Ok(ValidatorFacts {
+            vote_account: *vote_account,
+            identity: None,
+            name: None,
+            commission: None,
+            vote_history: None,
+            activated_stake,
+        });
+    }
+    let vote_history = get_vote_account_state(snapshot, vote_account)?.map(|state| state.history);
+    let identity = get_validator_identity(snapshot, vote_account)?;
+    Ok(ValidatorFacts {
+        vote_account: *vote_account,
+        identity: identity.node_pubkey,
+        name: identity.name,
+        commission: identity.commission,
+        vote_history,
+        activated_stake,
+    })
+}
+
+/// The evaluation of the validators of a Solido instance, and of the candidates.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ValidatorPolicyReport {
+    pub epoch: Epoch,
+
+    /// The active validators of the instance first, in their order in the
+    /// validator list, then the candidates, best score first.
+    pub validators: Vec<ValidatorEvaluation>,
+}
+
+impl ValidatorPolicyReport {
+    pub fn new(
+        snapshot: &mut Snapshot,
+        solido_address: &Pubkey,
+        policy: &Policy,
+    ) -> Result<ValidatorPolicyReport> {
+        let solido = snapshot.get_solido(solido_address)?;
+        let clock = snapshot.get_clock()?;
+        let cluster_stakes: HashMap<Pubkey, u64> =
+            snapshot.get_vote_account_stakes()?.into_iter().collect();
+        let total_cluster_stake: u64 = cluster_stakes.values().sum();
+
+        // Inactive validators are on their way out already, we leave them be.
+        let mut validators = Vec::new();
+        for validator in solido.validators.entries.iter() {
+            if !validator.entry.active {
+                continue;
+            }
+            let facts = get_validator_facts(snapshot, &validator.pubkey, &cluster_stakes)?;
+            validators.push(policy.evaluate(
+                facts,
+                clock.epoch,
+                total_cluster_stake,
+                Decision::Keep,
+            ));
+        }
+
+        // Candidates that are in the validator list already, are evaluated
+        // above if they are active, and cannot be added again if not.
+        let mut candidates = Vec::new();
+        for candidate in policy.candidates.iter() {
+            if solido.validators.get(&candidate.vote_account).is_ok() {
+                continue;
+            }
+            let facts = get_validator_facts(snapshot, &candidate.vote_account, &cluster_stakes)?;
+            let mut evaluation =
+                policy.evaluate(facts, clock.epoch, total_cluster_stake, Decision::Add);
+            evaluation.fee_account = Some(candidate.fee_account);
+            candidates.push(evaluation);
+        }
+        candidates.sort_by(|a, b| b.score.cmp(&a.score));
+
+        // Fill the free slots with the best candidates, as long as their
+        // datacenter has room. Validators that we deactivate still occupy
+        // their slot until they are removed.
+        let mut free_slots = (solido.validators.maximum_entries as usize)
+            .saturating_sub(solido.validators.entries.len());
+        let mut per_datacenter: HashMap<String, u32> = HashMap::new();
+        for evaluation in validators.iter() {
+            if let (Decision::Keep, Some(datacenter)) =
+                (evaluation.decision, &evaluation.datacenter)
+            {
+                *per_datacenter.entry(datacenter.clone()).or_insert(0) += 1;
+            }
+        }
+        for evaluation in candidates.iter_mut() {
+            if evaluation.decision != Decision::Add {
+                continue;
+            }
+            if free_slots == 0 {
+                evaluation.decision = Decision::Reject;
+                evaluation
+                    .violations
+                    .push("No free slot in the validator list.".to_string());
+                continue;
+            }
+            if let (Some(max), Some(datacenter)) =
+                (policy.max_validators_per_datacenter, &evaluation.datacenter)
+            {
+                let count = per_datacenter.entry(datacenter.clone()).or_insert(0);
+                if *count >= max {
+                    evaluation.decision = Decision::Reject;
+                    evaluation.violations.push(format!(
+                        "Datacenter {} has {} validators already.",
+                        datacenter, count
+                    ));
+                    continue;
+                }
+                *count += 1;
+            }
+            free_slots -= 1;
+        }
+
+        validators.extend(candidates);
+        Ok(ValidatorPolicyReport {
+            epoch: clock.epoch,
+            validators,
+        })
+    }
+}
+
+impl fmt::Display for ValidatorPolicyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Epoch: {}", self.epoch)?;
+        for validator in &self.validators {
+            writeln!(f, "\nValidator {}", validator.vote_account)?;
+            if let Some(name) = &validator.name {
+                writeln!(f, "  Name:          {}", name)?;
+            }
+            if let Some(datacenter) = &validator.datacenter {
+                writeln!(f, "  Datacenter:    {}", datacenter)?;
+            }
+            match validator.commission {
+                Some(commission) => writeln!(f, "  Commission:    {}%", commission)?,
+                None => writeln!(f, "  Commission:    unknown")?,
+            }
+            writeln!(f, "  Uptime:        {}%", validator.uptime_percent)?;
+            writeln!(
+                f,
+                "  Cluster stake: {:.2}%",
+                validator.cluster_stake_percent
+            )?;
+            writeln!(f, "  Score:         {}", validator.score)?;
+            for violation in &validator.violations {
+                writeln!(f, "  - {}", violation)?;
+            }
+            writeln!(f, "  Decision:      {:?}", validator.decision)?;
+            if let Some(proposal) = &validator.proposal {
+                writeln!(f, "  Proposed in multisig transaction {}", proposal)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_facts(
+        commission: u8,
+        epochs_with_credits: &[Epoch],
+        activated_stake: u64,
+    ) -> ValidatorFacts {
+        ValidatorFacts {
+            vote_account: Pubkey::new_unique(),
+            identity: Some(Pubkey::new_unique()),
+            name: None,
+            commission: Some(commission),
+            vote_history: Some(VoteHistory {
+                last_vote_slot: None,
+                epoch_credits: epochs_with_credits
+                    .iter()
+                    .map(|&epoch| (epoch, epoch * 10 + 10, epoch * 10))
+                    .collect(),
+            }),
+            activated_stake,
+        }
+    }
+
+    #[test]
+    fn test_policy_from_toml() {
+        let policy = Policy::from_toml(
+            r#"
+            max_commission = 10
+            max_cluster_stake_percent = 0.5
+
+            [datacenters]
+            "4rd2dt1ZqoRR8CVtJvdwZm7jJWDYzv2rZSMn7kwWjNHb" = "AS24940-FRA"
+
+            [[candidates]]
+            vote_account = "Ct5CWvnhVJVCL5fHiPJ6ETFt4cxUmHTVzdhzBZ7k7ZAA"
+            fee_account = "DJ7uiRpTC1RJVoWnbLPm5ze4f7dSwYBQjDUkp5MJF4Py"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.max_commission, Some(10));
+        assert_eq!(policy.min_uptime_percent, None);
+        assert_eq!(policy.uptime_epochs, 10);
+        assert_eq!(policy.datacenters.len(), 1);
+        assert_eq!(
+            policy.candidates[0].vote_account.to_string(),
+            "Ct5CWvnhVJVCL5fHiPJ6ETFt4cxUmHTVzdhzBZ7k7ZAA"
+        );
+
+        // Typos should not silently disable a rule.
+        assert!(Policy::from_toml("max_comission = 10").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_reports_every_violation() {
+        let policy = Policy::from_toml(
+            r#"
+            max_commission = 10
+            uptime_epochs = 4
+            min_uptime_percent = 75
+            max_cluster_stake_percent = 1.0
+            "#,
+        )
+        .unwrap();
+
+        // Credits in all of the epochs 6 through 9, little stake.
+        let good = policy.evaluate(new_facts(5, &[6, 7, 8, 9], 1), 10, 1_000, Decision::Keep);
+        assert_eq!(good.uptime_percent, 100);
+        assert_eq!(good.score, 95);
+        assert_eq!(good.violations, Vec::<String>::new());
+        assert_eq!(good.decision, Decision::Keep);
+
+        // Credits in only half of the epochs, high commission, and 2% of the stake.
+        let bad = policy.evaluate(new_facts(20, &[8, 9], 20), 10, 1_000, Decision::Keep);
+        assert_eq!(bad.uptime_percent, 50);
+        assert_eq!(bad.score, 40);
+        assert_eq!(bad.violations.len(), 3);
+        assert_eq!(bad.decision, Decision::Deactivate);
+
+        let candidate = policy.evaluate(new_facts(20, &[6, 7, 8, 9], 0), 10, 1_000, Decision::Add);
+        assert_eq!(candidate.decision, Decision::Reject);
+    }
+}
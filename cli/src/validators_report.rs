@@ -0,0 +1,473 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! An overview of the validator set, with performance and reward estimates.
+//!
+//! Next to the stake and target that the maintainer works with, this shows
+//! how well every validator does for stSOL holders. The skip rate comes from
+//! `getBlockProduction`, for the current epoch so far. The APY is estimated
+//! from the inflation rewards that the validator's stake accounts and vote
+//! account received in the last completed epoch, like the rewards report does
+//! for the pool as a whole. Its contribution is that APY weighted by the
+//! validator's share of the stake, so the contributions add up to the APY of
+//! the pool.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+use solana_program::clock::Epoch;
+use solana_program::pubkey::Pubkey;
+
+use lido::processor::StakeType;
+use lido::state::Lido;
+use lido::token::Lamports;
+use lido::util::serialize_b58;
+
+use crate::derivation_cache;
+use crate::rewards_report::{epochs_per_year, EpochRewards};
+use crate::snapshot::{Result, Snapshot};
+use crate::validator_info::get_validator_identity;
+
+/// The column to sort the validators by.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ValidatorSortKey {
+    /// Highest stake first.
+    Stake,
+
+    /// Highest estimated APY first.
+    Apy,
+
+    /// Lowest skip rate first.
+    SkipRate,
+
+    /// Alphabetically by name, validators without a name last.
+    Name,
+}
+
+impl FromStr for ValidatorSortKey {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<ValidatorSortKey, &'static str> {
+        match s {
+            "stake" => Ok(ValidatorSortKey::Stake),
+            "apy" => Ok(ValidatorSortKey::Apy),
+            "skip-rate" => Ok(ValidatorSortKey::SkipRate),
+            "name" => Ok(ValidatorSortKey::Name),
+            _ => Err("Invalid sort key, expected 'stake', 'apy', 'skip-rate', or 'name'."),
+        }
+    }
+}
+
+/// A validator in the validator set, and how it performs.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ValidatorSummary {
+    #[serde(serialize_with = "serialize_b58")]
+    pub vote_account: Pubkey,
+
+    /// The name that the validator published in its validator-info account.
+    pub name: Option<String>,
+
+    /// Whether the validator can receive new stake.
+    pub active: bool,
+
+    pub weight: u32,
+
+    /// Balance of the stake accounts, minus the unstake accounts.
+    #[serde(rename = "stake_lamports")]
+    pub stake: Lamports,
+
+    /// The balance that the maintainer stakes towards, `None` when no
+    /// validator receives stake.
+    #[serde(rename = "target_lamports")]
+    pub target: Option<Lamports>,
+
+    /// The commission in the vote account, in percent.
+    pub commission: Option<u8>,
+
+    /// Leader slots and produced blocks in the current epoch so far.
+    pub leader_slots: u64,
+    pub blocks_produced: u64,
+
+    /// Fraction of leader slots without a block, `None` if the validator
+    /// was not leader yet this epoch.
+    pub skip_rate: Option<f64>,
+
+    /// Appreciation relative to the validator's stake in the last completed
+    /// epoch, compounded over a year. `None` if there is no completed epoch.
+    pub apy: Option<f64>,
+
+    /// The APY weighted by the validator's share of the total stake.
+    pub apy_contribution: Option<f64>,
+}
+
+/// The validators of a Solido instance.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ValidatorsReport {
+    /// The current epoch, that the block production covers.
+    pub epoch: Epoch,
+
+    /// The epoch whose rewards the APY is estimated from.
+    pub rewards_epoch: Option<Epoch>,
+
+    pub validators: Vec<ValidatorSummary>,
+}
+
+impl ValidatorsReport {
+    pub fn new(
+        snapshot: &mut Snapshot,
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+        solido: &Lido,
+        sort_by: ValidatorSortKey,
+    ) -> Result<ValidatorsReport> {
+        let clock = snapshot.get_clock()?;
+        let epoch_schedule = snapshot.get_epoch_schedule()?;
+        let rent = snapshot.get_rent()?;
+        let reserve_address = solido.get_reserve_account(solido_program_id, solido_address)?;
+        let reserve = snapshot.get_account(&reserve_address)?;
+        let effective_reserve = Lamports(reserve.lamports.saturating_sub(rent.minimum_balance(0)));
+
+        // Computing the targets fails when no validator receives stake, in that
+        // case we only show the stake.
+        let targets = lido::balance::get_target_balance(
+            effective_reserve,
+            &solido.validators,
+            solido.get_max_stake_per_validator(),
+            None,
+        )
+        .ok();
+
+        let block_production: HashMap<Pubkey, _> =
+            snapshot.get_block_production()?.into_iter().collect();
+
+        // The rewards of an epoch are paid when the next epoch starts, so the
+        // last completed epoch is the most recent one with rewards.
+        let rewards_epoch = clock.epoch.checked_sub(1);
+        let vote_accounts: Vec<Pubkey> =
+            solido.validators.entries.iter().map(|v| v.pubkey).collect();
+        let mut stake_accounts = Vec::new();
+        let mut stake_account_ranges = Vec::new();
+        for validator in solido.validators.entries.iter() {
+            let begin = stake_accounts.len();
+            let seeds = [
+                (&validator.entry.stake_seeds, StakeType::Stake),
+                (&validator.entry.unstake_seeds, StakeType::Unstake),
+            ];
+            for &(seeds, stake_type) in seeds.iter() {
+                for seed in seeds {
+                    let (address, _bump_seed) = derivation_cache::find_stake_account_address(
+                        &validator.pubkey,
+                        solido_program_id,
+                        solido_address,
+                        seed,
+                        stake_type,
+                    );
+                    stake_accounts.push(address);
+                }
+            }
+            stake_account_ranges.push(begin..stake_accounts.len());
+        }
+        let rewards = match rewards_epoch {
+            Some(epoch) => Some((
+                epoch,
+                snapshot.get_inflation_reward(&stake_accounts, epoch)?,
+                snapshot.get_inflation_reward(&vote_accounts, epoch)?,
+            )),
+            None => None,
+        };
+
+        let total_stake: u64 = solido
+            .validators
+            .entries
+            .iter()
+            .map(|v| v.entry.effective_stake_balance().0)
+            .sum();
+
+        let mut validators = Vec::with_capacity(solido.validators.len());
+        for (i, validator) in solido.validators.entries.iter().enumerate() {
+            let identity = get_validator_identity(snapshot, &validator.pubkey)?;
+            let production = identity
+                .node_pubkey
+                .and_then(|node_pubkey| block_production.get(&node_pubkey).copied())
+                .unwrap_or_default();
+            let stake = validator.entry.effective_stake_balance();
+
+            let apy = rewards
+                .as_ref()
+                .map(|(epoch, stake_rewards, vote_rewards)| {
+                    EpochRewards::new(
+                        *epoch,
+                        &solido.reward_distribution,
+                        1,
+                        epochs_per_year(&epoch_schedule, *epoch),
+                        &stake_rewards[stake_account_ranges[i].clone()],
+                        &vote_rewards[i..i + 1],
+                    )
+                    .expect("The rewards of a validator do not overflow a u64.")
+                    .apy
+                });
+            let apy_contribution = apy.map(|apy| {
+                if total_stake == 0 {
+                    0.0
+                } else {
+                    apy * stake.0 as f64 / total_stake as f64
+                }
+            });
+
+            validators.push(ValidatorSummary {
+                vote_account: validator.pubkey,
+                name: identity.name,
+                active: validator.entry.active,
+                weight: validator.entry.weight,
+                stake,
+                target: targets.as_ref().map(|targets| targets[i]),
+                commission: identity.commission,
+                leader_slots: production.leader_slots,
+                blocks_produced: production.blocks_produced,
+                skip_rate: production.skip_rate(),
+                apy,
+                apy_contribution,
+            });
+        }
+
+        sort_validators(&mut validators, sort_by);
+
+        Ok(ValidatorsReport {
+            epoch: clock.epoch,
+            rewards_epoch,
+            validators,
+        })
+    }
+}
+
+/// Sort the validators, missing values last, and by vote account on ties.
+fn sort_validators(validators: &mut [ValidatorSummary], sort_by: ValidatorSortKey) {
+    // Compare optional floats with missing values last. Floats are not `Ord`,
+    // but we never produce NaN, so `partial_cmp` does not fail.
+    fn cmp_f64(a: Option<f64>, b: Option<f64>, descending: bool) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+        match (a, b) {
+            (Some(a), Some(b)) if descending => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    validators.sort_by(|a, b| {
+        let ordering = match sort_by {
+            ValidatorSortKey::Stake => b.stake.cmp(&a.stake),
+            ValidatorSortKey::Apy => cmp_f64(a.apy, b.apy, true),
+            ValidatorSortKey::SkipRate => cmp_f64(a.skip_rate, b.skip_rate, false),
+            ValidatorSortKey::Name => match (&a.name, &b.name) {
+                (Some(a), Some(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            },
+        };
+        ordering.then_with(|| a.vote_account.cmp(&b.vote_account))
+    });
+}
+
+/// Format a fraction as percentage, or a dash if it is missing.
+fn format_percentage(fraction: Option<f64>) -> String {
+    match fraction {
+        Some(fraction) => format!("{:.2}%", fraction * 100.0),
+        None => "-".to_string(),
+    }
+}
+
+impl fmt::Display for ValidatorsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Current epoch: {}", self.epoch)?;
+        match self.rewards_epoch {
+            Some(epoch) => writeln!(f, "APY estimated from the rewards of epoch {}.", epoch)?,
+            None => writeln!(f, "No completed epoch to estimate the APY from.")?,
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<44} {:<24} {:<8} {:>22} {:>22} {:>5} {:>9} {:>8} {:>9}",
+            "Vote account",
+            "Name",
+            "Status",
+            "Stake",
+            "Target",
+            "Comm",
+            "Skip rate",
+            "APY",
+            "APY share",
+        )?;
+        for v in &self.validators {
+            let mut name = v.name.clone().unwrap_or_else(|| "-".to_string());
+            if name.chars().count() > 24 {
+                name = name.chars().take(23).collect::<String>() + "…";
+            }
+            writeln!(
+                f,
+                "{:<44} {:<24} {:<8} {:>22} {:>22} {:>5} {:>9} {:>8} {:>9}",
+                v.vote_account.to_string(),
+                name,
+                if v.active { "active" } else { "inactive" },
+                v.stake.to_string(),
+                v.target.map_or("-".to_string(), |t| t.to_string()),
+                v.commission.map_or("-".to_string(), |c| format!("{}%", c)),
+                format_percentage(v.skip_rate),
+                format_percentage(v.apy),
+                format_percentage(v.apy_contribution),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::account::Account;
+    use solana_sdk::clock::Clock;
+
+    use lido::state::{SeedRange, Validator};
+
+    use super::*;
+    use crate::mock_rpc::new_solido_fixture;
+    use crate::rpc::{BlockProduction, InflationReward};
+    use crate::snapshot::SnapshotClient;
+
+    /// Vote account data with the node pubkey and commission, and no votes.
+    fn new_vote_account(node_pubkey: &Pubkey) -> Account {
+        let mut data = vec![0; 3762];
+        data[0..4].copy_from_slice(&2_u32.to_le_bytes());
+        data[4..36].copy_from_slice(node_pubkey.as_ref());
+        data[36..68].copy_from_slice(Pubkey::new_unique().as_ref());
+        data[68] = 100;
+        Account {
+            lamports: 1,
+            data,
+            owner: solana_vote_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_validators_report_estimates_apy_and_skip_rate() {
+        let mut fixture = new_solido_fixture(&Pubkey::new_unique());
+        let vote_accounts = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let identities = [Pubkey::new_unique(), Pubkey::new_unique()];
+        fixture.solido.validators.maximum_entries = 2;
+        for (i, vote_account) in vote_accounts.iter().enumerate() {
+            fixture
+                .rpc
+                .set_account(*vote_account, new_vote_account(&identities[i]));
+            fixture
+                .solido
+                .validators
+                .add(
+                    *vote_account,
+                    Validator {
+                        stake_seeds: SeedRange { begin: 0, end: 1 },
+                        stake_accounts_balance: Lamports(1_000_000_000 * (i as u64 + 1)),
+                        ..Validator::new(Pubkey::new_unique())
+                    },
+                )
+                .unwrap();
+        }
+        fixture.store_solido();
+        fixture.set_clock(&Clock {
+            epoch: 3,
+            ..Clock::default()
+        });
+
+        // Only the second validator earned rewards in epoch 2.
+        let validator = &fixture.solido.validators.entries[1];
+        let (stake_account, _) = validator.find_stake_account_address(
+            &fixture.solido_program_id,
+            &fixture.solido_address,
+            0,
+            StakeType::Stake,
+        );
+        fixture.rpc.set_inflation_reward(
+            stake_account,
+            InflationReward {
+                epoch: 2,
+                amount: 1_000,
+                post_balance: 2_000_001_000,
+            },
+        );
+        // Only the first validator was leader so far.
+        fixture.rpc.set_block_production(vec![(
+            identities[0],
+            BlockProduction {
+                leader_slots: 4,
+                blocks_produced: 3,
+            },
+        )]);
+
+        let mut client = SnapshotClient::new(fixture.rpc.clone());
+        let report = client
+            .with_snapshot(|mut snapshot| {
+                ValidatorsReport::new(
+                    &mut snapshot,
+                    &fixture.solido_program_id,
+                    &fixture.solido_address,
+                    &fixture.solido,
+                    ValidatorSortKey::Apy,
+                )
+            })
+            .unwrap();
+
+        assert_eq!(report.epoch, 3);
+        assert_eq!(report.rewards_epoch, Some(2));
+        let order: Vec<Pubkey> = report.validators.iter().map(|v| v.vote_account).collect();
+        assert_eq!(order, vec![vote_accounts[1], vote_accounts[0]]);
+
+        let best = &report.validators[0];
+        assert_eq!(best.stake, Lamports(2_000_000_000));
+        assert!(best.apy.unwrap() > 0.0);
+        // The validator holds two thirds of the stake.
+        let contribution = best.apy.unwrap() * 2.0 / 3.0;
+        assert!((best.apy_contribution.unwrap() - contribution).abs() < 1e-12);
+        assert_eq!(best.commission, Some(100));
+        assert_eq!(best.skip_rate, None);
+
+        let other = &report.validators[1];
+        assert_eq!(other.apy, Some(0.0));
+        assert_eq!(other.leader_slots, 4);
+        assert_eq!(other.skip_rate, Some(0.25));
+    }
+
+    #[test]
+    fn test_sort_validators_puts_missing_values_last() {
+        let summary = |name: Option<&str>, skip_rate: Option<f64>| ValidatorSummary {
+            vote_account: Pubkey::new_unique(),
+            name: name.map(|name| name.to_string()),
+            active: true,
+            weight: 1,
+            stake: Lamports(0),
+            target: None,
+            commission: None,
+            leader_slots: 0,
+            blocks_produced: 0,
+            skip_rate,
+            apy: None,
+            apy_contribution: None,
+        };
+        let mut validators = vec![
+            summary(None, Some(0.5)),
+            summary(Some("beta"), None),
+            summary(Some("Alpha"), Some(0.1)),
+        ];
+
+        sort_validators(&mut validators, ValidatorSortKey::SkipRate);
+        let skip_rates: Vec<Option<f64>> = validators.iter().map(|v| v.skip_rate).collect();
+        assert_eq!(skip_rates, vec![Some(0.1), Some(0.5), None]);
+
+        sort_validators(&mut validators, ValidatorSortKey::Name);
+        let names: Vec<Option<&str>> = validators.iter().map(|v| v.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Alpha"), Some("beta"), None]);
+    }
+}
@@ -0,0 +1,534 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Off-chain checks of the accounting invariants of a Solido instance.
+//!
+//! The program tracks the SOL in stake accounts, and the stSOL that validators
+//! are owed, in its own bookkeeping. When that bookkeeping drifts from the
+//! accounts on the network, no transaction fails, the exchange rate is just
+//! wrong. `solido verify` compares the two, so monitoring can catch this. The
+//! invariants are:
+//!
+//! * `validator_balance`: the stake accounts of a validator hold at least the
+//!   balance that Solido recorded. They may hold more, the surplus is rewards
+//!   or donations that were not observed yet.
+//! * `stake_account`: every stake account in the seed ranges of a validator
+//!   exists, and is delegated to the validator's vote account.
+//! * `exchange_rate`: the stSOL in existence, including unclaimed fee credits,
+//!   is worth at most the SOL under management, at the current exchange rate.
+//!   The SOL under management can be more, by the rewards observed since the
+//!   exchange rate was last updated.
+//! * `fee_credit`: validators can claim their fee credit, because their fee
+//!   address is an stSOL account.
+
+use std::fmt;
+
+use serde::Serialize;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::stake;
+
+use lido::account_map::PubkeyAndEntry;
+use lido::processor::StakeType;
+use lido::stake_account::deserialize_stake_account;
+use lido::state::{ExchangeRate, Lido, SeedRange, Validator};
+use lido::token::{self, Lamports, StLamports};
+use lido::util::serialize_b58;
+
+use crate::derivation_cache;
+use crate::snapshot::{Result, Snapshot};
+
+/// Conversions between SOL and stSOL round in favor of Solido, but the
+/// rational arithmetic can be off by a lamport here and there.
+const ROUNDING_TOLERANCE: Lamports = Lamports(10);
+
+/// An invariant that does not hold, with what we expected and what we found.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Violation {
+    /// Name of the invariant, see the module documentation.
+    pub invariant: &'static str,
+
+    /// The part of the instance that violates it, like a validator or stake account.
+    pub subject: String,
+
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}: {}", self.invariant, self.subject)?;
+        writeln!(f, "  - expected: {}", self.expected)?;
+        writeln!(f, "  + actual:   {}", self.actual)?;
+        Ok(())
+    }
+}
+
+/// The result of checking all invariants of a Solido instance.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Verification {
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido_address: Pubkey,
+
+    pub num_validators_checked: usize,
+    pub num_stake_accounts_checked: usize,
+
+    /// SOL under management according to Solido's bookkeeping.
+    #[serde(rename = "sol_balance_lamports")]
+    pub sol_balance: Lamports,
+
+    /// Minted stSOL plus unclaimed fee credits.
+    #[serde(rename = "st_sol_supply_st_lamports")]
+    pub st_sol_supply: StLamports,
+
+    pub violations: Vec<Violation>,
+}
+
+impl Verification {
+    /// Return whether all invariants hold.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Read the instance and the accounts it references, and check all invariants.
+    pub fn new(
+        snapshot: &mut Snapshot,
+        solido_program_id: &Pubkey,
+        solido_address: &Pubkey,
+    ) -> Result<Verification> {
+        let solido = snapshot.get_solido(solido_address)?;
+        let rent = snapshot.get_rent()?;
+        let reserve_address = solido.get_reserve_account(solido_program_id, solido_address)?;
+        let reserve = snapshot.get_account(&reserve_address)?;
+        let st_sol_mint: spl_token::state::Mint = snapshot.get_token_state(&solido.st_sol_mint)?;
+
+        let mut violations = Vec::new();
+        let mut num_stake_accounts_checked = 0;
+
+        for validator in solido.validators.entries.iter() {
+            let stake = check_stake_accounts(
+                snapshot,
+                solido_program_id,
+                solido_address,
+                validator,
+                StakeType::Stake,
+                &mut violations,
+            )?;
+            let unstake = check_stake_accounts(
+                snapshot,
+                solido_program_id,
+                solido_address,
+                validator,
+                StakeType::Unstake,
+                &mut violations,
+            )?;
+            num_stake_accounts_checked += seed_range_len(&validator.entry.stake_seeds)
+                + seed_range_len(&validator.entry.unstake_seeds);
+
+            let total = (stake + unstake).expect("SOL in stake accounts does not overflow a u64.");
+            check_validator_balance(validator, total, unstake, &mut violations);
+            check_fee_address(snapshot, &solido, validator, &mut violations)?;
+        }
+
+        let (sol_balance, st_sol_supply) = get_bookkept_balances(
+            &solido,
+            Lamports(reserve.lamports.saturating_sub(rent.minimum_balance(0))),
+            StLamports(st_sol_mint.supply),
+        )
+        .expect("Solido's balances do not overflow a u64.");
+        violations.extend(check_exchange_rate(
+            &solido.exchange_rate,
+            st_sol_supply,
+            sol_balance,
+        ));
+
+        Ok(Verification {
+            solido_address: *solido_address,
+            num_validators_checked: solido.validators.len(),
+            num_stake_accounts_checked,
+            sol_balance,
+            st_sol_supply,
+            violations,
+        })
+    }
+}
+
+fn seed_range_len(seeds: &SeedRange) -> usize {
+    (seeds.end - seeds.begin) as usize
+}
+
+/// Return the SOL under management, and the stSOL that exists, the way the program computes them.
+fn get_bookkept_balances(
+    solido: &Lido,
+    effective_reserve: Lamports,
+    minted_st_sol: StLamports,
+) -> token::Result<(Lamports, StLamports)> {
+    let validator_balance: Lamports = solido
+        .validators
+        .iter_entries()
+        .map(|v| v.stake_accounts_balance)
+        .sum::<token::Result<Lamports>>()?;
+    let fee_credit: StLamports = solido
+        .validators
+        .iter_entries()
+        .map(|v| v.fee_credit)
+        .sum::<token::Result<StLamports>>()?;
+
+    // The SOL owed to unfunded withdraw tickets is no longer under management,
+    // the stSOL for it has already been burned.
+    let total = (validator_balance + effective_reserve)?;
+    let sol_balance = Lamports(
+        total
+            .0
+            .saturating_sub(solido.withdraw_queue.get_outstanding().0),
+    );
+
+    Ok((sol_balance, (minted_st_sol + fee_credit)?))
+}
+
+/// Check the stake accounts of one type of a validator, and return the SOL they hold.
+fn check_stake_accounts(
+    snapshot: &mut Snapshot,
+    solido_program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validator: &PubkeyAndEntry<Validator>,
+    stake_type: StakeType,
+    violations: &mut Vec<Violation>,
+) -> Result<Lamports> {
+    let seeds = match stake_type {
+        StakeType::Stake => &validator.entry.stake_seeds,
+        StakeType::Unstake => &validator.entry.unstake_seeds,
+    };
+    let mut balance = Lamports(0);
+    for seed in seeds {
+        let (address, _bump_seed) = derivation_cache::find_stake_account_address(
+            &validator.pubkey,
+            solido_program_id,
+            solido_address,
+            seed,
+            stake_type,
+        );
+        let subject = format!(
+            "{} account {} (validator {}, seed {})",
+            stake_type, address, validator.pubkey, seed
+        );
+        let expected = format!("delegated to {}", validator.pubkey);
+
+        if !snapshot.account_exists(&address)? {
+            violations.push(Violation {
+                invariant: "stake_account",
+                subject,
+                expected,
+                actual: "account does not exist".to_string(),
+            });
+            continue;
+        }
+
+        let account = snapshot.get_account(&address)?;
+        balance = (balance + Lamports(account.lamports))
+            .expect("SOL in stake accounts does not overflow a u64.");
+
+        let actual = if account.owner != stake::program::id() {
+            Some(format!("owned by {}", account.owner))
+        } else {
+            match deserialize_stake_account(&account.data) {
+                Ok(stake) if stake.delegation.voter_pubkey == validator.pubkey => None,
+                Ok(stake) => Some(format!("delegated to {}", stake.delegation.voter_pubkey)),
+                Err(..) => Some("not a delegated stake account".to_string()),
+            }
+        };
+        if let Some(actual) = actual {
+            violations.push(Violation {
+                invariant: "stake_account",
+                subject,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(balance)
+}
+
+/// Check that the stake accounts hold at least the balance that Solido recorded.
+fn check_validator_balance(
+    validator: &PubkeyAndEntry<Validator>,
+    total_balance: Lamports,
+    unstake_balance: Lamports,
+    violations: &mut Vec<Violation>,
+) {
+    let checks = [
+        (
+            "stake and unstake accounts",
+            validator.entry.stake_accounts_balance,
+            total_balance,
+        ),
+        (
+            "unstake accounts",
+            validator.entry.unstake_accounts_balance,
+            unstake_balance,
+        ),
+    ];
+    for &(accounts, recorded, actual) in checks.iter() {
+        if actual < recorded {
+            violations.push(Violation {
+                invariant: "validator_balance",
+                subject: format!("{} of validator {}", accounts, validator.pubkey),
+                expected: format!("at least {} (recorded)", recorded),
+                actual: format!("{} (short by {})", actual, Lamports(recorded.0 - actual.0)),
+            });
+        }
+    }
+}
+
+/// Check that the validator can receive the stSOL that it is owed.
+fn check_fee_address(
+    snapshot: &mut Snapshot,
+    solido: &Lido,
+    validator: &PubkeyAndEntry<Validator>,
+    violations: &mut Vec<Violation>,
+) -> Result<()> {
+    let fee_address = validator.entry.fee_address;
+    let actual = if !snapshot.account_exists(&fee_address)? {
+        Some("account does not exist".to_string())
+    } else {
+        let account = snapshot.get_account(&fee_address)?;
+        let token_account = if lido::is_token_program(&account.owner)
+            && account.data.len() >= spl_token::state::Account::LEN
+        {
+            spl_token::state::Account::unpack_from_slice(
+                &account.data[..spl_token::state::Account::LEN],
+            )
+            .ok()
+        } else {
+            None
+        };
+        match token_account {
+            Some(token_account) if token_account.mint == solido.st_sol_mint => None,
+            Some(token_account) => Some(format!("a token account of mint {}", token_account.mint)),
+            None => Some(format!("not a token account, owned by {}", account.owner)),
+        }
+    };
+    if let Some(actual) = actual {
+        violations.push(Violation {
+            invariant: "fee_credit",
+            subject: format!(
+                "fee address {} of validator {}, credit {}",
+                fee_address, validator.pubkey, validator.entry.fee_credit
+            ),
+            expected: format!("an stSOL account of mint {}", solido.st_sol_mint),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Check that the stSOL in existence is covered by the SOL under management.
+fn check_exchange_rate(
+    exchange_rate: &ExchangeRate,
+    st_sol_supply: StLamports,
+    sol_balance: Lamports,
+) -> Option<Violation> {
+    // Like `ExchangeRate::exchange_sol`, without a rate, stSOL was minted 1:1.
+    let st_sol_value = if exchange_rate.st_sol_supply == StLamports(0)
+        || exchange_rate.sol_balance == Lamports(0)
+    {
+        Lamports(st_sol_supply.0)
+    } else {
+        exchange_rate
+            .exchange_st_sol(st_sol_supply)
+            .expect("The value of all stSOL does not overflow a u64.")
+    };
+
+    if st_sol_value.0 > sol_balance.0.saturating_add(ROUNDING_TOLERANCE.0) {
+        Some(Violation {
+            invariant: "exchange_rate",
+            subject: format!(
+                "{} at {} per {}",
+                st_sol_supply, exchange_rate.sol_balance, exchange_rate.st_sol_supply
+            ),
+            expected: format!("worth at most {} (SOL under management)", sol_balance),
+            actual: format!(
+                "worth {} (short by {})",
+                st_sol_value,
+                Lamports(st_sol_value.0 - sol_balance.0)
+            ),
+        })
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Solido instance:       {}", self.solido_address)?;
+        writeln!(f, "Validators checked:    {}", self.num_validators_checked)?;
+        writeln!(
+            f,
+            "Stake accounts checked: {}",
+            self.num_stake_accounts_checked
+        )?;
+        writeln!(f, "SOL under management:  {}", self.sol_balance)?;
+        writeln!(f, "stSOL supply:          {}", self.st_sol_supply)?;
+        if self.is_ok() {
+            writeln!(f, "\nAll invariants hold.")?;
+        } else {
+            writeln!(f, "\n{} invariant violation(s):\n", self.violations.len())?;
+            for violation in &self.violations {
+                writeln!(f, "{}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::account::Account;
+    use solana_sdk::program_option::COption;
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::snapshot::SnapshotClient;
+
+    fn new_st_sol_account(rpc: &crate::mock_rpc::MockRpc, mint: Pubkey) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let token_account = spl_token::state::Account {
+            mint,
+            owner: Pubkey::new_unique(),
+            amount: 0,
+            delegate: COption::None,
+            state: spl_token::state::AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(token_account, &mut data).unwrap();
+        rpc.set_account(
+            address,
+            Account {
+                lamports: 1,
+                data,
+                owner: spl_token::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        address
+    }
+
+    fn verify(simulation: &Simulation) -> Verification {
+        let solido_program_id = simulation.fixture.solido_program_id;
+        let solido_address = simulation.fixture.solido_address;
+        let mut client = SnapshotClient::new(simulation.fixture.rpc.clone());
+        client
+            .with_snapshot(|mut snapshot| {
+                Verification::new(&mut snapshot, &solido_program_id, &solido_address)
+            })
+            .unwrap()
+    }
+
+    /// Return a simulation with one validator that has one stake account.
+    fn new_simulation(maintainer: &Keypair) -> (Simulation, Pubkey, Pubkey) {
+        let mut simulation = Simulation::new(maintainer);
+        let vote_account = simulation.add_validator();
+        let fee_address = new_st_sol_account(
+            &simulation.fixture.rpc,
+            simulation.fixture.solido.st_sol_mint,
+        );
+        simulation
+            .fixture
+            .solido
+            .validators
+            .get_mut(&vote_account)
+            .unwrap()
+            .entry
+            .fee_address = fee_address;
+        let stake_account = simulation.add_stake_account(&vote_account, 10_000_000_000);
+        (simulation, vote_account, stake_account)
+    }
+
+    #[test]
+    fn test_verify_consistent_instance() {
+        let maintainer = Keypair::new();
+        let (mut simulation, _vote_account, stake_account) = new_simulation(&maintainer);
+        let verification = verify(&simulation);
+        assert_eq!(verification.violations, Vec::new());
+        assert_eq!(verification.num_validators_checked, 1);
+        assert_eq!(verification.num_stake_accounts_checked, 1);
+        assert_eq!(verification.sol_balance, Lamports(10_000_000_000));
+
+        // Rewards that were not observed yet are not a violation.
+        simulation.donate(&stake_account, 1_000);
+        assert!(verify(&simulation).is_ok());
+    }
+
+    #[test]
+    fn test_verify_reports_missing_stake_account() {
+        let maintainer = Keypair::new();
+        let (mut simulation, vote_account, stake_account) = new_simulation(&maintainer);
+        simulation.fixture.rpc.remove_account(&stake_account);
+
+        let verification = verify(&simulation);
+        let invariants: Vec<&str> = verification
+            .violations
+            .iter()
+            .map(|v| v.invariant)
+            .collect();
+        assert_eq!(invariants, vec!["stake_account", "validator_balance"]);
+        assert_eq!(
+            verification.violations[0].expected,
+            format!("delegated to {}", vote_account)
+        );
+        assert_eq!(verification.violations[0].actual, "account does not exist");
+    }
+
+    #[test]
+    fn test_verify_reports_unclaimable_fee_credit() {
+        let maintainer = Keypair::new();
+        let (mut simulation, vote_account, _stake_account) = new_simulation(&maintainer);
+        let validator = simulation
+            .fixture
+            .solido
+            .validators
+            .get_mut(&vote_account)
+            .unwrap();
+        validator.entry.fee_address = Pubkey::new_unique();
+        validator.entry.fee_credit = StLamports(1_000);
+        simulation.fixture.store_solido();
+
+        let verification = verify(&simulation);
+        assert_eq!(verification.violations.len(), 1);
+        assert_eq!(verification.violations[0].invariant, "fee_credit");
+    }
+
+    #[test]
+    fn test_check_exchange_rate() {
+        let exchange_rate = ExchangeRate {
+            computed_in_epoch: 0,
+            st_sol_supply: StLamports(1_000),
+            sol_balance: Lamports(2_000),
+        };
+        // Observed rewards make the SOL under management grow.
+        assert_eq!(
+            check_exchange_rate(&exchange_rate, StLamports(1_000), Lamports(2_100)),
+            None
+        );
+        // Rounding is tolerated.
+        assert_eq!(
+            check_exchange_rate(&exchange_rate, StLamports(1_000), Lamports(1_995)),
+            None
+        );
+        // But stSOL that is not backed by SOL is not.
+        let violation =
+            check_exchange_rate(&exchange_rate, StLamports(1_100), Lamports(2_000)).unwrap();
+        assert_eq!(violation.invariant, "exchange_rate");
+
+        // Without an exchange rate, stSOL is worth one SOL.
+        assert_eq!(
+            check_exchange_rate(&ExchangeRate::default(), StLamports(5), Lamports(5)),
+            None
+        );
+    }
+}
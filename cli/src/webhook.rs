@@ -0,0 +1,352 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Webhooks that notify external services about events that the maintenance
+//! daemon observes.
+//!
+//! Every event is delivered as a JSON object in a POST request. The body
+//! includes a `schema_version`; we increment it whenever we make a change to
+//! the format that is not backwards compatible (adding a field or event type is
+//! backwards compatible). The request carries an `X-Solido-Signature` header
+//! of the form `sha256=<hex>`, with the HMAC-SHA256 of the body under a shared
+//! secret, so receivers can verify that the event came from the daemon.
+
+use std::time::{Duration, SystemTime};
+
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use sha2::Sha256;
+use solana_program::clock::Slot;
+use solana_program::pubkey::Pubkey;
+
+use lido::state::Lido;
+use lido::token::Lamports;
+use lido::util::serialize_b58;
+
+use crate::logging;
+use crate::maintenance::{MaintenanceOutput, SolidoState};
+
+/// Version of the event format, see the module documentation.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Number of consecutive failed iterations after which we send an `ErrorStreak` event.
+pub const ERROR_STREAK_THRESHOLD: u64 = 5;
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// Deposits happened since the previous observation.
+    DepositObserved {
+        /// Number of deposits.
+        count: u64,
+        /// Total amount deposited.
+        #[serde(rename = "amount_lamports")]
+        amount: Lamports,
+    },
+
+    /// The daemon performed maintenance.
+    MaintenancePerformed { maintenance: &'a MaintenanceOutput },
+
+    /// The daemon failed to perform maintenance this many times in a row.
+    ErrorStreak { consecutive_errors: u64 },
+
+    /// A parameter of the Solido instance changed since the previous observation.
+    ParameterChanged {
+        parameter: &'static str,
+        old: serde_json::Value,
+        new: serde_json::Value,
+    },
+}
+
+/// The body of a webhook request.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    schema_version: u32,
+    #[serde(serialize_with = "serialize_b58")]
+    solido_address: Pubkey,
+    /// Slot at which the event was observed, if we managed to read the state.
+    slot: Option<Slot>,
+    timestamp_unix_seconds: u64,
+    event: &'a Event<'a>,
+}
+
+/// Turns the outcomes of successive daemon iterations into events.
+#[derive(Default)]
+pub struct EventDetector {
+    previous: Option<Lido>,
+    consecutive_errors: u64,
+}
+
+/// Total number and amount of deposits tracked in the on-chain metrics.
+fn deposit_totals(solido: &Lido) -> (u64, Lamports) {
+    let histogram = &solido.metrics.deposit_amount;
+    // The buckets are cumulative, the last one counts all deposits.
+    let count = histogram.counts[histogram.counts.len() - 1];
+    (count, histogram.total)
+}
+
+fn to_value<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).expect("Parameters can always be serialized.")
+}
+
+impl EventDetector {
+    /// Return the events for one daemon iteration.
+    ///
+    /// `solido` is `None` if the iteration failed.
+    pub fn detect<'a>(
+        &mut self,
+        solido: Option<&SolidoState>,
+        maintenance_output: Option<&'a MaintenanceOutput>,
+    ) -> Vec<Event<'a>> {
+        let mut events = Vec::new();
+
+        let state = match solido {
+            Some(state) => state,
+            None => {
+                self.consecutive_errors += 1;
+                if self.consecutive_errors == ERROR_STREAK_THRESHOLD {
+                    events.push(Event::ErrorStreak {
+                        consecutive_errors: self.consecutive_errors,
+                    });
+                }
+                return events;
+            }
+        };
+        self.consecutive_errors = 0;
+
+        if let Some(previous) = &self.previous {
+            let current = &state.solido;
+            let (old_count, old_amount) = deposit_totals(previous);
+            let (new_count, new_amount) = deposit_totals(current);
+            if new_count > old_count {
+                events.push(Event::DepositObserved {
+                    count: new_count - old_count,
+                    amount: Lamports(new_amount.0.saturating_sub(old_amount.0)),
+                });
+            }
+
+            let mut check =
+                |parameter: &'static str, old: serde_json::Value, new: serde_json::Value| {
+                    if old != new {
+                        events.push(Event::ParameterChanged {
+                            parameter,
+                            old,
+                            new,
+                        });
+                    }
+                };
+            check(
+                "manager",
+                to_value(&previous.manager.to_string()),
+                to_value(&current.manager.to_string()),
+            );
+            check(
+                "reward_distribution",
+                to_value(&previous.reward_distribution),
+                to_value(&current.reward_distribution),
+            );
+            check(
+                "fee_recipients",
+                to_value(&previous.fee_recipients),
+                to_value(&current.fee_recipients),
+            );
+            let validators = |solido: &Lido| -> Vec<(String, bool)> {
+                solido
+                    .validators
+                    .entries
+                    .iter()
+                    .map(|v| (v.pubkey.to_string(), v.entry.active))
+                    .collect()
+            };
+            check(
+                "validators",
+                to_value(&validators(previous)),
+                to_value(&validators(current)),
+            );
+            let maintainers = |solido: &Lido| -> Vec<String> {
+                solido
+                    .maintainers
+                    .entries
+                    .iter()
+                    .map(|m| m.pubkey.to_string())
+                    .collect()
+            };
+            check(
+                "maintainers",
+                to_value(&maintainers(previous)),
+                to_value(&maintainers(current)),
+            );
+        }
+
+        if let Some(maintenance) = maintenance_output {
+            events.push(Event::MaintenancePerformed { maintenance });
+        }
+
+        self.previous = Some(state.solido.clone());
+        events
+    }
+}
+
+/// Return the value for the `X-Solido-Signature` header.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC accepts keys of any length.");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Delivers events to a set of endpoints.
+pub struct Webhooks {
+    endpoints: Vec<String>,
+    secret: Vec<u8>,
+    client: reqwest::blocking::Client,
+    detector: EventDetector,
+}
+
+impl Webhooks {
+    pub fn new(endpoints: Vec<String>, secret: Vec<u8>) -> Webhooks {
+        let client = reqwest::blocking::Client::builder()
+            // Don't let a slow endpoint hold up maintenance for long.
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create http client.");
+        Webhooks {
+            endpoints,
+            secret,
+            client,
+            detector: EventDetector::default(),
+        }
+    }
+
+    /// Detect the events for one daemon iteration, and deliver them.
+    ///
+    /// Delivery is best-effort: we do not retry, errors are only logged.
+    pub fn observe(
+        &mut self,
+        solido_address: &Pubkey,
+        now: SystemTime,
+        solido: Option<&SolidoState>,
+        maintenance_output: Option<&MaintenanceOutput>,
+    ) {
+        let events = self.detector.detect(solido, maintenance_output);
+        let timestamp_unix_seconds = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        for event in &events {
+            let envelope = Envelope {
+                schema_version: SCHEMA_VERSION,
+                solido_address: *solido_address,
+                slot: solido.map(|state| state.snapshot_slot),
+                timestamp_unix_seconds,
+                event,
+            };
+            let body = serde_json::to_vec(&envelope).expect("Events can always be serialized.");
+            let signature = sign(&self.secret, &body);
+
+            for endpoint in &self.endpoints {
+                let result = self
+                    .client
+                    .post(endpoint)
+                    .header("Content-Type", "application/json")
+                    .header("X-Solido-Schema-Version", SCHEMA_VERSION.to_string())
+                    .header("X-Solido-Signature", &signature)
+                    .body(body.clone())
+                    .send()
+                    .and_then(|response| response.error_for_status());
+                if let Err(err) = result {
+                    logging::Event::warning(
+                        "webhook_delivery_failed",
+                        format!("Error while delivering webhook to {}.", endpoint),
+                    )
+                    .field("endpoint", endpoint)
+                    .field("error", err.to_string())
+                    .emit();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn test_sign_matches_rfc_4231() {
+        // Test case 2 from RFC 4231.
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+
+    #[test]
+    fn test_error_streak_is_reported_once() {
+        let mut detector = EventDetector::default();
+        for i in 1..ERROR_STREAK_THRESHOLD * 2 {
+            let events = detector.detect(None, None);
+            if i == ERROR_STREAK_THRESHOLD {
+                assert_eq!(
+                    events,
+                    vec![Event::ErrorStreak {
+                        consecutive_errors: ERROR_STREAK_THRESHOLD
+                    }]
+                );
+            } else {
+                assert_eq!(events, vec![]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_detects_deposits_and_parameter_changes() {
+        let maintainer = Keypair::new();
+        let mut sim = Simulation::new(&maintainer);
+        let mut detector = EventDetector::default();
+
+        // The first observation has nothing to compare against.
+        let mut state = sim.read_state();
+        assert_eq!(detector.detect(Some(&state), None), vec![]);
+
+        state
+            .solido
+            .metrics
+            .observe_deposit(Lamports(1_000_000_000))
+            .unwrap();
+        state
+            .solido
+            .metrics
+            .observe_deposit(Lamports(500_000_000))
+            .unwrap();
+        state.solido.reward_distribution.treasury_fee += 1;
+        let events = detector.detect(Some(&state), Some(&MaintenanceOutput::UpdateExchangeRate));
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            Event::DepositObserved {
+                count: 2,
+                amount: Lamports(1_500_000_000),
+            }
+        );
+        match &events[1] {
+            Event::ParameterChanged { parameter, .. } => {
+                assert_eq!(*parameter, "reward_distribution")
+            }
+            other => panic!("Expected a parameter change, got {:?}.", other),
+        }
+        assert_eq!(
+            events[2],
+            Event::MaintenancePerformed {
+                maintenance: &MaintenanceOutput::UpdateExchangeRate
+            }
+        );
+
+        // Nothing changed since the previous observation.
+        assert_eq!(detector.detect(Some(&state), None), vec![]);
+    }
+}
@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! End-to-end tests that run the `solido` CLI against a local test validator.
+//!
+//! These cover the same ground as the first part of `tests/test_solido.py`, but
+//! can be run with `cargo test -- --ignored`, see `fixture` for requirements.
+//! The last test goes further, and checks the decisions of the maintenance
+//! daemon against the real on-chain state, across an epoch boundary.
+
+mod fixture;
+
+use fixture::solido::SolidoTestInstance;
+use fixture::TestValidator;
+use serde_json::json;
+use solido_cli::maintenance::MaintenanceOutput;
+
+#[test]
+#[ignore = "requires solana-test-validator and the BPF programs, see fixture"]
+fn create_solido_sets_manager_and_distribution() {
+    let instance = SolidoTestInstance::new();
+    let solido = instance.show_solido();
+
+    assert_eq!(
+        solido["solido"]["reward_distribution"],
+        json!({
+            "treasury_fee": 5,
+            "validation_fee": 3,
+            "developer_fee": 2,
+            "st_sol_appreciation": 90,
+        })
+    );
+    assert_eq!(
+        solido["solido"]["exchange_rate"],
+        json!({
+            "computed_in_epoch": 0,
+            "st_sol_supply": 0,
+            "sol_balance": 0,
+        })
+    );
+}
+
+#[test]
+#[ignore = "requires solana-test-validator and the BPF programs, see fixture"]
+fn deposit_and_stake_with_one_maintenance_pass() {
+    let instance = SolidoTestInstance::new();
+    let vote_account = instance.add_validator("validator-1");
+
+    let maintainer = instance
+        .validator
+        .create_test_account("maintainer", Some("1.0"));
+    instance.add_maintainer(&maintainer);
+
+    let solido = instance.show_solido();
+    assert_eq!(
+        solido["solido"]["validators"]["entries"][0]["pubkey"],
+        json!(vote_account)
+    );
+    assert_eq!(
+        solido["solido"]["maintainers"]["entries"][0]["pubkey"],
+        json!(maintainer.pubkey.to_string())
+    );
+
+    // On a fresh validator we may or may not be past epoch 0 already, so the
+    // first pass can update the exchange rate. Run maintenance until there is
+    // nothing left to do before depositing.
+    while !instance.perform_maintenance(&maintainer).is_null() {}
+
+    let mut deposit = instance.validator.solido(
+        None,
+        &[
+            "deposit",
+            "--solido-address",
+            &instance.solido_address,
+            "--solido-program-id",
+            &instance.solido_program_id,
+            "--amount-sol",
+            "1.0",
+        ],
+    );
+    // The recipient address depends on the signer, don't compare it.
+    deposit.as_object_mut().unwrap().remove("recipient");
+    assert_eq!(
+        deposit,
+        json!({
+            "expected_st_lamports": 1_000_000_000_u64,
+            "st_lamports_balance_increase": 1_000_000_000_u64,
+            "created_associated_st_sol_account": true,
+        })
+    );
+
+    let mut result = instance.perform_maintenance(&maintainer);
+    // The stake account address is derived, we don't predict it here.
+    result["StakeDeposit"]
+        .as_object_mut()
+        .expect("Expected a StakeDeposit.")
+        .remove("stake_account");
+    assert_eq!(
+        result,
+        json!({
+            "StakeDeposit": {
+                "validator_vote_account": vote_account,
+                "amount_lamports": 1_000_000_000_u64,
+            }
+        })
+    );
+}
+
+#[test]
+#[ignore = "requires solana-test-validator and the BPF programs, see fixture"]
+fn maintenance_decisions_across_epoch_boundary() {
+    let validator = TestValidator::start_with_slots_per_epoch(128);
+    let instance = SolidoTestInstance::on_validator(validator);
+    let vote_account = instance.add_validator("validator-1");
+    let maintainer = instance
+        .validator
+        .create_test_account("maintainer", Some("1.0"));
+    instance.add_maintainer(&maintainer);
+
+    // Setting up takes a good part of an epoch. Start at an epoch boundary, so
+    // the next one does not pass while we check the decisions within an epoch.
+    instance.validator.wait_for_next_epoch();
+    instance.perform_maintenance_until_idle(&maintainer);
+
+    // After a deposit, the daemon stakes the reserve with the only validator.
+    instance.deposit("2.0");
+    let state = instance.solido_state(&maintainer);
+    match state.try_stake_deposit() {
+        Some((_, MaintenanceOutput::StakeDeposit { amount, .. })) => {
+            assert_eq!(amount.0, 2_000_000_000);
+        }
+        other => panic!("Expected a StakeDeposit, got {:?}.", other),
+    }
+    assert_eq!(state.try_update_exchange_rate(), None);
+    let performed = instance.perform_maintenance_until_idle(&maintainer);
+    assert_eq!(
+        performed[0]["StakeDeposit"]["validator_vote_account"],
+        json!(vote_account)
+    );
+
+    // In the next epoch, the exchange rate is outdated, and it is the first
+    // thing that maintenance updates.
+    let epoch = instance.validator.wait_for_next_epoch();
+    let state = instance.solido_state(&maintainer);
+    assert!(state.clock.epoch >= epoch);
+    assert_eq!(
+        state.try_update_exchange_rate().map(|(_, output)| output),
+        Some(MaintenanceOutput::UpdateExchangeRate),
+    );
+    let performed = instance.perform_maintenance_until_idle(&maintainer);
+    assert_eq!(performed[0], json!("UpdateExchangeRate"));
+
+    let state = instance.solido_state(&maintainer);
+    assert_eq!(
+        state.try_update_exchange_rate().map(|(_, output)| output),
+        None
+    );
+    assert_eq!(
+        state.solido.exchange_rate.computed_in_epoch,
+        instance.validator.get_epoch(),
+    );
+    // The deposit is all that Solido manages.
+    assert_eq!(state.solido.exchange_rate.sol_balance.0, 2_000_000_000);
+}
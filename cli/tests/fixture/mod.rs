@@ -0,0 +1,345 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Test fixture that runs a `solana-test-validator` and drives the `solido` CLI.
+//!
+//! This is the Rust counterpart of `tests/util.py`: it shells out to `solana`,
+//! `spl-token`, and the `solido` binary built by Cargo, and parses the json
+//! output of the latter. It requires the Solana tool suite to be on the `PATH`,
+//! and the BPF programs to be built (`cargo build-bpf`), so tests that use it
+//! are marked `#[ignore]`. Run them with `cargo test -- --ignored`.
+//!
+//! Besides the CLI, tests can read the on-chain state through the library, see
+//! [`TestValidator::snapshot_client`] and [`solido::SolidoTestInstance::solido_state`].
+
+pub mod solido;
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::clock::Epoch;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, write_keypair_file, Keypair, Signer};
+use solido_cli::snapshot::SnapshotClient;
+use tempfile::TempDir;
+
+/// A keypair written to a file, so we can pass it to the command-line tools.
+pub struct TestAccount {
+    pub pubkey: Pubkey,
+    pub keypair_path: PathBuf,
+}
+
+impl TestAccount {
+    /// Read the keypair back, to sign with it in-process.
+    pub fn keypair(&self) -> Keypair {
+        read_keypair_file(&self.keypair_path).expect("Failed to read keypair.")
+    }
+}
+
+/// Run a program, ensure it exits with code 0, return its stdout.
+pub fn run(program: &str, args: &[&str]) -> String {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run {}: {}", program, err));
+
+    if !output.status.success() {
+        panic!(
+            "Command failed: {} {}\nStdout: {}\nStderr: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        );
+    }
+
+    String::from_utf8(output.stdout).expect("Command output should be valid UTF-8.")
+}
+
+/// Return a TCP port on localhost that is not in use at the time of the call.
+fn get_free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("Failed to find a free port.")
+}
+
+/// Directory that contains `lido.so` and `serum_multisig.so`.
+///
+/// Like the Python tests, this can be overridden with `SOLCONPATH`.
+fn get_solido_program_path() -> PathBuf {
+    match std::env::var("SOLCONPATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(..) => Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/deploy"),
+    }
+}
+
+/// A running `solana-test-validator`, that is killed when dropped.
+pub struct TestValidator {
+    process: Child,
+    url: String,
+
+    /// Holds the ledger and the generated keypairs, deleted on drop.
+    dir: TempDir,
+
+    /// Account that pays for deployments and is the default signer.
+    pub payer: TestAccount,
+}
+
+impl TestValidator {
+    /// Start a validator with a fresh ledger, and wait until it produces blocks.
+    pub fn start() -> TestValidator {
+        TestValidator::start_with_args(&[])
+    }
+
+    /// Like [`start`](Self::start), but with short epochs.
+    ///
+    /// The default epoch of the test validator is too long to wait for in a
+    /// test. With a few dozen slots per epoch, tests can observe stake
+    /// activation and the epoch-boundary maintenance within a minute.
+    pub fn start_with_slots_per_epoch(slots_per_epoch: u64) -> TestValidator {
+        TestValidator::start_with_args(&["--slots-per-epoch", &slots_per_epoch.to_string()])
+    }
+
+    fn start_with_args(extra_args: &[&str]) -> TestValidator {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory.");
+        let rpc_port = get_free_port();
+        let faucet_port = get_free_port();
+
+        let process = Command::new("solana-test-validator")
+            .arg("--ledger")
+            .arg(dir.path().join("ledger"))
+            .arg("--rpc-port")
+            .arg(rpc_port.to_string())
+            .arg("--faucet-port")
+            .arg(faucet_port.to_string())
+            .arg("--quiet")
+            .args(extra_args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start solana-test-validator, is it on the PATH?");
+
+        let url = format!("http://127.0.0.1:{}", rpc_port);
+        let payer_keypair_path = dir.path().join("payer.json");
+        let payer_keypair = Keypair::new();
+        write_keypair_file(&payer_keypair, &payer_keypair_path)
+            .expect("Failed to write payer keypair.");
+
+        let mut validator = TestValidator {
+            process,
+            url,
+            dir,
+            payer: TestAccount {
+                pubkey: payer_keypair.pubkey(),
+                keypair_path: payer_keypair_path,
+            },
+        };
+        validator.wait_until_producing_blocks();
+        validator.solana(&["airdrop", "1000", &validator.payer.pubkey.to_string()]);
+        validator
+    }
+
+    /// Wait until the block height increases at least once.
+    ///
+    /// Like `start_test_validator.py`, we don't just check that the RPC
+    /// responds, because it can respond before the validator accepts
+    /// transactions.
+    fn wait_until_producing_blocks(&mut self) {
+        let mut last_observed_block_height: Option<u64> = None;
+        for _ in 0..300 {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                panic!("solana-test-validator exited prematurely with {}.", status);
+            }
+            let output = Command::new("solana")
+                .args(&["--url", &self.url, "block-height"])
+                .stderr(Stdio::null())
+                .output()
+                .expect("Failed to run solana, is it on the PATH?");
+            let current = String::from_utf8_lossy(&output.stdout).trim().parse().ok();
+            match (last_observed_block_height, current) {
+                (Some(last), Some(current)) if current > last => return,
+                (_, Some(current)) => last_observed_block_height = Some(current),
+                _ => {}
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("solana-test-validator did not start producing blocks in time.");
+    }
+
+    /// Return an RPC client for this validator, at the `confirmed` commitment level.
+    pub fn rpc_client(&self) -> RpcClient {
+        RpcClient::new_with_commitment(self.url.clone(), CommitmentConfig::confirmed())
+    }
+
+    /// Return a snapshot client for this validator, to read state through the library.
+    pub fn snapshot_client(&self) -> SnapshotClient {
+        SnapshotClient::new(self.rpc_client())
+    }
+
+    /// Return the current epoch.
+    pub fn get_epoch(&self) -> Epoch {
+        self.rpc_client()
+            .get_epoch_info()
+            .expect("Failed to get epoch info.")
+            .epoch
+    }
+
+    /// Wait until the validator enters the next epoch, return that epoch.
+    pub fn wait_for_next_epoch(&self) -> Epoch {
+        let rpc_client = self.rpc_client();
+        let epoch_info = rpc_client
+            .get_epoch_info()
+            .expect("Failed to get epoch info.");
+        // Slots take about 400ms, allow for a slow machine.
+        let slots_left = epoch_info.slots_in_epoch - epoch_info.slot_index;
+        let deadline = Instant::now() + Duration::from_secs(10 + slots_left);
+        while Instant::now() < deadline {
+            let epoch = rpc_client
+                .get_epoch_info()
+                .expect("Failed to get epoch info.")
+                .epoch;
+            if epoch > epoch_info.epoch {
+                return epoch;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        panic!(
+            "The validator did not reach epoch {} in time.",
+            epoch_info.epoch + 1
+        );
+    }
+
+    /// Run `solana` against this validator, with the payer as signer.
+    pub fn solana(&self, args: &[&str]) -> String {
+        let mut full_args = vec![
+            "--url",
+            &self.url,
+            "--keypair",
+            self.payer.keypair_path.to_str().unwrap(),
+        ];
+        full_args.extend_from_slice(args);
+        run("solana", &full_args)
+    }
+
+    /// Run `spl-token` against this validator, with the payer as fee payer.
+    pub fn spl_token(&self, args: &[&str]) -> String {
+        let mut full_args = vec![
+            "--url",
+            &self.url,
+            "--fee-payer",
+            self.payer.keypair_path.to_str().unwrap(),
+        ];
+        full_args.extend_from_slice(args);
+        run("spl-token", &full_args)
+    }
+
+    /// Run `solido` against this validator, return its parsed json output.
+    ///
+    /// Signs with `signer` if provided, and with the payer otherwise.
+    pub fn solido(&self, signer: Option<&TestAccount>, args: &[&str]) -> Value {
+        let keypair_path = signer.unwrap_or(&self.payer).keypair_path.to_str().unwrap();
+        let mut full_args = vec![
+            "--cluster",
+            &self.url,
+            "--output",
+            "json",
+            "--keypair-path",
+            keypair_path,
+        ];
+        full_args.extend_from_slice(args);
+        let output = run(env!("CARGO_BIN_EXE_solido"), &full_args);
+        if output.trim().is_empty() {
+            return Value::Null;
+        }
+        serde_json::from_str(&output)
+            .unwrap_or_else(|err| panic!("Failed to parse output as json: {}\n{}", err, output))
+    }
+
+    /// Generate a keypair in the temporary directory, fund it if requested.
+    pub fn create_test_account(&self, name: &str, fund_sol: Option<&str>) -> TestAccount {
+        let keypair = Keypair::new();
+        let keypair_path = self.dir.path().join(format!("{}.json", name));
+        write_keypair_file(&keypair, &keypair_path).expect("Failed to write keypair.");
+        let account = TestAccount {
+            pubkey: keypair.pubkey(),
+            keypair_path,
+        };
+        if let Some(amount) = fund_sol {
+            self.solana(&[
+                "transfer",
+                "--allow-unfunded-recipient",
+                &account.pubkey.to_string(),
+                amount,
+            ]);
+        }
+        account
+    }
+
+    /// Create a vote account for a new validator identity.
+    pub fn create_vote_account(&self, name: &str, authorized_withdrawer: &str) -> TestAccount {
+        let identity = self.create_test_account(&format!("{}-identity", name), Some("1.0"));
+        let vote_account = self.create_test_account(&format!("{}-vote", name), None);
+        self.solana(&[
+            "create-vote-account",
+            vote_account.keypair_path.to_str().unwrap(),
+            identity.keypair_path.to_str().unwrap(),
+            "--authorized-withdrawer",
+            authorized_withdrawer,
+            "--commission",
+            "100",
+        ]);
+        vote_account
+    }
+
+    /// Create an SPL token account for `mint`, owned by `owner`, return its address.
+    pub fn create_spl_token_account(&self, owner: &TestAccount, mint: &str) -> String {
+        // The output looks like 'Creating account <address>\n\nSignature: <sig>'.
+        let output = self.spl_token(&[
+            "create-account",
+            mint,
+            "--owner",
+            owner.keypair_path.to_str().unwrap(),
+        ]);
+        output
+            .lines()
+            .next()
+            .and_then(|line| line.split(' ').nth(2))
+            .expect("Unexpected spl-token output.")
+            .to_string()
+    }
+
+    /// Deploy a program from the program directory, return its program id.
+    pub fn deploy_program(&self, file_name: &str) -> String {
+        let path = get_solido_program_path().join(file_name);
+        assert!(
+            path.is_file(),
+            "{} does not exist, run 'cargo build-bpf' first.",
+            path.display()
+        );
+        let output = self.solana(&[
+            "program",
+            "deploy",
+            "--output",
+            "json",
+            path.to_str().unwrap(),
+        ]);
+        let result: Value = serde_json::from_str(&output).expect("Expected json from deploy.");
+        result["programId"]
+            .as_str()
+            .expect("Expected programId in deploy output.")
+            .to_string()
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        // Ignore errors, the process may have exited already.
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
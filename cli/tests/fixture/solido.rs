@@ -0,0 +1,252 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A Solido instance on a test validator, created and driven through the CLI.
+
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use solido_cli::config::OutputMode;
+use solido_cli::maintenance::SolidoState;
+use solido_cli::SnapshotClientConfig;
+
+use super::{TestAccount, TestValidator};
+
+/// A deployed Solido instance, with a multisig that has a single owner.
+pub struct SolidoTestInstance {
+    pub validator: TestValidator,
+    pub owner: TestAccount,
+    pub multisig_program_id: String,
+    pub multisig_address: String,
+    pub solido_program_id: String,
+    pub solido_address: String,
+    pub st_sol_mint: String,
+}
+
+impl SolidoTestInstance {
+    /// Start a test validator with default epochs, and create an instance on it.
+    pub fn new() -> SolidoTestInstance {
+        SolidoTestInstance::on_validator(TestValidator::start())
+    }
+
+    /// Deploy the programs to `validator`, and create an instance.
+    pub fn on_validator(validator: TestValidator) -> SolidoTestInstance {
+        let owner = validator.create_test_account("owner", Some("10.0"));
+        let treasury_owner = validator.create_test_account("treasury", None);
+        let developer_owner = validator.create_test_account("developer", None);
+
+        let solido_program_id = validator.deploy_program("lido.so");
+        let multisig_program_id = validator.deploy_program("serum_multisig.so");
+
+        let owner_pubkey = owner.pubkey.to_string();
+        let multisig = validator.solido(
+            None,
+            &[
+                "multisig",
+                "create-multisig",
+                "--multisig-program-id",
+                &multisig_program_id,
+                "--threshold",
+                "1",
+                "--owners",
+                &owner_pubkey,
+            ],
+        );
+        let multisig_address = multisig["multisig_address"].as_str().unwrap().to_string();
+
+        let result = validator.solido(
+            None,
+            &[
+                "create-solido",
+                "--multisig-program-id",
+                &multisig_program_id,
+                "--solido-program-id",
+                &solido_program_id,
+                "--max-validators",
+                "9",
+                "--max-maintainers",
+                "1",
+                "--treasury-fee-share",
+                "5",
+                "--validation-fee-share",
+                "3",
+                "--developer-fee-share",
+                "2",
+                "--st-sol-appreciation-share",
+                "90",
+                "--treasury-account-owner",
+                &treasury_owner.pubkey.to_string(),
+                "--developer-account-owner",
+                &developer_owner.pubkey.to_string(),
+                "--multisig-address",
+                &multisig_address,
+            ],
+        );
+
+        SolidoTestInstance {
+            solido_address: result["solido_address"].as_str().unwrap().to_string(),
+            st_sol_mint: result["st_sol_mint_address"].as_str().unwrap().to_string(),
+            validator,
+            owner,
+            multisig_program_id,
+            multisig_address,
+            solido_program_id,
+        }
+    }
+
+    pub fn show_solido(&self) -> Value {
+        self.validator.solido(
+            None,
+            &[
+                "show-solido",
+                "--solido-program-id",
+                &self.solido_program_id,
+                "--solido-address",
+                &self.solido_address,
+            ],
+        )
+    }
+
+    /// Execute a multisig transaction proposed by the single owner.
+    ///
+    /// With a threshold of 1, proposing already counts as approval.
+    pub fn execute(&self, proposal: &Value) {
+        let transaction_address = proposal["transaction_address"].as_str().unwrap();
+        self.validator.solido(
+            Some(&self.owner),
+            &[
+                "multisig",
+                "execute-transaction",
+                "--multisig-program-id",
+                &self.multisig_program_id,
+                "--multisig-address",
+                &self.multisig_address,
+                "--transaction-address",
+                transaction_address,
+            ],
+        );
+    }
+
+    /// Create a vote account and fee account, and add the validator to Solido.
+    pub fn add_validator(&self, name: &str) -> String {
+        let solido = self.show_solido();
+        let vote_account = self
+            .validator
+            .create_vote_account(name, solido["rewards_withdraw_authority"].as_str().unwrap());
+        let fee_owner = self
+            .validator
+            .create_test_account(&format!("{}-fee", name), Some("1.0"));
+        let fee_account = self
+            .validator
+            .create_spl_token_account(&fee_owner, &self.st_sol_mint);
+
+        let vote_account_pubkey = vote_account.pubkey.to_string();
+        let proposal = self.validator.solido(
+            Some(&self.owner),
+            &[
+                "add-validator",
+                "--multisig-program-id",
+                &self.multisig_program_id,
+                "--solido-program-id",
+                &self.solido_program_id,
+                "--solido-address",
+                &self.solido_address,
+                "--validator-vote-account",
+                &vote_account_pubkey,
+                "--validator-fee-account",
+                &fee_account,
+                "--multisig-address",
+                &self.multisig_address,
+            ],
+        );
+        self.execute(&proposal);
+        vote_account_pubkey
+    }
+
+    pub fn add_maintainer(&self, maintainer: &TestAccount) {
+        let proposal = self.validator.solido(
+            Some(&self.owner),
+            &[
+                "add-maintainer",
+                "--multisig-program-id",
+                &self.multisig_program_id,
+                "--solido-program-id",
+                &self.solido_program_id,
+                "--solido-address",
+                &self.solido_address,
+                "--maintainer-address",
+                &maintainer.pubkey.to_string(),
+                "--multisig-address",
+                &self.multisig_address,
+            ],
+        );
+        self.execute(&proposal);
+    }
+
+    pub fn perform_maintenance(&self, maintainer: &TestAccount) -> Value {
+        self.validator.solido(
+            Some(maintainer),
+            &[
+                "perform-maintenance",
+                "--solido-address",
+                &self.solido_address,
+                "--solido-program-id",
+                &self.solido_program_id,
+            ],
+        )
+    }
+
+    /// Deposit SOL from the payer, return the output of `solido deposit`.
+    pub fn deposit(&self, amount_sol: &str) -> Value {
+        self.validator.solido(
+            None,
+            &[
+                "deposit",
+                "--solido-address",
+                &self.solido_address,
+                "--solido-program-id",
+                &self.solido_program_id,
+                "--amount-sol",
+                amount_sol,
+            ],
+        )
+    }
+
+    /// Perform maintenance until there is nothing left to do, return what was done.
+    pub fn perform_maintenance_until_idle(&self, maintainer: &TestAccount) -> Vec<Value> {
+        let mut performed = Vec::new();
+        // Every task changes the state so it is not needed again, but put a
+        // bound on it, so a bug in the daemon fails the test instead of hanging.
+        for _ in 0..50 {
+            let result = self.perform_maintenance(maintainer);
+            if result.is_null() {
+                return performed;
+            }
+            performed.push(result);
+        }
+        panic!(
+            "Maintenance did not become idle, performed: {:?}",
+            performed
+        );
+    }
+
+    /// Read the state that the maintenance daemon decides on, as `maintainer` sees it.
+    ///
+    /// This runs the daemon's decision logic in-process against the real
+    /// on-chain state, rather than against a synthetic `SolidoState`.
+    pub fn solido_state(&self, maintainer: &TestAccount) -> SolidoState {
+        let signer = maintainer.keypair();
+        let mut config = SnapshotClientConfig {
+            client: self.validator.snapshot_client(),
+            signer: &signer,
+            fee_payer: &signer,
+            output_mode: OutputMode::Json,
+            output_transaction_path: None,
+            nonce: None,
+        };
+        let solido_program_id: Pubkey = self.solido_program_id.parse().unwrap();
+        let solido_address: Pubkey = self.solido_address.parse().unwrap();
+        config
+            .with_snapshot(|config| SolidoState::new(config, &solido_program_id, &solido_address))
+            .expect("Failed to read Solido state.")
+    }
+}
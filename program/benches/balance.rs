@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Benchmarks for the stake balancing computations that the maintainer runs
+//! on every iteration.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use solana_program::pubkey::Pubkey;
+
+use lido::balance::{get_target_balance, get_validator_furthest_below_target};
+use lido::state::{Validator, Validators};
+use lido::token::Lamports;
+
+/// Build a validator set with uneven balances, so the targets are not trivial.
+fn new_validators(n: u32) -> Validators {
+    let mut validators = Validators::new(n);
+    for i in 0..n {
+        let mut validator = Validator::new(Pubkey::new_unique());
+        validator.stake_accounts_balance = Lamports(1_000_000_000 * (u64::from(i) % 17 + 1));
+        validators.add(Pubkey::new_unique(), validator).unwrap();
+    }
+    validators
+}
+
+fn bench_target_balance(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_target_balance");
+    for &n in &[100, 1_000, 5_000] {
+        let validators = new_validators(n);
+        let undelegated = Lamports(123_456_789_000);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &validators, |b, v| {
+            b.iter(|| get_target_balance(black_box(undelegated), black_box(v), None, None).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_furthest_below_target(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_validator_furthest_below_target");
+    for &n in &[100, 1_000, 5_000] {
+        let validators = new_validators(n);
+        let targets =
+            get_target_balance(Lamports(123_456_789_000), &validators, None, None).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &validators, |b, v| {
+            b.iter(|| get_validator_furthest_below_target(black_box(v), black_box(&targets), None))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_target_balance, bench_furthest_below_target);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Round-trip arbitrary bytes through the `Lido` deserializer and serializer.
+//!
+//! Run with `cargo +nightly fuzz run lido_state` from the `program` directory.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    lido::fuzz::lido_roundtrip(data);
+});
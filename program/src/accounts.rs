@@ -54,6 +54,7 @@ macro_rules! accounts_struct_meta {
 /// ```
 /// This generates two structs:
 /// ```
+/// # use lido::idl::IdlAccount;
 /// # use solana_program::{pubkey::Pubkey, account_info::AccountInfo, instruction::AccountMeta, program_error::ProgramError};
 /// struct ExampleAccountsMeta {
 ///     frobnicator: Pubkey,
@@ -64,6 +65,10 @@ macro_rules! accounts_struct_meta {
 ///     pub fn to_vec(&self) -> Vec<AccountMeta> {
 ///         # unimplemented!("Body omitted in example.")
 ///     }
+///
+///     pub fn idl_accounts() -> Vec<IdlAccount> {
+///         # unimplemented!("Body omitted in example.")
+///     }
 /// }
 ///
 /// struct ExampleAccountsInfo<'a> {
@@ -158,7 +163,7 @@ macro_rules! accounts_struct {
                     $(
                         accounts_struct_meta!(
                             self.$var_account,
-                            is_signer: $is_signer,
+                            signer: $is_signer,
                             is_writable: $is_writable,
                         )
                     ),*
@@ -184,6 +189,46 @@ macro_rules! accounts_struct {
                 result
             }
 
+            /// Describe the accounts for the IDL, in the order that `to_vec` returns them.
+            #[cfg(not(target_arch = "bpf"))]
+            pub fn idl_accounts() -> Vec<$crate::idl::IdlAccount> {
+                // The mut is used depending on whether we have a variadic account at the end.
+                #[allow(unused_mut)]
+                let mut result = vec![
+                    $(
+                        $crate::idl::IdlAccount {
+                            name: stringify!($var_account),
+                            writable: $is_writable,
+                            signer: $is_signer,
+                            address: None,
+                            variadic: false,
+                        }
+                    ),*
+                    $(
+                        ,
+                        $(
+                            $crate::idl::IdlAccount {
+                                name: stringify!($const_account),
+                                writable: false,
+                                signer: false,
+                                address: Some($const_value.to_string()),
+                                variadic: false,
+                            }
+                        ),*
+                    )?
+                ];
+                $(
+                    result.push($crate::idl::IdlAccount {
+                        name: stringify!($multi_account),
+                        writable: true,
+                        signer: false,
+                        address: None,
+                        variadic: true,
+                    });
+                )?
+                result
+            }
+
             // The `AccountsMeta::try_from_slice` function is not always used,
             // so we put an allow(dead_code) on it. For the accounts structs to
             // be useful, the pair of `AccountsMeta::to_vec` and
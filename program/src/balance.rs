@@ -3,54 +3,82 @@
 
 //! Logic for keeping the stake pool balanced.
 
+use std::cmp::{Ordering, Reverse};
 use std::ops::Mul;
 
-use crate::account_map::PubkeyAndEntry;
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solana_program::pubkey::Pubkey;
+
 use crate::state::{Validator, Validators};
 use crate::{
     error::LidoError,
     token,
     token::{Lamports, Rational},
+    MINIMUM_STAKE_ACCOUNT_BALANCE,
 };
 
-/// Compute the ideal stake balance for each validator.
+/// Return whether a validator should receive stake, according to its `active` flag and weight.
+pub fn receives_stake(validator: &Validator) -> bool {
+    validator.active && validator.weight > 0
+}
+
+/// Return whether a validator has room for another stake account below the
+/// maximum stake per validator, if there is a maximum.
 ///
-/// The validator order in the result is the same as in `current_balance`.
+/// `StakeDeposit` only accepts validators that have room, and only up to the
+/// maximum, so this needs room for at least the minimum stake account balance.
+pub fn has_room_for_stake(
+    validator: &Validator,
+    max_stake_per_validator: Option<Lamports>,
+) -> bool {
+    match max_stake_per_validator {
+        None => true,
+        // If the sum overflows, there is certainly no room.
+        Some(max_stake) => {
+            match validator.effective_stake_balance() + MINIMUM_STAKE_ACCOUNT_BALANCE {
+                Ok(with_new_account) => with_new_account <= max_stake,
+                Err(..) => false,
+            }
+        }
+    }
+}
+
+/// Distribute `total_lamports` over the validators for which `participates`
+/// is true, proportionally to their weight.
 ///
-/// This function targets a uniform distribution over all active validators.
-pub fn get_target_balance(
-    undelegated_lamports: Lamports,
+/// Validators that do not participate get zero. There must be at least one
+/// participating validator, and all participating validators must have a
+/// nonzero weight.
+fn distribute_by_weight(
+    total_lamports: Lamports,
     validators: &Validators,
-) -> Result<Vec<Lamports>, LidoError> {
-    let total_delegated_lamports: token::Result<Lamports> = validators
+    participates: &[bool],
+) -> Vec<Lamports> {
+    let num_participating = participates.iter().filter(|p| **p).count() as u64;
+
+    // The sum of at most u32::MAX weights of u32::MAX each fits in a u64.
+    let total_weight: u64 = validators
         .iter_entries()
-        .map(|v| v.stake_accounts_balance)
+        .zip(participates)
+        .filter(|(_, p)| **p)
+        .map(|(v, _)| v.weight as u64)
         .sum();
 
-    let total_lamports = total_delegated_lamports.and_then(|t| t + undelegated_lamports)?;
-
-    // We only want to target validators that are not in the process of being
-    // removed.
-    let num_active_validators = validators.iter_active().count() as u64;
-
-    // No active validators.
-    if num_active_validators == 0 {
-        return Err(LidoError::NoActiveValidators);
-    }
-
-    let lamports_per_validator = total_lamports
-        .mul(Rational {
-            numerator: 1,
-            denominator: num_active_validators,
-        })
-        .expect("Does not divide by zero because `num_active_validators != 0`");
-
-    // Target an uniform distribution.
+    // Target a distribution proportional to the weights.
     let mut target_balance: Vec<Lamports> = validators
         .iter_entries()
-        .map(|validator| {
-            if validator.active {
-                lamports_per_validator
+        .zip(participates)
+        .map(|(validator, p)| {
+            if *p {
+                total_lamports
+                    .mul(Rational {
+                        numerator: validator.weight as u64,
+                        denominator: total_weight,
+                    })
+                    .expect(
+                        "Does not divide by zero because `total_weight != 0`, \
+                        and does not overflow because `weight <= total_weight`.",
+                    )
             } else {
                 Lamports(0)
             }
@@ -68,20 +96,22 @@ pub fn get_target_balance(
     let mut remainder = (total_lamports - total_lamports_distributed)
         .expect("Does not underflow because we distribute at most total_lamports.");
 
-    assert!(remainder.0 < num_active_validators);
+    // Every participating validator loses less than one Lamport due to
+    // rounding down.
+    assert!(remainder.0 < num_participating);
 
-    // Distribute the remainder among the first few active validators, give them
-    // one Lamport each. This does mean that the validators early in the list
-    // are in a more beneficial position because their stake target is one
-    // Lamport higher, but to put that number into perspective, the transaction
-    // fee per signature is 10k Lamports at the time of writing. Also, there is
-    // a minimum amount we can stake, so in practice, validators will never be
-    // as close to their target that the one Lamport matters anyway.
-    for (target, validator) in target_balance.iter_mut().zip(validators.iter_entries()) {
+    // Distribute the remainder among the first few participating validators,
+    // give them one Lamport each. This does mean that the validators early in
+    // the list are in a more beneficial position because their stake target is
+    // one Lamport higher, but to put that number into perspective, the
+    // transaction fee per signature is 10k Lamports at the time of writing.
+    // Also, there is a minimum amount we can stake, so in practice, validators
+    // will never be as close to their target that the one Lamport matters anyway.
+    for (target, p) in target_balance.iter_mut().zip(participates) {
         if remainder == Lamports(0) {
             break;
         }
-        if validator.active {
+        if *p {
             *target = (*target + Lamports(1)).expect(
                 "Does not overflow because per-validator balance is at most total_lamports.",
             );
@@ -99,16 +129,224 @@ pub fn get_target_balance(
 
     assert_eq!(total_lamports_distributed, total_lamports);
 
+    target_balance
+}
+
+/// Return the vote accounts of the cluster's superminority.
+///
+/// The superminority is the smallest set of vote accounts that together have
+/// more than a third of the activated stake. These validators can halt the
+/// cluster when they collude, so their number is the Nakamoto coefficient.
+/// `cluster_stakes` holds the activated stake of every vote account.
+pub fn find_superminority(cluster_stakes: &[(Pubkey, Lamports)]) -> Vec<Pubkey> {
+    let total_stake: u128 = cluster_stakes
+        .iter()
+        .map(|(_, stake)| stake.0 as u128)
+        .sum();
+    if total_stake == 0 {
+        return Vec::new();
+    }
+
+    let mut by_stake: Vec<&(Pubkey, Lamports)> = cluster_stakes.iter().collect();
+    by_stake.sort_by_key(|(pubkey, stake)| (Reverse(*stake), *pubkey));
+
+    let mut superminority = Vec::new();
+    let mut stake_so_far: u128 = 0;
+    for (pubkey, stake) in by_stake {
+        if stake_so_far * 3 > total_stake {
+            break;
+        }
+        superminority.push(*pubkey);
+        stake_so_far += stake.0 as u128;
+    }
+    superminority
+}
+
+/// Compute the ideal stake balance for each validator.
+///
+/// The validator order in the result is the same as in `current_balance`.
+///
+/// This function distributes the stake over all active validators,
+/// proportionally to their weight. Inactive validators target zero stake.
+///
+/// If there is a maximum stake per validator, then validators that are already
+/// at or above the maximum get no additional target, their target is their
+/// current stake. Validators that would get a target above the maximum get the
+/// maximum as target instead. In both cases, the excess is redistributed over
+/// the remaining validators. If all validators are at the maximum, the targets
+/// add up to less than the total, and the rest stays in the reserve.
+///
+/// If `superminority` is set, then validators whose vote account is in it get
+/// no target above their current stake, and the excess is redistributed over
+/// the other validators, so we don't concentrate more stake in the validators
+/// that can already halt the cluster, see [`find_superminority`]. The program
+/// has no view of the cluster's stake, so the maintainer passes this to
+/// `Rebalance`. Because it only ever lowers the stake above target, the
+/// program does not need to trust it. When no validator outside the
+/// superminority receives stake, it has no effect.
+///
+/// The target does not depend on the commission of the validator's vote
+/// account. `AddValidator` only accepts vote accounts with 100% commission and
+/// with Solido's rewards withdraw authority as withdrawer, and without the
+/// withdrawer's signature the commission cannot change, so all validators have
+/// the same commission. What validators earn is the `validation_fee` of the
+/// reward distribution, which is the same for all of them. To reduce the
+/// stake of a validator, the manager can lower its weight instead.
+pub fn get_target_balance(
+    undelegated_lamports: Lamports,
+    validators: &Validators,
+    max_stake_per_validator: Option<Lamports>,
+    superminority: Option<&[Pubkey]>,
+) -> Result<Vec<Lamports>, LidoError> {
+    let total_delegated_lamports: token::Result<Lamports> = validators
+        .iter_entries()
+        .map(|v| v.stake_accounts_balance)
+        .sum();
+
+    let total_lamports = total_delegated_lamports.and_then(|t| t + undelegated_lamports)?;
+
+    // We only want to target validators that are not in the process of being
+    // removed, and that have a nonzero weight.
+    let mut participates: Vec<bool> = validators.iter_entries().map(receives_stake).collect();
+
+    // No active validators with a nonzero weight.
+    if !participates.iter().any(|p| *p) {
+        return Err(LidoError::NoActiveValidators);
+    }
+
+    let in_superminority: Vec<bool> = match superminority {
+        None => vec![false; validators.len()],
+        Some(superminority) => validators
+            .entries
+            .iter()
+            .map(|v| superminority.contains(&v.pubkey))
+            .collect(),
+    };
+    let avoid_superminority = participates
+        .iter()
+        .zip(&in_superminority)
+        .any(|(p, s)| *p && !*s);
+
+    // The most that each validator can target. For validators in the
+    // superminority, that is their current stake.
+    let caps: Vec<Option<Lamports>> = validators
+        .iter_entries()
+        .zip(&in_superminority)
+        .map(|(validator, is_in_superminority)| {
+            let stake = validator.effective_stake_balance();
+            match (
+                max_stake_per_validator,
+                *is_in_superminority && avoid_superminority,
+            ) {
+                (None, false) => None,
+                (None, true) => Some(stake),
+                (Some(max_stake), false) => Some(max_stake),
+                (Some(max_stake), true) => Some(max_stake.min(stake)),
+            }
+        })
+        .collect();
+
+    if caps.iter().all(|cap| cap.is_none()) {
+        return Ok(distribute_by_weight(
+            total_lamports,
+            validators,
+            &participates,
+        ));
+    }
+
+    let mut target_balance = vec![Lamports(0); validators.len()];
+    let mut remaining_lamports = total_lamports;
+
+    // Validators at or above the maximum keep their current stake as target.
+    if let Some(max_stake) = max_stake_per_validator {
+        for (i, validator) in validators.iter_entries().enumerate() {
+            let stake = validator.effective_stake_balance();
+            if participates[i] && stake >= max_stake {
+                target_balance[i] = stake;
+                participates[i] = false;
+                remaining_lamports = (remaining_lamports - stake)
+                    .expect("Does not underflow, the total includes the stake of all validators.");
+            }
+        }
+    }
+
+    // Distribute the remainder over the other validators. When that puts some
+    // of them above their cap, fix their target at the cap, and distribute
+    // what is left over the others again. Every round fixes the target of at
+    // least one more validator, so this terminates.
+    while participates.iter().any(|p| *p) {
+        let proposed = distribute_by_weight(remaining_lamports, validators, &participates);
+        let mut any_above_cap = false;
+        for i in 0..proposed.len() {
+            match caps[i] {
+                Some(cap) if participates[i] && proposed[i] > cap => {
+                    target_balance[i] = cap;
+                    participates[i] = false;
+                    remaining_lamports = (remaining_lamports - cap).expect(
+                        "Does not underflow, the proposed target is at most the remainder.",
+                    );
+                    any_above_cap = true;
+                }
+                _ => {}
+            }
+        }
+        if !any_above_cap {
+            for (target, (p, proposed_target)) in target_balance
+                .iter_mut()
+                .zip(participates.iter().zip(proposed))
+            {
+                if *p {
+                    *target = proposed_target;
+                }
+            }
+            remaining_lamports = Lamports(0);
+            break;
+        }
+    }
+
+    // Sanity check: we distributed everything, except for what does not fit
+    // below the caps.
+    let total_lamports_distributed = target_balance
+        .iter()
+        .cloned()
+        .sum::<token::Result<Lamports>>()
+        .expect("Does not overflow, is at most total_lamports.");
+
+    assert_eq!(
+        (total_lamports_distributed + remaining_lamports),
+        Ok(total_lamports)
+    );
+
     Ok(target_balance)
 }
 
+/// Compare the stake of two validators relative to their weight.
+///
+/// Returns `Less` if `a` has less stake per unit of weight than `b`. Both
+/// validators must have a nonzero weight.
+pub fn cmp_stake_per_weight(a: &Validator, b: &Validator) -> Ordering {
+    // Cross-multiply instead of dividing, a u64 times a u32 fits in a u128.
+    let a_scaled = a.effective_stake_balance().0 as u128 * b.weight as u128;
+    let b_scaled = b.effective_stake_balance().0 as u128 * a.weight as u128;
+    a_scaled.cmp(&b_scaled)
+}
+
 /// Given a list of validators and their target balance, return the index of the
-/// one furthest below its target, and the amount by which it is below.
+/// one furthest below its target relative to its weight, and the amount by
+/// which it is below.
+///
+/// This is the validator with the least stake per unit of weight, among the
+/// validators that have room below the maximum stake per validator, which is
+/// the validator that `StakeDeposit` accepts. If multiple validators have the
+/// same stake per unit of weight, we pick the one that is the most below its
+/// target.
 ///
-/// This assumes that there is at least one active validator. Panics otherwise.
+/// This assumes that there is at least one active validator with a nonzero
+/// weight that has room for stake. Panics otherwise.
 pub fn get_validator_furthest_below_target(
     validators: &Validators,
     target_balance: &[Lamports],
+    max_stake_per_validator: Option<Lamports>,
 ) -> (usize, Lamports) {
     assert_eq!(
         validators.len(),
@@ -116,44 +354,252 @@ pub fn get_validator_furthest_below_target(
         "Must have as many target balances as current balances."
     );
 
-    // Our initial index, that will be returned when no validator is below its target,
-    // is the first active validator.
-    let mut index = validators
-        .iter_entries()
-        .position(|v| v.active)
-        .expect("get_validator_furthest_below_target requires at least one active validator.");
-    let mut amount = Lamports(0);
+    let mut best: Option<(usize, &Validator, Lamports)> = None;
 
     for (i, (validator, target)) in validators.iter_entries().zip(target_balance).enumerate() {
+        if !receives_stake(validator) || !has_room_for_stake(validator, max_stake_per_validator) {
+            continue;
+        }
         let amount_below = Lamports(
             target
                 .0
                 .saturating_sub(validator.effective_stake_balance().0),
         );
-        if amount_below > amount {
-            amount = amount_below;
-            index = i;
+        let is_better = match best {
+            None => true,
+            Some((_, best_validator, best_amount)) => {
+                match cmp_stake_per_weight(validator, best_validator) {
+                    Ordering::Less => true,
+                    Ordering::Equal => amount_below > best_amount,
+                    Ordering::Greater => false,
+                }
+            }
+        };
+        if is_better {
+            best = Some((i, validator, amount_below));
         }
     }
 
+    let (index, _, amount) =
+        best.expect("get_validator_furthest_below_target requires at least one active validator.");
     (index, amount)
 }
 
-pub fn get_validator_to_withdraw(
+/// How far a validator's stake must exceed its target before we rebalance, as
+/// a fraction of the target.
+///
+/// Without a margin, every deposit that shifts the targets would cause stake
+/// to move back and forth, and every move costs an epoch of rewards.
+pub const REBALANCE_THRESHOLD: Rational = Rational {
+    numerator: 1,
+    denominator: 10,
+};
+
+/// Return the amount of stake above its target that `Rebalance` may unstake
+/// from the validator, if the validator is overweight by more than the threshold.
+///
+/// Only validators that receive stake are rebalanced. Inactive validators are
+/// unstaked fully by `Unstake` already, and a validator with weight 0 has a
+/// target of 0, but it keeps its stake until it is deactivated. The excess
+/// must also be at least the minimum stake account balance, because it goes
+/// into a new unstake account.
+pub fn get_rebalance_amount(validator: &Validator, target: Lamports) -> Option<Lamports> {
+    if !receives_stake(validator) {
+        return None;
+    }
+    let excess = Lamports(
+        validator
+            .effective_stake_balance()
+            .0
+            .saturating_sub(target.0),
+    );
+    let threshold = target
+        .mul(REBALANCE_THRESHOLD)
+        .expect("Does not overflow because the threshold is less than one.")
+        .max(MINIMUM_STAKE_ACCOUNT_BALANCE);
+    if excess > threshold {
+        Some(excess)
+    } else {
+        None
+    }
+}
+
+/// Return the most that `Withdraw` splits off a stake account with `source_balance`.
+///
+/// This is 10% of the stake account's balance plus a small constant. The 10%
+/// caps the imbalance that a withdrawal can create at large balances, and in
+/// that case the constant is negligible, but the constant does ensure that we
+/// can reach the minimum in a finite number of withdrawals.
+pub fn get_max_withdraw_amount(source_balance: Lamports) -> token::Result<Lamports> {
+    let fraction = source_balance
+        * Rational {
+            numerator: 1,
+            denominator: 10,
+        };
+    fraction.expect("Multiplying with 0.1 does not overflow or divide by zero.")
+        + Lamports(10 * LAMPORTS_PER_SOL)
+}
+
+/// Return a plan to withdraw `amount` of SOL from the validators: the indices
+/// of the validators to withdraw from, with the amount to withdraw from each,
+/// in the order in which to withdraw.
+///
+/// `Withdraw` only accepts the validator with the most effective stake, so a
+/// withdrawal that is larger than the difference with the next validator has
+/// to be split. We lower the heaviest validators to a common level, which
+/// brings the validators closer together. The plan starts with the heaviest
+/// validator, and every validator in it keeps at least the balance that the
+/// validators before it end up with, so at every step we withdraw from a
+/// validator with the most effective stake. Every validator keeps at least the
+/// minimum stake account balance; if the validators together cannot cover
+/// `amount` with that, this returns `InvalidAmount`.
+///
+/// Note that `Withdraw` further limits the amount per stake account, larger
+/// withdrawals need to be broken up into more steps, see [`get_withdraw_steps`].
+pub fn get_withdraw_plan(
     validators: &Validators,
-) -> Result<&PubkeyAndEntry<Validator>, crate::error::LidoError> {
-    validators
-        .entries
-        .iter()
-        .max_by_key(|v| v.entry.effective_stake_balance())
-        .ok_or(LidoError::NoActiveValidators)
+    amount: Lamports,
+) -> Result<Vec<(usize, Lamports)>, LidoError> {
+    let balances: Vec<u64> = validators
+        .iter_entries()
+        .map(|v| v.effective_stake_balance().0)
+        .collect();
+    if balances.is_empty() {
+        return Err(LidoError::NoActiveValidators);
+    }
+    if amount == Lamports(0) {
+        return Ok(Vec::new());
+    }
+
+    // Heaviest first, ties in the order of the validator list.
+    let mut order: Vec<usize> = (0..balances.len()).collect();
+    order.sort_by_key(|&i| Reverse(balances[i]));
+
+    // Lower the `k` heaviest validators together, to the level of the next
+    // validator, until lowering them further covers the remaining amount.
+    let mut level = balances[order[0]];
+    let mut remaining = amount.0;
+    for k in 1..=order.len() {
+        let next_level = match order.get(k) {
+            Some(&i) => balances[i].max(MINIMUM_STAKE_ACCOUNT_BALANCE.0),
+            None => MINIMUM_STAKE_ACCOUNT_BALANCE.0,
+        };
+        let capacity = level
+            .saturating_sub(next_level)
+            .checked_mul(k as u64)
+            .ok_or(LidoError::CalculationFailure)?;
+
+        if remaining > capacity {
+            remaining -= capacity;
+            // If the heaviest validators are below the minimum already, the
+            // level stays there, and there is nothing to withdraw.
+            level = level.min(next_level);
+            continue;
+        }
+
+        // The first validators lose one Lamport more, to distribute the remainder.
+        let drop = remaining / k as u64;
+        let extra = (remaining % k as u64) as usize;
+        let final_level = level - drop;
+        let plan = order[..k]
+            .iter()
+            .enumerate()
+            .map(|(j, &i)| {
+                let final_balance = if j < extra {
+                    final_level - 1
+                } else {
+                    final_level
+                };
+                (i, Lamports(balances[i] - final_balance))
+            })
+            .filter(|(_, amount)| *amount > Lamports(0))
+            .collect();
+        return Ok(plan);
+    }
+
+    Err(LidoError::InvalidAmount)
+}
+
+/// A single `Withdraw` of a withdraw plan, see [`get_withdraw_steps`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawStep {
+    /// Index of the validator to withdraw from.
+    pub validator_index: usize,
+
+    /// Amount of SOL to withdraw.
+    pub amount: Lamports,
+
+    /// The most that `Withdraw` accepts in this step, given the balance of the
+    /// source stake account at that point.
+    pub max_amount: Lamports,
+}
+
+/// Break up a plan from [`get_withdraw_plan`] into withdrawals that `Withdraw`
+/// accepts, in the order in which to execute them.
+///
+/// `Withdraw` splits the validator's stake account at `stake_seeds.begin`,
+/// which holds `source_balances[i]` for validator `i`. It accepts at most
+/// [`get_max_withdraw_amount`] of that balance, and the stake account has to
+/// keep the minimum stake account balance, so a validator's part of the plan
+/// may take multiple withdrawals. At every step we withdraw from the validator
+/// with the most effective stake that still has a part left, which is the
+/// validator that `Withdraw` requires. If a stake account cannot cover its
+/// validator's part, this returns `InvalidAmount`.
+pub fn get_withdraw_steps(
+    validators: &Validators,
+    source_balances: &[Lamports],
+    plan: &[(usize, Lamports)],
+) -> Result<Vec<WithdrawStep>, LidoError> {
+    let mut balances: Vec<Lamports> = validators
+        .iter_entries()
+        .map(|v| v.effective_stake_balance())
+        .collect();
+    let mut source_balances = source_balances.to_vec();
+    let mut remaining = vec![Lamports(0); balances.len()];
+    for &(i, amount) in plan {
+        remaining[i] = amount;
+    }
+
+    let mut steps = Vec::new();
+    while let Some(i) = (0..balances.len())
+        .filter(|&i| remaining[i] > Lamports(0))
+        .max_by_key(|&i| balances[i])
+    {
+        let max_amount = get_max_withdraw_amount(source_balances[i])?.min(Lamports(
+            source_balances[i]
+                .0
+                .saturating_sub(MINIMUM_STAKE_ACCOUNT_BALANCE.0),
+        ));
+        let amount = remaining[i].min(max_amount);
+        if amount == Lamports(0) {
+            return Err(LidoError::InvalidAmount);
+        }
+        steps.push(WithdrawStep {
+            validator_index: i,
+            amount,
+            max_amount,
+        });
+        balances[i] = (balances[i] - amount)?;
+        source_balances[i] = (source_balances[i] - amount)?;
+        remaining[i] = (remaining[i] - amount)?;
+    }
+
+    Ok(steps)
 }
 
 #[cfg(test)]
 mod test {
-    use super::{get_target_balance, get_validator_furthest_below_target};
+    use solana_program::pubkey::Pubkey;
+
+    use super::{
+        find_superminority, get_max_withdraw_amount, get_rebalance_amount, get_target_balance,
+        get_validator_furthest_below_target, get_withdraw_plan, get_withdraw_steps,
+        has_room_for_stake, WithdrawStep,
+    };
+    use crate::error::LidoError;
     use crate::state::Validators;
     use crate::token::Lamports;
+    use crate::MINIMUM_STAKE_ACCOUNT_BALANCE;
 
     #[test]
     fn get_target_balance_works_for_single_validator() {
@@ -161,13 +607,13 @@ mod test {
         let mut validators = Validators::new_fill_default(1);
         validators.entries[0].entry.stake_accounts_balance = Lamports(100);
         let undelegated_stake = Lamports(50);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets[0], Lamports(150));
 
         // With only one validator, that one is the least balanced. It is
         // missing the 50 undelegated Lamports.
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (0, Lamports(50))
         );
     }
@@ -180,12 +626,12 @@ mod test {
         validators.entries[1].entry.stake_accounts_balance = Lamports(99);
 
         let undelegated_stake = Lamports(50);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets, [Lamports(125), Lamports(125)]);
 
         // The second validator is further away from its target.
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (1, Lamports(26))
         );
     }
@@ -199,12 +645,12 @@ mod test {
         validators.entries[1].entry.stake_accounts_balance = Lamports(99);
 
         let undelegated_stake = Lamports(51);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets, [Lamports(126), Lamports(125)]);
 
         // The second validator is further from its target, by one Lamport.
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (1, Lamports(26))
         );
     }
@@ -216,11 +662,11 @@ mod test {
         validators.entries[1].entry.stake_accounts_balance = Lamports(50);
 
         let undelegated_stake = Lamports(0);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets, [Lamports(50), Lamports(50)]);
 
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (0, Lamports(0))
         );
     }
@@ -233,11 +679,11 @@ mod test {
         validators.entries[2].entry.stake_accounts_balance = Lamports(99);
 
         let undelegated_stake = Lamports(51);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets, [Lamports(126), Lamports(0), Lamports(125)]);
 
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (2, Lamports(26))
         );
     }
@@ -253,11 +699,11 @@ mod test {
         validators.entries[2].entry.stake_accounts_balance = Lamports(300);
 
         let undelegated_stake = Lamports(0);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(targets, [Lamports(250), Lamports(0), Lamports(250)]);
 
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (0, Lamports(150))
         );
     }
@@ -274,7 +720,7 @@ mod test {
         validators.entries[2].entry.active = false;
 
         let undelegated_stake = Lamports(0);
-        let result = get_target_balance(undelegated_stake, &validators);
+        let result = get_target_balance(undelegated_stake, &validators, None, None);
         assert!(result.is_err());
     }
 
@@ -289,10 +735,647 @@ mod test {
         validators.entries[0].entry.active = false;
 
         let undelegated_stake = Lamports(0);
-        let targets = get_target_balance(undelegated_stake, &validators).unwrap();
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
         assert_eq!(
-            get_validator_furthest_below_target(&validators, &targets[..]),
+            get_validator_furthest_below_target(&validators, &targets[..], None),
             (1, Lamports(0)),
         );
     }
+
+    #[test]
+    fn get_target_balance_works_for_weighted_integer_multiple() {
+        // 250 Lamports in total, split 3:2 => 150 and 100.
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(101);
+        validators.entries[0].entry.weight = 3;
+        validators.entries[1].entry.stake_accounts_balance = Lamports(99);
+        validators.entries[1].entry.weight = 2;
+
+        let undelegated_stake = Lamports(50);
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
+        assert_eq!(targets, [Lamports(150), Lamports(100)]);
+
+        // The first validator is further away from its target.
+        assert_eq!(
+            get_validator_furthest_below_target(&validators, &targets[..], None),
+            (0, Lamports(49))
+        );
+    }
+
+    #[test]
+    fn get_target_balance_works_for_weighted_non_integer_multiple() {
+        // 100 Lamports in total, split 1:1:1 => 33 each, and the first
+        // validator gets the remaining Lamport.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.weight = 7;
+        validators.entries[1].entry.weight = 7;
+        validators.entries[2].entry.weight = 7;
+
+        let undelegated_stake = Lamports(100);
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
+        assert_eq!(targets, [Lamports(34), Lamports(33), Lamports(33)]);
+    }
+
+    #[test]
+    fn get_target_balance_zero_weight_gets_no_target() {
+        // A validator with weight zero is treated like an inactive one when
+        // computing targets, and the remainder skips it.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(100);
+        validators.entries[0].entry.weight = 0;
+        validators.entries[1].entry.weight = 1;
+        validators.entries[2].entry.weight = 2;
+
+        let undelegated_stake = Lamports(201);
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
+        assert_eq!(targets, [Lamports(0), Lamports(101), Lamports(200)]);
+
+        assert_eq!(
+            get_validator_furthest_below_target(&validators, &targets[..], None),
+            (2, Lamports(200))
+        );
+    }
+
+    #[test]
+    fn get_validator_furthest_below_target_is_relative_to_weight() {
+        // 400 Lamports in total, split 3:1 => 300 and 100.
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(290);
+        validators.entries[0].entry.weight = 3;
+        validators.entries[1].entry.stake_accounts_balance = Lamports(95);
+        validators.entries[1].entry.weight = 1;
+
+        let undelegated_stake = Lamports(15);
+        let targets = get_target_balance(undelegated_stake, &validators, None, None).unwrap();
+        assert_eq!(targets, [Lamports(300), Lamports(100)]);
+
+        // The first validator is further below its target in absolute terms,
+        // but the second one has less stake per unit of weight, so that is the
+        // one that `StakeDeposit` accepts.
+        assert_eq!(
+            get_validator_furthest_below_target(&validators, &targets[..], None),
+            (1, Lamports(5))
+        );
+    }
+
+    #[test]
+    fn get_target_balance_all_zero_weight() {
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.weight = 0;
+        validators.entries[1].entry.weight = 0;
+
+        let result = get_target_balance(Lamports(10), &validators, None, None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn get_target_balance_weighted_does_not_overflow() {
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(u64::MAX / 2);
+        validators.entries[0].entry.weight = u32::MAX;
+        validators.entries[1].entry.stake_accounts_balance = Lamports(u64::MAX / 2);
+        validators.entries[1].entry.weight = u32::MAX - 1;
+
+        let targets = get_target_balance(Lamports(1), &validators, None, None).unwrap();
+        assert_eq!(
+            targets[0].0 as u128 + targets[1].0 as u128,
+            u64::MAX as u128
+        );
+        assert!(targets[0] > targets[1]);
+    }
+
+    #[test]
+    fn get_target_balance_redistributes_above_max_stake() {
+        // 400 Lamports in total, split 2:1:1 would be 200, 100, 100, but the
+        // first validator can have at most 150, the rest goes to the others.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.weight = 2;
+        validators.entries[1].entry.weight = 1;
+        validators.entries[2].entry.weight = 1;
+
+        let targets =
+            get_target_balance(Lamports(400), &validators, Some(Lamports(150)), None).unwrap();
+        assert_eq!(targets, [Lamports(150), Lamports(125), Lamports(125)]);
+    }
+
+    #[test]
+    fn get_target_balance_redistributes_remainder_below_max_stake() {
+        // Like above, but with one Lamport extra. It goes to the first
+        // validator that is not at the maximum.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.weight = 2;
+        validators.entries[1].entry.weight = 1;
+        validators.entries[2].entry.weight = 1;
+
+        let targets =
+            get_target_balance(Lamports(401), &validators, Some(Lamports(150)), None).unwrap();
+        assert_eq!(targets, [Lamports(150), Lamports(126), Lamports(125)]);
+    }
+
+    #[test]
+    fn get_target_balance_already_above_max_stake() {
+        // The first validator already has more than the maximum. It gets no
+        // additional target, and the other validators share the rest.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(300);
+
+        let targets =
+            get_target_balance(Lamports(101), &validators, Some(Lamports(200)), None).unwrap();
+        assert_eq!(targets, [Lamports(300), Lamports(51), Lamports(50)]);
+    }
+
+    #[test]
+    fn get_target_balance_all_at_max_stake() {
+        // Every validator can have at most 80 Lamports, so 60 of the 300
+        // Lamports cannot be distributed, they stay in the reserve.
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(80);
+
+        let targets =
+            get_target_balance(Lamports(220), &validators, Some(Lamports(80)), None).unwrap();
+        assert_eq!(targets, [Lamports(80), Lamports(80), Lamports(80)]);
+    }
+
+    #[test]
+    fn find_superminority_takes_largest_validators_above_a_third() {
+        let stakes = [
+            (Pubkey::new_unique(), Lamports(15)),
+            (Pubkey::new_unique(), Lamports(25)),
+            (Pubkey::new_unique(), Lamports(28)),
+            (Pubkey::new_unique(), Lamports(32)),
+        ];
+        // 32 is not more than a third of 100, 32 + 28 is.
+        assert_eq!(find_superminority(&stakes), [stakes[3].0, stakes[2].0]);
+        assert!(find_superminority(&[]).is_empty());
+    }
+
+    #[test]
+    fn get_target_balance_gives_superminority_no_additional_stake() {
+        // Without the constraint, every validator would target 100. The first
+        // validator is in the superminority, so it keeps its 40, and the other
+        // two share the rest.
+        let mut validators = Validators::new_fill_default(3);
+        for validator in validators.entries.iter_mut() {
+            validator.pubkey = Pubkey::new_unique();
+        }
+        validators.entries[0].entry.stake_accounts_balance = Lamports(40);
+        let superminority = [validators.entries[0].pubkey];
+
+        let targets =
+            get_target_balance(Lamports(260), &validators, None, Some(&superminority)).unwrap();
+        assert_eq!(targets, [Lamports(40), Lamports(130), Lamports(130)]);
+
+        // A validator in the superminority with more than its share still
+        // targets its share, the superminority does not protect its stake.
+        validators.entries[0].entry.stake_accounts_balance = Lamports(250);
+        let targets =
+            get_target_balance(Lamports(50), &validators, None, Some(&superminority)).unwrap();
+        assert_eq!(targets, [Lamports(100), Lamports(100), Lamports(100)]);
+    }
+
+    #[test]
+    fn get_target_balance_ignores_superminority_if_it_contains_all_validators() {
+        let mut validators = Validators::new_fill_default(2);
+        for validator in validators.entries.iter_mut() {
+            validator.pubkey = Pubkey::new_unique();
+        }
+        let superminority = [validators.entries[0].pubkey, validators.entries[1].pubkey];
+        let targets =
+            get_target_balance(Lamports(200), &validators, None, Some(&superminority)).unwrap();
+        assert_eq!(targets, [Lamports(100), Lamports(100)]);
+    }
+
+    #[test]
+    fn get_validator_furthest_below_target_skips_validators_without_room() {
+        let sol = |amount: u64| Lamports(amount * 1_000_000_000);
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(9_500_000_000);
+        validators.entries[0].entry.weight = 10;
+        validators.entries[1].entry.stake_accounts_balance = sol(5);
+        validators.entries[1].entry.weight = 1;
+
+        let targets = get_target_balance(sol(0), &validators, Some(sol(10)), None).unwrap();
+        assert_eq!(targets, [sol(10), Lamports(4_500_000_000)]);
+
+        // The first validator has the least stake per unit of weight, but
+        // a stake account of the minimum size would put it above the maximum,
+        // so we can only stake with the second one.
+        assert_eq!(
+            get_validator_furthest_below_target(&validators, &targets[..], Some(sol(10))),
+            (1, Lamports(0))
+        );
+    }
+
+    mod proptests {
+        use proptest::prelude::*;
+
+        use crate::balance::get_target_balance;
+        use crate::state::Validators;
+        use crate::token::Lamports;
+
+        /// Build a validator set from `(stake_accounts_balance, active)` pairs.
+        fn make_validators(specs: &[(u64, bool)]) -> Validators {
+            let mut validators = Validators::new_fill_default(specs.len() as u32);
+            for (v, &(balance, active)) in validators.entries.iter_mut().zip(specs) {
+                v.entry.stake_accounts_balance = Lamports(balance);
+                v.entry.active = active;
+            }
+            validators
+        }
+
+        // Balances are bounded so that the sum over all validators plus the
+        // undelegated amount cannot overflow a u64.
+        fn arb_validators() -> impl Strategy<Value = Vec<(u64, bool)>> {
+            prop::collection::vec((0..u64::MAX / 256, any::<bool>()), 1..100)
+        }
+
+        proptest! {
+            #[test]
+            fn targets_sum_to_total(
+                specs in arb_validators(),
+                undelegated in 0..u64::MAX / 256,
+            ) {
+                prop_assume!(specs.iter().any(|&(_, active)| active));
+                let validators = make_validators(&specs);
+                let targets = get_target_balance(Lamports(undelegated), &validators, None, None).unwrap();
+
+                let total_delegated: u64 = specs.iter().map(|&(balance, _)| balance).sum();
+                let total_target: u64 = targets.iter().map(|t| t.0).sum();
+                prop_assert_eq!(total_target, total_delegated + undelegated);
+            }
+
+            #[test]
+            fn inactive_validators_get_zero_target(
+                specs in arb_validators(),
+                undelegated in 0..u64::MAX / 256,
+            ) {
+                prop_assume!(specs.iter().any(|&(_, active)| active));
+                let validators = make_validators(&specs);
+                let targets = get_target_balance(Lamports(undelegated), &validators, None, None).unwrap();
+
+                prop_assert_eq!(targets.len(), specs.len());
+                for (target, &(_, active)) in targets.iter().zip(&specs) {
+                    if !active {
+                        prop_assert_eq!(*target, Lamports(0));
+                    }
+                }
+            }
+
+            #[test]
+            fn active_targets_differ_by_at_most_one_lamport(
+                specs in arb_validators(),
+                undelegated in 0..u64::MAX / 256,
+            ) {
+                prop_assume!(specs.iter().any(|&(_, active)| active));
+                let validators = make_validators(&specs);
+                let targets = get_target_balance(Lamports(undelegated), &validators, None, None).unwrap();
+
+                let active_targets: Vec<u64> = targets
+                    .iter()
+                    .zip(&specs)
+                    .filter(|(_, &(_, active))| active)
+                    .map(|(target, _)| target.0)
+                    .collect();
+                let min = *active_targets.iter().min().unwrap();
+                let max = *active_targets.iter().max().unwrap();
+
+                // The remainder is handed out one Lamport at a time, so no
+                // validator gets more than one Lamport above the floor.
+                prop_assert!(max - min <= 1);
+            }
+
+            #[test]
+            fn weighted_targets_are_proportional(
+                weights in prop::collection::vec(1..1_000_u32, 1..100),
+                undelegated in 0..u64::MAX / 256,
+            ) {
+                let mut validators = Validators::new_fill_default(weights.len() as u32);
+                for (v, &weight) in validators.entries.iter_mut().zip(&weights) {
+                    v.entry.weight = weight;
+                }
+                let targets = get_target_balance(Lamports(undelegated), &validators, None, None).unwrap();
+
+                let total_target: u64 = targets.iter().map(|t| t.0).sum();
+                prop_assert_eq!(total_target, undelegated);
+
+                // Every target is within one Lamport of the exact share.
+                let total_weight: u64 = weights.iter().map(|&w| w as u64).sum();
+                for (target, &weight) in targets.iter().zip(&weights) {
+                    let floor = (undelegated as u128 * weight as u128 / total_weight as u128) as u64;
+                    prop_assert!(target.0 == floor || target.0 == floor + 1);
+                }
+            }
+
+            #[test]
+            fn targets_respect_max_stake(
+                specs in arb_validators(),
+                undelegated in 0..u64::MAX / 256,
+                max_stake in 1..u64::MAX / 256,
+            ) {
+                prop_assume!(specs.iter().any(|&(_, active)| active));
+                let validators = make_validators(&specs);
+                let max_stake = Lamports(max_stake);
+                let targets =
+                    get_target_balance(Lamports(undelegated), &validators, Some(max_stake), None).unwrap();
+
+                let total_delegated: u64 = specs.iter().map(|&(balance, _)| balance).sum();
+                let total_target: u64 = targets.iter().map(|t| t.0).sum();
+                prop_assert!(total_target <= total_delegated + undelegated);
+
+                let mut all_at_max = true;
+                for (target, &(balance, active)) in targets.iter().zip(&specs) {
+                    if !active {
+                        prop_assert_eq!(*target, Lamports(0));
+                    } else if balance >= max_stake.0 {
+                        // Validators at or above the maximum keep their stake.
+                        prop_assert_eq!(*target, Lamports(balance));
+                    } else {
+                        prop_assert!(*target <= max_stake);
+                        all_at_max = all_at_max && *target == max_stake;
+                    }
+                }
+
+                // We only keep lamports in the reserve when there is no room left.
+                if !all_at_max {
+                    prop_assert_eq!(total_target, total_delegated + undelegated);
+                }
+            }
+
+            #[test]
+            fn all_inactive_is_an_error(
+                balances in prop::collection::vec(0..u64::MAX / 256, 0..100),
+                undelegated in 0..u64::MAX / 256,
+            ) {
+                let specs: Vec<(u64, bool)> = balances.iter().map(|&b| (b, false)).collect();
+                let validators = make_validators(&specs);
+                prop_assert!(get_target_balance(Lamports(undelegated), &validators, None, None).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn get_rebalance_amount_requires_excess_above_threshold() {
+        let mut validators = Validators::new_fill_default(1);
+        let validator = &mut validators.entries[0].entry;
+        let target = Lamports(100_000_000_000);
+
+        // 10% above target is within the threshold.
+        validator.stake_accounts_balance = Lamports(110_000_000_000);
+        assert_eq!(get_rebalance_amount(validator, target), None);
+
+        // Above the threshold, we unstake everything above the target.
+        validator.stake_accounts_balance = Lamports(120_000_000_000);
+        assert_eq!(
+            get_rebalance_amount(validator, target),
+            Some(Lamports(20_000_000_000))
+        );
+
+        // Stake that is already unstaking does not count.
+        validator.unstake_accounts_balance = Lamports(15_000_000_000);
+        assert_eq!(get_rebalance_amount(validator, target), None);
+
+        // Inactive validators are not rebalanced.
+        validator.unstake_accounts_balance = Lamports(0);
+        validator.active = false;
+        assert_eq!(get_rebalance_amount(validator, target), None);
+    }
+
+    #[test]
+    fn get_rebalance_amount_skips_validators_with_zero_weight() {
+        let mut validators = Validators::new_fill_default(1);
+        let validator = &mut validators.entries[0].entry;
+        validator.stake_accounts_balance = Lamports(100_000_000_000);
+
+        // A validator with weight 0 has a target of 0, but it is not unstaked.
+        validator.weight = 0;
+        assert_eq!(get_rebalance_amount(validator, Lamports(0)), None);
+
+        validator.weight = 1;
+        assert_eq!(
+            get_rebalance_amount(validator, Lamports(0)),
+            Some(Lamports(100_000_000_000))
+        );
+    }
+
+    #[test]
+    fn get_rebalance_amount_requires_minimum_stake_account_balance() {
+        let mut validators = Validators::new_fill_default(1);
+        let validator = &mut validators.entries[0].entry;
+
+        // A small target has a small threshold, but we can't unstake less than
+        // the minimum stake account balance.
+        validator.stake_accounts_balance = MINIMUM_STAKE_ACCOUNT_BALANCE;
+        assert_eq!(get_rebalance_amount(validator, Lamports(0)), None);
+
+        validator.stake_accounts_balance = Lamports(MINIMUM_STAKE_ACCOUNT_BALANCE.0 + 1);
+        assert_eq!(
+            get_rebalance_amount(validator, Lamports(0)),
+            Some(Lamports(MINIMUM_STAKE_ACCOUNT_BALANCE.0 + 1))
+        );
+    }
+
+    #[test]
+    fn has_room_for_stake_does_not_overflow() {
+        let mut validators = Validators::new_fill_default(1);
+        let validator = &mut validators.entries[0].entry;
+        assert!(has_room_for_stake(validator, None));
+        assert!(has_room_for_stake(
+            validator,
+            Some(MINIMUM_STAKE_ACCOUNT_BALANCE)
+        ));
+
+        validator.stake_accounts_balance = Lamports(u64::MAX);
+        assert!(!has_room_for_stake(validator, Some(Lamports(u64::MAX))));
+    }
+
+    #[test]
+    fn get_withdraw_plan_uses_heaviest_validator_if_it_suffices() {
+        let mut validators = Validators::new_fill_default(2);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(10_000_000_000);
+        validators.entries[1].entry.stake_accounts_balance = Lamports(30_000_000_000);
+
+        assert_eq!(
+            get_withdraw_plan(&validators, Lamports(5_000_000_000)),
+            Ok(vec![(1, Lamports(5_000_000_000))])
+        );
+        assert_eq!(get_withdraw_plan(&validators, Lamports(0)), Ok(vec![]));
+    }
+
+    #[test]
+    fn get_withdraw_plan_splits_over_multiple_validators() {
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(10_000_000_000);
+        validators.entries[1].entry.stake_accounts_balance = Lamports(30_000_000_000);
+        validators.entries[2].entry.stake_accounts_balance = Lamports(20_000_000_000);
+
+        // Lowering the heaviest validator to the second covers 10 SOL, the
+        // remaining 5 SOL come from both, and both end up at 17.5 SOL.
+        assert_eq!(
+            get_withdraw_plan(&validators, Lamports(15_000_000_000)),
+            Ok(vec![
+                (1, Lamports(12_500_000_000)),
+                (2, Lamports(2_500_000_000)),
+            ])
+        );
+
+        // An odd remainder goes to the heaviest validator first.
+        assert_eq!(
+            get_withdraw_plan(&validators, Lamports(10_000_000_003)),
+            Ok(vec![(1, Lamports(10_000_000_002)), (2, Lamports(1))])
+        );
+
+        // Withdrawing everything but the minimum of every validator.
+        assert_eq!(
+            get_withdraw_plan(&validators, Lamports(57_000_000_000)),
+            Ok(vec![
+                (1, Lamports(29_000_000_000)),
+                (2, Lamports(19_000_000_000)),
+                (0, Lamports(9_000_000_000)),
+            ])
+        );
+        assert_eq!(
+            get_withdraw_plan(&validators, Lamports(57_000_000_001)),
+            Err(LidoError::InvalidAmount)
+        );
+    }
+
+    #[test]
+    fn get_withdraw_plan_withdraws_from_the_heaviest_validator_at_every_step() {
+        let mut validators = Validators::new_fill_default(4);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(7_000_000_001);
+        validators.entries[1].entry.stake_accounts_balance = Lamports(7_000_000_000);
+        validators.entries[2].entry.stake_accounts_balance = Lamports(12_000_000_000);
+        validators.entries[3].entry.stake_accounts_balance = Lamports(2_000_000_000);
+        validators.entries[3].entry.unstake_accounts_balance = Lamports(1_000_000_000);
+
+        for &amount in &[
+            1,
+            4_999_999_999,
+            5_000_000_000,
+            6_000_000_002,
+            12_000_000_001,
+        ] {
+            let plan = get_withdraw_plan(&validators, Lamports(amount)).unwrap();
+            let mut validators = validators.clone();
+            let mut total = 0;
+            for (i, withdraw_amount) in plan {
+                let heaviest = validators
+                    .iter_entries()
+                    .map(|v| v.effective_stake_balance())
+                    .max()
+                    .unwrap();
+                let validator = &mut validators.entries[i].entry;
+                assert_eq!(validator.effective_stake_balance(), heaviest);
+                validator.stake_accounts_balance =
+                    (validator.stake_accounts_balance - withdraw_amount).unwrap();
+                assert!(validator.effective_stake_balance() >= MINIMUM_STAKE_ACCOUNT_BALANCE);
+                total += withdraw_amount.0;
+            }
+            assert_eq!(total, amount);
+        }
+    }
+
+    #[test]
+    fn get_withdraw_steps_breaks_up_large_withdrawals() {
+        let mut validators = Validators::new_fill_default(1);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(100_000_000_000);
+        let source_balances = [Lamports(100_000_000_000)];
+
+        // The first withdrawal can take 10% + 10 SOL, after that the stake
+        // account holds less, so the limit is lower.
+        assert_eq!(
+            get_withdraw_steps(
+                &validators,
+                &source_balances,
+                &[(0, Lamports(30_000_000_000))]
+            ),
+            Ok(vec![
+                WithdrawStep {
+                    validator_index: 0,
+                    amount: Lamports(20_000_000_000),
+                    max_amount: Lamports(20_000_000_000),
+                },
+                WithdrawStep {
+                    validator_index: 0,
+                    amount: Lamports(10_000_000_000),
+                    max_amount: Lamports(18_000_000_000),
+                },
+            ])
+        );
+        assert_eq!(
+            get_withdraw_steps(&validators, &source_balances, &[]),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn get_withdraw_steps_fails_when_the_stake_account_cannot_cover_it() {
+        // The validator has more stake accounts, but `Withdraw` only splits
+        // the first one, which has to keep the minimum balance.
+        let mut validators = Validators::new_fill_default(1);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(100_000_000_000);
+        let source_balances = [Lamports(5_000_000_000)];
+
+        assert_eq!(
+            get_withdraw_steps(
+                &validators,
+                &source_balances,
+                &[(0, Lamports(5_000_000_000))]
+            ),
+            Err(LidoError::InvalidAmount)
+        );
+        assert_eq!(
+            get_withdraw_steps(
+                &validators,
+                &source_balances,
+                &[(
+                    0,
+                    (Lamports(5_000_000_000) - MINIMUM_STAKE_ACCOUNT_BALANCE).unwrap()
+                )]
+            )
+            .map(|steps| steps.len()),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn get_withdraw_steps_withdraws_from_the_heaviest_validator_at_every_step() {
+        let mut validators = Validators::new_fill_default(3);
+        validators.entries[0].entry.stake_accounts_balance = Lamports(100_000_000_000);
+        validators.entries[1].entry.stake_accounts_balance = Lamports(99_000_000_000);
+        validators.entries[2].entry.stake_accounts_balance = Lamports(20_000_000_000);
+        let mut source_balances: Vec<Lamports> = validators
+            .iter_entries()
+            .map(|v| v.stake_accounts_balance)
+            .collect();
+
+        let amount = Lamports(61_000_000_000);
+        let plan = get_withdraw_plan(&validators, amount).unwrap();
+        let steps = get_withdraw_steps(&validators, &source_balances, &plan).unwrap();
+        assert!(steps.len() > plan.len());
+
+        let mut validators = validators.clone();
+        let mut total = Lamports(0);
+        for step in steps {
+            let heaviest = validators
+                .iter_entries()
+                .map(|v| v.effective_stake_balance())
+                .max()
+                .unwrap();
+            let validator = &mut validators.entries[step.validator_index].entry;
+            assert_eq!(validator.effective_stake_balance(), heaviest);
+            assert!(step.amount <= step.max_amount);
+            assert!(
+                step.amount
+                    <= get_max_withdraw_amount(source_balances[step.validator_index]).unwrap()
+            );
+            validator.stake_accounts_balance =
+                (validator.stake_accounts_balance - step.amount).unwrap();
+            let source_balance = &mut source_balances[step.validator_index];
+            *source_balance = (*source_balance - step.amount).unwrap();
+            assert!(*source_balance >= MINIMUM_STAKE_ACCOUNT_BALANCE);
+            total = (total + step.amount).unwrap();
+        }
+        assert_eq!(total, amount);
+    }
 }
@@ -165,6 +165,63 @@ pub enum LidoError {
 
     /// We expected the StSol account to be owned by the SPL token program.
     InvalidStSolAccountOwner = 46,
+
+    /// The exchange rate oracle account is not the one derived from the Solido address.
+    InvalidExchangeRateOracle = 47,
+
+    /// Staking the amount would bring the validator's stake above the maximum
+    /// stake per validator.
+    MaxStakePerValidatorExceeded = 48,
+
+    /// The maintainer lease account is not the one derived from the Solido address.
+    InvalidMaintainerLease = 49,
+
+    /// A different maintainer holds the maintainer lease, and it has not expired yet.
+    MaintainerLeaseHeldByOther = 50,
+
+    /// The requested lease duration is zero, or longer than `MAXIMUM_MAINTAINER_LEASE_SLOTS`.
+    InvalidMaintainerLeaseDuration = 51,
+
+    /// The validator's stake does not exceed its target by more than the rebalance threshold.
+    ValidatorNotOverweight = 52,
+
+    /// The withdraw ticket account is not the one derived from the Solido
+    /// address and ticket id, or it does not belong to this Solido instance.
+    InvalidWithdrawTicket = 53,
+
+    /// The withdraw ticket has not been funded yet, try again later.
+    WithdrawTicketNotFunded = 54,
+
+    /// The signer is not the owner of the withdraw ticket.
+    InvalidWithdrawTicketOwner = 55,
+
+    /// Deposits and withdrawals are paused by the manager, see `SetPaused`.
+    Paused = 56,
+
+    /// The deposit would bring the SOL balance above `max_total_lamports`.
+    DepositCapExceeded = 57,
+
+    /// The account is not an auxiliary account of the Solido instance that
+    /// `SweepToReserve` can sweep.
+    InvalidSweepAccount = 58,
+
+    /// The account holds no SOL above its rent-exempt balance.
+    NothingToSweep = 59,
+
+    /// The reward distribution would change sooner than
+    /// `MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS` from now.
+    RewardDistributionDelayTooShort = 60,
+
+    /// The depositor allowlist is enabled, and the depositor is not on it.
+    DepositorNotAllowed = 61,
+
+    /// The depositor allowlist account is not the one derived from the Solido
+    /// address, or it was not created yet.
+    InvalidDepositorAllowlist = 62,
+
+    /// The Solido account is stored in a different version than the program
+    /// expects, it needs to be migrated first.
+    InvalidLidoVersion = 63,
 }
 
 // Just reuse the generated Debug impl for Display. It shows the variant names.
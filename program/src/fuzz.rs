@@ -0,0 +1,105 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Entry points for the fuzz targets in `program/fuzz`.
+//!
+//! The targets themselves only forward the input to the functions here, so
+//! the properties can also run as ordinary tests, without a fuzzing toolchain.
+//! A function panics when it finds a violation; that is what the fuzzer looks
+//! for.
+
+use borsh::BorshSerialize;
+use solana_program::borsh::try_from_slice_unchecked;
+
+use crate::balance::get_target_balance;
+use crate::state::Lido;
+use crate::token::Lamports;
+
+/// Round-trip arbitrary bytes through the `Lido` deserializer and serializer.
+///
+/// Deserializing must either fail or produce a `Lido` whose serialization is
+/// stable: deserializing it again yields the same value, and serializing that
+/// yields the same bytes. Checking whether the state is well-formed must not
+/// panic, and when it is, neither must the accessors that rely on it.
+pub fn lido_roundtrip(data: &[u8]) {
+    let lido = match try_from_slice_unchecked::<Lido>(data) {
+        Ok(lido) => lido,
+        Err(_) => return,
+    };
+    let serialized = lido
+        .try_to_vec()
+        .expect("Serializing into a Vec does not fail.");
+    let restored = try_from_slice_unchecked::<Lido>(&serialized)
+        .expect("A serialized Lido should deserialize again.");
+    assert_eq!(restored, lido);
+    assert_eq!(
+        restored
+            .try_to_vec()
+            .expect("Serializing into a Vec does not fail."),
+        serialized,
+    );
+
+    if lido.check_well_formed().is_ok() {
+        for validator in lido.validators.iter_entries() {
+            validator.effective_stake_balance();
+        }
+        // These may fail, but they must not panic.
+        let _ = get_target_balance(
+            Lamports(0),
+            &lido.validators,
+            lido.get_max_stake_per_validator(),
+            None,
+        );
+        let _ = lido.exchange_rate.exchange_sol(Lamports(1_000_000_000));
+        let _ = lido
+            .reward_distribution
+            .split_reward(Lamports(1_000_000_000), lido.validators.len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::state::{Maintainers, Validator, Validators};
+    use solana_program::pubkey::Pubkey;
+
+    fn serialized_lido() -> Vec<u8> {
+        let mut validators = Validators::new(3);
+        validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        let mut maintainers = Maintainers::new(2);
+        maintainers.add(Pubkey::new_unique(), ()).unwrap();
+        let lido = Lido {
+            validators,
+            maintainers,
+            ..Lido::default()
+        };
+        lido.try_to_vec().unwrap()
+    }
+
+    #[test]
+    fn test_lido_roundtrip_truncated_data() {
+        let data = serialized_lido();
+        for len in 0..=data.len() {
+            lido_roundtrip(&data[..len]);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn lido_roundtrip_arbitrary_bytes(data in prop::collection::vec(any::<u8>(), 0..1_000)) {
+            lido_roundtrip(&data);
+        }
+
+        #[test]
+        fn lido_roundtrip_corrupted_state(index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut data = serialized_lido();
+            let i = index.index(data.len());
+            data[i] = byte;
+            lido_roundtrip(&data);
+        }
+    }
+}
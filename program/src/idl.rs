@@ -0,0 +1,489 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Interface description (IDL) of the Solido program.
+//!
+//! The IDL has the shape of the IDL that Anchor generates, so tools that read
+//! Anchor IDLs can build instructions and decode accounts. We generate it from
+//! the `BorshSchema` of the instruction and state types, and from the
+//! `accounts_struct!` definitions, so it cannot drift from the program.
+//!
+//! Solido is not an Anchor program, so there are two differences with IDLs of
+//! Anchor programs:
+//!
+//!  * The discriminator of an instruction is the one-byte Borsh enum tag of
+//!    the `LidoInstruction` variant.
+//!  * Accounts have no discriminator, the account types are identified by
+//!    their owner and size.
+
+use std::collections::{BTreeMap, HashMap};
+
+use borsh::schema::{BorshSchema, Declaration, Definition, Fields};
+use num_traits::FromPrimitive;
+use serde::Serialize;
+
+use crate::error::LidoError;
+use crate::instruction::{
+    AcquireMaintainerLeaseMeta, AddAllowedDepositorMeta, AddMaintainerMeta, AddValidatorMeta,
+    ChangeFeeRecipientsMeta, ClaimValidatorFeeMeta, ClaimWithdrawTicketAccountsMeta,
+    CollectValidatorFeeMeta, CreateDepositorAllowlistMeta, CreateWithdrawTicketAccountsMeta,
+    DeactivateValidatorMeta, DepositAccountsMeta, DepositStakeAccountsMeta,
+    FundWithdrawTicketAccountsMeta, InitializeAccountsMeta, LidoInstruction, MergeStakeMeta,
+    MigrateStateToV1Meta, PublishExchangeRateMeta, RebalanceAccountsMeta,
+    RemoveAllowedDepositorMeta, RemoveMaintainerMeta, RemoveValidatorMeta,
+    ReplaceValidatorVoteAccountMeta, ScheduleRewardDistributionMeta,
+    SetDepositorAllowlistEnabledMeta, SetMaxStakePerValidatorMeta, SetMaxTotalLamportsMeta,
+    SetPausedMeta, SetValidatorWeightMeta, StakeDepositAccountsMeta, SweepToReserveAccountsMeta,
+    UnstakeAccountsMeta, UpdateExchangeRateAccountsMeta, WithdrawAccountsMeta,
+    WithdrawInactiveStakeMeta,
+};
+use crate::state::{DepositorAllowlist, ExchangeRateOracle, Lido, MaintainerLease, WithdrawTicket};
+
+/// Version of the Anchor IDL format that we follow.
+const IDL_SPEC_VERSION: &str = "0.1.0";
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Serialize)]
+pub struct Idl {
+    /// Address of the program, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub metadata: IdlMetadata,
+    pub instructions: Vec<IdlInstruction>,
+    pub accounts: Vec<IdlAccountType>,
+    pub errors: Vec<IdlError>,
+    pub types: Vec<IdlTypeDefinition>,
+}
+
+#[derive(Serialize)]
+pub struct IdlMetadata {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub spec: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct IdlInstruction {
+    pub name: String,
+    pub discriminator: Vec<u8>,
+    pub accounts: Vec<IdlAccount>,
+    pub args: Vec<IdlField>,
+}
+
+/// An account that an instruction expects, generated by `accounts_struct!`.
+#[derive(Debug, Serialize)]
+pub struct IdlAccount {
+    pub name: &'static str,
+    #[serde(skip_serializing_if = "is_false")]
+    pub writable: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub signer: bool,
+    /// The fixed address of the account, for sysvars and programs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Whether this stands for any number of accounts, at the end of the list.
+    #[serde(skip_serializing_if = "is_false")]
+    pub variadic: bool,
+}
+
+#[derive(Serialize)]
+pub struct IdlAccountType {
+    pub name: String,
+    pub discriminator: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct IdlError {
+    pub code: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: IdlType,
+}
+
+/// A type, serialized like `"u64"`, `{"vec": "u64"}`, or `{"defined": {"name": "Lido"}}`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum IdlType {
+    Primitive(&'static str),
+    Vec { vec: Box<IdlType> },
+    Option { option: Box<IdlType> },
+    Array { array: (Box<IdlType>, u32) },
+    Defined { defined: IdlDefinedName },
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct IdlDefinedName {
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct IdlTypeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: IdlTypeDefinitionKind,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum IdlTypeDefinitionKind {
+    Struct {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fields: Option<IdlDefinedFields>,
+    },
+    Enum {
+        variants: Vec<IdlEnumVariant>,
+    },
+}
+
+#[derive(Serialize)]
+pub struct IdlEnumVariant {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<IdlDefinedFields>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum IdlDefinedFields {
+    Named(Vec<IdlField>),
+    Tuple(Vec<IdlType>),
+}
+
+/// The accounts of the instruction with the given `LidoInstruction` variant name.
+fn instruction_accounts(variant: &str) -> Vec<IdlAccount> {
+    match variant {
+        "Initialize" => InitializeAccountsMeta::idl_accounts(),
+        "Deposit" => DepositAccountsMeta::idl_accounts(),
+        "Withdraw" => WithdrawAccountsMeta::idl_accounts(),
+        "StakeDeposit" => StakeDepositAccountsMeta::idl_accounts(),
+        "Unstake" => UnstakeAccountsMeta::idl_accounts(),
+        "UpdateExchangeRate" => UpdateExchangeRateAccountsMeta::idl_accounts(),
+        "WithdrawInactiveStake" => WithdrawInactiveStakeMeta::idl_accounts(),
+        "CollectValidatorFee" => CollectValidatorFeeMeta::idl_accounts(),
+        "ClaimValidatorFee" => ClaimValidatorFeeMeta::idl_accounts(),
+        "ChangeFeeRecipients" => ChangeFeeRecipientsMeta::idl_accounts(),
+        "AddValidator" => AddValidatorMeta::idl_accounts(),
+        "DeactivateValidator" => DeactivateValidatorMeta::idl_accounts(),
+        "RemoveValidator" => RemoveValidatorMeta::idl_accounts(),
+        "AddMaintainer" => AddMaintainerMeta::idl_accounts(),
+        "RemoveMaintainer" => RemoveMaintainerMeta::idl_accounts(),
+        "MergeStake" => MergeStakeMeta::idl_accounts(),
+        "PublishExchangeRate" => PublishExchangeRateMeta::idl_accounts(),
+        "SetValidatorWeight" => SetValidatorWeightMeta::idl_accounts(),
+        "SetMaxStakePerValidator" => SetMaxStakePerValidatorMeta::idl_accounts(),
+        "AcquireMaintainerLease" => AcquireMaintainerLeaseMeta::idl_accounts(),
+        "DepositStake" => DepositStakeAccountsMeta::idl_accounts(),
+        "Rebalance" => RebalanceAccountsMeta::idl_accounts(),
+        "CreateWithdrawTicket" => CreateWithdrawTicketAccountsMeta::idl_accounts(),
+        "FundWithdrawTicket" => FundWithdrawTicketAccountsMeta::idl_accounts(),
+        "ClaimWithdrawTicket" => ClaimWithdrawTicketAccountsMeta::idl_accounts(),
+        "SetPaused" => SetPausedMeta::idl_accounts(),
+        "SetMaxTotalLamports" => SetMaxTotalLamportsMeta::idl_accounts(),
+        "SweepToReserve" => SweepToReserveAccountsMeta::idl_accounts(),
+        "ScheduleRewardDistribution" => ScheduleRewardDistributionMeta::idl_accounts(),
+        "CreateDepositorAllowlist" => CreateDepositorAllowlistMeta::idl_accounts(),
+        "AddAllowedDepositor" => AddAllowedDepositorMeta::idl_accounts(),
+        "RemoveAllowedDepositor" => RemoveAllowedDepositorMeta::idl_accounts(),
+        "SetDepositorAllowlistEnabled" => SetDepositorAllowlistEnabledMeta::idl_accounts(),
+        "ReplaceValidatorVoteAccount" => ReplaceValidatorVoteAccountMeta::idl_accounts(),
+        "MigrateStateToV1" => MigrateStateToV1Meta::idl_accounts(),
+        _ => panic!("Instruction {} has no accounts in the IDL.", variant),
+    }
+}
+
+/// Convert a `PascalCase` name into `snake_case`.
+fn to_snake_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 4);
+    for (i, ch) in name.chars().enumerate() {
+        if ch.is_ascii_uppercase() && i > 0 {
+            result.push('_');
+        }
+        result.push(ch.to_ascii_lowercase());
+    }
+    result
+}
+
+/// Name of a defined type, without the punctuation of generic arguments.
+///
+/// For example, `AccountMap<Validator>` becomes `AccountMapValidator`.
+fn type_name(declaration: &str) -> String {
+    declaration
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric())
+        .collect()
+}
+
+struct TypeConverter {
+    definitions: HashMap<Declaration, Definition>,
+}
+
+impl TypeConverter {
+    fn convert_type(&self, declaration: &str) -> IdlType {
+        match declaration {
+            "u8" => return IdlType::Primitive("u8"),
+            "u16" => return IdlType::Primitive("u16"),
+            "u32" => return IdlType::Primitive("u32"),
+            "u64" => return IdlType::Primitive("u64"),
+            "u128" => return IdlType::Primitive("u128"),
+            "i8" => return IdlType::Primitive("i8"),
+            "i16" => return IdlType::Primitive("i16"),
+            "i32" => return IdlType::Primitive("i32"),
+            "i64" => return IdlType::Primitive("i64"),
+            "i128" => return IdlType::Primitive("i128"),
+            "f32" => return IdlType::Primitive("f32"),
+            "f64" => return IdlType::Primitive("f64"),
+            "bool" => return IdlType::Primitive("bool"),
+            "string" => return IdlType::Primitive("string"),
+            "Pubkey" => return IdlType::Primitive("pubkey"),
+            _ => {}
+        }
+
+        match self.definitions.get(declaration) {
+            Some(Definition::Array { length, elements }) => IdlType::Array {
+                array: (Box::new(self.convert_type(elements)), *length),
+            },
+            Some(Definition::Sequence { elements }) if elements == "u8" => {
+                IdlType::Primitive("bytes")
+            }
+            Some(Definition::Sequence { elements }) => IdlType::Vec {
+                vec: Box::new(self.convert_type(elements)),
+            },
+            Some(Definition::Enum { variants }) if declaration.starts_with("Option<") => {
+                // The variants of an `Option<T>` are `None` and `Some(T)`.
+                IdlType::Option {
+                    option: Box::new(self.convert_type(&variants[1].1)),
+                }
+            }
+            // Newtypes such as `Lamports` have the layout of their only field,
+            // and that is easier to consume than a wrapper type.
+            Some(Definition::Struct {
+                fields: Fields::UnnamedFields(fields),
+            }) if fields.len() == 1 => self.convert_type(&fields[0]),
+            Some(_) => IdlType::Defined {
+                defined: IdlDefinedName {
+                    name: type_name(declaration),
+                },
+            },
+            None => panic!("BorshSchema has no definition for {}.", declaration),
+        }
+    }
+
+    fn convert_fields(&self, fields: &Fields) -> Option<IdlDefinedFields> {
+        match fields {
+            Fields::NamedFields(fields) => Some(IdlDefinedFields::Named(
+                fields
+                    .iter()
+                    .map(|(name, declaration)| IdlField {
+                        name: name.clone(),
+                        type_: self.convert_type(declaration),
+                    })
+                    .collect(),
+            )),
+            Fields::UnnamedFields(fields) => Some(IdlDefinedFields::Tuple(
+                fields.iter().map(|d| self.convert_type(d)).collect(),
+            )),
+            Fields::Empty => None,
+        }
+    }
+
+    /// Return the definition of `declaration` if it is a defined type in the IDL.
+    fn convert_definition(&self, declaration: &str) -> Option<IdlTypeDefinition> {
+        let name = match self.convert_type(declaration) {
+            IdlType::Defined { defined } => defined.name,
+            _ => return None,
+        };
+        let type_ = match &self.definitions[declaration] {
+            Definition::Struct { fields } => IdlTypeDefinitionKind::Struct {
+                fields: self.convert_fields(fields),
+            },
+            Definition::Tuple { elements } => IdlTypeDefinitionKind::Struct {
+                fields: Some(IdlDefinedFields::Tuple(
+                    elements.iter().map(|d| self.convert_type(d)).collect(),
+                )),
+            },
+            Definition::Enum { variants } => IdlTypeDefinitionKind::Enum {
+                variants: variants
+                    .iter()
+                    .map(|(variant_name, variant_declaration)| IdlEnumVariant {
+                        name: variant_name.clone(),
+                        fields: match &self.definitions[variant_declaration] {
+                            Definition::Struct { fields } => self.convert_fields(fields),
+                            _ => Some(IdlDefinedFields::Tuple(vec![
+                                self.convert_type(variant_declaration)
+                            ])),
+                        },
+                    })
+                    .collect(),
+            },
+            Definition::Array { .. } | Definition::Sequence { .. } => {
+                unreachable!("Arrays and sequences are not defined types.")
+            }
+        };
+        Some(IdlTypeDefinition { name, type_ })
+    }
+}
+
+/// Generate the IDL of the Solido program, without the program address.
+pub fn generate_idl() -> Idl {
+    let mut definitions = HashMap::new();
+    LidoInstruction::add_definitions_recursively(&mut definitions);
+    Lido::add_definitions_recursively(&mut definitions);
+    ExchangeRateOracle::add_definitions_recursively(&mut definitions);
+    MaintainerLease::add_definitions_recursively(&mut definitions);
+    WithdrawTicket::add_definitions_recursively(&mut definitions);
+    DepositorAllowlist::add_definitions_recursively(&mut definitions);
+    let converter = TypeConverter { definitions };
+
+    let instruction_declaration = LidoInstruction::declaration();
+    let instruction_variants = match &converter.definitions[&instruction_declaration] {
+        Definition::Enum { variants } => variants,
+        _ => unreachable!("LidoInstruction is an enum."),
+    };
+
+    let mut instructions = Vec::with_capacity(instruction_variants.len());
+    for (i, (variant_name, variant_declaration)) in instruction_variants.iter().enumerate() {
+        let args = match &converter.definitions[variant_declaration] {
+            Definition::Struct {
+                fields: Fields::NamedFields(fields),
+            } => fields
+                .iter()
+                .map(|(name, declaration)| IdlField {
+                    name: name.clone(),
+                    type_: converter.convert_type(declaration),
+                })
+                .collect(),
+            Definition::Struct {
+                fields: Fields::Empty,
+            } => Vec::new(),
+            _ => panic!("Instruction {} should have named arguments.", variant_name),
+        };
+        instructions.push(IdlInstruction {
+            name: to_snake_case(variant_name),
+            discriminator: vec![i as u8],
+            accounts: instruction_accounts(variant_name),
+            args,
+        });
+    }
+
+    let accounts = [
+        Lido::declaration(),
+        ExchangeRateOracle::declaration(),
+        MaintainerLease::declaration(),
+        WithdrawTicket::declaration(),
+        DepositorAllowlist::declaration(),
+    ]
+    .iter()
+    .map(|declaration| IdlAccountType {
+        name: type_name(declaration),
+        discriminator: Vec::new(),
+    })
+    .collect();
+
+    // The instruction arguments are part of the instructions, not types. Sort
+    // the types by name, so the output does not depend on the hash map order.
+    let types: BTreeMap<String, IdlTypeDefinition> = converter
+        .definitions
+        .keys()
+        .filter(|declaration| {
+            **declaration != instruction_declaration
+                && !instruction_variants.iter().any(|(_, d)| d == *declaration)
+        })
+        .filter_map(|declaration| converter.convert_definition(declaration))
+        .map(|definition| (definition.name.clone(), definition))
+        .collect();
+
+    let errors = (0..=u8::MAX as u32)
+        .filter_map(|code| {
+            LidoError::from_u32(code).map(|error| IdlError {
+                code,
+                name: error.to_string(),
+            })
+        })
+        .collect();
+
+    Idl {
+        address: None,
+        metadata: IdlMetadata {
+            name: "lido",
+            version: env!("CARGO_PKG_VERSION"),
+            spec: IDL_SPEC_VERSION,
+        },
+        instructions,
+        accounts,
+        errors,
+        types: types
+            .into_iter()
+            .map(|(_, definition)| definition)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_snake_case() {
+        assert_eq!(to_snake_case("Deposit"), "deposit");
+        assert_eq!(to_snake_case("DepositStake"), "deposit_stake");
+        assert_eq!(
+            to_snake_case("SetMaxStakePerValidator"),
+            "set_max_stake_per_validator"
+        );
+    }
+
+    #[test]
+    fn test_idl_instructions_match_borsh_encoding() {
+        let idl = generate_idl();
+        let deposit = idl
+            .instructions
+            .iter()
+            .find(|instruction| instruction.name == "deposit")
+            .unwrap();
+
+        let data = LidoInstruction::Deposit {
+            amount: crate::token::Lamports(1),
+        }
+        .to_vec();
+        assert_eq!(deposit.discriminator[..], data[..1]);
+        assert_eq!(
+            deposit.args,
+            vec![IdlField {
+                name: "amount".to_string(),
+                type_: IdlType::Primitive("u64"),
+            }]
+        );
+        assert_eq!(deposit.accounts[0].name, "lido");
+        assert!(deposit.accounts[1].signer);
+        assert_eq!(
+            deposit.accounts.last().unwrap().address,
+            Some(solana_program::system_program::id().to_string()),
+        );
+    }
+
+    #[test]
+    fn test_idl_contains_account_types() {
+        let idl = generate_idl();
+        for account in &idl.accounts {
+            assert!(
+                idl.types.iter().any(|t| t.name == account.name),
+                "Account type {} should be defined.",
+                account.name,
+            );
+        }
+        // Instruction arguments are not types of their own.
+        assert!(!idl
+            .types
+            .iter()
+            .any(|t| t.name.starts_with("LidoInstruction")));
+        assert_eq!(idl.errors[0].name, "AlreadyInUse");
+    }
+}
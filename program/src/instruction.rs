@@ -74,10 +74,12 @@ pub enum LidoInstruction {
     /// Claim rewards from the validator account and distribute rewards.
     CollectValidatorFee,
     ClaimValidatorFee,
-    ChangeRewardDistribution {
-        #[allow(dead_code)] // but it's not
-        new_reward_distribution: RewardDistribution,
-    },
+
+    /// Replace the accounts that receive the treasury and developer fees.
+    ///
+    /// Requires the manager to sign. The fee split itself only changes with
+    /// `ScheduleRewardDistribution`, so stSOL holders get notice of it.
+    ChangeFeeRecipients,
 
     /// Add a new validator to the validator set.
     ///
@@ -101,6 +103,180 @@ pub enum LidoInstruction {
     AddMaintainer,
     RemoveMaintainer,
     MergeStake,
+
+    /// Write the current exchange rate to the exchange rate oracle account.
+    ///
+    /// Requires a maintainer to sign. The maintainer pays for the oracle
+    /// account when it does not exist yet. The exchange rate must have been
+    /// updated in the current epoch, so the oracle never publishes a stale rate.
+    PublishExchangeRate,
+
+    /// Set the weight of a validator, its share of the stake relative to the
+    /// other active validators.
+    ///
+    /// Requires the manager to sign.
+    SetValidatorWeight {
+        #[allow(dead_code)] // but it's not
+        weight: u32,
+    },
+
+    /// Set the maximum stake per validator, or zero to remove the maximum.
+    ///
+    /// Requires the manager to sign.
+    SetMaxStakePerValidator {
+        #[allow(dead_code)] // but it's not
+        max_stake_per_validator: Lamports,
+    },
+
+    /// Acquire or renew the maintainer lease for `lease_duration_slots` slots.
+    ///
+    /// Requires a maintainer to sign. Succeeds when the lease is not held, when
+    /// it expired, or when the signer already holds it. The maintainer pays for
+    /// the lease account when it does not exist yet.
+    AcquireMaintainerLease {
+        #[allow(dead_code)] // but it's not
+        lease_duration_slots: u64,
+    },
+
+    /// Deposit an active stake account that is delegated to one of our validators.
+    ///
+    /// This can be called by anybody. The user must be both the staker and the
+    /// withdrawer of the stake account. Solido takes over the stake account,
+    /// merges it into the validator's oldest stake account, and mints stSOL
+    /// for its full balance at the current exchange rate.
+    DepositStake,
+
+    /// Unstake from an active validator whose stake exceeds its target.
+    ///
+    /// Like `Unstake`, this splits `amount` off the validator's oldest stake
+    /// account, and deactivates it. The program only accepts this when the
+    /// validator is above its target by more than the rebalance threshold, and
+    /// `amount` must not bring it below the target. After the stake is inactive,
+    /// `WithdrawInactiveStake` returns it to the reserve, and `StakeDeposit`
+    /// stakes it with the validators that are below their target.
+    ///
+    /// `superminority` holds the vote accounts of our validators that are in
+    /// the cluster's superminority, which the program cannot observe itself.
+    /// The targets are computed with it like the maintainer computes them when
+    /// staking, see [`crate::balance::get_target_balance`], so the maintainer
+    /// does not unstake what it just staked. It can only lower the excess that
+    /// the program accepts, so the program does not need to verify it.
+    Rebalance {
+        #[allow(dead_code)] // but it's not
+        amount: Lamports,
+        #[allow(dead_code)] // but it's not
+        superminority: Vec<Pubkey>,
+    },
+
+    /// Burn `amount` stSOL in exchange for a withdraw ticket.
+    ///
+    /// This can be called by anybody, for withdrawals that are too large for
+    /// `Withdraw`. The ticket is owed the SOL value of the stSOL at the current
+    /// exchange rate, and it is appended to the withdraw queue. The owner pays
+    /// for the ticket account. The ticket must be owed at least
+    /// `MINIMUM_WITHDRAW_TICKET_AMOUNT`.
+    CreateWithdrawTicket {
+        #[allow(dead_code)] // but it's not
+        amount: StLamports,
+    },
+
+    /// Move the SOL for the next ticket in the withdraw queue from the reserve into the ticket.
+    ///
+    /// Requires a maintainer to sign. When the reserve does not hold enough,
+    /// the maintainer first unstakes, and funds the ticket once the unstaked
+    /// SOL is back in the reserve.
+    FundWithdrawTicket,
+
+    /// Close a funded withdraw ticket, and send its SOL to the recipient.
+    ///
+    /// Requires the ticket owner to sign.
+    ClaimWithdrawTicket,
+
+    /// Pause or resume deposits and withdrawals.
+    ///
+    /// Requires the manager to sign. While paused, maintenance continues.
+    SetPaused {
+        #[allow(dead_code)] // but it's not
+        paused: bool,
+    },
+
+    /// Set the maximum amount of SOL managed by Solido, or zero to remove the
+    /// maximum.
+    ///
+    /// Requires the manager to sign.
+    SetMaxTotalLamports {
+        #[allow(dead_code)] // but it's not
+        max_total_lamports: Lamports,
+    },
+
+    /// Move SOL above the rent-exempt balance from an auxiliary account into the reserve.
+    ///
+    /// Requires a maintainer to sign. SOL that was sent to the stake authority,
+    /// the mint authority, the rewards withdraw authority, the exchange rate
+    /// oracle, or the maintainer lease by mistake is stranded there, this
+    /// returns it to the reserve, where it benefits all stSOL holders.
+    SweepToReserve,
+
+    /// Replace the reward distribution with a new one, `delay_epochs` from now.
+    ///
+    /// Requires the manager to sign. The delay must be at least
+    /// `MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS`. This replaces any change
+    /// that was scheduled before. This is the only way to change the fee split,
+    /// the fee recipients change with `ChangeFeeRecipients`.
+    ScheduleRewardDistribution {
+        #[allow(dead_code)] // but it's not
+        new_reward_distribution: RewardDistribution,
+        #[allow(dead_code)] // but it's not
+        delay_epochs: u64,
+    },
+
+    /// Create the empty depositor allowlist account.
+    ///
+    /// This can be called by anybody, the payer funds the account. The list
+    /// has room for `MAXIMUM_ALLOWED_DEPOSITORS`, and it has no effect until
+    /// the manager enables it with `SetDepositorAllowlistEnabled`.
+    CreateDepositorAllowlist,
+
+    /// Add a depositor to the depositor allowlist.
+    ///
+    /// Requires the manager to sign.
+    AddAllowedDepositor,
+
+    /// Remove a depositor from the depositor allowlist.
+    ///
+    /// Requires the manager to sign.
+    RemoveAllowedDepositor,
+
+    /// Enable or disable the depositor allowlist.
+    ///
+    /// Requires the manager to sign. While enabled, `Deposit` and
+    /// `DepositStake` only accept depositors on the allowlist.
+    SetDepositorAllowlistEnabled {
+        #[allow(dead_code)] // but it's not
+        enabled: bool,
+    },
+
+    /// Move a validator to a new vote account, keeping its entry and its stake.
+    ///
+    /// Requires the manager to sign. The new vote account must satisfy the same
+    /// conditions as for `AddValidator`. Stake accounts are derived from the
+    /// vote account, so every stake and unstake account of the validator is
+    /// split in full into the account at the new address. Stake that is not
+    /// yet effective is delegated to the new vote account; the runtime does not
+    /// allow redelegating effective stake, so that is deactivated instead, and
+    /// becomes an unstake account. The fee credit, fee address, weight, and
+    /// `active` flag carry over. Rewards that the old vote account earns after
+    /// this are not collected.
+    ReplaceValidatorVoteAccount,
+
+    /// Convert the Solido account from the version 0 layout to version 1.
+    ///
+    /// Requires the manager to sign. Other instructions fail on an instance
+    /// that is not migrated. Fields that version 0 did not have get the value
+    /// that `Initialize` gives them, and every validator gets the default
+    /// weight. The account cannot grow, so the maximum number of validators
+    /// goes down to what fits; this fails if the current validators do not.
+    MigrateStateToV1,
 }
 
 impl LidoInstruction {
@@ -140,8 +316,12 @@ accounts_struct! {
             is_signer: false,
             is_writable: false,
         },
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
         const sysvar_rent = sysvar::rent::id(),
-        const spl_token = spl_token::id(),
     }
 }
 
@@ -199,7 +379,17 @@ accounts_struct! {
             is_signer: false,
             is_writable: false,
         },
-        const spl_token = spl_token::id(),
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
+        // Only read when the depositor allowlist is enabled, it does not
+        // need to exist otherwise.
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: false,
+        },
         const system_program = system_program::id(),
     }
 }
@@ -261,7 +451,11 @@ accounts_struct! {
             is_signer: false,
             is_writable: false,
         },
-        const spl_token = spl_token::id(),
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
         const sysvar_clock = sysvar::clock::id(),
         const system_program = system_program::id(),
         const stake_program = stake_program::program::id(),
@@ -548,13 +742,17 @@ accounts_struct! {
             is_writable: false,
         },
 
+        // Needed for minting rewards. The token program that owns the stSOL
+        // mint, SPL token or Token-2022.
+        pub spl_token_program {
+            is_signer: false,
+            is_writable: false,
+        },
+
         // We only allow updating balances if the exchange rate is up to date,
         // so we need to know the current epoch.
         const sysvar_clock = sysvar::clock::id(),
 
-        // Needed for minting rewards.
-        const spl_token_program = spl_token::id(),
-
         // Needed to calculate the validator's vote account rent exempt, so it
         // can subtracted from the rewards.
         const sysvar_rent = sysvar::rent::id(),
@@ -578,7 +776,7 @@ pub fn collect_validator_fee(
 // Changes the Fee spec
 // The new Fee structure is passed by argument and the recipients are passed here
 accounts_struct! {
-    ChangeRewardDistributionMeta, ChangeRewardDistributionInfo {
+    ChangeFeeRecipientsMeta, ChangeFeeRecipientsInfo {
         pub lido {
             is_signer: false,
             is_writable: true,
@@ -598,20 +796,14 @@ accounts_struct! {
     }
 }
 
-pub fn change_reward_distribution(
+pub fn change_fee_recipients(
     program_id: &Pubkey,
-    new_reward_distribution: RewardDistribution,
-    accounts: &ChangeRewardDistributionMeta,
+    accounts: &ChangeFeeRecipientsMeta,
 ) -> Instruction {
     Instruction {
         program_id: *program_id,
         accounts: accounts.to_vec(),
-        data: LidoInstruction::ChangeRewardDistribution {
-            new_reward_distribution,
-        }
-        // Serializing the instruction should never fail.
-        .try_to_vec()
-        .unwrap(),
+        data: LidoInstruction::ChangeFeeRecipients.to_vec(),
     }
 }
 
@@ -716,7 +908,11 @@ accounts_struct! {
             // st_sol account
             is_writable: true,
         },
-        const spl_token = spl_token::id(),
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
     }
 }
 
@@ -818,3 +1014,691 @@ pub fn merge_stake(program_id: &Pubkey, accounts: &MergeStakeMeta) -> Instructio
         data: LidoInstruction::MergeStake.try_to_vec().unwrap(), // This should never fail.
     }
 }
+
+accounts_struct! {
+    PublishExchangeRateMeta, PublishExchangeRateInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub maintainer {
+            is_signer: true,
+            // Is writable because it funds the oracle account when it is created.
+            is_writable: true,
+        },
+        pub exchange_rate_oracle {
+            is_signer: false,
+            is_writable: true,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn publish_exchange_rate(
+    program_id: &Pubkey,
+    accounts: &PublishExchangeRateMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::PublishExchangeRate.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SetValidatorWeightMeta, SetValidatorWeightInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub validator_vote_account {
+            is_signer: false,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn set_validator_weight(
+    program_id: &Pubkey,
+    weight: u32,
+    accounts: &SetValidatorWeightMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SetValidatorWeight { weight }.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SetMaxStakePerValidatorMeta, SetMaxStakePerValidatorInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn set_max_stake_per_validator(
+    program_id: &Pubkey,
+    max_stake_per_validator: Lamports,
+    accounts: &SetMaxStakePerValidatorMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SetMaxStakePerValidator {
+            max_stake_per_validator,
+        }
+        .to_vec(),
+    }
+}
+
+accounts_struct! {
+    AcquireMaintainerLeaseMeta, AcquireMaintainerLeaseInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub maintainer {
+            is_signer: true,
+            // Is writable because it funds the lease account when it is created.
+            is_writable: true,
+        },
+        pub maintainer_lease {
+            is_signer: false,
+            is_writable: true,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn acquire_maintainer_lease(
+    program_id: &Pubkey,
+    lease_duration_slots: u64,
+    accounts: &AcquireMaintainerLeaseMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::AcquireMaintainerLease {
+            lease_duration_slots,
+        }
+        .to_vec(),
+    }
+}
+
+accounts_struct! {
+    DepositStakeAccountsMeta, DepositStakeAccountsInfo {
+        pub lido {
+            is_signer: false,
+            // Needs to be writable for us to update the metrics and the
+            // validator's stake accounts balance.
+            is_writable: true,
+        },
+        pub user {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub recipient {
+            is_signer: false,
+            // Is writable due to mint to (spl_token::instruction::mint_to) recipient from
+            // st_sol_mint
+            is_writable: true,
+        },
+        pub st_sol_mint {
+            is_signer: false,
+            // Is writable due to mint to (spl_token::instruction::mint_to) recipient from
+            // st_sol_mint
+            is_writable: true,
+        },
+        pub mint_authority {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub validator_vote_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        // The user's stake account, it ceases to exist after the merge.
+        pub stake_account {
+            is_signer: false,
+            // Is writable due to authorize and merge (solana_program::stake::instruction).
+            is_writable: true,
+        },
+        // The validator's stake account at `stake_seeds.begin`.
+        pub stake_account_merge_into {
+            is_signer: false,
+            // Is writable due to merge (solana_program::stake::instruction::merge)
+            is_writable: true,
+        },
+        pub stake_authority {
+            is_signer: false,
+            is_writable: false,
+        },
+        // Only read, to check the deposit against `max_total_lamports`.
+        pub reserve_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
+        // Only read when the depositor allowlist is enabled.
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: false,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const stake_history = stake_history::id(),
+        const stake_program = stake_program::program::id(),
+    }
+}
+
+pub fn deposit_stake(program_id: &Pubkey, accounts: &DepositStakeAccountsMeta) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::DepositStake.to_vec(),
+    }
+}
+
+accounts_struct! {
+    RebalanceAccountsMeta, RebalanceAccountsInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub maintainer {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub validator_vote_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        // The validator's stake account at `stake_seeds.begin`, like for `Unstake`.
+        pub source_stake_account {
+            is_signer: false,
+            // Is writable due to split (`stake_program::intruction::split`).
+            is_writable: true,
+        },
+        // The validator's unstake account at `unstake_seeds.end`, like for `Unstake`.
+        pub destination_unstake_account {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub stake_authority {
+            is_signer: false,
+            is_writable: false,
+        },
+        // The reserve is part of the SOL that the target balances divide.
+        pub reserve_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+        const stake_program = stake_program::program::id(),
+    }
+}
+
+pub fn rebalance(
+    program_id: &Pubkey,
+    accounts: &RebalanceAccountsMeta,
+    amount: Lamports,
+    superminority: Vec<Pubkey>,
+) -> Instruction {
+    let data = LidoInstruction::Rebalance {
+        amount,
+        superminority,
+    };
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: data.to_vec(),
+    }
+}
+
+accounts_struct! {
+    CreateWithdrawTicketAccountsMeta, CreateWithdrawTicketAccountsInfo {
+        pub lido {
+            is_signer: false,
+            // Needs to be writable to append to the withdraw queue.
+            is_writable: true,
+        },
+        pub st_sol_account_owner {
+            is_signer: true,
+            // Is writable because it pays for the ticket account.
+            is_writable: true,
+        },
+        pub st_sol_account {
+            is_signer: false,
+            // Is writable due to st_sol burn (spl_token::instruction::burn)
+            is_writable: true,
+        },
+        pub st_sol_mint {
+            is_signer: false,
+            // Is writable due to st_sol burn (spl_token::instruction::burn)
+            is_writable: true,
+        },
+        // The ticket at `withdraw_queue.next_ticket_id`, it must not exist yet.
+        pub withdraw_ticket {
+            is_signer: false,
+            is_writable: true,
+        },
+        // The token program that owns the stSOL mint, SPL token or Token-2022.
+        pub spl_token {
+            is_signer: false,
+            is_writable: false,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn create_withdraw_ticket(
+    program_id: &Pubkey,
+    accounts: &CreateWithdrawTicketAccountsMeta,
+    amount: StLamports,
+) -> Instruction {
+    let data = LidoInstruction::CreateWithdrawTicket { amount };
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: data.to_vec(),
+    }
+}
+
+accounts_struct! {
+    FundWithdrawTicketAccountsMeta, FundWithdrawTicketAccountsInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub maintainer {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub reserve_account {
+            is_signer: false,
+            is_writable: true,
+        },
+        // The ticket at `withdraw_queue.next_fund_ticket_id`.
+        pub withdraw_ticket {
+            is_signer: false,
+            is_writable: true,
+        },
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn fund_withdraw_ticket(
+    program_id: &Pubkey,
+    accounts: &FundWithdrawTicketAccountsMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::FundWithdrawTicket.to_vec(),
+    }
+}
+
+accounts_struct! {
+    ClaimWithdrawTicketAccountsMeta, ClaimWithdrawTicketAccountsInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub ticket_owner {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub withdraw_ticket {
+            is_signer: false,
+            is_writable: true,
+        },
+        // Receives the SOL of the ticket, and the rent of the ticket account.
+        pub recipient {
+            is_signer: false,
+            is_writable: true,
+        },
+    }
+}
+
+pub fn claim_withdraw_ticket(
+    program_id: &Pubkey,
+    accounts: &ClaimWithdrawTicketAccountsMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::ClaimWithdrawTicket.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SetPausedMeta, SetPausedInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn set_paused(program_id: &Pubkey, paused: bool, accounts: &SetPausedMeta) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SetPaused { paused }.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SetMaxTotalLamportsMeta, SetMaxTotalLamportsInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn set_max_total_lamports(
+    program_id: &Pubkey,
+    max_total_lamports: Lamports,
+    accounts: &SetMaxTotalLamportsMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SetMaxTotalLamports { max_total_lamports }.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SweepToReserveAccountsMeta, SweepToReserveAccountsInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub maintainer {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub reserve_account {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub sweep_account {
+            is_signer: false,
+            is_writable: true,
+        },
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn sweep_to_reserve(program_id: &Pubkey, accounts: &SweepToReserveAccountsMeta) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SweepToReserve.to_vec(),
+    }
+}
+
+accounts_struct! {
+    ScheduleRewardDistributionMeta, ScheduleRewardDistributionInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+    }
+}
+
+pub fn schedule_reward_distribution(
+    program_id: &Pubkey,
+    new_reward_distribution: RewardDistribution,
+    delay_epochs: u64,
+    accounts: &ScheduleRewardDistributionMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::ScheduleRewardDistribution {
+            new_reward_distribution,
+            delay_epochs,
+        }
+        .to_vec(),
+    }
+}
+
+accounts_struct! {
+    CreateDepositorAllowlistMeta, CreateDepositorAllowlistInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub payer {
+            is_signer: true,
+            is_writable: true,
+        },
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: true,
+        },
+        const sysvar_rent = sysvar::rent::id(),
+        const system_program = system_program::id(),
+    }
+}
+
+pub fn create_depositor_allowlist(
+    program_id: &Pubkey,
+    accounts: &CreateDepositorAllowlistMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::CreateDepositorAllowlist.to_vec(),
+    }
+}
+
+accounts_struct! {
+    AddAllowedDepositorMeta, AddAllowedDepositorInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub depositor {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: true,
+        },
+    }
+}
+
+pub fn add_allowed_depositor(
+    program_id: &Pubkey,
+    accounts: &AddAllowedDepositorMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::AddAllowedDepositor.to_vec(),
+    }
+}
+
+accounts_struct! {
+    RemoveAllowedDepositorMeta, RemoveAllowedDepositorInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub depositor {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: true,
+        },
+    }
+}
+
+pub fn remove_allowed_depositor(
+    program_id: &Pubkey,
+    accounts: &RemoveAllowedDepositorMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::RemoveAllowedDepositor.to_vec(),
+    }
+}
+
+accounts_struct! {
+    SetDepositorAllowlistEnabledMeta, SetDepositorAllowlistEnabledInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        // Enabling checks that the allowlist exists, so deposits do not all
+        // fail because of a missing account.
+        pub depositor_allowlist {
+            is_signer: false,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn set_depositor_allowlist_enabled(
+    program_id: &Pubkey,
+    enabled: bool,
+    accounts: &SetDepositorAllowlistEnabledMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::SetDepositorAllowlistEnabled { enabled }.to_vec(),
+    }
+}
+
+accounts_struct! {
+    ReplaceValidatorVoteAccountMeta, ReplaceValidatorVoteAccountInfo {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+        pub old_validator_vote_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        pub new_validator_vote_account {
+            is_signer: false,
+            is_writable: false,
+        },
+        // Stake authority, to split, delegate, and deactivate the stake.
+        pub stake_authority {
+            is_signer: false,
+            is_writable: false,
+        },
+        const sysvar_clock = sysvar::clock::id(),
+        const sysvar_rent = sysvar::rent::id(),
+        const stake_history = stake_history::id(),
+        const stake_program_config = stake_program::config::id(),
+        // Required to call cross-program.
+        const system_program = system_program::id(),
+        const stake_program = stake_program::program::id(),
+        // First the validator's stake accounts, from the begin seed until (but
+        // excluding) the end seed, followed by its unstake accounts in the same
+        // way. Then the accounts to split them into, one for every source, in
+        // the same order. A source that gets delegated to the new vote account
+        // goes to the next seed of the new stake accounts, a source with
+        // effective stake goes to the next seed of the new unstake accounts,
+        // after the existing unstake accounts.
+        pub ...stake_accounts {
+            is_signer: false,
+            is_writable: true,
+        },
+    }
+}
+
+pub fn replace_validator_vote_account(
+    program_id: &Pubkey,
+    accounts: &ReplaceValidatorVoteAccountMeta,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::ReplaceValidatorVoteAccount.to_vec(),
+    }
+}
+
+accounts_struct! {
+    MigrateStateToV1Meta, MigrateStateToV1Info {
+        pub lido {
+            is_signer: false,
+            is_writable: true,
+        },
+        pub manager {
+            is_signer: true,
+            is_writable: false,
+        },
+    }
+}
+
+pub fn migrate_state_to_v1(program_id: &Pubkey, accounts: &MigrateStateToV1Meta) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts.to_vec(),
+        data: LidoInstruction::MigrateStateToV1.to_vec(),
+    }
+}
@@ -10,9 +10,15 @@ pub mod account_map;
 pub mod accounts;
 pub mod balance;
 pub mod error;
+// Only used off-chain, keep them out of the on-chain program.
+#[cfg(not(target_arch = "bpf"))]
+pub mod fuzz;
+#[cfg(not(target_arch = "bpf"))]
+pub mod idl;
 pub mod instruction;
 pub(crate) mod logic;
 pub mod metrics;
+pub mod migration;
 pub(crate) mod process_management;
 pub mod processor;
 pub mod stake_account;
@@ -40,6 +46,52 @@ pub const VALIDATOR_UNSTAKE_ACCOUNT: &[u8] = b"validator_unstake_account";
 /// Authority responsible for withdrawing the stake rewards.
 pub const REWARDS_WITHDRAW_AUTHORITY: &[u8] = b"rewards_withdraw_authority";
 
+/// Seed for the account that `PublishExchangeRate` writes the exchange rate to.
+pub const EXCHANGE_RATE_ORACLE: &[u8] = b"exchange_rate_oracle";
+
+/// Seed for the account that `AcquireMaintainerLease` writes the lease to.
+pub const MAINTAINER_LEASE: &[u8] = b"maintainer_lease";
+
+/// Seed for withdraw tickets, followed by the ticket id as little-endian u64.
+pub const WITHDRAW_TICKET: &[u8] = b"withdraw_ticket";
+
+/// Seed for the account that holds the depositors that `Deposit` accepts when
+/// the depositor allowlist is enabled.
+pub const DEPOSITOR_ALLOWLIST: &[u8] = b"depositor_allowlist";
+
+/// The longest lease that `AcquireMaintainerLease` grants, in slots (about 10 minutes).
+///
+/// A maintainer that crashes while holding the lease blocks the other
+/// maintainers until the lease expires, so this should not be too long.
+pub const MAXIMUM_MAINTAINER_LEASE_SLOTS: u64 = 1_500;
+
+/// The shortest delay with which `ScheduleRewardDistribution` can change the
+/// reward distribution, in epochs (about four days).
+///
+/// This gives stSOL holders notice of a fee change, and time to withdraw
+/// before it applies.
+pub const MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS: u64 = 2;
+
+/// The number of depositors that fit in the depositor allowlist.
+///
+/// The program creates the allowlist account with a cross-program invocation,
+/// which can allocate at most 10 KiB, so the list cannot be much longer.
+pub const MAXIMUM_ALLOWED_DEPOSITORS: u32 = 256;
+
+/// The Token-2022 program, a superset of the SPL token program.
+///
+/// We do not depend on the `spl-token-2022` crate, the token instructions that
+/// we use are encoded the same for both programs, and the base layouts of mint
+/// and token accounts are the same too.
+pub mod token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+/// Return whether the program can own the stSOL mint: SPL token or Token-2022.
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    program_id == &spl_token::id() || program_id == &token_2022::id()
+}
+
 /// Finds the public key and bump seed for a given authority.  Since this
 /// function can take some time to run, it's preferred to use
 /// `Pubkey::create_program_address(seeds, program_id)` inside programs.
@@ -64,6 +116,13 @@ pub fn find_authority_program_address(
 /// 1 SOL should be sufficient for that.
 pub const MINIMUM_STAKE_ACCOUNT_BALANCE: token::Lamports = token::Lamports(1_000_000_000);
 
+/// The minimum amount of SOL that a withdraw ticket can be owed (1 SOL).
+///
+/// Tickets are funded in order, with one maintainer transaction per ticket, so
+/// without a minimum, anybody could delay all other tickets by creating many
+/// tiny ones. Smaller amounts can be withdrawn with `Withdraw` instead.
+pub const MINIMUM_WITHDRAW_TICKET_AMOUNT: token::Lamports = token::Lamports(1_000_000_000);
+
 /// The maximum number of unstake accounts that a validator can have simultaneously.
 pub const MAXIMUM_UNSTAKE_ACCOUNTS: u64 = 3;
 
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use solana_program::entrypoint::ProgramResult;
+use solana_program::instruction::Instruction;
 use solana_program::program_option::COption;
 use solana_program::program_pack::Pack;
 use solana_program::stake::state::StakeAuthorize;
@@ -17,7 +18,7 @@ use crate::{
     error::LidoError,
     instruction::{CollectValidatorFeeInfo, UnstakeAccountsInfo, WithdrawAccountsInfo},
     state::Fees,
-    state::Lido,
+    state::{DepositorAllowlist, Lido, LIDO_VERSION},
     token::{Lamports, StLamports},
     MINT_AUTHORITY, RESERVE_ACCOUNT,
 };
@@ -173,6 +174,16 @@ pub fn initialize_stake_account_undelegated<'a>(
     )
 }
 
+/// Point a token instruction at the token program that owns the stSOL mint.
+///
+/// The instruction builders of `spl_token` refuse any program id other than
+/// their own, but Token-2022 encodes the instructions that we use the same way,
+/// so we build them for SPL token and then change the program.
+fn with_token_program(mut instruction: Instruction, token_program: &Pubkey) -> Instruction {
+    instruction.program_id = *token_program;
+    instruction
+}
+
 /// Mint the given amount of stSOL and put it in the recipient's account.
 ///
 /// * The stSOL mint must be the one configured in the Solido instance.
@@ -188,6 +199,7 @@ pub fn mint_st_sol_to<'a>(
 ) -> ProgramResult {
     solido.check_mint_is_st_sol_mint(st_sol_mint)?;
     solido.check_is_st_sol_account(recipient)?;
+    solido.check_token_program(spl_token_program)?;
 
     let solido_address_bytes = solido_address.to_bytes();
     let authority_signature_seeds = [
@@ -201,14 +213,17 @@ pub fn mint_st_sol_to<'a>(
     // use those.
     let mint_to_signers = [];
 
-    let instruction = spl_token::instruction::mint_to(
+    let instruction = with_token_program(
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            st_sol_mint.key,
+            recipient.key,
+            mint_authority.key,
+            &mint_to_signers,
+            amount.0,
+        )?,
         spl_token_program.key,
-        st_sol_mint.key,
-        recipient.key,
-        mint_authority.key,
-        &mint_to_signers,
-        amount.0,
-    )?;
+    );
 
     invoke_signed(
         &instruction,
@@ -226,13 +241,21 @@ pub fn mint_st_sol_to<'a>(
 ///
 /// * The stSOL mint must be the one configured in the Solido instance.
 /// * The account account must be an stSOL SPL token account.
+pub struct BurnStSolAccounts<'a, 'b> {
+    pub st_sol_account_owner: &'a AccountInfo<'b>,
+    pub st_sol_account: &'a AccountInfo<'b>,
+    pub st_sol_mint: &'a AccountInfo<'b>,
+    pub spl_token: &'a AccountInfo<'b>,
+}
+
 pub fn burn_st_sol<'a, 'b>(
     solido: &Lido,
-    accounts: &WithdrawAccountsInfo<'a, 'b>,
+    accounts: &BurnStSolAccounts<'a, 'b>,
     amount: StLamports,
 ) -> ProgramResult {
     solido.check_mint_is_st_sol_mint(accounts.st_sol_mint)?;
     solido.check_is_st_sol_account(accounts.st_sol_account)?;
+    solido.check_token_program(accounts.spl_token)?;
 
     let st_sol_account: spl_token::state::Account =
         spl_token::state::Account::unpack_from_slice(&accounts.st_sol_account.data.borrow())?;
@@ -250,14 +273,17 @@ pub fn burn_st_sol<'a, 'b>(
     // The SPL token program supports multisig-managed mints, but we do not
     // use those.
     let burn_signers = [];
-    let instruction = spl_token::instruction::burn(
+    let instruction = with_token_program(
+        spl_token::instruction::burn(
+            &spl_token::id(),
+            accounts.st_sol_account.key,
+            accounts.st_sol_mint.key,
+            accounts.st_sol_account_owner.key,
+            &burn_signers,
+            amount.0,
+        )?,
         accounts.spl_token.key,
-        accounts.st_sol_account.key,
-        accounts.st_sol_mint.key,
-        accounts.st_sol_account_owner.key,
-        &burn_signers,
-        amount.0,
-    )?;
+    );
 
     invoke(
         &instruction,
@@ -521,10 +547,65 @@ pub fn deserialize_lido(program_id: &Pubkey, lido: &AccountInfo) -> Result<Lido,
         );
         return Err(LidoError::InvalidOwner.into());
     }
-    let lido = try_from_slice_unchecked::<Lido>(&lido.data.borrow())?;
+    let data = lido.data.borrow();
+    let version = data.first().copied().unwrap_or_default();
+    if version != LIDO_VERSION {
+        msg!(
+            "Lido state has version {}, but the program expects version {}. Migrate it with MigrateStateToV1.",
+            version,
+            LIDO_VERSION
+        );
+        return Err(LidoError::InvalidLidoVersion.into());
+    }
+    let lido = try_from_slice_unchecked::<Lido>(&data)?;
     Ok(lido)
 }
 
+/// Deserialize the depositor allowlist of the Solido instance, and confirm that it is at its derived address.
+pub fn deserialize_depositor_allowlist(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    allowlist: &AccountInfo,
+) -> Result<DepositorAllowlist, ProgramError> {
+    let (allowlist_address, _) = DepositorAllowlist::find_address(program_id, solido_address);
+    if &allowlist_address != allowlist.key {
+        msg!(
+            "Invalid depositor allowlist, expected {} but got {}.",
+            allowlist_address,
+            allowlist.key
+        );
+        return Err(LidoError::InvalidDepositorAllowlist.into());
+    }
+    if allowlist.owner != program_id {
+        msg!(
+            "Depositor allowlist {} does not exist yet, create it with CreateDepositorAllowlist.",
+            allowlist.key
+        );
+        return Err(LidoError::InvalidDepositorAllowlist.into());
+    }
+    let allowlist = try_from_slice_unchecked::<DepositorAllowlist>(&allowlist.data.borrow())?;
+    Ok(allowlist)
+}
+
+/// Confirm that `depositor` can deposit, when the depositor allowlist is enabled.
+pub fn check_depositor_allowed(
+    program_id: &Pubkey,
+    lido: &Lido,
+    solido_address: &Pubkey,
+    allowlist: &AccountInfo,
+    depositor: &Pubkey,
+) -> ProgramResult {
+    if !lido.is_depositor_allowlist_enabled {
+        return Ok(());
+    }
+    let allowlist = deserialize_depositor_allowlist(program_id, solido_address, allowlist)?;
+    if !allowlist.is_allowed(depositor) {
+        msg!("Depositor {} is not on the depositor allowlist.", depositor);
+        return Err(LidoError::DepositorNotAllowed.into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,246 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Layouts of earlier versions of the `Lido` struct, and their conversion to the current one.
+//!
+//! Solana does not allow programs to resize accounts, so a migrated instance
+//! has to fit in the account it was created in. Version 1 stores more per
+//! validator and more in the constant part than version 0, so the migration
+//! lowers the maximum number of validators to what still fits.
+
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::msg;
+use solana_program::pubkey::Pubkey;
+
+use crate::account_map::{AccountMap, EntryConstantSize, PubkeyAndEntry};
+use crate::error::LidoError;
+use crate::metrics::Metrics;
+use crate::state::{
+    ExchangeRate, FeeRecipients, Lido, Maintainers, PendingRewardDistribution, RewardDistribution,
+    SeedRange, Validator, Validators, WithdrawQueue, DEFAULT_VALIDATOR_WEIGHT, LIDO_CONSTANT_SIZE,
+};
+use crate::token::{Lamports, StLamports};
+
+/// Size of a serialized `ValidatorV0`.
+pub const VALIDATOR_V0_CONSTANT_SIZE: usize = 89;
+
+pub type ValidatorsV0 = AccountMap<ValidatorV0>;
+
+/// A `Validator` as stored by version 0 of the program, before `weight` existed.
+#[repr(C)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct ValidatorV0 {
+    pub fee_credit: StLamports,
+    pub fee_address: Pubkey,
+    pub stake_seeds: SeedRange,
+    pub unstake_seeds: SeedRange,
+    pub stake_accounts_balance: Lamports,
+    pub unstake_accounts_balance: Lamports,
+    pub active: bool,
+}
+
+impl EntryConstantSize for ValidatorV0 {
+    const SIZE: usize = VALIDATOR_V0_CONSTANT_SIZE;
+}
+
+/// The `Lido` struct as stored by version 0 of the program.
+#[repr(C)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct LidoV0 {
+    pub lido_version: u8,
+    pub manager: Pubkey,
+    pub st_sol_mint: Pubkey,
+    pub exchange_rate: ExchangeRate,
+    pub sol_reserve_account_bump_seed: u8,
+    pub stake_authority_bump_seed: u8,
+    pub mint_authority_bump_seed: u8,
+    pub rewards_withdraw_authority_bump_seed: u8,
+    pub reward_distribution: RewardDistribution,
+    pub fee_recipients: FeeRecipients,
+    pub metrics: Metrics,
+    pub validators: ValidatorsV0,
+    pub maintainers: Maintainers,
+}
+
+impl LidoV0 {
+    /// Convert to the current `Lido`, for an account of `data_len` bytes.
+    ///
+    /// Fields that version 0 did not have get the value that `Initialize`
+    /// gives them. Version 0 only supported the SPL token program for stSOL.
+    pub fn migrate(self, data_len: usize) -> Result<Lido, LidoError> {
+        let bytes_for_maintainers =
+            Maintainers::required_bytes(self.maintainers.maximum_entries as usize);
+        let bytes_for_validators = data_len
+            .saturating_sub(LIDO_CONSTANT_SIZE)
+            .saturating_sub(bytes_for_maintainers);
+        let max_validators = (Validators::maximum_entries(bytes_for_validators) as u32)
+            .min(self.validators.maximum_entries);
+        if self.validators.len() > max_validators as usize {
+            msg!(
+                "After migrating, the Solido account has room for {} validators, but there are {}. Remove validators first.",
+                max_validators,
+                self.validators.len(),
+            );
+            return Err(LidoError::InvalidLidoSize);
+        }
+
+        let validators = Validators {
+            entries: self
+                .validators
+                .entries
+                .into_iter()
+                .map(|pe| PubkeyAndEntry {
+                    pubkey: pe.pubkey,
+                    entry: Validator {
+                        fee_credit: pe.entry.fee_credit,
+                        fee_address: pe.entry.fee_address,
+                        stake_seeds: pe.entry.stake_seeds,
+                        unstake_seeds: pe.entry.unstake_seeds,
+                        stake_accounts_balance: pe.entry.stake_accounts_balance,
+                        unstake_accounts_balance: pe.entry.unstake_accounts_balance,
+                        active: pe.entry.active,
+                        weight: DEFAULT_VALIDATOR_WEIGHT,
+                    },
+                })
+                .collect(),
+            maximum_entries: max_validators,
+        };
+
+        Ok(Lido {
+            lido_version: 1,
+            manager: self.manager,
+            st_sol_mint: self.st_sol_mint,
+            exchange_rate: self.exchange_rate,
+            sol_reserve_account_bump_seed: self.sol_reserve_account_bump_seed,
+            stake_authority_bump_seed: self.stake_authority_bump_seed,
+            mint_authority_bump_seed: self.mint_authority_bump_seed,
+            rewards_withdraw_authority_bump_seed: self.rewards_withdraw_authority_bump_seed,
+            reward_distribution: self.reward_distribution,
+            fee_recipients: self.fee_recipients,
+            metrics: self.metrics,
+            validators,
+            maintainers: self.maintainers,
+            st_sol_token_program: spl_token::id(),
+            pending_reward_distribution: PendingRewardDistribution::default(),
+            max_stake_per_validator: Lamports(0),
+            withdraw_queue: WithdrawQueue::default(),
+            is_paused: false,
+            max_total_lamports: Lamports(0),
+            is_depositor_allowlist_enabled: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::LIDO_VERSION;
+    use solana_program::borsh::{get_instance_packed_len, try_from_slice_unchecked};
+
+    const LIDO_V0_CONSTANT_SIZE: usize = 357;
+
+    fn size_v0(max_validators: usize, max_maintainers: usize) -> usize {
+        LIDO_V0_CONSTANT_SIZE
+            + ValidatorsV0::required_bytes(max_validators)
+            + Maintainers::required_bytes(max_maintainers)
+    }
+
+    fn lido_v0(num_validators: u32, max_validators: u32) -> LidoV0 {
+        let mut lido = LidoV0 {
+            manager: Pubkey::new_unique(),
+            st_sol_mint: Pubkey::new_unique(),
+            stake_authority_bump_seed: 7,
+            reward_distribution: RewardDistribution {
+                treasury_fee: 1,
+                validation_fee: 2,
+                developer_fee: 3,
+                st_sol_appreciation: 4,
+            },
+            validators: ValidatorsV0::new(max_validators),
+            maintainers: Maintainers::new(3),
+            ..LidoV0::default()
+        };
+        lido.exchange_rate.sol_balance = Lamports(1_000);
+        lido.exchange_rate.st_sol_supply = StLamports(900);
+        for i in 0..num_validators {
+            let validator = ValidatorV0 {
+                fee_credit: StLamports(i as u64),
+                stake_seeds: SeedRange { begin: 1, end: 3 },
+                stake_accounts_balance: Lamports(100 + i as u64),
+                active: i != 0,
+                ..ValidatorV0::default()
+            };
+            lido.validators
+                .add(Pubkey::new_unique(), validator)
+                .unwrap();
+        }
+        lido.maintainers.add(Pubkey::new_unique(), ()).unwrap();
+        lido
+    }
+
+    #[test]
+    fn test_lido_v0_constant_size() {
+        let validator = get_instance_packed_len(&ValidatorV0::default()).unwrap();
+        assert_eq!(validator, ValidatorV0::SIZE);
+        let lido = get_instance_packed_len(&LidoV0::default()).unwrap();
+        assert_eq!(lido, size_v0(0, 0));
+    }
+
+    #[test]
+    fn test_migrate_v0_fits_in_account() {
+        let lido = lido_v0(5, 10);
+        let mut data = vec![0_u8; size_v0(10, 3)];
+        BorshSerialize::serialize(&lido, &mut &mut data[..]).unwrap();
+
+        let restored = try_from_slice_unchecked::<LidoV0>(&data).unwrap();
+        let migrated = restored.migrate(data.len()).unwrap();
+
+        assert_eq!(migrated.lido_version, LIDO_VERSION);
+        assert_eq!(migrated.manager, lido.manager);
+        assert_eq!(migrated.st_sol_mint, lido.st_sol_mint);
+        assert_eq!(migrated.st_sol_token_program, spl_token::id());
+        assert_eq!(migrated.exchange_rate, lido.exchange_rate);
+        assert_eq!(migrated.stake_authority_bump_seed, 7);
+        assert_eq!(migrated.reward_distribution, lido.reward_distribution);
+        assert_eq!(migrated.maintainers, lido.maintainers);
+        assert_eq!(migrated.validators.len(), 5);
+        assert_eq!(migrated.validators.maximum_entries, 8);
+        for (old, new) in lido
+            .validators
+            .entries
+            .iter()
+            .zip(migrated.validators.entries.iter())
+        {
+            assert_eq!(old.pubkey, new.pubkey);
+            assert_eq!(old.entry.fee_credit, new.entry.fee_credit);
+            assert_eq!(old.entry.stake_seeds, new.entry.stake_seeds);
+            assert_eq!(
+                old.entry.stake_accounts_balance,
+                new.entry.stake_accounts_balance
+            );
+            assert_eq!(old.entry.active, new.entry.active);
+            assert_eq!(new.entry.weight, DEFAULT_VALIDATOR_WEIGHT);
+        }
+        assert_eq!(migrated.check_well_formed(), Ok(()));
+
+        // Even when full, the migrated instance must fit in the same account.
+        let mut full = migrated.clone();
+        while full.validators.len() < full.validators.maximum_entries as usize {
+            full.validators
+                .add(Pubkey::new_unique(), Validator::default())
+                .unwrap();
+        }
+        BorshSerialize::serialize(&full, &mut &mut data[..]).unwrap();
+        let restored = try_from_slice_unchecked::<Lido>(&data).unwrap();
+        assert_eq!(restored, full);
+    }
+
+    #[test]
+    fn test_migrate_v0_fails_when_validators_do_not_fit() {
+        let lido = lido_v0(10, 10);
+        assert_eq!(
+            lido.migrate(size_v0(10, 3)),
+            Err(LidoError::InvalidLidoSize)
+        );
+    }
+}
@@ -1,45 +1,96 @@
 // SPDX-FileCopyrightText: 2021 Chorus One AG
 // SPDX-License-Identifier: GPL-3.0
 
+use borsh::BorshSerialize;
+use solana_program::borsh::try_from_slice_unchecked;
+use solana_program::clock::Clock;
 use solana_program::program::invoke_signed;
 use solana_program::rent::Rent;
+use solana_program::stake as stake_program;
+use solana_program::stake_history::StakeHistory;
 use solana_program::sysvar::Sysvar;
 use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, msg, pubkey::Pubkey};
 
+use crate::account_map::PubkeyAndEntry;
 use crate::logic::check_rent_exempt;
+use crate::migration::LidoV0;
 use crate::processor::StakeType;
+use crate::stake_account::{deserialize_stake_account, StakeAccount};
 use crate::vote_state::PartialVoteState;
 use crate::{
     error::LidoError,
     instruction::{
-        AddMaintainerInfo, AddValidatorInfo, ChangeRewardDistributionInfo, ClaimValidatorFeeInfo,
-        DeactivateValidatorInfo, MergeStakeInfo, RemoveMaintainerInfo, RemoveValidatorInfo,
+        AddAllowedDepositorInfo, AddMaintainerInfo, AddValidatorInfo, ChangeFeeRecipientsInfo,
+        ClaimValidatorFeeInfo, DeactivateValidatorInfo, MergeStakeInfo, MigrateStateToV1Info,
+        RemoveAllowedDepositorInfo, RemoveMaintainerInfo, RemoveValidatorInfo,
+        ReplaceValidatorVoteAccountInfo, ScheduleRewardDistributionInfo,
+        SetDepositorAllowlistEnabledInfo, SetMaxStakePerValidatorInfo, SetMaxTotalLamportsInfo,
+        SetPausedInfo, SetValidatorWeightInfo,
     },
-    logic::{deserialize_lido, mint_st_sol_to},
-    state::{RewardDistribution, Validator},
-    token::StLamports,
-    STAKE_AUTHORITY,
+    logic::{
+        deserialize_depositor_allowlist, deserialize_lido, mint_st_sol_to, split_stake_account,
+        SplitStakeAccounts,
+    },
+    state::{PendingRewardDistribution, RewardDistribution, SeedRange, Validator},
+    token::{Lamports, StLamports},
+    MAXIMUM_UNSTAKE_ACCOUNTS, MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS, STAKE_AUTHORITY,
+    VALIDATOR_STAKE_ACCOUNT, VALIDATOR_UNSTAKE_ACCOUNT,
 };
 
-pub fn process_change_reward_distribution(
+pub fn process_change_fee_recipients(
     program_id: &Pubkey,
-    new_reward_distribution: RewardDistribution,
     accounts_raw: &[AccountInfo],
 ) -> ProgramResult {
-    let accounts = ChangeRewardDistributionInfo::try_from_slice(accounts_raw)?;
+    let accounts = ChangeFeeRecipientsInfo::try_from_slice(accounts_raw)?;
     let mut lido = deserialize_lido(program_id, accounts.lido)?;
     lido.check_manager(accounts.manager)?;
 
     lido.check_is_st_sol_account(accounts.treasury_account)?;
     lido.check_is_st_sol_account(accounts.developer_account)?;
 
-    lido.reward_distribution = new_reward_distribution;
     lido.fee_recipients.treasury_account = *accounts.treasury_account.key;
     lido.fee_recipients.developer_account = *accounts.developer_account.key;
 
     lido.save(accounts.lido)
 }
 
+pub fn process_schedule_reward_distribution(
+    program_id: &Pubkey,
+    new_reward_distribution: RewardDistribution,
+    delay_epochs: u64,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = ScheduleRewardDistributionInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    if delay_epochs < MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS {
+        msg!(
+            "The reward distribution can change {} epochs from now at the earliest, but the delay is {} epochs.",
+            MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS,
+            delay_epochs
+        );
+        return Err(LidoError::RewardDistributionDelayTooShort.into());
+    }
+
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let activation_epoch = clock
+        .epoch
+        .checked_add(delay_epochs)
+        .ok_or(LidoError::CalculationFailure)?;
+    msg!(
+        "Scheduling reward distribution {:?} for epoch {}.",
+        new_reward_distribution,
+        activation_epoch
+    );
+    lido.pending_reward_distribution = PendingRewardDistribution {
+        reward_distribution: new_reward_distribution,
+        activation_epoch,
+    };
+
+    lido.save(accounts.lido)
+}
+
 pub fn process_add_validator(program_id: &Pubkey, accounts_raw: &[AccountInfo]) -> ProgramResult {
     let accounts = AddValidatorInfo::try_from_slice(accounts_raw)?;
     let mut lido = deserialize_lido(program_id, accounts.lido)?;
@@ -117,6 +168,88 @@ pub fn process_deactivate_validator(
     lido.save(accounts.lido)
 }
 
+/// Set the weight of a validator, which determines its share of the stake.
+pub fn process_set_validator_weight(
+    program_id: &Pubkey,
+    weight: u32,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SetValidatorWeightInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    let validator = lido
+        .validators
+        .get_mut(accounts.validator_vote_account.key)?;
+
+    msg!(
+        "Changing weight of validator {} from {} to {}.",
+        validator.pubkey,
+        validator.entry.weight,
+        weight
+    );
+    validator.entry.weight = weight;
+
+    lido.save(accounts.lido)
+}
+
+pub fn process_set_max_stake_per_validator(
+    program_id: &Pubkey,
+    max_stake_per_validator: Lamports,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SetMaxStakePerValidatorInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    msg!(
+        "Changing maximum stake per validator from {} to {}.",
+        lido.max_stake_per_validator,
+        max_stake_per_validator
+    );
+    lido.max_stake_per_validator = max_stake_per_validator;
+
+    lido.save(accounts.lido)
+}
+
+pub fn process_set_max_total_lamports(
+    program_id: &Pubkey,
+    max_total_lamports: Lamports,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SetMaxTotalLamportsInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    msg!(
+        "Changing maximum total SOL from {} to {}.",
+        lido.max_total_lamports,
+        max_total_lamports
+    );
+    lido.max_total_lamports = max_total_lamports;
+
+    lido.save(accounts.lido)
+}
+
+pub fn process_set_paused(
+    program_id: &Pubkey,
+    paused: bool,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SetPausedInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    if paused {
+        msg!("Pausing deposits and withdrawals.");
+    } else {
+        msg!("Resuming deposits and withdrawals.");
+    }
+    lido.is_paused = paused;
+
+    lido.save(accounts.lido)
+}
+
 pub fn process_claim_validator_fee(
     program_id: &Pubkey,
     accounts_raw: &[AccountInfo],
@@ -171,6 +304,77 @@ pub fn process_remove_maintainer(
     lido.save(accounts.lido)
 }
 
+/// Adds a depositor to the depositor allowlist.
+pub fn process_add_allowed_depositor(
+    program_id: &Pubkey,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = AddAllowedDepositorInfo::try_from_slice(accounts_raw)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    let mut allowlist = deserialize_depositor_allowlist(
+        program_id,
+        accounts.lido.key,
+        accounts.depositor_allowlist,
+    )?;
+    allowlist.depositors.add(*accounts.depositor.key, ())?;
+
+    BorshSerialize::serialize(
+        &allowlist,
+        &mut *accounts.depositor_allowlist.data.borrow_mut(),
+    )?;
+    Ok(())
+}
+
+/// Removes a depositor from the depositor allowlist.
+pub fn process_remove_allowed_depositor(
+    program_id: &Pubkey,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = RemoveAllowedDepositorInfo::try_from_slice(accounts_raw)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    let mut allowlist = deserialize_depositor_allowlist(
+        program_id,
+        accounts.lido.key,
+        accounts.depositor_allowlist,
+    )?;
+    allowlist.depositors.remove(accounts.depositor.key)?;
+
+    BorshSerialize::serialize(
+        &allowlist,
+        &mut *accounts.depositor_allowlist.data.borrow_mut(),
+    )?;
+    Ok(())
+}
+
+pub fn process_set_depositor_allowlist_enabled(
+    program_id: &Pubkey,
+    enabled: bool,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SetDepositorAllowlistEnabledInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_manager(accounts.manager)?;
+
+    if enabled {
+        // Without the allowlist account, every deposit would fail.
+        deserialize_depositor_allowlist(
+            program_id,
+            accounts.lido.key,
+            accounts.depositor_allowlist,
+        )?;
+        msg!("Enabling the depositor allowlist.");
+    } else {
+        msg!("Disabling the depositor allowlist.");
+    }
+    lido.is_depositor_allowlist_enabled = enabled;
+
+    lido.save(accounts.lido)
+}
+
 /// TODO(#186) Allow validator to change fee account
 /// Called by the validator, changes the fee account which the validator
 /// receives tokens
@@ -269,3 +473,339 @@ pub fn process_merge_stake(program_id: &Pubkey, accounts_raw: &[AccountInfo]) ->
 
     lido.save(accounts.lido)
 }
+
+/// Move a validator to a new vote account, see `ReplaceValidatorVoteAccount`.
+///
+/// Stake account addresses are derived from the vote account, so we split
+/// every stake and unstake account in full into the account at the same seed
+/// range for the new vote account. Stake that is not effective yet gets
+/// delegated to the new vote account, effective stake cannot be redelegated,
+/// so we deactivate it and track it as an unstake account of the validator.
+pub fn process_replace_validator_vote_account(
+    program_id: &Pubkey,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = ReplaceValidatorVoteAccountInfo::try_from_slice(accounts_raw)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    let rent = &Rent::from_account_info(accounts.sysvar_rent)?;
+    lido.check_manager(accounts.manager)?;
+    lido.check_stake_authority(program_id, accounts.lido.key, accounts.stake_authority)?;
+
+    check_rent_exempt(
+        rent,
+        accounts.new_validator_vote_account,
+        "Validator vote account",
+    )?;
+    // The new vote account must satisfy the same conditions as in `AddValidator`.
+    let _partial_vote_state = PartialVoteState::deserialize(
+        program_id,
+        accounts.lido.key,
+        accounts.new_validator_vote_account,
+    )?;
+    if lido
+        .validators
+        .get(accounts.new_validator_vote_account.key)
+        .is_ok()
+    {
+        msg!(
+            "Vote account {} already belongs to a validator in the list.",
+            accounts.new_validator_vote_account.key
+        );
+        return Err(LidoError::DuplicatedEntry.into());
+    }
+
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let stake_history = StakeHistory::from_account_info(accounts.stake_history)?;
+
+    let old_validator = lido
+        .validators
+        .get(accounts.old_validator_vote_account.key)?
+        .clone();
+    let mut new_validator = old_validator.clone();
+    new_validator.pubkey = *accounts.new_validator_vote_account.key;
+
+    let n_stake_accounts =
+        old_validator.entry.stake_seeds.end - old_validator.entry.stake_seeds.begin;
+    let n_unstake_accounts =
+        old_validator.entry.unstake_seeds.end - old_validator.entry.unstake_seeds.begin;
+    let n_sources = (n_stake_accounts + n_unstake_accounts) as usize;
+    if accounts.stake_accounts.len() != 2 * n_sources {
+        msg!(
+            "Wrong number of stake accounts provided, expected {} stake accounts and {} unstake accounts, \
+            and as many destination accounts, but got {} accounts.",
+            n_stake_accounts,
+            n_unstake_accounts,
+            accounts.stake_accounts.len()
+        );
+        return Err(LidoError::InvalidStakeAccount.into());
+    }
+    // Does not panic, because len = 2 * n_sources.
+    let (sources, destinations) = accounts.stake_accounts.split_at(n_sources);
+
+    // For every source, the seed and type of the source and of its destination.
+    let mut moves = Vec::with_capacity(n_sources);
+    let mut new_stake_seeds = SeedRange {
+        begin: old_validator.entry.stake_seeds.begin,
+        end: old_validator.entry.stake_seeds.begin,
+    };
+    // The existing unstake accounts keep their seeds, deactivated stake
+    // accounts go after them.
+    let mut new_unstake_seeds = old_validator.entry.unstake_seeds.clone();
+    let mut deactivated_balance = Lamports(0);
+
+    for (seed, source) in old_validator.entry.stake_seeds.into_iter().zip(sources) {
+        check_stake_account_address(
+            program_id,
+            accounts.lido.key,
+            &old_validator,
+            source,
+            seed,
+            StakeType::Stake,
+        )?;
+        let stake = deserialize_stake_account(&source.data.borrow())?;
+        let source_balance = Lamports(source.lamports());
+        let stake_account = StakeAccount::from_delegated_account(
+            source_balance,
+            &stake,
+            &clock,
+            &stake_history,
+            seed,
+        );
+        // The stake program refuses to delegate stake that is effective.
+        if stake_account.balance.active == Lamports(0)
+            && stake_account.balance.deactivating == Lamports(0)
+        {
+            moves.push((
+                seed,
+                StakeType::Stake,
+                new_stake_seeds.end,
+                StakeType::Stake,
+            ));
+            new_stake_seeds.end += 1;
+        } else {
+            moves.push((
+                seed,
+                StakeType::Stake,
+                new_unstake_seeds.end,
+                StakeType::Unstake,
+            ));
+            new_unstake_seeds.end += 1;
+            deactivated_balance = (deactivated_balance + source_balance)?;
+        }
+    }
+    for (seed, source) in old_validator
+        .entry
+        .unstake_seeds
+        .into_iter()
+        .zip(&sources[n_stake_accounts as usize..])
+    {
+        check_stake_account_address(
+            program_id,
+            accounts.lido.key,
+            &old_validator,
+            source,
+            seed,
+            StakeType::Unstake,
+        )?;
+        moves.push((seed, StakeType::Unstake, seed, StakeType::Unstake));
+    }
+
+    // `WithdrawInactiveStake` needs to reference all unstake accounts in a
+    // single transaction, so we keep the same limit as `Unstake`.
+    if new_unstake_seeds.end - new_unstake_seeds.begin > MAXIMUM_UNSTAKE_ACCOUNTS {
+        msg!(
+            "Replacing the vote account would leave validator {} with {} unstake accounts, at most {} are allowed.",
+            old_validator.pubkey,
+            new_unstake_seeds.end - new_unstake_seeds.begin,
+            MAXIMUM_UNSTAKE_ACCOUNTS
+        );
+        msg!("Please merge the active stake accounts, or withdraw the unstake accounts first.");
+        return Err(LidoError::MaxUnstakeAccountsReached.into());
+    }
+
+    let stake_authority_seeds: &[&[u8]] = &[
+        &accounts.lido.key.to_bytes(),
+        STAKE_AUTHORITY,
+        &[lido.stake_authority_bump_seed],
+    ];
+
+    for ((source, destination), (source_seed, source_type, destination_seed, destination_type)) in
+        sources.iter().zip(destinations).zip(moves)
+    {
+        let (destination_address, destination_bump_seed) = new_validator
+            .find_stake_account_address(
+                program_id,
+                accounts.lido.key,
+                destination_seed,
+                destination_type,
+            );
+        if &destination_address != destination.key {
+            msg!(
+                "Destination for the account at seed {} should be {}, is {}.",
+                source_seed,
+                destination_address,
+                destination.key
+            );
+            return Err(LidoError::InvalidStakeAccount.into());
+        }
+        let authority = match destination_type {
+            StakeType::Stake => VALIDATOR_STAKE_ACCOUNT,
+            StakeType::Unstake => VALIDATOR_UNSTAKE_ACCOUNT,
+        };
+        let seeds = [
+            &accounts.lido.key.to_bytes(),
+            &new_validator.pubkey.to_bytes(),
+            authority,
+            &destination_seed.to_le_bytes()[..],
+            &[destination_bump_seed],
+        ];
+        split_stake_account(
+            accounts.lido.key,
+            &lido,
+            &SplitStakeAccounts {
+                source_stake_account: source,
+                destination_stake_account: destination,
+                authority: accounts.stake_authority,
+                system_program: accounts.system_program,
+                stake_program: accounts.stake_program,
+            },
+            Lamports(source.lamports()),
+            &[&seeds],
+        )?;
+
+        match (source_type, destination_type) {
+            (StakeType::Stake, StakeType::Stake) => {
+                msg!(
+                    "Delegating stake account at seed {} to {} ...",
+                    destination_seed,
+                    new_validator.pubkey
+                );
+                invoke_signed(
+                    &stake_program::instruction::delegate_stake(
+                        destination.key,
+                        accounts.stake_authority.key,
+                        accounts.new_validator_vote_account.key,
+                    ),
+                    &[
+                        destination.clone(),
+                        accounts.new_validator_vote_account.clone(),
+                        accounts.sysvar_clock.clone(),
+                        accounts.stake_history.clone(),
+                        accounts.stake_program_config.clone(),
+                        accounts.stake_authority.clone(),
+                        accounts.stake_program.clone(),
+                    ],
+                    &[stake_authority_seeds],
+                )?;
+            }
+            (StakeType::Stake, StakeType::Unstake) => {
+                msg!(
+                    "Stake account at seed {} is active, deactivating it as unstake account {} ...",
+                    source_seed,
+                    destination_seed
+                );
+                invoke_signed(
+                    &stake_program::instruction::deactivate_stake(
+                        destination.key,
+                        accounts.stake_authority.key,
+                    ),
+                    &[
+                        destination.clone(),
+                        accounts.sysvar_clock.clone(),
+                        accounts.stake_authority.clone(),
+                        accounts.stake_program.clone(),
+                    ],
+                    &[stake_authority_seeds],
+                )?;
+            }
+            // Unstake accounts are already deactivating, moving them is enough.
+            (StakeType::Unstake, _) => {}
+        }
+    }
+
+    let validator = lido
+        .validators
+        .get_mut(accounts.old_validator_vote_account.key)?;
+
+    // The stake account balance may not include rewards yet that are in the
+    // accounts we deactivated, but we can't unstake more than we have.
+    let unstake_increase = std::cmp::min(
+        deactivated_balance,
+        validator.entry.effective_stake_balance(),
+    );
+    validator.entry.unstake_accounts_balance =
+        (validator.entry.unstake_accounts_balance + unstake_increase)?;
+    validator.entry.stake_seeds = new_stake_seeds;
+    validator.entry.unstake_seeds = new_unstake_seeds;
+    msg!(
+        "Validator {} now votes with {}.",
+        validator.pubkey,
+        new_validator.pubkey
+    );
+    validator.pubkey = new_validator.pubkey;
+
+    lido.save(accounts.lido)
+}
+
+/// Confirm that `account` is the stake account of `validator` at `seed`.
+fn check_stake_account_address(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validator: &PubkeyAndEntry<Validator>,
+    account: &AccountInfo,
+    seed: u64,
+    stake_type: StakeType,
+) -> ProgramResult {
+    let (address, _bump_seed) =
+        validator.find_stake_account_address(program_id, solido_address, seed, stake_type);
+    if &address != account.key {
+        msg!(
+            "Stake account at seed {} should be {}, is {}.",
+            seed,
+            address,
+            account.key
+        );
+        return Err(LidoError::InvalidStakeAccount.into());
+    }
+    Ok(())
+}
+
+/// Convert the Solido account from version 0 to version 1, see `MigrateStateToV1`.
+pub fn process_migrate_state_to_v1(
+    program_id: &Pubkey,
+    accounts_raw: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = MigrateStateToV1Info::try_from_slice(accounts_raw)?;
+    if accounts.lido.owner != program_id {
+        msg!(
+            "Lido state is owned by {}, but should be owned by the Lido program ({}).",
+            accounts.lido.owner,
+            program_id
+        );
+        return Err(LidoError::InvalidOwner.into());
+    }
+    // Check the version before deserializing, other versions need not
+    // deserialize as version 0 at all.
+    let version = accounts.lido.data.borrow().first().copied();
+    if version != Some(0) {
+        msg!(
+            "Lido state has version {:?}, only version 0 can be migrated.",
+            version
+        );
+        return Err(LidoError::InvalidLidoVersion.into());
+    }
+    let lido_v0 = try_from_slice_unchecked::<LidoV0>(&accounts.lido.data.borrow())?;
+    if &lido_v0.manager != accounts.manager.key {
+        msg!("Invalid manager, not the same as the one stored in state");
+        return Err(LidoError::InvalidManager.into());
+    }
+
+    let lido = lido_v0.migrate(accounts.lido.data_len())?;
+    msg!(
+        "Migrated Solido state to version {}, with room for {} validators.",
+        lido.lido_version,
+        lido.validators.maximum_entries
+    );
+
+    lido.save(accounts.lido)
+}
@@ -3,48 +3,65 @@
 
 //! Program state processor
 
+use std::cmp::Ordering;
 use std::ops::{Add, Sub};
 
 use crate::{
+    balance::{
+        cmp_stake_per_weight, get_max_withdraw_amount, get_rebalance_amount, get_target_balance,
+        has_room_for_stake, receives_stake,
+    },
     error::LidoError,
     instruction::{
-        CollectValidatorFeeInfo, DepositAccountsInfo, InitializeAccountsInfo, LidoInstruction,
-        StakeDepositAccountsInfo, UnstakeAccountsInfo, UpdateExchangeRateAccountsInfo,
+        AcquireMaintainerLeaseInfo, ClaimWithdrawTicketAccountsInfo, CollectValidatorFeeInfo,
+        CreateDepositorAllowlistInfo, CreateWithdrawTicketAccountsInfo, DepositAccountsInfo,
+        DepositStakeAccountsInfo, FundWithdrawTicketAccountsInfo, InitializeAccountsInfo,
+        LidoInstruction, PublishExchangeRateInfo, RebalanceAccountsInfo, StakeDepositAccountsInfo,
+        SweepToReserveAccountsInfo, UnstakeAccountsInfo, UpdateExchangeRateAccountsInfo,
         WithdrawAccountsInfo, WithdrawInactiveStakeInfo,
     },
+    is_token_program,
     logic::{
-        burn_st_sol, check_mint, check_rent_exempt, check_unstake_accounts,
-        create_account_even_if_funded, deserialize_lido, distribute_fees,
-        initialize_stake_account_undelegated, mint_st_sol_to, split_stake_account,
-        transfer_stake_authority, CreateAccountOptions, SplitStakeAccounts,
+        burn_st_sol, check_depositor_allowed, check_mint, check_rent_exempt,
+        check_unstake_accounts, create_account_even_if_funded, deserialize_lido, distribute_fees,
+        get_reserve_available_balance, initialize_stake_account_undelegated, mint_st_sol_to,
+        split_stake_account, transfer_stake_authority, BurnStSolAccounts, CreateAccountOptions,
+        SplitStakeAccounts,
     },
     metrics::Metrics,
     process_management::{
-        process_add_maintainer, process_add_validator, process_change_reward_distribution,
-        process_claim_validator_fee, process_deactivate_validator, process_merge_stake,
+        process_add_allowed_depositor, process_add_maintainer, process_add_validator,
+        process_change_fee_recipients, process_claim_validator_fee, process_deactivate_validator,
+        process_merge_stake, process_migrate_state_to_v1, process_remove_allowed_depositor,
         process_remove_maintainer, process_remove_validator,
+        process_replace_validator_vote_account, process_schedule_reward_distribution,
+        process_set_depositor_allowlist_enabled, process_set_max_stake_per_validator,
+        process_set_max_total_lamports, process_set_paused, process_set_validator_weight,
     },
     stake_account::{deserialize_stake_account, StakeAccount},
     state::{
-        ExchangeRate, FeeRecipients, Lido, Maintainers, RewardDistribution, Validators,
-        LIDO_CONSTANT_SIZE, LIDO_VERSION,
+        DepositorAllowlist, Depositors, ExchangeRate, ExchangeRateOracle, FeeRecipients, Lido,
+        MaintainerLease, Maintainers, PendingRewardDistribution, RewardDistribution, Validators,
+        WithdrawQueue, WithdrawTicket, EXCHANGE_RATE_ORACLE_SIZE, LIDO_CONSTANT_SIZE, LIDO_VERSION,
+        MAINTAINER_LEASE_SIZE, WITHDRAW_TICKET_SIZE,
     },
-    token::{Lamports, Rational, StLamports},
-    vote_instruction, MAXIMUM_UNSTAKE_ACCOUNTS, MINIMUM_STAKE_ACCOUNT_BALANCE, MINT_AUTHORITY,
-    RESERVE_ACCOUNT, REWARDS_WITHDRAW_AUTHORITY, STAKE_AUTHORITY, VALIDATOR_STAKE_ACCOUNT,
-    VALIDATOR_UNSTAKE_ACCOUNT,
+    token::{Lamports, StLamports},
+    vote_instruction, DEPOSITOR_ALLOWLIST, EXCHANGE_RATE_ORACLE, MAINTAINER_LEASE,
+    MAXIMUM_ALLOWED_DEPOSITORS, MAXIMUM_MAINTAINER_LEASE_SLOTS, MAXIMUM_UNSTAKE_ACCOUNTS,
+    MINIMUM_STAKE_ACCOUNT_BALANCE, MINIMUM_WITHDRAW_TICKET_AMOUNT, MINT_AUTHORITY, RESERVE_ACCOUNT,
+    REWARDS_WITHDRAW_AUTHORITY, STAKE_AUTHORITY, VALIDATOR_STAKE_ACCOUNT,
+    VALIDATOR_UNSTAKE_ACCOUNT, WITHDRAW_TICKET,
 };
 
-use solana_program::stake::{self as stake_program};
+use solana_program::stake::{self as stake_program, state::StakeAuthorize};
 use solana_program::stake_history::StakeHistory;
 use {
-    borsh::BorshDeserialize,
+    borsh::{BorshDeserialize, BorshSerialize},
     solana_program::{
         account_info::AccountInfo,
         clock::Clock,
         entrypoint::ProgramResult,
         msg,
-        native_token::LAMPORTS_PER_SOL,
         program::{invoke, invoke_signed},
         program_error::ProgramError,
         pubkey::Pubkey,
@@ -102,6 +119,13 @@ pub fn process_initialize(
 
     let (mint_authority, mint_bump_seed) =
         Pubkey::find_program_address(&[&accounts.lido.key.to_bytes(), MINT_AUTHORITY], program_id);
+    if !is_token_program(accounts.st_sol_mint.owner) {
+        msg!(
+            "The stSOL mint is owned by {}, which is not a token program.",
+            accounts.st_sol_mint.owner
+        );
+        return Err(LidoError::InvalidMint.into());
+    }
     // Check if the token has no minted tokens and right mint authority.
     check_mint(rent, accounts.st_sol_mint, &mint_authority)?;
 
@@ -115,21 +139,30 @@ pub fn process_initialize(
         lido_version: version,
         manager: *accounts.manager.key,
         st_sol_mint: *accounts.st_sol_mint.key,
+        st_sol_token_program: *accounts.st_sol_mint.owner,
         exchange_rate: ExchangeRate::default(),
         sol_reserve_account_bump_seed: reserve_bump_seed,
         mint_authority_bump_seed: mint_bump_seed,
         stake_authority_bump_seed: deposit_bump_seed,
         rewards_withdraw_authority_bump_seed,
         reward_distribution,
+        pending_reward_distribution: PendingRewardDistribution::default(),
         fee_recipients: FeeRecipients {
             treasury_account: *accounts.treasury_account.key,
             developer_account: *accounts.developer_account.key,
         },
+        max_stake_per_validator: Lamports(0),
+        withdraw_queue: WithdrawQueue::default(),
+        is_paused: false,
+        max_total_lamports: Lamports(0),
+        is_depositor_allowlist_enabled: false,
         metrics: Metrics::new(),
         maintainers: Maintainers::new(max_maintainers),
         validators: Validators::new(max_validators),
     };
 
+    lido.check_token_program(accounts.spl_token)?;
+
     // Confirm that the fee recipients are actually stSOL accounts.
     lido.check_is_st_sol_account(accounts.treasury_account)?;
     lido.check_is_st_sol_account(accounts.developer_account)?;
@@ -150,7 +183,16 @@ pub fn process_deposit(
     }
 
     let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_not_paused()?;
     lido.check_reserve_account(program_id, accounts.lido.key, accounts.reserve_account)?;
+    lido.check_deposit_cap(accounts.reserve_account, amount)?;
+    check_depositor_allowed(
+        program_id,
+        &lido,
+        accounts.lido.key,
+        accounts.depositor_allowlist,
+        accounts.user.key,
+    )?;
 
     invoke(
         &system_instruction::transfer(accounts.user.key, accounts.reserve_account.key, amount.0),
@@ -186,6 +228,183 @@ pub fn process_deposit(
     lido.save(accounts.lido)
 }
 
+/// Take over an active stake account delegated to one of our validators, and
+/// mint stSOL for its balance.
+pub fn process_deposit_stake(program_id: &Pubkey, accounts_raw: &[AccountInfo]) -> ProgramResult {
+    let accounts = DepositStakeAccountsInfo::try_from_slice(accounts_raw)?;
+
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_not_paused()?;
+    check_depositor_allowed(
+        program_id,
+        &lido,
+        accounts.lido.key,
+        accounts.depositor_allowlist,
+        accounts.user.key,
+    )?;
+    lido.check_stake_authority(program_id, accounts.lido.key, accounts.stake_authority)?;
+    lido.check_reserve_account(program_id, accounts.lido.key, accounts.reserve_account)?;
+
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let stake_history = StakeHistory::from_account_info(accounts.stake_history)?;
+
+    if accounts.stake_account.owner != &stake_program::program::id() {
+        msg!(
+            "Stake account {} is not owned by the stake program.",
+            accounts.stake_account.key
+        );
+        return Err(LidoError::InvalidStakeAccount.into());
+    }
+
+    let validator = lido.validators.get(accounts.validator_vote_account.key)?;
+    if !validator.entry.active {
+        msg!(
+            "Validator {} is inactive, new deposits are not allowed",
+            validator.pubkey
+        );
+        return Err(LidoError::StakeToInactiveValidator.into());
+    }
+
+    let stake = deserialize_stake_account(&accounts.stake_account.data.borrow())?;
+    if &stake.delegation.voter_pubkey != accounts.validator_vote_account.key {
+        msg!(
+            "Stake account {} is delegated to {}, not to validator {}.",
+            accounts.stake_account.key,
+            stake.delegation.voter_pubkey,
+            validator.pubkey
+        );
+        return Err(LidoError::InvalidStakeAccount.into());
+    }
+
+    let amount = Lamports(accounts.stake_account.lamports());
+    // The seed is only used to identify Solido's own stake accounts, the
+    // user's stake account has none.
+    let stake_account =
+        StakeAccount::from_delegated_account(amount, &stake, &clock, &stake_history, 0);
+    if !stake_account.is_active() {
+        msg!(
+            "Stake account {} is not fully active, only active stake can be deposited.",
+            accounts.stake_account.key
+        );
+        return Err(LidoError::WrongStakeState.into());
+    }
+
+    if let Some(max_stake) = lido.get_max_stake_per_validator() {
+        let stake_after = (validator.entry.effective_stake_balance() + amount)?;
+        if stake_after > max_stake {
+            msg!(
+                "Depositing {} with {} would bring its stake to {}, above the maximum of {}.",
+                amount,
+                validator.pubkey,
+                stake_after,
+                max_stake,
+            );
+            return Err(LidoError::MaxStakePerValidatorExceeded.into());
+        }
+    }
+
+    // The deposited stake counts against the cap just like deposited SOL.
+    // Check before we add it to the validator's balance below.
+    lido.check_deposit_cap(accounts.reserve_account, amount)?;
+
+    let validator = lido
+        .validators
+        .get_mut(accounts.validator_vote_account.key)?;
+    if validator.entry.stake_seeds.end <= validator.entry.stake_seeds.begin {
+        msg!("Can only deposit stake if the validator has a stake account to merge into.");
+        return Err(LidoError::InvalidStakeAccount.into());
+    }
+    Lido::check_stake_account(
+        program_id,
+        accounts.lido.key,
+        validator,
+        validator.entry.stake_seeds.begin,
+        accounts.stake_account_merge_into,
+        VALIDATOR_STAKE_ACCOUNT,
+    )?;
+
+    // Take over the stake account. The stake program checks that the user is
+    // the current staker and withdrawer.
+    for stake_authorize in &[StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        invoke(
+            &stake_program::instruction::authorize(
+                accounts.stake_account.key,
+                accounts.user.key,
+                accounts.stake_authority.key,
+                *stake_authorize,
+                None,
+            ),
+            &[
+                accounts.stake_account.clone(),
+                accounts.sysvar_clock.clone(),
+                accounts.user.clone(),
+                accounts.stake_program.clone(),
+            ],
+        )?;
+    }
+
+    // The stake program checks that the two accounts can be merged, it fails
+    // if the account we merge into is not fully active.
+    msg!(
+        "Merging into existing stake account at seed {} ...",
+        validator.entry.stake_seeds.begin
+    );
+    let merge_instructions = stake_program::instruction::merge(
+        accounts.stake_account_merge_into.key,
+        accounts.stake_account.key,
+        accounts.stake_authority.key,
+    );
+    // For some reason, `merge` returns a `Vec`, but when we look at the
+    // implementation, we can see that it always returns a single instruction.
+    assert_eq!(merge_instructions.len(), 1);
+    let merge_instruction = &merge_instructions[0];
+
+    invoke_signed(
+        merge_instruction,
+        &[
+            accounts.stake_account_merge_into.clone(),
+            accounts.stake_account.clone(),
+            accounts.sysvar_clock.clone(),
+            accounts.stake_history.clone(),
+            accounts.stake_authority.clone(),
+            accounts.stake_program.clone(),
+        ],
+        &[&[
+            accounts.lido.key.as_ref(),
+            STAKE_AUTHORITY,
+            &[lido.stake_authority_bump_seed],
+        ]],
+    )?;
+
+    // Record the deposited stake as ours, otherwise `WithdrawInactiveStake`
+    // would treat it as a donation.
+    validator.entry.stake_accounts_balance = (validator.entry.stake_accounts_balance + amount)?;
+
+    let st_sol_amount = lido.exchange_rate.exchange_sol(amount)?;
+
+    mint_st_sol_to(
+        &lido,
+        accounts.lido.key,
+        accounts.spl_token,
+        accounts.st_sol_mint,
+        accounts.mint_authority,
+        accounts.recipient,
+        st_sol_amount,
+    )?;
+
+    // Explain what we did in the logs, because block explorers can be an
+    // inscrutable mess of accounts, especially without special parsers for
+    // Solido transactions. With the logs, we can still identify what happened.
+    msg!(
+        "Solido: Deposited stake account with {}, minted {} in return.",
+        amount,
+        st_sol_amount
+    );
+
+    lido.metrics.deposit_amount.observe(amount)?;
+    lido.save(accounts.lido)
+}
+
 pub fn process_stake_deposit(
     program_id: &Pubkey,
     amount: Lamports,
@@ -210,28 +429,57 @@ pub fn process_stake_deposit(
         return Err(LidoError::StakeToInactiveValidator.into());
     }
 
-    // Confirm that there is no other active validator with a lower balance that
-    // we could stake to. This alone is not sufficient to guarantee a uniform
-    // stake balance, but it limits the power that maintainers have to disturb
-    // the balance. More importantly, it ensures that when two maintainers create
-    // the same StakeDeposit transaction, only one of them succeeds.
+    if validator.entry.weight == 0 {
+        msg!(
+            "Validator {} has weight 0, new deposits are not allowed",
+            validator.pubkey
+        );
+        return Err(LidoError::StakeToInactiveValidator.into());
+    }
+
+    let max_stake_per_validator = lido.get_max_stake_per_validator();
+    if let Some(max_stake) = max_stake_per_validator {
+        let stake_after = (validator.entry.effective_stake_balance() + amount)?;
+        if stake_after > max_stake {
+            msg!(
+                "Staking {} with {} would bring its stake to {}, above the maximum of {}.",
+                amount,
+                validator.pubkey,
+                stake_after,
+                max_stake,
+            );
+            return Err(LidoError::MaxStakePerValidatorExceeded.into());
+        }
+    }
+
+    // Confirm that there is no other active validator with a lower balance,
+    // relative to its weight, that we could stake to. Validators that have no
+    // room left below the maximum stake are not candidates. This alone is not
+    // sufficient to guarantee a balanced stake distribution, but it limits the
+    // power that maintainers have to disturb the balance. More importantly, it
+    // ensures that when two maintainers create the same StakeDeposit
+    // transaction, only one of them succeeds.
     let minimum_stake_validator = lido
         .validators
         .iter_active_entries()
-        .min_by_key(|pair| pair.entry.effective_stake_balance())
+        .filter(|pair| {
+            receives_stake(&pair.entry) && has_room_for_stake(&pair.entry, max_stake_per_validator)
+        })
+        .min_by(|a, b| cmp_stake_per_weight(&a.entry, &b.entry))
         .ok_or(LidoError::NoActiveValidators)?;
 
     // Note that we compare balances, not keys, because the minimum might not be unique.
-    if validator.entry.effective_stake_balance()
-        > minimum_stake_validator.entry.effective_stake_balance()
-    {
+    if cmp_stake_per_weight(&validator.entry, &minimum_stake_validator.entry) == Ordering::Greater {
         msg!(
-            "Refusing to stake with {}, who has {} stake, \
-            because {} has less stake: {}. Stake there instead.",
+            "Refusing to stake with {}, who has {} stake and weight {}, \
+            because {} has less stake relative to its weight: {} and weight {}. \
+            Stake there instead.",
             validator.pubkey,
             validator.entry.effective_stake_balance(),
+            validator.entry.weight,
             minimum_stake_validator.pubkey,
             minimum_stake_validator.entry.effective_stake_balance(),
+            minimum_stake_validator.entry.weight,
         );
         return Err(LidoError::ValidatorWithLessStakeExists.into());
     }
@@ -407,10 +655,21 @@ pub fn process_unstake(
     raw_accounts: &[AccountInfo],
 ) -> ProgramResult {
     let accounts = UnstakeAccountsInfo::try_from_slice(raw_accounts)?;
-    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
     lido.check_maintainer(accounts.maintainer)?;
+    unstake_from_validator(program_id, lido, &accounts, amount)
+}
+
+/// Split `amount` off the validator's oldest stake account into a new unstake
+/// account, and deactivate it. Shared by `Unstake` and `Rebalance`.
+fn unstake_from_validator(
+    program_id: &Pubkey,
+    mut lido: Lido,
+    accounts: &UnstakeAccountsInfo,
+    amount: Lamports,
+) -> ProgramResult {
     lido.check_stake_authority(program_id, accounts.lido.key, accounts.stake_authority)?;
-    let destination_bump_seed = check_unstake_accounts(program_id, &lido, &accounts)?;
+    let destination_bump_seed = check_unstake_accounts(program_id, &lido, accounts)?;
 
     let validator = lido.validators.get(accounts.validator_vote_account.key)?;
 
@@ -514,6 +773,83 @@ pub fn process_unstake(
     lido.save(accounts.lido)
 }
 
+/// Unstakes from an active validator that is above its target balance by
+/// more than the rebalance threshold. Caller must be a maintainer.
+pub fn process_rebalance(
+    program_id: &Pubkey,
+    amount: Lamports,
+    superminority: &[Pubkey],
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = RebalanceAccountsInfo::try_from_slice(raw_accounts)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_maintainer(accounts.maintainer)?;
+    lido.check_reserve_account(program_id, accounts.lido.key, accounts.reserve_account)?;
+
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+    // SOL that we owe to withdraw tickets is not going to be staked, so it
+    // does not count towards the targets, like in `StakeDeposit`.
+    let undelegated_lamports = Lamports(
+        get_reserve_available_balance(&rent, accounts.reserve_account)?
+            .0
+            .saturating_sub(lido.withdraw_queue.get_outstanding().0),
+    );
+    let targets = get_target_balance(
+        undelegated_lamports,
+        &lido.validators,
+        lido.get_max_stake_per_validator(),
+        Some(superminority),
+    )?;
+
+    let validator_index = lido
+        .validators
+        .entries
+        .iter()
+        .position(|v| &v.pubkey == accounts.validator_vote_account.key)
+        .ok_or(LidoError::InvalidAccountMember)?;
+    let validator = &lido.validators.entries[validator_index];
+    let target = targets[validator_index];
+
+    match get_rebalance_amount(&validator.entry, target) {
+        None => {
+            msg!(
+                "Validator {} has {} of stake, its target is {}, which is within the threshold.",
+                validator.pubkey,
+                validator.entry.effective_stake_balance(),
+                target
+            );
+            return Err(LidoError::ValidatorNotOverweight.into());
+        }
+        Some(excess) if amount > excess => {
+            msg!(
+                "Unstaking {} would bring validator {} below its target, can unstake at most {}.",
+                amount,
+                validator.pubkey,
+                excess
+            );
+            return Err(LidoError::InvalidAmount.into());
+        }
+        Some(..) => {}
+    }
+
+    unstake_from_validator(
+        program_id,
+        lido,
+        &UnstakeAccountsInfo {
+            lido: accounts.lido,
+            maintainer: accounts.maintainer,
+            validator_vote_account: accounts.validator_vote_account,
+            source_stake_account: accounts.source_stake_account,
+            destination_unstake_account: accounts.destination_unstake_account,
+            stake_authority: accounts.stake_authority,
+            sysvar_clock: accounts.sysvar_clock,
+            system_program: accounts.system_program,
+            stake_program: accounts.stake_program,
+        },
+        amount,
+    )
+}
+
 pub fn process_update_exchange_rate(
     program_id: &Pubkey,
     raw_accounts: &[AccountInfo],
@@ -538,9 +874,561 @@ pub fn process_update_exchange_rate(
     lido.exchange_rate.sol_balance = lido.get_sol_balance(&rent, accounts.reserve)?;
     lido.exchange_rate.st_sol_supply = lido.get_st_sol_supply(accounts.st_sol_mint)?;
 
+    // Fees are only distributed after the exchange rate is updated, so all
+    // rewards of this epoch are split with the new distribution.
+    if lido.activate_pending_reward_distribution(clock.epoch) {
+        msg!(
+            "Activated the scheduled reward distribution in epoch {}.",
+            clock.epoch
+        );
+    }
+
     lido.save(accounts.lido)
 }
 
+pub fn process_publish_exchange_rate(
+    program_id: &Pubkey,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = PublishExchangeRateInfo::try_from_slice(raw_accounts)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_maintainer(accounts.maintainer)?;
+
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+    lido.check_exchange_rate_last_epoch(&clock, "PublishExchangeRate")?;
+
+    // We don't store the bump seed in the Solido state for this account, so we
+    // have to search for it. This is more expensive than `create_program_address`,
+    // but publishing is infrequent, and this instruction does little else.
+    let (oracle_address, oracle_bump_seed) =
+        ExchangeRateOracle::find_address(program_id, accounts.lido.key);
+    if &oracle_address != accounts.exchange_rate_oracle.key {
+        msg!(
+            "Invalid exchange rate oracle, expected {} but got {}.",
+            oracle_address,
+            accounts.exchange_rate_oracle.key
+        );
+        return Err(LidoError::InvalidExchangeRateOracle.into());
+    }
+
+    // The first publication creates the account, funded by the maintainer.
+    if accounts.exchange_rate_oracle.owner != program_id {
+        let oracle_bump_seed = [oracle_bump_seed];
+        create_program_derived_account(
+            program_id,
+            &rent,
+            accounts.maintainer,
+            accounts.exchange_rate_oracle,
+            accounts.system_program,
+            &[
+                accounts.lido.key.as_ref(),
+                EXCHANGE_RATE_ORACLE,
+                &oracle_bump_seed[..],
+            ],
+            EXCHANGE_RATE_ORACLE_SIZE,
+        )?;
+    }
+
+    let oracle = ExchangeRateOracle {
+        solido: *accounts.lido.key,
+        exchange_rate: lido.exchange_rate.clone(),
+        publisher: *accounts.maintainer.key,
+        published_slot: clock.slot,
+        published_unix_timestamp: clock.unix_timestamp,
+    };
+    BorshSerialize::serialize(
+        &oracle,
+        &mut *accounts.exchange_rate_oracle.data.borrow_mut(),
+    )?;
+    Ok(())
+}
+
+/// Create a program-owned account at a program-derived address, funded by `payer`.
+fn create_program_derived_account<'a>(
+    program_id: &Pubkey,
+    rent: &Rent,
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    seeds: &[&[u8]],
+    size: usize,
+) -> ProgramResult {
+    // Like in `create_account_even_if_funded`, we don't use
+    // `system_instruction::create_account`, so that somebody who transfers
+    // a small amount to the address cannot prevent its creation.
+    let minimum_balance = rent.minimum_balance(size);
+    let fund_amount = minimum_balance.saturating_sub(account.lamports());
+    if fund_amount > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, fund_amount),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(account.key, size as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    Ok(())
+}
+
+pub fn process_acquire_maintainer_lease(
+    program_id: &Pubkey,
+    lease_duration_slots: u64,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = AcquireMaintainerLeaseInfo::try_from_slice(raw_accounts)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_maintainer(accounts.maintainer)?;
+
+    if lease_duration_slots == 0 || lease_duration_slots > MAXIMUM_MAINTAINER_LEASE_SLOTS {
+        msg!(
+            "Lease duration must be between 1 and {} slots, but got {}.",
+            MAXIMUM_MAINTAINER_LEASE_SLOTS,
+            lease_duration_slots
+        );
+        return Err(LidoError::InvalidMaintainerLeaseDuration.into());
+    }
+
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+
+    let (lease_address, lease_bump_seed) =
+        MaintainerLease::find_address(program_id, accounts.lido.key);
+    if &lease_address != accounts.maintainer_lease.key {
+        msg!(
+            "Invalid maintainer lease, expected {} but got {}.",
+            lease_address,
+            accounts.maintainer_lease.key
+        );
+        return Err(LidoError::InvalidMaintainerLease.into());
+    }
+
+    // The first lease creates the account, funded by the maintainer.
+    let acquired_slot = if accounts.maintainer_lease.owner != program_id {
+        let lease_bump_seed = [lease_bump_seed];
+        create_program_derived_account(
+            program_id,
+            &rent,
+            accounts.maintainer,
+            accounts.maintainer_lease,
+            accounts.system_program,
+            &[
+                accounts.lido.key.as_ref(),
+                MAINTAINER_LEASE,
+                &lease_bump_seed[..],
+            ],
+            MAINTAINER_LEASE_SIZE,
+        )?;
+        clock.slot
+    } else {
+        let lease = MaintainerLease::try_from_slice(&accounts.maintainer_lease.data.borrow())?;
+        if &lease.holder == accounts.maintainer.key {
+            // A renewal by the current holder.
+            lease.acquired_slot
+        } else if lease.is_valid_at(clock.slot) {
+            msg!(
+                "Maintainer {} holds the lease until slot {}, current slot is {}.",
+                lease.holder,
+                lease.expires_slot,
+                clock.slot
+            );
+            return Err(LidoError::MaintainerLeaseHeldByOther.into());
+        } else {
+            clock.slot
+        }
+    };
+
+    let lease = MaintainerLease {
+        solido: *accounts.lido.key,
+        holder: *accounts.maintainer.key,
+        acquired_slot,
+        expires_slot: clock.slot.saturating_add(lease_duration_slots),
+    };
+    BorshSerialize::serialize(&lease, &mut *accounts.maintainer_lease.data.borrow_mut())?;
+    Ok(())
+}
+
+pub fn process_create_depositor_allowlist(
+    program_id: &Pubkey,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = CreateDepositorAllowlistInfo::try_from_slice(raw_accounts)?;
+    // Only to confirm that this is a Solido instance.
+    deserialize_lido(program_id, accounts.lido)?;
+
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+
+    let (allowlist_address, allowlist_bump_seed) =
+        DepositorAllowlist::find_address(program_id, accounts.lido.key);
+    if &allowlist_address != accounts.depositor_allowlist.key {
+        msg!(
+            "Invalid depositor allowlist, expected {} but got {}.",
+            allowlist_address,
+            accounts.depositor_allowlist.key
+        );
+        return Err(LidoError::InvalidDepositorAllowlist.into());
+    }
+    if accounts.depositor_allowlist.owner == program_id {
+        msg!(
+            "Depositor allowlist {} already exists.",
+            accounts.depositor_allowlist.key
+        );
+        return Err(LidoError::AlreadyInUse.into());
+    }
+
+    // The capacity is fixed, so anybody can create the empty list without
+    // affecting what the manager can do with it.
+    let allowlist_bump_seed = [allowlist_bump_seed];
+    create_program_derived_account(
+        program_id,
+        &rent,
+        accounts.payer,
+        accounts.depositor_allowlist,
+        accounts.system_program,
+        &[
+            accounts.lido.key.as_ref(),
+            DEPOSITOR_ALLOWLIST,
+            &allowlist_bump_seed[..],
+        ],
+        DepositorAllowlist::calculate_size(MAXIMUM_ALLOWED_DEPOSITORS),
+    )?;
+
+    let allowlist = DepositorAllowlist {
+        solido: *accounts.lido.key,
+        depositors: Depositors::new(MAXIMUM_ALLOWED_DEPOSITORS),
+    };
+    BorshSerialize::serialize(
+        &allowlist,
+        &mut *accounts.depositor_allowlist.data.borrow_mut(),
+    )?;
+    Ok(())
+}
+
+/// Deserialize a withdraw ticket of the Solido instance, and confirm that it is at its derived address.
+fn deserialize_withdraw_ticket(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    withdraw_ticket: &AccountInfo,
+) -> Result<WithdrawTicket, ProgramError> {
+    if withdraw_ticket.owner != program_id {
+        msg!(
+            "Withdraw ticket {} is owned by {}, not by the Solido program.",
+            withdraw_ticket.key,
+            withdraw_ticket.owner
+        );
+        return Err(LidoError::InvalidWithdrawTicket.into());
+    }
+    let ticket = WithdrawTicket::try_from_slice(&withdraw_ticket.data.borrow())?;
+    let (ticket_address, _) =
+        WithdrawTicket::find_address(program_id, solido_address, ticket.ticket_id);
+    if &ticket.solido != solido_address || &ticket_address != withdraw_ticket.key {
+        msg!(
+            "Invalid withdraw ticket, expected {} for ticket {} but got {}.",
+            ticket_address,
+            ticket.ticket_id,
+            withdraw_ticket.key
+        );
+        return Err(LidoError::InvalidWithdrawTicket.into());
+    }
+    Ok(ticket)
+}
+
+pub fn process_create_withdraw_ticket(
+    program_id: &Pubkey,
+    amount: StLamports,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = CreateWithdrawTicketAccountsInfo::try_from_slice(raw_accounts)?;
+
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_not_paused()?;
+    let clock = Clock::from_account_info(accounts.sysvar_clock)?;
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+    lido.check_exchange_rate_last_epoch(&clock, "CreateWithdrawTicket")?;
+
+    let ticket_id = lido.withdraw_queue.next_ticket_id;
+    let (ticket_address, ticket_bump_seed) =
+        WithdrawTicket::find_address(program_id, accounts.lido.key, ticket_id);
+    if &ticket_address != accounts.withdraw_ticket.key {
+        msg!(
+            "Invalid withdraw ticket, expected {} for ticket {} but got {}.",
+            ticket_address,
+            ticket_id,
+            accounts.withdraw_ticket.key
+        );
+        return Err(LidoError::InvalidWithdrawTicket.into());
+    }
+
+    let sol_amount = lido.exchange_rate.exchange_st_sol(amount)?;
+    if sol_amount < MINIMUM_WITHDRAW_TICKET_AMOUNT {
+        msg!(
+            "Withdraw ticket would be owed {}, less than the minimum {}.",
+            sol_amount,
+            MINIMUM_WITHDRAW_TICKET_AMOUNT
+        );
+        return Err(LidoError::InvalidAmount.into());
+    }
+
+    burn_st_sol(
+        &lido,
+        &BurnStSolAccounts {
+            st_sol_account_owner: accounts.st_sol_account_owner,
+            st_sol_account: accounts.st_sol_account,
+            st_sol_mint: accounts.st_sol_mint,
+            spl_token: accounts.spl_token,
+        },
+        amount,
+    )?;
+
+    let ticket_id_bytes = ticket_id.to_le_bytes();
+    let ticket_bump_seed = [ticket_bump_seed];
+    create_program_derived_account(
+        program_id,
+        &rent,
+        accounts.st_sol_account_owner,
+        accounts.withdraw_ticket,
+        accounts.system_program,
+        &[
+            accounts.lido.key.as_ref(),
+            WITHDRAW_TICKET,
+            &ticket_id_bytes[..],
+            &ticket_bump_seed[..],
+        ],
+        WITHDRAW_TICKET_SIZE,
+    )?;
+
+    let ticket = WithdrawTicket {
+        solido: *accounts.lido.key,
+        owner: *accounts.st_sol_account_owner.key,
+        ticket_id,
+        st_sol_burned: amount,
+        amount: sol_amount,
+        queue_position: lido.withdraw_queue.requested_total,
+        created_epoch: clock.epoch,
+        is_funded: false,
+        reserved: 0,
+    };
+    BorshSerialize::serialize(&ticket, &mut *accounts.withdraw_ticket.data.borrow_mut())?;
+
+    lido.withdraw_queue.next_ticket_id += 1;
+    lido.withdraw_queue.requested_total = (lido.withdraw_queue.requested_total + sol_amount)?;
+    lido.metrics.observe_withdrawal(amount, sol_amount)?;
+
+    msg!(
+        "Solido: Created withdraw ticket {} for {}, owed {}.",
+        ticket_id,
+        amount,
+        sol_amount
+    );
+
+    lido.save(accounts.lido)
+}
+
+pub fn process_fund_withdraw_ticket(
+    program_id: &Pubkey,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = FundWithdrawTicketAccountsInfo::try_from_slice(raw_accounts)?;
+    let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_maintainer(accounts.maintainer)?;
+    lido.check_reserve_account(program_id, accounts.lido.key, accounts.reserve_account)?;
+
+    let mut ticket =
+        deserialize_withdraw_ticket(program_id, accounts.lido.key, accounts.withdraw_ticket)?;
+
+    // Tickets are funded in the order in which they were created.
+    if ticket.ticket_id != lido.withdraw_queue.next_fund_ticket_id {
+        msg!(
+            "Expected withdraw ticket {} to be funded next, but got ticket {}.",
+            lido.withdraw_queue.next_fund_ticket_id,
+            ticket.ticket_id
+        );
+        return Err(LidoError::InvalidWithdrawTicket.into());
+    }
+
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+    let available_reserve_amount = get_reserve_available_balance(&rent, accounts.reserve_account)?;
+    if ticket.amount > available_reserve_amount {
+        msg!(
+            "Withdraw ticket {} needs {}, but the reserve has only {} available.",
+            ticket.ticket_id,
+            ticket.amount,
+            available_reserve_amount
+        );
+        return Err(LidoError::AmountExceedsReserve.into());
+    }
+
+    invoke_signed(
+        &system_instruction::transfer(
+            accounts.reserve_account.key,
+            accounts.withdraw_ticket.key,
+            ticket.amount.0,
+        ),
+        &[
+            accounts.reserve_account.clone(),
+            accounts.withdraw_ticket.clone(),
+            accounts.system_program.clone(),
+        ],
+        &[&[
+            accounts.lido.key.as_ref(),
+            RESERVE_ACCOUNT,
+            &[lido.sol_reserve_account_bump_seed],
+        ]],
+    )?;
+
+    ticket.is_funded = true;
+    BorshSerialize::serialize(&ticket, &mut *accounts.withdraw_ticket.data.borrow_mut())?;
+
+    lido.withdraw_queue.next_fund_ticket_id += 1;
+    lido.withdraw_queue.funded_total = (lido.withdraw_queue.funded_total + ticket.amount)?;
+
+    lido.save(accounts.lido)
+}
+
+pub fn process_claim_withdraw_ticket(
+    program_id: &Pubkey,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = ClaimWithdrawTicketAccountsInfo::try_from_slice(raw_accounts)?;
+
+    // We don't modify the Solido instance, but the ticket must belong to one.
+    deserialize_lido(program_id, accounts.lido)?;
+    let ticket =
+        deserialize_withdraw_ticket(program_id, accounts.lido.key, accounts.withdraw_ticket)?;
+
+    if &ticket.owner != accounts.ticket_owner.key {
+        msg!(
+            "Withdraw ticket {} is owned by {}, but provided owner is {}.",
+            ticket.ticket_id,
+            ticket.owner,
+            accounts.ticket_owner.key
+        );
+        return Err(LidoError::InvalidWithdrawTicketOwner.into());
+    }
+    if !ticket.is_funded {
+        msg!("Withdraw ticket {} is not funded yet.", ticket.ticket_id);
+        return Err(LidoError::WithdrawTicketNotFunded.into());
+    }
+
+    // Close the ticket account. The recipient gets the funded SOL, and the
+    // rent that the owner paid when creating the ticket.
+    let ticket_lamports = accounts.withdraw_ticket.lamports();
+    let recipient_lamports = accounts
+        .recipient
+        .lamports()
+        .checked_add(ticket_lamports)
+        .ok_or(LidoError::CalculationFailure)?;
+    **accounts.recipient.lamports.borrow_mut() = recipient_lamports;
+    **accounts.withdraw_ticket.lamports.borrow_mut() = 0;
+    accounts.withdraw_ticket.data.borrow_mut().fill(0);
+
+    msg!(
+        "Solido: Claimed withdraw ticket {} for {}.",
+        ticket.ticket_id,
+        ticket.amount
+    );
+
+    Ok(())
+}
+
+pub fn process_sweep_to_reserve(
+    program_id: &Pubkey,
+    raw_accounts: &[AccountInfo],
+) -> ProgramResult {
+    let accounts = SweepToReserveAccountsInfo::try_from_slice(raw_accounts)?;
+    let lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_maintainer(accounts.maintainer)?;
+    lido.check_reserve_account(program_id, accounts.lido.key, accounts.reserve_account)?;
+
+    let rent = Rent::from_account_info(accounts.sysvar_rent)?;
+    let sweep_account = accounts.sweep_account;
+    let amount = Lamports(
+        sweep_account
+            .lamports()
+            .saturating_sub(rent.minimum_balance(sweep_account.data_len())),
+    );
+
+    // The authorities are system accounts without data, we can only move SOL
+    // out of them through the system program, signing with their seeds.
+    let authorities = [
+        (STAKE_AUTHORITY, lido.stake_authority_bump_seed),
+        (MINT_AUTHORITY, lido.mint_authority_bump_seed),
+        (
+            REWARDS_WITHDRAW_AUTHORITY,
+            lido.rewards_withdraw_authority_bump_seed,
+        ),
+    ];
+    let authority = authorities.iter().find(|(seed, bump_seed)| {
+        Pubkey::create_program_address(
+            &[accounts.lido.key.as_ref(), seed, &[*bump_seed]],
+            program_id,
+        ) == Ok(*sweep_account.key)
+    });
+
+    // The oracle and the lease are owned by the program, so we can move SOL
+    // out of them directly.
+    let is_program_account = sweep_account.owner == program_id
+        && (sweep_account.key
+            == &ExchangeRateOracle::find_address(program_id, accounts.lido.key).0
+            || sweep_account.key
+                == &MaintainerLease::find_address(program_id, accounts.lido.key).0);
+
+    if authority.is_none() && !is_program_account {
+        msg!(
+            "Account {} is not an auxiliary account of the Solido instance that can be swept.",
+            sweep_account.key
+        );
+        return Err(LidoError::InvalidSweepAccount.into());
+    }
+    if amount == Lamports(0) {
+        msg!(
+            "Account {} holds no SOL above its rent-exempt balance.",
+            sweep_account.key
+        );
+        return Err(LidoError::NothingToSweep.into());
+    }
+
+    match authority {
+        Some((seed, bump_seed)) => invoke_signed(
+            &system_instruction::transfer(
+                sweep_account.key,
+                accounts.reserve_account.key,
+                amount.0,
+            ),
+            &[
+                sweep_account.clone(),
+                accounts.reserve_account.clone(),
+                accounts.system_program.clone(),
+            ],
+            &[&[accounts.lido.key.as_ref(), seed, &[*bump_seed]]],
+        )?,
+        None => {
+            let reserve_lamports = accounts
+                .reserve_account
+                .lamports()
+                .checked_add(amount.0)
+                .ok_or(LidoError::CalculationFailure)?;
+            **accounts.reserve_account.lamports.borrow_mut() = reserve_lamports;
+            **sweep_account.lamports.borrow_mut() -= amount.0;
+        }
+    }
+
+    msg!(
+        "Solido: Swept {} from {} to the reserve.",
+        amount,
+        sweep_account.key
+    );
+
+    Ok(())
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum StakeType {
     Stake,
@@ -900,6 +1788,7 @@ pub fn process_withdraw(
 ) -> ProgramResult {
     let accounts = WithdrawAccountsInfo::try_from_slice(raw_accounts)?;
     let mut lido = deserialize_lido(program_id, accounts.lido)?;
+    lido.check_not_paused()?;
     let clock = Clock::from_account_info(accounts.sysvar_clock)?;
     lido.check_exchange_rate_last_epoch(&clock, "Withdraw")?;
 
@@ -953,19 +1842,7 @@ pub fn process_withdraw(
         .get_mut(accounts.validator_vote_account.key)?;
 
     let source_balance = Lamports(accounts.source_stake_account.lamports());
-
-    // Limit the amount to withdraw to 10% of the stake account's balance + a
-    // small constant. The 10% caps the imbalance that a withdrawal can create
-    // at large balances, and in that case the constant is negligible, but the
-    // constant does ensure that we can reach the minimum in a finite number of
-    // withdrawals.
-    let max_withdraw_amount = (source_balance
-        * Rational {
-            numerator: 1,
-            denominator: 10,
-        })
-    .expect("Multiplying with 0.1 does not overflow or divide by zero.")
-    .add(Lamports(10 * LAMPORTS_PER_SOL))?;
+    let max_withdraw_amount = get_max_withdraw_amount(source_balance)?;
 
     if sol_to_withdraw > max_withdraw_amount {
         msg!(
@@ -991,7 +1868,16 @@ pub fn process_withdraw(
         (provided_validator.entry.stake_accounts_balance - sol_to_withdraw)?;
 
     // Burn stSol tokens
-    burn_st_sol(&lido, &accounts, amount)?;
+    burn_st_sol(
+        &lido,
+        &BurnStSolAccounts {
+            st_sol_account_owner: accounts.st_sol_account_owner,
+            st_sol_account: accounts.st_sol_account,
+            st_sol_mint: accounts.st_sol_mint,
+            spl_token: accounts.spl_token,
+        },
+        amount,
+    )?;
 
     // Update withdrawal metrics.
     lido.metrics.observe_withdrawal(amount, sol_to_withdraw)?;
@@ -1049,14 +1935,60 @@ pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> P
         LidoInstruction::CollectValidatorFee => process_collect_validator_fee(program_id, accounts),
         LidoInstruction::Withdraw { amount } => process_withdraw(program_id, amount, accounts),
         LidoInstruction::ClaimValidatorFee => process_claim_validator_fee(program_id, accounts),
-        LidoInstruction::ChangeRewardDistribution {
-            new_reward_distribution,
-        } => process_change_reward_distribution(program_id, new_reward_distribution, accounts),
+        LidoInstruction::ChangeFeeRecipients => process_change_fee_recipients(program_id, accounts),
         LidoInstruction::AddValidator => process_add_validator(program_id, accounts),
         LidoInstruction::RemoveValidator => process_remove_validator(program_id, accounts),
         LidoInstruction::DeactivateValidator => process_deactivate_validator(program_id, accounts),
         LidoInstruction::AddMaintainer => process_add_maintainer(program_id, accounts),
         LidoInstruction::RemoveMaintainer => process_remove_maintainer(program_id, accounts),
         LidoInstruction::MergeStake => process_merge_stake(program_id, accounts),
+        LidoInstruction::PublishExchangeRate => process_publish_exchange_rate(program_id, accounts),
+        LidoInstruction::SetValidatorWeight { weight } => {
+            process_set_validator_weight(program_id, weight, accounts)
+        }
+        LidoInstruction::SetMaxStakePerValidator {
+            max_stake_per_validator,
+        } => process_set_max_stake_per_validator(program_id, max_stake_per_validator, accounts),
+        LidoInstruction::AcquireMaintainerLease {
+            lease_duration_slots,
+        } => process_acquire_maintainer_lease(program_id, lease_duration_slots, accounts),
+        LidoInstruction::DepositStake => process_deposit_stake(program_id, accounts),
+        LidoInstruction::Rebalance {
+            amount,
+            superminority,
+        } => process_rebalance(program_id, amount, &superminority, accounts),
+        LidoInstruction::CreateWithdrawTicket { amount } => {
+            process_create_withdraw_ticket(program_id, amount, accounts)
+        }
+        LidoInstruction::FundWithdrawTicket => process_fund_withdraw_ticket(program_id, accounts),
+        LidoInstruction::ClaimWithdrawTicket => process_claim_withdraw_ticket(program_id, accounts),
+        LidoInstruction::SetPaused { paused } => process_set_paused(program_id, paused, accounts),
+        LidoInstruction::SetMaxTotalLamports { max_total_lamports } => {
+            process_set_max_total_lamports(program_id, max_total_lamports, accounts)
+        }
+        LidoInstruction::SweepToReserve => process_sweep_to_reserve(program_id, accounts),
+        LidoInstruction::ScheduleRewardDistribution {
+            new_reward_distribution,
+            delay_epochs,
+        } => process_schedule_reward_distribution(
+            program_id,
+            new_reward_distribution,
+            delay_epochs,
+            accounts,
+        ),
+        LidoInstruction::CreateDepositorAllowlist => {
+            process_create_depositor_allowlist(program_id, accounts)
+        }
+        LidoInstruction::AddAllowedDepositor => process_add_allowed_depositor(program_id, accounts),
+        LidoInstruction::RemoveAllowedDepositor => {
+            process_remove_allowed_depositor(program_id, accounts)
+        }
+        LidoInstruction::SetDepositorAllowlistEnabled { enabled } => {
+            process_set_depositor_allowlist_enabled(program_id, enabled, accounts)
+        }
+        LidoInstruction::ReplaceValidatorVoteAccount => {
+            process_replace_validator_vote_account(program_id, accounts)
+        }
+        LidoInstruction::MigrateStateToV1 => process_migrate_state_to_v1(program_id, accounts),
     }
 }
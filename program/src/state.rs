@@ -9,7 +9,7 @@ use serde::Serialize;
 
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::borsh::get_instance_packed_len;
-use solana_program::clock::Clock;
+use solana_program::clock::{Clock, Slot, UnixTimestamp};
 use solana_program::{
     account_info::AccountInfo, clock::Epoch, entrypoint::ProgramResult, msg,
     program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, rent::Rent, sysvar::Sysvar,
@@ -24,17 +24,30 @@ use crate::token::{self, Lamports, Rational, StLamports};
 use crate::util::serialize_b58;
 use crate::{
     account_map::{AccountMap, AccountSet, EntryConstantSize, PubkeyAndEntry},
-    MINIMUM_STAKE_ACCOUNT_BALANCE, MINT_AUTHORITY, RESERVE_ACCOUNT, STAKE_AUTHORITY,
+    DEPOSITOR_ALLOWLIST, EXCHANGE_RATE_ORACLE, MAINTAINER_LEASE, MINIMUM_STAKE_ACCOUNT_BALANCE,
+    MINT_AUTHORITY, RESERVE_ACCOUNT, STAKE_AUTHORITY, WITHDRAW_TICKET,
 };
 use crate::{REWARDS_WITHDRAW_AUTHORITY, VALIDATOR_STAKE_ACCOUNT, VALIDATOR_UNSTAKE_ACCOUNT};
 
-pub const LIDO_VERSION: u8 = 0;
+pub const LIDO_VERSION: u8 = 1;
 
 /// Size of a serialized `Lido` struct excluding validators and maintainers.
 ///
 /// To update this, run the tests and replace the value here with the test output.
-pub const LIDO_CONSTANT_SIZE: usize = 357;
-pub const VALIDATOR_CONSTANT_SIZE: usize = 89;
+pub const LIDO_CONSTANT_SIZE: usize = 463;
+pub const VALIDATOR_CONSTANT_SIZE: usize = 93;
+
+/// The weight of a newly added validator, see [`Validator::weight`].
+pub const DEFAULT_VALIDATOR_WEIGHT: u32 = 100;
+
+/// Size of a serialized `ExchangeRateOracle` struct.
+pub const EXCHANGE_RATE_ORACLE_SIZE: usize = 104;
+
+/// Size of a serialized `MaintainerLease` struct.
+pub const MAINTAINER_LEASE_SIZE: usize = 80;
+
+/// Size of a serialized `WithdrawTicket` struct.
+pub const WITHDRAW_TICKET_SIZE: usize = 113;
 
 pub type Validators = AccountMap<Validator>;
 
@@ -48,6 +61,7 @@ impl Validators {
     }
 }
 pub type Maintainers = AccountSet;
+pub type Depositors = AccountSet;
 
 impl EntryConstantSize for Validator {
     const SIZE: usize = VALIDATOR_CONSTANT_SIZE;
@@ -181,6 +195,235 @@ impl ExchangeRate {
     }
 }
 
+/// The exchange rate, as last published by a maintainer.
+///
+/// This is stored in a program-derived account of the Solido instance, with seed
+/// `EXCHANGE_RATE_ORACLE`, and written by `PublishExchangeRate`. The exchange
+/// rate itself is the same as `Lido::exchange_rate`, but consumers that only
+/// need the rate can read this small account rather than the full Solido state,
+/// and `published_slot` tells them how recently a maintainer confirmed it.
+#[repr(C)]
+#[derive(
+    Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
+)]
+pub struct ExchangeRateOracle {
+    /// The Solido instance that this oracle belongs to.
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido: Pubkey,
+
+    /// The exchange rate at the time of publication.
+    pub exchange_rate: ExchangeRate,
+
+    /// The maintainer that published the rate.
+    #[serde(serialize_with = "serialize_b58")]
+    pub publisher: Pubkey,
+
+    /// Slot in which the rate was published.
+    pub published_slot: Slot,
+
+    /// Unix timestamp of the clock sysvar when the rate was published.
+    pub published_unix_timestamp: UnixTimestamp,
+}
+
+impl ExchangeRateOracle {
+    /// Return the address of the oracle account, and its bump seed.
+    pub fn find_address(program_id: &Pubkey, solido_address: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&solido_address.to_bytes()[..], EXCHANGE_RATE_ORACLE],
+            program_id,
+        )
+    }
+}
+
+/// A lease that gives one maintainer the exclusive right to perform maintenance.
+///
+/// This is stored in a program-derived account of the Solido instance, with seed
+/// `MAINTAINER_LEASE`, and written by `AcquireMaintainerLease`. The program does
+/// not enforce the lease for other instructions; it only makes sure that at most
+/// one maintainer holds it at a time, so redundant maintainer daemons can agree
+/// on which one of them submits transactions, instead of racing each other.
+#[repr(C)]
+#[derive(
+    Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
+)]
+pub struct MaintainerLease {
+    /// The Solido instance that this lease belongs to.
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido: Pubkey,
+
+    /// The maintainer that holds the lease.
+    #[serde(serialize_with = "serialize_b58")]
+    pub holder: Pubkey,
+
+    /// Slot in which the holder acquired the lease, renewals keep this slot.
+    pub acquired_slot: Slot,
+
+    /// First slot in which the lease is no longer valid.
+    pub expires_slot: Slot,
+}
+
+impl MaintainerLease {
+    /// Return the address of the lease account, and its bump seed.
+    pub fn find_address(program_id: &Pubkey, solido_address: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&solido_address.to_bytes()[..], MAINTAINER_LEASE],
+            program_id,
+        )
+    }
+
+    /// Return whether the lease is held by somebody at the given slot.
+    pub fn is_valid_at(&self, slot: Slot) -> bool {
+        slot < self.expires_slot
+    }
+}
+
+/// The queue of withdraw tickets, for withdrawals too large to serve from one stake account.
+///
+/// Tickets are numbered in the order they were created, and funded in that
+/// same order. The amounts are cumulative, `requested_total - funded_total` is
+/// the SOL that Solido still owes to unfunded tickets.
+#[repr(C)]
+#[derive(
+    Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
+)]
+pub struct WithdrawQueue {
+    /// Id of the next ticket that `CreateWithdrawTicket` creates.
+    pub next_ticket_id: u64,
+
+    /// Id of the next ticket that `FundWithdrawTicket` funds.
+    ///
+    /// Tickets with a lower id are funded, this is at most `next_ticket_id`.
+    pub next_fund_ticket_id: u64,
+
+    /// Total SOL that all tickets created so far are owed.
+    #[serde(rename = "requested_total_lamports")]
+    pub requested_total: Lamports,
+
+    /// Total SOL moved from the reserve into tickets so far.
+    #[serde(rename = "funded_total_lamports")]
+    pub funded_total: Lamports,
+}
+
+impl WithdrawQueue {
+    /// Return the SOL that is owed to tickets, but not yet moved into them.
+    pub fn get_outstanding(&self) -> Lamports {
+        (self.requested_total - self.funded_total)
+            .expect("Tickets are never funded for more than they requested.")
+    }
+}
+
+/// A claim on SOL, for stSOL that was burned with `CreateWithdrawTicket`.
+///
+/// This is stored in a program-derived account of the Solido instance, with
+/// seeds `WITHDRAW_TICKET` and the ticket id. `FundWithdrawTicket` moves the
+/// SOL into the ticket account, after which the owner can claim it with
+/// `ClaimWithdrawTicket`, which closes the account.
+#[repr(C)]
+#[derive(
+    Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
+)]
+pub struct WithdrawTicket {
+    /// The Solido instance that this ticket belongs to.
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido: Pubkey,
+
+    /// The account that can claim the SOL.
+    #[serde(serialize_with = "serialize_b58")]
+    pub owner: Pubkey,
+
+    /// Position of the ticket in the queue.
+    pub ticket_id: u64,
+
+    /// The stSOL that was burned to create the ticket.
+    #[serde(rename = "st_sol_burned_st_lamports")]
+    pub st_sol_burned: StLamports,
+
+    /// The SOL that the ticket is owed, at the exchange rate of its creation.
+    #[serde(rename = "amount_lamports")]
+    pub amount: Lamports,
+
+    /// `WithdrawQueue::requested_total` before this ticket was created.
+    ///
+    /// The ticket can be funded once the queue has funded this much, plus `amount`.
+    #[serde(rename = "queue_position_lamports")]
+    pub queue_position: Lamports,
+
+    /// Epoch in which the ticket was created.
+    pub created_epoch: Epoch,
+
+    /// Whether `FundWithdrawTicket` moved the SOL into the ticket account.
+    pub is_funded: bool,
+
+    /// Reserved for future use, this is zero.
+    pub reserved: u64,
+}
+
+impl WithdrawTicket {
+    /// Return the address of the ticket account with the given id, and its bump seed.
+    pub fn find_address(
+        program_id: &Pubkey,
+        solido_address: &Pubkey,
+        ticket_id: u64,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                &solido_address.to_bytes()[..],
+                WITHDRAW_TICKET,
+                &ticket_id.to_le_bytes()[..],
+            ],
+            program_id,
+        )
+    }
+}
+
+/// The depositors that `Deposit` and `DepositStake` accept, when the allowlist is enabled.
+///
+/// This is stored in a program-derived account of the Solido instance, with seed
+/// `DEPOSITOR_ALLOWLIST`, created by `CreateDepositorAllowlist` with room for
+/// `MAXIMUM_ALLOWED_DEPOSITORS`. The manager adds and removes depositors with
+/// `AddAllowedDepositor` and `RemoveAllowedDepositor`. The list only takes
+/// effect while `Lido::is_depositor_allowlist_enabled` is set.
+#[repr(C)]
+#[derive(
+    Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
+)]
+pub struct DepositorAllowlist {
+    /// The Solido instance that this allowlist belongs to.
+    #[serde(serialize_with = "serialize_b58")]
+    pub solido: Pubkey,
+
+    /// The addresses that are allowed to deposit, the signers of the deposit.
+    pub depositors: Depositors,
+}
+
+impl DepositorAllowlist {
+    /// Return the address of the allowlist account, and its bump seed.
+    pub fn find_address(program_id: &Pubkey, solido_address: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&solido_address.to_bytes()[..], DEPOSITOR_ALLOWLIST],
+            program_id,
+        )
+    }
+
+    /// Return the size of an allowlist account with room for `max_depositors`.
+    pub fn calculate_size(max_depositors: u32) -> usize {
+        let allowlist = DepositorAllowlist {
+            solido: Pubkey::default(),
+            depositors: Depositors::new_fill_default(max_depositors),
+        };
+        get_instance_packed_len(&allowlist).unwrap()
+    }
+
+    /// Return whether `depositor` is on the allowlist.
+    pub fn is_allowed(&self, depositor: &Pubkey) -> bool {
+        self.depositors.get(depositor).is_ok()
+    }
+}
+
+/// The state of a Solido instance.
+///
+/// New fields go at the end, after the fields of the previous version, so a
+/// migration can convert the state in place, see [`crate::migration`].
 #[repr(C)]
 #[derive(
     Clone, Debug, Default, BorshDeserialize, BorshSerialize, BorshSchema, Eq, PartialEq, Serialize,
@@ -229,6 +472,48 @@ pub struct Lido {
     /// operations. These are gated on the signer being present in this set.
     /// In the future we plan to make maintenance operations callable by anybody.
     pub maintainers: Maintainers,
+
+    /// The token program that owns the stSOL mint, SPL token or Token-2022.
+    ///
+    /// All stSOL accounts are owned by this program, and we mint and burn
+    /// stSOL through it.
+    #[serde(serialize_with = "serialize_b58")]
+    pub st_sol_token_program: Pubkey,
+
+    /// A change of `reward_distribution` that the manager scheduled with
+    /// `ScheduleRewardDistribution`, and that takes effect in a later epoch.
+    pub pending_reward_distribution: PendingRewardDistribution,
+
+    /// Maximum stake per validator, or zero if there is no maximum.
+    ///
+    /// `StakeDeposit` does not stake with a validator if that would bring its
+    /// stake above this amount. The manager can change it with
+    /// `SetMaxStakePerValidator`.
+    pub max_stake_per_validator: Lamports,
+
+    /// Withdrawals that wait for SOL to be unstaked, see `CreateWithdrawTicket`.
+    pub withdraw_queue: WithdrawQueue,
+
+    /// Whether deposits and withdrawals are paused, an emergency stop.
+    ///
+    /// While paused, `Deposit`, `DepositStake`, `Withdraw`, and
+    /// `CreateWithdrawTicket` fail, but maintenance continues. Only the
+    /// manager can pause and resume, with `SetPaused`.
+    pub is_paused: bool,
+
+    /// Maximum amount of SOL managed by Solido, or zero if there is no maximum.
+    ///
+    /// `Deposit` fails if the deposit would bring the SOL balance above this
+    /// amount, see [`Lido::check_deposit_cap`]. The manager can change it with
+    /// `SetMaxTotalLamports`.
+    pub max_total_lamports: Lamports,
+
+    /// Whether only depositors on the depositor allowlist can deposit.
+    ///
+    /// While enabled, `Deposit` and `DepositStake` fail unless the user is in
+    /// the `DepositorAllowlist` account of this instance. The manager can
+    /// change it with `SetDepositorAllowlistEnabled`.
+    pub is_depositor_allowlist_enabled: bool,
 }
 
 impl Lido {
@@ -245,6 +530,131 @@ impl Lido {
         get_instance_packed_len(&lido_instance).unwrap()
     }
 
+    /// Check the invariants that the program maintains for the state it stores.
+    ///
+    /// Any byte string that is long enough deserializes as a `Lido`, but only
+    /// the program's own state is guaranteed to satisfy these. Off-chain code
+    /// that reads an account it does not control, for example the CLI with a
+    /// misconfigured Solido address, should check them before relying on them,
+    /// e.g. before calling [`Validator::effective_stake_balance`], which panics
+    /// when they do not hold. Returns a description of the first violation.
+    pub fn check_well_formed(&self) -> Result<(), String> {
+        if self.validators.len() > self.validators.maximum_entries as usize {
+            return Err(format!(
+                "Lido has {} validators, but room for only {}.",
+                self.validators.len(),
+                self.validators.maximum_entries,
+            ));
+        }
+        if self.maintainers.len() > self.maintainers.maximum_entries as usize {
+            return Err(format!(
+                "Lido has {} maintainers, but room for only {}.",
+                self.maintainers.len(),
+                self.maintainers.maximum_entries,
+            ));
+        }
+        for validator in self.validators.entries.iter() {
+            let entry = &validator.entry;
+            if entry.stake_seeds.begin > entry.stake_seeds.end
+                || entry.unstake_seeds.begin > entry.unstake_seeds.end
+            {
+                return Err(format!(
+                    "Validator {} has a seed range that ends before it begins.",
+                    validator.pubkey,
+                ));
+            }
+            if entry.unstake_accounts_balance > entry.stake_accounts_balance {
+                return Err(format!(
+                    "Validator {} has an unstake balance of {}, which exceeds its stake balance of {}.",
+                    validator.pubkey, entry.unstake_accounts_balance, entry.stake_accounts_balance,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the maximum stake per validator, if there is a maximum.
+    pub fn get_max_stake_per_validator(&self) -> Option<Lamports> {
+        match self.max_stake_per_validator {
+            Lamports(0) => None,
+            max_stake => Some(max_stake),
+        }
+    }
+
+    /// Return the scheduled change of the reward distribution, if there is one.
+    pub fn get_pending_reward_distribution(&self) -> Option<&PendingRewardDistribution> {
+        match self.pending_reward_distribution.activation_epoch {
+            0 => None,
+            _ => Some(&self.pending_reward_distribution),
+        }
+    }
+
+    /// Replace the reward distribution with the pending one, if it is due in `epoch`.
+    ///
+    /// Returns whether the reward distribution changed.
+    pub fn activate_pending_reward_distribution(&mut self, epoch: Epoch) -> bool {
+        match self.get_pending_reward_distribution() {
+            Some(pending) if pending.activation_epoch <= epoch => {
+                let pending = std::mem::take(&mut self.pending_reward_distribution);
+                self.reward_distribution = pending.reward_distribution;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the maximum amount of SOL managed by Solido, if there is a maximum.
+    pub fn get_max_total_lamports(&self) -> Option<Lamports> {
+        match self.max_total_lamports {
+            Lamports(0) => None,
+            max_total => Some(max_total),
+        }
+    }
+
+    /// Return the SOL balance that counts against `max_total_lamports`.
+    ///
+    /// Unlike [`Lido::get_sol_balance`], this takes the full reserve balance,
+    /// because `Deposit` does not receive the rent sysvar. The rent-exempt
+    /// amount in the reserve is negligible compared to any sensible cap.
+    pub fn get_deposit_cap_balance(&self, reserve_balance: Lamports) -> token::Result<Lamports> {
+        let validator_balance: token::Result<Lamports> = self
+            .validators
+            .iter_entries()
+            .map(|v| v.stake_accounts_balance)
+            .sum();
+        let total = (validator_balance? + reserve_balance)?;
+        total - self.withdraw_queue.get_outstanding()
+    }
+
+    /// Confirm that depositing `amount` does not exceed `max_total_lamports`.
+    pub fn check_deposit_cap(&self, reserve: &AccountInfo, amount: Lamports) -> ProgramResult {
+        let max_total = match self.get_max_total_lamports() {
+            None => return Ok(()),
+            Some(max_total) => max_total,
+        };
+        let balance = self.get_deposit_cap_balance(Lamports(reserve.lamports()))?;
+        let balance_after = (balance + amount)?;
+        if balance_after > max_total {
+            msg!(
+                "Depositing {} would bring the total to {}, but the maximum is {}.",
+                amount,
+                balance_after,
+                max_total
+            );
+            return Err(LidoError::DepositCapExceeded.into());
+        }
+        Ok(())
+    }
+
+    /// Confirm that deposits and withdrawals are not paused.
+    pub fn check_not_paused(&self) -> ProgramResult {
+        if self.is_paused {
+            msg!("Deposits and withdrawals are paused by the manager.");
+            return Err(LidoError::Paused.into());
+        }
+        Ok(())
+    }
+
     /// Confirm that the given account is Solido's stSOL mint.
     pub fn check_mint_is_st_sol_mint(&self, mint_account_info: &AccountInfo) -> ProgramResult {
         if &self.st_sol_mint != mint_account_info.key {
@@ -258,12 +668,25 @@ impl Lido {
         Ok(())
     }
 
+    /// Confirm that the given account is the token program that owns our stSOL mint.
+    pub fn check_token_program(&self, token_program_info: &AccountInfo) -> ProgramResult {
+        if &self.st_sol_token_program != token_program_info.key {
+            msg!(
+                "Expected the token program of our stSOL mint ({}), but got {} instead.",
+                self.st_sol_token_program,
+                token_program_info.key
+            );
+            return Err(LidoError::InvalidAccountInfo.into());
+        }
+        Ok(())
+    }
+
     /// Confirm that the given account is an SPL token account with our stSOL mint as mint.
     pub fn check_is_st_sol_account(&self, token_account_info: &AccountInfo) -> ProgramResult {
-        if token_account_info.owner != &spl_token::id() {
+        if token_account_info.owner != &self.st_sol_token_program {
             msg!(
                 "Expected SPL token account to be owned by {}, but it's owned by {} instead.",
-                spl_token::id(),
+                self.st_sol_token_program,
                 token_account_info.owner
             );
             return Err(LidoError::InvalidStSolAccountOwner.into());
@@ -488,7 +911,12 @@ impl Lido {
 
         let rent: Rent = Rent::from_account_info(sysvar_rent)?;
 
-        let available_reserve_amount = get_reserve_available_balance(&rent, reserve)?;
+        // SOL that we owe to withdraw tickets is not available for staking,
+        // `FundWithdrawTicket` gets it first.
+        let available_reserve_amount = get_reserve_available_balance(&rent, reserve)?
+            .0
+            .saturating_sub(self.withdraw_queue.get_outstanding().0);
+        let available_reserve_amount = Lamports(available_reserve_amount);
         if amount > available_reserve_amount {
             msg!(
                 "The requested amount {} is greater than the available amount {}, \
@@ -569,7 +997,11 @@ impl Lido {
             .map(|v| v.stake_accounts_balance)
             .sum();
 
-        let result = validator_balance.and_then(|s| s + effective_reserve_balance)?;
+        let total = validator_balance.and_then(|s| s + effective_reserve_balance)?;
+
+        // The SOL owed to unfunded withdraw tickets is still in the reserve or
+        // in stake accounts, but the stSOL for it has already been burned.
+        let result = (total - self.withdraw_queue.get_outstanding())?;
 
         Ok(result)
     }
@@ -635,6 +1067,14 @@ pub struct Validator {
     /// Controls if a validator is allowed to have new stake deposits.
     /// When removing a validator, this flag should be set to `false`.
     pub active: bool,
+
+    /// Share of the stake that this validator should receive, relative to the
+    /// weights of the other active validators.
+    ///
+    /// When all weights are equal, stake is distributed uniformly. A validator
+    /// with weight 0 receives no new stake, but unlike a deactivated validator,
+    /// it is not unstaked from either. Set by the manager with `SetValidatorWeight`.
+    pub weight: u32,
 }
 
 #[repr(C)]
@@ -706,6 +1146,7 @@ impl Default for Validator {
             stake_accounts_balance: Lamports(0),
             unstake_accounts_balance: Lamports(0),
             active: true,
+            weight: DEFAULT_VALIDATOR_WEIGHT,
         }
     }
 }
@@ -808,6 +1249,24 @@ pub struct RewardDistribution {
     pub st_sol_appreciation: u32,
 }
 
+/// A reward distribution that replaces the current one in a later epoch.
+///
+/// The manager schedules it with `ScheduleRewardDistribution`, at least
+/// [`MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS`](crate::MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS)
+/// ahead, so stSOL holders learn about a fee change before it applies. It
+/// takes effect when the exchange rate is updated in or after the activation
+/// epoch, before any rewards of that epoch are distributed.
+#[derive(
+    Clone, Default, Debug, Eq, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema, Serialize,
+)]
+pub struct PendingRewardDistribution {
+    pub reward_distribution: RewardDistribution,
+
+    /// The first epoch in which `reward_distribution` applies, or zero if
+    /// there is no pending change.
+    pub activation_epoch: Epoch,
+}
+
 /// Specifies the fee recipients, accounts that should be created by Lido's minter
 #[derive(
     Clone, Default, Debug, Eq, PartialEq, BorshSerialize, BorshDeserialize, BorshSchema, Serialize,
@@ -973,6 +1432,130 @@ mod test_lido {
         );
     }
 
+    #[test]
+    fn test_exchange_rate_oracle_size() {
+        let oracle = ExchangeRateOracle::default();
+        let data = oracle.try_to_vec().unwrap();
+        assert_eq!(data.len(), EXCHANGE_RATE_ORACLE_SIZE);
+    }
+
+    #[test]
+    fn test_maintainer_lease_size() {
+        let lease = MaintainerLease::default();
+        let data = lease.try_to_vec().unwrap();
+        assert_eq!(data.len(), MAINTAINER_LEASE_SIZE);
+    }
+
+    #[test]
+    fn test_withdraw_ticket_size() {
+        let ticket = WithdrawTicket::default();
+        let data = ticket.try_to_vec().unwrap();
+        assert_eq!(data.len(), WITHDRAW_TICKET_SIZE);
+    }
+
+    #[test]
+    fn test_depositor_allowlist_size() {
+        let size = DepositorAllowlist::calculate_size(crate::MAXIMUM_ALLOWED_DEPOSITORS);
+        assert_eq!(
+            size,
+            std::mem::size_of::<Pubkey>()
+                + Depositors::required_bytes(crate::MAXIMUM_ALLOWED_DEPOSITORS as usize)
+        );
+        // A cross-program invocation can allocate at most 10 KiB.
+        assert!(size <= 10 * 1024);
+    }
+
+    #[test]
+    fn test_get_sol_balance_excludes_outstanding_withdraw_tickets() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let rent = &Rent::default();
+        let mut lido = Lido::default();
+        let key = Pubkey::default();
+        let mut amount = rent.minimum_balance(0) + 100;
+        let mut reserve_account =
+            AccountInfo::new(&key, true, true, &mut amount, &mut [], &key, false, 0);
+
+        lido.withdraw_queue.requested_total = Lamports(70);
+        lido.withdraw_queue.funded_total = Lamports(30);
+        assert_eq!(
+            lido.get_sol_balance(&rent, &reserve_account),
+            Ok(Lamports(60))
+        );
+
+        // Funded tickets no longer count, their SOL left the reserve.
+        lido.withdraw_queue.funded_total = Lamports(70);
+        assert_eq!(
+            lido.get_sol_balance(&rent, &reserve_account),
+            Ok(Lamports(100))
+        );
+
+        // If the reserve cannot cover the outstanding tickets, that is a bug.
+        let mut small_amount = rent.minimum_balance(0) + 10;
+        reserve_account.lamports = Rc::new(RefCell::new(&mut small_amount));
+        lido.withdraw_queue.requested_total = Lamports(100);
+        assert_eq!(
+            lido.get_sol_balance(&rent, &reserve_account),
+            Err(LidoError::CalculationFailure)
+        );
+    }
+
+    #[test]
+    fn test_check_deposit_cap() {
+        let mut lido = Lido::default();
+        let key = Pubkey::default();
+        let mut amount = 100;
+        let reserve_account =
+            AccountInfo::new(&key, true, true, &mut amount, &mut [], &key, false, 0);
+        lido.withdraw_queue.requested_total = Lamports(30);
+
+        // Without a maximum, any deposit is fine.
+        assert_eq!(
+            lido.check_deposit_cap(&reserve_account, Lamports(1_000)),
+            Ok(())
+        );
+
+        // The outstanding tickets do not count towards the balance, so 70 is
+        // in use, and 30 more is the most we can deposit.
+        lido.max_total_lamports = Lamports(100);
+        assert_eq!(
+            lido.check_deposit_cap(&reserve_account, Lamports(30)),
+            Ok(())
+        );
+        assert_eq!(
+            lido.check_deposit_cap(&reserve_account, Lamports(31)),
+            Err(LidoError::DepositCapExceeded.into())
+        );
+    }
+
+    #[test]
+    fn test_activate_pending_reward_distribution() {
+        let mut lido = Lido::default();
+        assert_eq!(lido.get_pending_reward_distribution(), None);
+        assert!(!lido.activate_pending_reward_distribution(5));
+
+        let new_distribution = RewardDistribution {
+            treasury_fee: 1,
+            validation_fee: 2,
+            developer_fee: 3,
+            st_sol_appreciation: 94,
+        };
+        lido.pending_reward_distribution = PendingRewardDistribution {
+            reward_distribution: new_distribution.clone(),
+            activation_epoch: 7,
+        };
+
+        // Before the activation epoch, nothing changes.
+        assert!(!lido.activate_pending_reward_distribution(6));
+        assert_eq!(lido.reward_distribution, RewardDistribution::default());
+
+        // In or after it, the pending distribution replaces the current one.
+        assert!(lido.activate_pending_reward_distribution(8));
+        assert_eq!(lido.reward_distribution, new_distribution);
+        assert_eq!(lido.get_pending_reward_distribution(), None);
+    }
+
     #[test]
     fn test_lido_serialization_roundtrips() {
         use solana_sdk::borsh::try_from_slice_unchecked;
@@ -986,6 +1569,7 @@ mod test_lido {
             lido_version: 0,
             manager: Pubkey::new_unique(),
             st_sol_mint: Pubkey::new_unique(),
+            st_sol_token_program: spl_token::id(),
             exchange_rate: ExchangeRate {
                 computed_in_epoch: 11,
                 sol_balance: Lamports(13),
@@ -1001,10 +1585,29 @@ mod test_lido {
                 developer_fee: 4,
                 st_sol_appreciation: 7,
             },
+            pending_reward_distribution: PendingRewardDistribution {
+                reward_distribution: RewardDistribution {
+                    treasury_fee: 3,
+                    validation_fee: 5,
+                    developer_fee: 2,
+                    st_sol_appreciation: 9,
+                },
+                activation_epoch: 33,
+            },
             fee_recipients: FeeRecipients {
                 treasury_account: Pubkey::new_unique(),
                 developer_account: Pubkey::new_unique(),
             },
+            max_stake_per_validator: Lamports(19),
+            withdraw_queue: WithdrawQueue {
+                next_ticket_id: 23,
+                next_fund_ticket_id: 21,
+                requested_total: Lamports(29),
+                funded_total: Lamports(27),
+            },
+            is_paused: true,
+            max_total_lamports: Lamports(31),
+            is_depositor_allowlist_enabled: true,
             metrics: Metrics::new(),
             validators: validators,
             maintainers: maintainers,
@@ -1351,4 +1954,30 @@ mod test_lido {
         }
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_check_token_program_accepts_configured_program_only() {
+        let mut lido = Lido::default();
+        lido.st_sol_token_program = crate::token_2022::id();
+
+        let mut lamports = 0;
+        let mut data = [];
+        let owner = solana_program::bpf_loader::id();
+        let token_2022_id = crate::token_2022::id();
+        let token_2022 = AccountInfo::new(
+            &token_2022_id,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            true,
+            0,
+        );
+        assert_eq!(lido.check_token_program(&token_2022), Ok(()));
+
+        lido.st_sol_token_program = spl_token::id();
+        let expected_error: ProgramError = LidoError::InvalidAccountInfo.into();
+        assert_eq!(lido.check_token_program(&token_2022), Err(expected_error));
+    }
 }
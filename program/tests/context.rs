@@ -35,7 +35,10 @@ use lido::{
     error::LidoError, instruction, RESERVE_ACCOUNT, REWARDS_WITHDRAW_AUTHORITY, STAKE_AUTHORITY,
 };
 use lido::{
-    state::{FeeRecipients, Lido, RewardDistribution, Validator},
+    state::{
+        DepositorAllowlist, ExchangeRateOracle, FeeRecipients, Lido, MaintainerLease,
+        RewardDistribution, Validator, WithdrawTicket, WITHDRAW_TICKET_SIZE,
+    },
     MINT_AUTHORITY,
 };
 
@@ -314,6 +317,7 @@ impl Context {
                         treasury_account: result.treasury_st_sol_account,
                         developer_account: result.developer_st_sol_account,
                         reserve_account: result.reserve_address,
+                        spl_token: spl_token::id(),
                     },
                 ),
             ],
@@ -762,6 +766,271 @@ impl Context {
         .expect("Failed to deactivate validator.");
     }
 
+    /// Move the validator to a new vote account, with its stake and unstake accounts.
+    pub async fn try_replace_validator_vote_account(
+        &mut self,
+        old_vote_account: Pubkey,
+        new_vote_account: Pubkey,
+    ) -> transport::Result<()> {
+        let solido = self.get_solido().await;
+        let validator = solido.validators.get(&old_vote_account).unwrap().clone();
+        let mut new_validator = validator.clone();
+        new_validator.pubkey = new_vote_account;
+
+        let mut sources = Vec::new();
+        let mut destinations = Vec::new();
+        let mut new_stake_seed = validator.entry.stake_seeds.begin;
+        let mut new_unstake_seed = validator.entry.unstake_seeds.end;
+        for seed in &validator.entry.stake_seeds {
+            let stake_account = self.get_stake_account_from_seed(&validator, seed).await;
+            // Stake that is not effective yet can be delegated to the new vote
+            // account, effective stake gets deactivated.
+            let (destination_seed, destination_type) = if stake_account.balance.active
+                == Lamports(0)
+                && stake_account.balance.deactivating == Lamports(0)
+            {
+                new_stake_seed += 1;
+                (new_stake_seed - 1, StakeType::Stake)
+            } else {
+                new_unstake_seed += 1;
+                (new_unstake_seed - 1, StakeType::Unstake)
+            };
+            sources.push(
+                validator
+                    .find_stake_account_address(
+                        &id(),
+                        &self.solido.pubkey(),
+                        seed,
+                        StakeType::Stake,
+                    )
+                    .0,
+            );
+            destinations.push(
+                new_validator
+                    .find_stake_account_address(
+                        &id(),
+                        &self.solido.pubkey(),
+                        destination_seed,
+                        destination_type,
+                    )
+                    .0,
+            );
+        }
+        for seed in &validator.entry.unstake_seeds {
+            sources.push(
+                validator
+                    .find_stake_account_address(
+                        &id(),
+                        &self.solido.pubkey(),
+                        seed,
+                        StakeType::Unstake,
+                    )
+                    .0,
+            );
+            destinations.push(
+                new_validator
+                    .find_stake_account_address(
+                        &id(),
+                        &self.solido.pubkey(),
+                        seed,
+                        StakeType::Unstake,
+                    )
+                    .0,
+            );
+        }
+        sources.extend(destinations);
+
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::replace_validator_vote_account(
+                &id(),
+                &instruction::ReplaceValidatorVoteAccountMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                    old_validator_vote_account: old_vote_account,
+                    new_validator_vote_account: new_vote_account,
+                    stake_authority: self.stake_authority,
+                    stake_accounts: sources,
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_set_validator_weight(
+        &mut self,
+        vote_account: Pubkey,
+        weight: u32,
+    ) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[lido::instruction::set_validator_weight(
+                &id(),
+                weight,
+                &lido::instruction::SetValidatorWeightMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                    validator_vote_account: vote_account,
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_set_max_stake_per_validator(
+        &mut self,
+        max_stake_per_validator: Lamports,
+    ) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[lido::instruction::set_max_stake_per_validator(
+                &id(),
+                max_stake_per_validator,
+                &lido::instruction::SetMaxStakePerValidatorMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_set_max_total_lamports(
+        &mut self,
+        max_total_lamports: Lamports,
+    ) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[lido::instruction::set_max_total_lamports(
+                &id(),
+                max_total_lamports,
+                &lido::instruction::SetMaxTotalLamportsMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_set_paused(&mut self, paused: bool) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[lido::instruction::set_paused(
+                &id(),
+                paused,
+                &lido::instruction::SetPausedMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    /// Create the depositor allowlist, paid for by the context payer, and return its address.
+    pub async fn try_create_depositor_allowlist(&mut self) -> transport::Result<Pubkey> {
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
+        let payer = self.context.payer.pubkey();
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::create_depositor_allowlist(
+                &id(),
+                &instruction::CreateDepositorAllowlistMeta {
+                    lido: self.solido.pubkey(),
+                    payer,
+                    depositor_allowlist,
+                },
+            )],
+            vec![],
+        )
+        .await?;
+        Ok(depositor_allowlist)
+    }
+
+    pub async fn try_add_allowed_depositor(&mut self, depositor: Pubkey) -> transport::Result<()> {
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::add_allowed_depositor(
+                &id(),
+                &instruction::AddAllowedDepositorMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                    depositor,
+                    depositor_allowlist,
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_remove_allowed_depositor(
+        &mut self,
+        depositor: Pubkey,
+    ) -> transport::Result<()> {
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::remove_allowed_depositor(
+                &id(),
+                &instruction::RemoveAllowedDepositorMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                    depositor,
+                    depositor_allowlist,
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn try_set_depositor_allowlist_enabled(
+        &mut self,
+        enabled: bool,
+    ) -> transport::Result<()> {
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::set_depositor_allowlist_enabled(
+                &id(),
+                enabled,
+                &instruction::SetDepositorAllowlistEnabledMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                    depositor_allowlist,
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
+    pub async fn get_depositor_allowlist(&mut self, address: Pubkey) -> DepositorAllowlist {
+        let account = self.get_account(address).await;
+        try_from_slice_unchecked::<DepositorAllowlist>(account.data.as_slice()).unwrap()
+    }
+
     pub async fn try_remove_validator(&mut self, vote_account: Pubkey) -> transport::Result<()> {
         send_transaction(
             &mut self.context,
@@ -784,7 +1053,19 @@ impl Context {
         // will hold the SOL to deposit, and it will also be the owner of the
         // stSOL account that holds the proceeds.
         let user = self.deterministic_keypair.new_keypair();
+        let recipient = self.try_deposit_from(&user, amount).await?;
+        Ok((user, recipient))
+    }
+
+    /// Deposit from the given user, and return the stSOL account that holds the proceeds.
+    pub async fn try_deposit_from(
+        &mut self,
+        user: &Keypair,
+        amount: Lamports,
+    ) -> transport::Result<Pubkey> {
         let recipient = self.create_st_sol_account(user.pubkey()).await;
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
 
         // Fund the user account, so the user can deposit that into Solido.
         self.fund(user.pubkey(), amount).await;
@@ -801,14 +1082,16 @@ impl Context {
                     st_sol_mint: self.st_sol_mint,
                     reserve_account: self.reserve_address,
                     mint_authority: self.mint_authority,
+                    spl_token: spl_token::id(),
+                    depositor_allowlist,
                 },
                 amount,
             )],
-            vec![&user],
+            vec![user],
         )
         .await?;
 
-        Ok((user, recipient))
+        Ok(recipient)
     }
 
     pub async fn deposit(&mut self, amount: Lamports) -> (Keypair, Pubkey) {
@@ -817,6 +1100,68 @@ impl Context {
             .expect("Failed to call Deposit on Solido instance.")
     }
 
+    /// Deposit an active stake account delegated to `validator_vote_account`.
+    ///
+    /// Returns the stSOL account that holds the proceeds, owned by `user`.
+    pub async fn try_deposit_stake(
+        &mut self,
+        user: &Keypair,
+        stake_account: Pubkey,
+        validator_vote_account: Pubkey,
+    ) -> transport::Result<Pubkey> {
+        let recipient = self.create_st_sol_account(user.pubkey()).await;
+        let solido = self.get_solido().await;
+        let validator = solido
+            .validators
+            .get(&validator_vote_account)
+            .expect("Trying to deposit stake with a non-member validator.");
+        let (stake_account_merge_into, _) = validator.find_stake_account_address(
+            &id(),
+            &self.solido.pubkey(),
+            validator.entry.stake_seeds.begin,
+            StakeType::Stake,
+        );
+        let (depositor_allowlist, _) =
+            DepositorAllowlist::find_address(&id(), &self.solido.pubkey());
+
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::deposit_stake(
+                &id(),
+                &instruction::DepositStakeAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    user: user.pubkey(),
+                    recipient,
+                    st_sol_mint: self.st_sol_mint,
+                    mint_authority: self.mint_authority,
+                    validator_vote_account,
+                    stake_account,
+                    stake_account_merge_into,
+                    stake_authority: self.stake_authority,
+                    reserve_account: self.reserve_address,
+                    spl_token: spl_token::id(),
+                    depositor_allowlist,
+                },
+            )],
+            vec![user],
+        )
+        .await?;
+
+        Ok(recipient)
+    }
+
+    pub async fn deposit_stake(
+        &mut self,
+        user: &Keypair,
+        stake_account: Pubkey,
+        validator_vote_account: Pubkey,
+    ) -> Pubkey {
+        self.try_deposit_stake(user, stake_account, validator_vote_account)
+            .await
+            .expect("Failed to call DepositStake on Solido instance.")
+    }
+
     /// Withdraw from the given validator and stake account.
     pub async fn try_withdraw(
         &mut self,
@@ -843,6 +1188,7 @@ impl Context {
                     source_stake_account,
                     destination_stake_account: new_stake.pubkey(),
                     stake_authority: self.stake_authority,
+                    spl_token: spl_token::id(),
                 },
                 amount,
             )],
@@ -998,18 +1344,67 @@ impl Context {
             .expect("Failed to call Unstake on Solido instance.");
     }
 
-    pub async fn try_change_reward_distribution(
+    pub async fn try_rebalance(
+        &mut self,
+        validator_vote_account: Pubkey,
+        amount: Lamports,
+        superminority: Vec<Pubkey>,
+    ) -> transport::Result<()> {
+        let solido = self.get_solido().await;
+        let validator = solido.validators.get(&validator_vote_account).unwrap();
+
+        let (source_stake_account, _) = validator.find_stake_account_address(
+            &id(),
+            &self.solido.pubkey(),
+            validator.entry.stake_seeds.begin,
+            StakeType::Stake,
+        );
+        let (destination_unstake_account, _) = validator.find_stake_account_address(
+            &id(),
+            &self.solido.pubkey(),
+            validator.entry.unstake_seeds.end,
+            StakeType::Unstake,
+        );
+
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::rebalance(
+                &id(),
+                &instruction::RebalanceAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    maintainer: self.maintainer.as_ref().unwrap().pubkey(),
+                    validator_vote_account,
+                    source_stake_account,
+                    destination_unstake_account,
+                    stake_authority: self.stake_authority,
+                    reserve_account: self.reserve_address,
+                },
+                amount,
+                superminority,
+            )],
+            vec![self.maintainer.as_ref().unwrap()],
+        )
+        .await
+    }
+
+    /// Unstake the stake above its target from an overweight validator.
+    pub async fn rebalance(&mut self, validator_vote_account: Pubkey, amount: Lamports) {
+        self.try_rebalance(validator_vote_account, amount, Vec::new())
+            .await
+            .expect("Failed to call Rebalance on Solido instance.");
+    }
+
+    pub async fn try_change_fee_recipients(
         &mut self,
-        new_reward_distribution: &RewardDistribution,
         new_fee_recipients: &FeeRecipients,
     ) -> transport::Result<()> {
         send_transaction(
             &mut self.context,
             &mut self.nonce,
-            &[instruction::change_reward_distribution(
+            &[instruction::change_fee_recipients(
                 &id(),
-                new_reward_distribution.clone(),
-                &instruction::ChangeRewardDistributionMeta {
+                &instruction::ChangeFeeRecipientsMeta {
                     lido: self.solido.pubkey(),
                     manager: self.manager.pubkey(),
                     treasury_account: new_fee_recipients.treasury_account,
@@ -1021,6 +1416,28 @@ impl Context {
         .await
     }
 
+    pub async fn try_schedule_reward_distribution(
+        &mut self,
+        new_reward_distribution: &RewardDistribution,
+        delay_epochs: u64,
+    ) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::schedule_reward_distribution(
+                &id(),
+                new_reward_distribution.clone(),
+                delay_epochs,
+                &instruction::ScheduleRewardDistributionMeta {
+                    lido: self.solido.pubkey(),
+                    manager: self.manager.pubkey(),
+                },
+            )],
+            vec![&self.manager],
+        )
+        .await
+    }
+
     pub async fn try_update_exchange_rate(&mut self) -> transport::Result<()> {
         send_transaction(
             &mut self.context,
@@ -1044,6 +1461,211 @@ impl Context {
             .expect("Failed to update exchange rate.");
     }
 
+    /// Publish the exchange rate to the oracle account, return the oracle address.
+    pub async fn try_publish_exchange_rate(&mut self) -> transport::Result<Pubkey> {
+        let (exchange_rate_oracle, _) =
+            ExchangeRateOracle::find_address(&id(), &self.solido.pubkey());
+        let maintainer = self
+            .maintainer
+            .as_ref()
+            .expect("Must have maintainer to call PublishExchangeRate.");
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::publish_exchange_rate(
+                &id(),
+                &instruction::PublishExchangeRateMeta {
+                    lido: self.solido.pubkey(),
+                    maintainer: maintainer.pubkey(),
+                    exchange_rate_oracle,
+                },
+            )],
+            vec![maintainer],
+        )
+        .await?;
+        Ok(exchange_rate_oracle)
+    }
+
+    pub async fn get_exchange_rate_oracle(&mut self, address: Pubkey) -> ExchangeRateOracle {
+        let account = self.get_account(address).await;
+        try_from_slice_unchecked::<ExchangeRateOracle>(account.data.as_slice()).unwrap()
+    }
+
+    /// Acquire or renew the maintainer lease, return the lease address.
+    pub async fn try_acquire_maintainer_lease(
+        &mut self,
+        lease_duration_slots: u64,
+    ) -> transport::Result<Pubkey> {
+        let (maintainer_lease, _) = MaintainerLease::find_address(&id(), &self.solido.pubkey());
+        let maintainer = self
+            .maintainer
+            .as_ref()
+            .expect("Must have maintainer to call AcquireMaintainerLease.");
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::acquire_maintainer_lease(
+                &id(),
+                lease_duration_slots,
+                &instruction::AcquireMaintainerLeaseMeta {
+                    lido: self.solido.pubkey(),
+                    maintainer: maintainer.pubkey(),
+                    maintainer_lease,
+                },
+            )],
+            vec![maintainer],
+        )
+        .await?;
+        Ok(maintainer_lease)
+    }
+
+    pub async fn get_maintainer_lease(&mut self, address: Pubkey) -> MaintainerLease {
+        let account = self.get_account(address).await;
+        try_from_slice_unchecked::<MaintainerLease>(account.data.as_slice()).unwrap()
+    }
+
+    /// Move the SOL above the rent-exempt balance of `sweep_account` into the reserve.
+    pub async fn try_sweep_to_reserve(&mut self, sweep_account: Pubkey) -> transport::Result<()> {
+        let maintainer = self
+            .maintainer
+            .as_ref()
+            .expect("Must have maintainer to call SweepToReserve.");
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::sweep_to_reserve(
+                &id(),
+                &instruction::SweepToReserveAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    maintainer: maintainer.pubkey(),
+                    reserve_account: self.reserve_address,
+                    sweep_account,
+                },
+            )],
+            vec![maintainer],
+        )
+        .await
+    }
+
+    /// Burn stSOL for a withdraw ticket, return the ticket address.
+    ///
+    /// This funds the user with the rent for the ticket account.
+    pub async fn try_create_withdraw_ticket(
+        &mut self,
+        user: &Keypair,
+        st_sol_account: Pubkey,
+        amount: StLamports,
+    ) -> transport::Result<Pubkey> {
+        let solido = self.get_solido().await;
+        let (withdraw_ticket, _) = WithdrawTicket::find_address(
+            &id(),
+            &self.solido.pubkey(),
+            solido.withdraw_queue.next_ticket_id,
+        );
+
+        let rent = self.get_rent().await;
+        self.fund(
+            user.pubkey(),
+            Lamports(rent.minimum_balance(WITHDRAW_TICKET_SIZE)),
+        )
+        .await;
+
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::create_withdraw_ticket(
+                &id(),
+                &instruction::CreateWithdrawTicketAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    st_sol_account_owner: user.pubkey(),
+                    st_sol_account,
+                    st_sol_mint: self.st_sol_mint,
+                    withdraw_ticket,
+                    spl_token: spl_token::id(),
+                },
+                amount,
+            )],
+            vec![user],
+        )
+        .await?;
+        Ok(withdraw_ticket)
+    }
+
+    pub async fn create_withdraw_ticket(
+        &mut self,
+        user: &Keypair,
+        st_sol_account: Pubkey,
+        amount: StLamports,
+    ) -> Pubkey {
+        self.try_create_withdraw_ticket(user, st_sol_account, amount)
+            .await
+            .expect("Failed to call CreateWithdrawTicket on Solido instance.")
+    }
+
+    /// Fund the next ticket in the withdraw queue from the reserve.
+    pub async fn try_fund_withdraw_ticket(&mut self) -> transport::Result<()> {
+        let solido = self.get_solido().await;
+        let (withdraw_ticket, _) = WithdrawTicket::find_address(
+            &id(),
+            &self.solido.pubkey(),
+            solido.withdraw_queue.next_fund_ticket_id,
+        );
+        let maintainer = self
+            .maintainer
+            .as_ref()
+            .expect("Must have maintainer to call FundWithdrawTicket.");
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::fund_withdraw_ticket(
+                &id(),
+                &instruction::FundWithdrawTicketAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    maintainer: maintainer.pubkey(),
+                    reserve_account: self.reserve_address,
+                    withdraw_ticket,
+                },
+            )],
+            vec![maintainer],
+        )
+        .await
+    }
+
+    pub async fn fund_withdraw_ticket(&mut self) {
+        self.try_fund_withdraw_ticket()
+            .await
+            .expect("Failed to call FundWithdrawTicket on Solido instance.")
+    }
+
+    /// Claim a funded withdraw ticket, and send its SOL to `recipient`.
+    pub async fn try_claim_withdraw_ticket(
+        &mut self,
+        user: &Keypair,
+        withdraw_ticket: Pubkey,
+        recipient: Pubkey,
+    ) -> transport::Result<()> {
+        send_transaction(
+            &mut self.context,
+            &mut self.nonce,
+            &[instruction::claim_withdraw_ticket(
+                &id(),
+                &instruction::ClaimWithdrawTicketAccountsMeta {
+                    lido: self.solido.pubkey(),
+                    ticket_owner: user.pubkey(),
+                    withdraw_ticket,
+                    recipient,
+                },
+            )],
+            vec![user],
+        )
+        .await
+    }
+
+    pub async fn get_withdraw_ticket(&mut self, address: Pubkey) -> WithdrawTicket {
+        let account = self.get_account(address).await;
+        try_from_slice_unchecked::<WithdrawTicket>(account.data.as_slice()).unwrap()
+    }
+
     /// Merge two accounts of a given validator.
     ///
     /// Returns the address that stake was merged into.
@@ -1175,6 +1797,7 @@ impl Context {
                     developer_st_sol_account: self.developer_st_sol_account,
                     reserve: self.reserve_address,
                     rewards_withdraw_authority,
+                    spl_token_program: spl_token::id(),
                 },
             )],
             vec![],
@@ -1219,6 +1842,7 @@ impl Context {
                     validator_fee_st_sol_account: validator_before.entry.fee_address,
                     st_sol_mint: self.st_sol_mint,
                     mint_authority: self.mint_authority,
+                    spl_token: spl_token::id(),
                 },
             )],
             vec![],
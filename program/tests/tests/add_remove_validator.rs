@@ -10,6 +10,7 @@ use crate::assert_solido_error;
 use crate::context::{Context, StakeDeposit, ValidatorAccounts};
 
 use lido::error::LidoError;
+use lido::state::DEFAULT_VALIDATOR_WEIGHT;
 use lido::token::{Lamports, StLamports};
 
 pub const TEST_DEPOSIT_AMOUNT: Lamports = Lamports(100_000_000_000);
@@ -152,3 +153,30 @@ async fn test_deactivate_validator() {
     let solido_after_second_deactivation = context.get_solido().await;
     assert_eq!(solido, solido_after_second_deactivation);
 }
+
+#[tokio::test]
+async fn test_set_validator_weight() {
+    let mut context = Context::new_with_maintainer().await;
+
+    let validator = context.add_validator().await;
+    let solido = context.get_solido().await;
+    assert_eq!(
+        solido.validators.entries[0].entry.weight,
+        DEFAULT_VALIDATOR_WEIGHT
+    );
+
+    context
+        .try_set_validator_weight(validator.vote_account, 7)
+        .await
+        .expect("Failed to set validator weight.");
+    let solido = context.get_solido().await;
+    assert_eq!(solido.validators.entries[0].entry.weight, 7);
+    assert!(solido.validators.entries[0].entry.active);
+
+    // Only the manager can change the weight.
+    context.manager = context.deterministic_keypair.new_keypair();
+    let result = context
+        .try_set_validator_weight(validator.vote_account, 0)
+        .await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+}
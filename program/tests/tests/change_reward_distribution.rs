@@ -8,12 +8,13 @@ use solana_sdk::signature::Signer;
 
 use lido::error::LidoError;
 use lido::state::{FeeRecipients, RewardDistribution};
+use lido::MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS;
 
 use crate::assert_solido_error;
 use crate::context::Context;
 
 #[tokio::test]
-async fn test_successful_change_reward_distribution() {
+async fn test_successful_change_fee_recipients() {
     let mut context = Context::new_with_maintainer().await;
 
     let solido = context.get_solido().await;
@@ -27,13 +28,6 @@ async fn test_successful_change_reward_distribution() {
         context.developer_st_sol_account,
     );
 
-    let new_fee = RewardDistribution {
-        treasury_fee: 87,
-        validation_fee: 44,
-        developer_fee: 54,
-        st_sol_appreciation: 122,
-    };
-
     let new_treasury_owner = context.deterministic_keypair.new_keypair();
     let new_treasury_addr = context
         .create_st_sol_account(new_treasury_owner.pubkey())
@@ -45,24 +39,22 @@ async fn test_successful_change_reward_distribution() {
         .await;
 
     context
-        .try_change_reward_distribution(
-            &new_fee,
-            &FeeRecipients {
-                developer_account: new_developer_addr,
-                treasury_account: new_treasury_addr,
-            },
-        )
+        .try_change_fee_recipients(&FeeRecipients {
+            developer_account: new_developer_addr,
+            treasury_account: new_treasury_addr,
+        })
         .await
-        .expect("Failed to change fees.");
+        .expect("Failed to change fee recipients.");
 
+    // The fee split only changes through `ScheduleRewardDistribution`.
     let solido = context.get_solido().await;
-    assert_eq!(solido.reward_distribution, new_fee);
+    assert_eq!(solido.reward_distribution, context.reward_distribution);
     assert_eq!(solido.fee_recipients.treasury_account, new_treasury_addr,);
     assert_eq!(solido.fee_recipients.developer_account, new_developer_addr,);
 }
 
 #[tokio::test]
-async fn test_change_reward_distribution_wrong_minter() {
+async fn test_change_fee_recipients_wrong_minter() {
     let mut context = Context::new_with_maintainer().await;
 
     let wrong_mint_authority = context.deterministic_keypair.new_keypair();
@@ -78,24 +70,77 @@ async fn test_change_reward_distribution_wrong_minter() {
     let solido = context.get_solido().await;
 
     let result = context
-        .try_change_reward_distribution(
-            &solido.reward_distribution,
-            &FeeRecipients {
-                developer_account: not_st_sol_account,
-                ..solido.fee_recipients
-            },
-        )
+        .try_change_fee_recipients(&FeeRecipients {
+            developer_account: not_st_sol_account,
+            ..solido.fee_recipients
+        })
         .await;
     assert_solido_error!(result, LidoError::InvalidFeeRecipient);
 
     let result = context
-        .try_change_reward_distribution(
-            &solido.reward_distribution,
-            &FeeRecipients {
-                treasury_account: not_st_sol_account,
-                ..solido.fee_recipients
-            },
-        )
+        .try_change_fee_recipients(&FeeRecipients {
+            treasury_account: not_st_sol_account,
+            ..solido.fee_recipients
+        })
         .await;
     assert_solido_error!(result, LidoError::InvalidFeeRecipient);
 }
+
+#[tokio::test]
+async fn test_scheduled_reward_distribution_activates_after_delay() {
+    let mut context = Context::new_with_maintainer().await;
+    context.advance_to_normal_epoch(0);
+    let epoch = context.get_clock().await.epoch;
+
+    let new_fee = RewardDistribution {
+        treasury_fee: 5,
+        validation_fee: 3,
+        developer_fee: 1,
+        st_sol_appreciation: 91,
+    };
+    let result = context
+        .try_schedule_reward_distribution(&new_fee, MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS - 1)
+        .await;
+    assert_solido_error!(result, LidoError::RewardDistributionDelayTooShort);
+
+    context
+        .try_schedule_reward_distribution(&new_fee, MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS)
+        .await
+        .expect("Failed to schedule the reward distribution.");
+    let solido = context.get_solido().await;
+    assert_eq!(solido.reward_distribution, context.reward_distribution);
+    let pending = solido.get_pending_reward_distribution().unwrap();
+    assert_eq!(pending.reward_distribution, new_fee);
+    assert_eq!(
+        pending.activation_epoch,
+        epoch + MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS
+    );
+
+    // Before the activation epoch, updating the exchange rate keeps the
+    // current distribution.
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+    let solido = context.get_solido().await;
+    assert_eq!(solido.reward_distribution, context.reward_distribution);
+
+    context.advance_to_normal_epoch(MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS);
+    context.update_exchange_rate().await;
+    let solido = context.get_solido().await;
+    assert_eq!(solido.reward_distribution, new_fee);
+    assert_eq!(solido.get_pending_reward_distribution(), None);
+}
+
+#[tokio::test]
+async fn test_schedule_reward_distribution_requires_manager() {
+    let mut context = Context::new_with_maintainer().await;
+    context.manager = context.deterministic_keypair.new_keypair();
+
+    let reward_distribution = context.reward_distribution.clone();
+    let result = context
+        .try_schedule_reward_distribution(
+            &reward_distribution,
+            MINIMUM_REWARD_DISTRIBUTION_DELAY_EPOCHS,
+        )
+        .await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+}
@@ -52,3 +52,27 @@ async fn test_deposit_fails_with_wrong_reserve() {
 
     assert_solido_error!(result, LidoError::InvalidReserveAccount);
 }
+
+#[tokio::test]
+async fn test_deposit_fails_above_max_total_lamports() {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let rent = context.get_rent().await;
+
+    // The reserve holds its rent-exempt balance, which counts towards the cap.
+    let max_total = (TEST_DEPOSIT_AMOUNT + Lamports(rent.minimum_balance(0))).unwrap();
+    context
+        .try_set_max_total_lamports(max_total)
+        .await
+        .expect("Manager should be able to set the deposit cap.");
+
+    context.deposit(TEST_DEPOSIT_AMOUNT).await;
+    let result = context.try_deposit(Lamports(1)).await;
+    assert_solido_error!(result, LidoError::DepositCapExceeded);
+
+    // Zero removes the cap.
+    context
+        .try_set_max_total_lamports(Lamports(0))
+        .await
+        .unwrap();
+    context.deposit(TEST_DEPOSIT_AMOUNT).await;
+}
@@ -0,0 +1,155 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use crate::assert_solido_error;
+use crate::context::{Context, StakeDeposit};
+
+use lido::error::LidoError;
+use lido::token::{Lamports, StLamports};
+use solana_program_test::tokio;
+use solana_sdk::signer::Signer;
+
+const TEST_STAKE_AMOUNT: Lamports = Lamports(2_000_000_000);
+
+/// Set up a Solido instance whose validator has an active stake account.
+async fn new_context_with_active_stake() -> Context {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    context.advance_to_normal_epoch(0);
+
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    context.deposit(Lamports(10_000_000_000)).await;
+    context
+        .stake_deposit(vote_account, StakeDeposit::Append, TEST_STAKE_AMOUNT)
+        .await;
+    context
+}
+
+#[tokio::test]
+async fn test_successful_deposit_stake() {
+    let mut context = new_context_with_active_stake().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+
+    let user = context.deterministic_keypair.new_keypair();
+    let user_stake = context
+        .create_stake_account(TEST_STAKE_AMOUNT, user.pubkey())
+        .await;
+    context
+        .delegate_stake_account(user_stake, vote_account, &user)
+        .await;
+
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+
+    let solido_before = context.get_solido().await;
+    let recipient = context.deposit_stake(&user, user_stake, vote_account).await;
+    let solido_after = context.get_solido().await;
+
+    // The user's stake account was merged into Solido's.
+    assert!(context.try_get_account(user_stake).await.is_none());
+
+    let expected_st_sol = solido_before
+        .exchange_rate
+        .exchange_sol(TEST_STAKE_AMOUNT)
+        .unwrap();
+    let st_sol_balance = context.get_st_sol_balance(recipient).await;
+    assert_eq!(st_sol_balance, expected_st_sol);
+    assert!(st_sol_balance > StLamports(0));
+
+    let validator_before = &solido_before.validators.entries[0].entry;
+    let validator_after = &solido_after.validators.entries[0].entry;
+    assert_eq!(
+        validator_after.stake_accounts_balance,
+        (validator_before.stake_accounts_balance + TEST_STAKE_AMOUNT).unwrap(),
+    );
+    assert_eq!(validator_after.stake_seeds, validator_before.stake_seeds);
+
+    // The deposited stake is accounted for, so it is not seen as a donation.
+    context.withdraw_inactive_stake(vote_account).await;
+    let solido_final = context.get_solido().await;
+    assert_eq!(
+        solido_final.validators.entries[0]
+            .entry
+            .stake_accounts_balance,
+        validator_after.stake_accounts_balance,
+    );
+}
+
+#[tokio::test]
+async fn test_deposit_stake_fails_with_wrong_validator() {
+    let mut context = new_context_with_active_stake().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    let other_validator = context.add_validator().await;
+
+    let user = context.deterministic_keypair.new_keypair();
+    let user_stake = context
+        .create_stake_account(TEST_STAKE_AMOUNT, user.pubkey())
+        .await;
+    context
+        .delegate_stake_account(user_stake, other_validator.vote_account, &user)
+        .await;
+
+    context.advance_to_normal_epoch(1);
+
+    let result = context
+        .try_deposit_stake(&user, user_stake, vote_account)
+        .await;
+    assert_solido_error!(result, LidoError::InvalidStakeAccount);
+}
+
+#[tokio::test]
+async fn test_deposit_stake_fails_with_activating_stake() {
+    let mut context = new_context_with_active_stake().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    context.advance_to_normal_epoch(1);
+
+    let user = context.deterministic_keypair.new_keypair();
+    let user_stake = context
+        .create_stake_account(TEST_STAKE_AMOUNT, user.pubkey())
+        .await;
+    context
+        .delegate_stake_account(user_stake, vote_account, &user)
+        .await;
+
+    let result = context
+        .try_deposit_stake(&user, user_stake, vote_account)
+        .await;
+    assert_solido_error!(result, LidoError::WrongStakeState);
+}
+
+#[tokio::test]
+async fn test_deposit_stake_fails_above_max_total_lamports() {
+    let mut context = new_context_with_active_stake().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+
+    let user = context.deterministic_keypair.new_keypair();
+    let user_stake = context
+        .create_stake_account(TEST_STAKE_AMOUNT, user.pubkey())
+        .await;
+    context
+        .delegate_stake_account(user_stake, vote_account, &user)
+        .await;
+
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+
+    // Leave room for one lamport less than the stake account holds.
+    let solido = context.get_solido().await;
+    let reserve_balance = context.get_sol_balance(context.reserve_address).await;
+    let balance = solido.get_deposit_cap_balance(reserve_balance).unwrap();
+    let max_total = (balance + TEST_STAKE_AMOUNT).unwrap();
+    context
+        .try_set_max_total_lamports((max_total - Lamports(1)).unwrap())
+        .await
+        .unwrap();
+
+    let result = context
+        .try_deposit_stake(&user, user_stake, vote_account)
+        .await;
+    assert_solido_error!(result, LidoError::DepositCapExceeded);
+
+    // With exactly enough room, the deposit succeeds.
+    context.try_set_max_total_lamports(max_total).await.unwrap();
+    context.deposit_stake(&user, user_stake, vote_account).await;
+}
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+use solana_sdk::signer::Signer;
+
+use crate::assert_solido_error;
+use crate::context::Context;
+use lido::error::LidoError;
+use lido::token::Lamports;
+
+#[tokio::test]
+async fn test_enable_requires_allowlist_account() {
+    let mut context = Context::new_empty().await;
+    let result = context.try_set_depositor_allowlist_enabled(true).await;
+    assert_solido_error!(result, LidoError::InvalidDepositorAllowlist);
+
+    context.try_create_depositor_allowlist().await.unwrap();
+    context
+        .try_set_depositor_allowlist_enabled(true)
+        .await
+        .expect("Manager should be able to enable the allowlist once it exists.");
+    assert!(context.get_solido().await.is_depositor_allowlist_enabled);
+
+    // The allowlist can only be created once.
+    let result = context.try_create_depositor_allowlist().await;
+    assert_solido_error!(result, LidoError::AlreadyInUse);
+}
+
+#[tokio::test]
+async fn test_only_manager_can_change_allowlist() {
+    let mut context = Context::new_empty().await;
+    let address = context.try_create_depositor_allowlist().await.unwrap();
+    let depositor = context.deterministic_keypair.new_keypair().pubkey();
+
+    let impostor = context.deterministic_keypair.new_keypair();
+    let manager = std::mem::replace(&mut context.manager, impostor);
+    let result = context.try_add_allowed_depositor(depositor).await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+    let result = context.try_set_depositor_allowlist_enabled(true).await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+
+    context.manager = manager;
+    context.try_add_allowed_depositor(depositor).await.unwrap();
+    let allowlist = context.get_depositor_allowlist(address).await;
+    assert_eq!(allowlist.solido, context.solido.pubkey());
+    assert!(allowlist.is_allowed(&depositor));
+
+    // Adding the same depositor twice fails.
+    let result = context.try_add_allowed_depositor(depositor).await;
+    assert_solido_error!(result, LidoError::DuplicatedEntry);
+
+    context
+        .try_remove_allowed_depositor(depositor)
+        .await
+        .unwrap();
+    let allowlist = context.get_depositor_allowlist(address).await;
+    assert!(!allowlist.is_allowed(&depositor));
+}
+
+#[tokio::test]
+async fn test_deposit_requires_allowed_depositor_when_enabled() {
+    let mut context = Context::new_empty().await;
+    let amount = Lamports(1_000_000_000);
+    let depositor = context.deterministic_keypair.new_keypair();
+
+    // Without the allowlist enabled, anybody can deposit, even if the
+    // allowlist account does not exist.
+    context.deposit(amount).await;
+
+    context.try_create_depositor_allowlist().await.unwrap();
+    context
+        .try_add_allowed_depositor(depositor.pubkey())
+        .await
+        .unwrap();
+    context
+        .try_set_depositor_allowlist_enabled(true)
+        .await
+        .unwrap();
+
+    let result = context.try_deposit(amount).await;
+    assert_solido_error!(result, LidoError::DepositorNotAllowed);
+    context
+        .try_deposit_from(&depositor, amount)
+        .await
+        .expect("A depositor on the allowlist should be able to deposit.");
+
+    context
+        .try_remove_allowed_depositor(depositor.pubkey())
+        .await
+        .unwrap();
+    let result = context.try_deposit_from(&depositor, amount).await;
+    assert_solido_error!(result, LidoError::DepositorNotAllowed);
+
+    context
+        .try_set_depositor_allowlist_enabled(false)
+        .await
+        .unwrap();
+    context.deposit(amount).await;
+}
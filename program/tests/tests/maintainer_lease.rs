@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+use crate::assert_solido_error;
+use crate::context::Context;
+
+use lido::error::LidoError;
+use lido::token::Lamports;
+use lido::MAXIMUM_MAINTAINER_LEASE_SLOTS;
+
+#[tokio::test]
+async fn test_acquire_maintainer_lease() {
+    let mut context = Context::new_with_maintainer().await;
+    let first = context.maintainer.as_ref().unwrap().pubkey();
+    // The maintainer pays for the lease account on the first acquisition.
+    context.fund(first, Lamports(1_000_000_000)).await;
+
+    let second = context.add_maintainer().await;
+    let second_pubkey = second.pubkey();
+    context.fund(second_pubkey, Lamports(1_000_000_000)).await;
+
+    let lease_address = context
+        .try_acquire_maintainer_lease(100)
+        .await
+        .expect("Failed to acquire lease.");
+    let lease = context.get_maintainer_lease(lease_address).await;
+    let clock = context.get_clock().await;
+    assert_eq!(lease.solido, context.solido.pubkey());
+    assert_eq!(lease.holder, first);
+    assert_eq!(lease.expires_slot, lease.acquired_slot + 100);
+    assert!(lease.is_valid_at(clock.slot));
+
+    // While the lease is valid, the other maintainer cannot take it.
+    let first_keypair = context.maintainer.replace(second).unwrap();
+    let result = context.try_acquire_maintainer_lease(100).await;
+    assert_solido_error!(result, LidoError::MaintainerLeaseHeldByOther);
+
+    // The holder can renew the lease, which extends the expiry.
+    let second = context.maintainer.replace(first_keypair).unwrap();
+    context.context.warp_to_slot(clock.slot + 50).unwrap();
+    context
+        .try_acquire_maintainer_lease(100)
+        .await
+        .expect("Failed to renew lease.");
+    let renewed = context.get_maintainer_lease(lease_address).await;
+    assert_eq!(renewed.holder, first);
+    assert_eq!(renewed.acquired_slot, lease.acquired_slot);
+    assert!(renewed.expires_slot > lease.expires_slot);
+
+    // After the lease expires, the other maintainer can take over.
+    context
+        .context
+        .warp_to_slot(renewed.expires_slot + 1)
+        .unwrap();
+    context.maintainer = Some(second);
+    context
+        .try_acquire_maintainer_lease(100)
+        .await
+        .expect("Failed to take over expired lease.");
+    let taken = context.get_maintainer_lease(lease_address).await;
+    assert_eq!(taken.holder, second_pubkey);
+    assert!(taken.acquired_slot > renewed.expires_slot);
+}
+
+#[tokio::test]
+async fn test_acquire_maintainer_lease_rejects_invalid_duration() {
+    let mut context = Context::new_with_maintainer().await;
+    let maintainer = context.maintainer.as_ref().unwrap().pubkey();
+    context.fund(maintainer, Lamports(1_000_000_000)).await;
+
+    let result = context.try_acquire_maintainer_lease(0).await;
+    assert_solido_error!(result, LidoError::InvalidMaintainerLeaseDuration);
+
+    let result = context
+        .try_acquire_maintainer_lease(MAXIMUM_MAINTAINER_LEASE_SLOTS + 1)
+        .await;
+    assert_solido_error!(result, LidoError::InvalidMaintainerLeaseDuration);
+}
+
+#[tokio::test]
+async fn test_acquire_maintainer_lease_requires_maintainer() {
+    let mut context = Context::new_with_maintainer().await;
+
+    // Replace the maintainer with a keypair that is not in the maintainer set.
+    let impostor = context.deterministic_keypair.new_keypair();
+    context
+        .fund(impostor.pubkey(), Lamports(1_000_000_000))
+        .await;
+    context.maintainer = Some(impostor);
+
+    let result = context.try_acquire_maintainer_lease(100).await;
+    assert_solido_error!(result, LidoError::InvalidMaintainer);
+}
@@ -0,0 +1,168 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use borsh::BorshSerialize;
+use solana_program::borsh::try_from_slice_unchecked;
+use solana_program::rent::Rent;
+use solana_program_test::{processor, tokio, ProgramTest};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::assert_solido_error;
+use crate::context::{id, send_transaction};
+use lido::account_map::PubkeyAndEntry;
+use lido::error::LidoError;
+use lido::instruction;
+use lido::migration::{LidoV0, ValidatorV0, ValidatorsV0};
+use lido::state::{Lido, Maintainers, RewardDistribution, DEFAULT_VALIDATOR_WEIGHT, LIDO_VERSION};
+use lido::token::Lamports;
+
+#[tokio::test]
+async fn test_migrate_state_to_v1() {
+    let manager = Keypair::new();
+    let solido_address = Pubkey::new_unique();
+    let vote_account = Pubkey::new_unique();
+
+    let mut validators = ValidatorsV0::new(10);
+    validators
+        .add(
+            vote_account,
+            ValidatorV0 {
+                stake_accounts_balance: Lamports(1_000_000_000),
+                active: true,
+                ..ValidatorV0::default()
+            },
+        )
+        .unwrap();
+    let lido_v0 = LidoV0 {
+        manager: manager.pubkey(),
+        st_sol_mint: Pubkey::new_unique(),
+        reward_distribution: RewardDistribution {
+            treasury_fee: 3,
+            validation_fee: 5,
+            developer_fee: 2,
+            st_sol_appreciation: 90,
+        },
+        validators,
+        maintainers: Maintainers::new(3),
+        ..LidoV0::default()
+    };
+
+    // The size that `Initialize` of version 0 required: 357 constant bytes,
+    // plus 8 + (32 + 89) bytes per validator, plus 8 + 32 bytes per maintainer.
+    let data_len = 357 + 8 + 121 * 10 + 8 + 32 * 3;
+    let mut data = vec![0_u8; data_len];
+    BorshSerialize::serialize(&lido_v0, &mut &mut data[..]).unwrap();
+
+    // This name must match the name of the crate that contains the program, see `Context`.
+    let mut program_test = ProgramTest::new("lido", id(), processor!(lido::processor::process));
+    program_test.add_account(
+        solido_address,
+        Account {
+            lamports: Rent::default().minimum_balance(data_len),
+            data,
+            owner: id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    let mut context = program_test.start_with_context().await;
+    let mut nonce = 0;
+
+    // Other instructions refuse to touch the instance before it is migrated.
+    let result = send_transaction(
+        &mut context,
+        &mut nonce,
+        &[instruction::set_paused(
+            &id(),
+            true,
+            &instruction::SetPausedMeta {
+                lido: solido_address,
+                manager: manager.pubkey(),
+            },
+        )],
+        vec![&manager],
+    )
+    .await;
+    assert_solido_error!(result, LidoError::InvalidLidoVersion);
+
+    let migrate = |manager: &Keypair| {
+        instruction::migrate_state_to_v1(
+            &id(),
+            &instruction::MigrateStateToV1Meta {
+                lido: solido_address,
+                manager: manager.pubkey(),
+            },
+        )
+    };
+
+    let impostor = Keypair::new();
+    let result = send_transaction(
+        &mut context,
+        &mut nonce,
+        &[migrate(&impostor)],
+        vec![&impostor],
+    )
+    .await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+
+    send_transaction(
+        &mut context,
+        &mut nonce,
+        &[migrate(&manager)],
+        vec![&manager],
+    )
+    .await
+    .expect("Manager should be able to migrate the state.");
+
+    let account = context
+        .banks_client
+        .get_account(solido_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), data_len);
+    let solido = try_from_slice_unchecked::<Lido>(&account.data).unwrap();
+    assert_eq!(solido.lido_version, LIDO_VERSION);
+    assert_eq!(solido.manager, lido_v0.manager);
+    assert_eq!(solido.st_sol_mint, lido_v0.st_sol_mint);
+    assert_eq!(solido.st_sol_token_program, spl_token::id());
+    assert_eq!(solido.reward_distribution, lido_v0.reward_distribution);
+    assert_eq!(solido.maintainers, lido_v0.maintainers);
+    assert_eq!(solido.validators.maximum_entries, 8);
+    let PubkeyAndEntry { pubkey, entry } = &solido.validators.entries[0];
+    assert_eq!(*pubkey, vote_account);
+    assert_eq!(entry.stake_accounts_balance, Lamports(1_000_000_000));
+    assert_eq!(entry.weight, DEFAULT_VALIDATOR_WEIGHT);
+    assert!(entry.active);
+
+    // Migrating twice would reinterpret version 1 state as version 0.
+    let result = send_transaction(
+        &mut context,
+        &mut nonce,
+        &[migrate(&manager)],
+        vec![&manager],
+    )
+    .await;
+    assert_solido_error!(result, LidoError::InvalidLidoVersion);
+
+    // After the migration, the instance works as usual.
+    send_transaction(
+        &mut context,
+        &mut nonce,
+        &[instruction::set_paused(
+            &id(),
+            true,
+            &instruction::SetPausedMeta {
+                lido: solido_address,
+                manager: manager.pubkey(),
+            },
+        )],
+        vec![&manager],
+    )
+    .await
+    .expect("Manager should be able to pause after migrating.");
+}
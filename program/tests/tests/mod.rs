@@ -5,12 +5,22 @@ pub mod add_remove_validator;
 pub mod change_reward_distribution;
 pub mod collect_validator_fee;
 pub mod deposit;
+pub mod deposit_stake;
+pub mod depositor_allowlist;
 pub mod limits;
+pub mod maintainer_lease;
 pub mod maintainers;
 pub mod merge_stake;
+pub mod migrate_state;
+pub mod pause;
+pub mod publish_exchange_rate;
+pub mod rebalance;
+pub mod replace_validator_vote_account;
 pub mod solana_assumptions;
 pub mod stake_deposit;
+pub mod sweep_to_reserve;
 pub mod unstake;
 pub mod update_exchange_rate;
 pub mod withdraw_inactive_stake;
+pub mod withdraw_tickets;
 pub mod withdrawals;
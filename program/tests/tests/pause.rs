@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+
+use crate::assert_solido_error;
+use crate::context::{Context, StakeDeposit};
+use lido::error::LidoError;
+use lido::token::{Lamports, StLamports};
+
+#[tokio::test]
+async fn test_only_manager_can_pause() {
+    let mut context = Context::new_empty().await;
+    assert!(!context.get_solido().await.is_paused);
+
+    let impostor = context.deterministic_keypair.new_keypair();
+    let manager = std::mem::replace(&mut context.manager, impostor);
+    let result = context.try_set_paused(true).await;
+    assert_solido_error!(result, LidoError::InvalidManager);
+
+    context.manager = manager;
+    context
+        .try_set_paused(true)
+        .await
+        .expect("Manager should be able to pause.");
+    assert!(context.get_solido().await.is_paused);
+}
+
+#[tokio::test]
+async fn test_deposit_and_withdraw_fail_while_paused() {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let amount = Lamports(10_000_000_000);
+
+    let (user, token_addr) = context.deposit(amount).await;
+    let validator = context.validator.take().unwrap();
+    let stake_account = context
+        .stake_deposit(validator.vote_account, StakeDeposit::Append, amount)
+        .await;
+    context.advance_to_normal_epoch(0);
+    context.update_exchange_rate().await;
+
+    context.try_set_paused(true).await.unwrap();
+
+    let result = context.try_deposit(amount).await;
+    assert_solido_error!(result, LidoError::Paused);
+
+    let result = context
+        .try_withdraw(
+            &user,
+            token_addr,
+            StLamports(1_000_000_000),
+            validator.vote_account,
+            stake_account,
+        )
+        .await;
+    assert_solido_error!(result, LidoError::Paused);
+
+    // Maintenance is not affected by the pause.
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+
+    context.try_set_paused(false).await.unwrap();
+    context.deposit(amount).await;
+    context
+        .withdraw(
+            &user,
+            token_addr,
+            StLamports(1_000_000_000),
+            validator.vote_account,
+            stake_account,
+        )
+        .await;
+}
@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+use crate::assert_solido_error;
+use crate::context::Context;
+
+use lido::error::LidoError;
+use lido::token::{Lamports, StLamports};
+
+#[tokio::test]
+async fn test_publish_exchange_rate() {
+    let mut context = Context::new_with_maintainer().await;
+    let maintainer = context.maintainer.as_ref().unwrap().pubkey();
+    // The maintainer pays for the oracle account on the first publication.
+    context.fund(maintainer, Lamports(1_000_000_000)).await;
+
+    // The exchange rate has not been updated in this epoch yet, so publishing
+    // would publish an outdated rate.
+    context.advance_to_normal_epoch(0);
+    let result = context.try_publish_exchange_rate().await;
+    assert_solido_error!(result, LidoError::ExchangeRateNotUpdatedInThisEpoch);
+
+    context.deposit(Lamports(100_000_000)).await;
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+
+    let oracle_address = context
+        .try_publish_exchange_rate()
+        .await
+        .expect("Failed to publish exchange rate.");
+    let oracle = context.get_exchange_rate_oracle(oracle_address).await;
+    let solido = context.get_solido().await;
+    assert_eq!(oracle.solido, context.solido.pubkey());
+    assert_eq!(oracle.publisher, maintainer);
+    assert_eq!(oracle.exchange_rate, solido.exchange_rate);
+    assert_eq!(oracle.exchange_rate.st_sol_supply, StLamports(100_000_000));
+    let first_slot = oracle.published_slot;
+
+    // Publishing again later in the same epoch refreshes the publication slot.
+    let clock = context.get_clock().await;
+    context.context.warp_to_slot(clock.slot + 10).unwrap();
+    context
+        .try_publish_exchange_rate()
+        .await
+        .expect("Failed to publish exchange rate again.");
+    let oracle = context.get_exchange_rate_oracle(oracle_address).await;
+    assert!(oracle.published_slot > first_slot);
+}
+
+#[tokio::test]
+async fn test_publish_exchange_rate_requires_maintainer() {
+    let mut context = Context::new_with_maintainer().await;
+    context.advance_to_normal_epoch(0);
+    context.update_exchange_rate().await;
+
+    // Replace the maintainer with a keypair that is not in the maintainer set.
+    let impostor = context.deterministic_keypair.new_keypair();
+    context
+        .fund(impostor.pubkey(), Lamports(1_000_000_000))
+        .await;
+    context.maintainer = Some(impostor);
+
+    let result = context.try_publish_exchange_rate().await;
+    assert_solido_error!(result, LidoError::InvalidMaintainer);
+}
@@ -0,0 +1,89 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use crate::assert_solido_error;
+use crate::context::{Context, StakeDeposit};
+use lido::{error::LidoError, token::Lamports};
+use solana_program_test::tokio;
+
+const STAKE_AMOUNT: Lamports = Lamports(20_000_000_000);
+
+/// Set up a Solido instance with one validator that has all of the stake.
+async fn new_rebalance_context() -> Context {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+
+    context.deposit(STAKE_AMOUNT).await;
+    context
+        .stake_deposit(vote_account, StakeDeposit::Append, STAKE_AMOUNT)
+        .await;
+
+    // Wait for the stake to activate.
+    context.advance_to_normal_epoch(0);
+    context.update_exchange_rate().await;
+
+    context
+}
+
+#[tokio::test]
+async fn test_successful_rebalance() {
+    let mut context = new_rebalance_context().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+
+    // With a second validator, the first one has twice its target.
+    context.add_validator().await;
+
+    let rebalance_lamports = Lamports(5_000_000_000);
+    context.rebalance(vote_account, rebalance_lamports).await;
+
+    let solido = context.get_solido().await;
+    let validator = &solido.validators.entries[0].entry;
+    assert_eq!(validator.unstake_seeds.end, 1);
+    assert_eq!(validator.unstake_accounts_balance, rebalance_lamports);
+    assert_eq!(validator.stake_accounts_balance, STAKE_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_rebalance_fails_for_validator_at_target() {
+    let mut context = new_rebalance_context().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+
+    // The only validator holds all stake, which is exactly its target.
+    let result = context
+        .try_rebalance(vote_account, Lamports(1_000_000_000), Vec::new())
+        .await;
+    assert_solido_error!(result, LidoError::ValidatorNotOverweight);
+}
+
+#[tokio::test]
+async fn test_rebalance_fails_for_amount_above_excess() {
+    let mut context = new_rebalance_context().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    context.add_validator().await;
+
+    // The target is 10 SOL, so at most 10 SOL can be rebalanced.
+    let result = context
+        .try_rebalance(vote_account, Lamports(11_000_000_000), Vec::new())
+        .await;
+    assert_solido_error!(result, LidoError::InvalidAmount);
+}
+
+#[tokio::test]
+async fn test_rebalance_fails_when_others_are_in_superminority() {
+    let mut context = new_rebalance_context().await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    let other_validator = context.add_validator().await;
+
+    // The other validator gets no stake while it is in the superminority, so
+    // the target of the first validator is all of the stake.
+    let result = context
+        .try_rebalance(
+            vote_account,
+            Lamports(5_000_000_000),
+            vec![other_validator.vote_account],
+        )
+        .await;
+    assert_solido_error!(result, LidoError::ValidatorNotOverweight);
+}
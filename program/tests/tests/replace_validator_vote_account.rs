@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+use solana_sdk::signer::Signer;
+
+use crate::assert_solido_error;
+use crate::context::{id, Context, StakeDeposit};
+
+use lido::error::LidoError;
+use lido::processor::StakeType;
+use lido::token::Lamports;
+
+const STAKE_DEPOSIT_AMOUNT: Lamports = Lamports(10_000_000_000);
+
+#[tokio::test]
+async fn test_replace_vote_account_delegates_activating_stake() {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let old_vote_account = context.validator.as_ref().unwrap().vote_account;
+    context.deposit(Lamports(100_000_000_000)).await;
+    context
+        .stake_deposit(old_vote_account, StakeDeposit::Append, STAKE_DEPOSIT_AMOUNT)
+        .await;
+    let solido_before = context.get_solido().await;
+    let validator_before = solido_before.validators.get(&old_vote_account).unwrap();
+
+    let node_key = context.deterministic_keypair.new_keypair();
+    let new_vote_account = context.create_vote_account(&node_key).await;
+    context
+        .try_replace_validator_vote_account(old_vote_account, new_vote_account)
+        .await
+        .expect("Failed to replace the vote account.");
+
+    let solido_after = context.get_solido().await;
+    assert_eq!(solido_after.validators.len(), 1);
+    assert!(solido_after.validators.get(&old_vote_account).is_err());
+    let validator_after = solido_after.validators.get(&new_vote_account).unwrap();
+    // Everything but the vote account stays the same.
+    assert_eq!(validator_after.entry, validator_before.entry);
+
+    // The stake account moved to the new address, and is delegated to the new vote account.
+    let (old_stake_account, _) = validator_before.find_stake_account_address(
+        &id(),
+        &context.solido.pubkey(),
+        0,
+        StakeType::Stake,
+    );
+    assert!(context.try_get_account(old_stake_account).await.is_none());
+    let stake_account = context
+        .get_stake_account_from_seed(validator_after, 0)
+        .await;
+    assert!(stake_account.is_activating());
+    assert_eq!(stake_account.balance.total(), STAKE_DEPOSIT_AMOUNT);
+    let (new_stake_account, _) = validator_after.find_stake_account_address(
+        &id(),
+        &context.solido.pubkey(),
+        0,
+        StakeType::Stake,
+    );
+    let stake = context.get_stake_state(new_stake_account).await;
+    assert_eq!(stake.delegation.voter_pubkey, new_vote_account);
+}
+
+#[tokio::test]
+async fn test_replace_vote_account_deactivates_active_stake() {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let old_vote_account = context.validator.as_ref().unwrap().vote_account;
+    context.advance_to_normal_epoch(0);
+    context.deposit(Lamports(100_000_000_000)).await;
+    context
+        .stake_deposit(old_vote_account, StakeDeposit::Append, STAKE_DEPOSIT_AMOUNT)
+        .await;
+    context.advance_to_normal_epoch(1);
+    context
+        .stake_deposit(old_vote_account, StakeDeposit::Append, STAKE_DEPOSIT_AMOUNT)
+        .await;
+
+    let node_key = context.deterministic_keypair.new_keypair();
+    let new_vote_account = context.create_vote_account(&node_key).await;
+    context
+        .try_replace_validator_vote_account(old_vote_account, new_vote_account)
+        .await
+        .expect("Failed to replace the vote account.");
+
+    // The active account became an unstake account, the activating one is
+    // delegated to the new vote account.
+    let solido = context.get_solido().await;
+    let validator = solido.validators.get(&new_vote_account).unwrap();
+    assert_eq!(validator.entry.stake_seeds.begin, 0);
+    assert_eq!(validator.entry.stake_seeds.end, 1);
+    assert_eq!(validator.entry.unstake_seeds.begin, 0);
+    assert_eq!(validator.entry.unstake_seeds.end, 1);
+    assert_eq!(
+        validator.entry.unstake_accounts_balance,
+        STAKE_DEPOSIT_AMOUNT
+    );
+    assert_eq!(
+        validator.entry.effective_stake_balance(),
+        STAKE_DEPOSIT_AMOUNT
+    );
+
+    let stake_account = context.get_stake_account_from_seed(validator, 0).await;
+    assert!(stake_account.is_activating());
+    let unstake_account = context.get_unstake_account_from_seed(validator, 0).await;
+    assert_eq!(unstake_account.balance.deactivating, STAKE_DEPOSIT_AMOUNT);
+
+    // In the next epoch, the deactivated stake returns to the reserve as usual.
+    context.advance_to_normal_epoch(2);
+    context.update_exchange_rate().await;
+    context.withdraw_inactive_stake(new_vote_account).await;
+    let solido = context.get_solido().await;
+    let validator = solido.validators.get(&new_vote_account).unwrap();
+    assert_eq!(validator.entry.unstake_seeds.begin, 1);
+    assert_eq!(validator.entry.unstake_seeds.end, 1);
+    assert_eq!(validator.entry.unstake_accounts_balance, Lamports(0));
+}
+
+#[tokio::test]
+async fn test_replace_vote_account_with_existing_validator_fails() {
+    let mut context = Context::new_with_maintainer_and_validator().await;
+    let old_vote_account = context.validator.as_ref().unwrap().vote_account;
+    let other_validator = context.add_validator().await;
+
+    let result = context
+        .try_replace_validator_vote_account(old_vote_account, other_validator.vote_account)
+        .await;
+    assert_solido_error!(result, LidoError::DuplicatedEntry);
+}
@@ -258,3 +258,129 @@ async fn test_stake_deposit_fails_if_validator_with_less_stake_exists() {
         )
         .await;
 }
+
+#[tokio::test]
+async fn test_stake_deposit_compares_stake_relative_to_weight() {
+    let mut context = Context::new_with_maintainer().await;
+    let v1 = context.add_validator().await;
+    let v2 = context.add_validator().await;
+
+    // v1 should get twice as much stake as v2.
+    context
+        .try_set_validator_weight(v1.vote_account, 200)
+        .await
+        .unwrap();
+
+    context.deposit(Lamports(10_000_000_000)).await;
+
+    // Stake 1 SOL with v2, and then 3 SOL with v1. Before every deposit, v1 has
+    // no more stake per unit of weight than v2, so all of these should succeed,
+    // even though with equal weights, the last two would be disallowed.
+    let vote_accounts = [
+        v2.vote_account,
+        v1.vote_account,
+        v1.vote_account,
+        v1.vote_account,
+    ];
+    for vote_account in vote_accounts.iter() {
+        context
+            .stake_deposit(*vote_account, StakeDeposit::Append, Lamports(1_000_000_000))
+            .await;
+    }
+
+    // Now v1 has more stake relative to its weight, we should top up v2 first.
+    let result = context
+        .try_stake_deposit(
+            v1.vote_account,
+            StakeDeposit::Append,
+            Lamports(1_000_000_000),
+        )
+        .await;
+    assert_solido_error!(result, LidoError::ValidatorWithLessStakeExists);
+}
+
+#[tokio::test]
+async fn test_stake_deposit_fails_for_zero_weight_validator() {
+    let mut context = Context::new_with_maintainer().await;
+    let v1 = context.add_validator().await;
+    let v2 = context.add_validator().await;
+
+    context
+        .try_set_validator_weight(v1.vote_account, 0)
+        .await
+        .unwrap();
+
+    context.deposit(TEST_DEPOSIT_AMOUNT).await;
+    let result = context
+        .try_stake_deposit(
+            v1.vote_account,
+            StakeDeposit::Append,
+            TEST_STAKE_DEPOSIT_AMOUNT,
+        )
+        .await;
+    assert_solido_error!(result, LidoError::StakeToInactiveValidator);
+
+    // The validator with weight 0 has the least stake, but it is not a
+    // candidate, so staking with the other validator is allowed.
+    context
+        .stake_deposit(
+            v2.vote_account,
+            StakeDeposit::Append,
+            TEST_STAKE_DEPOSIT_AMOUNT,
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn test_stake_deposit_respects_max_stake_per_validator() {
+    let mut context = Context::new_with_maintainer().await;
+    let v1 = context.add_validator().await;
+    let v2 = context.add_validator().await;
+
+    context
+        .try_set_max_stake_per_validator(Lamports(2_500_000_000))
+        .await
+        .unwrap();
+    let solido = context.get_solido().await;
+    assert_eq!(solido.max_stake_per_validator, Lamports(2_500_000_000));
+
+    context.deposit(Lamports(10_000_000_000)).await;
+    context
+        .stake_deposit(
+            v1.vote_account,
+            StakeDeposit::Append,
+            Lamports(2_000_000_000),
+        )
+        .await;
+
+    // Staking another 1 SOL would bring v1 to 3 SOL, above the maximum.
+    let result = context
+        .try_stake_deposit(
+            v1.vote_account,
+            StakeDeposit::Append,
+            Lamports(1_000_000_000),
+        )
+        .await;
+    assert_solido_error!(result, LidoError::MaxStakePerValidatorExceeded);
+
+    // Once v2 has as much stake as v1, lifting the maximum allows staking
+    // with v1 again.
+    context
+        .stake_deposit(
+            v2.vote_account,
+            StakeDeposit::Append,
+            Lamports(2_000_000_000),
+        )
+        .await;
+    context
+        .try_set_max_stake_per_validator(Lamports(0))
+        .await
+        .unwrap();
+    context
+        .stake_deposit(
+            v1.vote_account,
+            StakeDeposit::Append,
+            Lamports(1_000_000_000),
+        )
+        .await;
+}
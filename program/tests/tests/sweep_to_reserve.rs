@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+use crate::assert_solido_error;
+use crate::context::Context;
+
+use lido::error::LidoError;
+use lido::token::Lamports;
+
+#[tokio::test]
+async fn test_sweep_authority_to_reserve() {
+    let mut context = Context::new_with_maintainer().await;
+    let reserve_before = context.get_sol_balance(context.reserve_address).await;
+
+    // Someone sends SOL to the mint authority, which never needs any.
+    context
+        .fund(context.mint_authority, Lamports(2_000_000_000))
+        .await;
+    context
+        .try_sweep_to_reserve(context.mint_authority)
+        .await
+        .expect("Failed to sweep the mint authority.");
+
+    let rent = context.get_rent().await;
+    assert_eq!(
+        context.get_sol_balance(context.mint_authority).await,
+        Lamports(rent.minimum_balance(0)),
+    );
+    let reserve_after = context.get_sol_balance(context.reserve_address).await;
+    assert_eq!(
+        reserve_after,
+        (reserve_before + Lamports(2_000_000_000 - rent.minimum_balance(0))).unwrap(),
+    );
+
+    // There is nothing left to sweep now.
+    let result = context.try_sweep_to_reserve(context.mint_authority).await;
+    assert_solido_error!(result, LidoError::NothingToSweep);
+}
+
+#[tokio::test]
+async fn test_sweep_oracle_to_reserve() {
+    let mut context = Context::new_with_maintainer().await;
+    let maintainer = context.maintainer.as_ref().unwrap().pubkey();
+    context.fund(maintainer, Lamports(1_000_000_000)).await;
+    context.advance_to_normal_epoch(0);
+    context.update_exchange_rate().await;
+    let oracle = context
+        .try_publish_exchange_rate()
+        .await
+        .expect("Failed to publish exchange rate.");
+
+    let oracle_balance = context.get_sol_balance(oracle).await;
+    context.fund(oracle, Lamports(500_000_000)).await;
+    let reserve_before = context.get_sol_balance(context.reserve_address).await;
+    context
+        .try_sweep_to_reserve(oracle)
+        .await
+        .expect("Failed to sweep the oracle.");
+
+    // The oracle keeps its rent-exempt balance, and its data.
+    assert_eq!(context.get_sol_balance(oracle).await, oracle_balance);
+    assert_eq!(
+        context.get_exchange_rate_oracle(oracle).await.publisher,
+        maintainer
+    );
+    let reserve_after = context.get_sol_balance(context.reserve_address).await;
+    assert_eq!(
+        reserve_after,
+        (reserve_before + Lamports(500_000_000)).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_sweep_rejects_other_accounts() {
+    let mut context = Context::new_with_maintainer().await;
+    let stranger = context.deterministic_keypair.new_keypair();
+    context
+        .fund(stranger.pubkey(), Lamports(1_000_000_000))
+        .await;
+
+    let result = context.try_sweep_to_reserve(stranger.pubkey()).await;
+    assert_solido_error!(result, LidoError::InvalidSweepAccount);
+
+    // The reserve itself is not an auxiliary account either.
+    let reserve = context.reserve_address;
+    let result = context.try_sweep_to_reserve(reserve).await;
+    assert_solido_error!(result, LidoError::InvalidSweepAccount);
+}
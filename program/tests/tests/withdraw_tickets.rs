@@ -0,0 +1,186 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+#![cfg(feature = "test-bpf")]
+
+use crate::assert_solido_error;
+use crate::context::{Context, StakeDeposit};
+
+use lido::error::LidoError;
+use lido::state::WITHDRAW_TICKET_SIZE;
+use lido::token::{Lamports, StLamports};
+use lido::MINIMUM_WITHDRAW_TICKET_AMOUNT;
+use solana_program_test::tokio;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+
+const DEPOSIT_AMOUNT: Lamports = Lamports(10_000_000_000);
+const TICKET_AMOUNT: StLamports = StLamports(6_000_000_000);
+
+/// Set up a Solido instance where a user deposited `DEPOSIT_AMOUNT`, and the
+/// exchange rate is up to date.
+async fn new_withdraw_ticket_context(mut context: Context) -> (Context, Keypair, Pubkey) {
+    let (user, st_sol_account) = context.deposit(DEPOSIT_AMOUNT).await;
+    context.advance_to_normal_epoch(0);
+    context.update_exchange_rate().await;
+    (context, user, st_sol_account)
+}
+
+#[tokio::test]
+async fn test_successful_withdraw_ticket() {
+    let (mut context, user, st_sol_account) =
+        new_withdraw_ticket_context(Context::new_with_maintainer().await).await;
+
+    let ticket_address = context
+        .create_withdraw_ticket(&user, st_sol_account, TICKET_AMOUNT)
+        .await;
+
+    // The exchange rate is 1:1, so the ticket is owed as much SOL as it burned stSOL.
+    let ticket = context.get_withdraw_ticket(ticket_address).await;
+    assert_eq!(ticket.owner, user.pubkey());
+    assert_eq!(ticket.ticket_id, 0);
+    assert_eq!(ticket.st_sol_burned, TICKET_AMOUNT);
+    assert_eq!(ticket.amount, Lamports(TICKET_AMOUNT.0));
+    assert_eq!(ticket.queue_position, Lamports(0));
+    assert!(!ticket.is_funded);
+    assert_eq!(
+        context.get_st_sol_balance(st_sol_account).await,
+        (StLamports(DEPOSIT_AMOUNT.0) - TICKET_AMOUNT).unwrap(),
+    );
+
+    let solido = context.get_solido().await;
+    assert_eq!(solido.withdraw_queue.next_ticket_id, 1);
+    assert_eq!(solido.withdraw_queue.requested_total, ticket.amount);
+    assert_eq!(solido.withdraw_queue.get_outstanding(), ticket.amount);
+
+    // The SOL that the ticket is owed no longer counts towards the exchange rate.
+    context.advance_to_normal_epoch(1);
+    context.update_exchange_rate().await;
+    let solido = context.get_solido().await;
+    assert_eq!(
+        solido.exchange_rate.sol_balance,
+        (DEPOSIT_AMOUNT - ticket.amount).unwrap()
+    );
+    assert_eq!(
+        solido.exchange_rate.st_sol_supply,
+        (StLamports(DEPOSIT_AMOUNT.0) - TICKET_AMOUNT).unwrap()
+    );
+
+    let reserve_before = context.get_sol_balance(context.reserve_address).await;
+    context.fund_withdraw_ticket().await;
+    let reserve_after = context.get_sol_balance(context.reserve_address).await;
+    assert_eq!((reserve_before - reserve_after).unwrap(), ticket.amount);
+
+    let solido = context.get_solido().await;
+    assert_eq!(solido.withdraw_queue.next_fund_ticket_id, 1);
+    assert_eq!(solido.withdraw_queue.get_outstanding(), Lamports(0));
+    assert!(context.get_withdraw_ticket(ticket_address).await.is_funded);
+
+    // Claiming pays out the SOL and the rent of the ticket account.
+    let recipient = context.deterministic_keypair.new_keypair().pubkey();
+    context
+        .try_claim_withdraw_ticket(&user, ticket_address, recipient)
+        .await
+        .unwrap();
+    let rent = context.get_rent().await;
+    assert_eq!(
+        context.get_sol_balance(recipient).await,
+        (ticket.amount + Lamports(rent.minimum_balance(WITHDRAW_TICKET_SIZE))).unwrap(),
+    );
+    assert!(context.try_get_account(ticket_address).await.is_none());
+}
+
+#[tokio::test]
+async fn test_claim_withdraw_ticket_requires_funding_and_owner() {
+    let (mut context, user, st_sol_account) =
+        new_withdraw_ticket_context(Context::new_with_maintainer().await).await;
+    let ticket_address = context
+        .create_withdraw_ticket(&user, st_sol_account, TICKET_AMOUNT)
+        .await;
+
+    let recipient = context.deterministic_keypair.new_keypair().pubkey();
+    let result = context
+        .try_claim_withdraw_ticket(&user, ticket_address, recipient)
+        .await;
+    assert_solido_error!(result, LidoError::WithdrawTicketNotFunded);
+
+    context.fund_withdraw_ticket().await;
+
+    let other_user = context.deterministic_keypair.new_keypair();
+    let result = context
+        .try_claim_withdraw_ticket(&other_user, ticket_address, recipient)
+        .await;
+    assert_solido_error!(result, LidoError::InvalidWithdrawTicketOwner);
+}
+
+#[tokio::test]
+async fn test_withdraw_tickets_go_before_staking() {
+    let (mut context, user, st_sol_account) =
+        new_withdraw_ticket_context(Context::new_with_maintainer_and_validator().await).await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    context
+        .create_withdraw_ticket(&user, st_sol_account, TICKET_AMOUNT)
+        .await;
+
+    // 6 SOL of the reserve are owed to the ticket, only 4 SOL can be staked.
+    let result = context
+        .try_stake_deposit(vote_account, StakeDeposit::Append, Lamports(5_000_000_000))
+        .await;
+    assert_solido_error!(result, LidoError::AmountExceedsReserve);
+
+    context
+        .stake_deposit(vote_account, StakeDeposit::Append, Lamports(4_000_000_000))
+        .await;
+
+    // And the ticket can still be funded.
+    context.fund_withdraw_ticket().await;
+}
+
+#[tokio::test]
+async fn test_fund_withdraw_ticket_fails_when_reserve_is_insufficient() {
+    let (mut context, user, st_sol_account) =
+        new_withdraw_ticket_context(Context::new_with_maintainer_and_validator().await).await;
+    let vote_account = context.validator.as_ref().unwrap().vote_account;
+    context
+        .stake_deposit(vote_account, StakeDeposit::Append, DEPOSIT_AMOUNT)
+        .await;
+    context
+        .create_withdraw_ticket(&user, st_sol_account, TICKET_AMOUNT)
+        .await;
+
+    // All SOL is staked, the maintainer has to unstake before funding the ticket.
+    let result = context.try_fund_withdraw_ticket().await;
+    assert_solido_error!(result, LidoError::AmountExceedsReserve);
+}
+
+#[tokio::test]
+async fn test_create_withdraw_ticket_rejects_small_amounts() {
+    let (mut context, user, st_sol_account) =
+        new_withdraw_ticket_context(Context::new_with_maintainer().await).await;
+
+    // The exchange rate is 1:1, so one stLamport less than the minimum is owed too little SOL.
+    for &amount in &[
+        StLamports(0),
+        StLamports(1),
+        StLamports(MINIMUM_WITHDRAW_TICKET_AMOUNT.0 - 1),
+    ] {
+        let result = context
+            .try_create_withdraw_ticket(&user, st_sol_account, amount)
+            .await;
+        assert_solido_error!(result, LidoError::InvalidAmount);
+    }
+    assert_eq!(
+        context.get_st_sol_balance(st_sol_account).await,
+        StLamports(DEPOSIT_AMOUNT.0)
+    );
+    assert_eq!(context.get_solido().await.withdraw_queue.next_ticket_id, 0);
+
+    context
+        .create_withdraw_ticket(
+            &user,
+            st_sol_account,
+            StLamports(MINIMUM_WITHDRAW_TICKET_AMOUNT.0),
+        )
+        .await;
+}
@@ -210,6 +210,9 @@ assert solido_instance['solido']['reward_distribution'] == {
     'developer_fee': 2,
     'st_sol_appreciation': 90,
 }
+assert solido_instance['solido']['max_stake_per_validator'] == 0
+assert solido_instance['solido']['is_paused'] == False
+assert solido_instance['solido']['max_total_lamports'] == 0
 
 print('\nAdding a validator ...')
 validator_fee_account_owner = create_test_account(
@@ -313,6 +316,7 @@ assert solido_instance['solido']['validators']['entries'][0] == {
         'stake_accounts_balance': 0,
         'unstake_accounts_balance': 0,
         'active': True,
+        'weight': 100,
     },
 }, f'Unexpected validator entry, in {json.dumps(solido_instance, indent=True)}'
 
@@ -418,6 +422,36 @@ else:
     print('> Updated the exchange rate, as expected in a change of Epoch.')
 
 
+def set_paused(command: str) -> bool:
+    transaction_result = solido(
+        command,
+        '--multisig-program-id',
+        multisig_program_id,
+        '--solido-program-id',
+        solido_program_id,
+        '--solido-address',
+        solido_address,
+        '--multisig-address',
+        multisig_instance,
+        keypair_path=test_addrs[0].keypair_path,
+    )
+    approve_and_execute(transaction_result['transaction_address'], test_addrs[1])
+    solido_instance = solido(
+        'show-solido',
+        '--solido-program-id',
+        solido_program_id,
+        '--solido-address',
+        solido_address,
+    )
+    return solido_instance['solido']['is_paused']
+
+
+print('\nPausing and resuming Solido ...')
+assert set_paused('pause') == True
+print('> Paused deposits and withdrawals.')
+assert set_paused('resume') == False
+print('> Resumed deposits and withdrawals.')
+
 def deposit(lamports: int, expect_created_token_account: bool = False) -> None:
     print(f'\nDepositing {lamports/1_000_000_000} SOL ...')
     deposit_result = solido(
@@ -431,6 +465,9 @@ def deposit(lamports: int, expect_created_token_account: bool = False) -> None:
     )
     # The recipient address depends on the signer, it does not have a fixed expectation.
     del deposit_result['recipient']
+    # The exchange rate depends on the deposits and rewards so far.
+    exchange_rate = deposit_result.pop('exchange_rate')
+    assert set(exchange_rate) == {'computed_in_epoch', 'st_sol_supply', 'sol_balance'}
     expected = {
         'expected_st_lamports': lamports,
         'st_lamports_balance_increase': lamports,